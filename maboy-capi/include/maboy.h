@@ -0,0 +1,129 @@
+#ifndef MABOY_H
+#define MABOY_H
+
+/* C interface of MaBoy, see src/lib.rs. Regenerate after changing the API:
+ *   cbindgen --config cbindgen.toml --output include/maboy.h
+ */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Width of the frame buffer in pixels
+#define MABOY_FRAME_WIDTH 160
+
+// Height of the frame buffer in pixels
+#define MABOY_FRAME_HEIGHT 144
+
+// Bumped whenever functions are added
+#define MABOY_ABI_VERSION 1
+
+// Button bits for [`maboy_set_buttons`]
+#define MABOY_BUTTON_RIGHT 1
+
+#define MABOY_BUTTON_LEFT 2
+
+#define MABOY_BUTTON_UP 4
+
+#define MABOY_BUTTON_DOWN 8
+
+#define MABOY_BUTTON_A 16
+
+#define MABOY_BUTTON_B 32
+
+#define MABOY_BUTTON_SELECT 64
+
+#define MABOY_BUTTON_START 128
+
+// Returned by every function that can fail
+typedef enum MaBoyResult {
+  MABOY_RESULT_OK = 0,
+  // A required pointer was null
+  MABOY_RESULT_NULL_POINTER = 1,
+  // The ROM is invalid (or can't be unpacked), or its cartridge type is not supported
+  MABOY_RESULT_INVALID_ROM = 2,
+  // The savestate is corrupted, from another version or from another ROM
+  MABOY_RESULT_INVALID_STATE = 3,
+  // The buffer is too small; The required size was written anyway
+  MABOY_RESULT_BUFFER_TOO_SMALL = 4,
+} MaBoyResult;
+
+// An emulator, together with the last frame it produced. Opaque to C.
+typedef struct MaBoy MaBoy;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// See [`MABOY_ABI_VERSION`]
+uint32_t maboy_abi_version(void);
+
+// Creates an emulator for a ROM image, which may be zipped or gzipped. The ROM is
+// copied, so the buffer can be freed afterwards. On success, the emulator is written to
+// `out` and has to be freed with [`maboy_destroy`].
+//
+// # Safety
+// `rom` must point to `rom_len` readable bytes, `out` must be writable
+MaBoyResult maboy_create(const uint8_t *rom, size_t rom_len, MaBoy **out);
+
+// Frees an emulator. Does nothing if `maboy` is null.
+//
+// # Safety
+// `maboy` must come from [`maboy_create`] and must not be used afterwards
+void maboy_destroy(MaBoy *maboy);
+
+// Runs the emulator for one frame. Returns false if the LCD was turned off during that
+// frame, in which case the frame buffer is blank.
+//
+// # Safety
+// `maboy` must be a valid emulator
+bool maboy_run_frame(MaBoy *maboy);
+
+// The last frame: [`MABOY_FRAME_WIDTH`] × [`MABOY_FRAME_HEIGHT`] pixels, row by row,
+// with 4 bytes (RGBA) per pixel. The pointer stays valid until the next call of
+// [`maboy_run_frame`] or [`maboy_destroy`].
+//
+// # Safety
+// `maboy` must be a valid emulator
+const uint8_t *maboy_framebuffer(const MaBoy *maboy);
+
+// Sets the buttons that are currently held down, as a combination of `MABOY_BUTTON_*`
+// bits
+//
+// # Safety
+// `maboy` must be a valid emulator
+void maboy_set_buttons(MaBoy *maboy, uint8_t buttons);
+
+// Captures the complete state of the emulator. The size of the state is always written
+// to `state_len`. If `buf` is null or smaller than that, nothing else happens and
+// `MABOY_RESULT_BUFFER_TOO_SMALL` is returned, so the usual pattern is to call this
+// twice: Once to get the size, and once to get the state.
+//
+// # Safety
+// `maboy` must be a valid emulator, `buf` (if not null) must point to `buf_len`
+// writable bytes, and `state_len` must be writable
+MaBoyResult maboy_save_state(const MaBoy *maboy, uint8_t *buf, size_t buf_len, size_t *state_len);
+
+// Restores a state from [`maboy_save_state`]. If that fails, the emulator is left
+// unchanged.
+//
+// # Safety
+// `maboy` must be a valid emulator, `state` must point to `state_len` readable bytes
+MaBoyResult maboy_load_state(MaBoy *maboy, const uint8_t *state, size_t state_len);
+
+// The battery-backed cartridge RAM, which is what should be stored as the savegame.
+// Its size is written to `len`. To load a savegame, write it into the returned buffer
+// right after [`maboy_create`]. Returns null if the cartridge has no battery. The
+// pointer stays valid until [`maboy_destroy`].
+//
+// # Safety
+// `maboy` must be a valid emulator, `len` must be writable
+uint8_t *maboy_savegame(MaBoy *maboy, size_t *len);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif // MABOY_H
@@ -0,0 +1,277 @@
+//! C bindings for MaBoy, so it can be embedded in C, C++, C# or anything else that can
+//! call C functions. Build the shared (or static) library with
+//!
+//! ```text
+//! cargo build --release
+//! ```
+//!
+//! and include `include/maboy.h`:
+//!
+//! ```c
+//! MaBoy *maboy;
+//! if (maboy_create(rom, rom_len, &maboy) != MABOY_RESULT_OK) { ... }
+//!
+//! // Once per frame
+//! maboy_set_buttons(maboy, MABOY_BUTTON_A | MABOY_BUTTON_START);
+//! maboy_run_frame(maboy);
+//! draw_rgba(maboy_framebuffer(maboy), MABOY_FRAME_WIDTH, MABOY_FRAME_HEIGHT);
+//!
+//! maboy_destroy(maboy);
+//! ```
+//!
+//! Like the WebAssembly bindings, this is deliberately small: Timing, audio output and
+//! storing savegames are up to the caller. Functions never take ownership of the
+//! buffers passed to them. A `MaBoy` must not be used from two threads at once.
+//!
+//! The ABI only ever grows; Existing functions keep their signatures. Check
+//! [`maboy_abi_version`] if you need a function that was added later.
+
+use maboy::pixel_format;
+use maboy::{
+    rom_loader, Buttons, CartridgeVariant, Emulator, EmulatorHandle, FrameResult, MemPixel,
+};
+use std::{ptr, slice};
+
+/// Width of the frame buffer in pixels
+pub const MABOY_FRAME_WIDTH: usize = 160;
+/// Height of the frame buffer in pixels
+pub const MABOY_FRAME_HEIGHT: usize = 144;
+
+/// Bumped whenever functions are added
+pub const MABOY_ABI_VERSION: u32 = 1;
+
+/// Button bits for [`maboy_set_buttons`]
+pub const MABOY_BUTTON_RIGHT: u8 = 0b_0000_0001;
+pub const MABOY_BUTTON_LEFT: u8 = 0b_0000_0010;
+pub const MABOY_BUTTON_UP: u8 = 0b_0000_0100;
+pub const MABOY_BUTTON_DOWN: u8 = 0b_0000_1000;
+pub const MABOY_BUTTON_A: u8 = 0b_0001_0000;
+pub const MABOY_BUTTON_B: u8 = 0b_0010_0000;
+pub const MABOY_BUTTON_SELECT: u8 = 0b_0100_0000;
+pub const MABOY_BUTTON_START: u8 = 0b_1000_0000;
+
+/// Returned by every function that can fail
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MaBoyResult {
+    Ok = 0,
+    /// A required pointer was null
+    NullPointer = 1,
+    /// The ROM is invalid (or can't be unpacked), or its cartridge type is not supported
+    InvalidRom = 2,
+    /// The savestate is corrupted, from another version or from another ROM
+    InvalidState = 3,
+    /// The buffer is too small; The required size was written anyway
+    BufferTooSmall = 4,
+}
+
+/// An emulator, together with the last frame it produced. Opaque to C.
+pub struct MaBoy {
+    core: Box<dyn EmulatorHandle>,
+    /// RGBA, row by row
+    frame: Vec<u8>,
+}
+
+/// See [`MABOY_ABI_VERSION`]
+#[no_mangle]
+pub extern "C" fn maboy_abi_version() -> u32 {
+    MABOY_ABI_VERSION
+}
+
+/// Creates an emulator for a ROM image, which may be zipped or gzipped. The ROM is
+/// copied, so the buffer can be freed afterwards. On success, the emulator is written to
+/// `out` and has to be freed with [`maboy_destroy`].
+///
+/// # Safety
+/// `rom` must point to `rom_len` readable bytes, `out` must be writable
+#[no_mangle]
+pub unsafe extern "C" fn maboy_create(
+    rom: *const u8,
+    rom_len: usize,
+    out: *mut *mut MaBoy,
+) -> MaBoyResult {
+    if rom.is_null() || out.is_null() {
+        return MaBoyResult::NullPointer;
+    }
+
+    let rom = match rom_loader::unpack(slice::from_raw_parts(rom, rom_len).to_vec()) {
+        Ok(rom) => rom,
+        Err(err) => {
+            log::error!("Could not unpack ROM: {:?}", err);
+            return MaBoyResult::InvalidRom;
+        }
+    };
+
+    let cartridge = match CartridgeVariant::from_bytes(rom) {
+        Ok(cartridge) => cartridge,
+        Err(err) => {
+            log::error!("Could not load ROM: {:?}", err);
+            return MaBoyResult::InvalidRom;
+        }
+    };
+
+    let maboy = MaBoy {
+        core: core_for(cartridge),
+        frame: blank_frame(),
+    };
+
+    *out = Box::into_raw(Box::new(maboy));
+    MaBoyResult::Ok
+}
+
+/// Frees an emulator. Does nothing if `maboy` is null.
+///
+/// # Safety
+/// `maboy` must come from [`maboy_create`] and must not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn maboy_destroy(maboy: *mut MaBoy) {
+    if !maboy.is_null() {
+        drop(Box::from_raw(maboy));
+    }
+}
+
+/// Runs the emulator for one frame. Returns false if the LCD was turned off during that
+/// frame, in which case the frame buffer is blank.
+///
+/// # Safety
+/// `maboy` must be a valid emulator
+#[no_mangle]
+pub unsafe extern "C" fn maboy_run_frame(maboy: *mut MaBoy) -> bool {
+    let maboy = match maboy.as_mut() {
+        Some(maboy) => maboy,
+        None => return false,
+    };
+
+    match maboy.core.run_frame() {
+        FrameResult::Frame(pixels) => {
+            pixel_format::to_rgba8(pixels, &mut maboy.frame);
+            true
+        }
+        FrameResult::LcdOff => {
+            maboy.frame = blank_frame();
+            false
+        }
+    }
+}
+
+/// The last frame: [`MABOY_FRAME_WIDTH`] × [`MABOY_FRAME_HEIGHT`] pixels, row by row,
+/// with 4 bytes (RGBA) per pixel. The pointer stays valid until the next call of
+/// [`maboy_run_frame`] or [`maboy_destroy`].
+///
+/// # Safety
+/// `maboy` must be a valid emulator
+#[no_mangle]
+pub unsafe extern "C" fn maboy_framebuffer(maboy: *const MaBoy) -> *const u8 {
+    match maboy.as_ref() {
+        Some(maboy) => maboy.frame.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Sets the buttons that are currently held down, as a combination of `MABOY_BUTTON_*`
+/// bits
+///
+/// # Safety
+/// `maboy` must be a valid emulator
+#[no_mangle]
+pub unsafe extern "C" fn maboy_set_buttons(maboy: *mut MaBoy, buttons: u8) {
+    if let Some(maboy) = maboy.as_mut() {
+        maboy
+            .core
+            .notify_buttons_state(Buttons::from_bits_truncate(buttons));
+    }
+}
+
+/// Captures the complete state of the emulator. The size of the state is always written
+/// to `state_len`. If `buf` is null or smaller than that, nothing else happens and
+/// `MABOY_RESULT_BUFFER_TOO_SMALL` is returned, so the usual pattern is to call this
+/// twice: Once to get the size, and once to get the state.
+///
+/// # Safety
+/// `maboy` must be a valid emulator, `buf` (if not null) must point to `buf_len`
+/// writable bytes, and `state_len` must be writable
+#[no_mangle]
+pub unsafe extern "C" fn maboy_save_state(
+    maboy: *const MaBoy,
+    buf: *mut u8,
+    buf_len: usize,
+    state_len: *mut usize,
+) -> MaBoyResult {
+    let maboy = match (maboy.as_ref(), state_len.is_null()) {
+        (Some(maboy), false) => maboy,
+        _ => return MaBoyResult::NullPointer,
+    };
+
+    let state = maboy.core.save_state();
+    *state_len = state.len();
+
+    if buf.is_null() || buf_len < state.len() {
+        return MaBoyResult::BufferTooSmall;
+    }
+
+    slice::from_raw_parts_mut(buf, state.len()).copy_from_slice(&state);
+    MaBoyResult::Ok
+}
+
+/// Restores a state from [`maboy_save_state`]. If that fails, the emulator is left
+/// unchanged.
+///
+/// # Safety
+/// `maboy` must be a valid emulator, `state` must point to `state_len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn maboy_load_state(
+    maboy: *mut MaBoy,
+    state: *const u8,
+    state_len: usize,
+) -> MaBoyResult {
+    let maboy = match (maboy.as_mut(), state.is_null()) {
+        (Some(maboy), false) => maboy,
+        _ => return MaBoyResult::NullPointer,
+    };
+
+    match maboy
+        .core
+        .load_state(slice::from_raw_parts(state, state_len))
+    {
+        Ok(()) => MaBoyResult::Ok,
+        Err(err) => {
+            log::warn!("Could not load state: {:?}", err);
+            MaBoyResult::InvalidState
+        }
+    }
+}
+
+/// The battery-backed cartridge RAM, which is what should be stored as the savegame.
+/// Its size is written to `len`. To load a savegame, write it into the returned buffer
+/// right after [`maboy_create`]. Returns null if the cartridge has no battery. The
+/// pointer stays valid until [`maboy_destroy`].
+///
+/// # Safety
+/// `maboy` must be a valid emulator, `len` must be writable
+#[no_mangle]
+pub unsafe extern "C" fn maboy_savegame(maboy: *mut MaBoy, len: *mut usize) -> *mut u8 {
+    let maboy = match (maboy.as_mut(), len.is_null()) {
+        (Some(maboy), false) => maboy,
+        _ => return ptr::null_mut(),
+    };
+
+    match maboy.core.savegame_mut() {
+        Some(cram) => {
+            *len = cram.len();
+            cram.as_mut_ptr()
+        }
+        None => {
+            *len = 0;
+            ptr::null_mut()
+        }
+    }
+}
+
+fn blank_frame() -> Vec<u8> {
+    pixel_format::as_rgba8(&[MemPixel::LCD_OFF]).repeat(MABOY_FRAME_WIDTH * MABOY_FRAME_HEIGHT)
+}
+
+fn core_for(cartridge: CartridgeVariant) -> Box<dyn EmulatorHandle> {
+    // CartridgeVariant dispatches internally, so there is only one Emulator type to compile
+    Box::new(Emulator::new(cartridge))
+}
@@ -0,0 +1,250 @@
+//! Python bindings for MaBoy, mostly meant for research and bots (reinforcement
+//! learning, tool-assisted runs, scripted tests). Build and install them into the
+//! active virtualenv with [maturin](https://github.com/PyO3/maturin):
+//!
+//! ```text
+//! maturin develop --release
+//! ```
+//!
+//! ```python
+//! import maboy
+//!
+//! gb = maboy.MaBoy.from_file("tetris.gb")
+//! gb.set_buttons(maboy.BUTTON_START)
+//! gb.run_frames(60)
+//!
+//! frame = gb.frame()      # numpy array, shape (144, 160, 4), RGBA
+//! lines = gb.peek(0xC0A3)
+//! state = gb.save_state() # bytes, can be loaded again with gb.load_state(state)
+//! ```
+//!
+//! Emulation releases the GIL, so running one emulator per Python thread actually
+//! runs them in parallel. A single emulator can be shared between threads as well; Calls
+//! on it simply wait for each other.
+
+use maboy::{pixel_format, rom_loader};
+use maboy::{Buttons, CartridgeVariant, Emulator, EmulatorHandle, FrameResult, MemPixel};
+use numpy::ndarray::Array3;
+use numpy::{IntoPyArray, PyArray3};
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::sync::{Mutex, MutexGuard};
+
+/// Width of the frame in pixels
+const FRAME_WIDTH: usize = 160;
+/// Height of the frame in pixels
+const FRAME_HEIGHT: usize = 144;
+
+/// Button bits for [`MaBoy::set_buttons`]. Same values as in the C bindings.
+const BUTTONS: [(&str, Buttons); 8] = [
+    ("BUTTON_RIGHT", Buttons::RIGHT),
+    ("BUTTON_LEFT", Buttons::LEFT),
+    ("BUTTON_UP", Buttons::UP),
+    ("BUTTON_DOWN", Buttons::DOWN),
+    ("BUTTON_A", Buttons::A),
+    ("BUTTON_B", Buttons::B),
+    ("BUTTON_SELECT", Buttons::SELECT),
+    ("BUTTON_START", Buttons::START),
+];
+
+/// An emulator, together with the last frame it produced
+#[pyclass(name = "MaBoy", module = "maboy")]
+pub struct MaBoy {
+    // Python objects can be shared between threads, and the GIL is released while the
+    // emulator runs, so this needs a lock of its own
+    machine: Mutex<Machine>,
+}
+
+struct Machine {
+    core: Box<dyn EmulatorHandle + Send>,
+    /// RGBA, row by row
+    frame: Vec<u8>,
+}
+
+#[pymethods]
+impl MaBoy {
+    /// Creates an emulator for a ROM image (`bytes`), which may be zipped or gzipped.
+    /// Raises `ValueError` if the ROM is invalid or its cartridge type is not supported.
+    #[new]
+    fn new(rom: &[u8]) -> PyResult<Self> {
+        let rom = rom_loader::unpack(rom.to_vec())
+            .map_err(|err| PyValueError::new_err(format!("Could not unpack ROM: {:?}", err)))?;
+        let cartridge = CartridgeVariant::from_bytes(rom)
+            .map_err(|err| PyValueError::new_err(format!("Could not load ROM: {:?}", err)))?;
+
+        Ok(Self::from_cartridge(cartridge))
+    }
+
+    /// Creates an emulator for the ROM at `path`
+    #[staticmethod]
+    fn from_file(path: &str) -> PyResult<Self> {
+        let cartridge = CartridgeVariant::from_file(path)
+            .map_err(|err| PyValueError::new_err(format!("Could not load ROM: {:?}", err)))?;
+
+        Ok(Self::from_cartridge(cartridge))
+    }
+
+    /// Runs the emulator for `frames` frames without holding the GIL. Returns false if
+    /// the LCD was off during the last one, in which case the frame is blank.
+    #[pyo3(signature = (frames = 1))]
+    fn run_frames(&self, py: Python<'_>, frames: u32) -> bool {
+        py.detach(|| {
+            let mut machine = self.lock();
+            let machine = &mut *machine;
+            let mut lcd_on = false;
+
+            for _ in 0..frames {
+                lcd_on = match machine.core.run_frame() {
+                    FrameResult::Frame(pixels) => {
+                        pixel_format::to_rgba8(pixels, &mut machine.frame);
+                        true
+                    }
+                    FrameResult::LcdOff => {
+                        machine.frame = blank_frame();
+                        false
+                    }
+                };
+            }
+
+            lcd_on
+        })
+    }
+
+    /// A copy of the last frame as a numpy array with shape (144, 160, 4): Rows, columns
+    /// and RGBA channels
+    fn frame<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray3<u8>> {
+        let frame = self.lock().frame.clone();
+
+        Array3::from_shape_vec((FRAME_HEIGHT, FRAME_WIDTH, 4), frame)
+            .expect("Frame has the wrong size")
+            .into_pyarray(py)
+    }
+
+    /// Sets the buttons that are currently held down, as a combination of the `BUTTON_*`
+    /// bits. Takes effect with the next frame.
+    fn set_buttons(&self, buttons: u8) {
+        self.lock()
+            .core
+            .notify_buttons_state(Buttons::from_bits_truncate(buttons));
+    }
+
+    /// Reads a byte the way the CPU would, but without any side effects
+    fn peek(&self, addr: u16) -> u8 {
+        self.lock().core.peek(addr)
+    }
+
+    /// Reads `len` consecutive bytes starting at `addr` (see `peek`) as `bytes`
+    fn peek_range<'py>(
+        &self,
+        py: Python<'py>,
+        addr: u16,
+        len: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        if addr as usize + len > 0x10000 {
+            return Err(PyIndexError::new_err("Range goes past 0xFFFF"));
+        }
+
+        let machine = self.lock();
+        let bytes: Vec<u8> = (0..len)
+            .map(|offset| machine.core.peek(addr + offset as u16))
+            .collect();
+
+        Ok(PyBytes::new(py, &bytes))
+    }
+
+    /// Writes a byte right away, like a cheat device would
+    fn poke(&self, addr: u16, val: u8) {
+        self.lock().core.poke(addr, val);
+    }
+
+    /// Number of machine cycles since the emulator was created
+    #[getter]
+    fn mcycles(&self) -> u64 {
+        self.lock().core.mcycles()
+    }
+
+    /// Captures the complete state of the emulator as `bytes`
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.lock().core.save_state())
+    }
+
+    /// Restores a state from `save_state`. Raises `ValueError` (and leaves the emulator
+    /// unchanged) if the state is corrupted, from another version or from another ROM.
+    fn load_state(&self, state: &[u8]) -> PyResult<()> {
+        self.lock()
+            .core
+            .load_state(state)
+            .map_err(|err| PyValueError::new_err(format!("Could not load state: {:?}", err)))
+    }
+
+    /// The battery-backed cartridge RAM as `bytes`, or `None` if the cartridge has no
+    /// battery
+    fn savegame<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyBytes>> {
+        let mut machine = self.lock();
+        machine
+            .core
+            .savegame_mut()
+            .map(|cram| PyBytes::new(py, cram))
+    }
+
+    /// Replaces the battery-backed cartridge RAM. Should be called right after creating
+    /// the emulator. Raises `ValueError` if the cartridge has no battery or the size is
+    /// wrong.
+    fn load_savegame(&self, savegame: &[u8]) -> PyResult<()> {
+        match self.lock().core.savegame_mut() {
+            Some(cram) if cram.len() == savegame.len() => {
+                cram.copy_from_slice(savegame);
+                Ok(())
+            }
+            Some(cram) => Err(PyValueError::new_err(format!(
+                "Savegame has {} bytes, but the cartridge RAM has {}",
+                savegame.len(),
+                cram.len()
+            ))),
+            None => Err(PyValueError::new_err("The cartridge has no battery")),
+        }
+    }
+}
+
+impl MaBoy {
+    fn from_cartridge(cartridge: CartridgeVariant) -> Self {
+        Self {
+            machine: Mutex::new(Machine {
+                core: core_for(cartridge),
+                frame: blank_frame(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Machine> {
+        // A panic in the emulator poisons the lock. The machine is still in a
+        // consistent enough state to look at, so that's up to the caller.
+        self.machine
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn blank_frame() -> Vec<u8> {
+    pixel_format::as_rgba8(&[MemPixel::LCD_OFF]).repeat(FRAME_WIDTH * FRAME_HEIGHT)
+}
+
+#[pymodule]
+#[pyo3(name = "maboy")]
+fn maboy_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<MaBoy>()?;
+    m.add("FRAME_WIDTH", FRAME_WIDTH)?;
+    m.add("FRAME_HEIGHT", FRAME_HEIGHT)?;
+
+    for (name, button) in BUTTONS.iter() {
+        m.add(*name, button.bits())?;
+    }
+
+    Ok(())
+}
+
+fn core_for(cartridge: CartridgeVariant) -> Box<dyn EmulatorHandle + Send> {
+    // CartridgeVariant dispatches internally, so there is only one Emulator type to compile
+    Box::new(Emulator::new(cartridge))
+}
@@ -0,0 +1,128 @@
+//! WebAssembly bindings for MaBoy, so it can run in a web page. Build with
+//!
+//! ```text
+//! wasm-pack build --target web --release
+//! ```
+//!
+//! and drive it from JavaScript, e.g. once per `requestAnimationFrame`:
+//!
+//! ```js
+//! import init, { MaBoy, Button } from "./pkg/maboy_wasm.js";
+//!
+//! await init();
+//! const maboy = new MaBoy(new Uint8Array(await romFile.arrayBuffer()));
+//!
+//! maboy.set_buttons(Button.A | Button.Start);
+//! maboy.run_frame();
+//! context.putImageData(new ImageData(maboy.frame_rgba(), 160, 144), 0, 0);
+//! ```
+//!
+//! Timing is up to the page: Every call of `run_frame` emulates one frame, which takes
+//! ~16.7ms on a real Game Boy. There is no file system in the browser, so storing the
+//! savegame (e.g. in `localStorage`) is up to the page as well. The real-time clock of
+//! MBC3 cartridges doesn't tick, since WebAssembly has no access to the system time.
+
+use maboy::{pixel_format, rom_loader};
+use maboy::{Buttons, CartridgeVariant, Emulator, EmulatorHandle, FrameResult, MemPixel};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+
+const FRAME_WIDTH: usize = 160;
+const FRAME_HEIGHT: usize = 144;
+
+/// The Game Boy buttons, as bits for [`MaBoy::set_buttons`]
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug)]
+pub enum Button {
+    Right = 0b_0000_0001,
+    Left = 0b_0000_0010,
+    Up = 0b_0000_0100,
+    Down = 0b_0000_1000,
+    A = 0b_0001_0000,
+    B = 0b_0010_0000,
+    Select = 0b_0100_0000,
+    Start = 0b_1000_0000,
+}
+
+/// An emulator, together with the last frame it produced
+#[wasm_bindgen]
+pub struct MaBoy {
+    core: Box<dyn EmulatorHandle>,
+    /// RGBA, row by row
+    frame: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl MaBoy {
+    /// Loads a ROM image, which may be zipped or gzipped. Throws if the ROM is invalid
+    /// or its cartridge type is not supported.
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom_bytes: &[u8]) -> Result<MaBoy, JsValue> {
+        let rom = rom_loader::unpack(rom_bytes.to_vec())
+            .map_err(|err| JsValue::from_str(&format!("Could not unpack ROM: {:?}", err)))?;
+        let cartridge = CartridgeVariant::from_bytes(rom)
+            .map_err(|err| JsValue::from_str(&format!("Could not load ROM: {:?}", err)))?;
+
+        let mut maboy = MaBoy {
+            core: core_for(cartridge),
+            frame: Vec::new(),
+        };
+
+        maboy.fill_frame(MemPixel::LCD_OFF);
+        Ok(maboy)
+    }
+
+    /// Runs the emulator for one frame. Returns false if the LCD was turned off during
+    /// that frame, in which case the frame is blank.
+    pub fn run_frame(&mut self) -> bool {
+        match self.core.run_frame() {
+            FrameResult::Frame(pixels) => {
+                pixel_format::to_rgba8(pixels, &mut self.frame);
+
+                true
+            }
+            FrameResult::LcdOff => {
+                self.fill_frame(MemPixel::LCD_OFF);
+                false
+            }
+        }
+    }
+
+    /// The last frame (160x144 pixels, RGBA), ready to be put into an `ImageData`
+    pub fn frame_rgba(&self) -> Clamped<Vec<u8>> {
+        Clamped(self.frame.clone())
+    }
+
+    /// Sets the buttons that are currently held down, as a combination of [`Button`] bits
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.core
+            .notify_buttons_state(Buttons::from_bits_truncate(buttons));
+    }
+
+    /// The contents of the cartridge RAM, or nothing if the cartridge has none
+    pub fn savegame(&self) -> Option<Vec<u8>> {
+        self.core.savegame().map(<[u8]>::to_vec)
+    }
+
+    /// Overwrites the cartridge RAM with a savegame from [`MaBoy::savegame`]. Should be
+    /// called right after loading the ROM. Throws if the savegame doesn't fit the
+    /// cartridge.
+    pub fn load_savegame(&mut self, savegame: &[u8]) -> Result<(), JsValue> {
+        match self.core.savegame_mut() {
+            Some(cram) if cram.len() == savegame.len() => {
+                cram.copy_from_slice(savegame);
+                Ok(())
+            }
+            _ => Err(JsValue::from_str("Savegame doesn't match the cartridge")),
+        }
+    }
+
+    fn fill_frame(&mut self, pixel: MemPixel) {
+        self.frame = pixel_format::as_rgba8(&[pixel]).repeat(FRAME_WIDTH * FRAME_HEIGHT);
+    }
+}
+
+fn core_for(cartridge: CartridgeVariant) -> Box<dyn EmulatorHandle> {
+    // CartridgeVariant dispatches internally, so there is only one Emulator type to compile
+    Box::new(Emulator::new(cartridge))
+}
@@ -0,0 +1,688 @@
+//! Cross-platform frontend for MaBoy, based on winit (window and input) and softbuffer
+//! (drawing). It runs on Linux, macOS and Windows, but is a lot more basic than the
+//! Windows frontend: No gamepads, no debugger and no file dialog.
+//!
+//! ```text
+//! cargo run --release -- <rom file> [options]
+//!
+//! --record-movie <file>        Record a movie
+//! --play-movie <file>          Play back a movie
+//! --game-db <file>             Load variable definitions for the game
+//! --splits <file>              Auto-split in LiveSplit (needs --game-db)
+//! --livesplit <address:port>   Where the LiveSplit Server runs
+//! --save-naming <rom|title>    Name saves after the ROM file or the game's title
+//! --state-server <address:port> Serve the emulator state as JSON (e.g. localhost:8017)
+//! --remote <address:port>      Let other programs control the emulator (e.g. localhost:8018)
+//! --boot-rom <file>            Run this boot ROM instead of the built-in one
+//! --skip-boot                  Don't run any boot ROM, start the game right away
+//! --palette <name|colors>      green, gray, pocket or four hex colors (light to dark)
+//! --speed <multiplier>         Run faster or slower than the Game Boy (e.g. 2 or 0.5)
+//! --frame-skip <auto|frames>   Skip drawing frames on slow machines
+//! ```
+//!
+//! `--play-movie` plays back a movie (see [`maboy::input_log`]) before handing control
+//! to the keyboard, `--record-movie` records one and writes it when the window is
+//! closed. Rewind is disabled while recording, since it would break the movie.
+//!
+//! `--game-db` loads the game's variable definitions (see [`maboy::gamedb`]), which
+//! `--splits` uses to auto-split in LiveSplit (see [`maboy::autosplit`]). LiveSplit is
+//! expected at `localhost:16834` unless `--livesplit` says otherwise.
+//!
+//! With `--save-naming title`, saves are named after the title in the cartridge header
+//! (see [`maboy::storage::SaveNaming`]), and existing saves are renamed accordingly.
+//!
+//! `--state-server` answers HTTP requests for the CPU, PPU, timer and bank state (see
+//! [`maboy::state_server`]), so dashboards can follow along while you play. `--remote`
+//! goes further and lets other programs pause the emulator, press buttons, take
+//! screenshots and write to memory (see [`maboy::remote`]).
+//!
+//! `--palette` changes the colors of the four shades (see [`maboy::dmg_palette`]),
+//! either to a preset or to custom colors like `e0f8d0,88c070,346856,081820`.
+//!
+//! P pauses the game (and resumes it), F4 resets the Game Boy and F11 does the same
+//! without running the boot ROM (see [`Emulator::soft_reset`]).
+//!
+//! `--speed` sets the initial speed, which F6 and F7 step through while playing (see
+//! [`maboy::frontend::SPEED_STEPS`]). Holding Tab runs the emulator as fast as it can
+//! and only shows a few frames per second.
+//!
+//! `--frame-skip 2` only draws every third frame, `--frame-skip auto` starts skipping
+//! frames once the emulator falls behind (see [`maboy::frontend::FrameSkipMode`]). The
+//! game itself still runs at full speed either way.
+//!
+//! Savegames, the key bindings (`input.toml` next to the executable, see
+//! [`maboy::frontend::InputConfig`]) and savestates are shared with the Windows frontend
+//! via [`maboy::frontend`].
+
+use maboy::frontend::{self, InputConfig, InputConfigError, InputMap, Turbo};
+use maboy::*;
+use softbuffer::{Context, Surface};
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use winit::application::ApplicationHandler;
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, KeyEvent, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::{Window, WindowId};
+
+const REWIND_KEY: KeyCode = KeyCode::Backspace;
+const QUICK_SAVE_KEY: KeyCode = KeyCode::F5;
+const QUICK_LOAD_KEY: KeyCode = KeyCode::F9;
+const SLOWER_KEY: KeyCode = KeyCode::F6;
+const FASTER_KEY: KeyCode = KeyCode::F7;
+const TURBO_KEY: KeyCode = KeyCode::Tab;
+const SCREENSHOT_KEY: KeyCode = KeyCode::F12;
+const PAUSE_KEY: KeyCode = KeyCode::KeyP;
+const RESET_KEY: KeyCode = KeyCode::F4;
+const SOFT_RESET_KEY: KeyCode = KeyCode::F11;
+
+/// A rewind point is recorded every `REWIND_INTERVAL` frames, and up to
+/// `REWIND_CAPACITY` of them are kept (roughly 20 seconds)
+const REWIND_INTERVAL: u32 = 2;
+const REWIND_CAPACITY: usize = 600;
+/// How many frames we go back for every frame displayed while rewinding
+const REWIND_SPEED: u32 = 4;
+
+/// The Game Boy runs at ~59.7 frames per second
+const FRAME_DURATION: Duration = Duration::from_nanos(16_750_419);
+
+/// In turbo mode, we emulate as many frames as fit into this time, then show the last one
+const TURBO_PRESENT_INTERVAL: Duration = Duration::from_nanos(16_666_667);
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+fn main() {
+    env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+
+    let rom_path = args.next().unwrap_or_else(|| usage());
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        if arg == "--skip-boot" {
+            options.skip_boot = true;
+            continue;
+        }
+
+        let value = args.next().unwrap_or_else(|| usage());
+
+        match arg.as_str() {
+            "--record-movie" => options.record_movie = Some(PathBuf::from(value)),
+            "--play-movie" => options.play_movie = Some(PathBuf::from(value)),
+            "--game-db" => options.game_db = Some(PathBuf::from(value)),
+            "--splits" => options.splits = Some(PathBuf::from(value)),
+            "--livesplit" => options.livesplit = value,
+            "--save-naming" => options.save_naming = value.parse().unwrap_or_else(|_| usage()),
+            "--state-server" => options.state_server = Some(value),
+            "--remote" => options.remote = Some(value),
+            "--boot-rom" => options.boot_rom = Some(PathBuf::from(value)),
+            "--palette" => {
+                options.palette = Some(dmg_palette::parse(&value).unwrap_or_else(|| usage()))
+            }
+            "--speed" => options.speed = frontend::parse_speed(&value).unwrap_or_else(|| usage()),
+            "--frame-skip" => {
+                options.frame_skip =
+                    frontend::FrameSkipMode::parse(&value).unwrap_or_else(|| usage())
+            }
+            _ => usage(),
+        }
+    }
+
+    // Splits refer to the variables of the game database
+    if options.splits.is_some() && options.game_db.is_none() {
+        usage();
+    }
+
+    let cartridge = CartridgeVariant::from_file(&rom_path)
+        .unwrap_or_else(|err| exit_with("Could not open rom file", err));
+
+    run_emu(Path::new(&rom_path), cartridge, &options);
+}
+
+struct Options {
+    record_movie: Option<PathBuf>,
+    play_movie: Option<PathBuf>,
+    game_db: Option<PathBuf>,
+    splits: Option<PathBuf>,
+    /// Address of the LiveSplit Server component
+    livesplit: String,
+    save_naming: storage::SaveNaming,
+    /// Where the state server listens, if it should run at all
+    state_server: Option<String>,
+    /// Where the remote control server listens, if it should run at all
+    remote: Option<String>,
+    /// Replaces the built-in boot ROM
+    boot_rom: Option<PathBuf>,
+    skip_boot: bool,
+    palette: Option<DmgPalette>,
+    /// Multiple of the Game Boy's frame rate
+    speed: f32,
+    frame_skip: frontend::FrameSkipMode,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            record_movie: None,
+            play_movie: None,
+            game_db: None,
+            splits: None,
+            livesplit: format!("localhost:{}", autosplit::LIVESPLIT_PORT),
+            save_naming: storage::SaveNaming::default(),
+            state_server: None,
+            remote: None,
+            boot_rom: None,
+            skip_boot: false,
+            palette: None,
+            speed: 1.0,
+            frame_skip: frontend::FrameSkipMode::default(),
+        }
+    }
+}
+
+fn run_emu(rom_path: &Path, mut cartridge: CartridgeVariant, options: &Options) {
+    storage::migrate_saves(rom_path, cartridge.rom(), options.save_naming)
+        .unwrap_or_else(|err| exit_with("Could not rename existing saves", err));
+
+    let save_path = storage::save_base_path(rom_path, cartridge.rom(), options.save_naming);
+
+    frontend::load_savegame(&save_path, &mut cartridge)
+        .unwrap_or_else(|err| exit_with("Failed to load savegame", err));
+
+    frontend::load_metadata(&save_path, &mut cartridge)
+        .unwrap_or_else(|err| exit_with("Metadata file was found, but had invalid contents", err));
+
+    let event_loop =
+        EventLoop::new().unwrap_or_else(|err| exit_with("Could not create event loop", err));
+
+    let mut emu = Emulator::new(&mut cartridge);
+
+    if options.skip_boot {
+        emu = emu.with_boot_rom(None);
+    } else if let Some(path) = &options.boot_rom {
+        let boot_rom = frontend::load_boot_rom(path)
+            .unwrap_or_else(|err| exit_with("Could not read boot ROM", err));
+
+        emu = emu.with_boot_rom(Some(boot_rom));
+    }
+
+    if let Some(palette) = options.palette {
+        emu.set_dmg_palette(palette);
+    }
+
+    let mut app = App::new(&save_path, emu);
+    app.speed = options.speed;
+    app.frame_skip = options.frame_skip;
+
+    if let Some(path) = &options.game_db {
+        let game_db = gamedb::GameDb::load(path)
+            .unwrap_or_else(|err| exit_with("Could not read game database", err));
+
+        if let Some(path) = &options.splits {
+            let splitter = autosplit::AutoSplitter::load(path, &game_db)
+                .unwrap_or_else(|err| exit_with("Could not read splits file", err));
+
+            app.autosplit = Some(autosplit::LiveSplitSession::connect(
+                splitter,
+                options.livesplit.as_str(),
+            ));
+        }
+
+        app.emu.set_game_db(Some(game_db));
+    }
+
+    if let Some(addr) = &options.state_server {
+        app.state_server = Some(
+            state_server::StateServer::bind(addr.as_str())
+                .unwrap_or_else(|err| exit_with("Could not start the state server", err)),
+        );
+    }
+
+    if let Some(addr) = &options.remote {
+        app.remote = Some(
+            remote::RemoteServer::bind(addr.as_str())
+                .unwrap_or_else(|err| exit_with("Could not start the remote server", err)),
+        );
+    }
+
+    if let Some(path) = &options.play_movie {
+        let movie = input_log::Movie::load(path)
+            .unwrap_or_else(|err| exit_with("Could not read movie file", err));
+
+        app.emu
+            .play_movie(movie)
+            .unwrap_or_else(|err| exit_with("Movie doesn't belong to this ROM", err));
+    }
+
+    if options.record_movie.is_some() {
+        app.emu.set_rewind_buffer(None);
+        app.emu.start_recording();
+    }
+
+    event_loop
+        .run_app(&mut app)
+        .unwrap_or_else(|err| exit_with("Event loop failed", err));
+
+    if let (Some(path), Some(movie)) = (&options.record_movie, app.emu.stop_recording()) {
+        movie
+            .save(path)
+            .unwrap_or_else(|err| exit_with("Could not write movie file", err));
+    }
+
+    drop(app);
+
+    frontend::store_savegame(&save_path, &cartridge)
+        .unwrap_or_else(|err| exit_with("Could not write savegame to disk", err));
+
+    frontend::store_metadata(&save_path, &cartridge)
+        .unwrap_or_else(|err| exit_with("Could not write cartridge metadata to disk", err));
+}
+
+struct App<C> {
+    /// Where savestates and the savegame go, see [`storage::save_base_path`]
+    save_path: PathBuf,
+    emu: Emulator<C, debug::NoDbgLogger, debug::NoDbgLogger>,
+    input_map: InputMap<KeyCode>,
+    turbo: Turbo,
+    pressed_keys: HashSet<KeyCode>,
+    /// Created once the event loop is running
+    gfx: Option<Gfx>,
+    /// The last frame, drawn with the pause overlay while the game is paused
+    frame: Vec<MemPixel>,
+    paused: bool,
+    next_frame: Instant,
+    /// Multiple of the Game Boy's frame rate, see [`frontend::SPEED_STEPS`]
+    speed: f32,
+    frame_skip: frontend::FrameSkipMode,
+    /// Used to report a crashed game only once instead of every frame
+    cpu_stuck: bool,
+    autosplit: Option<autosplit::LiveSplitSession>,
+    state_server: Option<state_server::StateServer>,
+    remote: Option<remote::RemoteServer>,
+    autosave: frontend::AutoSave,
+}
+
+struct Gfx {
+    window: Rc<Window>,
+    surface: Surface<Rc<Window>, Rc<Window>>,
+}
+
+impl<C: Cartridge + Savegame + Metadata> App<C> {
+    fn new(save_path: &Path, mut emu: Emulator<C, debug::NoDbgLogger, debug::NoDbgLogger>) -> Self {
+        emu.set_reset_combo(ResetCombo::Reset);
+        emu.set_rewind_buffer(Some(RewindBuffer::new(REWIND_CAPACITY, REWIND_INTERVAL)));
+        // So screenshots always show a complete frame
+        emu.set_double_buffered(true);
+
+        let lcd_off = emu.dmg_palette()[0];
+
+        // There are no gamepads here, but their bindings are kept for the Windows frontend
+        let input_config = frontend::input_config_path()
+            .map_err(InputConfigError::from)
+            .and_then(InputConfig::load_or_create)
+            .unwrap_or_else(|err| exit_with("Could not read input config", err));
+
+        App {
+            save_path: save_path.to_path_buf(),
+            emu,
+            input_map: InputMap::from_bindings(&input_config.keyboard, key_for_name)
+                .with_turbo_bindings(&input_config.keyboard_turbo, key_for_name),
+            turbo: Turbo::new(input_config.turbo_frames),
+            pressed_keys: HashSet::new(),
+            gfx: None,
+            frame: vec![lcd_off; WIDTH * HEIGHT],
+            paused: false,
+            next_frame: Instant::now(),
+            speed: 1.0,
+            frame_skip: frontend::FrameSkipMode::default(),
+            cpu_stuck: false,
+            autosplit: None,
+            state_server: None,
+            remote: None,
+            autosave: frontend::AutoSave::default(),
+        }
+    }
+
+    fn emulate_frame(&mut self) {
+        if let Some(remote) = &mut self.remote {
+            remote.poll(&mut self.emu);
+
+            if !remote.should_run_frame() {
+                return;
+            }
+        }
+
+        // Turbo buttons change every few frames without any key events. Otherwise, the
+        // buttons are left alone, since the remote server might be pressing them.
+        self.turbo.next_frame();
+
+        let pressed = self.pressed_keys.iter().copied();
+        if !self.input_map.turbo_buttons(pressed).is_empty() {
+            self.update_buttons();
+        }
+
+        let frame = self.emu.run_frame();
+
+        if let Some(remote) = &mut self.remote {
+            remote.update_frame(&frame);
+        }
+
+        match frame {
+            FrameResult::Frame(pixels) => self.frame.copy_from_slice(pixels),
+            FrameResult::LcdOff => {
+                let lcd_off = self.emu.dmg_palette()[0];
+                self.frame.fill(lcd_off);
+            }
+        }
+
+        if let Some(autosplit) = &mut self.autosplit {
+            let emu = &self.emu;
+            autosplit.update(|addr| emu.peek(addr));
+        }
+
+        if let Some(server) = &mut self.state_server {
+            let emu = &self.emu;
+            server.poll(|| emu.state_snapshot());
+        }
+
+        if self.pressed_keys.contains(&REWIND_KEY) {
+            self.emu.rewind(REWIND_SPEED);
+        } else {
+            self.emu.push_rewind_point();
+        }
+
+        if let Err(err) = self
+            .autosave
+            .update(&self.save_path, self.emu.cartridge_mut())
+        {
+            log::error!("Could not write savegame to disk: {:?}", err);
+        }
+
+        if self.emu.poll_reset_combo() {
+            log::info!("Reset combo (A+B+Select+Start) pressed, resetting at the end of the frame");
+        }
+
+        match self.emu.illegal_instr() {
+            Some(illegal_instr) if !self.cpu_stuck => {
+                log::error!(
+                    "The game crashed (illegal instruction {:#04X} @ {:#06X}). Hold A+B+Select+Start to reset.",
+                    illegal_instr.opcode,
+                    illegal_instr.pc
+                );
+                self.cpu_stuck = true;
+            }
+            Some(_) => (),
+            None => self.cpu_stuck = false,
+        }
+    }
+
+    fn update_buttons(&mut self) {
+        let pressed = || self.pressed_keys.iter().copied();
+        let turbo_buttons = self.input_map.turbo_buttons(pressed());
+        let buttons = self.input_map.buttons(pressed()) | self.turbo.apply(turbo_buttons);
+
+        self.emu.notify_buttons_state(buttons);
+    }
+
+    fn key_pressed(&mut self, key: KeyCode) {
+        match key {
+            QUICK_SAVE_KEY => {
+                if let Err(err) = frontend::store_state(&self.save_path, &self.emu) {
+                    log::error!("Could not save state: {:?}", err);
+                }
+            }
+            QUICK_LOAD_KEY => {
+                if let Err(err) = frontend::load_state(&self.save_path, &mut self.emu) {
+                    log::warn!("Could not load state: {:?}", err);
+                }
+            }
+            SLOWER_KEY | FASTER_KEY => {
+                self.speed = frontend::next_speed(self.speed, key == FASTER_KEY);
+                log::info!("Speed: {}x", self.speed);
+            }
+            SCREENSHOT_KEY => {
+                if let Err(err) = frontend::store_screenshot(&self.save_path, &self.emu) {
+                    log::error!("Could not save screenshot: {}", err);
+                }
+            }
+            PAUSE_KEY => {
+                self.paused = !self.paused;
+                log::info!("{}", if self.paused { "Paused" } else { "Resumed" });
+
+                if let Some(gfx) = &self.gfx {
+                    gfx.window.request_redraw();
+                }
+            }
+            RESET_KEY => self.emu.reset(),
+            SOFT_RESET_KEY => self.emu.soft_reset(),
+            _ => (),
+        }
+    }
+
+    /// Scales the frame to the window size (nearest neighbor) and presents it
+    fn draw(&mut self) {
+        let gfx = match &mut self.gfx {
+            Some(gfx) => gfx,
+            None => return,
+        };
+
+        let size = gfx.window.inner_size();
+
+        let (width, height) = match (NonZeroU32::new(size.width), NonZeroU32::new(size.height)) {
+            (Some(width), Some(height)) => (width, height),
+            // Minimized
+            _ => return,
+        };
+
+        gfx.surface
+            .resize(width, height)
+            .unwrap_or_else(|err| exit_with("Could not resize drawing surface", err));
+
+        let mut buffer = gfx
+            .surface
+            .buffer_mut()
+            .unwrap_or_else(|err| exit_with("Could not access drawing surface", err));
+
+        let (width, height) = (width.get() as usize, height.get() as usize);
+
+        let mut paused_frame;
+        let frame = if self.paused {
+            paused_frame = self.frame.clone();
+            frontend::draw_pause_overlay(&mut paused_frame);
+            &paused_frame
+        } else {
+            &self.frame
+        };
+
+        for (y, row) in buffer.chunks_exact_mut(width).enumerate() {
+            let src_row = &frame[(y * HEIGHT / height) * WIDTH..][..WIDTH];
+
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = pixel_format::xrgb8888(src_row[x * WIDTH / width]);
+            }
+        }
+
+        buffer
+            .present()
+            .unwrap_or_else(|err| exit_with("Could not present frame", err));
+    }
+}
+
+impl<C: Cartridge + Savegame + Metadata> ApplicationHandler for App<C> {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.gfx.is_some() {
+            return;
+        }
+
+        let attributes = Window::default_attributes()
+            .with_title("MaBoy Emulatin'")
+            .with_inner_size(LogicalSize::new(WIDTH as u32 * 2, HEIGHT as u32 * 2));
+
+        let window = Rc::new(
+            event_loop
+                .create_window(attributes)
+                .unwrap_or_else(|err| exit_with("Could not create game window", err)),
+        );
+
+        let context = Context::new(Rc::clone(&window))
+            .unwrap_or_else(|err| exit_with("Could not access graphics device", err));
+
+        let surface = Surface::new(&context, Rc::clone(&window))
+            .unwrap_or_else(|err| exit_with("Could not create drawing surface", err));
+
+        self.gfx = Some(Gfx { window, surface });
+        self.next_frame = Instant::now();
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key),
+                        state,
+                        repeat,
+                        ..
+                    },
+                ..
+            } => {
+                match state {
+                    ElementState::Pressed => {
+                        if !repeat {
+                            self.key_pressed(key);
+                        }
+                        self.pressed_keys.insert(key);
+                    }
+                    ElementState::Released => {
+                        self.pressed_keys.remove(&key);
+                    }
+                }
+
+                self.update_buttons();
+            }
+            WindowEvent::RedrawRequested => self.draw(),
+            _ => (),
+        }
+    }
+
+    /// Emulation is driven from here: Whenever it's time for the next frame, we run
+    /// the emulator for one frame and sleep until the frame after that is due.
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let window = match &self.gfx {
+            Some(gfx) => Rc::clone(&gfx.window),
+            None => return,
+        };
+
+        let now = Instant::now();
+
+        if self.paused {
+            // Remote clients shouldn't hang while the game is paused
+            if let Some(remote) = &mut self.remote {
+                remote.poll(&mut self.emu);
+            }
+
+            self.next_frame = now + FRAME_DURATION;
+            event_loop.set_control_flow(ControlFlow::WaitUntil(self.next_frame));
+            return;
+        }
+
+        if self.pressed_keys.contains(&TURBO_KEY) {
+            // Unthrottled, and only the last of all these frames is shown
+            while Instant::now() - now < TURBO_PRESENT_INTERVAL {
+                self.emulate_frame();
+            }
+
+            window.request_redraw();
+            self.next_frame = Instant::now();
+            event_loop.set_control_flow(ControlFlow::Poll);
+            return;
+        }
+
+        if now >= self.next_frame {
+            let frame_duration = FRAME_DURATION.div_f32(self.speed);
+
+            // Running half a frame late every now and then is normal, but not all the time
+            let behind = now - self.next_frame > frame_duration / 2;
+            let frame_skip = self.frame_skip.update(behind);
+
+            if frame_skip != self.emu.frame_skip() {
+                self.emu.set_frame_skip(frame_skip);
+            }
+
+            self.emulate_frame();
+            window.request_redraw();
+
+            // After a stall (e.g. the window being dragged), we don't try to catch up
+            self.next_frame = (self.next_frame + frame_duration).max(now);
+        }
+
+        event_loop.set_control_flow(ControlFlow::WaitUntil(self.next_frame));
+    }
+}
+
+/// The default layout only uses letters
+fn key_for_label(label: char) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    const LETTERS: [KeyCode; 26] = [
+        KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM, KeyN, KeyO,
+        KeyP, KeyQ, KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ,
+    ];
+
+    let label = label.to_ascii_uppercase();
+
+    if label.is_ascii_uppercase() {
+        Some(LETTERS[(label as u8 - b'A') as usize])
+    } else {
+        None
+    }
+}
+
+/// The key with the given name in the input config (see [`InputConfig`])
+fn key_for_name(name: &str) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    let mut chars = name.chars();
+
+    if let (Some(label), None) = (chars.next(), chars.next()) {
+        return key_for_label(label);
+    }
+
+    const NAMED: [(KeyCode, &str); 8] = [
+        (Space, "Space"),
+        (Enter, "Enter"),
+        (Backspace, "Backspace"),
+        (ArrowUp, "Up"),
+        (ArrowRight, "Right"),
+        (ArrowDown, "Down"),
+        (ArrowLeft, "Left"),
+        (Tab, "Tab"),
+    ];
+
+    NAMED
+        .iter()
+        .find(|(_, key_name)| key_name.eq_ignore_ascii_case(name))
+        .map(|&(key, _)| key)
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: maboy_winit <rom file> [--record-movie <file>] [--play-movie <file>] [--game-db <file> [--splits <file>] [--livesplit <address:port>]] [--save-naming rom|title] [--state-server <address:port>] [--remote <address:port>] [--boot-rom <file> | --skip-boot] [--palette <name|colors>] [--speed <multiplier>] [--frame-skip <auto|frames>]"
+    );
+    process::exit(2);
+}
+
+fn exit_with<E: Debug>(msg: &str, err: E) -> ! {
+    log::error!("{} ({:?})", msg, err);
+    eprintln!("{} ({:?})", msg, err);
+    process::exit(1);
+}
@@ -0,0 +1,51 @@
+//! Generates `MABOY_BUILD_ID`, which identifies the exact emulator source code that
+//! was built. The test harness uses it to decide whether cached test results are still
+//! valid. It consists of the git commit (if available) and a hash over all source files,
+//! so uncommitted changes also invalidate the cache.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_owned())
+        .unwrap_or_else(|| "nogit".to_owned());
+
+    let mut files = Vec::new();
+    collect_files(Path::new("src"), &mut files);
+    files.sort();
+
+    // FNV-1a over all file paths and contents
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for file in files {
+        let content = fs::read(&file).expect("Could not read source file");
+
+        for byte in file.to_string_lossy().bytes().chain(content) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    println!("cargo:rustc-env=MABOY_BUILD_ID={}-{:016x}", commit, hash);
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) {
+    for entry in fs::read_dir(dir).expect("Could not read source directory") {
+        let path = entry.expect("Could not read source directory").path();
+
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Runs all test ROMs in a directory once for every order in which the components can
+//! be advanced within a machine cycle (see `maboy::StepOrder`), and reports the ROMs
+//! whose outcome depends on the order.
+//!
+//! ```text
+//! cargo run --release --example cycle_skew -- <rom dir> [options]
+//!
+//! --orders <list>     Orders to compare against the default, separated by spaces
+//!                     (e.g. "ppu,timer,serial,dma dma,timer,ppu,serial").
+//!                     Default: all 23 other orders
+//! --cache <file>      Result cache (default: <rom dir>/.maboy-test-cache)
+//! --no-cache          Don't use the result cache
+//! --threads <n>       Number of ROMs to run in parallel (default: number of cores)
+//! --timeout <secs>    Wall-clock timeout per ROM (default: 30)
+//! ```
+//!
+//! The ROMs that depend on the order are the ones that lock the default order in
+//! place: Any change to it shows up as a regression in `golden_tests`.
+//!
+//! Exits with code 1 if another order passes a ROM that the default order doesn't
+//! pass, since the default is supposed to be the most accurate order we know of.
+
+use maboy::test_harness::{SuiteResults, TestConfig, TestOutcome, TestSuite};
+use maboy::StepOrder;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+fn main() {
+    env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+
+    let rom_dir = PathBuf::from(args.next().unwrap_or_else(|| usage()));
+    let mut cache_file = Some(rom_dir.join(".maboy-test-cache"));
+    let mut orders = StepOrder::all();
+    let mut threads = None;
+    let mut config = TestConfig::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--orders" => {
+                let list = args.next().unwrap_or_else(|| usage());
+
+                orders = std::iter::once(Ok(StepOrder::DEFAULT))
+                    .chain(list.split_whitespace().map(str::parse))
+                    .collect::<Result<_, _>>()
+                    .unwrap_or_else(|_| usage());
+            }
+            "--cache" => cache_file = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--no-cache" => cache_file = None,
+            "--threads" => threads = Some(parse_arg(args.next())),
+            "--timeout" => config.timeout = Duration::from_secs(parse_arg(args.next())),
+            _ => usage(),
+        }
+    }
+
+    // The first entry is always the default order
+    let results: Vec<(StepOrder, SuiteResults)> = orders
+        .into_iter()
+        .map(|step_order| {
+            let config = TestConfig {
+                step_order,
+                ..config
+            };
+
+            let mut suite =
+                TestSuite::from_dir(&rom_dir, config).expect("Could not read ROM directory");
+            suite.set_cache_file(cache_file.clone());
+
+            if let Some(threads) = threads {
+                suite.set_threads(threads);
+            }
+
+            let results = suite.run();
+
+            println!(
+                "{:<24} {} passed, {} failed",
+                step_order.to_string(),
+                results.count(TestOutcome::Passed),
+                results.count(TestOutcome::Failed)
+            );
+
+            (step_order, results)
+        })
+        .collect();
+
+    let (_, default_results) = &results[0];
+    let mut better_order_found = false;
+
+    println!();
+
+    for (idx, default_result) in default_results.results.iter().enumerate() {
+        let deviations: Vec<(StepOrder, TestOutcome)> = results[1..]
+            .iter()
+            .map(|(order, results)| (*order, results.results[idx].outcome))
+            .filter(|&(_, outcome)| outcome != default_result.outcome)
+            .collect();
+
+        if deviations.is_empty() {
+            continue;
+        }
+
+        println!(
+            "ORDER-DEPENDENT {}: {} with the default order",
+            default_result.name, default_result.outcome
+        );
+
+        for (order, outcome) in deviations {
+            println!("    {} with {}", outcome, order);
+
+            if outcome == TestOutcome::Passed {
+                better_order_found = true;
+            }
+        }
+    }
+
+    if better_order_found {
+        println!("\nSome ROMs only pass with another order than the default one");
+        process::exit(1);
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<String>) -> T {
+    arg.and_then(|arg| arg.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: cycle_skew <rom dir> [--orders <list>] [--cache <file>] [--no-cache] [--threads <n>] [--timeout <secs>]");
+    process::exit(2);
+}
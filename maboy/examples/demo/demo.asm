@@ -0,0 +1,152 @@
+; The ROM that `cargo run --example demo` plays: A block on a checkerboard that is
+; moved around with the D-pad. Holding START inverts the background palette.
+;
+; Build it with RGBDS:
+;
+;     rgbasm -o demo.o demo.asm
+;     rgblink -o demo.gb demo.o
+;     rgbfix -v -p 0 -t "MABOY DEMO" -l 0x33 demo.gb
+;
+; Released under the same license as MaBoy.
+
+DEF rP1   EQU $FF00
+DEF rLCDC EQU $FF40
+DEF rLY   EQU $FF44
+DEF rBGP  EQU $FF47
+DEF rOBP0 EQU $FF48
+
+DEF hBlockY EQU $FF80
+DEF hBlockX EQU $FF81
+
+SECTION "Header", ROM0[$100]
+    nop
+    jp Start
+    ds $150 - @, 0                  ; Filled in by rgbfix
+
+SECTION "Main", ROM0[$150]
+Start:
+    di
+    ld sp, $FFFE
+
+    ; The LCD may only be turned off during VBlank
+.waitVBlank
+    ldh a, [rLY]
+    cp 144
+    jr c, .waitVBlank
+    xor a
+    ldh [rLCDC], a
+
+    ; Tile 0: Checkerboard (color 1), tile 1: Solid block (color 3)
+    ld hl, $8000
+    ld b, 4
+.checker
+    ld a, $AA
+    ld [hl+], a
+    xor a
+    ld [hl+], a
+    ld a, $55
+    ld [hl+], a
+    xor a
+    ld [hl+], a
+    dec b
+    jr nz, .checker
+    ld a, $FF
+    ld b, 16
+.block
+    ld [hl+], a
+    dec b
+    jr nz, .block
+
+    ; Background: Tile 0 everywhere
+    ld hl, $9800
+    ld bc, $400
+.clearMap
+    xor a
+    ld [hl+], a
+    dec bc
+    ld a, b
+    or c
+    jr nz, .clearMap
+
+    ; Sprites: Only sprite 0 shows the block, the others are off screen
+    ld hl, $FE00
+    ld b, 160
+    xor a
+.clearOam
+    ld [hl+], a
+    dec b
+    jr nz, .clearOam
+    ld a, 1
+    ld [$FE02], a
+
+    ; The block starts in the middle of the screen
+    ld a, 76 + 16
+    ldh [hBlockY], a
+    ld a, 76 + 8
+    ldh [hBlockX], a
+
+    ld a, %11100100
+    ldh [rBGP], a
+    ldh [rOBP0], a
+    ld a, %10010011                 ; LCD, tiles at $8000, sprites and background on
+    ldh [rLCDC], a
+
+    ; Once per frame, at the start of VBlank
+MainLoop:
+.waitNotVBlank
+    ldh a, [rLY]
+    cp 144
+    jr z, .waitNotVBlank
+.waitVBlank
+    ldh a, [rLY]
+    cp 144
+    jr nz, .waitVBlank
+
+    ; D-pad, inverted so a set bit means pressed: 0 = right, 1 = left, 2 = up, 3 = down
+    ld a, $20
+    ldh [rP1], a
+    ldh a, [rP1]
+    ldh a, [rP1]
+    cpl
+    and $0F
+    ld b, a
+
+    ld hl, hBlockX
+    bit 0, b
+    jr z, .notRight
+    inc [hl]
+.notRight
+    bit 1, b
+    jr z, .notLeft
+    dec [hl]
+.notLeft
+    ld hl, hBlockY
+    bit 2, b
+    jr z, .notUp
+    dec [hl]
+.notUp
+    bit 3, b
+    jr z, .notDown
+    inc [hl]
+.notDown
+
+    ; Buttons: Bit 3 is low while START is held
+    ld a, $10
+    ldh [rP1], a
+    ldh a, [rP1]
+    ldh a, [rP1]
+    ld b, a
+    ld a, $30
+    ldh [rP1], a
+    ld a, %11100100
+    bit 3, b
+    jr nz, .notStart
+    cpl
+.notStart
+    ldh [rBGP], a
+
+    ldh a, [hBlockY]
+    ld [$FE00], a
+    ldh a, [hBlockX]
+    ld [$FE01], a
+    jr MainLoop
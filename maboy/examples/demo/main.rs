@@ -0,0 +1,182 @@
+//! Plays back a recorded input script on a tiny homebrew ROM that comes with MaBoy
+//! (`demo.gb`, built from `demo.asm`), without a window: The block on the screen is
+//! moved around with the D-pad, and holding START inverts the background. The last
+//! frame is printed to the terminal, and screenshots can be saved as PNGs.
+//!
+//! This is also the shortest complete example of the headless API: Loading a ROM from
+//! memory, injecting input with an `InputProvider`, stepping frames and taking
+//! screenshots. Since the result is known in advance, it doubles as a smoke test and
+//! exits with code 1 if the block didn't end up where the script put it.
+//!
+//! ```text
+//! cargo run --release --example demo -- [options]
+//!
+//! --out <dir>         Save screenshots of every input change and the last frame
+//! ```
+
+use maboy::headless::{HeadlessRunner, FRAME_HEIGHT, FRAME_WIDTH};
+use maboy::*;
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const ROM: &[u8] = include_bytes!("demo.gb");
+const INPUTS: &str = include_str!("inputs.txt");
+
+/// How long the demo runs, which leaves some time after the last input change
+const FRAMES: u64 = 640;
+
+/// Where `demo.asm` keeps the position of the block
+const BLOCK_Y: u16 = 0xFF80;
+const BLOCK_X: u16 = 0xFF81;
+
+/// The OAM position of the block once the script has run: 10 pixels to the right of
+/// where it started
+const EXPECTED_POS: (u8, u8) = (76 + 16, 86 + 8);
+
+fn main() {
+    env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+    let mut out_dir = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            _ => usage(),
+        }
+    }
+
+    let script = parse_script(INPUTS);
+
+    let cartridge = match CartridgeVariant::from_rom(ROM.into()) {
+        Ok(CartridgeVariant::Rom(c)) => c,
+        Ok(_) => exit_with("Unexpected cartridge type", "demo.gb should be ROM only"),
+        Err(err) => exit_with("Could not load demo.gb", err),
+    };
+
+    let mut runner = HeadlessRunner::new(cartridge);
+
+    // Installed before the first frame, so input frames and frames line up: Every
+    // frame after the boot ROM starts with VBlank, and so does every input frame.
+    let provider_script = script.clone();
+    runner.emulator_mut().set_input_provider(move |frame: u64| {
+        let buttons = provider_script
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= frame)
+            .map_or(Buttons::empty(), |&(_, buttons)| buttons);
+
+        Some(buttons)
+    });
+
+    // A bit after every change, so the effect is visible
+    for &(frame, _) in &script {
+        runner.schedule_screenshot(frame + 10);
+    }
+    runner.schedule_screenshot(FRAMES);
+
+    runner.run_until_frame(FRAMES);
+
+    print_frame(runner.frame());
+
+    if let Some(out_dir) = out_dir {
+        fs::create_dir_all(&out_dir)
+            .unwrap_or_else(|err| exit_with("Could not create output directory", err));
+
+        for screenshot in runner.take_screenshots() {
+            let path = out_dir.join(format!("demo-{:04}.png", screenshot.frame));
+
+            save_png(&path, &screenshot.pixels)
+                .unwrap_or_else(|err| exit_with("Could not save screenshot", err));
+
+            println!("Saved {}", path.display());
+        }
+    }
+
+    let emu = runner.emulator();
+    let pos = (emu.peek(BLOCK_Y), emu.peek(BLOCK_X));
+
+    println!(
+        "Block at x = {}, y = {} after {} frames (frame hash {:016x})",
+        pos.1,
+        pos.0,
+        FRAMES,
+        runner.frame_hash()
+    );
+
+    if pos != EXPECTED_POS {
+        eprintln!(
+            "Expected the block at x = {}, y = {}",
+            EXPECTED_POS.1, EXPECTED_POS.0
+        );
+        process::exit(1);
+    }
+}
+
+/// Lines of `<frame> <buttons>`, where the buttons are held from that frame on. Empty
+/// lines and lines starting with `#` are ignored.
+fn parse_script(script: &str) -> Vec<(u64, Buttons)> {
+    script
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+
+            let frame = parts.next().and_then(|frame| frame.parse().ok());
+            let buttons = parts.next().and_then(|buttons| buttons.parse().ok());
+
+            match (frame, buttons) {
+                (Some(frame), Some(buttons)) => (frame, buttons),
+                _ => exit_with("Invalid line in input script", line),
+            }
+        })
+        .collect()
+}
+
+/// Shows the frame in 4 shades, with one character per 2×4 pixels
+fn print_frame(frame: &[MemPixel]) {
+    const SHADES: [char; 4] = ['#', '+', '.', ' '];
+
+    for y in (0..FRAME_HEIGHT).step_by(4) {
+        let line: String = (0..FRAME_WIDTH)
+            .step_by(2)
+            .map(|x| {
+                let pixel = frame[y * FRAME_WIDTH + x];
+                let luma = (pixel.r as u32 + pixel.g as u32 + pixel.b as u32) / 3;
+                SHADES[(luma * 4 / 256) as usize]
+            })
+            .collect();
+
+        println!("{}", line);
+    }
+}
+
+fn save_png(path: &Path, pixels: &[MemPixel]) -> Result<(), png::EncodingError> {
+    let file = File::create(path)?;
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        FRAME_WIDTH as u32,
+        FRAME_HEIGHT as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()?
+        .write_image_data(pixel_format::as_rgba8(pixels))
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: demo [--out <dir>]");
+    process::exit(2);
+}
+
+fn exit_with<E: Debug>(msg: &str, err: E) -> ! {
+    eprintln!("{} ({:?})", msg, err);
+    process::exit(1);
+}
@@ -0,0 +1,122 @@
+//! Runs all test ROMs in a directory and reports the ROMs that passed according to the
+//! golden results, but don't pass anymore.
+//!
+//! ```text
+//! cargo run --release --example golden_tests -- <rom dir> [options]
+//!
+//! --golden <file>     Golden results (default: <rom dir>/golden.txt)
+//! --update-golden     Overwrite the golden results with the results of this run
+//! --cache <file>      Result cache (default: <rom dir>/.maboy-test-cache)
+//! --no-cache          Don't use the result cache
+//! --threads <n>       Number of ROMs to run in parallel (default: number of cores)
+//! --timeout <secs>    Wall-clock timeout per ROM (default: 30)
+//! ```
+//!
+//! Before running any ROMs, the self-test programs (see `Emulator::self_test`) are run.
+//!
+//! Exits with code 1 if there are regressions.
+
+use maboy::test_harness::{run_self_test, SuiteResults, TestConfig, TestOutcome, TestSuite};
+use maboy::ClockRatio;
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+
+fn main() {
+    env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+
+    let rom_dir = PathBuf::from(args.next().unwrap_or_else(|| usage()));
+    let mut golden_file = rom_dir.join("golden.txt");
+    let mut cache_file = Some(rom_dir.join(".maboy-test-cache"));
+    let mut update_golden = false;
+    let mut threads = None;
+    let mut config = TestConfig::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--golden" => golden_file = PathBuf::from(args.next().unwrap_or_else(|| usage())),
+            "--update-golden" => update_golden = true,
+            "--cache" => cache_file = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--no-cache" => cache_file = None,
+            "--threads" => threads = Some(parse_arg(args.next())),
+            "--timeout" => config.timeout = Duration::from_secs(parse_arg(args.next())),
+            _ => usage(),
+        }
+    }
+
+    let self_test = run_self_test(config.step_order, ClockRatio::default());
+
+    for failure in self_test.failures() {
+        println!("SELF TEST: {}", failure);
+    }
+
+    if !self_test.passed() {
+        process::exit(1);
+    }
+
+    let mut suite = TestSuite::from_dir(&rom_dir, config).expect("Could not read ROM directory");
+    suite.set_cache_file(cache_file);
+
+    if let Some(threads) = threads {
+        suite.set_threads(threads);
+    }
+
+    let started = Instant::now();
+    let results = suite.run();
+
+    println!(
+        "Ran {} ROMs in {:.1}s ({} cached): {} passed, {} failed, {} other",
+        results.results.len(),
+        started.elapsed().as_secs_f32(),
+        results.results.iter().filter(|r| r.cached).count(),
+        results.count(TestOutcome::Passed),
+        results.count(TestOutcome::Failed),
+        results.results.len()
+            - results.count(TestOutcome::Passed)
+            - results.count(TestOutcome::Failed),
+    );
+
+    if update_golden {
+        results
+            .save_golden(&golden_file)
+            .expect("Could not write golden results");
+        println!("Golden results written to {:?}", golden_file);
+        return;
+    }
+
+    let golden = match SuiteResults::load_golden(&golden_file) {
+        Ok(golden) => golden,
+        Err(err) => {
+            println!(
+                "Could not load golden results from {:?} ({}). Run with --update-golden first.",
+                golden_file, err
+            );
+            return;
+        }
+    };
+
+    let regressions = results.regressions(&golden);
+
+    for regression in &regressions {
+        println!(
+            "REGRESSION {}: expected {}, got {}",
+            regression.name, regression.expected, regression.actual
+        );
+    }
+
+    if !regressions.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<String>) -> T {
+    arg.and_then(|arg| arg.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: golden_tests <rom dir> [--golden <file>] [--update-golden] [--cache <file>] [--no-cache] [--threads <n>] [--timeout <secs>]");
+    process::exit(2);
+}
@@ -0,0 +1,74 @@
+//! Measures how long the conversions in `maboy::pixel_format` take for a whole frame.
+//!
+//! ```text
+//! cargo run --release --example pixel_formats [-- <frames>]
+//! ```
+//!
+//! Every format converts the same frames (default: 100000), which use all four
+//! colors of the default palette, so the numbers are comparable.
+
+use maboy::pixel_format::{self, PixelFormat};
+use maboy::MemPixel;
+use std::hint::black_box;
+use std::process;
+use std::time::Instant;
+
+const FRAME_SIZE: usize = 160 * 144;
+
+fn main() {
+    let frames: u32 = match std::env::args().nth(1) {
+        Some(arg) => arg.parse().unwrap_or_else(|_| usage()),
+        None => 100_000,
+    };
+
+    let palette = [
+        MemPixel::new(239, 255, 222, 255),
+        MemPixel::new(173, 215, 148, 255),
+        MemPixel::new(82, 146, 115, 255),
+        MemPixel::new(24, 52, 66, 255),
+    ];
+
+    let frame: Vec<MemPixel> = (0..FRAME_SIZE)
+        .map(|idx| palette[(idx * 7 + idx / 160) % 4])
+        .collect();
+
+    for format in PixelFormat::ALL.iter().copied() {
+        let mut dst = vec![0u8; FRAME_SIZE * format.bytes_per_pixel()];
+        report(format.name(), frames, || {
+            format.convert(black_box(&frame), &mut dst)
+        });
+    }
+
+    // The typed variants skip the little-endian byte shuffling of `PixelFormat::convert`
+    let mut dst = vec![0u32; FRAME_SIZE];
+    report("xrgb8888 (u32)", frames, || {
+        pixel_format::to_xrgb8888(black_box(&frame), &mut dst)
+    });
+
+    let mut dst = vec![0u16; FRAME_SIZE];
+    report("rgb565 (u16)", frames, || {
+        pixel_format::to_rgb565(black_box(&frame), &mut dst)
+    });
+}
+
+fn report(name: &str, frames: u32, mut convert: impl FnMut()) {
+    let start = Instant::now();
+
+    for _ in 0..frames {
+        convert();
+    }
+
+    let per_frame = start.elapsed() / frames;
+
+    println!(
+        "{:<16} {:>8.2} µs/frame {:>8.2} GPixel/s",
+        name,
+        per_frame.as_secs_f64() * 1e6,
+        FRAME_SIZE as f64 / per_frame.as_secs_f64() / 1e9
+    );
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: pixel_formats [<frames>]");
+    process::exit(2);
+}
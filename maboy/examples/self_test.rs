@@ -0,0 +1,41 @@
+//! Runs the built-in self-test (see `Emulator::self_test`) and prints the report. No
+//! test ROMs required.
+//!
+//! ```text
+//! cargo run --release --example self_test -- [--cpu-clock <ratio>]
+//! ```
+//!
+//! Exits with code 1 if any self-test failed.
+
+use maboy::test_harness::run_self_test;
+use maboy::{ClockRatio, StepOrder};
+use std::process;
+
+fn main() {
+    env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+    let mut clock_ratio = ClockRatio::default();
+
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--cpu-clock", Some(ratio)) => {
+                clock_ratio = ratio.parse().unwrap_or_else(|_| usage());
+            }
+            _ => usage(),
+        }
+    }
+
+    let report = run_self_test(StepOrder::DEFAULT, clock_ratio);
+
+    println!("{}", report);
+
+    if !report.passed() {
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: self_test [--cpu-clock <ratio>]");
+    process::exit(2);
+}
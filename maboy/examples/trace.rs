@@ -0,0 +1,71 @@
+//! Runs a ROM without a window and writes an instruction trace (see
+//! `maboy::debug::TraceLogger`), e.g. to diff it against another emulator with
+//! Gameboy Doctor.
+//!
+//! ```text
+//! cargo run --release --example trace -- <rom file> <trace file> [options]
+//!
+//! --format <format>   doctor, text or binary (default: doctor)
+//! --frames <n>        How many frames to run (default: 600)
+//! ```
+
+use maboy::debug::{NoDbgLogger, TraceFormat, TraceLogger};
+use maboy::*;
+use std::fmt::Debug;
+use std::process;
+
+fn main() {
+    env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+
+    let rom_path = args.next().unwrap_or_else(|| usage());
+    let trace_path = args.next().unwrap_or_else(|| usage());
+
+    let mut format = TraceFormat::Doctor;
+    let mut frames = 600;
+
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+
+        match arg.as_str() {
+            "--format" => format = value.parse().unwrap_or_else(|_| usage()),
+            "--frames" => frames = value.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+
+    let cartridge = CartridgeVariant::from_file(&rom_path)
+        .unwrap_or_else(|err| exit_with("Could not open rom file", err));
+
+    let logger = TraceLogger::create(&trace_path, format)
+        .unwrap_or_else(|err| exit_with("Could not create trace file", err));
+
+    let instrs = run_emu(cartridge, logger, frames);
+
+    println!("Traced {} instructions to {}", instrs, trace_path);
+}
+
+fn run_emu(cartridge: CartridgeVariant, logger: TraceLogger<std::fs::File>, frames: u32) -> u64 {
+    let mut emu = Emulator::with_debugger(cartridge, logger, NoDbgLogger);
+
+    for _ in 0..frames {
+        emu.run_frame();
+    }
+
+    emu.cpu_logger_mut()
+        .flush()
+        .unwrap_or_else(|err| exit_with("Could not write trace", err));
+
+    emu.cpu_logger().instructions()
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: trace <rom file> <trace file> [--format doctor|text|binary] [--frames <n>]");
+    process::exit(2);
+}
+
+fn exit_with<E: Debug>(msg: &str, err: E) -> ! {
+    eprintln!("{} ({:?})", msg, err);
+    process::exit(1);
+}
@@ -113,6 +113,42 @@ impl TryFrom<u16> for IOReg {
     }
 }
 
+impl IOReg {
+    /// The global address of the register (inverse of `IOReg::try_from`)
+    pub fn addr(self) -> u16 {
+        use IOReg::*;
+
+        match self {
+            P1 => 0xFF00,
+            Serial(SerialReg::SB) => 0xFF01,
+            Serial(SerialReg::SC) => 0xFF02,
+            Timer(TimerReg::DIV) => 0xFF04,
+            Timer(TimerReg::TIMA) => 0xFF05,
+            Timer(TimerReg::TMA) => 0xFF06,
+            Timer(TimerReg::TAC) => 0xFF07,
+            IF => 0xFF0F,
+            Apu(ApuReg::NR14) => 0xFF14,
+            Apu(ApuReg::NR50) => 0xFF24,
+            Apu(ApuReg::NR51) => 0xFF25,
+            Apu(ApuReg::NR52) => 0xFF26,
+            Ppu(PpuReg::LCDC) => 0xFF40,
+            Ppu(PpuReg::LCDS) => 0xFF41,
+            Ppu(PpuReg::SCY) => 0xFF42,
+            Ppu(PpuReg::SCX) => 0xFF43,
+            Ppu(PpuReg::LY) => 0xFF44,
+            Ppu(PpuReg::LYC) => 0xFF45,
+            OamDma => 0xFF46,
+            Ppu(PpuReg::BGP) => 0xFF47,
+            Ppu(PpuReg::OBP0) => 0xFF48,
+            Ppu(PpuReg::OBP1) => 0xFF49,
+            Ppu(PpuReg::WY) => 0xFF4A,
+            Ppu(PpuReg::WX) => 0xFF4B,
+            BootRomDisable => 0xFF50,
+            Unimplemented(addr) => addr,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum SerialReg {
     SB, // 0xFF01
@@ -198,3 +234,172 @@ impl From<u16> for Addr {
         }
     }
 }
+
+impl Addr {
+    /// The global address that this local address was decoded from. Echo RAM addresses
+    /// map back into echo RAM, not into WRAM.
+    pub fn global(self) -> u16 {
+        use Addr::*;
+        use MemAddr::*;
+        use VideoMemAddr::*;
+
+        match self {
+            Mem(CROM(CRomAddr::CROM0(offset))) => offset,
+            Mem(CROM(CRomAddr::CROMn(offset))) => 0x4000 + offset,
+            Mem(CRAM(CRamAddr(offset))) => 0xA000 + offset,
+            Mem(WRAM(offset)) => 0xC000 + offset,
+            Mem(ECHO(offset)) => 0xE000 + offset,
+            Mem(HRAM(offset)) => 0xFF80 + offset,
+            VideoMem(TileData(offset)) => 0x8000 + offset,
+            VideoMem(TileMaps(offset)) => 0x9800 + offset,
+            VideoMem(OAM(offset)) => 0xFE00 + offset,
+            // There is no way to tell which address this was, so we return the first one
+            Unusable => 0xFEA0,
+            IO(reg) => reg.addr(),
+            IE => 0xFFFF,
+        }
+    }
+}
+
+/// Exhaustive checks of the decoder. The expected memory map is written down
+/// independently of the decoder, straight from the Pan Docs, so a mistake in the decoder
+/// doesn't slip through by being repeated here.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First and last address and name of every memory region. Local addresses are
+    /// offsets from the first address.
+    const REGIONS: [(u16, u16, &str); 12] = [
+        (0x0000, 0x3FFF, "CROM0"),
+        (0x4000, 0x7FFF, "CROMn"),
+        (0x8000, 0x97FF, "TileData"),
+        (0x9800, 0x9FFF, "TileMaps"),
+        (0xA000, 0xBFFF, "CRAM"),
+        (0xC000, 0xDFFF, "WRAM"),
+        (0xE000, 0xFDFF, "ECHO"),
+        (0xFE00, 0xFE9F, "OAM"),
+        (0xFEA0, 0xFEFF, "Unusable"),
+        (0xFF00, 0xFF7F, "IO"),
+        (0xFF80, 0xFFFE, "HRAM"),
+        (0xFFFF, 0xFFFF, "IE"),
+    ];
+
+    /// All IO registers that the decoder knows about. Everything else in the IO range
+    /// has to decode to [`IOReg::Unimplemented`].
+    const IO_REGISTERS: [(u16, &str); 25] = [
+        (0xFF00, "P1"),
+        (0xFF01, "Serial(SB)"),
+        (0xFF02, "Serial(SC)"),
+        (0xFF04, "Timer(DIV)"),
+        (0xFF05, "Timer(TIMA)"),
+        (0xFF06, "Timer(TMA)"),
+        (0xFF07, "Timer(TAC)"),
+        (0xFF0F, "IF"),
+        (0xFF14, "Apu(NR14)"),
+        (0xFF24, "Apu(NR50)"),
+        (0xFF25, "Apu(NR51)"),
+        (0xFF26, "Apu(NR52)"),
+        (0xFF40, "Ppu(LCDC)"),
+        (0xFF41, "Ppu(LCDS)"),
+        (0xFF42, "Ppu(SCY)"),
+        (0xFF43, "Ppu(SCX)"),
+        (0xFF44, "Ppu(LY)"),
+        (0xFF45, "Ppu(LYC)"),
+        (0xFF46, "OamDma"),
+        (0xFF47, "Ppu(BGP)"),
+        (0xFF48, "Ppu(OBP0)"),
+        (0xFF49, "Ppu(OBP1)"),
+        (0xFF4A, "Ppu(WY)"),
+        (0xFF4B, "Ppu(WX)"),
+        (0xFF50, "BootRomDisable"),
+    ];
+
+    /// Region name and local address of a decoded address
+    fn describe(addr: Addr) -> (&'static str, Option<u16>) {
+        use Addr::*;
+        use MemAddr::*;
+        use VideoMemAddr::*;
+
+        match addr {
+            Mem(CROM(CRomAddr::CROM0(offset))) => ("CROM0", Some(offset)),
+            Mem(CROM(CRomAddr::CROMn(offset))) => ("CROMn", Some(offset)),
+            Mem(CRAM(cram_addr)) => ("CRAM", Some(cram_addr.raw())),
+            Mem(WRAM(offset)) => ("WRAM", Some(offset)),
+            Mem(ECHO(offset)) => ("ECHO", Some(offset)),
+            Mem(HRAM(offset)) => ("HRAM", Some(offset)),
+            VideoMem(TileData(offset)) => ("TileData", Some(offset)),
+            VideoMem(TileMaps(offset)) => ("TileMaps", Some(offset)),
+            VideoMem(OAM(offset)) => ("OAM", Some(offset)),
+            Unusable => ("Unusable", None),
+            IO(_) => ("IO", None),
+            IE => ("IE", None),
+        }
+    }
+
+    #[test]
+    fn addresses_decode_to_region_and_offset() {
+        for &(first, last, expected_name) in REGIONS.iter() {
+            for addr in first..=last {
+                let (name, offset) = describe(Addr::from(addr));
+
+                assert_eq!(name, expected_name, "{:#06X}", addr);
+
+                if let Some(offset) = offset {
+                    assert_eq!(offset, addr - first, "{:#06X}", addr);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn regions_cover_address_space() {
+        let covered: u32 = REGIONS
+            .iter()
+            .map(|&(first, last, _)| (last - first) as u32 + 1)
+            .sum();
+
+        assert_eq!(covered, 0x10000);
+    }
+
+    #[test]
+    fn echo_ram_aliases_wram() {
+        for addr in 0xE000..=0xFDFF {
+            assert_eq!(
+                describe(Addr::from(addr)).1,
+                describe(Addr::from(addr - 0x2000)).1,
+                "{:#06X}",
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn addresses_map_back_to_themselves() {
+        for addr in 0..=u16::MAX {
+            let decoded = Addr::from(addr);
+
+            // Unusable addresses can't be told apart, so they don't round-trip
+            if !matches!(decoded, Addr::Unusable) {
+                assert_eq!(decoded.global(), addr, "{:?}", decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn io_registers_decode_like_reference() {
+        for addr in 0..=u16::MAX {
+            let expected = match IO_REGISTERS.iter().find(|&&(reg_addr, _)| reg_addr == addr) {
+                Some(&(_, name)) => Some(name.to_owned()),
+                None if (0xFF00..=0xFF7F).contains(&addr) => {
+                    Some(format!("Unimplemented({})", addr))
+                }
+                None => None,
+            };
+
+            let actual = IOReg::try_from(addr).ok().map(|reg| format!("{:?}", reg));
+
+            assert_eq!(actual, expected, "{:#06X}", addr);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Audio output of the emulator. The Game Boy produces a new (stereo) sample every
+//! machine cycle, which is way more than any frontend can (or wants to) play back.
+//! [`AudioOutput`] resamples this stream to the output rate requested by the
+//! frontend and stores the result in a ring buffer, which the frontend drains at
+//! its own pace via [`Emulator::drain_audio_samples`].
+//!
+//! For debugging, the output can also be recorded to WAV files, optionally with one
+//! file per channel (see [`Emulator::start_audio_recording`] and [`WavWriter`]).
+//!
+//! Before resampling, the four channels of the Game Boy are mixed according to an
+//! [`AudioConfig`], which can mute single channels, play only one of them (solo) and
+//! change the overall volume.
+//!
+//! There is no APU yet, so the emulator currently only produces silence. The
+//! infrastructure is already here so frontends can be written against the final API.
+
+mod wav;
+
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use wav::AudioRecorder;
+
+pub use wav::WavWriter;
+
+/// Machine cycles per second, which is also the rate at which the Game Boy produces
+/// audio samples
+const MCYCLE_RATE: u32 = 1_048_576;
+
+/// One of the four sound channels of the Game Boy
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AudioChannel {
+    /// Channel 1, the square wave with frequency sweep
+    Square1,
+    /// Channel 2, the square wave without sweep
+    Square2,
+    /// Channel 3, which plays the samples in wave RAM
+    Wave,
+    /// Channel 4, the noise generator
+    Noise,
+}
+
+impl AudioChannel {
+    pub const ALL: [AudioChannel; 4] = [
+        AudioChannel::Square1,
+        AudioChannel::Square2,
+        AudioChannel::Wave,
+        AudioChannel::Noise,
+    ];
+
+    /// Short lowercase name, e.g. for file names
+    pub fn name(self) -> &'static str {
+        match self {
+            AudioChannel::Square1 => "square1",
+            AudioChannel::Square2 => "square2",
+            AudioChannel::Wave => "wave",
+            AudioChannel::Noise => "noise",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Output of a single channel during one machine cycle (left, right)
+pub type ChannelSample = (i16, i16);
+
+/// How the channels are mixed into the audio output (see
+/// [`crate::Emulator::set_audio_config`]). This is purely a listening preference (or
+/// debugging aid); The sound registers that the game sees are unaffected.
+///
+/// Without an APU, every channel is silent, so this has no audible effect yet. The mixer
+/// already honours it, so the APU only has to feed the channels' output.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AudioConfig {
+    /// Whether each channel is audible, indexed like [`AudioChannel::ALL`]
+    pub channels: [bool; 4],
+    /// Multiplies the mixed output. 1.0 is the original volume, 0.0 mutes everything.
+    /// Values above 1.0 are allowed, but the output is clipped.
+    pub master_volume: f32,
+}
+
+impl AudioConfig {
+    pub fn is_enabled(&self, channel: AudioChannel) -> bool {
+        self.channels[channel.index()]
+    }
+
+    pub fn set_enabled(&mut self, channel: AudioChannel, enabled: bool) {
+        self.channels[channel.index()] = enabled;
+    }
+
+    /// Mutes every channel except the given one
+    pub fn solo(&mut self, channel: AudioChannel) {
+        for other in AudioChannel::ALL.iter().copied() {
+            self.set_enabled(other, other == channel);
+        }
+    }
+
+    /// Whether the given channel is the only one that is audible
+    pub fn is_solo(&self, channel: AudioChannel) -> bool {
+        AudioChannel::ALL
+            .iter()
+            .all(|&other| self.is_enabled(other) == (other == channel))
+    }
+
+    /// Makes all channels audible again (the master volume is kept)
+    pub fn enable_all(&mut self) {
+        self.channels = [true; 4];
+    }
+
+    /// Mixes the output of the four channels into a single stereo sample
+    fn mix(&self, channels: &[ChannelSample; 4]) -> (i32, i32) {
+        let (left, right) = channels
+            .iter()
+            .zip(self.channels.iter())
+            .filter(|(_, &enabled)| enabled)
+            .fold((0i32, 0i32), |(left, right), (sample, _)| {
+                (left + sample.0 as i32, right + sample.1 as i32)
+            });
+
+        let clip = |value: i32| {
+            ((value as f32 * self.master_volume) as i32).clamp(i16::MIN as i32, i16::MAX as i32)
+        };
+
+        (clip(left), clip(right))
+    }
+}
+
+impl Default for AudioConfig {
+    /// All channels at their original volume
+    fn default() -> Self {
+        AudioConfig {
+            channels: [true; 4],
+            master_volume: 1.0,
+        }
+    }
+}
+
+/// Turns the stream of one sample per machine cycle into a stream at a lower sample
+/// rate, by averaging all input samples that fall into the same output sample (box
+/// filter)
+struct Resampler {
+    sample_rate: u32,
+    /// Advances by `sample_rate` every machine cycle. Once it reaches [`MCYCLE_RATE`],
+    /// an output sample is produced.
+    phase: u32,
+    /// Sum of all input samples since the last output sample
+    acc_left: i32,
+    acc_right: i32,
+    acc_count: i32,
+}
+
+impl Resampler {
+    fn new(sample_rate: u32) -> Resampler {
+        assert!(
+            sample_rate > 0 && sample_rate <= MCYCLE_RATE,
+            "Audio sample rate must be between 1 and {} Hz",
+            MCYCLE_RATE
+        );
+
+        Resampler {
+            sample_rate,
+            phase: 0,
+            acc_left: 0,
+            acc_right: 0,
+            acc_count: 0,
+        }
+    }
+
+    /// Feeds the sample of one machine cycle. Returns an output sample whenever one is
+    /// complete.
+    fn push(&mut self, left: i32, right: i32) -> Option<(i16, i16)> {
+        self.acc_left += left;
+        self.acc_right += right;
+        self.acc_count += 1;
+
+        self.phase += self.sample_rate;
+
+        if self.phase < MCYCLE_RATE {
+            return None;
+        }
+
+        self.phase -= MCYCLE_RATE;
+
+        let left = (self.acc_left / self.acc_count) as i16;
+        let right = (self.acc_right / self.acc_count) as i16;
+
+        self.acc_left = 0;
+        self.acc_right = 0;
+        self.acc_count = 0;
+
+        Some((left, right))
+    }
+}
+
+/// Resamples the audio output of the Game Boy and buffers it for the frontend.
+/// Disabled (and practically free) until an output rate is set. Can also record the
+/// output to WAV files (see [`AudioRecorder`]).
+pub struct AudioOutput {
+    /// Produces samples at the output rate, or `None` if audio output is disabled
+    resampler: Option<Resampler>,
+    /// How the channels are mixed
+    config: AudioConfig,
+    /// Interleaved stereo samples (left, right, left, right, ...)
+    buffer: VecDeque<i16>,
+    /// Maximum number of values (not stereo samples!) kept in [`buffer`]
+    capacity: usize,
+    /// Started via [`Emulator::start_audio_recording`]
+    recorder: Option<AudioRecorder>,
+    /// The step (0 - 7) of the frame sequencer, see [`AudioOutput::clock_frame_sequencer`]
+    frame_sequencer_step: u8,
+}
+
+impl AudioOutput {
+    pub fn new() -> AudioOutput {
+        AudioOutput {
+            resampler: None,
+            config: AudioConfig::default(),
+            buffer: VecDeque::new(),
+            capacity: 0,
+            recorder: None,
+            frame_sequencer_step: 0,
+        }
+    }
+
+    /// Called with the clocks from the timer's system counter (see
+    /// [`crate::timer::Timer::take_frame_sequencer_clocks`]). The frame sequencer clocks
+    /// the length counters, volume envelopes and frequency sweep of the channels, so
+    /// resetting DIV changes their timing as well. Without an APU, only the step is
+    /// tracked.
+    pub fn clock_frame_sequencer(&mut self, clocks: u32) {
+        self.frame_sequencer_step = ((self.frame_sequencer_step as u32 + clocks) % 8) as u8;
+    }
+
+    /// See [`Emulator::set_audio_sample_rate`]
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.resampler = sample_rate.map(Resampler::new);
+
+        // Keep a quarter of a second of audio around. If the frontend doesn't drain
+        // the buffer fast enough, the oldest samples are dropped.
+        self.capacity = sample_rate.map_or(0, |rate| (rate as usize / 4) * 2);
+
+        self.buffer.clear();
+        self.buffer.shrink_to_fit();
+        self.buffer.reserve(self.capacity);
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.resampler
+            .as_ref()
+            .map(|resampler| resampler.sample_rate)
+    }
+
+    /// See [`Emulator::set_audio_config`]. Takes effect right away, samples that are
+    /// already buffered are kept.
+    pub fn set_config(&mut self, config: AudioConfig) {
+        self.config = config;
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    /// See [`Emulator::start_audio_recording`]. A recording that is already running is
+    /// finished first.
+    pub fn start_recording(
+        &mut self,
+        path: &Path,
+        sample_rate: u32,
+        stems: bool,
+    ) -> io::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(AudioRecorder::start(path, sample_rate, stems)?);
+        Ok(())
+    }
+
+    /// See [`Emulator::stop_audio_recording`]
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Feeds the samples that the channels produced during the current machine cycle
+    pub fn advance_mcycle(&mut self, channels: &[ChannelSample; 4]) {
+        if self.resampler.is_none() && self.recorder.is_none() {
+            return;
+        }
+
+        let (left, right) = self.config.mix(channels);
+
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.push((left, right), channels) {
+                log::error!("Audio recording failed, stopping it: {}", err);
+                self.recorder = None;
+            }
+        }
+
+        let sample = match &mut self.resampler {
+            Some(resampler) => resampler.push(left, right),
+            None => None,
+        };
+
+        if let Some((left, right)) = sample {
+            if self.buffer.len() + 2 > self.capacity {
+                self.buffer.pop_front();
+                self.buffer.pop_front();
+            }
+
+            self.buffer.push_back(left);
+            self.buffer.push_back(right);
+        }
+    }
+
+    /// Number of buffered values (two per stereo sample)
+    pub fn available(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// See [`Emulator::drain_audio_samples`]
+    pub fn drain(&mut self, target: &mut [i16]) -> usize {
+        // Never split a stereo sample
+        let count = self.buffer.len().min(target.len() & !1);
+
+        for (dst, src) in target.iter_mut().zip(self.buffer.drain(..count)) {
+            *dst = src;
+        }
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNELS: [ChannelSample; 4] = [(100, -100), (200, 0), (400, 0), (800, 0)];
+
+    #[test]
+    fn mix_honours_muted_channels_and_volume() {
+        let mut config = AudioConfig::default();
+        assert_eq!(config.mix(&CHANNELS), (1500, -100));
+
+        config.set_enabled(AudioChannel::Noise, false);
+        assert_eq!(config.mix(&CHANNELS), (700, -100));
+
+        config.solo(AudioChannel::Wave);
+        assert!(config.is_solo(AudioChannel::Wave));
+        assert_eq!(config.mix(&CHANNELS), (400, 0));
+
+        config.enable_all();
+        config.master_volume = 0.5;
+        assert_eq!(config.mix(&CHANNELS), (750, -50));
+    }
+
+    #[test]
+    fn mix_clips() {
+        let config = AudioConfig {
+            master_volume: 100.0,
+            ..AudioConfig::default()
+        };
+
+        assert_eq!(config.mix(&CHANNELS), (i16::MAX as i32, -10000));
+    }
+}
@@ -0,0 +1,155 @@
+//! Writes audio to WAV files (16-bit stereo PCM), see [`WavWriter`] and
+//! [`AudioRecorder`]
+
+use super::{AudioChannel, ChannelSample, Resampler};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Size of the RIFF header that precedes the samples
+const HEADER_LEN: u32 = 44;
+
+/// Writes 16-bit stereo samples to a WAV file. The header contains the length of the
+/// data, so it is written with a length of 0 first and fixed by [`WavWriter::finish`].
+/// Files that were never finished can still be opened by most players.
+pub struct WavWriter<W: Write + Seek = BufWriter<File>> {
+    writer: W,
+    /// Number of bytes of sample data written so far
+    data_len: u32,
+}
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<WavWriter> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        const CHANNELS: u16 = 2;
+        const BYTES_PER_SAMPLE: u16 = 2;
+
+        let block_align = CHANNELS * BYTES_PER_SAMPLE;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(HEADER_LEN - 8).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&CHANNELS.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            writer,
+            data_len: 0,
+        })
+    }
+
+    pub fn write_sample(&mut self, left: i16, right: i16) -> io::Result<()> {
+        self.writer.write_all(&left.to_le_bytes())?;
+        self.writer.write_all(&right.to_le_bytes())?;
+        self.data_len += 4;
+        Ok(())
+    }
+
+    /// Writes the final length into the header
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+/// A WAV file with its own resampler
+struct Track {
+    writer: WavWriter,
+    resampler: Resampler,
+}
+
+impl Track {
+    fn create(path: &Path, sample_rate: u32) -> io::Result<Track> {
+        Ok(Track {
+            writer: WavWriter::create(path, sample_rate)?,
+            resampler: Resampler::new(sample_rate),
+        })
+    }
+
+    fn push(&mut self, left: i32, right: i32) -> io::Result<()> {
+        match self.resampler.push(left, right) {
+            Some((left, right)) => self.writer.write_sample(left, right),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Records the mixed output (as the frontend gets it, see [`super::AudioConfig`]) and,
+/// optionally, the unmixed output of every channel (stems) to WAV files. The stems are
+/// written next to the main file, e.g. `out.wave.wav` for `out.wav`.
+pub(super) struct AudioRecorder {
+    mix: Track,
+    /// Indexed like [`AudioChannel::ALL`]
+    stems: Vec<Track>,
+}
+
+impl AudioRecorder {
+    pub fn start(path: &Path, sample_rate: u32, stems: bool) -> io::Result<AudioRecorder> {
+        let mix = Track::create(path, sample_rate)?;
+
+        let stems = if stems {
+            AudioChannel::ALL
+                .iter()
+                .map(|channel| Track::create(&stem_path(path, *channel), sample_rate))
+                .collect::<io::Result<_>>()?
+        } else {
+            Vec::new()
+        };
+
+        log::info!("Recording audio to {:?}", path);
+
+        Ok(AudioRecorder { mix, stems })
+    }
+
+    pub fn push(&mut self, mix: (i32, i32), channels: &[ChannelSample; 4]) -> io::Result<()> {
+        self.mix.push(mix.0, mix.1)?;
+
+        for (stem, sample) in self.stems.iter_mut().zip(channels.iter()) {
+            stem.push(sample.0 as i32, sample.1 as i32)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.mix.writer.finish()?;
+
+        for stem in self.stems {
+            stem.writer.finish()?;
+        }
+
+        log::info!("Finished audio recording");
+        Ok(())
+    }
+}
+
+/// `out.wav` -> `out.square1.wav`
+fn stem_path(path: &Path, channel: AudioChannel) -> PathBuf {
+    let extension = path
+        .extension()
+        .map_or("wav".into(), |ext| ext.to_string_lossy());
+
+    path.with_extension(format!("{}.{}", channel.name(), extension))
+}
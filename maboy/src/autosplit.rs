@@ -0,0 +1,392 @@
+//! An auto-splitter for speedruns: It watches game variables (see [`crate::gamedb`])
+//! once per frame and tells a [LiveSplit](https://livesplit.org) timer when to start,
+//! split and reset.
+//!
+//! The conditions are defined in a splits file, which refers to variables by their
+//! names in the game's variable definitions:
+//!
+//! ```text
+//! # Pokémon Red, any% (excerpt)
+//! start  map_id == 0 && player_x == 5
+//! split  badges >= 1
+//! split  map_id == 118
+//! reset  map_id == 0 && player_x == 3
+//! ```
+//!
+//! - `start` starts the run, `reset` aborts it. There can be any number of each, and
+//!   any one of them triggers.
+//! - `split` lines are the splits of the run, in order. Only the next split can trigger.
+//! - Conditions compare a variable with a number (decimal, or hex with `0x` or `$`)
+//!   via `==`, `!=`, `<`, `<=`, `>` or `>=`. Comparisons can be chained with `&&`.
+//!
+//! A condition only triggers when it *becomes* true, i.e. it has to be false for at
+//! least one frame first. This keeps a split from triggering right away when its
+//! condition is still true from the previous split.
+//!
+//! The timer is controlled through the LiveSplit Server component (see
+//! [`LiveSplitClient`]), but the [`AutoSplitter`] itself doesn't care where its
+//! [`SplitEvent`]s go.
+
+use crate::gamedb::{GameDb, GameVar};
+use std::fs;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    /// Longer operators first, so `<=` isn't mistaken for `<`
+    const ALL: [(&'static str, CmpOp); 6] = [
+        ("==", CmpOp::Eq),
+        ("!=", CmpOp::Ne),
+        ("<=", CmpOp::Le),
+        (">=", CmpOp::Ge),
+        ("<", CmpOp::Lt),
+        (">", CmpOp::Gt),
+    ];
+
+    fn apply(self, lhs: i64, rhs: i64) -> bool {
+        match self {
+            CmpOp::Eq => lhs == rhs,
+            CmpOp::Ne => lhs != rhs,
+            CmpOp::Lt => lhs < rhs,
+            CmpOp::Le => lhs <= rhs,
+            CmpOp::Gt => lhs > rhs,
+            CmpOp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub var: GameVar,
+    pub op: CmpOp,
+    pub value: i64,
+}
+
+/// Comparisons that all have to be true
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub comparisons: Vec<Comparison>,
+}
+
+impl Condition {
+    /// Parses a single condition like `map_id == 118 && player_x > 3`, outside of a
+    /// splits file. The variables are looked up in `game_db`.
+    pub fn parse(text: &str, game_db: &GameDb) -> Result<Condition, AutoSplitError> {
+        parse_condition(text, game_db, 1)
+    }
+
+    pub fn eval<F: Fn(u16) -> u8>(&self, peek: F) -> bool {
+        self.comparisons
+            .iter()
+            .all(|cmp| cmp.op.apply(cmp.var.read(&peek), cmp.value))
+    }
+}
+
+/// What the timer should do
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SplitEvent {
+    Start,
+    /// The split with this index (starting at 0) was reached
+    Split(usize),
+    Reset,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RunState {
+    NotRunning,
+    /// The index of the next split
+    Running(usize),
+    /// All splits were reached
+    Finished,
+}
+
+#[derive(Debug)]
+pub enum AutoSplitError {
+    Io(io::Error),
+    /// A line could not be parsed (line numbers start at 1)
+    InvalidLine {
+        line: usize,
+        content: String,
+    },
+    /// A condition refers to a variable that the [`GameDb`] doesn't define
+    UnknownVar {
+        line: usize,
+        name: String,
+    },
+}
+
+impl From<io::Error> for AutoSplitError {
+    fn from(err: io::Error) -> Self {
+        AutoSplitError::Io(err)
+    }
+}
+
+/// A condition that only triggers when it becomes true (see the
+/// [module documentation](self))
+#[derive(Clone)]
+struct Trigger {
+    condition: Condition,
+    /// Whether the condition was false the last time it was checked
+    armed: bool,
+}
+
+impl Trigger {
+    fn new(condition: Condition) -> Trigger {
+        Trigger {
+            condition,
+            armed: false,
+        }
+    }
+
+    fn check<F: Fn(u16) -> u8>(&mut self, peek: F) -> bool {
+        let fulfilled = self.condition.eval(peek);
+        let triggered = fulfilled && self.armed;
+        self.armed = !fulfilled;
+        triggered
+    }
+}
+
+/// Evaluates the conditions of a splits file. Call [`AutoSplitter::update`] once per
+/// frame.
+#[derive(Clone)]
+pub struct AutoSplitter {
+    starts: Vec<Trigger>,
+    splits: Vec<Trigger>,
+    resets: Vec<Trigger>,
+    state: RunState,
+}
+
+impl AutoSplitter {
+    /// Parses a splits file (see the [module documentation](self) for the format). The
+    /// variables are looked up in `game_db`.
+    pub fn parse(text: &str, game_db: &GameDb) -> Result<AutoSplitter, AutoSplitError> {
+        let mut splitter = AutoSplitter {
+            starts: Vec::new(),
+            splits: Vec::new(),
+            resets: Vec::new(),
+            state: RunState::NotRunning,
+        };
+
+        for (idx, line) in text.lines().enumerate() {
+            let content = line.trim();
+
+            if content.is_empty() || content.starts_with('#') || content.starts_with(';') {
+                continue;
+            }
+
+            let invalid = || AutoSplitError::InvalidLine {
+                line: idx + 1,
+                content: content.to_owned(),
+            };
+
+            let (kind, condition) = content
+                .split_once(char::is_whitespace)
+                .ok_or_else(invalid)?;
+
+            let condition = parse_condition(condition, game_db, idx + 1)?;
+
+            match kind {
+                "start" => splitter.starts.push(Trigger::new(condition)),
+                "split" => splitter.splits.push(Trigger::new(condition)),
+                "reset" => splitter.resets.push(Trigger::new(condition)),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(splitter)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, game_db: &GameDb) -> Result<AutoSplitter, AutoSplitError> {
+        AutoSplitter::parse(&fs::read_to_string(path)?, game_db)
+    }
+
+    pub fn state(&self) -> RunState {
+        self.state
+    }
+
+    pub fn split_count(&self) -> usize {
+        self.splits.len()
+    }
+
+    /// Checks the conditions against the current memory contents (`peek` returns the
+    /// byte at an address, e.g. via [`crate::Emulator::peek`]). Returns what the timer
+    /// should do, if anything.
+    pub fn update<F: Fn(u16) -> u8>(&mut self, peek: F) -> Option<SplitEvent> {
+        // Every trigger is checked every frame, so it notices when its condition turns
+        // false, even if it doesn't matter right now
+        let reset = check_any(&mut self.resets, &peek);
+        let start = check_any(&mut self.starts, &peek);
+
+        let splits: Vec<bool> = self
+            .splits
+            .iter_mut()
+            .map(|trigger| trigger.check(&peek))
+            .collect();
+
+        let split = match self.state {
+            RunState::Running(idx) => splits[idx],
+            _ => false,
+        };
+
+        let event = match self.state {
+            RunState::Running(_) | RunState::Finished if reset => SplitEvent::Reset,
+            RunState::NotRunning if start => SplitEvent::Start,
+            RunState::Running(idx) if split => SplitEvent::Split(idx),
+            _ => return None,
+        };
+
+        self.state = match event {
+            SplitEvent::Reset => RunState::NotRunning,
+            SplitEvent::Start if self.splits.is_empty() => RunState::Finished,
+            SplitEvent::Start => RunState::Running(0),
+            SplitEvent::Split(idx) if idx + 1 == self.splits.len() => RunState::Finished,
+            SplitEvent::Split(idx) => RunState::Running(idx + 1),
+        };
+
+        log::info!("Auto-splitter: {:?}", event);
+        Some(event)
+    }
+}
+
+fn check_any<F: Fn(u16) -> u8>(triggers: &mut [Trigger], peek: &F) -> bool {
+    // No short-circuiting, since every trigger has to see every frame
+    let mut any = false;
+
+    for trigger in triggers {
+        any |= trigger.check(peek);
+    }
+
+    any
+}
+
+/// `var op value && var op value && ...`
+fn parse_condition(text: &str, game_db: &GameDb, line: usize) -> Result<Condition, AutoSplitError> {
+    let invalid = || AutoSplitError::InvalidLine {
+        line,
+        content: text.trim().to_owned(),
+    };
+
+    let comparisons = text
+        .split("&&")
+        .map(|cmp| -> Result<Comparison, AutoSplitError> {
+            let cmp = cmp.trim();
+
+            let (op_idx, op_str, op) = CmpOp::ALL
+                .iter()
+                .filter_map(|&(op_str, op)| cmp.find(op_str).map(|idx| (idx, op_str, op)))
+                .min_by_key(|&(idx, _, _)| idx)
+                .ok_or_else(invalid)?;
+
+            let name = cmp[..op_idx].trim();
+            let value = parse_value(cmp[op_idx + op_str.len()..].trim()).ok_or_else(invalid)?;
+
+            let var = game_db
+                .get(name)
+                .cloned()
+                .ok_or_else(|| AutoSplitError::UnknownVar {
+                    line,
+                    name: name.to_owned(),
+                })?;
+
+            Ok(Comparison { var, op, value })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Condition { comparisons })
+}
+
+fn parse_value(text: &str) -> Option<i64> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+
+    let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/// The default port of the LiveSplit Server component
+pub const LIVESPLIT_PORT: u16 = 16834;
+
+/// Talks to the LiveSplit Server component, which has to be added to the LiveSplit
+/// layout and started (right-click → Control → Start Server).
+pub struct LiveSplitClient {
+    stream: TcpStream,
+}
+
+impl LiveSplitClient {
+    /// Connects to LiveSplit, e.g. at `("localhost", LIVESPLIT_PORT)`
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<LiveSplitClient> {
+        let stream = TcpStream::connect(addr)?;
+
+        // Commands are tiny and timing is the whole point
+        stream.set_nodelay(true)?;
+
+        Ok(LiveSplitClient { stream })
+    }
+
+    pub fn send(&mut self, event: SplitEvent) -> io::Result<()> {
+        let command = match event {
+            SplitEvent::Start => "starttimer",
+            SplitEvent::Split(_) => "split",
+            SplitEvent::Reset => "reset",
+        };
+
+        self.stream.write_all(format!("{}\r\n", command).as_bytes())
+    }
+}
+
+/// An [`AutoSplitter`] that sends its events straight to LiveSplit, which is what
+/// frontends usually want. Keeps splitting (and logging the events) if LiveSplit can't
+/// be reached, so a broken connection doesn't interrupt the game.
+pub struct LiveSplitSession {
+    splitter: AutoSplitter,
+    client: Option<LiveSplitClient>,
+}
+
+impl LiveSplitSession {
+    pub fn connect<A: ToSocketAddrs>(splitter: AutoSplitter, addr: A) -> LiveSplitSession {
+        let client = LiveSplitClient::connect(addr)
+            .map_err(|err| log::warn!("Could not connect to LiveSplit ({:?})", err))
+            .ok();
+
+        LiveSplitSession { splitter, client }
+    }
+
+    pub fn splitter(&self) -> &AutoSplitter {
+        &self.splitter
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// See [`AutoSplitter::update`]
+    pub fn update<F: Fn(u16) -> u8>(&mut self, peek: F) -> Option<SplitEvent> {
+        let event = self.splitter.update(peek)?;
+
+        if let Some(client) = self.client.as_mut() {
+            if let Err(err) = client.send(event) {
+                log::warn!("Lost connection to LiveSplit ({:?})", err);
+                self.client = None;
+            }
+        }
+
+        Some(event)
+    }
+}
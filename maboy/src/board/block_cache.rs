@@ -0,0 +1,179 @@
+//! The cache of the cached interpreter (see [`crate::Emulator::set_block_cache`]).
+//!
+//! Straight-line code in ROM is decoded into blocks, which end after the first jump,
+//! call or return (or a maximum number of instructions). Blocks are keyed by the bank
+//! and address they start at. While the CPU executes a block, its opcodes and immediate
+//! operands come straight from the block, which skips the address decoding, cartridge
+//! banking and debugging hooks of a regular memory read. Each fetch still takes its
+//! machine cycle, so the timing is exactly the same as without the cache.
+//!
+//! ROM never changes, so cached blocks never become stale. Only the block that is
+//! currently executing has to be left when the game switches ROM banks, since the code
+//! that follows might be in a different bank now.
+
+use crate::debug::{disasm, BankedAddr};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Blocks end after this many instructions, even if there is no jump
+const MAX_BLOCK_INSTRS: usize = 32;
+
+/// A run of instructions without jumps (except for the last one)
+struct Block {
+    /// The address of the first instruction
+    start: u16,
+    /// The bytes of all instructions, including operands
+    bytes: Vec<u8>,
+}
+
+/// See the [module documentation](self)
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: HashMap<BankedAddr, Arc<Block>>,
+    /// The block that the CPU is currently executing
+    current: Option<Arc<Block>>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The byte at `addr`, if it is part of the current block
+    #[inline]
+    pub fn current_byte(&self, addr: u16) -> Option<u8> {
+        let block = self.current.as_ref()?;
+
+        block
+            .bytes
+            .get(addr.wrapping_sub(block.start) as usize)
+            .copied()
+    }
+
+    /// Makes the block that starts at `addr` the current one, and returns its first
+    /// byte. If the block isn't cached yet, it is decoded first, reading ROM via `read`.
+    /// Blocks don't extend beyond `region_end`, the last address of the ROM region that
+    /// they start in. Returns `None` if not even one instruction fits.
+    pub fn enter_block<F: Fn(u16) -> u8>(
+        &mut self,
+        addr: BankedAddr,
+        region_end: u16,
+        read: F,
+    ) -> Option<u8> {
+        let block = self
+            .blocks
+            .entry(addr)
+            .or_insert_with(|| Arc::new(decode_block(addr.addr, region_end, read)));
+
+        self.current = Some(Arc::clone(block)).filter(|block| !block.bytes.is_empty());
+        self.current_byte(addr.addr)
+    }
+
+    /// Stops serving bytes from the current block, e.g. because the ROM bank changed
+    pub fn leave_block(&mut self) {
+        self.current = None;
+    }
+
+    /// The number of cached blocks
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+fn decode_block<F: Fn(u16) -> u8>(start: u16, region_end: u16, read: F) -> Block {
+    let mut bytes = Vec::new();
+    let mut addr = start;
+
+    for _ in 0..MAX_BLOCK_INSTRS {
+        let opcode = read(addr);
+        let len = disasm::instr_len(opcode) as u16;
+
+        // An instruction that reaches into the next region isn't cached. This is checked
+        // before reading its operands, which might not even be in ROM.
+        if region_end - addr < len - 1 {
+            break;
+        }
+
+        let instr_bytes: Vec<u8> = std::iter::once(opcode)
+            .chain((1..len).map(|i| read(addr + i)))
+            .collect();
+        let instr = disasm::decode(&instr_bytes, addr).expect("The whole instruction was read");
+
+        bytes.extend_from_slice(&instr.bytes);
+
+        if instr.changes_control_flow || instr.illegal || instr.next_addr() > region_end {
+            break;
+        }
+
+        addr = instr.next_addr();
+    }
+
+    Block { start, bytes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::RomBuilder;
+    use crate::{CartridgeVariant, Emulator};
+
+    #[test]
+    fn decode_block_never_reads_past_region_end() {
+        // NOP, NOP, then `LD SP, d16` at 0x7FFF whose operands would lie beyond ROM
+        let read = |addr: u16| match addr {
+            0x7FFD | 0x7FFE => 0x00,
+            0x7FFF => 0x31,
+            _ => panic!("Read from {:#06X} outside of the region", addr),
+        };
+
+        let block = decode_block(0x7FFD, 0x7FFF, read);
+
+        assert_eq!(block.bytes, [0x00, 0x00]);
+    }
+
+    #[test]
+    fn blocks_follow_mbc1_bank0_remapping() {
+        // MBC1 with 1 MiB ROM: In advanced banking mode, the upper bank bits select
+        // bank 32 at 0x0000-0x3FFF instead of bank 0
+        let mut program = RomBuilder::with_mbc(0x01, 64, 0x00);
+
+        // Start of the Nintendo logo, so the ROM isn't detected as an MBC1 multicart
+        program.bytes_at(0x104, &[0xCE, 0xED]);
+
+        // LD A,$11; RET in bank 0, LD A,$22; RET in bank 32
+        program.bytes_at(0x0000, &[0x3E, 0x11, 0xC9]);
+        program.bytes_at(32 * 0x4000, &[0x3E, 0x22, 0xC9]);
+
+        // The program is mirrored in bank 32, since it runs from 0x0000-0x3FFF as well
+        for offset in [0, 32 * 0x4000] {
+            #[rustfmt::skip]
+            program.bytes_at(offset + 0x150, &[
+                // CALL $0000; LDH ($80),A
+                0xCD, 0x00, 0x00, 0xE0, 0x80,
+                // LD A,1; LD ($6000),A; LD ($4000),A
+                0x3E, 0x01, 0xEA, 0x00, 0x60, 0xEA, 0x00, 0x40,
+                // CALL $0000; LDH ($81),A
+                0xCD, 0x00, 0x00, 0xE0, 0x81,
+                // JR -2
+                0x18, 0xFE,
+            ]);
+        }
+        let done = 0x150 + 18;
+
+        let cartridge = CartridgeVariant::from_bytes(program.build()).unwrap();
+        let mut emu = Emulator::new(cartridge).with_boot_rom(None);
+        emu.set_block_cache(true);
+
+        while emu.cpu.reg.pc != done {
+            assert!(emu.mcycles() < 10_000, "Program didn't finish");
+            emu.emulate_step();
+        }
+
+        assert_eq!(emu.peek(0xFF80), 0x11);
+        assert_eq!(emu.peek(0xFF81), 0x22);
+    }
+}
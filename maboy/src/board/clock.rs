@@ -0,0 +1,131 @@
+//! Lets the CPU run at a different speed than the rest of the system. This is purely
+//! a debugging tool (and a toy): Real hardware has one clock for everything.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// How fast the CPU runs compared to the rest of the system (PPU, timer, serial port,
+/// OAM DMA and audio), as a fraction `cpu / system`. A ratio of 2/1 lets the CPU execute
+/// twice as many machine cycles per frame, which removes slowdown in many games. A
+/// ratio of 1/2 halves the CPU speed, which is useful to find code that only works by
+/// accident of timing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ClockRatio {
+    cpu: u32,
+    system: u32,
+}
+
+impl ClockRatio {
+    /// The ratio of the real hardware
+    pub const NORMAL: ClockRatio = ClockRatio { cpu: 1, system: 1 };
+
+    /// Returns `None` if any of the parts is zero. The fraction is reduced.
+    pub fn new(cpu: u32, system: u32) -> Option<ClockRatio> {
+        if cpu == 0 || system == 0 {
+            return None;
+        }
+
+        let gcd = gcd(cpu, system);
+
+        Some(ClockRatio {
+            cpu: cpu / gcd,
+            system: system / gcd,
+        })
+    }
+
+    pub fn cpu(&self) -> u32 {
+        self.cpu
+    }
+
+    pub fn system(&self) -> u32 {
+        self.system
+    }
+}
+
+impl Default for ClockRatio {
+    fn default() -> Self {
+        ClockRatio::NORMAL
+    }
+}
+
+/// Writes "2" for 2/1 and "3/2" for 3/2
+impl Display for ClockRatio {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.system == 1 {
+            write!(f, "{}", self.cpu)
+        } else {
+            write!(f, "{}/{}", self.cpu, self.system)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseClockRatioError(pub String);
+
+/// Parses the format written by [`Display`], i.e. a CPU multiplier ("2") or a
+/// fraction ("3/2", "1/2")
+impl FromStr for ClockRatio {
+    type Err = ParseClockRatioError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseClockRatioError(s.to_owned());
+
+        let (cpu, system) = match s.trim().split_once('/') {
+            Some((cpu, system)) => (cpu.trim(), system.trim()),
+            None => (s.trim(), "1"),
+        };
+
+        let cpu = cpu.parse().map_err(|_| err())?;
+        let system = system.parse().map_err(|_| err())?;
+
+        ClockRatio::new(cpu, system).ok_or_else(err)
+    }
+}
+
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+
+    a
+}
+
+/// Decides when the system components get to advance, given the CPU cycles that
+/// pass. With a ratio of `cpu / system`, every CPU machine cycle adds `system` to the
+/// accumulator, and the system advances once for every `cpu` that it contains.
+pub(super) struct SystemClock {
+    ratio: ClockRatio,
+    acc: u32,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            ratio: ClockRatio::NORMAL,
+            acc: 0,
+        }
+    }
+
+    pub fn ratio(&self) -> ClockRatio {
+        self.ratio
+    }
+
+    pub fn set_ratio(&mut self, ratio: ClockRatio) {
+        self.ratio = ratio;
+        self.acc = 0;
+    }
+
+    /// Called for every CPU machine cycle. Returns how many system machine cycles
+    /// pass during it (0 or more).
+    #[inline]
+    pub fn tick(&mut self) -> u32 {
+        self.acc += self.ratio.system;
+
+        let cycles = self.acc / self.ratio.cpu;
+        self.acc %= self.ratio.cpu;
+
+        cycles
+    }
+}
@@ -0,0 +1,85 @@
+//! Statistics about what happened during a frame, for speed displays and profiling
+//! overlays in frontends, see [`FrameStats`]
+
+use crate::ppu::Mode;
+
+/// Length of a frame in machine cycles
+const FRAME_MCYCLES: u64 = 17556;
+
+/// What happened while a frame was emulated. A frame ends when the PPU enters VBlank.
+/// While the LCD is off, there are no frames, so the statistics are cut after the time
+/// a frame would take instead.
+///
+/// All durations are in (system) machine cycles, like [`crate::Emulator::mcycles`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// How long the frame took. This is 17556 unless the LCD was switched on or off.
+    pub mcycles: u64,
+    /// Time spent in mode 0
+    pub hblank_mcycles: u64,
+    /// Time spent in mode 1
+    pub vblank_mcycles: u64,
+    /// Time spent in mode 2
+    pub oam_search_mcycles: u64,
+    /// Time spent in mode 3
+    pub pixel_transfer_mcycles: u64,
+    pub lcd_off_mcycles: u64,
+    /// Time the CPU spent in HALT (waiting for an interrupt)
+    pub halt_mcycles: u64,
+    /// Number of OAM DMA transfers that were started
+    pub oam_dma_transfers: u32,
+}
+
+/// Collects the [`FrameStats`] of the current frame and keeps those of the last one
+#[derive(Default)]
+pub(crate) struct FrameStatsCounter {
+    current: FrameStats,
+    last: Option<FrameStats>,
+}
+
+impl FrameStatsCounter {
+    /// `before` and `after` are the modes of the PPU before and after the cycle. The
+    /// cycle that enters VBlank is the first one of the next frame.
+    pub fn count_mcycle(&mut self, before: Mode, after: Mode) {
+        let vblank_started = after == Mode::VBlank && before != Mode::VBlank;
+        let lcd_off_frame_over = after == Mode::LCDOff && self.current.mcycles >= FRAME_MCYCLES;
+
+        if vblank_started || lcd_off_frame_over {
+            self.last = Some(std::mem::take(&mut self.current));
+        }
+
+        self.count_idle_mcycles(after, 1);
+    }
+
+    /// Counts cycles in which the PPU stayed in `mode` (and didn't start a frame)
+    pub fn count_idle_mcycles(&mut self, mode: Mode, mcycles: u64) {
+        let stats = &mut self.current;
+        stats.mcycles += mcycles;
+
+        match mode {
+            Mode::HBlank => stats.hblank_mcycles += mcycles,
+            Mode::VBlank => stats.vblank_mcycles += mcycles,
+            Mode::OAMSearch => stats.oam_search_mcycles += mcycles,
+            Mode::PixelTransfer => stats.pixel_transfer_mcycles += mcycles,
+            Mode::LCDOff => stats.lcd_off_mcycles += mcycles,
+        }
+    }
+
+    /// How many cycles can be counted with [`FrameStatsCounter::count_idle_mcycles`]
+    /// before the statistics of a frame with the LCD off are cut
+    pub fn mcycles_until_lcd_off_cut(&self) -> u64 {
+        FRAME_MCYCLES.saturating_sub(self.current.mcycles)
+    }
+
+    pub fn count_halt(&mut self, mcycles: u64) {
+        self.current.halt_mcycles += mcycles;
+    }
+
+    pub fn count_oam_dma(&mut self) {
+        self.current.oam_dma_transfers += 1;
+    }
+
+    pub fn last(&self) -> Option<FrameStats> {
+        self.last
+    }
+}
@@ -4,18 +4,36 @@
 //! annotated types to hide the generic parameters, which would be very
 //! annoying to carry with us everywhere.
 
+mod block_cache;
+mod clock;
+mod frame_stats;
 mod oam_dma;
+mod step_order;
+mod watchpoints;
 
-use super::address::{Addr, IOReg, VideoMemAddr};
+use super::address::{Addr, IOReg, TimerReg};
+use super::audio::AudioOutput;
 use super::cartridge::Cartridge;
-use super::debug::{CpuEvt, DbgEvtSrc, PpuEvt};
-use super::interrupt_system::InterruptSystem;
+use super::cpu::Registers;
+use super::debug::{BankedAddr, CpuEvt, CpuTrace, DbgEvtSrc, MemStats, PpuEvt, Profiler};
+use super::hardware::HardwareModel;
+use super::interrupt_system::{Interrupt, InterruptSystem};
 use super::joypad::{Buttons, JoyPad};
 use super::memory::Memory;
-use super::ppu::{VideoFrameStatus, PPU};
+use super::ppu::{Mode, OamBugAccess, VideoFrameStatus, PPU};
+use super::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use super::serial_port::SerialPort;
+use super::sgb::Sgb;
 use super::timer::Timer;
-use oam_dma::OamDma;
+pub use block_cache::BlockCache;
+use clock::SystemClock;
+use frame_stats::FrameStatsCounter;
+pub(crate) use oam_dma::OamDma;
+
+pub use clock::{ClockRatio, ParseClockRatioError};
+pub use frame_stats::FrameStats;
+pub use step_order::{Component, ParseStepOrderError, StepOrder};
+pub use watchpoints::{DebugTrap, TrapCause, ValueMatch, WatchKind, Watchpoint, WatchpointSet};
 
 /// See the [module documentation](super::board)
 pub trait Board {
@@ -29,12 +47,26 @@ pub trait Board {
     type PpuDbgEvtSrc: DbgEvtSrc<PpuEvt>;
 
     /// Advances all components of the gameboy by 1 machine cycle (4 clock cycles).
+    /// If the CPU runs at a non-standard [`ClockRatio`], this is 1 *CPU* machine cycle,
+    /// and the rest of the system might advance more or less than that.
     ///
     /// To be called only from the CPU, whenever a cycle is advanced *without*
     /// any memory reads/writes. This method is automatically called when
     /// `read8`, `read16`, `write8`, `write16` are called.
     fn advance_mcycle(&mut self);
 
+    /// Lets one machine cycle pass while the system clock is halted by STOP. Timer, PPU,
+    /// serial port and OAM DMA are frozen, but we keep counting cycles (and produce
+    /// silent audio), so the frontend keeps running at its usual pace.
+    fn advance_mcycle_stopped(&mut self);
+
+    /// Called when the CPU enters STOP mode. Like on the real hardware, this resets DIV.
+    fn enter_stop(&mut self);
+
+    /// Whether one of the currently selected joypad input lines is low. This is what
+    /// wakes the CPU from STOP mode.
+    fn joypad_input_low(&self) -> bool;
+
     /// Reads a byte from memory *without* consuming a cycle. Should not be called
     /// from the CPU unless for very special cases (like IR handling). This method
     /// is also necessary to handle OAM DMA.
@@ -50,8 +82,10 @@ pub trait Board {
     /// Should not be called from the CPU unless for very special cases (like IR handling).
     fn read16_instant(&self, addr: u16) -> u16;
 
-    /// Reads a 16bit little-endian integer from memory, consuming two cycles in the process.
-    fn read16(&mut self, addr: u16) -> u16;
+    /// Reads a byte of an instruction (the opcode or an immediate operand), consuming a
+    /// cycle before doing so. This is like `read8`, but the byte might come from the
+    /// [`BlockCache`].
+    fn fetch8(&mut self, addr: u16) -> u8;
 
     /// Writes a 16bit little-endian integer to memory, consuming two cycles in the process.
     fn write16(&mut self, addr: u16, val: u16);
@@ -60,9 +94,25 @@ pub trait Board {
     /// correctly.
     fn ir_system(&mut self) -> &mut InterruptSystem;
 
+    /// Called by the CPU right before it fetches the instruction at `pc`, for the
+    /// [`Profiler`]
+    fn notify_instr_fetch(&mut self, pc: u16);
+
+    /// Called by the CPU when it jumps to an interrupt handler, so interrupt breakpoints
+    /// (see [`WatchpointSet`]) can trap
+    fn notify_interrupt_dispatch(&mut self, interrupt: Interrupt);
+
+    /// Called by the CPU after a machine cycle in which it put `addr` on the bus in a way
+    /// that can trigger the OAM bug (see [`OamBugAccess`])
+    fn notify_oam_bug(&mut self, addr: u16, access: OamBugAccess);
+
     /// Push an event to the [`CpuDbgEvtSrc`] implementation
     fn push_cpu_evt(&mut self, evt: CpuEvt);
 
+    /// Push a [`CpuEvt::Trace`] to the [`CpuDbgEvtSrc`] implementation, but only if it
+    /// is tracing. To be called right before an instruction is fetched.
+    fn push_cpu_trace(&mut self, reg: &Registers);
+
     /// Push an event to the [`PpuDbgEvtSrc`] implementation
     fn push_ppu_evt(&mut self, evt: PpuEvt);
 }
@@ -76,6 +126,32 @@ pub struct BoardImpl<CMem, CpuDbg, PpuDbg> {
     pub oam_dma: OamDma,
     pub timer: Timer,
     pub serial_port: SerialPort,
+    pub audio: AudioOutput,
+    /// Only there if a Super Game Boy is emulated (see [`HardwareModel::Sgb`])
+    pub sgb: Option<Sgb>,
+    /// The Game Boy model that is emulated. A setting like the accuracy options that
+    /// depend on it, so it is kept across resets and not part of savestates.
+    model: HardwareModel,
+    /// Number of machine cycles that have passed since the emulator was created.
+    /// Not affected by resets or savestates. These are system cycles, so they keep
+    /// measuring emulated time if the CPU runs at a non-standard [`ClockRatio`].
+    pub mcycles: u64,
+    /// Not part of savestates and kept across resets, like other debugging options
+    clock: SystemClock,
+    /// Debugging option as well, see [`StepOrder`]
+    step_order: StepOrder,
+    /// Debugging option as well, see [`BoardImpl::skip_idle_mcycles`]
+    idle_skipping: bool,
+    /// Kept across resets, see [`BlockCache`]
+    block_cache: Option<BlockCache>,
+    /// Checked on every memory access of the CPU, see [`WatchpointSet`]
+    pub watchpoints: WatchpointSet,
+    /// Counts the memory accesses of the CPU while a capture is running, see [`MemStats`]
+    pub mem_stats: Option<MemStats>,
+    /// Counts the executed instructions while profiling, see [`Profiler`]
+    pub profiler: Option<Profiler>,
+    /// Kept across resets, see [`FrameStats`]
+    pub(crate) frame_stats: FrameStatsCounter,
     pub cpu_evt_src: CpuDbg,
     pub ppu_evt_src: PpuDbg,
 }
@@ -92,58 +168,252 @@ impl<CMem: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>
             oam_dma: OamDma::new(),
             timer: Timer::new(),
             serial_port: SerialPort::new(),
+            audio: AudioOutput::new(),
+            sgb: None,
+            model: HardwareModel::default(),
+            mcycles: 0,
+            clock: SystemClock::new(),
+            step_order: StepOrder::DEFAULT,
+            idle_skipping: true,
+            block_cache: None,
+            watchpoints: WatchpointSet::new(),
+            mem_stats: None,
+            profiler: None,
+            frame_stats: FrameStatsCounter::default(),
             cpu_evt_src,
             ppu_evt_src,
         }
     }
 
-    // See documentaion of this method on [`crate::maboy::Emulator`]
-    pub fn query_video_frame_status(&mut self) -> VideoFrameStatus {
-        self.ppu.query_frame_status()
+    /// Puts every component back into its power-on state. The debug event sources,
+    /// the link cable and the state of the buttons are kept.
+    pub fn reset(&mut self) {
+        self.mem.reset();
+        // The palette, frame skip, double buffering and the accuracy options are settings,
+        // not hardware state
+        let mut ppu = PPU::new();
+        ppu.set_dmg_palette(*self.ppu.dmg_palette());
+        ppu.set_frame_skip(self.ppu.frame_skip());
+        ppu.set_double_buffered(self.ppu.double_buffered());
+        ppu.set_oam_bug(self.ppu.oam_bug());
+        ppu.set_stat_write_bug(self.ppu.stat_write_bug());
+        ppu.set_shade_frame(self.ppu.shade_frame().is_some());
+        self.ppu = ppu;
+        self.ir_system = InterruptSystem::new();
+        self.joypad.reset();
+        self.oam_dma = OamDma::new();
+        self.timer = Timer::new();
+        self.serial_port.reset();
+        self.sgb = self.sgb.as_ref().map(|_| Sgb::new());
+        self.leave_cached_block();
     }
 
-    // See documentaion of this method on [`crate::maboy::Emulator`]
-    pub fn notify_buttons_pressed(&mut self, buttons: Buttons) {
-        self.joypad
-            .notify_buttons_pressed(&mut self.ir_system, buttons);
+    pub fn model(&self) -> HardwareModel {
+        self.model
     }
 
-    // See documentaion of this method on [`crate::maboy::Emulator`]
-    pub fn notify_buttons_released(&mut self, buttons: Buttons) {
-        self.joypad.notify_buttons_released(buttons);
+    /// See [`crate::Emulator::with_model`]. Doesn't reset the board.
+    pub fn set_model(&mut self, model: HardwareModel) {
+        self.model = model;
+        self.ppu.set_stat_write_bug(model.has_stat_write_bug());
+        self.serial_port
+            .set_high_speed_available(model.has_serial_high_speed());
+
+        let sgb = matches!(model, HardwareModel::Sgb);
+        self.ppu.set_shade_frame(sgb);
+        self.sgb = if sgb { Some(Sgb::new()) } else { None };
     }
 
-    // See documentaion of this method on [`crate::maboy::Emulator`]
-    pub fn notify_buttons_state(&mut self, buttons: Buttons) {
-        self.joypad
-            .notify_buttons_state(&mut self.ir_system, buttons);
+    /// Puts the IO registers into the state that the boot ROM of the emulated model
+    /// leaves them in, for starting a game without running the boot ROM. VRAM stays
+    /// empty, so the Nintendo logo is not in there.
+    pub fn skip_boot(&mut self) {
+        self.timer = Timer::after_boot(self.model);
+        self.ir_system.schedule_interrupt(Interrupt::VBlank);
+        self.write8_instant(0xFF47, 0xFC); // BGP
+        self.write8_instant(0xFF40, 0x91); // LCDC: LCD and BG on, tile data at 0x8000
+        self.mem.write_ff50(1);
     }
-}
 
-impl<CMem: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>> Board
-    for BoardImpl<CMem, CpuDbg, PpuDbg>
-{
-    type CMem = CMem;
-    type CpuDbgEvtSrc = CpuDbg;
-    type PpuDbgEvtSrc = PpuDbg;
+    pub fn clock_ratio(&self) -> ClockRatio {
+        self.clock.ratio()
+    }
 
-    fn advance_mcycle(&mut self) {
-        self.timer.advance_mcycle(&mut self.ir_system);
-        self.ppu.advance_mcycle(&mut self.ir_system);
-        OamDma::advance_mcycle(self);
+    pub fn set_clock_ratio(&mut self, ratio: ClockRatio) {
+        self.clock.set_ratio(ratio);
     }
 
-    fn read8_instant(&self, addr: Addr) -> u8 {
+    pub fn step_order(&self) -> StepOrder {
+        self.step_order
+    }
+
+    pub fn set_step_order(&mut self, step_order: StepOrder) {
+        self.step_order = step_order;
+    }
+
+    /// Advances everything except the CPU by one machine cycle. The components only
+    /// interact through the interrupt system and memory, so the order matters where one
+    /// of them raises an interrupt or changes memory that another one looks at in the
+    /// same cycle (e.g. the timer and the PPU both requesting interrupts, or OAM DMA
+    /// copying into OAM while the PPU scans it).
+    fn advance_system_mcycle(&mut self) {
+        self.mcycles += 1;
+
+        let ppu_mode = self.ppu.mode();
+
+        for &component in self.step_order.components().iter() {
+            match component {
+                Component::Timer => self.timer.advance_mcycle(&mut self.ir_system),
+                Component::Ppu => self
+                    .ppu
+                    .advance_mcycle(&mut self.ir_system, &mut self.ppu_evt_src),
+                Component::SerialPort => self.serial_port.advance_mcycle(&mut self.ir_system),
+                Component::OamDma => OamDma::advance_mcycle(self),
+            }
+        }
+
+        self.frame_stats.count_mcycle(ppu_mode, self.ppu.mode());
+
+        if let (Some(sgb), Some(shades)) = (&mut self.sgb, self.ppu.shade_frame()) {
+            if ppu_mode != Mode::VBlank && self.ppu.mode() == Mode::VBlank {
+                sgb.notify_vblank(shades);
+            }
+        }
+
+        // TODO: Feed the actual APU output once there is an APU
+        self.audio
+            .clock_frame_sequencer(self.timer.take_frame_sequencer_clocks());
+        self.audio.advance_mcycle(&[(0, 0); 4]);
+    }
+
+    /// Lets up to `max` machine cycles pass at once, as long as none of the components
+    /// has anything to do in them (see [`PPU::idle_mcycles`] and friends). This is much
+    /// faster than advancing them cycle by cycle, and meant for when the CPU is halted.
+    /// Returns the number of cycles that passed, which might well be 0.
+    ///
+    /// Only works at [`ClockRatio::NORMAL`], since otherwise the CPU and the system don't
+    /// count the same cycles.
+    pub fn skip_idle_mcycles(&mut self, max: u32) -> u32 {
+        if !self.idle_skipping
+            || self.clock.ratio() != ClockRatio::NORMAL
+            || self.oam_dma.is_active()
+        {
+            return 0;
+        }
+
+        let mut max = max;
+
+        if !self.ppu.lcd_enabled() {
+            max = max.min(self.frame_stats.mcycles_until_lcd_off_cut() as u32);
+        }
+
+        let mcycles = max
+            .min(self.timer.idle_mcycles())
+            .min(self.ppu.idle_mcycles())
+            .min(self.serial_port.idle_mcycles());
+
+        if mcycles == 0 {
+            return 0;
+        }
+
+        self.mcycles += mcycles as u64;
+        self.timer.skip_mcycles(mcycles);
+        self.ppu.skip_mcycles(mcycles);
+        self.serial_port.skip_mcycles(mcycles);
+
+        self.frame_stats
+            .count_idle_mcycles(self.ppu.mode(), mcycles as u64);
+
+        self.audio
+            .clock_frame_sequencer(self.timer.take_frame_sequencer_clocks());
+
+        for _ in 0..mcycles {
+            self.audio.advance_mcycle(&[(0, 0); 4]);
+        }
+
+        mcycles
+    }
+
+    pub fn idle_skipping(&self) -> bool {
+        self.idle_skipping
+    }
+
+    pub fn set_idle_skipping(&mut self, enabled: bool) {
+        self.idle_skipping = enabled;
+    }
+
+    pub fn block_cache(&self) -> Option<&BlockCache> {
+        self.block_cache.as_ref()
+    }
+
+    /// Enables the cached interpreter (see [`BlockCache`]) with an empty cache, or
+    /// disables it and drops the cache
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.block_cache = if enabled {
+            Some(BlockCache::new())
+        } else {
+            None
+        };
+    }
+
+    /// To be called whenever the mapped ROM bank might have changed
+    pub fn leave_cached_block(&mut self) {
+        if let Some(cache) = self.block_cache.as_mut() {
+            cache.leave_block();
+        }
+    }
+
+    /// The instruction byte at `addr` from the block cache. `None` if the cache is
+    /// disabled, `addr` isn't in ROM, the read must not be skipped because something
+    /// is watching memory accesses (a debugger, watchpoints, statistics or cheats), or
+    /// OAM DMA might occupy the bus.
+    fn cached_instr_byte(&mut self, addr: u16) -> Option<u8> {
+        let must_read = self.oam_dma.is_active()
+            || self.cpu_evt_src.is_logging()
+            || !self.watchpoints.is_empty()
+            || self.mem_stats.is_some()
+            || !self.mem.cheats().cheats().is_empty();
+
+        let cache = self.block_cache.as_mut().filter(|_| !must_read)?;
+
+        if let Some(byte) = cache.current_byte(addr) {
+            return Some(byte);
+        }
+
+        let (bank, region_end) = match addr {
+            0x0000..=0x00FF if self.mem.boot_rom_mapped() => return None,
+            0x0000..=0x3FFF => (self.mem.cartridge().bank_state().rom_bank0, 0x3FFF),
+            0x4000..=0x7FFF => (self.mem.cartridge().bank_state().rom_bank, 0x7FFF),
+            _ => return None,
+        };
+
+        let mem = &self.mem;
+
+        cache.enter_block(
+            BankedAddr { bank, addr },
+            region_end,
+            |addr| match Addr::from(addr) {
+                Addr::Mem(mem_addr) => mem.read8(mem_addr),
+                // Blocks stop at the end of ROM, but be defensive about what lies beyond
+                _ => 0xFF,
+            },
+        )
+    }
+
+    /// Reads a byte from memory *without* consuming a cycle, ignoring whether OAM DMA
+    /// occupies the bus. This is how the DMA unit itself reads.
+    pub fn read8_bypassing_dma(&self, addr: Addr) -> u8 {
         use Addr::*;
 
         match addr {
             Mem(mem_addr) => self.mem.read8(mem_addr),
-            // OAM is unavailable during OAM DMA
-            VideoMem(VideoMemAddr::OAM(_)) if self.oam_dma.is_active() => 0xff,
             VideoMem(vid_mem_addr) => self.ppu.read_video_mem(vid_mem_addr),
             // TODO: Research if read of Unusable always return 0 even in different PPU modes
             Unusable => 0, // Reads from here curiously return 0 on DMG systems
-            IO(IOReg::P1) => self.joypad.read_p1(),
+            IO(IOReg::P1) => {
+                let p1 = self.joypad.read_p1(self.mcycles);
+                self.sgb.as_ref().map_or(p1, |sgb| sgb.read_p1(p1))
+            }
             IO(IOReg::Serial(serial_reg)) => self.serial_port.read_reg(serial_reg),
             IO(IOReg::Timer(timer_reg)) => self.timer.read_reg(timer_reg),
             IO(IOReg::Ppu(ppu_reg)) => self.ppu.read_reg(ppu_reg),
@@ -161,38 +431,194 @@ impl<CMem: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>> Boar
         }
     }
 
-    fn read8(&mut self, addr: u16) -> u8 {
-        self.advance_mcycle();
+    /// Writes a byte to memory *without* consuming a cycle. Apart from that, the write
+    /// has the same effect as one by the CPU.
+    pub fn write8_instant(&mut self, addr: u16, val: u8) {
+        use Addr::*;
 
-        let result = self.read8_instant(Addr::from(addr));
-        self.push_cpu_evt(CpuEvt::ReadMem(addr, result));
-        result
-    }
+        let mapped_addr = Addr::from(addr);
 
-    fn write8(&mut self, addr: u16, val: u8) {
-        use Addr::*;
+        match mapped_addr {
+            // The buses might be occupied by OAM DMA
+            _ if self.oam_dma.blocks_write(mapped_addr) => (),
+            Mem(mem_addr) => {
+                self.mem.write8(mem_addr, val);
 
-        self.advance_mcycle();
+                // Writes to ROM go to the memory bank controller
+                if addr < 0x8000 {
+                    self.leave_cached_block();
+                }
+            }
+            VideoMem(vid_mem_addr) => {
+                if !self.ppu.video_mem_accessible(vid_mem_addr) {
+                    self.push_ppu_evt(PpuEvt::BlockedWrite(addr, self.ppu.mode()));
+                }
 
-        match Addr::from(addr) {
-            Mem(mem_addr) => self.mem.write8(mem_addr, val),
-            // OAM is unavailable during OAM DMA
-            VideoMem(VideoMemAddr::OAM(_)) if self.oam_dma.is_active() => (),
-            VideoMem(vid_mem_addr) => self.ppu.write_video_mem(vid_mem_addr, val),
+                self.ppu.write_video_mem(vid_mem_addr, val)
+            }
             Unusable => (), // Writes to here are ignored by DMG systems
-            IO(IOReg::P1) => self.joypad.write_p1(val),
+            IO(IOReg::P1) => {
+                self.joypad.write_p1(&mut self.ir_system, val);
+
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_p1(val);
+                }
+            }
             IO(IOReg::Serial(serial_reg)) => self.serial_port.write_reg(serial_reg, val),
-            IO(IOReg::Timer(timer_reg)) => {
-                self.timer.write_reg(&mut self.ir_system, timer_reg, val)
+            IO(IOReg::Timer(timer_reg)) => self.timer.write_reg(timer_reg, val),
+            IO(IOReg::Ppu(ppu_reg)) => {
+                self.ppu
+                    .write_reg(&mut self.ir_system, &mut self.ppu_evt_src, ppu_reg, val)
+            }
+            IO(IOReg::OamDma) => {
+                self.oam_dma.write_ff46(val);
+                self.frame_stats.count_oam_dma();
             }
-            IO(IOReg::Ppu(ppu_reg)) => self.ppu.write_reg(&mut self.ir_system, ppu_reg, val),
-            IO(IOReg::OamDma) => self.oam_dma.write_ff46(val),
             IO(IOReg::BootRomDisable) => self.mem.write_ff50(val),
             IO(IOReg::IF) => self.ir_system.write_if(val),
             IO(IOReg::Unimplemented(addr)) => log::warn!("Unimplemented IO write: {:#06X}", addr),
             IO(reg) => log::warn!("Unimplemented IO write: {:?}", reg),
             IE => self.ir_system.write_ie(val),
         }
+    }
+
+    // See documentaion of this method on [`crate::maboy::Emulator`]
+    pub fn query_video_frame_status(&mut self) -> VideoFrameStatus {
+        // A finished frame means that the VBlank period just started, which is when the
+        // GameShark applied its codes
+        if self.ppu.has_frame_status() {
+            self.mem.apply_ram_cheats();
+        }
+
+        match (&self.sgb, self.ppu.query_frame_status()) {
+            (Some(sgb), VideoFrameStatus::Ready(_)) => VideoFrameStatus::SgbReady(sgb.frame()),
+            (_, status) => status,
+        }
+    }
+
+    // See documentaion of this method on [`crate::maboy::Emulator`]
+    pub fn notify_buttons_pressed(&mut self, buttons: Buttons) {
+        self.joypad
+            .notify_buttons_pressed(&mut self.ir_system, buttons, self.mcycles);
+    }
+
+    // See documentaion of this method on [`crate::maboy::Emulator`]
+    pub fn notify_buttons_released(&mut self, buttons: Buttons) {
+        self.joypad.notify_buttons_released(buttons, self.mcycles);
+    }
+
+    // See documentaion of this method on [`crate::maboy::Emulator`]
+    pub fn notify_buttons_state(&mut self, buttons: Buttons) {
+        self.joypad
+            .notify_buttons_state(&mut self.ir_system, buttons, self.mcycles);
+    }
+}
+
+/// The debug event sources are not part of the savestate
+impl<CMem: Cartridge, CpuDbg, PpuDbg> SaveState for BoardImpl<CMem, CpuDbg, PpuDbg> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.mem.save_state(writer);
+        self.ppu.save_state(writer);
+        self.ir_system.save_state(writer);
+        self.joypad.save_state(writer);
+        self.oam_dma.save_state(writer);
+        self.timer.save_state(writer);
+        self.serial_port.save_state(writer);
+
+        if let Some(sgb) = &self.sgb {
+            sgb.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.mem.load_state(reader)?;
+
+        // The mapped ROM bank is part of the cartridge state
+        if let Some(cache) = self.block_cache.as_mut() {
+            cache.leave_block();
+        }
+
+        self.ppu.load_state(reader)?;
+        self.ir_system.load_state(reader)?;
+        self.joypad.load_state(reader)?;
+        self.oam_dma.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.serial_port.load_state(reader)?;
+
+        // Whether there is a Super Game Boy was already checked by the PPU
+        match &mut self.sgb {
+            Some(sgb) => sgb.load_state(reader),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<CMem: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>> Board
+    for BoardImpl<CMem, CpuDbg, PpuDbg>
+{
+    type CMem = CMem;
+    type CpuDbgEvtSrc = CpuDbg;
+    type PpuDbgEvtSrc = PpuDbg;
+
+    fn advance_mcycle(&mut self) {
+        for _ in 0..self.clock.tick() {
+            self.advance_system_mcycle();
+        }
+    }
+
+    fn advance_mcycle_stopped(&mut self) {
+        self.mcycles += 1;
+        self.frame_stats
+            .count_mcycle(self.ppu.mode(), self.ppu.mode());
+        self.audio.advance_mcycle(&[(0, 0); 4]);
+    }
+
+    fn enter_stop(&mut self) {
+        self.timer.write_reg(TimerReg::DIV, 0);
+    }
+
+    fn joypad_input_low(&self) -> bool {
+        self.joypad.input_low()
+    }
+
+    fn read8_instant(&self, addr: Addr) -> u8 {
+        // The buses might be occupied by OAM DMA
+        self.oam_dma
+            .conflicting_read(addr)
+            .unwrap_or_else(|| self.read8_bypassing_dma(addr))
+    }
+
+    fn read8(&mut self, addr: u16) -> u8 {
+        self.advance_mcycle();
+
+        let mapped_addr = Addr::from(addr);
+        let result = self.read8_instant(mapped_addr);
+
+        if let Addr::VideoMem(vid_mem_addr) = mapped_addr {
+            if !self.ppu.video_mem_accessible(vid_mem_addr) {
+                self.push_ppu_evt(PpuEvt::BlockedRead(addr, self.ppu.mode()));
+            }
+        }
+
+        self.watchpoints.check(addr, result, WatchKind::Read);
+
+        if let Some(stats) = self.mem_stats.as_mut() {
+            stats.record_read(addr);
+        }
+
+        self.push_cpu_evt(CpuEvt::ReadMem(addr, result));
+        result
+    }
+
+    fn write8(&mut self, addr: u16, val: u8) {
+        self.advance_mcycle();
+        self.write8_instant(addr, val);
+
+        self.watchpoints.check(addr, val, WatchKind::Write);
+
+        if let Some(stats) = self.mem_stats.as_mut() {
+            stats.record_write(addr);
+        }
 
         self.push_cpu_evt(CpuEvt::WriteMem(addr, val));
     }
@@ -204,8 +630,14 @@ impl<CMem: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>> Boar
         ])
     }
 
-    fn read16(&mut self, addr: u16) -> u16 {
-        u16::from_le_bytes([self.read8(addr), self.read8(addr.wrapping_add(1))])
+    fn fetch8(&mut self, addr: u16) -> u8 {
+        match self.cached_instr_byte(addr) {
+            Some(byte) => {
+                self.advance_mcycle();
+                byte
+            }
+            None => self.read8(addr),
+        }
     }
 
     fn write16(&mut self, addr: u16, val: u16) {
@@ -217,10 +649,65 @@ impl<CMem: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>> Boar
         &mut self.ir_system
     }
 
+    fn notify_instr_fetch(&mut self, pc: u16) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            let banks = self.mem.cartridge().bank_state();
+
+            let bank = match pc {
+                0x0000..=0x3FFF => banks.rom_bank0,
+                0x4000..=0x7FFF => banks.rom_bank,
+                0xA000..=0xBFFF => banks.ram_bank.unwrap_or(0) as u16,
+                _ => 0,
+            };
+
+            profiler.record_fetch(BankedAddr { bank, addr: pc }, self.mcycles);
+        }
+    }
+
+    fn notify_interrupt_dispatch(&mut self, interrupt: Interrupt) {
+        self.watchpoints.check_interrupt(interrupt);
+    }
+
+    fn notify_oam_bug(&mut self, addr: u16, access: OamBugAccess) {
+        if (0xFE00..=0xFEFF).contains(&addr) {
+            self.ppu.trigger_oam_bug(access);
+        }
+    }
+
     fn push_cpu_evt(&mut self, evt: CpuEvt) {
         self.cpu_evt_src.push(evt);
     }
 
+    fn push_cpu_trace(&mut self, reg: &Registers) {
+        if !self.cpu_evt_src.is_tracing() {
+            return;
+        }
+
+        let [b, c] = reg.bc.to_be_bytes();
+        let [d, e] = reg.de.to_be_bytes();
+        let [h, l] = reg.hl.to_be_bytes();
+
+        let mut pcmem = [0; 4];
+        for (offset, byte) in pcmem.iter_mut().enumerate() {
+            *byte = self.read8_instant(Addr::from(reg.pc.wrapping_add(offset as u16)));
+        }
+
+        self.cpu_evt_src.push(CpuEvt::Trace(CpuTrace {
+            a: reg.a,
+            f: reg.flags.bits(),
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: reg.sp,
+            pc: reg.pc,
+            pcmem,
+            mcycles: self.mcycles,
+        }));
+    }
+
     fn push_ppu_evt(&mut self, evt: PpuEvt) {
         self.ppu_evt_src.push(evt);
     }
@@ -1,5 +1,6 @@
-use crate::address::{Addr, VideoMemAddr};
-use crate::board::{Board, BoardImpl};
+use crate::address::{Addr, MemAddr, VideoMemAddr};
+use crate::board::BoardImpl;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use crate::{
     cartridge::Cartridge,
     debug::{CpuEvt, DbgEvtSrc, PpuEvt},
@@ -8,11 +9,45 @@ use crate::{
 // TODO: Move this onto emulator. It's too ugly here, i think...
 
 /// Stores the DMA register, as well as the internal state necessary to perform OAM DMA.
+///
+/// On the DMG, the CPU and the DMA unit share two buses: The external bus (cartridge ROM
+/// and RAM, WRAM) and the video bus (VRAM). While a transfer is running, the DMA occupies
+/// the bus that it reads from, as well as OAM. CPU reads from there return the byte that
+/// the DMA is transferring in that cycle (0xFF for OAM), and writes are lost. Only HRAM,
+/// the IO registers and the other bus stay accessible to the CPU.
 pub struct OamDma {
     reg: u8,
     src_addr: u16,
     oam_dst_idx: u8,
     read_buf: u8,
+    /// The source address of a transfer that was started by writing FF46. It begins in
+    /// the next mcycle, while a running transfer keeps going until then.
+    pending_src_addr: Option<u16>,
+    /// Whether a byte is written to OAM in the current mcycle, which is when the DMA
+    /// occupies the buses. This is not the case in the first mcycle of a transfer, which
+    /// only reads the first byte.
+    transferring: bool,
+}
+
+/// A bus that the CPU shares with the DMA unit, see [`OamDma`]
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Bus {
+    External,
+    Video,
+}
+
+impl Bus {
+    /// The bus that the given address is accessed through, or `None` if the CPU can
+    /// always access it (like HRAM and the IO registers) or if it is OAM
+    fn of(addr: Addr) -> Option<Bus> {
+        match addr {
+            Addr::Mem(MemAddr::HRAM(_)) => None,
+            Addr::Mem(_) => Some(Bus::External),
+            Addr::VideoMem(VideoMemAddr::TileData(_))
+            | Addr::VideoMem(VideoMemAddr::TileMaps(_)) => Some(Bus::Video),
+            Addr::VideoMem(VideoMemAddr::OAM(_)) | Addr::Unusable | Addr::IO(_) | Addr::IE => None,
+        }
+    }
 }
 
 impl OamDma {
@@ -22,11 +57,40 @@ impl OamDma {
             src_addr: 0,
             oam_dst_idx: 0xA0,
             read_buf: 0,
+            pending_src_addr: None,
+            transferring: false,
         }
     }
 
     pub fn is_active(&self) -> bool {
-        self.oam_dst_idx < 0xA0
+        self.oam_dst_idx < 0xA0 || self.pending_src_addr.is_some()
+    }
+
+    /// Whether the CPU might be locked out of the given address while OAM DMA is
+    /// running, depending on the bus that the DMA reads from. Used for
+    /// [`crate::debug::describe_memory_map`].
+    pub fn blocks_cpu_access(addr: Addr) -> bool {
+        matches!(addr, Addr::VideoMem(VideoMemAddr::OAM(_))) || Bus::of(addr).is_some()
+    }
+
+    /// What the CPU reads from `addr` in the current mcycle if the DMA is in its way (see
+    /// [`OamDma`]), or `None` if the read is not affected
+    pub fn conflicting_read(&self, addr: Addr) -> Option<u8> {
+        if !self.transferring {
+            None
+        } else if let Addr::VideoMem(VideoMemAddr::OAM(_)) = addr {
+            Some(0xff)
+        } else if Bus::of(addr).is_some() && Bus::of(addr) == Bus::of(self.src_addr()) {
+            Some(self.read_buf)
+        } else {
+            None
+        }
+    }
+
+    /// Whether a CPU write to `addr` in the current mcycle is lost because the DMA is in
+    /// its way (see [`OamDma`])
+    pub fn blocks_write(&self, addr: Addr) -> bool {
+        self.conflicting_read(addr).is_some()
     }
 
     pub fn read_ff46(&self) -> u8 {
@@ -36,15 +100,22 @@ impl OamDma {
     pub fn write_ff46(&mut self, val: u8) {
         self.reg = val;
 
-        // OAM DMA just starts again if it is already running
+        // OAM DMA starts again if it is already running, but not before the next mcycle
+        self.pending_src_addr = Some((val as u16) * 0x100);
+    }
 
-        if val > 0xf1 {
-            log::debug!("Illegal source address range for OAM DMA");
-            return;
+    /// OAM DMA can read from cartridge ROM, VRAM, cartridge RAM and WRAM. Everything
+    /// above 0xDFFF is not visible to the DMA unit; On the DMG, the upper address bits
+    /// are just ignored there, so those reads end up in WRAM (just like echo RAM).
+    ///
+    /// Cartridge reads go through the MBC like normal CPU reads, so the currently
+    /// selected ROM/RAM bank and the RAM enable state are applied for each byte.
+    fn src_addr(&self) -> Addr {
+        if self.src_addr >= 0xE000 {
+            Addr::from(self.src_addr - 0x2000)
+        } else {
+            Addr::from(self.src_addr)
         }
-
-        self.src_addr = (val as u16) * 0x100;
-        self.oam_dst_idx = 0;
     }
 
     /// This function has a weird signature because OAM DMA kinda needs a mutable reference to itself
@@ -55,23 +126,250 @@ impl OamDma {
         board: &mut BoardImpl<CMem, CpuDbg, PpuDbg>,
     ) {
         // TODO: Don't progress when CPU is in halt or stop
-        if board.oam_dma.is_active() {
-            // In the very first cycle of OAM DMA, we just fill the read buffer,
-            // while in all other cycles, we first write out the buffer and
-            // then fetch the next entry.
-
-            if board.oam_dma.src_addr & 0xff != 0 {
-                // Write most recently read byte
-                board.ppu.write_video_mem_unchecked(
-                    VideoMemAddr::OAM(board.oam_dma.oam_dst_idx as u16),
-                    board.oam_dma.read_buf,
-                );
-                board.oam_dma.oam_dst_idx += 1;
-            }
-
-            // Read next byte (we read one too much at the very end, but noone cares ;)
-            board.oam_dma.read_buf = board.read8_instant(Addr::from(board.oam_dma.src_addr));
+
+        // In the very first cycle of OAM DMA, we just fill the read buffer,
+        // while in all other cycles, we first write out the buffer and
+        // then fetch the next entry.
+
+        board.oam_dma.transferring = false;
+
+        if board.oam_dma.oam_dst_idx < 0xA0 && board.oam_dma.src_addr & 0xff != 0 {
+            // Write most recently read byte
+            board.ppu.write_video_mem_unchecked(
+                VideoMemAddr::OAM(board.oam_dma.oam_dst_idx as u16),
+                board.oam_dma.read_buf,
+            );
+            board.oam_dma.oam_dst_idx += 1;
+            board.oam_dma.transferring = true;
+        }
+
+        // A restarted transfer takes over after the old one wrote its last byte
+        if let Some(src_addr) = board.oam_dma.pending_src_addr.take() {
+            board.oam_dma.src_addr = src_addr;
+            board.oam_dma.oam_dst_idx = 0;
+        }
+
+        if board.oam_dma.oam_dst_idx < 0xA0 {
+            // Read next byte
+            board.oam_dma.read_buf = board.read8_bypassing_dma(board.oam_dma.src_addr());
             board.oam_dma.src_addr += 1;
         }
     }
 }
+
+impl SaveState for OamDma {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.reg);
+        writer.write_u16(self.src_addr);
+        writer.write_u8(self.oam_dst_idx);
+        writer.write_u8(self.read_buf);
+        writer.write_bool(self.pending_src_addr.is_some());
+        writer.write_u16(self.pending_src_addr.unwrap_or(0));
+        writer.write_bool(self.transferring);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.reg = reader.read_u8()?;
+        self.src_addr = reader.read_u16()?;
+        self.oam_dst_idx = reader.read_u8()?;
+        self.read_buf = reader.read_u8()?;
+        let pending = reader.read_bool()?;
+        let pending_src_addr = reader.read_u16()?;
+        self.pending_src_addr = Some(pending_src_addr).filter(|_| pending);
+        self.transferring = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::address::VideoMemAddr;
+    use crate::board::Board;
+    use crate::debug::NoDbgLogger;
+    use crate::test_harness::RomBuilder;
+    use crate::{CartridgeVariant, Emulator};
+
+    type TestEmulator = Emulator<CartridgeVariant, NoDbgLogger, NoDbgLogger>;
+
+    /// An emulator that halted right after starting, with the LCD off so OAM is readable.
+    /// Everything else is done by poking, so the CPU doesn't fight the DMA for the bus.
+    fn halted_emulator(program: &mut RomBuilder) -> TestEmulator {
+        // DI; HALT
+        program.code(&[0xF3, 0x76]);
+        let halted = program.code_addr();
+
+        let cartridge = CartridgeVariant::from_bytes(program.build()).unwrap();
+        let mut emu = Emulator::new(cartridge).with_boot_rom(None);
+
+        while emu.cpu.reg.pc != halted {
+            emu.emulate_step();
+        }
+
+        emu.poke(0xFF40, 0x00);
+        emu
+    }
+
+    /// Starts OAM DMA from `src_page` * 0x100 and returns OAM once it finished
+    fn dma_to_oam(emu: &mut TestEmulator, src_page: u8) -> Vec<u8> {
+        emu.poke(0xFF46, src_page);
+
+        let done = emu.mcycles() + 200;
+        while emu.mcycles() < done {
+            emu.emulate_step();
+        }
+
+        (0xFE00..0xFEA0).map(|addr| emu.peek(addr)).collect()
+    }
+
+    /// Starts OAM DMA from `src_page` * 0x100 and lets `mcycles` pass. The first mcycle
+    /// only reads, so after `n` mcycles, `n - 1` bytes are written and the next one is
+    /// in flight.
+    fn start_dma(emu: &mut TestEmulator, src_page: u8, mcycles: usize) {
+        emu.poke(0xFF46, src_page);
+
+        for _ in 0..mcycles {
+            emu.board.advance_mcycle();
+        }
+    }
+
+    fn fill(emu: &mut TestEmulator, start: u16, bytes: &[u8]) {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            emu.poke(start + offset as u16, byte);
+        }
+    }
+
+    fn oam(emu: &TestEmulator, idx: u16) -> u8 {
+        emu.board.ppu.read_video_mem(VideoMemAddr::OAM(idx))
+    }
+
+    fn pattern(seed: u8) -> Vec<u8> {
+        (0..0xA0u8)
+            .map(|i| i.wrapping_mul(3).wrapping_add(seed))
+            .collect()
+    }
+
+    #[test]
+    fn dma_from_switched_rom_bank() {
+        // MBC1 with 8 banks
+        let mut program = RomBuilder::with_mbc(0x01, 8, 0x00);
+        program.bytes_at(0x4000, &pattern(1));
+        program.bytes_at(5 * 0x4000, &pattern(5));
+
+        let mut emu = halted_emulator(&mut program);
+        assert_eq!(dma_to_oam(&mut emu, 0x40), pattern(1));
+
+        emu.poke(0x2000, 5);
+        assert_eq!(dma_to_oam(&mut emu, 0x40), pattern(5));
+    }
+
+    #[test]
+    fn dma_from_cartridge_ram() {
+        // MBC1 with 8 KiB of RAM
+        let mut program = RomBuilder::with_mbc(0x02, 2, 0x02);
+        let mut emu = halted_emulator(&mut program);
+
+        emu.poke(0x0000, 0x0A);
+        for (offset, &byte) in pattern(7).iter().enumerate() {
+            emu.poke(0xA000 + offset as u16, byte);
+        }
+
+        assert_eq!(dma_to_oam(&mut emu, 0xA0), pattern(7));
+
+        // Disabled RAM reads as 0xFF, for OAM DMA as well
+        emu.poke(0x0000, 0x00);
+        assert_eq!(dma_to_oam(&mut emu, 0xA0), vec![0xFF; 0xA0]);
+    }
+
+    #[test]
+    fn dma_from_above_0xdfff_reads_wram() {
+        let mut emu = halted_emulator(&mut RomBuilder::new());
+
+        for (offset, &byte) in pattern(9).iter().enumerate() {
+            emu.poke(0xDF00 + offset as u16, byte);
+        }
+
+        assert_eq!(dma_to_oam(&mut emu, 0xFF), pattern(9));
+    }
+
+    #[test]
+    fn wram_reads_during_dma_from_wram_see_the_in_flight_byte() {
+        let mut emu = halted_emulator(&mut RomBuilder::new());
+        fill(&mut emu, 0xC100, &pattern(3));
+        emu.poke(0xC000, 0x55);
+
+        // Nothing is in the way while the first byte is read
+        start_dma(&mut emu, 0xC1, 1);
+        assert_eq!(emu.peek(0xC000), 0x55);
+
+        start_dma(&mut emu, 0xC1, 11);
+        assert_eq!(emu.peek(0xC000), pattern(3)[10]);
+        assert_eq!(emu.peek(0xFE00), 0xFF);
+
+        // The write is lost
+        emu.poke(0xC000, 0x66);
+        emu.board.advance_mcycle();
+        assert_eq!(emu.peek(0xC000), pattern(3)[11]);
+
+        dma_to_oam(&mut emu, 0xC1);
+        assert_eq!(emu.peek(0xC000), 0x55);
+    }
+
+    #[test]
+    fn reads_from_the_other_bus_are_not_affected() {
+        let mut emu = halted_emulator(&mut RomBuilder::new());
+        fill(&mut emu, 0xC100, &pattern(3));
+        fill(&mut emu, 0x8100, &pattern(4));
+        emu.poke(0xC000, 0x55);
+        emu.poke(0x8000, 0x77);
+
+        // Cartridge ROM and WRAM are both on the external bus, VRAM is not
+        start_dma(&mut emu, 0x01, 11);
+        assert_eq!(emu.peek(0xC000), RomBuilder::new().build()[0x010A]);
+        assert_eq!(emu.peek(0x8000), 0x77);
+        emu.poke(0x8000, 0x78);
+        assert_eq!(emu.peek(0x8000), 0x78);
+
+        start_dma(&mut emu, 0x81, 11);
+        assert_eq!(emu.peek(0x8000), pattern(4)[10]);
+        assert_eq!(emu.peek(0xC000), 0x55);
+        emu.poke(0xC000, 0x56);
+        assert_eq!(emu.peek(0xC000), 0x56);
+    }
+
+    #[test]
+    fn hram_stays_accessible_during_dma() {
+        let mut emu = halted_emulator(&mut RomBuilder::new());
+        fill(&mut emu, 0xC100, &pattern(3));
+        emu.poke(0xFF80, 0x55);
+
+        start_dma(&mut emu, 0xC1, 11);
+        assert_eq!(emu.peek(0xFF80), 0x55);
+
+        emu.poke(0xFF80, 0x66);
+        assert_eq!(emu.peek(0xFF80), 0x66);
+    }
+
+    #[test]
+    fn rewriting_ff46_finishes_the_old_byte_before_restarting() {
+        let mut emu = halted_emulator(&mut RomBuilder::new());
+        fill(&mut emu, 0xC100, &pattern(3));
+        fill(&mut emu, 0xC200, &pattern(5));
+
+        // Bytes 0x00 to 0x0F are written, 0x10 is in flight
+        start_dma(&mut emu, 0xC1, 0x11);
+        assert_eq!(oam(&emu, 0x0F), pattern(3)[0x0F]);
+        assert_ne!(oam(&emu, 0x10), pattern(3)[0x10]);
+
+        // The old transfer writes its byte in the next mcycle, while the new one reads
+        start_dma(&mut emu, 0xC2, 1);
+        assert_eq!(oam(&emu, 0x10), pattern(3)[0x10]);
+        assert_eq!(oam(&emu, 0x00), pattern(3)[0x00]);
+        assert_eq!(emu.peek(0xC000), pattern(5)[0x00]);
+
+        emu.board.advance_mcycle();
+        assert_eq!(oam(&emu, 0x00), pattern(5)[0x00]);
+        assert_eq!(oam(&emu, 0x11), 0x00);
+
+        assert_eq!(dma_to_oam(&mut emu, 0xC2), pattern(5));
+    }
+}
@@ -0,0 +1,125 @@
+//! The order in which the components of the system are advanced within a machine
+//! cycle. On real hardware, everything happens in parallel, so any fixed order is an
+//! approximation. Changing it is only useful to find out which behaviour depends on it
+//! (see the `cycle_skew` example).
+
+use std::convert::TryInto;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// A component that is advanced every machine cycle
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Component {
+    Timer,
+    Ppu,
+    SerialPort,
+    OamDma,
+}
+
+impl Component {
+    const ALL: [Component; 4] = [
+        Component::Timer,
+        Component::Ppu,
+        Component::SerialPort,
+        Component::OamDma,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Component::Timer => "timer",
+            Component::Ppu => "ppu",
+            Component::SerialPort => "serial",
+            Component::OamDma => "dma",
+        }
+    }
+}
+
+/// A permutation of all [`Component`]s
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StepOrder([Component; 4]);
+
+impl StepOrder {
+    /// The order that the emulator uses unless told otherwise. It is the one that
+    /// passes the most test ROMs; `cycle_skew` complains if that's not the case anymore.
+    pub const DEFAULT: StepOrder = StepOrder(Component::ALL);
+
+    /// Returns `None` unless `order` contains every component exactly once
+    pub fn new(order: [Component; 4]) -> Option<StepOrder> {
+        let complete = Component::ALL
+            .iter()
+            .all(|component| order.contains(component));
+
+        if complete {
+            Some(StepOrder(order))
+        } else {
+            None
+        }
+    }
+
+    pub fn components(&self) -> [Component; 4] {
+        self.0
+    }
+
+    /// All 24 possible orders, starting with [`StepOrder::DEFAULT`]
+    pub fn all() -> Vec<StepOrder> {
+        let mut orders = Vec::with_capacity(24);
+        permute(Component::ALL, 0, &mut orders);
+
+        orders
+    }
+}
+
+impl Default for StepOrder {
+    fn default() -> Self {
+        StepOrder::DEFAULT
+    }
+}
+
+/// Heap's algorithm would be faster, but this keeps the default order first
+fn permute(order: [Component; 4], fixed: usize, orders: &mut Vec<StepOrder>) {
+    if fixed == order.len() {
+        orders.push(StepOrder(order));
+        return;
+    }
+
+    for i in fixed..order.len() {
+        let mut order = order;
+        order[fixed..=i].rotate_right(1);
+        permute(order, fixed + 1, orders);
+    }
+}
+
+/// Writes the component names separated by commas, e.g. "timer,ppu,serial,dma"
+impl Display for StepOrder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.0.iter().map(|component| component.name()).collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseStepOrderError(pub String);
+
+/// Parses the format written by [`Display`]. Whitespace around the names is ignored.
+impl FromStr for StepOrder {
+    type Err = ParseStepOrderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseStepOrderError(s.to_owned());
+
+        let components = s
+            .split(',')
+            .map(|name| {
+                Component::ALL
+                    .iter()
+                    .copied()
+                    .find(|component| component.name() == name.trim())
+            })
+            .collect::<Option<Vec<Component>>>()
+            .ok_or_else(err)?;
+
+        let order: [Component; 4] = components.try_into().map_err(|_| err())?;
+
+        StepOrder::new(order).ok_or_else(err)
+    }
+}
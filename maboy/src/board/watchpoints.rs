@@ -0,0 +1,190 @@
+//! Memory watchpoints that are checked on every memory access of the CPU. Unlike
+//! scanning the debug event log after the fact, this can't miss an access, no matter
+//! how many of them an instruction (or an interrupt dispatch) makes.
+//!
+//! The same set also holds interrupt breakpoints, which trap whenever the CPU dispatches
+//! a certain interrupt.
+
+use crate::interrupt_system::Interrupt;
+use std::ops::RangeInclusive;
+
+/// Which kind of memory access a [`Watchpoint`] reacts to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: WatchKind) -> bool {
+        self == WatchKind::ReadWrite || self == access
+    }
+}
+
+/// Watches an (inclusive) range of addresses, which is usually just a single one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addrs: RangeInclusive<u16>,
+    pub kind: WatchKind,
+    /// Only trigger for accesses with a matching value, e.g. writes to an IO register
+    /// that set a certain bit
+    pub value: Option<ValueMatch>,
+}
+
+impl Watchpoint {
+    pub fn new(addr: u16, kind: WatchKind) -> Watchpoint {
+        Watchpoint {
+            addrs: addr..=addr,
+            kind,
+            value: None,
+        }
+    }
+
+    pub fn with_value(self, value: ValueMatch) -> Watchpoint {
+        Watchpoint {
+            value: Some(value),
+            ..self
+        }
+    }
+}
+
+/// Matches values whose bits in `mask` are the same as in `value`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ValueMatch {
+    pub mask: u8,
+    pub value: u8,
+}
+
+impl ValueMatch {
+    pub fn matches(self, val: u8) -> bool {
+        val & self.mask == self.value & self.mask
+    }
+}
+
+/// Describes what triggered a trap. Reported via [`crate::StepOutcome::Watchpoint`] and
+/// [`crate::Emulator::debug_trap`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DebugTrap {
+    /// The address of the instruction that made the access. For interrupt dispatches
+    /// (and the pushes they make), this is where the CPU was interrupted.
+    pub pc: u16,
+    pub cause: TrapCause,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrapCause {
+    /// A memory access hit a [`Watchpoint`]
+    Access {
+        addr: u16,
+        /// The value that was read or written
+        val: u8,
+        /// Either [`WatchKind::Read`] or [`WatchKind::Write`]
+        access: WatchKind,
+    },
+    /// The CPU dispatched an interrupt that has an interrupt breakpoint
+    Interrupt(Interrupt),
+}
+
+/// The watchpoints of an emulator. Not part of savestates and kept across resets, like
+/// other debugging options.
+#[derive(Debug, Clone, Default)]
+pub struct WatchpointSet {
+    watchpoints: Vec<Watchpoint>,
+    interrupts: Vec<Interrupt>,
+    /// The first access that hit a watchpoint since the last call of `take_trap`
+    trap: Option<DebugTrap>,
+}
+
+impl WatchpointSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes the watchpoint with the given index (see [`WatchpointSet::watchpoints`])
+    pub fn remove(&mut self, idx: usize) -> Option<Watchpoint> {
+        if idx < self.watchpoints.len() {
+            Some(self.watchpoints.remove(idx))
+        } else {
+            None
+        }
+    }
+
+    /// Traps whenever the CPU dispatches this interrupt
+    pub fn add_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts.push(interrupt);
+    }
+
+    /// Removes the interrupt breakpoint with the given index (see
+    /// [`WatchpointSet::interrupts`])
+    pub fn remove_interrupt(&mut self, idx: usize) -> Option<Interrupt> {
+        if idx < self.interrupts.len() {
+            Some(self.interrupts.remove(idx))
+        } else {
+            None
+        }
+    }
+
+    /// Removes the watchpoints as well as the interrupt breakpoints
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+        self.interrupts.clear();
+    }
+
+    /// In the order they were added
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// In the order they were added
+    pub fn interrupts(&self) -> &[Interrupt] {
+        &self.interrupts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty() && self.interrupts.is_empty()
+    }
+
+    /// Called by the board on every read and write of the CPU. Only the first hit is
+    /// kept until the trap is taken, since that's where a debugger wants to stop.
+    #[inline]
+    pub(crate) fn check(&mut self, addr: u16, val: u8, access: WatchKind) {
+        // Keeps the common case (no watchpoints at all) cheap
+        if self.watchpoints.is_empty() || self.trap.is_some() {
+            return;
+        }
+
+        let hit = self.watchpoints.iter().any(|wp| {
+            wp.kind.matches(access)
+                && wp.addrs.contains(&addr)
+                && wp.value.is_none_or(|value| value.matches(val))
+        });
+
+        if hit {
+            self.trap(TrapCause::Access { addr, val, access });
+        }
+    }
+
+    /// Called by the board whenever the CPU dispatches an interrupt
+    pub(crate) fn check_interrupt(&mut self, interrupt: Interrupt) {
+        if self.trap.is_none() && self.interrupts.contains(&interrupt) {
+            self.trap(TrapCause::Interrupt(interrupt));
+        }
+    }
+
+    fn trap(&mut self, cause: TrapCause) {
+        self.trap = Some(DebugTrap {
+            // Filled in by the emulator, which knows where the instruction started
+            pc: 0,
+            cause,
+        });
+    }
+
+    pub(crate) fn take_trap(&mut self) -> Option<DebugTrap> {
+        self.trap.take()
+    }
+}
@@ -4,9 +4,11 @@
 //! supports it.
 //!
 //! Some RAM types support batteries; In that case, they should make their internal
-//! state public via the [`Savegame`] trait if a battery is present.
+//! state public via the [`Savegame`] trait if a battery is present, and keep track of
+//! whether it was written to (see [`Savegame::savegame_dirty`]).
 
 use super::desc::RamSize;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 use crate::{address::CRamAddr, Savegame};
 use std::pin::Pin;
 
@@ -17,6 +19,13 @@ pub trait CartridgeRam: Savegame {
     fn read(&self, addr: CRamAddr) -> u8;
     fn write(&mut self, addr: CRamAddr, val: u8);
     fn try_select_bank(&mut self, bank: u8);
+
+    /// The currently mapped RAM bank, or `None` if there is no RAM
+    fn mapped_bank(&self) -> Option<u8>;
+
+    /// Writes the RAM contents (and the selected bank, if any) into a savestate
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError>;
 }
 
 /// Cartridges with no internal RAM should use this implementation, where every
@@ -33,6 +42,16 @@ impl CartridgeRam for NoCRam {
     fn write(&mut self, _addr: CRamAddr, _val: u8) {}
 
     fn try_select_bank(&mut self, _bank: u8) {}
+
+    fn mapped_bank(&self) -> Option<u8> {
+        None
+    }
+
+    fn save_state(&self, _writer: &mut StateWriter) {}
+
+    fn load_state(&mut self, _reader: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
 }
 
 /// A fixed amount of RAM without banking support. Attempts to switch the RAM bank
@@ -40,6 +59,7 @@ impl CartridgeRam for NoCRam {
 pub struct CRamUnbanked {
     cram: Box<[u8]>,
     has_battery: bool,
+    dirty: bool,
 }
 
 impl CRamUnbanked {
@@ -51,7 +71,11 @@ impl CRamUnbanked {
             RamSize::Ram32Kb => panic!("Invalid ram size for CRAMUnbanked"),
         };
 
-        Self { cram, has_battery }
+        Self {
+            cram,
+            has_battery,
+            dirty: false,
+        }
     }
 }
 
@@ -72,6 +96,14 @@ impl Savegame for CRamUnbanked {
             None
         }
     }
+
+    fn savegame_dirty(&self) -> bool {
+        self.has_battery && self.dirty
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.dirty = false;
+    }
 }
 
 impl CartridgeRam for CRamUnbanked {
@@ -82,31 +114,56 @@ impl CartridgeRam for CRamUnbanked {
     fn write(&mut self, addr: CRamAddr, val: u8) {
         if let Some(mem) = self.cram.get_mut(addr.raw() as usize) {
             *mem = val;
+            self.dirty = true;
         }
     }
 
     fn try_select_bank(&mut self, _bank: u8) {}
+
+    fn mapped_bank(&self) -> Option<u8> {
+        Some(0)
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.cram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.dirty = true;
+        reader.read_bytes(&mut self.cram)
+    }
 }
 
-/// MBC2 has a weird half-byte RAM, where only the lower 4 bits of each addressable byte are used.
-/// We store this in a compressed format so we use all 8 bits of each byte. The lower half of the
-/// byte contains the lower address.
-pub struct CRamMBC2 {
-    // TODO: Internally, this looks very much like CRAMUnbanked. The Savegame impl is also the same. See if it should be modularized
+/// The RAM that is built into MBC2: 512 half-bytes. Only the lower 4 bits of each
+/// address are stored; The upper 4 bits are not connected and always read as 1. The
+/// 512 addresses are mirrored across the whole CRAM area (0xA000 - 0xBFFF).
+///
+/// Every half-byte is stored in a byte of its own, which is also the savegame format
+/// that other emulators use.
+pub struct Mbc2Ram {
     cram: Box<[u8]>,
     has_battery: bool,
+    dirty: bool,
 }
 
-impl CRamMBC2 {
+/// Number of half-bytes in the MBC2 RAM
+pub(crate) const MBC2_RAM_LEN: usize = 0x200;
+
+impl Mbc2Ram {
     pub fn new(has_battery: bool) -> Self {
         Self {
-            cram: vec![0u8; 256].into_boxed_slice(),
+            cram: vec![0u8; MBC2_RAM_LEN].into_boxed_slice(),
             has_battery,
+            dirty: false,
         }
     }
+
+    fn index(addr: CRamAddr) -> usize {
+        addr.raw() as usize % MBC2_RAM_LEN
+    }
 }
 
-impl Savegame for CRamMBC2 {
+impl Savegame for Mbc2Ram {
     fn savegame(&self) -> Option<&[u8]> {
         if self.has_battery {
             Some(&self.cram)
@@ -116,40 +173,48 @@ impl Savegame for CRamMBC2 {
     }
 
     fn savegame_mut(&mut self) -> Option<&mut [u8]> {
+        // Imported savegames can contain anything in the upper half of each byte, but
+        // that is masked away by `read`
         if self.has_battery {
             Some(&mut self.cram)
         } else {
             None
         }
     }
+
+    fn savegame_dirty(&self) -> bool {
+        self.has_battery && self.dirty
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.dirty = false;
+    }
 }
 
-impl CartridgeRam for CRamMBC2 {
+impl CartridgeRam for Mbc2Ram {
     fn read(&self, addr: CRamAddr) -> u8 {
-        let shift = (addr.raw() & 1) * 4;
-        let sub_addr = (addr.raw() >> 1) as usize;
-
-        self.cram
-            .get(sub_addr)
-            .map(|val| (val >> shift) & 0xF)
-            .unwrap_or(0xF) // TODO: Check if illegal reads here return 0xF or 0xFF (or something wild)
+        0xF0 | (self.cram[Self::index(addr)] & 0x0F)
     }
 
     fn write(&mut self, addr: CRamAddr, val: u8) {
-        let sub_addr = (addr.raw() >> 1) as usize;
+        self.cram[Self::index(addr)] = val & 0x0F;
+        self.dirty = true;
+    }
 
-        if let Some(mem) = self.cram.get_mut(sub_addr) {
-            let shift = (addr.raw() & 1) * 4;
+    fn try_select_bank(&mut self, _bank: u8) {}
 
-            // Clear the old content
-            *mem &= 0xF0u8.rotate_left(shift as u32);
+    fn mapped_bank(&self) -> Option<u8> {
+        Some(0)
+    }
 
-            // Write the new value
-            *mem |= (val & 0xF) << shift;
-        }
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.cram);
     }
 
-    fn try_select_bank(&mut self, _bank: u8) {}
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.dirty = true;
+        reader.read_bytes(&mut self.cram)
+    }
 }
 
 /// A large amount of RAM with banking support. Selection of the current RAM bank is done by the MBC.
@@ -157,7 +222,9 @@ impl CartridgeRam for CRamMBC2 {
 pub struct CRamBanked {
     cram: Pin<Box<[u8]>>,
     mapped_bank: &'static mut [u8],
+    bank: u8,
     has_battery: bool,
+    dirty: bool,
 }
 
 impl CRamBanked {
@@ -171,7 +238,9 @@ impl CRamBanked {
         Self {
             cram,
             mapped_bank,
+            bank: 0,
             has_battery,
+            dirty: false,
         }
     }
 }
@@ -192,6 +261,14 @@ impl Savegame for CRamBanked {
             None
         }
     }
+
+    fn savegame_dirty(&self) -> bool {
+        self.has_battery && self.dirty
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.dirty = false;
+    }
 }
 
 impl CartridgeRam for CRamBanked {
@@ -201,6 +278,7 @@ impl CartridgeRam for CRamBanked {
 
     fn write(&mut self, addr: CRamAddr, val: u8) {
         self.mapped_bank[addr.raw() as usize] = val;
+        self.dirty = true;
     }
 
     fn try_select_bank(&mut self, bank: u8) {
@@ -210,6 +288,29 @@ impl CartridgeRam for CRamBanked {
             // will never become invalid
             self.mapped_bank =
                 unsafe { std::mem::transmute(&mut self.cram[0x2000 * bank as usize..]) };
+            self.bank = bank;
+        }
+    }
+
+    fn mapped_bank(&self) -> Option<u8> {
+        Some(self.bank)
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.cram);
+        writer.write_u8(self.bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.dirty = true;
+        reader.read_bytes(&mut self.cram)?;
+
+        let bank = reader.read_u8()?;
+        if bank >= 4 {
+            return Err(SaveStateError::InvalidValue);
         }
+
+        self.try_select_bank(bank);
+        Ok(())
     }
 }
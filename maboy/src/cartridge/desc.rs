@@ -36,6 +36,12 @@ impl CartridgeDesc<'_> {
         RamSize::try_from(self.0[0x49]).ok()
     }
 
+    /// Checksum over the whole ROM, which (unlike the header checksum) is not verified
+    /// by the Game Boy, but tells apart different revisions of a game
+    pub fn global_checksum(&self) -> u16 {
+        u16::from_be_bytes([self.0[0x4E], self.0[0x4F]])
+    }
+
     pub fn has_valid_checksum(&self) -> bool {
         let mut checksum = 0u8;
         for i in 0x34..=0x4C {
@@ -120,7 +126,7 @@ impl CartridgeType {
             CartridgeType::MBC5_RUMBLE_RAM_BATTERY => true,
             CartridgeType::POCKET_CAMERA => false,
             CartridgeType::BANDAI_TAMA5 => false,
-            CartridgeType::HuC3 => false,
+            CartridgeType::HuC3 => true,
             CartridgeType::HuC1_RAM_BATTERY => true,
         }
     }
@@ -1,4 +1,5 @@
 use crate::address::CRomAddr;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use std::pin::Pin;
 
 // TODO: Be more consistent where warn!, debug!, error! are used
@@ -9,6 +10,14 @@ pub struct BankedRom {
     rom: Pin<Box<[u8]>>,
     // TODO: Figure out exact behaviour when a non-existent bank is selected
     mapped_bank: Option<&'static [u8]>,
+    /// Index of the most recently selected bank, even if it doesn't exist. MBC5 has 9 bits
+    /// of bank number, all others have at most 8.
+    bank: u16,
+    /// Debugging override that is mapped instead of [`bank`] if present
+    forced_bank: Option<u16>,
+    /// Offset of the bank at 0x0000 - 0x3FFF, which is always bank 0 except for MBC1
+    /// in advanced banking mode
+    bank0_offset: usize,
 }
 
 impl BankedRom {
@@ -19,12 +28,72 @@ impl BankedRom {
         // lives inside of self
         let mapped_bank = Some(unsafe { std::mem::transmute(&rom[0x4000..]) });
 
-        Self { rom, mapped_bank }
+        Self {
+            rom,
+            mapped_bank,
+            bank: 1,
+            forced_bank: None,
+            bank0_offset: 0,
+        }
     }
 
     /// If the ROM bank does not exist, this activates a "fake" ROM bank which will
     /// only ever return `0xFF` on reads
-    pub fn select_bank(&mut self, bank: u8) {
+    pub fn select_bank(&mut self, bank: u16) {
+        self.bank = bank;
+        self.map_bank(self.forced_bank.unwrap_or(bank));
+    }
+
+    /// Maps another bank at 0x0000 - 0x3FFF. Non-existent banks are ignored, since
+    /// the only MBC that can do this (MBC1) never selects them.
+    pub fn select_bank0(&mut self, bank: u8) {
+        let bank_idx = bank as usize * 0x4000;
+
+        if self.rom.len() >= bank_idx + 0x4000 {
+            self.bank0_offset = bank_idx;
+        } else {
+            log::warn!("Attempted to map non-existent ROM bank {} at 0x0000", bank);
+        }
+    }
+
+    /// The bank that is actually mapped: The forced bank (see [`BankedRom::force_bank`])
+    /// if there is one, otherwise the one that was selected by the game
+    pub fn current_bank(&self) -> u16 {
+        self.forced_bank.unwrap_or(self.bank)
+    }
+
+    /// The bank that is mapped at 0x0000 - 0x3FFF (see [`BankedRom::select_bank0`])
+    pub fn current_bank0(&self) -> u16 {
+        (self.bank0_offset / 0x4000) as u16
+    }
+
+    pub fn forced_bank(&self) -> Option<u16> {
+        self.forced_bank
+    }
+
+    pub fn bank_count(&self) -> u16 {
+        (self.rom.len() / 0x4000) as u16
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Maps `bank` regardless of what the game selects, until the override is removed
+    /// by passing `None`. Returns false (and changes nothing) if the bank doesn't exist.
+    pub fn force_bank(&mut self, bank: Option<u16>) -> bool {
+        if let Some(bank) = bank {
+            if bank >= self.bank_count() {
+                return false;
+            }
+        }
+
+        self.forced_bank = bank;
+        self.map_bank(bank.unwrap_or(self.bank));
+        true
+    }
+
+    fn map_bank(&mut self, bank: u16) {
         let bank_idx = bank as usize * 0x4000;
 
         self.mapped_bank = if self.rom.len() >= bank_idx + 0x4000 {
@@ -41,7 +110,7 @@ impl BankedRom {
     /// Reads a byte from ROM (bank 0 or the currently active switchable bank)
     pub fn read(&self, addr: CRomAddr) -> u8 {
         match addr {
-            CRomAddr::CROM0(addr) => self.rom[addr as usize],
+            CRomAddr::CROM0(addr) => self.rom[self.bank0_offset + addr as usize],
             CRomAddr::CROMn(addr) => self
                 .mapped_bank
                 .map(|bank| bank[addr as usize])
@@ -49,3 +118,46 @@ impl BankedRom {
         }
     }
 }
+
+impl SaveState for BankedRom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.select_bank(reader.read_u16()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ROM with the maximum MBC5 size, where every bank is filled with the lower byte
+    /// of its number
+    fn rom_512_banks() -> BankedRom {
+        let rom: Vec<u8> = (0..512u16)
+            .flat_map(|bank| std::iter::repeat_n(bank as u8, 0x4000))
+            .collect();
+
+        BankedRom::new(rom.into_boxed_slice())
+    }
+
+    #[test]
+    fn banks_above_255_can_be_forced() {
+        let mut rom = rom_512_banks();
+        rom.select_bank(2);
+
+        assert!(rom.force_bank(Some(300)));
+        assert_eq!(rom.current_bank(), 300);
+        assert_eq!(rom.read(CRomAddr::CROMn(0)), 300u16 as u8);
+
+        assert!(!rom.force_bank(Some(512)));
+        assert_eq!(rom.current_bank(), 300);
+
+        assert!(rom.force_bank(None));
+        assert_eq!(rom.current_bank(), 2);
+        assert_eq!(rom.read(CRomAddr::CROMn(0)), 2);
+    }
+}
@@ -0,0 +1,120 @@
+use super::{banked_rom::BankedRom, CartridgeMBC};
+use crate::address::{CRamAddr, CRomAddr};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::{cartridge::cram::CartridgeRam, BankState, Metadata, Savegame};
+
+/// What reading the IR port returns when no light is received. Nothing ever sends
+/// any, since the IR port is only stubbed.
+pub(super) const IR_NO_LIGHT: u8 = 0xC0;
+
+/// Hudson's MBC with an infrared port, used by a handful of Japanese games. Banking
+/// works similar to MBC1, but there is no RAM enable register: 0x0000 - 0x1FFF
+/// instead selects whether the IR port or CRAM is mapped to 0xA000 - 0xBFFF.
+///
+/// The IR port is stubbed: It never receives light, and the LED is ignored.
+pub struct HuC1<CRAM> {
+    rom: BankedRom,
+    cram: CRAM,
+    ir_mapped: bool,
+}
+
+impl<CRAM: CartridgeRam> HuC1<CRAM> {
+    pub fn new(rom: Box<[u8]>, cram: CRAM) -> Self {
+        Self {
+            rom: BankedRom::new(rom),
+            cram,
+            ir_mapped: false,
+        }
+    }
+}
+
+impl<CRAM: CartridgeRam> Savegame for HuC1<CRAM> {
+    fn savegame(&self) -> Option<&[u8]> {
+        self.cram.savegame()
+    }
+
+    fn savegame_mut(&mut self) -> Option<&mut [u8]> {
+        self.cram.savegame_mut()
+    }
+
+    fn savegame_dirty(&self) -> bool {
+        self.cram.savegame_dirty()
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.cram.mark_savegame_clean()
+    }
+}
+
+impl<CRAM> Metadata for HuC1<CRAM> {}
+
+impl<CRAM: CartridgeRam> CartridgeMBC for HuC1<CRAM> {
+    type CRAM = CRAM;
+
+    fn read_rom(&self, addr: CRomAddr) -> u8 {
+        self.rom.read(addr)
+    }
+
+    fn write_rom(&mut self, addr: CRomAddr, val: u8) {
+        match addr {
+            CRomAddr::CROM0(addr) if addr < 0x2000 => self.ir_mapped = val & 0x0F == 0x0E,
+            CRomAddr::CROM0(_) => self.rom.select_bank((val & 0b_0011_1111).max(1).into()),
+            CRomAddr::CROMn(addr) if addr < 0x2000 => self.cram.try_select_bank(val & 0b11),
+            // Doesn't do anything on HuC1, but games write to it anyway
+            CRomAddr::CROMn(_) => (),
+        }
+    }
+
+    fn read_cram(&self, addr: CRamAddr) -> u8 {
+        if self.ir_mapped {
+            IR_NO_LIGHT
+        } else {
+            self.cram.read(addr)
+        }
+    }
+
+    fn write_cram(&mut self, addr: CRamAddr, val: u8) {
+        if !self.ir_mapped {
+            self.cram.write(addr, val);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ir_mapped = false;
+        self.rom.select_bank(1);
+        self.cram.try_select_bank(0);
+    }
+
+    fn bank_state(&self) -> BankState {
+        BankState {
+            rom_bank0: self.rom.current_bank0(),
+            rom_bank: self.rom.current_bank(),
+            rom_bank_count: self.rom.bank_count(),
+            rom_bank_forced: self.rom.forced_bank().is_some(),
+            ram_bank: self.cram.mapped_bank(),
+            ram_enabled: !self.ir_mapped,
+            rtc_mapped: false,
+        }
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        self.rom.force_bank(bank)
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.rom.rom()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.rom.save_state(writer);
+        self.cram.save_state(writer);
+        writer.write_bool(self.ir_mapped);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.rom.load_state(reader)?;
+        self.cram.load_state(reader)?;
+        self.ir_mapped = reader.read_bool()?;
+        Ok(())
+    }
+}
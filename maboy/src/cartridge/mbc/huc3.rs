@@ -0,0 +1,157 @@
+use super::{banked_rom::BankedRom, huc1::IR_NO_LIGHT, write_ram_enable, CartridgeMBC};
+use crate::address::{CRamAddr, CRomAddr};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::{cartridge::cram::CartridgeRam, BankState, Metadata, Savegame};
+
+/// Hudson's successor to HuC1, which adds a real-time clock. The lower nibble written
+/// to 0x0000 - 0x1FFF selects what is mapped to 0xA000 - 0xBFFF:
+///
+/// ```text
+/// 0x0  CRAM, read-only
+/// 0xA  CRAM, also enables writing to it like the RAM enable register of other MBCs
+/// 0xB  RTC command (write)
+/// 0xC  RTC response (read)
+/// 0xD  RTC semaphore
+/// 0xE  IR port
+/// ```
+///
+/// Both the RTC and the IR port are stubbed: RTC commands are accepted, but every
+/// value reads back as 0, and the IR port never receives light. Games that only use
+/// the clock for flavor still run, but won't see any time pass.
+pub struct HuC3<CRAM> {
+    rom: BankedRom,
+    cram: CRAM,
+    /// Whether CRAM is writable, which is only the case in [`MODE_CRAM`]
+    cram_enabled: bool,
+    mode: u8,
+    /// The most recent RTC command. Its upper nibble is echoed in the response.
+    rtc_command: u8,
+}
+
+const MODE_CRAM_READ_ONLY: u8 = 0x0;
+const MODE_CRAM: u8 = 0xA;
+const MODE_RTC_COMMAND: u8 = 0xB;
+const MODE_RTC_RESPONSE: u8 = 0xC;
+const MODE_RTC_SEMAPHORE: u8 = 0xD;
+const MODE_IR: u8 = 0xE;
+
+impl<CRAM: CartridgeRam> HuC3<CRAM> {
+    pub fn new(rom: Box<[u8]>, cram: CRAM) -> Self {
+        Self {
+            rom: BankedRom::new(rom),
+            cram,
+            cram_enabled: false,
+            mode: MODE_CRAM_READ_ONLY,
+            rtc_command: 0,
+        }
+    }
+}
+
+impl<CRAM: CartridgeRam> Savegame for HuC3<CRAM> {
+    fn savegame(&self) -> Option<&[u8]> {
+        self.cram.savegame()
+    }
+
+    fn savegame_mut(&mut self) -> Option<&mut [u8]> {
+        self.cram.savegame_mut()
+    }
+
+    fn savegame_dirty(&self) -> bool {
+        self.cram.savegame_dirty()
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.cram.mark_savegame_clean()
+    }
+
+    fn savegame_ram_enabled(&self) -> bool {
+        self.cram_enabled
+    }
+}
+
+impl<CRAM> Metadata for HuC3<CRAM> {}
+
+impl<CRAM: CartridgeRam> CartridgeMBC for HuC3<CRAM> {
+    type CRAM = CRAM;
+
+    fn read_rom(&self, addr: CRomAddr) -> u8 {
+        self.rom.read(addr)
+    }
+
+    fn write_rom(&mut self, addr: CRomAddr, val: u8) {
+        match addr {
+            CRomAddr::CROM0(addr) if addr < 0x2000 => {
+                self.mode = val & 0x0F;
+                write_ram_enable(&mut self.cram_enabled, val);
+            }
+            CRomAddr::CROM0(_) => self.rom.select_bank((val & 0b_0111_1111).max(1).into()),
+            CRomAddr::CROMn(addr) if addr < 0x2000 => self.cram.try_select_bank(val & 0b11),
+            CRomAddr::CROMn(_) => (),
+        }
+    }
+
+    fn read_cram(&self, addr: CRamAddr) -> u8 {
+        match self.mode {
+            MODE_CRAM_READ_ONLY | MODE_CRAM => self.cram.read(addr),
+            MODE_RTC_RESPONSE => 0x80 | (self.rtc_command & 0x70),
+            // Always ready for the next command
+            MODE_RTC_SEMAPHORE => 0xFF,
+            MODE_IR => IR_NO_LIGHT,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_cram(&mut self, addr: CRamAddr, val: u8) {
+        match self.mode {
+            _ if self.cram_enabled => self.cram.write(addr, val),
+            MODE_RTC_COMMAND => self.rtc_command = val,
+            _ => (),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.cram_enabled = false;
+        self.mode = MODE_CRAM_READ_ONLY;
+        self.rom.select_bank(1);
+        self.cram.try_select_bank(0);
+    }
+
+    fn bank_state(&self) -> BankState {
+        BankState {
+            rom_bank0: self.rom.current_bank0(),
+            rom_bank: self.rom.current_bank(),
+            rom_bank_count: self.rom.bank_count(),
+            rom_bank_forced: self.rom.forced_bank().is_some(),
+            ram_bank: self.cram.mapped_bank(),
+            ram_enabled: self.cram_enabled,
+            rtc_mapped: matches!(
+                self.mode,
+                MODE_RTC_COMMAND | MODE_RTC_RESPONSE | MODE_RTC_SEMAPHORE
+            ),
+        }
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        self.rom.force_bank(bank)
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.rom.rom()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.rom.save_state(writer);
+        self.cram.save_state(writer);
+        writer.write_u8(self.mode);
+        writer.write_u8(self.rtc_command);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.rom.load_state(reader)?;
+        self.cram.load_state(reader)?;
+        self.mode = reader.read_u8()?;
+        self.cram_enabled = self.mode == MODE_CRAM;
+        self.rtc_command = reader.read_u8()?;
+        Ok(())
+    }
+}
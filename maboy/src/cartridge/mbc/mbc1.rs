@@ -1,8 +1,9 @@
-use super::{banked_rom::BankedRom, CartridgeMBC};
+use super::{banked_rom::BankedRom, write_ram_enable, CartridgeMBC};
 use crate::{
     address::{CRamAddr, CRomAddr},
     cartridge::cram::CartridgeRam,
-    Metadata, Savegame,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+    BankState, Metadata, Savegame,
 };
 
 pub struct MBC1<CRAM> {
@@ -10,7 +11,11 @@ pub struct MBC1<CRAM> {
     cram: CRAM,
     cram_enabled: bool,
     mode: MBC1Mode,
-    mapped_bank_index: u8,
+    wiring: MBC1Wiring,
+    /// The lower ROM bank bits (5 bits, 0x2000 - 0x3FFF)
+    bank1: u8,
+    /// The upper ROM bank bits or the RAM bank (2 bits, 0x4000 - 0x5FFF)
+    bank2: u8,
 }
 
 enum MBC1Mode {
@@ -18,27 +23,80 @@ enum MBC1Mode {
     RamBanking,
 }
 
+/// How the upper bank register (`bank2`) is connected to the ROM
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MBC1Wiring {
+    /// `bank2` supplies bits 5 and 6 of the ROM bank
+    Standard,
+    /// MBC1M: Multicarts (like "Mortal Kombat I & II") that contain several 256 KB games
+    /// in a 1 MB ROM. Bit 4 of `bank1` isn't connected, and `bank2` supplies bits 4 and
+    /// 5 of the ROM bank instead, which selects the game.
+    Multicart,
+}
+
+impl MBC1Wiring {
+    /// There is no header field for this, but every game in a multicart has its own
+    /// header (with the Nintendo logo) at the start of its first bank. Ordinary 1 MB
+    /// games have code or data there instead.
+    pub fn detect(rom: &[u8]) -> MBC1Wiring {
+        const LOGO: std::ops::Range<usize> = 0x104..0x134;
+        const SECOND_GAME: usize = 0x10 * 0x4000;
+
+        if rom.len() == 64 * 0x4000
+            && rom[SECOND_GAME + LOGO.start..SECOND_GAME + LOGO.end] == rom[LOGO]
+        {
+            log::info!("Detected an MBC1 multicart");
+            MBC1Wiring::Multicart
+        } else {
+            MBC1Wiring::Standard
+        }
+    }
+
+    /// How far `bank2` is shifted to form the upper bits of the ROM bank
+    fn bank2_shift(self) -> u8 {
+        match self {
+            MBC1Wiring::Standard => 5,
+            MBC1Wiring::Multicart => 4,
+        }
+    }
+}
+
 impl<CRAM: CartridgeRam> MBC1<CRAM> {
-    pub fn new(rom: Box<[u8]>, cram: CRAM) -> MBC1<CRAM> {
+    pub fn new(rom: Box<[u8]>, cram: CRAM, wiring: MBC1Wiring) -> MBC1<CRAM> {
         MBC1 {
             rom: BankedRom::new(rom),
             cram,
             cram_enabled: false,
             mode: MBC1Mode::RomBanking,
-            mapped_bank_index: 1,
+            wiring,
+            bank1: 1,
+            bank2: 0,
         }
     }
 
-    fn update_mapped_bank(&mut self) {
-        self.mapped_bank_index = match self.mapped_bank_index {
-            0x00 => 0x01,
-            0x20 => 0x21,
-            0x40 => 0x41,
-            0x60 => 0x61,
-            n => n,
-        };
+    /// Maps the banks that the registers select. Bank numbers wrap around at the ROM
+    /// size, since the MBC has more bank lines than small ROMs have address lines.
+    fn update_banks(&mut self) {
+        let bank_mask = (self.rom.bank_count() - 1) as u8;
+        let shift = self.wiring.bank2_shift();
+
+        // A value of 0 in bank1 is treated as 1, but this check looks at all 5 bits,
+        // even if the multicart wiring only uses 4 of them
+        let bank1 = self.bank1.max(1) & ((1 << shift) - 1);
+        let upper = self.bank2 << shift;
+
+        self.rom.select_bank(((upper | bank1) & bank_mask).into());
 
-        self.rom.select_bank(self.mapped_bank_index);
+        match self.mode {
+            MBC1Mode::RomBanking => {
+                self.rom.select_bank0(0);
+                self.cram.try_select_bank(0);
+            }
+            MBC1Mode::RamBanking => {
+                self.rom.select_bank0(upper & bank_mask);
+                self.cram.try_select_bank(self.bank2);
+            }
+        }
     }
 }
 
@@ -50,6 +108,18 @@ impl<CRAM: CartridgeRam> Savegame for MBC1<CRAM> {
     fn savegame_mut(&mut self) -> Option<&mut [u8]> {
         self.cram.savegame_mut()
     }
+
+    fn savegame_dirty(&self) -> bool {
+        self.cram.savegame_dirty()
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.cram.mark_savegame_clean()
+    }
+
+    fn savegame_ram_enabled(&self) -> bool {
+        self.cram_enabled
+    }
 }
 
 impl<CRAM> Metadata for MBC1<CRAM> {}
@@ -63,32 +133,24 @@ impl<CRAM: CartridgeRam> CartridgeMBC for MBC1<CRAM> {
 
     fn write_rom(&mut self, addr: CRomAddr, val: u8) {
         match addr {
-            CRomAddr::CROM0(n) if n < 0x2000 => self.cram_enabled = val & 0xA == 0xA,
+            CRomAddr::CROM0(n) if n < 0x2000 => write_ram_enable(&mut self.cram_enabled, val),
             CRomAddr::CROM0(_) => {
-                if matches!(self.mode, MBC1Mode::RomBanking) {
-                    self.mapped_bank_index = (self.mapped_bank_index & (!0x1F)) + (val & 0x1F);
-                    self.update_mapped_bank();
-                }
+                self.bank1 = val & 0x1F;
+                self.update_banks();
+            }
+            CRomAddr::CROMn(n) if n < 0x2000 => {
+                self.bank2 = val & 0b11;
+                self.update_banks();
+            }
+            CRomAddr::CROMn(_) => {
+                // Only the lowest bit is connected
+                self.mode = if val & 1 == 0 {
+                    MBC1Mode::RomBanking
+                } else {
+                    MBC1Mode::RamBanking
+                };
+                self.update_banks();
             }
-            CRomAddr::CROMn(n) if n < 0x2000 => match self.mode {
-                MBC1Mode::RomBanking => {
-                    self.mapped_bank_index = self.mapped_bank_index & 0x1F + ((val & 0b11) << 5);
-                    self.update_mapped_bank();
-                }
-                MBC1Mode::RamBanking => self.cram.try_select_bank(val),
-            },
-            CRomAddr::CROMn(_) => match val {
-                0 => {
-                    self.mode = MBC1Mode::RomBanking;
-                    self.cram.try_select_bank(0);
-                }
-                1 => {
-                    self.mode = MBC1Mode::RamBanking;
-                    self.mapped_bank_index &= 0x1F;
-                    self.update_mapped_bank();
-                }
-                n => log::warn!("Invalid value {:#04X} written to MBC1 mode select", n),
-            },
         }
     }
 
@@ -105,4 +167,60 @@ impl<CRAM: CartridgeRam> CartridgeMBC for MBC1<CRAM> {
             self.cram.write(addr, val)
         }
     }
+
+    fn reset(&mut self) {
+        self.cram_enabled = false;
+        self.mode = MBC1Mode::RomBanking;
+        self.bank1 = 1;
+        self.bank2 = 0;
+        self.update_banks();
+    }
+
+    fn bank_state(&self) -> BankState {
+        BankState {
+            rom_bank0: self.rom.current_bank0(),
+            rom_bank: self.rom.current_bank(),
+            rom_bank_count: self.rom.bank_count(),
+            rom_bank_forced: self.rom.forced_bank().is_some(),
+            ram_bank: self.cram.mapped_bank(),
+            ram_enabled: self.cram_enabled,
+            rtc_mapped: false,
+        }
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        self.rom.force_bank(bank)
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.rom.rom()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.rom.save_state(writer);
+        self.cram.save_state(writer);
+        writer.write_bool(self.cram_enabled);
+        writer.write_bool(matches!(self.mode, MBC1Mode::RamBanking));
+        // Both registers in one byte, which is what older versions stored as the
+        // selected bank
+        writer.write_u8((self.bank2 << 5) | self.bank1);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.rom.load_state(reader)?;
+        self.cram.load_state(reader)?;
+        self.cram_enabled = reader.read_bool()?;
+        self.mode = if reader.read_bool()? {
+            MBC1Mode::RamBanking
+        } else {
+            MBC1Mode::RomBanking
+        };
+
+        let banks = reader.read_u8()?;
+        self.bank1 = banks & 0x1F;
+        self.bank2 = (banks >> 5) & 0b11;
+        self.update_banks();
+
+        Ok(())
+    }
 }
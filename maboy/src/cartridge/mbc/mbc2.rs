@@ -1,11 +1,12 @@
-use super::{banked_rom::BankedRom, CartridgeMBC};
+use super::{banked_rom::BankedRom, write_ram_enable, CartridgeMBC};
 use crate::address::{CRamAddr, CRomAddr};
-use crate::cartridge::cram::CRamMBC2;
-use crate::{cartridge::CartridgeRam, util::BitOps, Metadata, Savegame};
+use crate::cartridge::cram::Mbc2Ram;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::{cartridge::CartridgeRam, util::BitOps, BankState, Metadata, Savegame};
 
 pub struct MBC2 {
     rom: BankedRom,
-    cram: CRamMBC2,
+    cram: Mbc2Ram,
     cram_enabled: bool,
 }
 
@@ -13,7 +14,7 @@ impl MBC2 {
     pub fn new(rom: Box<[u8]>, has_battery: bool) -> MBC2 {
         MBC2 {
             rom: BankedRom::new(rom),
-            cram: CRamMBC2::new(has_battery),
+            cram: Mbc2Ram::new(has_battery),
             cram_enabled: false,
         }
     }
@@ -27,12 +28,24 @@ impl Savegame for MBC2 {
     fn savegame_mut(&mut self) -> Option<&mut [u8]> {
         self.cram.savegame_mut()
     }
+
+    fn savegame_dirty(&self) -> bool {
+        self.cram.savegame_dirty()
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.cram.mark_savegame_clean()
+    }
+
+    fn savegame_ram_enabled(&self) -> bool {
+        self.cram_enabled
+    }
 }
 
 impl Metadata for MBC2 {}
 
 impl CartridgeMBC for MBC2 {
-    type CRAM = CRamMBC2;
+    type CRAM = Mbc2Ram;
 
     fn read_rom(&self, addr: CRomAddr) -> u8 {
         self.rom.read(addr)
@@ -42,13 +55,11 @@ impl CartridgeMBC for MBC2 {
         if let CRomAddr::CROM0(addr) = addr {
             if addr < 0x2000 {
                 if !addr.bit(8) {
-                    // TODO: Check if this conditions is correct. I just assume it's
-                    // the same as for MBC1
-                    self.cram_enabled = val & 0xA == 0xA;
+                    write_ram_enable(&mut self.cram_enabled, val);
                 }
             } else {
                 if addr.bit(8) {
-                    self.rom.select_bank(val & 0xF)
+                    self.rom.select_bank((val & 0xF).into())
                 }
             }
         }
@@ -67,4 +78,42 @@ impl CartridgeMBC for MBC2 {
             self.cram.write(addr, val)
         }
     }
+
+    fn reset(&mut self) {
+        self.cram_enabled = false;
+        self.rom.select_bank(1);
+    }
+
+    fn bank_state(&self) -> BankState {
+        BankState {
+            rom_bank0: self.rom.current_bank0(),
+            rom_bank: self.rom.current_bank(),
+            rom_bank_count: self.rom.bank_count(),
+            rom_bank_forced: self.rom.forced_bank().is_some(),
+            ram_bank: self.cram.mapped_bank(),
+            ram_enabled: self.cram_enabled,
+            rtc_mapped: false,
+        }
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        self.rom.force_bank(bank)
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.rom.rom()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.rom.save_state(writer);
+        self.cram.save_state(writer);
+        writer.write_bool(self.cram_enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.rom.load_state(reader)?;
+        self.cram.load_state(reader)?;
+        self.cram_enabled = reader.read_bool()?;
+        Ok(())
+    }
 }
@@ -1,6 +1,7 @@
-use super::{banked_rom::BankedRom, rtc::Rtc, CartridgeMBC};
+use super::{banked_rom::BankedRom, rtc::Rtc, write_ram_enable, CartridgeMBC};
 use crate::address::{CRamAddr, CRomAddr};
-use crate::{cartridge::cram::CartridgeRam, Metadata, Savegame};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::{cartridge::cram::CartridgeRam, BankState, Metadata, Savegame};
 
 /// For speedyness reasons, we split MBC3 into a variant with an RTC module,
 /// and one without it.
@@ -29,6 +30,18 @@ impl<CRAM: CartridgeRam> Savegame for MBC3<CRAM> {
     fn savegame_mut(&mut self) -> Option<&mut [u8]> {
         self.cram.savegame_mut()
     }
+
+    fn savegame_dirty(&self) -> bool {
+        self.cram.savegame_dirty()
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.cram.mark_savegame_clean()
+    }
+
+    fn savegame_ram_enabled(&self) -> bool {
+        self.cram_enabled
+    }
 }
 
 impl<CRAM> Metadata for MBC3<CRAM> {}
@@ -42,16 +55,16 @@ impl<CRAM: CartridgeRam> CartridgeMBC for MBC3<CRAM> {
 
     fn write_rom(&mut self, addr: CRomAddr, val: u8) {
         match addr {
-            CRomAddr::CROM0(addr) if addr < 0x2000 => self.cram_enabled = val & 0xA == 0xA,
+            CRomAddr::CROM0(addr) if addr < 0x2000 => write_ram_enable(&mut self.cram_enabled, val),
             CRomAddr::CROM0(_) => {
                 if val != 0 {
-                    self.rom.select_bank(val & 0b_0111_1111)
+                    self.rom.select_bank((val & 0b_0111_1111).into())
                 } else {
                     self.rom.select_bank(1)
                 }
             }
             CRomAddr::CROMn(addr) => {
-                if addr < 0x6000 {
+                if addr < 0x2000 {
                     self.cram.try_select_bank(val)
                 }
             }
@@ -71,6 +84,45 @@ impl<CRAM: CartridgeRam> CartridgeMBC for MBC3<CRAM> {
             self.cram.write(addr, val);
         }
     }
+
+    fn reset(&mut self) {
+        self.cram_enabled = false;
+        self.rom.select_bank(1);
+        self.cram.try_select_bank(0);
+    }
+
+    fn bank_state(&self) -> BankState {
+        BankState {
+            rom_bank0: self.rom.current_bank0(),
+            rom_bank: self.rom.current_bank(),
+            rom_bank_count: self.rom.bank_count(),
+            rom_bank_forced: self.rom.forced_bank().is_some(),
+            ram_bank: self.cram.mapped_bank(),
+            ram_enabled: self.cram_enabled,
+            rtc_mapped: false,
+        }
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        self.rom.force_bank(bank)
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.rom.rom()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.rom.save_state(writer);
+        self.cram.save_state(writer);
+        writer.write_bool(self.cram_enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.rom.load_state(reader)?;
+        self.cram.load_state(reader)?;
+        self.cram_enabled = reader.read_bool()?;
+        Ok(())
+    }
 }
 
 pub struct MBC3Rtc<CRAM> {
@@ -108,6 +160,18 @@ impl<CRAM: CartridgeRam> Savegame for MBC3Rtc<CRAM> {
     fn savegame_mut(&mut self) -> Option<&mut [u8]> {
         self.cram.savegame_mut()
     }
+
+    fn savegame_dirty(&self) -> bool {
+        self.cram.savegame_dirty()
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.cram.mark_savegame_clean()
+    }
+
+    fn savegame_ram_enabled(&self) -> bool {
+        self.cram_rtc_enabled
+    }
 }
 
 impl<CRAM> Metadata for MBC3Rtc<CRAM> {
@@ -133,15 +197,17 @@ impl<CRAM: CartridgeRam> CartridgeMBC for MBC3Rtc<CRAM> {
 
     fn write_rom(&mut self, addr: CRomAddr, val: u8) {
         match addr {
-            CRomAddr::CROM0(addr) if addr < 0x2000 => self.cram_rtc_enabled = val & 0xA == 0xA,
+            CRomAddr::CROM0(addr) if addr < 0x2000 => {
+                write_ram_enable(&mut self.cram_rtc_enabled, val)
+            }
             CRomAddr::CROM0(_) => {
                 if val != 0 {
-                    self.rom.select_bank(val & 0b_0111_1111);
+                    self.rom.select_bank((val & 0b_0111_1111).into());
                 } else {
                     self.rom.select_bank(1);
                 }
             }
-            CRomAddr::CROMn(addr) if addr < 0x6000 => {
+            CRomAddr::CROMn(addr) if addr < 0x2000 => {
                 if val < 4 {
                     self.cram.try_select_bank(val);
 
@@ -154,7 +220,7 @@ impl<CRAM: CartridgeRam> CartridgeMBC for MBC3Rtc<CRAM> {
             }
             CRomAddr::CROMn(_) => {
                 if self.latch_reg_last_write == 0 && val == 1 {
-                    self.rtc.toggle_latched()
+                    self.rtc.latch()
                 }
 
                 self.latch_reg_last_write = val;
@@ -181,4 +247,56 @@ impl<CRAM: CartridgeRam> CartridgeMBC for MBC3Rtc<CRAM> {
             }
         }
     }
+
+    fn reset(&mut self) {
+        // The RTC is battery-powered, so it just keeps on ticking
+        self.cram_rtc_enabled = false;
+        self.rom.select_bank(1);
+        self.cram.try_select_bank(0);
+        self.mapping = Mapping::CRam;
+        self.latch_reg_last_write = 1;
+    }
+
+    fn bank_state(&self) -> BankState {
+        BankState {
+            rom_bank0: self.rom.current_bank0(),
+            rom_bank: self.rom.current_bank(),
+            rom_bank_count: self.rom.bank_count(),
+            rom_bank_forced: self.rom.forced_bank().is_some(),
+            ram_bank: self.cram.mapped_bank(),
+            ram_enabled: self.cram_rtc_enabled,
+            rtc_mapped: matches!(self.mapping, Mapping::Rtc),
+        }
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        self.rom.force_bank(bank)
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.rom.rom()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.rom.save_state(writer);
+        self.cram.save_state(writer);
+        self.rtc.save_state(writer);
+        writer.write_bool(self.cram_rtc_enabled);
+        writer.write_bool(matches!(self.mapping, Mapping::Rtc));
+        writer.write_u8(self.latch_reg_last_write);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.rom.load_state(reader)?;
+        self.cram.load_state(reader)?;
+        self.rtc.load_state(reader)?;
+        self.cram_rtc_enabled = reader.read_bool()?;
+        self.mapping = if reader.read_bool()? {
+            Mapping::Rtc
+        } else {
+            Mapping::CRam
+        };
+        self.latch_reg_last_write = reader.read_u8()?;
+        Ok(())
+    }
 }
@@ -0,0 +1,168 @@
+use super::{banked_rom::BankedRom, write_ram_enable, CartridgeMBC};
+use crate::address::{CRamAddr, CRomAddr};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::{cartridge::cram::CartridgeRam, util::BitOps, BankState, Metadata, Savegame};
+
+/// The simplest of the big MBCs: A 9-bit ROM bank register (split over two addresses),
+/// a 4-bit RAM bank register, and no special cases. Unlike on every other MBC, bank 0
+/// can be mapped to 0x4000 - 0x7FFF.
+///
+/// Rumble cartridges (e.g. Pokémon Pinball) connect the motor to bit 3 of the RAM bank
+/// register instead, leaving 3 bits for the RAM bank.
+pub struct MBC5<CRAM> {
+    rom: BankedRom,
+    cram: CRAM,
+    cram_enabled: bool,
+    /// The 9-bit ROM bank register, before it is wrapped at the ROM size
+    rom_bank: u16,
+    has_rumble: bool,
+    motor_on: bool,
+    /// Whether the motor was on at any time since the last [`CartridgeMBC::poll_rumble`].
+    /// Games drive the motor in short bursts to control its strength, which would be
+    /// missed if only the current state was polled.
+    motor_was_on: bool,
+}
+
+impl<CRAM: CartridgeRam> MBC5<CRAM> {
+    pub fn new(rom: Box<[u8]>, cram: CRAM, has_rumble: bool) -> Self {
+        Self {
+            rom: BankedRom::new(rom),
+            cram,
+            cram_enabled: false,
+            rom_bank: 1,
+            has_rumble,
+            motor_on: false,
+            motor_was_on: false,
+        }
+    }
+
+    fn update_rom_bank(&mut self) {
+        let bank_mask = self.rom.bank_count() - 1;
+        self.rom.select_bank(self.rom_bank & bank_mask);
+    }
+
+    fn set_motor(&mut self, on: bool) {
+        self.motor_on = on;
+        self.motor_was_on |= on;
+    }
+}
+
+impl<CRAM: CartridgeRam> Savegame for MBC5<CRAM> {
+    fn savegame(&self) -> Option<&[u8]> {
+        self.cram.savegame()
+    }
+
+    fn savegame_mut(&mut self) -> Option<&mut [u8]> {
+        self.cram.savegame_mut()
+    }
+
+    fn savegame_dirty(&self) -> bool {
+        self.cram.savegame_dirty()
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.cram.mark_savegame_clean()
+    }
+
+    fn savegame_ram_enabled(&self) -> bool {
+        self.cram_enabled
+    }
+}
+
+impl<CRAM> Metadata for MBC5<CRAM> {}
+
+impl<CRAM: CartridgeRam> CartridgeMBC for MBC5<CRAM> {
+    type CRAM = CRAM;
+
+    fn read_rom(&self, addr: CRomAddr) -> u8 {
+        self.rom.read(addr)
+    }
+
+    fn write_rom(&mut self, addr: CRomAddr, val: u8) {
+        match addr {
+            CRomAddr::CROM0(addr) if addr < 0x2000 => write_ram_enable(&mut self.cram_enabled, val),
+            CRomAddr::CROM0(addr) if addr < 0x3000 => {
+                self.rom_bank = (self.rom_bank & 0x100) | val as u16;
+                self.update_rom_bank();
+            }
+            CRomAddr::CROM0(_) => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((val & 1) as u16) << 8;
+                self.update_rom_bank();
+            }
+            CRomAddr::CROMn(addr) if addr < 0x2000 => {
+                if self.has_rumble {
+                    self.set_motor(val.bit(3));
+                    self.cram.try_select_bank(val & 0b_0111);
+                } else {
+                    self.cram.try_select_bank(val & 0b_1111);
+                }
+            }
+            CRomAddr::CROMn(_) => (),
+        }
+    }
+
+    fn read_cram(&self, addr: CRamAddr) -> u8 {
+        if self.cram_enabled {
+            self.cram.read(addr)
+        } else {
+            0xff
+        }
+    }
+
+    fn write_cram(&mut self, addr: CRamAddr, val: u8) {
+        if self.cram_enabled {
+            self.cram.write(addr, val);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.cram_enabled = false;
+        self.rom_bank = 1;
+        self.update_rom_bank();
+        self.cram.try_select_bank(0);
+        self.motor_on = false;
+    }
+
+    fn bank_state(&self) -> BankState {
+        BankState {
+            rom_bank0: self.rom.current_bank0(),
+            rom_bank: self.rom.current_bank(),
+            rom_bank_count: self.rom.bank_count(),
+            rom_bank_forced: self.rom.forced_bank().is_some(),
+            ram_bank: self.cram.mapped_bank(),
+            ram_enabled: self.cram_enabled,
+            rtc_mapped: false,
+        }
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        self.rom.force_bank(bank)
+    }
+
+    fn poll_rumble(&mut self) -> bool {
+        let was_on = self.motor_was_on;
+        self.motor_was_on = self.motor_on;
+        was_on
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.rom.rom()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.rom.save_state(writer);
+        self.cram.save_state(writer);
+        writer.write_bool(self.cram_enabled);
+        writer.write_u16(self.rom_bank);
+        writer.write_bool(self.motor_on);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.rom.load_state(reader)?;
+        self.cram.load_state(reader)?;
+        self.cram_enabled = reader.read_bool()?;
+        self.rom_bank = reader.read_u16()?;
+        self.set_motor(reader.read_bool()?);
+        Ok(())
+    }
+}
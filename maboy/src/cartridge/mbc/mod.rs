@@ -12,9 +12,12 @@
 //! are never made public beyond the [`crate:::maboy::cartridge`] module.
 
 mod banked_rom;
+mod huc1;
+mod huc3;
 mod mbc1;
 mod mbc2;
 mod mbc3;
+mod mbc5;
 mod rtc;
 
 // TODO: Consistent naming: CRam, Mbc, Ppu, Cpu, ...
@@ -23,12 +26,34 @@ mod rtc;
 use super::cram::CartridgeRam;
 use crate::{
     address::{CRamAddr, CRomAddr},
-    Metadata, Savegame,
+    savestate::{SaveStateError, StateReader, StateWriter},
+    BankState, Metadata, Savegame,
 };
 
-pub(super) use mbc1::MBC1;
+pub(super) use huc1::HuC1;
+pub(super) use huc3::HuC3;
+pub(super) use mbc1::{MBC1Wiring, MBC1};
 pub(super) use mbc2::MBC2;
 pub(super) use mbc3::{MBC3Rtc, MBC3};
+pub(super) use mbc5::MBC5;
+pub(crate) use rtc::metadata_from_regs as rtc_metadata_from_regs;
+
+/// Handles a write to the RAM enable register (0x0000 - 0x1FFF) of MBC1, MBC2, MBC3, MBC5
+/// and HuC3: RAM is enabled by any value with 0xA in the lower nibble, and disabled by
+/// everything else. Reads of disabled RAM return 0xFF, and writes are ignored.
+fn write_ram_enable(enabled: &mut bool, val: u8) {
+    let enable = val & 0x0F == 0x0A;
+
+    if enable != *enabled {
+        log::debug!(
+            "Cartridge RAM {} (wrote {:#04X})",
+            if enable { "enabled" } else { "disabled" },
+            val
+        );
+    }
+
+    *enabled = enable;
+}
 
 /// The public interface of all MBCs. The CPU only communicates with cartridge memory
 /// via this trait.
@@ -40,6 +65,27 @@ pub trait CartridgeMBC: Savegame + Metadata {
 
     fn read_cram(&self, addr: CRamAddr) -> u8;
     fn write_cram(&mut self, addr: CRamAddr, val: u8);
+
+    /// Resets all MBC registers to their power-on values
+    fn reset(&mut self);
+
+    /// See [`crate::Emulator::current_banks`]
+    fn bank_state(&self) -> BankState;
+
+    /// See [`crate::Emulator::force_rom_bank`]
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool;
+
+    /// See [`crate::Emulator::poll_rumble`]. Only rumble cartridges have a motor.
+    fn poll_rumble(&mut self) -> bool {
+        false
+    }
+
+    /// The complete cartridge ROM
+    fn rom(&self) -> &[u8];
+
+    /// Writes all MBC registers and the cartridge RAM into a savestate
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError>;
 }
 
 /// Cartridges with no MBC (e.g. Tetris) can use this MBC implementation where any
@@ -64,6 +110,14 @@ impl<CRAM: CartridgeRam> Savegame for NoMBC<CRAM> {
     fn savegame_mut(&mut self) -> Option<&mut [u8]> {
         self.cram.savegame_mut()
     }
+
+    fn savegame_dirty(&self) -> bool {
+        self.cram.savegame_dirty()
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.cram.mark_savegame_clean()
+    }
 }
 
 impl<CRAM: CartridgeRam> Metadata for NoMBC<CRAM> {}
@@ -89,4 +143,105 @@ impl<CRAM: CartridgeRam> CartridgeMBC for NoMBC<CRAM> {
     fn write_cram(&mut self, addr: CRamAddr, val: u8) {
         self.cram.write(addr, val);
     }
+
+    fn reset(&mut self) {}
+
+    fn bank_state(&self) -> BankState {
+        BankState {
+            rom_bank0: 0,
+            rom_bank: 1,
+            rom_bank_count: 2,
+            rom_bank_forced: false,
+            ram_bank: self.cram.mapped_bank(),
+            ram_enabled: self.cram.mapped_bank().is_some(),
+            rtc_mapped: false,
+        }
+    }
+
+    /// Without an MBC, there is nothing to switch
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        bank.is_none()
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.cram.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.cram.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_ram_enable;
+    use std::cell::RefCell;
+    use std::sync::Once;
+
+    thread_local! {
+        /// Everything that was logged on this thread, so tests running in parallel don't
+        /// see each other's messages
+        static LOGGED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    struct CaptureLogger;
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOGGED.with(|logged| logged.borrow_mut().push(record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Everything that was logged on this thread since the last call
+    fn take_logged() -> Vec<String> {
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+
+        LOGGED.with(|logged| logged.borrow_mut().drain(..).collect())
+    }
+
+    #[test]
+    fn ram_enable_only_looks_at_the_lower_nibble() {
+        for val in 0..=0xFFu8 {
+            let mut enabled = false;
+            write_ram_enable(&mut enabled, val);
+            assert_eq!(enabled, val & 0x0F == 0x0A, "wrote {:#04X}", val);
+
+            let mut enabled = true;
+            write_ram_enable(&mut enabled, val);
+            assert_eq!(enabled, val & 0x0F == 0x0A, "wrote {:#04X}", val);
+        }
+    }
+
+    #[test]
+    fn ram_enable_logs_only_transitions() {
+        let mut enabled = false;
+        take_logged();
+
+        for &val in &[0x00, 0x0A, 0xFA, 0x0B, 0xFF] {
+            write_ram_enable(&mut enabled, val);
+        }
+
+        assert_eq!(
+            take_logged(),
+            [
+                "Cartridge RAM enabled (wrote 0x0A)",
+                "Cartridge RAM disabled (wrote 0x0B)",
+            ]
+        );
+    }
 }
@@ -4,9 +4,24 @@
 //! it to/from a raw byte vector, which is useful for storing the state of the RTC
 //! on disk.
 //!
+//! That byte vector (the cartridge metadata, stored in the `.meta` file) contains the
+//! registers together with the wall-clock time at which they had these values. The time
+//! that passed in between is added back when the metadata is loaded, so the clock keeps
+//! running while the emulator is closed, just like it would with a real battery:
+//!
+//! ```text
+//! u64   Unix timestamp in milliseconds, little-endian
+//! 5×u8  Seconds, minutes, hours, lower 8 bits of the day counter, flags
+//! u8    1 if the registers were latched, 0 otherwise
+//! 5×u8  The latched registers, in the same order (zero if not latched)
+//! ```
+//!
+//! Older versions only wrote the first 13 bytes, which can still be loaded.
+//!
 //! The MBC3 RTC is not very straight-forward. I would recommend reading up on it
 //! somewhere first before diving into this code.
 
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use crate::{util::BitOps, CartridgeParseError};
 use bitflags::bitflags;
 use num_enum::TryFromPrimitive;
@@ -16,17 +31,20 @@ use std::{
     time::{Duration, SystemTime},
 };
 
-// TODO: Figure out if my understadning of latching is correct
-// TODO: Also figure out what fields to serialize (Basically: What
-// is powered by the gameboy, and what is powered by the battery?)
+/// Size of the metadata without latched registers, as written by older versions
+const METADATA_LEN_UNLATCHED: usize = size_of::<u64>() + 5;
+
+/// Size of the metadata
+const METADATA_LEN: usize = METADATA_LEN_UNLATCHED + 1 + 5;
 
 pub struct Rtc {
     /// The system time when this RTC was last written to (changed)
     base: SystemTime,
     /// The values of all RTC registers at the system time [`base`]
     base_reg: RtcReg,
-    /// The system time when the RTC was latched (frozen) to allow safe reading
-    latched: Option<SystemTime>,
+    /// The register values that were frozen by the last latch, which is what the game
+    /// reads. Until the game latches for the first time, it reads the running clock.
+    latched: Option<RtcReg>,
     /// The register of the RTC that is currently selected for reading/writing
     selected_reg: RtcRegAddr,
 }
@@ -34,7 +52,7 @@ pub struct Rtc {
 impl Rtc {
     pub fn new() -> Self {
         Self {
-            base: SystemTime::now(),
+            base: now(),
             base_reg: RtcReg::default(),
             latched: None,
             selected_reg: RtcRegAddr::Seconds,
@@ -44,7 +62,7 @@ impl Rtc {
     /// Attempt to deserialize the state of this struct from a byte vector that was previously
     /// exported via [`Self::export_metadata`]
     pub fn apply_metadata(&mut self, metadata: Vec<u8>) -> Result<(), CartridgeParseError> {
-        if metadata.len() != size_of::<u64>() + 5 {
+        if metadata.len() != METADATA_LEN && metadata.len() != METADATA_LEN_UNLATCHED {
             return Err(CartridgeParseError::InvalidRtcMetadata);
         }
 
@@ -57,50 +75,46 @@ impl Rtc {
             .checked_add(duration_since_epoch)
             .ok_or(CartridgeParseError::InvalidRtcMetadata)?;
 
-        let base_reg = RtcReg {
-            seconds: metadata[size_of::<u64>() + 0],
-            minutes: metadata[size_of::<u64>() + 1],
-            hours: metadata[size_of::<u64>() + 2],
-            days_lower: metadata[size_of::<u64>() + 3],
-            flags: RtcFlags::from_bits(metadata[size_of::<u64>() + 4])
-                .ok_or(CartridgeParseError::InvalidRtcMetadata)?,
+        let base_reg = RtcReg::from_bytes(&metadata[size_of::<u64>()..METADATA_LEN_UNLATCHED])
+            .ok_or(CartridgeParseError::InvalidRtcMetadata)?;
+
+        let latched = match metadata.get(METADATA_LEN_UNLATCHED) {
+            None | Some(0) => None,
+            Some(1) => Some(
+                RtcReg::from_bytes(&metadata[METADATA_LEN_UNLATCHED + 1..])
+                    .ok_or(CartridgeParseError::InvalidRtcMetadata)?,
+            ),
+            Some(_) => return Err(CartridgeParseError::InvalidRtcMetadata),
         };
 
         self.base = base;
         self.base_reg = base_reg;
+        self.latched = latched;
 
         Ok(())
     }
 
     /// Serializes the current state of the struct to store it on disk
     pub fn export_metadata(&self) -> Vec<u8> {
-        let time_since_epoch = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or(Duration::from_secs(0))
-            .as_millis() as u64;
-
-        let mut data = Vec::with_capacity(size_of::<u64>() + 5);
-
-        data.extend_from_slice(&time_since_epoch.to_le_bytes());
-
-        data.push(self.base_reg.seconds);
-        data.push(self.base_reg.minutes);
-        data.push(self.base_reg.hours);
-        data.push(self.base_reg.days_lower);
-        data.push(self.base_reg.flags.bits);
-
-        data
+        metadata_from_regs(
+            self.base,
+            self.base_reg.to_bytes(),
+            self.latched.as_ref().map(RtcReg::to_bytes),
+        )
     }
 
-    /// If unlatched, latches the current time into the RTC registers. Otherwise, the
-    /// latched time is unlatched. Latched registers are only relevant for reading,
-    /// writing is not affected.
-    pub fn toggle_latched(&mut self) {
-        if self.latched.is_some() {
-            self.latched = None;
-        } else {
-            self.latched = Some(SystemTime::now());
-        }
+    /// Freezes the current time in the latched registers, which is what the game reads
+    /// from then on. Writing is not affected.
+    pub fn latch(&mut self) {
+        let elapsed = self.elapsed();
+
+        self.latched = Some(RtcReg {
+            seconds: self.calc_reg(RtcRegAddr::Seconds, elapsed),
+            minutes: self.calc_reg(RtcRegAddr::Minutes, elapsed),
+            hours: self.calc_reg(RtcRegAddr::Hours, elapsed),
+            days_lower: self.calc_reg(RtcRegAddr::DaysLower, elapsed),
+            flags: RtcFlags::from_bits_truncate(self.calc_reg(RtcRegAddr::Flags, elapsed)),
+        });
     }
 
     /// Attempts to set currently selected register. If no corresponding
@@ -116,28 +130,24 @@ impl Rtc {
 
     /// Reads the currently mapped register, respecting latched registers
     pub fn read_reg(&self) -> u8 {
-        if let Some(latched_at) = self.latched {
-            self.calc_reg(
-                self.selected_reg,
-                latched_at
-                    .duration_since(self.base)
-                    .unwrap_or(Duration::from_secs(0)),
-            )
-        } else {
-            self.calc_reg(
-                self.selected_reg,
-                self.base.elapsed().unwrap_or(Duration::from_secs(0)),
-            )
+        match &self.latched {
+            Some(latched) => latched.get(self.selected_reg),
+            None => self.calc_reg(self.selected_reg, self.elapsed()),
         }
     }
 
-    /// Writes to the currently mapped register
+    /// Writes to the currently mapped register. The latched copy of the register is
+    /// updated as well, so the game can read back what it wrote without latching again.
     pub fn write_reg(&mut self, val: u8) {
+        if let Some(latched) = &mut self.latched {
+            *latched.get_mut(self.selected_reg) = self.selected_reg.constrain_value(val);
+        }
+
         if let RtcRegAddr::Flags = self.selected_reg {
             // We unforunately have to recalculate all base registers here, since
             // the DAY_MSB and DAY_CARRY bits can't be fooled by any trickery
 
-            let elapsed = self.base.elapsed().unwrap_or(Duration::from_secs(0));
+            let elapsed = self.elapsed();
 
             self.base_reg.seconds = self.calc_reg(RtcRegAddr::Seconds, elapsed);
             self.base_reg.minutes = self.calc_reg(RtcRegAddr::Minutes, elapsed);
@@ -146,17 +156,14 @@ impl Rtc {
             self.base_reg.flags = RtcFlags::from_bits_truncate(val);
 
             // Eliminate drift by subtracting the fractional second that we "forgot about"
-            self.base = SystemTime::now() - Duration::from_nanos(elapsed.subsec_nanos() as u64);
+            self.base = now() - Duration::from_nanos(elapsed.subsec_nanos() as u64);
         } else {
             // We use a trick here: To avoid recalculating all registers and
             // setting a new self.base, we propagate the relative register
             // difference back to correpsponding register in base_reg.
 
             let target = self.selected_reg.constrain_value(val);
-            let current = self.calc_reg(
-                self.selected_reg,
-                self.base.elapsed().unwrap_or(Duration::from_secs(0)),
-            );
+            let current = self.calc_reg(self.selected_reg, self.elapsed());
 
             if target > current {
                 *self.base_reg.get_mut(self.selected_reg) += target - current;
@@ -171,6 +178,16 @@ impl Rtc {
         }
     }
 
+    /// How long the clock has been running since [`self.base`]. A halted clock doesn't
+    /// run, no matter how much time passes.
+    fn elapsed(&self) -> Duration {
+        if self.base_reg.flags.contains(RtcFlags::HALTED) {
+            Duration::from_secs(0)
+        } else {
+            elapsed_since(self.base)
+        }
+    }
+
     /// Calculates the current value of a register based on the duration that has elpased since
     /// [`self.base`]
     fn calc_reg(&self, reg: RtcRegAddr, elapsed: Duration) -> u8 {
@@ -207,6 +224,101 @@ impl Rtc {
     }
 }
 
+/// The current system time. On `wasm32-unknown-unknown`, the standard library has no
+/// clock (it panics instead), so the RTC stays frozen at the time it was last set to.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn now() -> SystemTime {
+    SystemTime::UNIX_EPOCH
+}
+
+fn elapsed_since(time: SystemTime) -> Duration {
+    now().duration_since(time).unwrap_or(Duration::from_secs(0))
+}
+
+/// Builds metadata as understood by [`Rtc::apply_metadata`] from the register values
+/// (seconds, minutes, hours, lower day bits, flags) that the RTC had at time `base`,
+/// and the latched registers (in the same order), if there are any. Flag bits that the
+/// RTC doesn't know about are dropped.
+pub fn metadata_from_regs(base: SystemTime, regs: [u8; 5], latched: Option<[u8; 5]>) -> Vec<u8> {
+    let mut data = Vec::with_capacity(METADATA_LEN);
+
+    data.extend_from_slice(&system_time_to_millis(base).to_le_bytes());
+    data.extend_from_slice(&RtcReg::from_bytes_truncate(regs).to_bytes());
+
+    match latched {
+        Some(latched) => {
+            data.push(1);
+            data.extend_from_slice(&RtcReg::from_bytes_truncate(latched).to_bytes());
+        }
+        None => data.extend_from_slice(&[0; 6]),
+    }
+
+    data
+}
+
+fn system_time_to_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_millis() as u64
+}
+
+fn millis_to_system_time(millis: u64) -> Result<SystemTime, SaveStateError> {
+    SystemTime::UNIX_EPOCH
+        .checked_add(Duration::from_millis(millis))
+        .ok_or(SaveStateError::InvalidValue)
+}
+
+impl SaveState for Rtc {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(system_time_to_millis(self.base));
+
+        writer.write_u8(self.base_reg.seconds);
+        writer.write_u8(self.base_reg.minutes);
+        writer.write_u8(self.base_reg.hours);
+        writer.write_u8(self.base_reg.days_lower);
+        writer.write_u8(self.base_reg.flags.bits);
+
+        match &self.latched {
+            Some(latched) => {
+                writer.write_bool(true);
+                writer.write_bytes(&latched.to_bytes());
+            }
+            None => writer.write_bool(false),
+        }
+
+        writer.write_u8(self.selected_reg as u8);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base = millis_to_system_time(reader.read_u64()?)?;
+
+        self.base_reg.seconds = reader.read_u8()?;
+        self.base_reg.minutes = reader.read_u8()?;
+        self.base_reg.hours = reader.read_u8()?;
+        self.base_reg.days_lower = reader.read_u8()?;
+        self.base_reg.flags =
+            RtcFlags::from_bits(reader.read_u8()?).ok_or(SaveStateError::InvalidValue)?;
+
+        self.latched = if reader.read_bool()? {
+            let mut latched = [0; 5];
+            reader.read_bytes(&mut latched)?;
+            Some(RtcReg::from_bytes(&latched).ok_or(SaveStateError::InvalidValue)?)
+        } else {
+            None
+        };
+
+        self.selected_reg =
+            RtcRegAddr::try_from(reader.read_u8()?).map_err(|_| SaveStateError::InvalidValue)?;
+
+        Ok(())
+    }
+}
+
 #[derive(TryFromPrimitive, Copy, Clone, Debug)]
 #[repr(u8)]
 enum RtcRegAddr {
@@ -250,6 +362,51 @@ struct RtcReg {
 }
 
 impl RtcReg {
+    /// Seconds, minutes, hours, lower day bits and flags. Returns `None` if the flags
+    /// contain unknown bits.
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            seconds: bytes[0],
+            minutes: bytes[1],
+            hours: bytes[2],
+            days_lower: bytes[3],
+            flags: RtcFlags::from_bits(bytes[4])?,
+        })
+    }
+
+    /// Like [`RtcReg::from_bytes`], but drops unknown flag bits
+    fn from_bytes_truncate(bytes: [u8; 5]) -> Self {
+        let [seconds, minutes, hours, days_lower, flags] = bytes;
+
+        Self {
+            seconds,
+            minutes,
+            hours,
+            days_lower,
+            flags: RtcFlags::from_bits_truncate(flags),
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days_lower,
+            self.flags.bits,
+        ]
+    }
+
+    fn get(&self, addr: RtcRegAddr) -> u8 {
+        match addr {
+            RtcRegAddr::Seconds => self.seconds,
+            RtcRegAddr::Minutes => self.minutes,
+            RtcRegAddr::Hours => self.hours,
+            RtcRegAddr::DaysLower => self.days_lower,
+            RtcRegAddr::Flags => self.flags.bits,
+        }
+    }
+
     fn get_mut(&mut self, addr: RtcRegAddr) -> &mut u8 {
         match addr {
             RtcRegAddr::Seconds => &mut self.seconds,
@@ -9,9 +9,13 @@ mod mbc;
 mod variant;
 
 use super::address::{CRamAddr, CRomAddr};
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 use cram::CartridgeRam;
 use mbc::CartridgeMBC;
 
+pub(crate) use cram::MBC2_RAM_LEN;
+pub(crate) use mbc::rtc_metadata_from_regs;
+
 pub use desc::CartridgeDesc;
 pub use variant::{CartridgeParseError, CartridgeVariant};
 
@@ -38,19 +42,59 @@ impl<MBC: CartridgeMBC> CartridgeImpl<MBC> {
 /// have to write out the MBC type parameter in a million places, and instead can just
 /// accept any type that implements this trait.
 pub trait Cartridge {
-    /// The MBC (memory bank controller) used in the cartridge
-    type MBC: CartridgeMBC;
-
     fn read_rom(&self, addr: CRomAddr) -> u8;
     fn write_rom(&mut self, addr: CRomAddr, val: u8);
 
     fn read_cram(&self, addr: CRamAddr) -> u8;
     fn write_cram(&mut self, addr: CRamAddr, val: u8);
+
+    /// Puts the cartridge into the state it would be in after a power cycle. Cartridge
+    /// RAM and other battery-backed components (like the RTC) are left untouched.
+    fn reset(&mut self);
+
+    /// Writes the state of the MBC and the cartridge RAM into a savestate. ROM is not
+    /// part of the savestate.
+    fn save_state(&self, writer: &mut StateWriter);
+
+    /// Restores the state previously written by [`Cartridge::save_state`]
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError>;
+
+    /// See [`crate::Emulator::current_banks`]
+    fn bank_state(&self) -> BankState;
+
+    /// See [`crate::Emulator::force_rom_bank`]
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool;
+
+    /// See [`crate::Emulator::poll_rumble`]
+    fn poll_rumble(&mut self) -> bool;
+
+    /// The complete cartridge ROM
+    fn rom(&self) -> &[u8];
 }
 
-impl<MBC: CartridgeMBC> Cartridge for CartridgeImpl<MBC> {
-    type MBC = MBC;
+/// The memory banks that are currently mapped into the address space
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BankState {
+    /// The ROM bank mapped to 0x0000-0x3FFF. This is bank 0 except for MBC1 in
+    /// advanced banking mode.
+    pub rom_bank0: u16,
+    /// The ROM bank mapped to 0x4000-0x7FFF
+    pub rom_bank: u16,
+    /// Number of ROM banks on the cartridge
+    pub rom_bank_count: u16,
+    /// Whether [`BankState::rom_bank`] is a debugging override (see
+    /// [`crate::Emulator::force_rom_bank`]) instead of the bank selected by the game
+    pub rom_bank_forced: bool,
+    /// The RAM bank mapped to 0xA000-0xBFFF, or `None` if the cartridge has no RAM
+    pub ram_bank: Option<u8>,
+    /// Whether the game enabled access to cartridge RAM (and RTC)
+    pub ram_enabled: bool,
+    /// Whether an RTC register is mapped to 0xA000-0xBFFF instead of RAM (MBC3 only)
+    pub rtc_mapped: bool,
+}
 
+impl<MBC: CartridgeMBC> Cartridge for CartridgeImpl<MBC> {
     fn read_rom(&self, addr: CRomAddr) -> u8 {
         self.mbc.read_rom(addr)
     }
@@ -66,6 +110,34 @@ impl<MBC: CartridgeMBC> Cartridge for CartridgeImpl<MBC> {
     fn write_cram(&mut self, addr: CRamAddr, val: u8) {
         self.mbc.write_cram(addr, val);
     }
+
+    fn reset(&mut self) {
+        self.mbc.reset();
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.mbc.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.mbc.load_state(reader)
+    }
+
+    fn bank_state(&self) -> BankState {
+        self.mbc.bank_state()
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        self.mbc.force_rom_bank(bank)
+    }
+
+    fn poll_rumble(&mut self) -> bool {
+        self.mbc.poll_rumble()
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.mbc.rom()
+    }
 }
 
 /// This trait is used to provide access to the internal cartridge RAM. This is
@@ -88,6 +160,13 @@ impl<MBC: CartridgeMBC> Cartridge for CartridgeImpl<MBC> {
 ///     fs::write(savegame_path, cram).expect("Could not write savegame to disk");
 /// }
 /// ```
+///
+/// Writing the savegame to disk whenever the game changed it, so a crash doesn't lose
+/// any progress (see [`crate::frontend::AutoSave`] for a version that waits until the
+/// game is done writing):
+/// ```ignore
+/// cartridge.flush_if_dirty(|cram| fs::write(savegame_path, cram))?;
+/// ```
 pub trait Savegame {
     fn savegame(&self) -> Option<&[u8]> {
         None
@@ -96,6 +175,39 @@ pub trait Savegame {
     fn savegame_mut(&mut self) -> Option<&mut [u8]> {
         None
     }
+
+    /// Whether the game wrote to the savegame since the last call of
+    /// [`Savegame::mark_savegame_clean`] (or since the cartridge was created). Loading a
+    /// savestate counts as a write. Changes made via [`Savegame::savegame_mut`] don't.
+    fn savegame_dirty(&self) -> bool {
+        false
+    }
+
+    fn mark_savegame_clean(&mut self) {}
+
+    /// Whether the game left cartridge RAM enabled. Games usually disable it as soon as
+    /// they are done writing (to protect it from stray writes), so a savegame that is
+    /// stored while RAM is enabled might be halfway written. Cartridges without a RAM
+    /// enable gate always return `false`.
+    fn savegame_ram_enabled(&self) -> bool {
+        false
+    }
+
+    /// Calls `flush` with the savegame if it is dirty, and marks it as clean if that
+    /// succeeds. Returns whether `flush` was called.
+    fn flush_if_dirty<E, F: FnOnce(&[u8]) -> Result<(), E>>(&mut self, flush: F) -> Result<bool, E>
+    where
+        Self: Sized,
+    {
+        match self.savegame() {
+            Some(savegame) if self.savegame_dirty() => {
+                flush(savegame)?;
+                self.mark_savegame_clean();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
 }
 
 impl<MBC: CartridgeMBC> Savegame for CartridgeImpl<MBC> {
@@ -106,12 +218,29 @@ impl<MBC: CartridgeMBC> Savegame for CartridgeImpl<MBC> {
     fn savegame_mut(&mut self) -> Option<&mut [u8]> {
         self.mbc.savegame_mut()
     }
+
+    fn savegame_dirty(&self) -> bool {
+        self.mbc.savegame_dirty()
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        self.mbc.mark_savegame_clean()
+    }
+
+    fn savegame_ram_enabled(&self) -> bool {
+        self.mbc.savegame_ram_enabled()
+    }
 }
 
 /// Some cartridges can use external metadata to provide some functionality. MBC3, for
 /// example, can use metadata to persist real-time clock state across multiple emulator
 /// runs. This trait provides access to load and store such metadata, if present.
 ///
+/// RTC metadata contains the clock registers (including the latched ones) together with
+/// the wall-clock time at which they were stored. When it is loaded again, the time that
+/// passed in between is added to the clock, so in-game time keeps running while the
+/// emulator is closed.
+///
 /// This trait is similar to ['Savegame'], which contains some useful further information.
 pub trait Metadata {
     fn supports_metadata(&self) -> bool {
@@ -151,6 +280,18 @@ impl<C: Savegame> Savegame for &mut C {
     fn savegame_mut(&mut self) -> Option<&mut [u8]> {
         C::savegame_mut(self)
     }
+
+    fn savegame_dirty(&self) -> bool {
+        C::savegame_dirty(self)
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        C::mark_savegame_clean(self)
+    }
+
+    fn savegame_ram_enabled(&self) -> bool {
+        C::savegame_ram_enabled(self)
+    }
 }
 
 impl<C: Metadata> Metadata for &mut C {
@@ -168,8 +309,6 @@ impl<C: Metadata> Metadata for &mut C {
 }
 
 impl<C: Cartridge> Cartridge for &mut C {
-    type MBC = C::MBC;
-
     fn read_rom(&self, addr: CRomAddr) -> u8 {
         C::read_rom(self, addr)
     }
@@ -185,4 +324,32 @@ impl<C: Cartridge> Cartridge for &mut C {
     fn write_cram(&mut self, addr: CRamAddr, val: u8) {
         C::write_cram(self, addr, val)
     }
+
+    fn reset(&mut self) {
+        C::reset(self)
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        C::save_state(self, writer)
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        C::load_state(self, reader)
+    }
+
+    fn bank_state(&self) -> BankState {
+        C::bank_state(self)
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        C::force_rom_bank(self, bank)
+    }
+
+    fn poll_rumble(&mut self) -> bool {
+        C::poll_rumble(self)
+    }
+
+    fn rom(&self) -> &[u8] {
+        C::rom(self)
+    }
 }
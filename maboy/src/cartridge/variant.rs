@@ -3,8 +3,11 @@
 use super::cram::*;
 use super::desc::*;
 use super::mbc::*;
-use super::CartridgeImpl;
-use std::{fs, path::Path};
+use super::{BankState, Cartridge, CartridgeImpl, Metadata, Savegame};
+use crate::address::{CRamAddr, CRomAddr};
+use crate::rom_loader::{self, RomArchiveError};
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use std::{fs, io::Read, path::Path};
 
 /// For maximum speed, we want to avoid dynamic dispatch for everything that is called
 /// in a hot loop. Cartridge memory access is both very hot and very loopy. This enum
@@ -13,6 +16,12 @@ use std::{fs, path::Path};
 /// all variants implement the [`super::Cartridge`] trait, the emulation loop typically
 /// only needs to be written once.
 ///
+/// If binary size or simplicity matter more than the last bit of speed (e.g. when
+/// embedding the emulator), the dispatch can be skipped: [`CartridgeVariant`] implements
+/// [`super::Cartridge`] (as well as [`super::Savegame`] and [`super::Metadata`]) itself
+/// by matching on the variant in every call, so `Emulator::new(cartridge)` only needs to
+/// be instantiated once.
+///
 /// # Example
 /// ```
 /// // Boilerplate to dispatch all variants
@@ -47,12 +56,25 @@ pub enum CartridgeVariant {
     MBC3RamBanked(CartridgeImpl<MBC3<CRamBanked>>),
     MBC3RamRtc(CartridgeImpl<MBC3Rtc<CRamUnbanked>>),
     MBC3RamBankedRtc(CartridgeImpl<MBC3Rtc<CRamBanked>>),
+
+    MBC5(CartridgeImpl<MBC5<NoCRam>>),
+    MBC5Ram(CartridgeImpl<MBC5<CRamUnbanked>>),
+    MBC5RamBanked(CartridgeImpl<MBC5<CRamBanked>>),
+
+    HuC1Ram(CartridgeImpl<HuC1<CRamUnbanked>>),
+    HuC1RamBanked(CartridgeImpl<HuC1<CRamBanked>>),
+
+    HuC3Ram(CartridgeImpl<HuC3<CRamUnbanked>>),
+    HuC3RamBanked(CartridgeImpl<HuC3<CRamBanked>>),
 }
 
 #[derive(Debug)]
 pub enum CartridgeParseError {
     IoError(std::io::Error),
 
+    /// The ROM file is an archive that could not be unpacked, see [`rom_loader`]
+    Archive(RomArchiveError),
+
     // Invalid/Missing cartridge data
     /// Size is not a multiple of 0x4000
     InvalidRomSize,
@@ -85,12 +107,36 @@ pub enum CartridgeParseError {
 }
 
 impl CartridgeVariant {
-    /// Attempts to parse a cartridge from a ROM file on disk
+    /// Attempts to parse a cartridge from a ROM file on disk. Zipped or gzipped ROMs are
+    /// unpacked first (see [`rom_loader`]).
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<CartridgeVariant, CartridgeParseError> {
-        let rom = fs::read(&path)
-            .map_err(|io_err| CartridgeParseError::IoError(io_err))?
-            .into_boxed_slice();
+        let data = fs::read(&path).map_err(|io_err| CartridgeParseError::IoError(io_err))?;
+        let rom = rom_loader::unpack(data).map_err(CartridgeParseError::Archive)?;
+
+        CartridgeVariant::from_bytes(rom)
+    }
+
+    /// Reads the whole ROM image from `reader` (e.g. a network stream or an entry of an
+    /// archive) and parses it like [`CartridgeVariant::from_rom`]
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<CartridgeVariant, CartridgeParseError> {
+        let mut rom = Vec::new();
+        reader
+            .read_to_end(&mut rom)
+            .map_err(CartridgeParseError::IoError)?;
+
+        CartridgeVariant::from_bytes(rom)
+    }
 
+    /// Like [`CartridgeVariant::from_rom`], for ROM images in a `Vec` (which is what
+    /// most ways of reading them end up with)
+    pub fn from_bytes(rom: Vec<u8>) -> Result<CartridgeVariant, CartridgeParseError> {
+        CartridgeVariant::from_rom(rom.into_boxed_slice())
+    }
+
+    /// Parses a cartridge from a ROM image that is already in memory, e.g. when there
+    /// is no file system to load it from. The header is validated the same way no
+    /// matter where the ROM comes from.
+    pub fn from_rom(rom: Box<[u8]>) -> Result<CartridgeVariant, CartridgeParseError> {
         // This condition sets up an important invariant that a lot of code relies upon,
         // for example the MBC code. Change it only if you are sure about what you're doing.
         if rom.len() < 0x8000 || rom.len() % 0x4000 != 0 {
@@ -136,17 +182,24 @@ impl CartridgeVariant {
             },
 
             // MBC1
-            CT::MBC1 | CT::MBC1_RAM | CT::MBC1_RAM_BATTERY => match ram_size {
-                RamSize::RamNone => CV::MBC1(C::new(MBC1::new(rom, NoCRam))),
-                RamSize::Ram2Kb | RamSize::Ram8Kb => CV::MBC1Ram(C::new(MBC1::new(
-                    rom,
-                    URam::new(ram_size, ctype.has_battery()),
-                ))),
+            CT::MBC1 | CT::MBC1_RAM | CT::MBC1_RAM_BATTERY => {
+                let wiring = MBC1Wiring::detect(&rom);
 
-                RamSize::Ram32Kb => {
-                    CV::MBC1RamBanked(C::new(MBC1::new(rom, BRam::new(ctype.has_battery()))))
+                match ram_size {
+                    RamSize::RamNone => CV::MBC1(C::new(MBC1::new(rom, NoCRam, wiring))),
+                    RamSize::Ram2Kb | RamSize::Ram8Kb => CV::MBC1Ram(C::new(MBC1::new(
+                        rom,
+                        URam::new(ram_size, ctype.has_battery()),
+                        wiring,
+                    ))),
+
+                    RamSize::Ram32Kb => CV::MBC1RamBanked(C::new(MBC1::new(
+                        rom,
+                        BRam::new(ctype.has_battery()),
+                        wiring,
+                    ))),
                 }
-            },
+            }
 
             // MBC2
             CT::MBC2 | CT::MBC2_BATTERY => CV::MBC2(C::new(MBC2::new(rom, ctype.has_battery()))),
@@ -173,8 +226,167 @@ impl CartridgeVariant {
                 }
             },
 
+            // MBC5
+            CT::MBC5
+            | CT::MBC5_RAM
+            | CT::MBC5_RAM_BATTERY
+            | CT::MBC5_RUMBLE
+            | CT::MBC5_RUMBLE_RAM
+            | CT::MBC5_RUMBLE_RAM_BATTERY => {
+                let has_rumble = matches!(
+                    ctype,
+                    CT::MBC5_RUMBLE | CT::MBC5_RUMBLE_RAM | CT::MBC5_RUMBLE_RAM_BATTERY
+                );
+
+                match ram_size {
+                    RamSize::RamNone => CV::MBC5(C::new(MBC5::new(rom, NoCRam, has_rumble))),
+                    RamSize::Ram2Kb | RamSize::Ram8Kb => CV::MBC5Ram(C::new(MBC5::new(
+                        rom,
+                        URam::new(ram_size, ctype.has_battery()),
+                        has_rumble,
+                    ))),
+                    RamSize::Ram32Kb => CV::MBC5RamBanked(C::new(MBC5::new(
+                        rom,
+                        BRam::new(ctype.has_battery()),
+                        has_rumble,
+                    ))),
+                }
+            }
+
+            // Hudson. Both always come with RAM.
+            CT::HuC1_RAM_BATTERY => match ram_size {
+                RamSize::RamNone => return err_unsupported,
+                RamSize::Ram2Kb | RamSize::Ram8Kb => CV::HuC1Ram(C::new(HuC1::new(
+                    rom,
+                    URam::new(ram_size, ctype.has_battery()),
+                ))),
+                RamSize::Ram32Kb => {
+                    CV::HuC1RamBanked(C::new(HuC1::new(rom, BRam::new(ctype.has_battery()))))
+                }
+            },
+            CT::HuC3 => match ram_size {
+                RamSize::RamNone => return err_unsupported,
+                RamSize::Ram2Kb | RamSize::Ram8Kb => CV::HuC3Ram(C::new(HuC3::new(
+                    rom,
+                    URam::new(ram_size, ctype.has_battery()),
+                ))),
+                RamSize::Ram32Kb => {
+                    CV::HuC3RamBanked(C::new(HuC3::new(rom, BRam::new(ctype.has_battery()))))
+                }
+            },
+
             // Anything else is not supported (yet)
             _ => return err_unsupported,
         })
     }
 }
+
+/// Forwards a call to the cartridge inside of any variant
+macro_rules! dispatch {
+    ($variant:expr, $c:ident => $body:expr) => {
+        match $variant {
+            CartridgeVariant::Rom($c) => $body,
+            CartridgeVariant::RomRam($c) => $body,
+            CartridgeVariant::RomRamBanked($c) => $body,
+            CartridgeVariant::MBC1($c) => $body,
+            CartridgeVariant::MBC1Ram($c) => $body,
+            CartridgeVariant::MBC1RamBanked($c) => $body,
+            CartridgeVariant::MBC2($c) => $body,
+            CartridgeVariant::MBC3($c) => $body,
+            CartridgeVariant::MBC3Rtc($c) => $body,
+            CartridgeVariant::MBC3Ram($c) => $body,
+            CartridgeVariant::MBC3RamBanked($c) => $body,
+            CartridgeVariant::MBC3RamRtc($c) => $body,
+            CartridgeVariant::MBC3RamBankedRtc($c) => $body,
+            CartridgeVariant::MBC5($c) => $body,
+            CartridgeVariant::MBC5Ram($c) => $body,
+            CartridgeVariant::MBC5RamBanked($c) => $body,
+            CartridgeVariant::HuC1Ram($c) => $body,
+            CartridgeVariant::HuC1RamBanked($c) => $body,
+            CartridgeVariant::HuC3Ram($c) => $body,
+            CartridgeVariant::HuC3RamBanked($c) => $body,
+        }
+    };
+}
+
+impl Cartridge for CartridgeVariant {
+    fn read_rom(&self, addr: CRomAddr) -> u8 {
+        dispatch!(self, c => c.read_rom(addr))
+    }
+
+    fn write_rom(&mut self, addr: CRomAddr, val: u8) {
+        dispatch!(self, c => c.write_rom(addr, val))
+    }
+
+    fn read_cram(&self, addr: CRamAddr) -> u8 {
+        dispatch!(self, c => c.read_cram(addr))
+    }
+
+    fn write_cram(&mut self, addr: CRamAddr, val: u8) {
+        dispatch!(self, c => c.write_cram(addr, val))
+    }
+
+    fn reset(&mut self) {
+        dispatch!(self, c => c.reset())
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        dispatch!(self, c => c.save_state(writer))
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        dispatch!(self, c => c.load_state(reader))
+    }
+
+    fn bank_state(&self) -> BankState {
+        dispatch!(self, c => c.bank_state())
+    }
+
+    fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        dispatch!(self, c => c.force_rom_bank(bank))
+    }
+
+    fn poll_rumble(&mut self) -> bool {
+        dispatch!(self, c => c.poll_rumble())
+    }
+
+    fn rom(&self) -> &[u8] {
+        dispatch!(self, c => c.rom())
+    }
+}
+
+impl Savegame for CartridgeVariant {
+    fn savegame(&self) -> Option<&[u8]> {
+        dispatch!(self, c => c.savegame())
+    }
+
+    fn savegame_mut(&mut self) -> Option<&mut [u8]> {
+        dispatch!(self, c => c.savegame_mut())
+    }
+
+    fn savegame_dirty(&self) -> bool {
+        dispatch!(self, c => c.savegame_dirty())
+    }
+
+    fn mark_savegame_clean(&mut self) {
+        dispatch!(self, c => c.mark_savegame_clean())
+    }
+
+    fn savegame_ram_enabled(&self) -> bool {
+        dispatch!(self, c => c.savegame_ram_enabled())
+    }
+}
+
+impl Metadata for CartridgeVariant {
+    fn supports_metadata(&self) -> bool {
+        dispatch!(self, c => c.supports_metadata())
+    }
+
+    fn serialize_metadata(&self) -> Result<Vec<u8>, CartridgeParseError> {
+        dispatch!(self, c => c.serialize_metadata())
+    }
+
+    fn deserialize_metadata(&mut self, data: Vec<u8>) -> Result<(), CartridgeParseError> {
+        dispatch!(self, c => c.deserialize_metadata(data))
+    }
+}
@@ -0,0 +1,228 @@
+//! Cheat codes, the way the GameShark and the Game Genie did it:
+//!
+//! - **GameShark** codes (`01FF42C1`) overwrite a byte of RAM. The GameShark did that
+//!   during every VBlank, so we do it once per frame (see
+//!   [`crate::Emulator::query_video_frame_status`]). The format is `ttvvaaaa`, with
+//!   the type `tt` (ignored), the value `vv` and the address `aaaa` in little-endian.
+//!   Writes to cartridge RAM go to the bank that is currently mapped.
+//! - **Game Genie** codes (`00A-17B` or `00A-17B-C49`) replace a byte of ROM whenever
+//!   it is read. The optional third part is a compare byte: The byte is only replaced
+//!   if the original byte matches, which is how codes target a single ROM bank.
+//!
+//! Cheats are managed via [`crate::Emulator::add_cheat`] and its siblings. They are
+//! neither part of savestates nor affected by resets.
+
+use crate::address::{Addr, CRomAddr, MemAddr};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CheatCode {
+    /// Writes `val` to `addr` once per frame
+    GameShark { addr: u16, val: u8 },
+    /// Replaces the ROM byte at `addr` with `val`, if it equals `compare` (or always,
+    /// if there is no compare byte)
+    GameGenie {
+        addr: u16,
+        val: u8,
+        compare: Option<u8>,
+    },
+}
+
+#[derive(Debug)]
+pub enum ParseCheatError {
+    /// The code is neither in GameShark nor in Game Genie format
+    InvalidFormat(String),
+    /// The code targets memory it can't patch (e.g. a GameShark code writing to ROM)
+    InvalidAddress(u16),
+}
+
+/// Accepts both formats. Whitespace around the code is ignored, and so is case.
+impl FromStr for CheatCode {
+    type Err = ParseCheatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim();
+
+        let digits = code
+            .chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| ParseCheatError::InvalidFormat(s.to_owned()))?;
+
+        let dashes: Vec<usize> = code.match_indices('-').map(|(idx, _)| idx).collect();
+
+        match (digits.len(), &dashes[..]) {
+            (8, []) => parse_gameshark(&digits),
+            (6, [3]) | (9, [3, 7]) => parse_game_genie(&digits),
+            _ => Err(ParseCheatError::InvalidFormat(s.to_owned())),
+        }
+    }
+}
+
+fn parse_gameshark(digits: &[u8]) -> Result<CheatCode, ParseCheatError> {
+    let byte = |idx: usize| digits[idx] << 4 | digits[idx + 1];
+
+    let val = byte(2);
+    let addr = u16::from_le_bytes([byte(4), byte(6)]);
+
+    match Addr::from(addr) {
+        Addr::Mem(MemAddr::CROM(_)) => Err(ParseCheatError::InvalidAddress(addr)),
+        Addr::Mem(_) => Ok(CheatCode::GameShark { addr, val }),
+        _ => Err(ParseCheatError::InvalidAddress(addr)),
+    }
+}
+
+/// Digits ABC-DEF-GHI: AB is the new value, FCDE the address with F xor'ed by 0xF, and
+/// GI (rotated right by 2 and xor'ed with 0xBA) the compare byte. H is unused.
+fn parse_game_genie(digits: &[u8]) -> Result<CheatCode, ParseCheatError> {
+    let val = digits[0] << 4 | digits[1];
+
+    let addr = ((digits[5] ^ 0xF) as u16) << 12
+        | (digits[2] as u16) << 8
+        | (digits[3] as u16) << 4
+        | digits[4] as u16;
+
+    if addr >= 0x8000 {
+        return Err(ParseCheatError::InvalidAddress(addr));
+    }
+
+    let compare = match digits.len() {
+        9 => Some((digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA),
+        _ => None,
+    };
+
+    Ok(CheatCode::GameGenie { addr, val, compare })
+}
+
+/// Handle of a cheat that was added to a [`CheatEngine`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CheatId(u32);
+
+#[derive(Debug, Clone)]
+pub struct Cheat {
+    pub id: CheatId,
+    /// The code as it was entered (or a description, for codes that weren't entered as
+    /// text, like the debugger's patches)
+    pub text: String,
+    pub code: CheatCode,
+    pub enabled: bool,
+}
+
+/// All cheats of an emulator. Lives in the memory module, which asks it to patch ROM
+/// reads and to apply RAM writes.
+#[derive(Default)]
+pub struct CheatEngine {
+    cheats: Vec<Cheat>,
+    next_id: u32,
+    /// Whether any enabled Game Genie code exists. Checked on every ROM read, so
+    /// reads stay fast without cheats.
+    patches_rom: bool,
+}
+
+impl CheatEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a code (in either format) and adds it as an enabled cheat
+    pub fn add(&mut self, text: &str) -> Result<CheatId, ParseCheatError> {
+        let code = text.parse()?;
+        Ok(self.add_code(text.trim().to_owned(), code))
+    }
+
+    /// Adds an already parsed code as an enabled cheat
+    pub fn add_code(&mut self, text: String, code: CheatCode) -> CheatId {
+        let id = CheatId(self.next_id);
+        self.next_id += 1;
+
+        self.cheats.push(Cheat {
+            id,
+            text,
+            code,
+            enabled: true,
+        });
+
+        self.update_patches_rom();
+        id
+    }
+
+    /// Returns false if there is no cheat with that id
+    pub fn set_enabled(&mut self, id: CheatId, enabled: bool) -> bool {
+        let found = match self.cheats.iter_mut().find(|cheat| cheat.id == id) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        };
+
+        self.update_patches_rom();
+        found
+    }
+
+    /// Returns the removed cheat, if there was one with that id
+    pub fn remove(&mut self, id: CheatId) -> Option<Cheat> {
+        let idx = self.cheats.iter().position(|cheat| cheat.id == id)?;
+        let cheat = self.cheats.remove(idx);
+
+        self.update_patches_rom();
+        Some(cheat)
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.patches_rom = false;
+    }
+
+    /// All cheats, in the order they were added
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /// The byte the CPU sees when reading `val` from ROM address `addr`
+    #[inline]
+    pub fn patch_rom(&self, addr: CRomAddr, val: u8) -> u8 {
+        if !self.patches_rom {
+            return val;
+        }
+
+        let addr = Addr::Mem(MemAddr::CROM(addr)).global();
+
+        self.enabled_codes()
+            .find_map(|code| match code {
+                CheatCode::GameGenie {
+                    addr: patch_addr,
+                    val: patch_val,
+                    compare,
+                } if patch_addr == addr && compare.is_none_or(|compare| compare == val) => {
+                    Some(patch_val)
+                }
+                _ => None,
+            })
+            .unwrap_or(val)
+    }
+
+    /// Address and value of all enabled RAM writes
+    pub fn ram_writes(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.enabled_codes().filter_map(|code| match code {
+            CheatCode::GameShark { addr, val } => Some((addr, val)),
+            CheatCode::GameGenie { .. } => None,
+        })
+    }
+
+    fn enabled_codes(&self) -> impl Iterator<Item = CheatCode> + '_ {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .map(|cheat| cheat.code)
+    }
+
+    fn update_patches_rom(&mut self) {
+        let patches_rom = self
+            .enabled_codes()
+            .any(|code| matches!(code, CheatCode::GameGenie { .. }));
+
+        self.patches_rom = patches_rom;
+    }
+}
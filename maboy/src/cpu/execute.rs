@@ -6,6 +6,7 @@ use super::operands::{Dst8, Src8};
 use super::registers::*;
 use super::CPU;
 use crate::board::Board;
+use crate::ppu::OamBugAccess;
 use crate::{debug::CpuEvt, util::BitOps};
 
 pub fn ld8<B: Board, D: Dst8, S: Src8>(cpu: &mut CPU, board: &mut B, dst: D, src: S) {
@@ -142,19 +143,40 @@ pub fn jp_hl<B: Board>(cpu: &mut CPU, board: &mut B) {
 }
 
 pub fn pop<B: Board>(cpu: &mut CPU, board: &mut B, rr: R16) {
-    cpu.reg.set_r16(rr, board.read16(cpu.reg.sp));
-    cpu.reg.sp = cpu.reg.sp.wrapping_add(2);
+    let val = pop_u16(cpu, board);
+    cpu.reg.set_r16(rr, val);
 }
 
 pub fn pop_af<B: Board>(cpu: &mut CPU, board: &mut B) {
-    cpu.reg.set_r16(R16::AF, board.read16(cpu.reg.sp));
-    cpu.reg.sp = cpu.reg.sp.wrapping_add(2);
+    let val = pop_u16(cpu, board);
+    cpu.reg.set_r16(R16::AF, val);
+}
+
+/// Each byte is read while SP is increased, which can trigger the OAM bug
+fn pop_u16<B: Board>(cpu: &mut CPU, board: &mut B) -> u16 {
+    let mut bytes = [0; 2];
+
+    for byte in bytes.iter_mut() {
+        *byte = board.read8(cpu.reg.sp);
+        board.notify_oam_bug(cpu.reg.sp, OamBugAccess::ReadIncDec);
+        cpu.reg.sp = cpu.reg.sp.wrapping_add(1);
+    }
+
+    u16::from_le_bytes(bytes)
 }
 
 pub fn push<B: Board>(cpu: &mut CPU, board: &mut B, rr: R16) {
-    cpu.reg.sp = cpu.reg.sp.wrapping_sub(2);
+    // The first decrease of SP happens in a cycle of its own, which can trigger the OAM
+    // bug just like the writes
     board.advance_mcycle();
-    board.write16(cpu.reg.sp, cpu.reg.get_r16(rr));
+    board.notify_oam_bug(cpu.reg.sp, OamBugAccess::Write);
+    cpu.reg.sp = cpu.reg.sp.wrapping_sub(2);
+
+    let [lo, hi] = cpu.reg.get_r16(rr).to_le_bytes();
+    board.write8(cpu.reg.sp, lo);
+    board.notify_oam_bug(cpu.reg.sp, OamBugAccess::Write);
+    board.write8(cpu.reg.sp.wrapping_add(1), hi);
+    board.notify_oam_bug(cpu.reg.sp.wrapping_add(1), OamBugAccess::Write);
 }
 
 pub fn rst<B: Board>(cpu: &mut CPU, board: &mut B, target: u16) {
@@ -238,13 +260,17 @@ pub fn add_sp_r8<B: Board>(cpu: &mut CPU, board: &mut B) {
 }
 
 pub fn inc_rr<B: Board>(cpu: &mut CPU, board: &mut B, rr: R16) {
-    cpu.reg.set_r16(rr, cpu.reg.get_r16(rr).wrapping_add(1));
+    let old = cpu.reg.get_r16(rr);
+    cpu.reg.set_r16(rr, old.wrapping_add(1));
     board.advance_mcycle();
+    board.notify_oam_bug(old, OamBugAccess::Write);
 }
 
 pub fn dec_rr<B: Board>(cpu: &mut CPU, board: &mut B, rr: R16) {
-    cpu.reg.set_r16(rr, cpu.reg.get_r16(rr).wrapping_sub(1));
+    let old = cpu.reg.get_r16(rr);
+    cpu.reg.set_r16(rr, old.wrapping_sub(1));
     board.advance_mcycle();
+    board.notify_oam_bug(old, OamBugAccess::Write);
 }
 
 pub fn inc8<B: Board, T: Src8 + Dst8 + Copy>(cpu: &mut CPU, board: &mut B, target: T) {
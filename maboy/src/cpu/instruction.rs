@@ -1,7 +1,32 @@
 // TODO: Number "NOT_USED" instructions correctly (starting at 0)... I'm an idiot
+
+/// Declares an instruction set enum together with a lookup table that decodes opcodes
+/// without `unsafe`. The table has to have exactly 256 entries, so the compiler rejects
+/// an instruction set with missing (or superfluous) variants.
+macro_rules! instruction_set {
+    ($(#[$attr:meta])* pub enum $name:ident { $($variant:ident,)* }) => {
+        $(#[$attr])*
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            /// Every instruction, indexed by its opcode
+            const DECODE_TABLE: [$name; 256] = [$($name::$variant,)*];
+
+            /// Decodes an opcode. Every byte is a valid opcode.
+            #[inline]
+            pub fn decode(opcode: u8) -> $name {
+                Self::DECODE_TABLE[opcode as usize]
+            }
+        }
+    };
+}
+
+instruction_set! {
 /// Every instruction supported (or unsupported) by the Game Boy CPU. Note that
 /// this enum is `#[repr(u8)]` with a direct mapping of the instruction byte-code
-/// to enum members.
+/// to enum members. Use [`ByteInstr::decode`] to get the instruction for a byte.
 #[allow(non_camel_case_types, dead_code)]
 #[derive(Debug, Copy, Clone)]
 #[repr(u8)]
@@ -263,7 +288,9 @@ pub enum ByteInstr {
     CP_d8,
     RST_38H,
 }
+}
 
+instruction_set! {
 /// Extended instruction set, which is considered when the PREFIX_CB instruction
 /// is encountered. Like [`ByteInstr`], this enum is `#[repr(u8)]` and directly
 /// maps any byte value to the corresponding instruction.
@@ -528,3 +555,50 @@ pub enum CBByteInstr {
     SET_7_xHLx,
     SET_7_A,
 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Opcodes whose instruction is known from the Pan Docs, as a sanity check that the
+    /// instruction sets are in the right order
+    const KNOWN_OPCODES: [(u8, &str); 6] = [
+        (0x00, "NOP"),
+        (0x40, "LD_B_B"),
+        (0x76, "HALT"),
+        (0xC3, "JP_a16"),
+        (0xCB, "PREFIX_CB"),
+        (0xFF, "RST_38H"),
+    ];
+
+    /// Same as [`KNOWN_OPCODES`], for the CB-prefixed instructions
+    const KNOWN_CB_OPCODES: [(u8, &str); 4] = [
+        (0x00, "RLC_B"),
+        (0x37, "SWAP_A"),
+        (0x7C, "BIT_7_H"),
+        (0xFF, "SET_7_A"),
+    ];
+
+    #[test]
+    fn every_opcode_decodes_to_itself() {
+        for opcode in 0..=u8::MAX {
+            let instr = ByteInstr::decode(opcode);
+            assert_eq!(instr as u8, opcode, "{:?}", instr);
+
+            let cb_instr = CBByteInstr::decode(opcode);
+            assert_eq!(cb_instr as u8, opcode, "CB {:?}", cb_instr);
+        }
+    }
+
+    #[test]
+    fn known_opcodes_decode_to_their_instruction() {
+        for &(opcode, expected) in KNOWN_OPCODES.iter() {
+            assert_eq!(format!("{:?}", ByteInstr::decode(opcode)), expected);
+        }
+
+        for &(opcode, expected) in KNOWN_CB_OPCODES.iter() {
+            assert_eq!(format!("{:?}", CBByteInstr::decode(opcode)), expected);
+        }
+    }
+}
@@ -4,6 +4,8 @@ mod operands;
 mod registers;
 
 use super::board::Board;
+use super::hardware::HardwareModel;
+use super::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use super::{debug::CpuEvt, interrupt_system::Interrupt};
 use execute::*;
 use operands::{HighRamOperand, HlOperand, Imm8, ImmAddr};
@@ -32,13 +34,32 @@ pub struct CPU {
     /// is false, but the jump to the interrupt routine is not performed.
     pub ime: bool,
 
+    /// Set by EI, which only enables IME after the instruction that follows it. This
+    /// is why `EI; DI` never lets an interrupt through.
+    pub ime_pending: bool,
+
     /// The special HALT and STOP instructions can suspend CPU operation
     /// until an interrupt occurs. They also have minor timing
     /// implications and provide opportunity for power saving.
     pub halt_state: HaltState,
+
+    /// The illegal instruction that got the CPU stuck, if [`HaltState::Stuck`]
+    pub illegal_instr: Option<IllegalInstr>,
+
+    /// Set when the halt bug was triggered (see [`CPU::halt`]). The next opcode fetch
+    /// will fail to increment PC.
+    pub halt_bug: bool,
+}
+
+/// An unused opcode that was executed by the CPU, which causes it to get stuck
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IllegalInstr {
+    /// Address of the instruction
+    pub pc: u16,
+    pub opcode: u8,
 }
 
-// TODO: Respect these states!
 #[derive(Debug, Copy, Clone)]
 pub enum HaltState {
     Running,
@@ -50,8 +71,9 @@ pub enum HaltState {
     Stopped,
 
     /// Reached after encountering one of the unused Instructions. There is
-    /// no way to recover from this state.
-    Stuck, // TODO: Think about return some kind of Err when this state is reached instead of panicking
+    /// no way to recover from this state (except for a reset). This is reported
+    /// to the frontend via [`crate::StepOutcome::Stuck`].
+    Stuck,
 }
 
 impl CPU {
@@ -59,10 +81,39 @@ impl CPU {
         CPU {
             reg: Registers::new(),
             ime: false,
+            ime_pending: false,
             halt_state: HaltState::Running,
+            illegal_instr: None,
+            halt_bug: false,
         }
     }
 
+    /// The state that the boot ROM of the given model leaves the CPU in, right before it
+    /// jumps to the cartridge at 0x100. Games mostly look at register A to detect the
+    /// model. The original Game Boy and the Pocket actually set H and C only if the
+    /// header checksum isn't 0, but every working cartridge has a checksum.
+    pub fn after_boot(model: HardwareModel) -> CPU {
+        let mut cpu = CPU::new();
+
+        let (a, flags, bc, de, hl) = match model {
+            HardwareModel::Dmg0 => (0x01, Flags::empty(), 0xFF13, 0x00C1, 0x8403),
+            HardwareModel::Dmg => (0x01, Flags::Z | Flags::H | Flags::C, 0x0013, 0x00D8, 0x014D),
+            HardwareModel::Mgb => (0xFF, Flags::Z | Flags::H | Flags::C, 0x0013, 0x00D8, 0x014D),
+            HardwareModel::Sgb => (0x01, Flags::empty(), 0x0014, 0x0000, 0xC060),
+            HardwareModel::Cgb => (0x11, Flags::Z, 0x0000, 0x0008, 0x007C),
+        };
+
+        cpu.reg.a = a;
+        cpu.reg.flags = flags;
+        cpu.reg.bc = bc;
+        cpu.reg.de = de;
+        cpu.reg.hl = hl;
+        cpu.reg.sp = 0xFFFE;
+        cpu.reg.pc = 0x0100;
+
+        cpu
+    }
+
     /// Steps forward one entire instruction (including the fetch operation in the beginning). If an
     /// interrupt is encountered, performs the jump to the interrupt handler, but doesn't execute the
     /// next instruction until the next call.
@@ -70,13 +121,26 @@ impl CPU {
         match self.halt_state {
             HaltState::Running => match board.ir_system().query_interrupt_request() {
                 Some(interrupt) if self.ime => self.jmp_to_interrupt_handler(board, interrupt),
-                _ => self.fetch_exec(board),
+                _ => {
+                    // Only an EI from an earlier instruction counts, not this one
+                    let enable_ime = self.ime_pending;
+
+                    self.fetch_exec(board);
+
+                    if enable_ime && self.ime_pending {
+                        self.ime_pending = false;
+                        self.set_ime(board, true);
+                    }
+                }
             },
             HaltState::Halted => {
                 if let Some(interrupt) = board.ir_system().query_interrupt_request() {
                     self.set_halt_state(board, HaltState::Running);
 
                     if self.ime {
+                        // Waking up from HALT takes an additional machine cycle before
+                        // the interrupt can be dispatched
+                        board.advance_mcycle();
                         self.jmp_to_interrupt_handler(board, interrupt);
                     } else {
                         self.fetch_exec(board);
@@ -85,12 +149,23 @@ impl CPU {
                     board.advance_mcycle();
                 }
             }
-            HaltState::Stopped => unimplemented!(),
-            HaltState::Stuck => unimplemented!(),
+            HaltState::Stopped => {
+                if board.joypad_input_low() {
+                    log::info!("Left STOP mode @ PC {:#06X}", self.reg.pc);
+                    self.set_halt_state(board, HaltState::Running);
+                }
+
+                board.advance_mcycle_stopped();
+            }
+            // The CPU doesn't do anything anymore, but the rest of the hardware keeps running
+            HaltState::Stuck => board.advance_mcycle(),
         }
     }
 
     fn fetch_exec<B: Board>(&mut self, board: &mut B) {
+        board.push_cpu_trace(&self.reg);
+        board.notify_instr_fetch(self.reg.pc);
+
         let instr = self.prefetch(board);
         board.push_cpu_evt(CpuEvt::Exec(self.reg.pc, instr));
         self.execute(board, instr);
@@ -98,27 +173,37 @@ impl CPU {
 
     /// Reads 8 bits of immediate data and increments PC. Consumes cycles.
     fn read8i<B: Board>(&mut self, board: &mut B) -> u8 {
-        let result = board.read8(self.reg.pc);
+        let result = board.fetch8(self.reg.pc);
         self.reg.pc = self.reg.pc.wrapping_add(1);
         result
     }
 
     /// Read 16 bits of immediate data (little endian) and increments PC twice. Consume cycles.
     fn read16i<B: Board>(&mut self, board: &mut B) -> u16 {
-        let result = board.read16(self.reg.pc);
+        let result = u16::from_le_bytes([
+            board.fetch8(self.reg.pc),
+            board.fetch8(self.reg.pc.wrapping_add(1)),
+        ]);
         self.reg.pc = self.reg.pc.wrapping_add(2);
         result
     }
 
     /// Jumps to an interrupt handler and clears the corresponding interrupt request bit
     fn jmp_to_interrupt_handler<B: Board>(&mut self, board: &mut B, interrupt: Interrupt) {
-        // TODO: Add additional 4 clock wait if waking from HALT (and STOP???)
         // TODO: Recheck the timing in this function
 
         board.push_cpu_evt(CpuEvt::HandleIR(interrupt));
+        board.notify_interrupt_dispatch(interrupt);
 
         self.set_ime(board, false);
 
+        // With EI right before HALT, the halt bug is triggered, but the interrupt is
+        // dispatched before the next opcode fetch. The handler returns to HALT.
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.reg.pc = self.reg.pc.wrapping_sub(1);
+        }
+
         // TODO: Make this stuff prettier... I mean we have IRSystem...
         // TODO: Move this code into IRSystem
         // Clear the interrupt request in the IF register
@@ -140,19 +225,79 @@ impl CPU {
         // 5th mcycle omitted, since it is spent during the next prefetch
     }
 
+    /// Executes the HALT instruction. If IME is disabled and an interrupt is already
+    /// pending, the CPU doesn't halt at all. Instead, the DMG fails to increment PC
+    /// after fetching the next opcode, so the byte after HALT is read twice (halt bug).
+    fn halt<B: Board>(&mut self, board: &mut B) {
+        if !self.ime && board.ir_system().query_interrupt_request().is_some() {
+            log::debug!("Halt bug triggered @ PC {:#06X}", self.reg.pc);
+            self.halt_bug = true;
+        } else {
+            self.set_halt_state(board, HaltState::Halted);
+        }
+    }
+
+    /// Executes the STOP instruction, which halts the system clock until one of the
+    /// selected joypad input lines goes low. STOP is usually two bytes long (the second
+    /// byte is skipped), but what exactly happens depends on whether a button is held
+    /// down and whether an interrupt is pending:
+    ///
+    /// | Button held | Interrupt pending | Length  | Result                  |
+    /// |-------------|-------------------|---------|-------------------------|
+    /// | yes         | yes               | 1 byte  | Nothing, keeps running  |
+    /// | yes         | no                | 2 bytes | HALT mode               |
+    /// | no          | yes               | 1 byte  | STOP mode, DIV reset    |
+    /// | no          | no                | 2 bytes | STOP mode, DIV reset    |
+    ///
+    /// On the CGB, STOP performs the speed switch instead if it was prepared via KEY1.
+    /// Once there is a CGB mode, that check belongs at the top of this function.
+    fn stop<B: Board>(&mut self, board: &mut B) {
+        let ir_pending = board.ir_system().query_interrupt_request().is_some();
+
+        if !ir_pending {
+            self.reg.pc = self.reg.pc.wrapping_add(1);
+        }
+
+        if board.joypad_input_low() {
+            if !ir_pending {
+                self.set_halt_state(board, HaltState::Halted);
+            }
+        } else {
+            board.enter_stop();
+            self.set_halt_state(board, HaltState::Stopped);
+        }
+    }
+
     fn set_halt_state<B: Board>(&mut self, board: &mut B, halt_state: HaltState) {
         board.push_cpu_evt(CpuEvt::EnterHalt(halt_state));
 
         self.halt_state = halt_state;
 
-        // TODO: Remove this once we implemented all HaltStates correctly
-        match halt_state {
-            HaltState::Halted => (),
-            HaltState::Running => (),
-            _ => unimplemented!("{:?} @ PC {:#06X}", halt_state, self.reg.pc),
+        if let HaltState::Stopped = halt_state {
+            log::info!("Entered STOP mode @ PC {:#06X}", self.reg.pc);
         }
     }
 
+    /// Called when executing one of the unused opcodes. The CPU stays stuck until
+    /// it is reset.
+    fn enter_stuck<B: Board>(&mut self, board: &mut B, instr: ByteInstr) {
+        let illegal_instr = IllegalInstr {
+            // PC already points past the instruction
+            pc: self.reg.pc.wrapping_sub(1),
+            opcode: instr as u8,
+        };
+
+        log::warn!(
+            "Executed illegal instruction {:#04X} @ PC {:#06X}, CPU is stuck",
+            illegal_instr.opcode,
+            illegal_instr.pc
+        );
+
+        board.push_cpu_evt(CpuEvt::IllegalInstr(illegal_instr));
+        self.illegal_instr = Some(illegal_instr);
+        self.set_halt_state(board, HaltState::Stuck);
+    }
+
     fn set_ime<B: Board>(&mut self, board: &mut B, ime: bool) {
         self.ime = ime;
 
@@ -164,13 +309,18 @@ impl CPU {
     }
 
     fn prefetch<B: Board>(&mut self, board: &mut B) -> ByteInstr {
-        // Safe since any u8 value is a valid enum variant
-        unsafe { std::mem::transmute(self.read8i(board)) }
+        let opcode = if self.halt_bug {
+            self.halt_bug = false;
+            board.fetch8(self.reg.pc)
+        } else {
+            self.read8i(board)
+        };
+
+        ByteInstr::decode(opcode)
     }
 
     fn fetch_cb<B: Board>(&mut self, board: &mut B) -> CBByteInstr {
-        // Safe since any u8 value is a valid enum variant
-        unsafe { std::mem::transmute(self.read8i(board)) }
+        CBByteInstr::decode(self.read8i(board))
     }
 
     fn execute<B: Board>(&mut self, board: &mut B, instr: ByteInstr) {
@@ -196,7 +346,7 @@ impl CPU {
             DEC_C => dec8(self, board, C),
             LD_C_d8 => ld8(self, board, C, Imm8),
             RRCA => rrca(self),
-            STOP => self.set_halt_state(board, HaltState::Stopped),
+            STOP => self.stop(board),
             LD_DE_d16 => ld_rr_d16(self, board, DE),
             LD_xDEx_A => ld8(self, board, DE, A),
             INC_DE => inc_rr(self, board, DE),
@@ -298,7 +448,7 @@ impl CPU {
             LD_xHLx_E => ld8(self, board, HL, E),
             LD_xHLx_H => ld8(self, board, HL, H),
             LD_xHLx_L => ld8(self, board, HL, L),
-            HALT => self.set_halt_state(board, HaltState::Halted),
+            HALT => self.halt(board),
             LD_xHLx_A => ld8(self, board, HL, A),
             LD_A_B => ld8(self, board, A, B),
             LD_A_C => ld8(self, board, A, C),
@@ -391,7 +541,7 @@ impl CPU {
             RET_NC => ret_cond(self, board, !self.reg.flags.contains(Flags::C)),
             POP_DE => pop(self, board, DE),
             JP_NC_a16 => jp_cond(self, board, !self.reg.flags.contains(Flags::C)),
-            NOT_USED => self.set_halt_state(board, HaltState::Stuck),
+            NOT_USED => self.enter_stuck(board, instr),
             CALL_NC_a16 => call_cond(self, board, !self.reg.flags.contains(Flags::C)),
             PUSH_DE => push(self, board, DE),
             SUB_d8 => sub8(self, board, Imm8),
@@ -399,41 +549,44 @@ impl CPU {
             RET_C => ret_cond(self, board, self.reg.flags.contains(Flags::C)),
             RETI => ret(self, board, true),
             JP_C_a16 => jp_cond(self, board, self.reg.flags.contains(Flags::C)),
-            NOT_USED_0 => self.set_halt_state(board, HaltState::Stuck),
+            NOT_USED_0 => self.enter_stuck(board, instr),
             CALL_C_a16 => call_cond(self, board, self.reg.flags.contains(Flags::C)),
-            NOT_USED_1 => self.set_halt_state(board, HaltState::Stuck),
+            NOT_USED_1 => self.enter_stuck(board, instr),
             SBC_A_d8 => sbc8(self, board, Imm8),
             RST_18H => rst(self, board, 0x18),
             LDH_xa8x_A => ld8(self, board, HighRamOperand::Imm8, A),
             POP_HL => pop(self, board, HL),
             LD_xCx_A => ld8(self, board, HighRamOperand::C, A),
-            NOT_USED_2 => self.set_halt_state(board, HaltState::Stuck),
-            NOT_USED_3 => self.set_halt_state(board, HaltState::Stuck),
+            NOT_USED_2 => self.enter_stuck(board, instr),
+            NOT_USED_3 => self.enter_stuck(board, instr),
             PUSH_HL => push(self, board, HL),
             AND_d8 => and8(self, board, Imm8),
             RST_20H => rst(self, board, 0x20),
             ADD_SP_r8 => add_sp_r8(self, board),
             JP_xHLx => jp_hl(self, board),
             LD_xa16x_A => ld8(self, board, ImmAddr, A),
-            NOT_USED_4 => self.set_halt_state(board, HaltState::Stuck),
-            NOT_USED_5 => self.set_halt_state(board, HaltState::Stuck),
-            NOT_USED_6 => self.set_halt_state(board, HaltState::Stuck),
+            NOT_USED_4 => self.enter_stuck(board, instr),
+            NOT_USED_5 => self.enter_stuck(board, instr),
+            NOT_USED_6 => self.enter_stuck(board, instr),
             XOR_d8 => xor8(self, board, Imm8),
             RST_28H => rst(self, board, 0x28),
             LDH_A_xa8x => ld8(self, board, A, HighRamOperand::Imm8),
             POP_AF => pop_af(self, board),
             LD_A_xCx => ld8(self, board, A, HighRamOperand::C),
-            DI => self.set_ime(board, false),
-            NOT_USED_7 => self.set_halt_state(board, HaltState::Stuck),
+            DI => {
+                self.ime_pending = false;
+                self.set_ime(board, false);
+            }
+            NOT_USED_7 => self.enter_stuck(board, instr),
             PUSH_AF => push(self, board, AF),
             OR_d8 => or8(self, board, Imm8),
             RST_30H => rst(self, board, 0x30),
             LD_HL_SPpr8 => ld_hl_sp_r8(self, board),
             LD_SP_HL => ld_sp_hl(self, board),
             LD_A_xa16x => ld8(self, board, A, ImmAddr),
-            EI => self.set_ime(board, true),
-            NOT_USED_8 => self.set_halt_state(board, HaltState::Stuck),
-            NOT_USED_9 => self.set_halt_state(board, HaltState::Stuck),
+            EI => self.ime_pending = true,
+            NOT_USED_8 => self.enter_stuck(board, instr),
+            NOT_USED_9 => self.enter_stuck(board, instr),
             CP_d8 => drop(cp8(self, board, Imm8)),
             RST_38H => rst(self, board, 0x38),
         }
@@ -707,3 +860,59 @@ impl CPU {
         }
     }
 }
+
+impl SaveState for CPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.reg.a);
+        writer.write_u8(self.reg.flags.bits());
+        writer.write_u16(self.reg.bc);
+        writer.write_u16(self.reg.de);
+        writer.write_u16(self.reg.hl);
+        writer.write_u16(self.reg.sp);
+        writer.write_u16(self.reg.pc);
+        writer.write_bool(self.ime);
+        writer.write_bool(self.ime_pending);
+        writer.write_bool(self.halt_bug);
+        writer.write_u8(match self.halt_state {
+            HaltState::Running => 0,
+            HaltState::Halted => 1,
+            HaltState::Stopped => 2,
+            HaltState::Stuck => 3,
+        });
+
+        if let Some(illegal_instr) = self.illegal_instr {
+            writer.write_u16(illegal_instr.pc);
+            writer.write_u8(illegal_instr.opcode);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.reg.a = reader.read_u8()?;
+        self.reg.flags = Flags::from_bits(reader.read_u8()?).ok_or(SaveStateError::InvalidValue)?;
+        self.reg.bc = reader.read_u16()?;
+        self.reg.de = reader.read_u16()?;
+        self.reg.hl = reader.read_u16()?;
+        self.reg.sp = reader.read_u16()?;
+        self.reg.pc = reader.read_u16()?;
+        self.ime = reader.read_bool()?;
+        self.ime_pending = reader.read_bool()?;
+        self.halt_bug = reader.read_bool()?;
+        self.halt_state = match reader.read_u8()? {
+            0 => HaltState::Running,
+            1 => HaltState::Halted,
+            2 => HaltState::Stopped,
+            3 => HaltState::Stuck,
+            _ => return Err(SaveStateError::InvalidValue),
+        };
+
+        self.illegal_instr = match self.halt_state {
+            HaltState::Stuck => Some(IllegalInstr {
+                pc: reader.read_u16()?,
+                opcode: reader.read_u8()?,
+            }),
+            _ => None,
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! A tiny assembler for single instructions, the counterpart of [`super::disasm`]. It
+//! understands everything the disassembler produces, so disassembled code can be edited
+//! and assembled again. The CPU debugger uses it for its `patch` command.
+//!
+//! Besides the syntax of the disassembler, a few common alternatives are accepted:
+//! square brackets instead of parentheses (`ld a, [hl]`), `(hli)` and `(hld)`, leaving
+//! out the `a` of 8-bit arithmetic (`sub b`), and numbers in decimal (`62`), hex
+//! (`$3E`, `0x3E`, `3Eh`) or binary (`%00111110`). Relative jumps take their target
+//! address, just like the disassembler shows them. `db` emits raw bytes.
+
+use super::disasm;
+use crate::cpu::{ByteInstr, CBByteInstr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    /// No instruction has this mnemonic and these operands
+    UnknownInstruction(String),
+    /// A number doesn't fit into its operand
+    InvalidOperand(String),
+    /// The target of a relative jump is too far away
+    JumpOutOfRange(u16),
+}
+
+/// Assembles a single instruction that will be located at `addr` (which is only needed
+/// for relative jumps), e.g. `assemble("ld a, $3E", 0x150) == Ok(vec![0x3E, 0x3E])`
+pub fn assemble(line: &str, addr: u16) -> Result<Vec<u8>, AsmError> {
+    let line = line
+        .trim()
+        .to_lowercase()
+        .replace('[', "(")
+        .replace(']', ")");
+
+    let (mnemonic, operands) = match line.find(char::is_whitespace) {
+        Some(idx) => (&line[..idx], split_operands(&line[idx..])),
+        None => (&line[..], Vec::new()),
+    };
+
+    if mnemonic == "db" {
+        return operands
+            .iter()
+            .map(|operand| immediate_u8(operand))
+            .collect::<Option<_>>()
+            .ok_or_else(|| AsmError::InvalidOperand(line.clone()));
+    }
+
+    let mut first_error = None;
+
+    for (opcode, template) in templates() {
+        let (template_mnemonic, template_operands) = template;
+
+        if template_mnemonic != mnemonic {
+            continue;
+        }
+
+        // `sub b` is the usual way to write it, but `sub a, b` is accepted too
+        let operands = match (&operands[..], template_operands.len()) {
+            ([a, rest @ ..], len) if a == "a" && rest.len() == len && is_alu(mnemonic) => rest,
+            _ => &operands[..],
+        };
+
+        match encode(&opcode, &template_operands, operands, addr) {
+            Some(Ok(bytes)) => return Ok(bytes),
+            Some(Err(err)) => {
+                first_error.get_or_insert(err);
+            }
+            None => (),
+        }
+    }
+
+    Err(first_error.unwrap_or(AsmError::UnknownInstruction(line)))
+}
+
+/// Mnemonic and operands of every instruction, together with its opcode (two bytes
+/// for CB instructions). Immediate operands are named like in [`ByteInstr`], in angle
+/// brackets (like `<d8>`).
+fn templates() -> impl Iterator<Item = (Vec<u8>, (String, Vec<String>))> {
+    let byte_instrs = (0..=u8::MAX)
+        .filter(|&opcode| {
+            let name = format!("{:?}", ByteInstr::decode(opcode));
+            !name.starts_with("NOT_USED") && name != "PREFIX_CB"
+        })
+        .map(|opcode| {
+            let name = format!("{:?}", ByteInstr::decode(opcode));
+            let text = disasm::assembly(&name, |operand| format!("<{}>", operand));
+
+            // STOP is two bytes long, see the disassembler
+            match opcode {
+                0x10 => (vec![0x10, 0x00], split_instr(&text)),
+                _ => (vec![opcode], split_instr(&text)),
+            }
+        });
+
+    let cb_instrs = (0..=u8::MAX).map(|opcode| {
+        let name = format!("{:?}", CBByteInstr::decode(opcode));
+        let text = disasm::assembly(&name, |_| {
+            unreachable!("CB instructions have no immediates")
+        });
+        (vec![0xCB, opcode], split_instr(&text))
+    });
+
+    byte_instrs.chain(cb_instrs)
+}
+
+fn split_instr(text: &str) -> (String, Vec<String>) {
+    match text.find(' ') {
+        Some(idx) => (text[..idx].to_owned(), split_operands(&text[idx..])),
+        None => (text.to_owned(), Vec::new()),
+    }
+}
+
+/// Operands without any whitespace, e.g. `sp + 5` becomes `sp+5`
+fn split_operands(operands: &str) -> Vec<String> {
+    operands
+        .split(',')
+        .map(
+            |operand| match operand.split_whitespace().collect::<String>() {
+                operand if operand == "(hli)" => "(hl+)".to_owned(),
+                operand if operand == "(hld)" => "(hl-)".to_owned(),
+                operand => operand,
+            },
+        )
+        .collect()
+}
+
+fn is_alu(mnemonic: &str) -> bool {
+    matches!(mnemonic, "sub" | "and" | "xor" | "or" | "cp")
+}
+
+/// Returns `None` if the operands don't fit the template, or an error if they fit, but
+/// a number is out of range
+fn encode(
+    opcode: &[u8],
+    template: &[String],
+    operands: &[String],
+    addr: u16,
+) -> Option<Result<Vec<u8>, AsmError>> {
+    if template.len() != operands.len() {
+        return None;
+    }
+
+    let mut bytes = opcode.to_vec();
+
+    for (expected, operand) in template.iter().zip(operands) {
+        let invalid = || AsmError::InvalidOperand(operand.clone());
+
+        match expected.as_str() {
+            "<d8>" => match parse_num(operand)? {
+                val @ -0x80..=0xFF => bytes.push(val as u8),
+                _ => return Some(Err(invalid())),
+            },
+            "<d16>" | "<a16>" => match parse_num(operand)? {
+                val @ -0x8000..=0xFFFF => bytes.extend_from_slice(&(val as u16).to_le_bytes()),
+                _ => return Some(Err(invalid())),
+            },
+            "<xa16x>" => match parse_num(indirect(operand)?)? {
+                val @ 0..=0xFFFF => bytes.extend_from_slice(&(val as u16).to_le_bytes()),
+                _ => return Some(Err(invalid())),
+            },
+            // `ldh` accepts both `($FF44)` and `($44)`
+            "<xa8x>" => match parse_num(indirect(operand)?)? {
+                val @ 0..=0xFF | val @ 0xFF00..=0xFFFF => bytes.push(val as u8),
+                _ => return Some(Err(invalid())),
+            },
+            "<SPpr8>" => {
+                let offset = operand.strip_prefix("sp")?;
+
+                if !offset.starts_with('+') && !offset.starts_with('-') {
+                    return None;
+                }
+
+                match parse_num(offset)? {
+                    val @ -0x80..=0x7F => bytes.push(val as u8),
+                    _ => return Some(Err(invalid())),
+                }
+            }
+            // Relative jumps have a target address, `add sp` has an offset
+            "<r8>" if opcode[0] == 0xE8 => match parse_num(operand)? {
+                val @ -0x80..=0x7F => bytes.push(val as u8),
+                _ => return Some(Err(invalid())),
+            },
+            "<r8>" => {
+                let target = match parse_num(operand)? {
+                    val @ 0..=0xFFFF => val as u16,
+                    _ => return Some(Err(invalid())),
+                };
+                let offset = target.wrapping_sub(addr.wrapping_add(2)) as i16;
+
+                if !(-0x80..=0x7F).contains(&offset) {
+                    return Some(Err(AsmError::JumpOutOfRange(target)));
+                }
+
+                bytes.push(offset as u8);
+            }
+            // Restart vectors are numbers, so `rst 38h` works as well as `rst $38`
+            vector if vector.starts_with('$') => {
+                if parse_num(vector) != parse_num(operand) {
+                    return None;
+                }
+            }
+            register => {
+                if register != operand {
+                    return None;
+                }
+            }
+        }
+    }
+
+    Some(Ok(bytes))
+}
+
+/// The inside of parentheses
+fn indirect(operand: &str) -> Option<&str> {
+    operand.strip_prefix('(')?.strip_suffix(')')
+}
+
+fn immediate_u8(operand: &str) -> Option<u8> {
+    match parse_num(operand)? {
+        val @ -0x80..=0xFF => Some(val as u8),
+        _ => None,
+    }
+}
+
+/// Parses a number in any of the formats listed in the module documentation, with an
+/// optional sign
+fn parse_num(s: &str) -> Option<i32> {
+    let (sign, s) = match s.as_bytes().first()? {
+        b'-' => (-1, &s[1..]),
+        b'+' => (1, &s[1..]),
+        _ => (1, s),
+    };
+
+    let val = if let Some(hex) = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")) {
+        i32::from_str_radix(hex, 16)
+    } else if let Some(hex) = s.strip_suffix('h') {
+        i32::from_str_radix(hex, 16)
+    } else if let Some(bin) = s.strip_prefix('%') {
+        i32::from_str_radix(bin, 2)
+    } else {
+        s.parse()
+    };
+
+    // Registers like `c` or `hl` aren't numbers, even though they look like hex
+    match val {
+        Ok(val) if s.bytes().next()?.is_ascii_digit() || s.starts_with(['$', '%']) => {
+            Some(sign * val)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,267 @@
+//! Conditions of breakpoints, like `a == $3E && b != 0`, which are evaluated against the
+//! CPU registers whenever the breakpoint is reached.
+//!
+//! Operands are registers (`a`, `b`, `c`, `d`, `e`, `h`, `l`, `f`, `af`, `bc`, `de`, `hl`,
+//! `sp`, `pc`), flags (`zf`, `nf`, `hf`, `cf`, which are 0 or 1) and numbers in decimal
+//! (`62`), hex (`$3E`, `0x3E`) or binary (`%00111110`). They are compared with `==`,
+//! `!=`, `<`, `<=`, `>` and `>=`. Comparisons are combined with `&&` and `||`, where `&&`
+//! binds stronger, and can be grouped with parentheses. Case doesn't matter.
+
+use crate::cpu::{Flags, Registers, R16, R8};
+use std::fmt;
+use std::iter::Peekable;
+use std::str::FromStr;
+
+/// A parsed breakpoint condition (see the [module documentation](self))
+#[derive(Clone)]
+pub struct Condition {
+    /// What the user typed, for display
+    text: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseConditionError {
+    /// Something that is neither a register, a number nor an operator
+    UnknownToken(String),
+    /// A token where it doesn't belong, like the second `==` in `a == == 1`
+    UnexpectedToken(String),
+    /// The condition ended where an operand or operator was expected
+    UnexpectedEnd,
+}
+
+#[derive(Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Cmp(Operand, CmpOp, Operand),
+}
+
+#[derive(Copy, Clone)]
+enum Operand {
+    R8(R8),
+    R16(R16),
+    /// The flags register
+    F,
+    Flag(Flags),
+    Const(u16),
+}
+
+#[derive(Copy, Clone)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Condition {
+    /// Whether the condition holds for these register values
+    pub fn eval(&self, reg: &Registers) -> bool {
+        self.expr.eval(reg)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ParseConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(&s.to_lowercase())?;
+        let mut tokens = tokens.iter().map(String::as_str).peekable();
+
+        let expr = parse_or(&mut tokens)?;
+
+        match tokens.next() {
+            Some(token) => Err(ParseConditionError::UnexpectedToken(token.to_owned())),
+            None => Ok(Condition {
+                text: s.trim().to_owned(),
+                expr,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ParseConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseConditionError::UnknownToken(token) => write!(f, "Unknown token '{}'", token),
+            ParseConditionError::UnexpectedToken(token) => {
+                write!(f, "Unexpected token '{}'", token)
+            }
+            ParseConditionError::UnexpectedEnd => f.write_str("Unexpected end of condition"),
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, reg: &Registers) -> bool {
+        match self {
+            Expr::Or(lhs, rhs) => lhs.eval(reg) || rhs.eval(reg),
+            Expr::And(lhs, rhs) => lhs.eval(reg) && rhs.eval(reg),
+            Expr::Cmp(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval(reg), rhs.eval(reg));
+
+                match op {
+                    CmpOp::Eq => lhs == rhs,
+                    CmpOp::Ne => lhs != rhs,
+                    CmpOp::Lt => lhs < rhs,
+                    CmpOp::Le => lhs <= rhs,
+                    CmpOp::Gt => lhs > rhs,
+                    CmpOp::Ge => lhs >= rhs,
+                }
+            }
+        }
+    }
+}
+
+impl Operand {
+    fn eval(self, reg: &Registers) -> u16 {
+        match self {
+            Operand::R8(r) => reg.get_r8(r) as u16,
+            Operand::R16(rr) => reg.get_r16(rr),
+            Operand::F => reg.flags.bits() as u16,
+            Operand::Flag(flag) => reg.flags.contains(flag) as u16,
+            Operand::Const(val) => val,
+        }
+    }
+
+    fn parse(token: &str) -> Result<Operand, ParseConditionError> {
+        let operand = match token {
+            "a" => Operand::R8(R8::A),
+            "b" => Operand::R8(R8::B),
+            "c" => Operand::R8(R8::C),
+            "d" => Operand::R8(R8::D),
+            "e" => Operand::R8(R8::E),
+            "h" => Operand::R8(R8::H),
+            "l" => Operand::R8(R8::L),
+            "f" => Operand::F,
+            "af" => Operand::R16(R16::AF),
+            "bc" => Operand::R16(R16::BC),
+            "de" => Operand::R16(R16::DE),
+            "hl" => Operand::R16(R16::HL),
+            "sp" => Operand::R16(R16::SP),
+            "pc" => Operand::R16(R16::PC),
+            "zf" => Operand::Flag(Flags::Z),
+            "nf" => Operand::Flag(Flags::N),
+            "hf" => Operand::Flag(Flags::H),
+            "cf" => Operand::Flag(Flags::C),
+            _ => Operand::Const(
+                parse_num(token)
+                    .ok_or_else(|| ParseConditionError::UnknownToken(token.to_owned()))?,
+            ),
+        };
+
+        Ok(operand)
+    }
+}
+
+impl CmpOp {
+    fn parse(token: &str) -> Option<CmpOp> {
+        match token {
+            "==" => Some(CmpOp::Eq),
+            "!=" => Some(CmpOp::Ne),
+            "<" => Some(CmpOp::Lt),
+            "<=" => Some(CmpOp::Le),
+            ">" => Some(CmpOp::Gt),
+            ">=" => Some(CmpOp::Ge),
+            _ => None,
+        }
+    }
+}
+
+/// Splits the condition into operands and operators, e.g. `a==$3E` into `a`, `==`, `$3E`
+fn tokenize(s: &str) -> Result<Vec<String>, ParseConditionError> {
+    const OPERATORS: [&str; 10] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "(", ")"];
+
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+
+    while !rest.is_empty() {
+        let len = match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            Some(op) => op.len(),
+            None => rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '$' || c == '%'))
+                .unwrap_or(rest.len()),
+        };
+
+        if len == 0 {
+            let unknown = rest.chars().next().unwrap_or_default();
+            return Err(ParseConditionError::UnknownToken(unknown.to_string()));
+        }
+
+        tokens.push(rest[..len].to_owned());
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+fn parse_or<'a, I: Iterator<Item = &'a str>>(
+    tokens: &mut Peekable<I>,
+) -> Result<Expr, ParseConditionError> {
+    let mut expr = parse_and(tokens)?;
+
+    while tokens.next_if_eq(&"||").is_some() {
+        expr = Expr::Or(Box::new(expr), Box::new(parse_and(tokens)?));
+    }
+
+    Ok(expr)
+}
+
+fn parse_and<'a, I: Iterator<Item = &'a str>>(
+    tokens: &mut Peekable<I>,
+) -> Result<Expr, ParseConditionError> {
+    let mut expr = parse_cmp(tokens)?;
+
+    while tokens.next_if_eq(&"&&").is_some() {
+        expr = Expr::And(Box::new(expr), Box::new(parse_cmp(tokens)?));
+    }
+
+    Ok(expr)
+}
+
+/// A comparison or a condition in parentheses
+fn parse_cmp<'a, I: Iterator<Item = &'a str>>(
+    tokens: &mut Peekable<I>,
+) -> Result<Expr, ParseConditionError> {
+    if tokens.next_if_eq(&"(").is_some() {
+        let expr = parse_or(tokens)?;
+
+        return match tokens.next() {
+            Some(")") => Ok(expr),
+            Some(token) => Err(ParseConditionError::UnexpectedToken(token.to_owned())),
+            None => Err(ParseConditionError::UnexpectedEnd),
+        };
+    }
+
+    let lhs = Operand::parse(tokens.next().ok_or(ParseConditionError::UnexpectedEnd)?)?;
+
+    let op = match tokens.next() {
+        Some(token) => CmpOp::parse(token)
+            .ok_or_else(|| ParseConditionError::UnexpectedToken(token.to_owned()))?,
+        None => return Err(ParseConditionError::UnexpectedEnd),
+    };
+
+    let rhs = Operand::parse(tokens.next().ok_or(ParseConditionError::UnexpectedEnd)?)?;
+
+    Ok(Expr::Cmp(lhs, op, rhs))
+}
+
+/// Parses a number in any of the formats listed in the module documentation
+fn parse_num(s: &str) -> Option<u16> {
+    if let Some(hex) = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = s.strip_prefix('%') {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        s.parse().ok()
+    }
+}
@@ -1,60 +1,162 @@
-use super::{fmt::FmtNum, CpuEvt, DbgEvtLogger, DbgEvtSrc, PpuEvt};
-use crate::cartridge::Cartridge;
+use super::asm;
+use super::condition::Condition;
+use super::disasm::{self, DecodedInstr};
+use super::{
+    describe_memory_map, fmt::FmtNum, memory_map_markdown, CpuEvt, DbgEvtLogger, DbgEvtSrc, PpuEvt,
+};
+use crate::cartridge::{BankState, Cartridge};
+use crate::frame_dump::{DumpFormat, FrameDump};
 use crate::{
-    address::{Addr, PpuReg},
+    address::{Addr, IOReg, MemAddr, PpuReg},
     board::Board,
-    cpu::{ByteInstr, Registers, CPU, R8},
+    cheats::CheatCode,
+    cpu::{ByteInstr, IllegalInstr, Registers, CPU, R8},
     ppu::{LCDC, LCDS, PPU},
-    Emulator,
+    DebugTrap, Emulator, Interrupt, MemPixel, TrapCause, ValueMatch, WatchKind, Watchpoint,
+    WatchpointSet,
 };
 use console::{style, StyledObject, Term};
+use std::convert::TryFrom;
 use std::fmt::Write;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
 
 // TODO: When printing upcoming instructions, keep in mind that
 // we cannot know those instructions if they live in IO registers
 // or MBC-switched areas
 
 pub struct CpuDebugger {
-    pub breakpoints: Vec<u16>,
-    pub mem_breakpoints: Vec<(u16, BreakCond)>,
+    pub breakpoints: Vec<Breakpoint>,
+    /// Break when the CPU executes an illegal instruction and gets stuck
+    pub break_on_illegal_instr: bool,
+    /// Whether we already broke for the current stuck state
+    stuck_reported: bool,
     break_in: Option<usize>,
+    /// Set by `step over` and `step out`, until the CPU gets there
+    step_target: Option<StepTarget>,
     output_buffer: String,
+    /// Started via the `dump` command and fed via [`CpuDebugger::notify_frame`]
+    frame_dump: Option<FrameDump>,
+    /// Set by the `frame` command, until the next frame is finished
+    break_on_frame: bool,
+    /// Set by [`CpuDebugger::notify_frame`] when we should break for the `frame` command
+    frame_finished: bool,
+    /// Whether we broke and are waiting for commands
+    paused: bool,
+    /// Commands typed into the console. Read on a separate thread (which is started at
+    /// the first break), so the emulation thread never blocks on the console.
+    commands: Option<Receiver<String>>,
+}
+
+/// Breaks when the CPU is about to execute the instruction at `addr` (or one that
+/// overlaps it)
+#[derive(Clone)]
+pub struct Breakpoint {
+    pub addr: u16,
+    /// Only break if this holds for the registers at that point
+    pub condition: Option<Condition>,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum BreakCond {
-    ReadWrite,
-    Read,
-    Write,
+/// Where `step over` and `step out` break. Both compare SP, so recursive calls and
+/// interrupts in between don't end the step too early.
+#[derive(Copy, Clone)]
+enum StepTarget {
+    /// Break when the CPU is back at `addr` (the instruction after a call), with the
+    /// return address popped off the stack again
+    Return { addr: u16, sp: u16 },
+    /// Break after a return pops the stack above `sp`. `ret_at` is set while the CPU is
+    /// at a return instruction, so we can tell whether it was taken in the next step.
+    Out { sp: u16, ret_at: Option<u16> },
+}
+
+/// What the frontend should do after [`CpuDebugger::poll`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebuggerStatus {
+    /// Go ahead and step the emulator
+    Running,
+    /// We are waiting for commands. Don't step the emulator, but keep the window alive
+    /// (and ideally show that the game is paused).
+    Paused,
 }
 
 enum BreakReason {
     UserRequest,
     BreakpointHit(u16),
-    CondBreakpointHit(u16, BreakCond),
+    /// Memory breakpoints are the emulator's watchpoints
+    WatchpointHit(DebugTrap),
+    IllegalInstr(IllegalInstr),
+    FrameFinished,
 }
 
 impl CpuDebugger {
     pub fn new() -> CpuDebugger {
         CpuDebugger {
             breakpoints: Vec::new(),
-            mem_breakpoints: Vec::new(),
+            break_on_illegal_instr: true,
+            stuck_reported: false,
             break_in: None,
+            step_target: None,
             output_buffer: String::new(),
+            frame_dump: None,
+            break_on_frame: false,
+            frame_finished: false,
+            paused: false,
+            commands: None,
         }
     }
 
-    /// Call this *before* calling Emulator::emulate_step()
-    pub fn try_run_blocking<C: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>>(
+    /// Call this *before* every call of [`Emulator::emulate_step`], and only step the
+    /// emulator if this returns [`DebuggerStatus::Running`]. Never blocks: While we are
+    /// paused, the commands typed into the console are picked up here, so the frontend
+    /// keeps running its loop (handling window events, presenting frames) in between.
+    pub fn poll<C: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>>(
+        &mut self,
+        emu: &mut Emulator<C, DbgEvtLogger<CpuEvt>, PpuDbg>,
+    ) -> DebuggerStatus {
+        let term = Term::stdout();
+
+        if !self.paused {
+            match self.break_reason(emu) {
+                Some(break_reason) => self.enter_break(emu, &term, break_reason),
+                None => return DebuggerStatus::Running,
+            }
+        }
+
+        loop {
+            let command = match self.commands.as_ref().map(Receiver::try_recv) {
+                Some(Ok(command)) => command,
+                Some(Err(TryRecvError::Empty)) => return DebuggerStatus::Paused,
+                // Without a console, nobody could ever resume emulation
+                Some(Err(TryRecvError::Disconnected)) | None => break,
+            };
+
+            if self.execute(emu, &term, &command) {
+                break;
+            }
+
+            print_prompt(&term);
+        }
+
+        self.paused = false;
+        term.clear_screen().unwrap();
+
+        DebuggerStatus::Running
+    }
+
+    /// Whether we are waiting for commands (see [`CpuDebugger::poll`])
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn enter_break<C: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>>(
         &mut self,
         emu: &Emulator<C, DbgEvtLogger<CpuEvt>, PpuDbg>,
+        term: &Term,
+        break_reason: BreakReason,
     ) {
-        if let Some(break_reason) = self.break_reason(emu) {
-            self.output_buffer.clear();
-            self.print_break_reason(break_reason);
-        } else {
-            return;
-        }
+        self.output_buffer.clear();
+        self.print_break_reason(break_reason);
 
         writeln!(self.output_buffer, "CPU").unwrap();
         self.print_cpu_state(&emu.cpu.reg);
@@ -62,43 +164,100 @@ impl CpuDebugger {
         writeln!(self.output_buffer, "\nPPU").unwrap();
         self.print_ppu_state(&emu.board.ppu);
 
+        writeln!(self.output_buffer, "\nCartridge").unwrap();
+        self.print_bank_state(emu.current_banks());
+
         writeln!(self.output_buffer, "\nMem").unwrap();
         self.print_preceding_instr(emu);
         self.print_upcoming_instr(&emu.cpu, &emu.board);
 
-        let term = Term::stdout();
         term.clear_screen().unwrap();
-
         term.write_line(&self.output_buffer).unwrap();
+        print_prompt(term);
 
-        loop {
-            term.write_str(&style("Enter command: ").yellow().to_string())
-                .unwrap();
-            let command = term.read_line().unwrap();
+        if self.commands.is_none() {
+            self.commands = Some(spawn_command_reader());
+        }
 
-            match &command[..] {
-                "run" => break,
-                _ if command.starts_with("step") => {
-                    if self.cmd_step(&term, command.split_ascii_whitespace().skip(1)) {
-                        break;
-                    }
-                }
-                _ if command.starts_with("bp") => {
-                    cmd_bp::execute(self, &term, command.split_ascii_whitespace().skip(1));
-                }
-                _ => term
-                    .write_line(&style("Unknown command\n").red().to_string())
-                    .unwrap(),
+        self.step_target = None;
+        self.paused = true;
+    }
+
+    /// Returns true if emulation should resume
+    fn execute<C: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>>(
+        &mut self,
+        emu: &mut Emulator<C, DbgEvtLogger<CpuEvt>, PpuDbg>,
+        term: &Term,
+        command: &str,
+    ) -> bool {
+        match command {
+            "run" => return true,
+            "frame" => {
+                self.break_on_frame = true;
+                return true;
+            }
+            _ if command.starts_with("step") => {
+                return self.cmd_step(emu, term, command.split_ascii_whitespace().skip(1));
+            }
+            _ if command.starts_with("bp") => {
+                cmd_bp::execute(
+                    self,
+                    emu.watchpoints_mut(),
+                    term,
+                    command.split_ascii_whitespace().skip(1),
+                );
+            }
+            _ if command.starts_with("dump") => {
+                self.cmd_dump(term, command.split_ascii_whitespace().skip(1));
+            }
+            _ if command.starts_with("heatmap") => {
+                cmd_heatmap(emu, term, command.split_ascii_whitespace().skip(1));
             }
+            _ if command.starts_with("profile") => {
+                cmd_profile(emu, term, command.split_ascii_whitespace().skip(1));
+            }
+            _ if command.starts_with("map") => {
+                cmd_map(term, command.split_ascii_whitespace().skip(1));
+            }
+            _ if command.starts_with("memset") => {
+                cmd_memset(emu, term, command.split_ascii_whitespace().skip(1));
+            }
+            _ if command.starts_with("mem") => {
+                cmd_mem(emu, term, command.split_ascii_whitespace().skip(1));
+            }
+            _ if command.starts_with("patch") => {
+                cmd_patch(emu, term, command["patch".len()..].trim());
+            }
+            _ => term
+                .write_line(&style("Unknown command\n").red().to_string())
+                .unwrap(),
         }
 
-        term.clear_screen().unwrap();
+        false
     }
 
     fn break_reason<CMem: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>>(
         &mut self,
         emu: &Emulator<CMem, DbgEvtLogger<CpuEvt>, PpuDbg>,
     ) -> Option<BreakReason> {
+        match emu.cpu.illegal_instr {
+            Some(illegal_instr) if !self.stuck_reported => {
+                self.stuck_reported = true;
+
+                if self.break_on_illegal_instr {
+                    return Some(BreakReason::IllegalInstr(illegal_instr));
+                }
+            }
+            Some(_) => (),
+            None => self.stuck_reported = false,
+        }
+
+        if self.frame_finished {
+            self.frame_finished = false;
+            self.break_in = None;
+            return Some(BreakReason::FrameFinished);
+        }
+
         if let Some(steps) = &mut self.break_in {
             if *steps == 0 {
                 self.break_in = None;
@@ -108,44 +267,74 @@ impl CpuDebugger {
             }
         }
 
-        let instr_start = emu.cpu.reg.pc;
-        // Safe transmute because every u8 represents a valid enum variant
-        let instr: ByteInstr =
-            unsafe { std::mem::transmute(emu.board.read8_instant(Addr::from(instr_start))) };
-        let instr_end =
-            instr_start.wrapping_add(instr.operand_type().map(|o| o.len()).unwrap_or(0) as u16);
+        let reg = &emu.cpu.reg;
+        let opcode = emu.board.read8_instant(Addr::from(reg.pc));
 
-        for bp in self.breakpoints.iter().copied() {
-            if bp >= instr_start && bp <= instr_end {
-                return Some(BreakReason::BreakpointHit(bp));
+        if let Some(target) = &mut self.step_target {
+            let reached = match target {
+                StepTarget::Return { addr, sp } => reg.pc == *addr && reg.sp >= *sp,
+                StepTarget::Out { sp, ret_at } => {
+                    // A conditional return that isn't taken continues right after it
+                    let returned = ret_at.is_some_and(|ret_pc| reg.pc != ret_pc.wrapping_add(1));
+                    *ret_at = Some(reg.pc).filter(|_| is_return(opcode));
+                    returned && reg.sp > *sp
+                }
+            };
+
+            if reached {
+                self.step_target = None;
+                return Some(BreakReason::UserRequest);
             }
         }
 
-        for (bp, cond) in self.mem_breakpoints.iter().copied() {
-            // Can't move this outside of the loop, or it will be consumed by the first breakpoint!
-            let mut latest_mem_acceses = emu
-                .board
-                .cpu_evt_src
-                .evts()
-                .rev()
-                .take_while(|evt| !matches!(evt, CpuEvt::Exec(_, _)));
+        let instr_start = reg.pc;
+        let instr_len = disasm::instr_len(opcode);
+        let instr_end = instr_start.wrapping_add(instr_len as u16 - 1);
 
-            if latest_mem_acceses.any(|evt| match evt {
-                CpuEvt::ReadMem(addr, _) => {
-                    bp == *addr && matches!(cond, BreakCond::Read | BreakCond::ReadWrite)
-                }
-                CpuEvt::WriteMem(addr, _) => {
-                    bp == *addr && matches!(cond, BreakCond::Write | BreakCond::ReadWrite)
-                }
-                _ => false,
-            }) {
-                return Some(BreakReason::CondBreakpointHit(bp, cond));
+        for bp in &self.breakpoints {
+            let in_instr = bp.addr >= instr_start && bp.addr <= instr_end;
+
+            if in_instr && bp.condition.as_ref().is_none_or(|c| c.eval(&emu.cpu.reg)) {
+                return Some(BreakReason::BreakpointHit(bp.addr));
             }
         }
 
+        // The trap is from the previous step, so the accessing instruction has already
+        // been executed
+        if let Some(trap) = emu.debug_trap() {
+            return Some(BreakReason::WatchpointHit(trap));
+        }
+
         None
     }
 
+    /// Call this for every frame the frontend displays, so frame dumps started via
+    /// the `dump` command receive their frames. Frames where the LCD is off are skipped.
+    ///
+    /// This is also what the `frame` command waits for, so frontends should call it as
+    /// soon as [`crate::VideoFrameStatus::Ready`] is reported, even if they display the
+    /// frame later.
+    pub fn notify_frame(&mut self, frame: &[MemPixel]) {
+        if self.break_on_frame {
+            self.break_on_frame = false;
+            self.frame_finished = true;
+        }
+
+        if let Some(dump) = &mut self.frame_dump {
+            match dump.push_frame(frame) {
+                Ok(false) => return,
+                Ok(true) => log::info!(
+                    "Dumped {} frames to {:?}",
+                    dump.frames_written(),
+                    dump.path()
+                ),
+                Err(err) => log::error!("Frame dump to {:?} failed: {}", dump.path(), err),
+            }
+
+            self.frame_dump = None;
+        }
+    }
+
     pub fn request_break(&mut self) {
         self.break_in(0);
     }
@@ -155,8 +344,31 @@ impl CpuDebugger {
     }
 
     /// Returns true if the command was succesful
-    fn cmd_step<'a, I: Iterator<Item = &'a str>>(&mut self, term: &Term, mut args: I) -> bool {
+    fn cmd_step<'a, C: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>, I: Iterator<Item = &'a str>>(
+        &mut self,
+        emu: &Emulator<C, DbgEvtLogger<CpuEvt>, PpuDbg>,
+        term: &Term,
+        mut args: I,
+    ) -> bool {
+        let reg = &emu.cpu.reg;
+
         match args.next() {
+            // Anything but a call is just a single step
+            Some("over") => match ByteInstr::decode(emu.peek(reg.pc)) {
+                instr if is_call(instr) => {
+                    self.step_target = Some(StepTarget::Return {
+                        addr: disasm::decode_at(|addr| emu.peek(addr), reg.pc).next_addr(),
+                        sp: reg.sp,
+                    })
+                }
+                _ => self.break_in(0),
+            },
+            Some("out") => {
+                self.step_target = Some(StepTarget::Out {
+                    sp: reg.sp,
+                    ret_at: Some(reg.pc).filter(|&pc| is_return(emu.peek(pc))),
+                })
+            }
             Some("line") => self.break_in(114 - 4),
             Some("frame") => self.break_in(17556 - 4),
             Some(steps_str) => match steps_str.parse::<usize>() {
@@ -177,6 +389,28 @@ impl CpuDebugger {
         true
     }
 
+    /// `dump <frames> <path> [npy|png]`: Dumps the next frames once emulation continues
+    fn cmd_dump<'a, I: Iterator<Item = &'a str>>(&mut self, term: &Term, mut args: I) {
+        let frames = args.next().and_then(|frames| frames.parse().ok());
+        let path = args.next();
+        let format = DumpFormat::from_name(args.next().unwrap_or("npy"));
+
+        let msg = match (frames, path, format) {
+            (Some(frames), Some(path), Some(format)) => {
+                match FrameDump::start(path, format, frames) {
+                    Ok(dump) => {
+                        self.frame_dump = Some(dump);
+                        style(format!("Dumping the next {} frames to {}", frames, path)).green()
+                    }
+                    Err(err) => style(format!("Could not start frame dump: {}", err)).red(),
+                }
+            }
+            _ => style("ERROR: Use 'dump <frames> <path> [npy|png]'".to_owned()).red(),
+        };
+
+        term.write_line(&msg.to_string()).unwrap();
+    }
+
     fn print_break_reason(&mut self, break_reason: BreakReason) {
         match break_reason {
             BreakReason::UserRequest => writeln!(
@@ -192,14 +426,43 @@ impl CpuDebugger {
                 addr.fmt_addr()
             )
             .unwrap(),
-            BreakReason::CondBreakpointHit(addr, cond) => writeln!(
+            BreakReason::WatchpointHit(DebugTrap {
+                pc,
+                cause: TrapCause::Access { addr, val, access },
+            }) => writeln!(
                 self.output_buffer,
-                "{} {} ({:?})\n",
+                "{} {} ({:?} of {}, PC {})\n",
                 style("Memory breakpoint hit at").red(),
                 addr.fmt_addr(),
-                cond
+                access,
+                val.fmt_val(),
+                pc.fmt_addr()
+            )
+            .unwrap(),
+            BreakReason::WatchpointHit(DebugTrap {
+                pc,
+                cause: TrapCause::Interrupt(interrupt),
+            }) => writeln!(
+                self.output_buffer,
+                "{} {} {} {}\n",
+                style("Dispatched interrupt").red(),
+                interrupt.name(),
+                style("at").red(),
+                pc.fmt_addr()
             )
             .unwrap(),
+            BreakReason::IllegalInstr(illegal_instr) => writeln!(
+                self.output_buffer,
+                "{} {} {} {}\n",
+                style("Executed illegal instruction").red(),
+                illegal_instr.opcode.fmt_val(),
+                style("at").red(),
+                illegal_instr.pc.fmt_addr()
+            )
+            .unwrap(),
+            BreakReason::FrameFinished => {
+                writeln!(self.output_buffer, "{}\n", style("Frame finished").red()).unwrap()
+            }
         }
     }
 
@@ -279,15 +542,53 @@ impl CpuDebugger {
 
         writeln!(
             self.output_buffer,
-            " SCY: {}, SCX: {}, LY: {} ({})\n LYC: {}, WY: {} ({}), WX: {}",
+            " SCY: {}, SCX: {}, LY: {} ({})\n LYC: {}, WY: {}, WX: {}, Window line: {}",
             ppu.read_reg(PpuReg::SCY).fmt_val(),
             ppu.read_reg(PpuReg::SCX).fmt_val(),
             ppu.read_reg(PpuReg::LY).fmt_val(),
             ppu.ly_internal().fmt_val(),
             ppu.read_reg(PpuReg::LYC).fmt_val(),
             ppu.read_reg(PpuReg::WY).fmt_val(),
-            ppu.wy_internal().fmt_val(),
             ppu.read_reg(PpuReg::WX).fmt_val(),
+            ppu.window_line_internal()
+                .map_or("-".to_owned(), |line| line.to_string()),
+        )
+        .unwrap();
+
+        writeln!(
+            self.output_buffer,
+            " Scanline: {}, Cycle: {}/114 (dot {}), Dropped sprites: {}",
+            ppu.ly_internal(),
+            ppu.scanline_mcycle_internal(),
+            4 * ppu.scanline_mcycle_internal() as u16,
+            ppu.dropped_sprites_internal(),
+        )
+        .unwrap();
+    }
+
+    fn print_bank_state(&mut self, banks: BankState) {
+        let ram = match banks.ram_bank {
+            _ if banks.rtc_mapped => "RTC".to_string(),
+            Some(bank) => bank.to_string(),
+            None => "None".to_string(),
+        };
+
+        writeln!(
+            self.output_buffer,
+            " ROM: {}/{}{}, RAM: {} ({})",
+            banks.rom_bank,
+            banks.rom_bank_count,
+            if banks.rom_bank_forced {
+                style(" (forced)").yellow()
+            } else {
+                style("")
+            },
+            ram,
+            if banks.ram_enabled {
+                style("Enabled").green()
+            } else {
+                style("Disabled").red()
+            },
         )
         .unwrap();
     }
@@ -342,6 +643,13 @@ impl CpuDebugger {
                     halt_state
                 )
                 .unwrap(),
+                CpuEvt::IllegalInstr(illegal_instr) => writeln!(
+                    self.output_buffer,
+                    " {} {}",
+                    style("Illegal instruction, CPU is stuck:").red(),
+                    illegal_instr.opcode.fmt_val()
+                )
+                .unwrap(),
                 CpuEvt::IrEnable => writeln!(
                     self.output_buffer,
                     " {}",
@@ -354,6 +662,8 @@ impl CpuDebugger {
                     style("Interrupts Disabled").red()
                 )
                 .unwrap(),
+                // The debugger's logger never traces
+                CpuEvt::Trace(_) => (),
             }
         }
     }
@@ -367,55 +677,448 @@ impl CpuDebugger {
         .unwrap();
 
         let mut pc = cpu.reg.pc;
-        // Safe transmute because every u8 represents a valid enum variant
-        let instr: ByteInstr =
-            unsafe { std::mem::transmute(board.read8_instant(Addr::from(cpu.reg.pc))) };
 
-        self.print_single_instr(board, &mut pc, instr);
+        // The upcoming instruction, and up to 10 more until the control flow changes
+        for _ in 0..11 {
+            let instr = disasm::decode_at(|addr| board.read8_instant(Addr::from(addr)), pc);
 
-        if instr.is_control_flow_change() {
-            return;
+            write_instr(&mut self.output_buffer, &instr);
+            pc = instr.next_addr();
+
+            if instr.changes_control_flow {
+                return;
+            }
         }
+    }
+}
 
-        for _ in 0..10 {
-            // Safe transmute because every u8 represents a valid enum variant
-            let instr: ByteInstr =
-                unsafe { std::mem::transmute(board.read8_instant(Addr::from(pc))) };
+fn write_instr(output: &mut String, instr: &DecodedInstr) {
+    let text = if instr.illegal {
+        style(instr.text.clone()).red()
+    } else {
+        style(instr.text.clone())
+    };
 
-            self.print_single_instr(board, &mut pc, instr);
+    write!(output, " [{}] {}", instr.addr.fmt_addr(), text).unwrap();
 
-            if instr.is_control_flow_change() {
-                return;
+    // Name the IO register, like the address formatting does everywhere else
+    if let Some(reg) = instr
+        .mem_operand
+        .and_then(|addr| IOReg::try_from(addr).ok())
+    {
+        write!(output, " {}", style(format!("; {:?}", reg)).green()).unwrap();
+    }
+
+    writeln!(output).unwrap();
+}
+
+fn print_prompt(term: &Term) {
+    term.write_str(&style("Enter command: ").yellow().to_string())
+        .unwrap();
+}
+
+/// Sends every line typed into the console, until the console is closed. Reads stdin
+/// directly (instead of via [`Term`]), so commands can be piped in as well.
+fn spawn_command_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let stdin = io::stdin();
+
+        for command in stdin.lock().lines().map_while(Result::ok) {
+            if sender.send(command).is_err() {
+                break;
             }
         }
+    });
+
+    receiver
+}
+
+/// `heatmap start` begins counting memory accesses, `heatmap save <path>` ends the
+/// capture, writes the heatmap as a PNG and prints the totals per region
+fn cmd_heatmap<'a, C: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>, I: Iterator<Item = &'a str>>(
+    emu: &mut Emulator<C, DbgEvtLogger<CpuEvt>, PpuDbg>,
+    term: &Term,
+    mut args: I,
+) {
+    let mut output = String::new();
+
+    match (args.next(), args.next()) {
+        (Some("start"), None) => {
+            emu.start_mem_stats();
+            writeln!(
+                output,
+                "{}",
+                style("Counting memory accesses until 'heatmap save'").green()
+            )
+            .unwrap();
+        }
+        (Some("save"), Some(path)) => match emu.stop_mem_stats() {
+            Some(stats) => {
+                for region in stats.regions() {
+                    writeln!(
+                        output,
+                        "{:<8} {}-{}: {:>10} reads, {:>10} writes, {:>5} addresses",
+                        region.region.name,
+                        region.region.start.fmt_addr(),
+                        region.region.end.fmt_addr(),
+                        region.reads,
+                        region.writes,
+                        region.addrs_touched
+                    )
+                    .unwrap();
+                }
+
+                match stats.save_heatmap(path) {
+                    Ok(()) => writeln!(output, "{} {}", style("Saved heatmap to").green(), path),
+                    Err(err) => writeln!(
+                        output,
+                        "{} {}",
+                        style("Could not save heatmap:").red(),
+                        style(err).red()
+                    ),
+                }
+                .unwrap();
+            }
+            None => writeln!(
+                output,
+                "{}",
+                style("ERROR: Use 'heatmap start' first").red()
+            )
+            .unwrap(),
+        },
+        _ => writeln!(
+            output,
+            "{}",
+            style("ERROR: Use either 'heatmap start' or 'heatmap save <path>'").red()
+        )
+        .unwrap(),
     }
 
-    /// Returns new PC after reading the instruction
-    fn print_single_instr<B: Board>(&mut self, board: &B, pc: &mut u16, instr: ByteInstr) {
-        if let Some(operand) = instr.operand_type() {
+    term.write_line(&output).unwrap();
+}
+
+/// `profile start` begins counting the executed instructions, `profile stop [path]`
+/// prints the hottest code and writes all counts to a CSV file
+fn cmd_profile<'a, C: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>, I: Iterator<Item = &'a str>>(
+    emu: &mut Emulator<C, DbgEvtLogger<CpuEvt>, PpuDbg>,
+    term: &Term,
+    mut args: I,
+) {
+    let mut output = String::new();
+
+    match (args.next(), args.next()) {
+        (Some("start"), None) => {
+            emu.start_profiler();
             writeln!(
-                self.output_buffer,
-                " [{}] {:?} {}",
-                pc.fmt_addr(),
-                instr,
-                operand.fmt(board, *pc)
+                output,
+                "{}",
+                style("Profiling until 'profile stop'").green()
             )
             .unwrap();
+        }
+        (Some("stop"), path) => match emu.stop_profiler() {
+            Some(profiler) => {
+                let mut report = Vec::new();
+                profiler.write_report(&mut report, 10).unwrap();
+                output.push_str(&String::from_utf8_lossy(&report));
 
-            *pc = pc.wrapping_add(1 + operand.len() as u16);
-        } else {
-            writeln!(self.output_buffer, " [{}] {:?}", pc.fmt_addr(), instr).unwrap();
+                if let Some(path) = path {
+                    let result = std::fs::File::create(path)
+                        .and_then(|file| profiler.write_csv(io::BufWriter::new(file)));
 
-            *pc = pc.wrapping_add(1);
+                    match result {
+                        Ok(()) => {
+                            writeln!(output, "{} {}", style("Saved profile to").green(), path)
+                        }
+                        Err(err) => writeln!(
+                            output,
+                            "{} {}",
+                            style("Could not save profile:").red(),
+                            style(err).red()
+                        ),
+                    }
+                    .unwrap();
+                }
+            }
+            None => writeln!(
+                output,
+                "{}",
+                style("ERROR: Use 'profile start' first").red()
+            )
+            .unwrap(),
+        },
+        _ => writeln!(
+            output,
+            "{}",
+            style("ERROR: Use either 'profile start' or 'profile stop [path]'").red()
+        )
+        .unwrap(),
+    }
+
+    term.write_line(&output).unwrap();
+}
+
+/// `map [path]`: Prints how the address space is decoded, or writes it to a markdown file
+fn cmd_map<'a, I: Iterator<Item = &'a str>>(term: &Term, mut args: I) {
+    let entries = describe_memory_map();
+    let mut output = String::new();
+
+    match args.next() {
+        Some(path) => match std::fs::write(path, memory_map_markdown(&entries)) {
+            Ok(()) => writeln!(output, "{} {}", style("Wrote memory map to").green(), path),
+            Err(err) => writeln!(
+                output,
+                "{} {}",
+                style("Could not write memory map:").red(),
+                style(err).red()
+            ),
+        }
+        .unwrap(),
+        None => {
+            for entry in &entries {
+                writeln!(
+                    output,
+                    "{}-{} {:<8} {:<55} {}",
+                    entry.start.fmt_addr(),
+                    entry.end.fmt_addr(),
+                    entry.region,
+                    entry.handler,
+                    entry.restrictions()
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    term.write_line(&output).unwrap();
+}
+
+/// Number of bytes that `mem` shows if no length is given
+const MEM_DEFAULT_LEN: u32 = 64;
+
+fn is_call(instr: ByteInstr) -> bool {
+    use ByteInstr::*;
+
+    matches!(
+        instr,
+        CALL_a16
+            | CALL_NZ_a16
+            | CALL_Z_a16
+            | CALL_NC_a16
+            | CALL_C_a16
+            | RST_00H
+            | RST_08H
+            | RST_10H
+            | RST_18H
+            | RST_20H
+            | RST_28H
+            | RST_30H
+            | RST_38H
+    )
+}
+
+fn is_return(opcode: u8) -> bool {
+    use ByteInstr::*;
+
+    matches!(
+        ByteInstr::decode(opcode),
+        RET | RETI | RET_NZ | RET_Z | RET_NC | RET_C
+    )
+}
+
+/// `mem <addr> [len]` shows memory as a hex dump
+fn cmd_mem<'a, C: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>, I: Iterator<Item = &'a str>>(
+    emu: &Emulator<C, DbgEvtLogger<CpuEvt>, PpuDbg>,
+    term: &Term,
+    mut args: I,
+) {
+    let mut output = String::new();
+
+    let addr = args.next().map(parse_int::parse::<u16>);
+    let len = args
+        .next()
+        .map_or(Ok(MEM_DEFAULT_LEN), parse_int::parse::<u32>);
+
+    match (addr, len) {
+        (Some(Ok(addr)), Ok(len)) => write_hexdump(&mut output, emu, addr, len.min(0x10000)),
+        (Some(Err(err)), _) | (_, Err(err)) => writeln!(
+            output,
+            "{} {}",
+            style("Could not parse number:").red(),
+            style(err).red()
+        )
+        .unwrap(),
+        (None, _) => writeln!(output, "{}", style("Needs argument: Address").red()).unwrap(),
+    }
+
+    term.write_line(&output).unwrap();
+}
+
+/// `memset <addr> <bytes...>` writes bytes the way the CPU would (see [`Emulator::poke`])
+/// and shows the result
+fn cmd_memset<'a, C: Cartridge, PpuDbg: DbgEvtSrc<PpuEvt>, I: Iterator<Item = &'a str>>(
+    emu: &mut Emulator<C, DbgEvtLogger<CpuEvt>, PpuDbg>,
+    term: &Term,
+    mut args: I,
+) {
+    let mut output = String::new();
+
+    let addr = args.next().map(parse_int::parse::<u16>);
+    let bytes: Result<Vec<u8>, _> = args.map(parse_int::parse::<u8>).collect();
+
+    match (addr, bytes) {
+        (Some(Ok(addr)), Ok(bytes)) if !bytes.is_empty() => {
+            for (offset, &val) in bytes.iter().enumerate() {
+                emu.poke(addr.wrapping_add(offset as u16), val);
+            }
+
+            write_hexdump(&mut output, emu, addr, bytes.len() as u32);
+        }
+        (Some(Err(err)), _) | (_, Err(err)) => writeln!(
+            output,
+            "{} {}",
+            style("Could not parse number:").red(),
+            style(err).red()
+        )
+        .unwrap(),
+        _ => writeln!(
+            output,
+            "{}",
+            style("Needs arguments: Address and bytes").red()
+        )
+        .unwrap(),
+    }
+
+    term.write_line(&output).unwrap();
+}
+
+/// A classic hex dump with 16 bytes per row, followed by the bytes as ASCII. IO
+/// registers are green, the byte at PC is cyan and the one at SP is magenta.
+fn write_hexdump<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>(
+    output: &mut String,
+    emu: &Emulator<C, CpuDbg, PpuDbg>,
+    addr: u16,
+    len: u32,
+) {
+    let pc = emu.cpu.reg.pc;
+    let sp = emu.cpu.reg.sp;
+
+    for row_start in (0..len).step_by(16) {
+        let row_addr = addr.wrapping_add(row_start as u16);
+        let row_len = (len - row_start).min(16) as u16;
+
+        // Not `fmt_addr`, which would name IO registers and break the alignment
+        write!(output, "{} ", style(format!("{:#06X}", row_addr)).yellow()).unwrap();
+
+        let mut ascii = String::new();
+
+        for col in 0..16 {
+            if col >= row_len {
+                write!(output, "   ").unwrap();
+                continue;
+            }
+
+            let byte_addr = row_addr.wrapping_add(col);
+            let val = emu.board.read8_instant(Addr::from(byte_addr));
+            let hex = style(format!("{:02X}", val));
+            let is_io_reg = match Addr::from(byte_addr) {
+                Addr::IO(IOReg::Unimplemented(_)) => false,
+                Addr::IO(_) => true,
+                _ => false,
+            };
+
+            let hex = if byte_addr == pc {
+                hex.cyan().bold()
+            } else if byte_addr == sp {
+                hex.magenta().bold()
+            } else if is_io_reg {
+                hex.green()
+            } else {
+                hex
+            };
+
+            write!(output, " {}", hex).unwrap();
+
+            ascii.push(match val {
+                0x20..=0x7E => val as char,
+                _ => '.',
+            });
         }
+
+        writeln!(output, "  |{}|", ascii).unwrap();
     }
 }
 
+/// `patch <addr> <instr>` assembles an instruction and writes it to memory. ROM can't be
+/// written to, so ROM bytes are patched with Game Genie codes instead (with the current
+/// byte as compare byte, so only the currently mapped bank is affected).
+fn cmd_patch<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>(
+    emu: &mut Emulator<C, CpuDbg, PpuDbg>,
+    term: &Term,
+    args: &str,
+) {
+    let mut output = String::new();
+
+    let (addr, instr) = match args.split_once(char::is_whitespace) {
+        Some((addr, instr)) => (addr, instr.trim()),
+        None => {
+            term.write_line(
+                &style("Needs arguments: Address and instruction\n")
+                    .red()
+                    .to_string(),
+            )
+            .unwrap();
+            return;
+        }
+    };
+
+    let result = parse_int::parse::<u16>(addr)
+        .map_err(|err| format!("Could not parse address: {}", err))
+        .and_then(|addr| {
+            asm::assemble(instr, addr)
+                .map(|bytes| (addr, bytes))
+                .map_err(|err| format!("Could not assemble instruction: {:?}", err))
+        });
+
+    match result {
+        Ok((addr, bytes)) => {
+            for (offset, &val) in bytes.iter().enumerate() {
+                let byte_addr = addr.wrapping_add(offset as u16);
+
+                match Addr::from(byte_addr) {
+                    Addr::Mem(MemAddr::CROM(rom_addr)) => {
+                        let code = CheatCode::GameGenie {
+                            addr: byte_addr,
+                            val,
+                            compare: Some(emu.cartridge().read_rom(rom_addr)),
+                        };
+                        let text = format!("patch {:#06X} {:#04X}", byte_addr, val);
+
+                        emu.board.mem.cheats_mut().add_code(text, code);
+                    }
+                    _ => emu.poke(byte_addr, val),
+                }
+            }
+
+            let instr = disasm::decode_at(|addr| emu.peek(addr), addr);
+
+            write!(output, "{}", style("Patched").green()).unwrap();
+            write_instr(&mut output, &instr);
+        }
+        Err(err) => writeln!(output, "{}", style(err).red()).unwrap(),
+    }
+
+    term.write_line(&output).unwrap();
+}
+
 mod cmd_bp {
     use super::*;
 
     pub fn execute<'a, I: Iterator<Item = &'a str>>(
         dbg: &mut CpuDebugger,
+        wps: &mut WatchpointSet,
         term: &Term,
         mut args: I,
     ) {
@@ -423,14 +1126,16 @@ mod cmd_bp {
 
         match args.by_ref().next() {
             Some("set") => set(dbg, &mut output, args),
-            Some("mem") => mem(dbg, &mut output, args),
-            Some("list") => list(dbg, &mut output),
-            Some("rm") => rm(dbg, &mut output, args),
-            Some("clear") => clear(dbg, &mut output),
+            Some("mem") => mem(wps, &mut output, args),
+            Some("io") => io(wps, &mut output, args),
+            Some("irq") => irq(wps, &mut output, args),
+            Some("list") => list(dbg, wps, &mut output),
+            Some("rm") => rm(dbg, wps, &mut output, args),
+            Some("clear") => clear(dbg, wps, &mut output),
             _ => writeln!(
                 output,
                 "{}",
-                style("ERROR: Use either 'set', 'mem', 'rm', 'list' or 'clear'").red()
+                style("ERROR: Use either 'set', 'mem', 'io', 'irq', 'rm', 'list' or 'clear'").red()
             )
             .unwrap(),
         }
@@ -443,20 +1148,57 @@ mod cmd_bp {
         output: &mut String,
         mut args: I,
     ) {
-        cmd_bp::exec_with_addr(args.next(), output, |addr, output: &mut String| {
-            dbg.breakpoints.push(addr);
-            writeln!(
-                output,
-                "{} {}",
-                style("Added breakpoint at").green(),
-                addr.fmt_addr()
-            )
+        let addr_str = args.next();
+
+        // Everything after `if` is the condition, so it may contain spaces
+        let mut condition = match args.next() {
+            Some("if") => match args.collect::<Vec<_>>().join(" ").parse::<Condition>() {
+                Ok(condition) => Some(condition),
+                Err(err) => {
+                    writeln!(
+                        output,
+                        "{} {}",
+                        style("Could not parse condition:").red(),
+                        style(err).red()
+                    )
+                    .unwrap();
+                    return;
+                }
+            },
+            Some(_) => {
+                writeln!(output, "{}", style("Expected 'if' after the address").red()).unwrap();
+                return;
+            }
+            None => None,
+        };
+
+        cmd_bp::exec_with_addr(addr_str, output, |addr, output: &mut String| {
+            match &condition {
+                Some(condition) => writeln!(
+                    output,
+                    "{} {} if {}",
+                    style("Added breakpoint at").green(),
+                    addr.fmt_addr(),
+                    condition
+                ),
+                None => writeln!(
+                    output,
+                    "{} {}",
+                    style("Added breakpoint at").green(),
+                    addr.fmt_addr()
+                ),
+            }
             .unwrap();
+
+            dbg.breakpoints.push(Breakpoint {
+                addr,
+                condition: condition.take(),
+            });
         });
     }
 
     fn mem<'a, I: Iterator<Item = &'a str>>(
-        dbg: &mut CpuDebugger,
+        wps: &mut WatchpointSet,
         output: &mut String,
         mut args: I,
     ) {
@@ -472,33 +1214,179 @@ mod cmd_bp {
 
         match args.by_ref().next() {
             Some("r") => cmd_bp::exec_with_addr(args.next(), output, |addr, output| {
-                dbg.mem_breakpoints.push((addr, BreakCond::Read));
+                wps.add(Watchpoint::new(addr, WatchKind::Read));
                 print_bp_added_msg(addr, output);
             }),
             Some("w") => cmd_bp::exec_with_addr(args.next(), output, |addr, output| {
-                dbg.mem_breakpoints.push((addr, BreakCond::Write));
+                wps.add(Watchpoint::new(addr, WatchKind::Write));
                 print_bp_added_msg(addr, output);
             }),
             Some("rw") => cmd_bp::exec_with_addr(args.next(), output, |addr, output| {
-                dbg.mem_breakpoints.push((addr, BreakCond::ReadWrite));
+                wps.add(Watchpoint::new(addr, WatchKind::ReadWrite));
                 print_bp_added_msg(addr, output);
             }),
             _ => writeln!(output, "{}", style("Use either 'r', 'w', or 'rw'").red()).unwrap(),
         }
     }
 
-    fn list(dbg: &CpuDebugger, output: &mut String) {
-        for (idx, bp) in dbg.breakpoints.iter().copied().enumerate() {
-            writeln!(output, " {:>3}. {}", idx, bp.fmt_addr()).unwrap();
+    /// `bp io <addr> [value] [mask <mask>]`: Breaks on writes to an IO register. With a
+    /// mask, only the masked bits are compared, and they have to be set if there is no
+    /// value (so `bp io FF40 mask 0x80` breaks when the LCD is turned on).
+    fn io<'a, I: Iterator<Item = &'a str>>(
+        wps: &mut WatchpointSet,
+        output: &mut String,
+        mut args: I,
+    ) {
+        let addr_str = args.next();
+        let mut value = None;
+        let mut mask = None;
+
+        while let Some(arg) = args.next() {
+            let (target, arg) = match arg {
+                "mask" => (&mut mask, args.next().unwrap_or_default()),
+                _ => (&mut value, arg),
+            };
+
+            match parse_int::parse::<u8>(arg) {
+                Ok(val) => *target = Some(val),
+                Err(err) => {
+                    writeln!(
+                        output,
+                        "{} {}",
+                        style("Could not parse value:").red(),
+                        style(err).red()
+                    )
+                    .unwrap();
+                    return;
+                }
+            }
         }
 
-        for (idx, (addr, cond)) in dbg.mem_breakpoints.iter().copied().enumerate() {
+        let value = match (value, mask) {
+            (Some(value), mask) => Some(ValueMatch {
+                mask: mask.unwrap_or(0xFF),
+                value,
+            }),
+            (None, Some(mask)) => Some(ValueMatch { mask, value: mask }),
+            (None, None) => None,
+        };
+
+        cmd_bp::exec_with_addr(addr_str, output, |addr, output| {
+            if IOReg::try_from(addr).is_err() {
+                writeln!(output, "{}", style("Not an IO register").red()).unwrap();
+                return;
+            }
+
+            let wp = Watchpoint::new(addr, WatchKind::Write);
+
+            wps.add(match value {
+                Some(value) => wp.with_value(value),
+                None => wp,
+            });
+
             writeln!(
                 output,
-                " {:>3}. {} ({:?})",
-                idx + dbg.breakpoints.len(),
-                addr.fmt_addr(),
-                cond
+                "{} {}",
+                style("Breakpoint added at").green(),
+                addr.fmt_addr()
+            )
+            .unwrap();
+        });
+    }
+
+    /// `bp irq <vblank|stat|timer|serial|joypad>`
+    fn irq<'a, I: Iterator<Item = &'a str>>(
+        wps: &mut WatchpointSet,
+        output: &mut String,
+        mut args: I,
+    ) {
+        let name = args.next().unwrap_or_default();
+
+        match Interrupt::ALL.iter().find(|ir| ir.name() == name) {
+            Some(&interrupt) => {
+                wps.add_interrupt(interrupt);
+                writeln!(
+                    output,
+                    "{} {}",
+                    style("Breakpoint added for interrupt").green(),
+                    interrupt.name()
+                )
+                .unwrap();
+            }
+            None => writeln!(
+                output,
+                "{}",
+                style("Use either 'vblank', 'stat', 'timer', 'serial' or 'joypad'").red()
+            )
+            .unwrap(),
+        }
+    }
+
+    fn list(dbg: &CpuDebugger, wps: &WatchpointSet, output: &mut String) {
+        for (idx, bp) in dbg.breakpoints.iter().enumerate() {
+            match &bp.condition {
+                Some(condition) => {
+                    writeln!(
+                        output,
+                        " {:>3}. {} if {}",
+                        idx,
+                        bp.addr.fmt_addr(),
+                        condition
+                    )
+                }
+                None => writeln!(output, " {:>3}. {}", idx, bp.addr.fmt_addr()),
+            }
+            .unwrap();
+        }
+
+        for (idx, wp) in wps.watchpoints().iter().enumerate() {
+            let addrs = if wp.addrs.start() == wp.addrs.end() {
+                wp.addrs.start().fmt_addr().to_string()
+            } else {
+                format!(
+                    "{}-{}",
+                    wp.addrs.start().fmt_addr(),
+                    wp.addrs.end().fmt_addr()
+                )
+            };
+
+            match wp.value {
+                Some(ValueMatch { mask: 0xFF, value }) => writeln!(
+                    output,
+                    " {:>3}. {} ({:?} of {})",
+                    idx + dbg.breakpoints.len(),
+                    addrs,
+                    wp.kind,
+                    value.fmt_addr()
+                ),
+                Some(ValueMatch { mask, value }) => writeln!(
+                    output,
+                    " {:>3}. {} ({:?} of {} with mask {})",
+                    idx + dbg.breakpoints.len(),
+                    addrs,
+                    wp.kind,
+                    value.fmt_addr(),
+                    mask.fmt_addr()
+                ),
+                None => writeln!(
+                    output,
+                    " {:>3}. {} ({:?})",
+                    idx + dbg.breakpoints.len(),
+                    addrs,
+                    wp.kind
+                ),
+            }
+            .unwrap();
+        }
+
+        let first_irq_idx = dbg.breakpoints.len() + wps.watchpoints().len();
+
+        for (idx, interrupt) in wps.interrupts().iter().enumerate() {
+            writeln!(
+                output,
+                " {:>3}. Interrupt {}",
+                idx + first_irq_idx,
+                interrupt.name()
             )
             .unwrap();
         }
@@ -506,24 +1394,30 @@ mod cmd_bp {
 
     fn rm<'a, I: Iterator<Item = &'a str>>(
         dbg: &mut CpuDebugger,
+        wps: &mut WatchpointSet,
         output: &mut String,
         mut args: I,
     ) {
         match args.next() {
             Some(idx) => match idx.parse::<usize>() {
                 Ok(idx) => {
-                    if idx < dbg.breakpoints.len() {
+                    // Same order as in `list`
+                    let wp_idx = idx.wrapping_sub(dbg.breakpoints.len());
+                    let irq_idx = wp_idx.wrapping_sub(wps.watchpoints().len());
+
+                    let removed = if idx < dbg.breakpoints.len() {
                         dbg.breakpoints.remove(idx);
+                        true
+                    } else if wp_idx < wps.watchpoints().len() {
+                        wps.remove(wp_idx).is_some()
+                    } else {
+                        wps.remove_interrupt(irq_idx).is_some()
+                    };
+
+                    if removed {
                         writeln!(output, "{}", style("Breakpoint removed").green()).unwrap();
                     } else {
-                        let idx = idx - dbg.breakpoints.len();
-                        if idx < dbg.mem_breakpoints.len() {
-                            dbg.mem_breakpoints.remove(idx);
-                            writeln!(output, "{}", style("Breakpoint removed").green()).unwrap();
-                        } else {
-                            writeln!(output, "{}", style("Invalid breakpoint index").red())
-                                .unwrap();
-                        }
+                        writeln!(output, "{}", style("Invalid breakpoint index").red()).unwrap();
                     }
                 }
                 Err(err) => writeln!(
@@ -543,9 +1437,9 @@ mod cmd_bp {
         }
     }
 
-    fn clear(dbg: &mut CpuDebugger, output: &mut String) {
+    fn clear(dbg: &mut CpuDebugger, wps: &mut WatchpointSet, output: &mut String) {
         dbg.breakpoints.clear();
-        dbg.mem_breakpoints.clear();
+        wps.clear();
         writeln!(output, "{}", style("All breakpoints cleared").green()).unwrap();
     }
 
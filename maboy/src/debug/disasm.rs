@@ -0,0 +1,190 @@
+//! Turns machine code back into assembly, e.g. for the CPU debugger or for external
+//! tools that look at ROM dumps. Use [`disassemble`] for a block of bytes, or
+//! [`decode_at`] to decode a single instruction straight from the address space (like
+//! `decode_at(|addr| emu.peek(addr), pc)`).
+//!
+//! The syntax is lowercase, with hexadecimal numbers prefixed by `$`, e.g.
+//! `ld a, ($FF44)` or `jr nz, $0150`. Relative jumps show their target address instead
+//! of the offset. Opcodes that the CPU doesn't know are shown as data (`db $D3`).
+
+use crate::cpu::{ByteInstr, CBByteInstr};
+
+/// A single disassembled instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInstr {
+    /// Address of the first byte of the instruction
+    pub addr: u16,
+    /// The opcode (including the 0xCB prefix) and the operands
+    pub bytes: Vec<u8>,
+    /// The instruction in assembly, see the module documentation
+    pub text: String,
+    /// Where the instruction jumps to (if the jump is taken), for jumps, calls and
+    /// restarts with a constant target. `None` for returns and `jp (hl)`.
+    pub jump_target: Option<u16>,
+    /// The address of a constant memory operand, like `$FF44` in `ld a, ($FF44)`
+    pub mem_operand: Option<u16>,
+    /// Whether the instruction might continue somewhere else than at
+    /// [`DecodedInstr::next_addr`] (jumps, calls, returns and restarts)
+    pub changes_control_flow: bool,
+    /// Whether the opcode is unknown to the CPU, which gets stuck when executing it
+    pub illegal: bool,
+}
+
+impl DecodedInstr {
+    /// The address of the instruction that follows in memory
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+
+    /// A single byte that is shown as data, because it can't be decoded
+    fn data(addr: u16, byte: u8) -> DecodedInstr {
+        DecodedInstr {
+            addr,
+            bytes: vec![byte],
+            text: format!("db ${:02X}", byte),
+            jump_target: None,
+            mem_operand: None,
+            changes_control_flow: false,
+            illegal: false,
+        }
+    }
+}
+
+/// Disassembles a block of bytes that starts at `base_addr`. If the last instruction is
+/// cut off, its bytes are shown as data.
+pub fn disassemble(bytes: &[u8], base_addr: u16) -> impl Iterator<Item = DecodedInstr> + '_ {
+    let mut offset = 0;
+
+    std::iter::from_fn(move || {
+        let rest = bytes.get(offset..).filter(|rest| !rest.is_empty())?;
+        let addr = base_addr.wrapping_add(offset as u16);
+
+        let instr = decode(rest, addr).unwrap_or_else(|| DecodedInstr::data(addr, rest[0]));
+        offset += instr.bytes.len();
+
+        Some(instr)
+    })
+}
+
+/// Decodes the instruction at `addr`, reading its bytes via `read`. Addresses wrap
+/// around at the end of the address space, like they do for the CPU.
+pub fn decode_at<F: Fn(u16) -> u8>(read: F, addr: u16) -> DecodedInstr {
+    let bytes = [
+        read(addr),
+        read(addr.wrapping_add(1)),
+        read(addr.wrapping_add(2)),
+    ];
+
+    decode(&bytes, addr).expect("Instructions are at most 3 bytes long")
+}
+
+/// Decodes the instruction at the start of `bytes`, which is located at `addr`.
+/// Returns `None` if `bytes` is too short to hold the whole instruction.
+pub fn decode(bytes: &[u8], addr: u16) -> Option<DecodedInstr> {
+    let instr = ByteInstr::decode(*bytes.first()?);
+    let bytes = bytes.get(..instr_len(bytes[0]) as usize)?;
+    let name = format!("{:?}", instr);
+
+    let changes_control_flow = instr.is_control_flow_change();
+    let next_addr = addr.wrapping_add(bytes.len() as u16);
+    let d8 = bytes.get(1).copied().unwrap_or(0);
+    let d16 = u16::from_le_bytes([d8, bytes.get(2).copied().unwrap_or(0)]);
+
+    let mut jump_target = None;
+    let mut mem_operand = None;
+
+    let text = match instr {
+        _ if name.starts_with("NOT_USED") => format!("db ${:02X}", bytes[0]),
+        ByteInstr::PREFIX_CB => assembly(&format!("{:?}", CBByteInstr::decode(d8)), |_| {
+            unreachable!("CB instructions have no immediate operands")
+        }),
+        // Only `10 00` is a proper STOP, everything else is a corrupted STOP
+        ByteInstr::STOP if d8 == 0 => "stop".to_owned(),
+        ByteInstr::STOP => format!("stop ${:02X}", d8),
+        _ => assembly(&name, |operand| match operand {
+            "d8" => format!("${:02X}", d8),
+            "d16" => format!("${:04X}", d16),
+            "a16" => {
+                jump_target = Some(d16);
+                format!("${:04X}", d16)
+            }
+            "xa16x" => {
+                mem_operand = Some(d16);
+                format!("(${:04X})", d16)
+            }
+            "xa8x" => {
+                mem_operand = Some(0xFF00 | d8 as u16);
+                format!("(${:04X})", 0xFF00 | d8 as u16)
+            }
+            // Only used by `add sp, r8`, since relative jumps show their target instead
+            "r8" if !changes_control_flow => signed(d8 as i8),
+            "r8" => {
+                let target = next_addr.wrapping_add(d8 as i8 as u16);
+                jump_target = Some(target);
+                format!("${:04X}", target)
+            }
+            "SPpr8" if (d8 as i8) < 0 => format!("sp{}", signed(d8 as i8)),
+            "SPpr8" => format!("sp+{}", signed(d8 as i8)),
+            _ => unreachable!("Unknown operand {}", operand),
+        }),
+    };
+
+    if let Some(vector) = name.strip_prefix("RST_") {
+        jump_target = u16::from_str_radix(vector.trim_end_matches('H'), 16).ok();
+    }
+
+    Some(DecodedInstr {
+        addr,
+        bytes: bytes.to_vec(),
+        text,
+        jump_target,
+        mem_operand,
+        changes_control_flow,
+        illegal: name.starts_with("NOT_USED"),
+    })
+}
+
+/// Length of the instruction that starts with `opcode` in bytes (including operands)
+pub fn instr_len(opcode: u8) -> u8 {
+    1 + ByteInstr::decode(opcode)
+        .operand_type()
+        .map_or(0, |operand| operand.len())
+}
+
+/// Turns the name of an instruction (like `LD_xHLix_A`) into assembly (`ld (hl+), a`).
+/// Immediate operands (like `d8`) are formatted by `immediate`.
+pub(super) fn assembly<F: FnMut(&str) -> String>(name: &str, mut immediate: F) -> String {
+    let mut parts = name.split('_');
+    let mnemonic = parts.next().unwrap_or_default().to_lowercase();
+
+    let operands: Vec<String> = parts
+        .map(|operand| match operand {
+            "d8" | "d16" | "a16" | "r8" | "xa8x" | "xa16x" | "SPpr8" => immediate(operand),
+            // Restart vectors, like 38H
+            vector if vector.ends_with('H') && vector.len() == 3 => {
+                format!("${}", vector.trim_end_matches('H'))
+            }
+            "xHLix" => "(hl+)".to_owned(),
+            "xHLdx" => "(hl-)".to_owned(),
+            indirect if indirect.starts_with('x') && indirect.ends_with('x') => {
+                format!("({})", indirect[1..indirect.len() - 1].to_lowercase())
+            }
+            register => register.to_lowercase(),
+        })
+        .collect();
+
+    if operands.is_empty() {
+        mnemonic
+    } else {
+        format!("{} {}", mnemonic, operands.join(", "))
+    }
+}
+
+/// `$05` or `-$05`
+fn signed(val: i8) -> String {
+    if val < 0 {
+        format!("-${:02X}", val.unsigned_abs())
+    } else {
+        format!("${:02X}", val)
+    }
+}
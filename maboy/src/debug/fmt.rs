@@ -1,8 +1,6 @@
 //! Colorful and consistent formatting for outputting stuff in the console
 
-use super::dbg_instr::OperandType;
-use crate::address::{Addr, IOReg};
-use crate::{board::Board, cpu::CBByteInstr};
+use crate::address::IOReg;
 use console::{style, StyledObject};
 use std::convert::TryFrom;
 
@@ -33,37 +31,3 @@ impl FmtNum for u16 {
         }
     }
 }
-
-impl OperandType {
-    // TODO: Handle non-constant PC values here (basically everything
-    // that external components like MBCs and the PPU can influence)
-    pub fn fmt<B: Board>(self, board: &B, pc: u16) -> StyledObject<String> {
-        let pc = pc.wrapping_add(1);
-
-        match self {
-            OperandType::D8 => board.read8_instant(Addr::from(pc)).fmt_val(),
-            OperandType::D16 => board.read16_instant(pc).fmt_val(),
-            OperandType::A8 => (0xFF00 + board.read8_instant(Addr::from(pc)) as u16).fmt_addr(),
-            OperandType::A16 => board.read16_instant(pc).fmt_addr(),
-            OperandType::R8 => {
-                let offset = board.read8_instant(Addr::from(pc)) as i8;
-                (pc.wrapping_add(offset as u16)).fmt_addr()
-            }
-            OperandType::PrefixInstr => {
-                // Safe transmute because every u8 represents a valid enum variant
-                let instr: CBByteInstr =
-                    unsafe { std::mem::transmute(board.read8_instant(Addr::from(pc))) };
-                style(format!("{:?}", instr)).blue()
-            }
-            OperandType::StopOperand => {
-                let operand = board.read8_instant(Addr::from(pc));
-
-                if operand == 0 {
-                    style("0x00 (Valid STOP)".to_owned()).green()
-                } else {
-                    style(format!("{:#04X} (Corrupted STOP)", operand)).red()
-                }
-            }
-        }
-    }
-}
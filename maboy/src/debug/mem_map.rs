@@ -0,0 +1,162 @@
+//! Describes how the emulator decodes the address space: Which component handles each
+//! address, and when the CPU is locked out of it. The table is generated from the same
+//! decoding logic that the emulator uses (see [`crate::address`]), so it can't get out
+//! of sync with the implementation. The debugger shows it with the `map` command.
+
+use super::{PpuMode, MEM_REGIONS};
+use crate::address::{Addr, CRomAddr, IOReg, MemAddr, VideoMemAddr};
+use crate::board::OamDma;
+use crate::ppu::PPU;
+use std::fmt::Write;
+
+/// The PPU modes in which video memory might be blocked, in the order the PPU goes
+/// through them during a scanline
+const PPU_MODES: [PpuMode; 4] = [
+    PpuMode::OAMSearch,
+    PpuMode::PixelTransfer,
+    PpuMode::HBlank,
+    PpuMode::VBlank,
+];
+
+/// A range of addresses that are all handled the same way
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemMapEntry {
+    pub start: u16,
+    /// Inclusive
+    pub end: u16,
+    /// The name of the [`crate::debug::MemRegion`] that contains the range
+    pub region: &'static str,
+    /// Which component handles accesses, e.g. `Timer (TAC)`
+    pub handler: String,
+    /// The PPU modes in which the CPU can't access the range. Reads return 0xFF and
+    /// writes are ignored.
+    pub blocked_in_modes: Vec<PpuMode>,
+    /// Whether the CPU can't access the range while OAM DMA is running
+    pub blocked_by_oam_dma: bool,
+}
+
+impl MemMapEntry {
+    /// A short description of the access restrictions, or `-` if there are none
+    pub fn restrictions(&self) -> String {
+        let mut restrictions: Vec<String> = self
+            .blocked_in_modes
+            .iter()
+            .map(|mode| format!("{:?}", mode))
+            .collect();
+
+        if self.blocked_by_oam_dma {
+            restrictions.push("OAM DMA".to_owned());
+        }
+
+        if restrictions.is_empty() {
+            "-".to_owned()
+        } else {
+            format!("blocked during {}", restrictions.join(", "))
+        }
+    }
+}
+
+/// Decodes every address of the Game Boy's address space and merges neighboring
+/// addresses that are handled the same way into one entry. Every IO register gets an
+/// entry of its own.
+pub fn describe_memory_map() -> Vec<MemMapEntry> {
+    let mut entries: Vec<MemMapEntry> = Vec::new();
+
+    for addr in 0..=0xFFFF {
+        let entry = describe_addr(addr);
+
+        match entries.last_mut() {
+            Some(last)
+                if last.region == entry.region
+                    && last.handler == entry.handler
+                    && last.blocked_in_modes == entry.blocked_in_modes
+                    && last.blocked_by_oam_dma == entry.blocked_by_oam_dma =>
+            {
+                last.end = addr
+            }
+            _ => entries.push(entry),
+        }
+    }
+
+    entries
+}
+
+/// The memory map as a markdown table, e.g. for documentation
+pub fn memory_map_markdown(entries: &[MemMapEntry]) -> String {
+    let mut markdown = String::new();
+
+    writeln!(markdown, "| Range | Region | Handler | Access |").unwrap();
+    writeln!(markdown, "|-------|--------|---------|--------|").unwrap();
+
+    for entry in entries {
+        writeln!(
+            markdown,
+            "| `{:04X}-{:04X}` | {} | {} | {} |",
+            entry.start,
+            entry.end,
+            entry.region,
+            entry.handler,
+            entry.restrictions()
+        )
+        .unwrap();
+    }
+
+    markdown
+}
+
+fn describe_addr(addr: u16) -> MemMapEntry {
+    let decoded = Addr::from(addr);
+
+    let blocked_in_modes = match decoded {
+        Addr::VideoMem(vid_mem_addr) => PPU_MODES
+            .iter()
+            .copied()
+            .filter(|&mode| !PPU::video_mem_accessible_in(mode, vid_mem_addr))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    MemMapEntry {
+        start: addr,
+        end: addr,
+        region: MEM_REGIONS
+            .iter()
+            .find(|region| region.start <= addr && addr <= region.end)
+            .map_or("?", |region| region.name),
+        handler: handler(decoded),
+        blocked_in_modes,
+        blocked_by_oam_dma: OamDma::blocks_cpu_access(decoded),
+    }
+}
+
+/// This match is exhaustive on purpose, so new kinds of addresses can't be forgotten
+fn handler(addr: Addr) -> String {
+    use Addr::*;
+
+    match addr {
+        Mem(MemAddr::CROM(CRomAddr::CROM0(_))) => {
+            "Cartridge ROM bank 0 (writes go to the MBC)".into()
+        }
+        Mem(MemAddr::CROM(CRomAddr::CROMn(_))) => {
+            "Cartridge ROM, switchable bank (writes go to the MBC)".into()
+        }
+        Mem(MemAddr::CRAM(_)) => "Cartridge RAM (if present and enabled)".into(),
+        Mem(MemAddr::WRAM(_)) => "Work RAM".into(),
+        Mem(MemAddr::ECHO(_)) => "Work RAM (echo)".into(),
+        Mem(MemAddr::HRAM(_)) => "High RAM".into(),
+        VideoMem(VideoMemAddr::TileData(_)) => "PPU (tile data)".into(),
+        VideoMem(VideoMemAddr::TileMaps(_)) => "PPU (tile maps)".into(),
+        VideoMem(VideoMemAddr::OAM(_)) => "PPU (OAM)".into(),
+        Unusable => "Nothing (reads return 0, writes are ignored)".into(),
+        IO(IOReg::P1) => "Joypad (P1)".into(),
+        IO(IOReg::Serial(reg)) => format!("Serial port ({:?})", reg),
+        IO(IOReg::Timer(reg)) => format!("Timer ({:?})", reg),
+        IO(IOReg::IF) => "Interrupts (IF)".into(),
+        IO(IOReg::Apu(reg)) => format!("APU ({:?}, not implemented)", reg),
+        IO(IOReg::Ppu(reg)) => format!("PPU ({:?})", reg),
+        IO(IOReg::OamDma) => "OAM DMA (DMA)".into(),
+        IO(IOReg::BootRomDisable) => "Boot ROM (disable)".into(),
+        IO(IOReg::Unimplemented(_)) => "Not implemented (reads return 0xFF)".into(),
+        IE => "Interrupts (IE)".into(),
+    }
+}
@@ -0,0 +1,167 @@
+//! Counts how often the CPU reads and writes every address, e.g. to find out what a game
+//! touches while it does something specific. The counts can be summarized per memory
+//! region or exported as a heatmap.
+
+use crate::frame_dump::write_png;
+use crate::MemPixel;
+use std::io;
+use std::path::Path;
+
+/// Width and height of the heatmap. Every row holds the 256 addresses that share the
+/// same high byte.
+pub const HEATMAP_SIZE: usize = 256;
+
+/// The regions of the Game Boy's address space
+pub const MEM_REGIONS: [MemRegion; 11] = [
+    MemRegion::new("ROM0", 0x0000, 0x3FFF),
+    MemRegion::new("ROMX", 0x4000, 0x7FFF),
+    MemRegion::new("VRAM", 0x8000, 0x9FFF),
+    MemRegion::new("SRAM", 0xA000, 0xBFFF),
+    MemRegion::new("WRAM", 0xC000, 0xDFFF),
+    MemRegion::new("Echo", 0xE000, 0xFDFF),
+    MemRegion::new("OAM", 0xFE00, 0xFE9F),
+    MemRegion::new("Unusable", 0xFEA0, 0xFEFF),
+    MemRegion::new("IO", 0xFF00, 0xFF7F),
+    MemRegion::new("HRAM", 0xFF80, 0xFFFE),
+    MemRegion::new("IE", 0xFFFF, 0xFFFF),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemRegion {
+    pub name: &'static str,
+    pub start: u16,
+    /// Inclusive
+    pub end: u16,
+}
+
+impl MemRegion {
+    const fn new(name: &'static str, start: u16, end: u16) -> MemRegion {
+        MemRegion { name, start, end }
+    }
+}
+
+/// The accesses to a [`MemRegion`], see [`MemStats::regions`]
+#[derive(Debug, Copy, Clone)]
+pub struct RegionStats {
+    pub region: MemRegion,
+    pub reads: u64,
+    pub writes: u64,
+    /// How many different addresses were accessed at all
+    pub addrs_touched: u32,
+}
+
+/// Access counts for every address. Enabled via [`crate::Emulator::start_mem_stats`].
+/// Counts saturate instead of overflowing.
+#[derive(Clone)]
+pub struct MemStats {
+    reads: Box<[u32]>,
+    writes: Box<[u32]>,
+}
+
+impl MemStats {
+    pub fn new() -> Self {
+        MemStats {
+            reads: vec![0; 0x10000].into_boxed_slice(),
+            writes: vec![0; 0x10000].into_boxed_slice(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record_read(&mut self, addr: u16) {
+        let count = &mut self.reads[addr as usize];
+        *count = count.saturating_add(1);
+    }
+
+    #[inline]
+    pub(crate) fn record_write(&mut self, addr: u16) {
+        let count = &mut self.writes[addr as usize];
+        *count = count.saturating_add(1);
+    }
+
+    pub fn reads(&self, addr: u16) -> u32 {
+        self.reads[addr as usize]
+    }
+
+    pub fn writes(&self, addr: u16) -> u32 {
+        self.writes[addr as usize]
+    }
+
+    /// Starts over, e.g. to begin a new capture window
+    pub fn clear(&mut self) {
+        self.reads.fill(0);
+        self.writes.fill(0);
+    }
+
+    /// Totals for every region in [`MEM_REGIONS`]
+    pub fn regions(&self) -> Vec<RegionStats> {
+        MEM_REGIONS
+            .iter()
+            .map(|&region| {
+                let addrs = region.start as usize..=region.end as usize;
+
+                let mut stats = RegionStats {
+                    region,
+                    reads: 0,
+                    writes: 0,
+                    addrs_touched: 0,
+                };
+
+                for (&reads, &writes) in self.reads[addrs.clone()].iter().zip(&self.writes[addrs]) {
+                    stats.reads += reads as u64;
+                    stats.writes += writes as u64;
+                    stats.addrs_touched += (reads > 0 || writes > 0) as u32;
+                }
+
+                stats
+            })
+            .collect()
+    }
+
+    /// A [`HEATMAP_SIZE`]² image with one pixel per address, row by row. Reads are
+    /// green, writes are red (so addresses that are both read and written are yellow),
+    /// and addresses that were never accessed are black. The brightness grows with the
+    /// logarithm of the count, since a few addresses (like the stack or the register
+    /// that games poll while waiting for VBlank) are accessed way more than the rest.
+    pub fn heatmap(&self) -> Vec<MemPixel> {
+        let max_log = |counts: &[u32]| (*counts.iter().max().unwrap_or(&0) as f32).ln_1p();
+        let (max_reads, max_writes) = (max_log(&self.reads), max_log(&self.writes));
+
+        let brightness = |count: u32, max: f32| {
+            if count == 0 {
+                0
+            } else {
+                // Even a single access should be clearly visible
+                (64.0 + 191.0 * (count as f32).ln_1p() / max) as u8
+            }
+        };
+
+        self.reads
+            .iter()
+            .zip(self.writes.iter())
+            .map(|(&reads, &writes)| {
+                MemPixel::new(
+                    brightness(writes, max_writes),
+                    brightness(reads, max_reads),
+                    0,
+                    255,
+                )
+            })
+            .collect()
+    }
+
+    pub fn save_heatmap<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let rgba: Vec<u8> = self
+            .heatmap()
+            .iter()
+            .flat_map(|pixel| [pixel.r, pixel.g, pixel.b, pixel.a])
+            .collect();
+
+        write_png(path.as_ref(), HEATMAP_SIZE, HEATMAP_SIZE, &rgba)
+    }
+}
+
+impl Default for MemStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,19 +1,52 @@
 //! This module is subject to heavy change in the future, so it will not be documented for now.
 
+pub mod asm;
+mod condition;
 mod cpu_debugger;
 mod dbg_instr;
+pub mod disasm;
 mod fmt;
+mod mem_map;
+mod mem_stats;
+mod ppu_inspector;
+mod profiler;
+mod snapshot;
+mod trace;
 
-use super::cpu::{ByteInstr, CBByteInstr, HaltState};
+use super::cpu::{ByteInstr, CBByteInstr, HaltState, IllegalInstr};
 use super::interrupt_system::Interrupt;
 use std::collections::VecDeque;
 
-pub use cpu_debugger::CpuDebugger;
+pub use super::ppu::Mode as PpuMode;
+pub use condition::{Condition, ParseConditionError};
+pub use cpu_debugger::{Breakpoint, CpuDebugger, DebuggerStatus};
+pub use mem_map::{describe_memory_map, memory_map_markdown, MemMapEntry};
+pub use mem_stats::{MemRegion, MemStats, RegionStats, HEATMAP_SIZE, MEM_REGIONS};
+pub use ppu_inspector::{
+    PpuInspector, SpriteInfo, TileMap, SPRITES_HEIGHT, SPRITES_WIDTH, TILE_DATA_HEIGHT,
+    TILE_DATA_WIDTH, TILE_MAP_SIZE,
+};
+pub use profiler::{BankedAddr, HotRegion, ProfileCounts, Profiler};
+pub use snapshot::{CpuStateSnapshot, PpuStateSnapshot, StateSnapshot, TimerState};
+pub use trace::{ParseTraceFormatError, TraceFormat, TraceLogger};
 
 pub const MAX_EVTS_LOGGED: usize = 50;
 
 pub trait DbgEvtSrc<T> {
     fn push(&mut self, evt: T);
+
+    /// Whether [`CpuEvt::Trace`] events should be pushed. Those are comparatively
+    /// expensive to create, so they are skipped unless a source asks for them.
+    fn is_tracing(&self) -> bool {
+        false
+    }
+
+    /// Whether events are recorded at all. The cached interpreter (see
+    /// [`crate::BlockCache`]) skips the events of instruction fetches, so it is
+    /// only used with sources that don't.
+    fn is_logging(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -26,16 +59,64 @@ pub enum CpuEvt {
     TakeJmpTo(u16),
     SkipJmpTo(u16),
     EnterHalt(HaltState),
+    IllegalInstr(IllegalInstr),
     IrEnable,
     IrDisable,
+    /// The CPU state right before an instruction is fetched. Only pushed to sources
+    /// that are tracing (see [`DbgEvtSrc::is_tracing`]).
+    Trace(CpuTrace),
+}
+
+/// A snapshot of the CPU registers (and a bit more) before an instruction executes,
+/// which is what instruction traces consist of
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CpuTrace {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    /// The address of the instruction
+    pub pc: u16,
+    /// The 4 bytes starting at PC, i.e. the instruction and whatever follows it
+    pub pcmem: [u8; 4],
+    /// See [`crate::Emulator::mcycles`]
+    pub mcycles: u64,
 }
 
-pub enum PpuEvt {}
+/// Events of the PPU, so its timeline can be lined up with the one of the CPU. Scanlines
+/// are the internal ones of the PPU, which don't always match the LY register.
+#[derive(Debug, Copy, Clone)]
+pub enum PpuEvt {
+    /// The PPU entered a mode in the given scanline
+    ModeChange(u8, PpuMode),
+    /// LY matched LYC in the given scanline. The flag tells whether this requested a
+    /// STAT interrupt.
+    LycMatch(u8, bool),
+    LcdOn,
+    /// The LCD was turned off in the given scanline
+    LcdOff(u8),
+    /// A finished frame was not handed to the frontend, which happens right after the
+    /// LCD is turned on
+    FrameSkipped,
+    /// The CPU read from VRAM or OAM while the PPU was using it (in the given mode)
+    BlockedRead(u16, PpuMode),
+    /// The CPU wrote to VRAM or OAM while the PPU was using it (in the given mode)
+    BlockedWrite(u16, PpuMode),
+}
 
 pub struct NoDbgLogger;
 
 impl<T> DbgEvtSrc<T> for NoDbgLogger {
     fn push(&mut self, _evt: T) {}
+
+    fn is_logging(&self) -> bool {
+        false
+    }
 }
 
 pub struct DbgEvtLogger<T>(VecDeque<T>);
@@ -0,0 +1,297 @@
+//! Renders the contents of video memory (tiles, tile maps and sprites) into pixel
+//! buffers, for finding out why a game looks the way it does. See [`PpuInspector`].
+
+use crate::address::{PpuReg, VideoMemAddr};
+use crate::ppu::{Color, PPU};
+use crate::util::BitOps;
+use crate::MemPixel;
+
+/// Width of [`PpuInspector::render_tile_data`]: 16 tiles per row
+pub const TILE_DATA_WIDTH: usize = 16 * 8;
+/// Height of [`PpuInspector::render_tile_data`]: 24 rows of tiles, 384 tiles in total
+pub const TILE_DATA_HEIGHT: usize = 24 * 8;
+/// Width and height of [`PpuInspector::render_tile_map`]: 32×32 tiles
+pub const TILE_MAP_SIZE: usize = 32 * 8;
+/// Width of [`PpuInspector::render_sprites`]: 8 sprites per row
+pub const SPRITES_WIDTH: usize = 8 * 8;
+/// Height of [`PpuInspector::render_sprites`]: 5 rows of 8×16 cells
+pub const SPRITES_HEIGHT: usize = 5 * 16;
+
+/// Shows tile colors as they are stored, without any palette
+const IDENTITY_PALETTE: u8 = 0b11_10_01_00;
+
+/// Drawn around the part of the background that is on screen
+const VIEWPORT_COLOR: MemPixel = MemPixel::new(255, 0, 0, 255);
+/// Drawn around the part of the window that is on screen
+const WINDOW_COLOR: MemPixel = MemPixel::new(0, 96, 255, 255);
+/// Sprite pixels with color 0
+const TRANSPARENT: MemPixel = MemPixel::new(0, 0, 0, 0);
+
+/// One of the two tile maps
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TileMap {
+    /// 0x9800 - 0x9BFF
+    Low,
+    /// 0x9C00 - 0x9FFF
+    High,
+}
+
+impl TileMap {
+    /// Offset from the beginning of the tile maps
+    fn offset(self) -> u16 {
+        match self {
+            TileMap::Low => 0,
+            TileMap::High => 0x400,
+        }
+    }
+}
+
+/// An OAM entry, decoded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SpriteInfo {
+    /// Position in OAM (0-39)
+    pub index: u8,
+    /// Screen position of the top left corner, i.e. the OAM values minus 8 and 16
+    pub x: i16,
+    pub y: i16,
+    pub tile: u8,
+    /// Whether OBP1 is used instead of OBP0
+    pub obp1: bool,
+    pub x_flipped: bool,
+    pub y_flipped: bool,
+    /// Whether background colors 1-3 are drawn over the sprite
+    pub behind_bg: bool,
+}
+
+impl SpriteInfo {
+    /// Whether any part of the sprite is on screen (with the current sprite size)
+    pub fn is_on_screen(&self, height: u8) -> bool {
+        self.x > -8 && self.x < 160 && self.y > -(height as i16) && self.y < 144
+    }
+}
+
+/// Looks at the video memory of an emulator (see [`crate::Emulator::ppu_inspector`])
+/// regardless of the PPU mode, so nothing it does has side effects.
+///
+/// The `render_*` methods write into buffers that the caller provides (so they can be
+/// reused every frame), and panic if the buffer doesn't have the documented size.
+/// Background and window colors use BGP, sprite colors use OBP0 or OBP1.
+pub struct PpuInspector<'a> {
+    ppu: &'a PPU,
+}
+
+impl<'a> PpuInspector<'a> {
+    pub(crate) fn new(ppu: &'a PPU) -> Self {
+        PpuInspector { ppu }
+    }
+
+    /// All 384 tiles of 0x8000 - 0x97FF, in the order they are stored. The colors are
+    /// shown as stored (without a palette), since a tile can be used by both the
+    /// background and sprites. The buffer has to hold
+    /// [`TILE_DATA_WIDTH`]×[`TILE_DATA_HEIGHT`] pixels.
+    pub fn render_tile_data(&self, buf: &mut [MemPixel]) {
+        assert_eq!(
+            buf.len(),
+            TILE_DATA_WIDTH * TILE_DATA_HEIGHT,
+            "Invalid buffer size"
+        );
+
+        for (y, row) in buf.chunks_exact_mut(TILE_DATA_WIDTH).enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let tile = (y / 8 * 16 + x / 8) as u16;
+                let color = self.tile_pixel(tile, x as u8 % 8, y as u8 % 8);
+
+                *pixel = self.shade(IDENTITY_PALETTE, color);
+            }
+        }
+    }
+
+    /// A whole tile map, with the tile data addressing mode that is currently selected
+    /// in LCDC. If the map is currently used for the background, the part that's on
+    /// screen (SCX/SCY) is outlined in red. If it's used for the window, the part of it
+    /// that's on screen (WX/WY) is outlined in blue. The buffer has to hold
+    /// [`TILE_MAP_SIZE`]² pixels.
+    pub fn render_tile_map(&self, map: TileMap, buf: &mut [MemPixel]) {
+        assert_eq!(
+            buf.len(),
+            TILE_MAP_SIZE * TILE_MAP_SIZE,
+            "Invalid buffer size"
+        );
+
+        let lcdc = self.reg(PpuReg::LCDC);
+        let bgp = self.reg(PpuReg::BGP);
+
+        for (y, row) in buf.chunks_exact_mut(TILE_MAP_SIZE).enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let tile_id = self.ppu.peek_video_mem(VideoMemAddr::TileMaps(
+                    map.offset() + (y / 8 * 32 + x / 8) as u16,
+                ));
+
+                let tile = if lcdc.bit(4) {
+                    tile_id as u16
+                } else {
+                    // Signed ids relative to 0x9000
+                    (256 + tile_id as i8 as i16) as u16
+                };
+
+                *pixel = self.shade(bgp, self.tile_pixel(tile, x as u8 % 8, y as u8 % 8));
+            }
+        }
+
+        let bg_map = if lcdc.bit(3) {
+            TileMap::High
+        } else {
+            TileMap::Low
+        };
+
+        let wnd_map = if lcdc.bit(6) {
+            TileMap::High
+        } else {
+            TileMap::Low
+        };
+
+        if lcdc.bit(0) && map == bg_map {
+            let (scx, scy) = (self.reg(PpuReg::SCX), self.reg(PpuReg::SCY));
+            draw_outline(buf, scx, scy, 160, 144, VIEWPORT_COLOR);
+        }
+
+        let (wx, wy) = (self.reg(PpuReg::WX), self.reg(PpuReg::WY));
+
+        if lcdc.bit(5) && map == wnd_map && wx <= 166 && wy <= 143 {
+            // The window always starts at its top left corner, no matter where it is
+            // on screen
+            let width = 160 - (wx as usize).saturating_sub(7);
+            draw_outline(buf, 0, 0, width, 144 - wy as usize, WINDOW_COLOR);
+        }
+    }
+
+    /// All 40 OAM entries in order
+    pub fn sprites(&self) -> Vec<SpriteInfo> {
+        (0..40)
+            .map(|index| {
+                let byte = |offset| {
+                    self.ppu
+                        .peek_video_mem(VideoMemAddr::OAM(index as u16 * 4 + offset))
+                };
+
+                let flags = byte(3);
+
+                SpriteInfo {
+                    index,
+                    x: byte(1) as i16 - 8,
+                    y: byte(0) as i16 - 16,
+                    tile: byte(2),
+                    obp1: flags.bit(4),
+                    x_flipped: flags.bit(5),
+                    y_flipped: flags.bit(6),
+                    behind_bg: flags.bit(7),
+                }
+            })
+            .collect()
+    }
+
+    /// The height of sprites (8 or 16), as selected in LCDC
+    pub fn sprite_height(&self) -> u8 {
+        if self.reg(PpuReg::LCDC).bit(2) {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// All 40 sprites, as they are drawn (flipped and with their palette), in a grid
+    /// of 8×16 cells. With 8×8 sprites, the bottom half of each cell stays empty.
+    /// Transparent pixels have an alpha value of 0. The buffer has to hold
+    /// [`SPRITES_WIDTH`]×[`SPRITES_HEIGHT`] pixels.
+    pub fn render_sprites(&self, buf: &mut [MemPixel]) {
+        assert_eq!(
+            buf.len(),
+            SPRITES_WIDTH * SPRITES_HEIGHT,
+            "Invalid buffer size"
+        );
+
+        buf.fill(TRANSPARENT);
+
+        let height = self.sprite_height();
+
+        for sprite in self.sprites() {
+            let palette = if sprite.obp1 {
+                self.reg(PpuReg::OBP1)
+            } else {
+                self.reg(PpuReg::OBP0)
+            };
+
+            let cell_x = sprite.index as usize % 8 * 8;
+            let cell_y = sprite.index as usize / 8 * 16;
+
+            for y in 0..height {
+                for x in 0..8 {
+                    let src_x = if sprite.x_flipped { 7 - x } else { x };
+                    let src_y = if sprite.y_flipped { height - 1 - y } else { y };
+
+                    // 8×16 sprites ignore the lowest bit of the tile id
+                    let tile = if height == 16 {
+                        (sprite.tile & 0xFE) as u16 + src_y as u16 / 8
+                    } else {
+                        sprite.tile as u16
+                    };
+
+                    let color = self.tile_pixel(tile, src_x, src_y % 8);
+
+                    if !color.is_zero() {
+                        buf[(cell_y + y as usize) * SPRITES_WIDTH + cell_x + x as usize] =
+                            self.shade(palette, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The color of a pixel of the tile with the given index (0-383)
+    fn tile_pixel(&self, tile: u16, x: u8, y: u8) -> Color {
+        let row_addr = tile * 16 + y as u16 * 2;
+        let lower = self.ppu.peek_video_mem(VideoMemAddr::TileData(row_addr));
+        let upper = self
+            .ppu
+            .peek_video_mem(VideoMemAddr::TileData(row_addr + 1));
+
+        let bit = 7 - x;
+        Color::from_u8_lsb((((upper >> bit) & 1) << 1) | ((lower >> bit) & 1))
+    }
+
+    fn reg(&self, reg: PpuReg) -> u8 {
+        self.ppu.read_reg(reg)
+    }
+
+    /// Applies a palette register and the DMG palette of the emulator
+    fn shade(&self, palette: u8, color: Color) -> MemPixel {
+        let shade = (palette >> (2 * color.into_raw())) & 0b11;
+        self.ppu.dmg_palette()[shade as usize]
+    }
+}
+
+/// Outlines a rectangle on a tile map, wrapping around the edges like the PPU does
+fn draw_outline(
+    buf: &mut [MemPixel],
+    left: u8,
+    top: u8,
+    width: usize,
+    height: usize,
+    color: MemPixel,
+) {
+    let mut plot = |x: usize, y: usize| {
+        let x = (left as usize + x) % TILE_MAP_SIZE;
+        let y = (top as usize + y) % TILE_MAP_SIZE;
+        buf[y * TILE_MAP_SIZE + x] = color;
+    };
+
+    for x in 0..width {
+        plot(x, 0);
+        plot(x, height - 1);
+    }
+
+    for y in 0..height {
+        plot(0, y);
+        plot(width - 1, y);
+    }
+}
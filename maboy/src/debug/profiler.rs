@@ -0,0 +1,191 @@
+//! Counts which instructions the CPU executes and how much time they take, to find the
+//! hot loops and functions of a game. Enabled via [`crate::Emulator::start_profiler`].
+//!
+//! Addresses are keyed by their bank (see [`BankedAddr`]), so code in different ROM
+//! banks that is mapped to the same address doesn't get mixed up.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/// An address together with the bank that was mapped there when the CPU executed it.
+/// The bank is the ROM bank for 0x4000-0x7FFF, the RAM bank for cartridge RAM, and 0
+/// everywhere else.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BankedAddr {
+    pub bank: u16,
+    pub addr: u16,
+}
+
+/// `01:4000`
+impl fmt::Display for BankedAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02X}:{:04X}", self.bank, self.addr)
+    }
+}
+
+/// The counts of a single instruction, or of a [`HotRegion`]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ProfileCounts {
+    /// How often the CPU executed the instruction(s)
+    pub execs: u64,
+    /// Machine cycles from the start of the instruction until the CPU fetched the next
+    /// one. This includes the time spent in HALT and dispatching interrupts afterwards,
+    /// so idle loops show up as hot.
+    pub mcycles: u64,
+}
+
+impl ProfileCounts {
+    fn add(&mut self, other: ProfileCounts) {
+        self.execs += other.execs;
+        self.mcycles += other.mcycles;
+    }
+}
+
+/// A stretch of code that was executed without gaps, which is usually a loop or (part
+/// of) a function. See [`Profiler::hot_regions`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HotRegion {
+    pub start: BankedAddr,
+    /// The address of the last instruction in the region
+    pub end: BankedAddr,
+    pub counts: ProfileCounts,
+}
+
+/// Per-instruction execution counts, see the [module documentation](self)
+#[derive(Clone, Default)]
+pub struct Profiler {
+    counts: HashMap<BankedAddr, ProfileCounts>,
+    /// The instruction that is currently executing and the cycle it started at. It is
+    /// counted once the next instruction is fetched.
+    current: Option<(BankedAddr, u64)>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Called right before the CPU fetches the instruction at `addr`
+    pub(crate) fn record_fetch(&mut self, addr: BankedAddr, mcycles: u64) {
+        if let Some((prev_addr, start)) = self.current.replace((addr, mcycles)) {
+            self.counts
+                .entry(prev_addr)
+                .or_default()
+                .add(ProfileCounts {
+                    execs: 1,
+                    mcycles: mcycles - start,
+                });
+        }
+    }
+
+    /// Starts over, e.g. to begin a new capture window
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.current = None;
+    }
+
+    /// The total over all instructions
+    pub fn total(&self) -> ProfileCounts {
+        let mut total = ProfileCounts::default();
+
+        for counts in self.counts.values() {
+            total.add(*counts);
+        }
+
+        total
+    }
+
+    /// Every executed instruction, hottest (by machine cycles) first
+    pub fn instrs(&self) -> Vec<(BankedAddr, ProfileCounts)> {
+        let mut instrs: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(&addr, &counts)| (addr, counts))
+            .collect();
+
+        instrs.sort_by(|a, b| b.1.mcycles.cmp(&a.1.mcycles).then(a.0.cmp(&b.0)));
+        instrs
+    }
+
+    /// Groups the executed instructions into regions without gaps (instructions are at
+    /// most 3 bytes long, so that is the largest distance between neighbours), hottest
+    /// first
+    pub fn hot_regions(&self) -> Vec<HotRegion> {
+        let mut instrs = self.instrs();
+        instrs.sort_by_key(|(addr, _)| *addr);
+
+        let mut regions: Vec<HotRegion> = Vec::new();
+
+        for (addr, counts) in instrs {
+            match regions.last_mut() {
+                Some(region)
+                    if region.end.bank == addr.bank
+                        && addr.addr.wrapping_sub(region.end.addr) <= 3 =>
+                {
+                    region.end = addr;
+                    region.counts.add(counts);
+                }
+                _ => regions.push(HotRegion {
+                    start: addr,
+                    end: addr,
+                    counts,
+                }),
+            }
+        }
+
+        regions.sort_by_key(|region| Reverse(region.counts.mcycles));
+        regions
+    }
+
+    /// Writes the hottest `n` regions and instructions as a plain text report
+    pub fn write_report<W: Write>(&self, mut writer: W, n: usize) -> io::Result<()> {
+        let total = self.total().mcycles.max(1) as f64;
+
+        writeln!(writer, "Hottest regions:")?;
+
+        for region in self.hot_regions().iter().take(n) {
+            writeln!(
+                writer,
+                "  {}-{}: {:>5.1}% ({} mcycles, {} instructions executed)",
+                region.start,
+                region.end,
+                region.counts.mcycles as f64 / total * 100.0,
+                region.counts.mcycles,
+                region.counts.execs
+            )?;
+        }
+
+        writeln!(writer, "Hottest instructions:")?;
+
+        for (addr, counts) in self.instrs().iter().take(n) {
+            writeln!(
+                writer,
+                "  {}: {:>5.1}% ({} mcycles, executed {} times)",
+                addr,
+                counts.mcycles as f64 / total * 100.0,
+                counts.mcycles,
+                counts.execs
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every instruction as CSV (`bank,addr,execs,mcycles`), hottest first, so
+    /// it can be analyzed with other tools
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "bank,addr,execs,mcycles")?;
+
+        for (addr, counts) in self.instrs() {
+            writeln!(
+                writer,
+                "{:02X},{:04X},{},{}",
+                addr.bank, addr.addr, counts.execs, counts.mcycles
+            )?;
+        }
+
+        Ok(())
+    }
+}
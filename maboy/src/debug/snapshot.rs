@@ -0,0 +1,83 @@
+//! Plain copies of the emulator state that are cheap to take and easy to show outside of
+//! the emulator, e.g. in a dashboard. With the `serde` feature, all of them implement
+//! `serde::Serialize` (see also [`crate::state_server`]).
+
+use super::PpuMode;
+use crate::{BankState, IllegalInstr};
+
+/// The CPU registers and execution state, see [`crate::Emulator::cpu_state`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CpuStateSnapshot {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    /// Interrupt master enable
+    pub ime: bool,
+    /// Waiting for an interrupt after HALT
+    pub halted: bool,
+    /// Waiting for a button press after STOP
+    pub stopped: bool,
+    /// The illegal instruction that got the CPU stuck, if any
+    pub illegal_instr: Option<IllegalInstr>,
+    /// See [`crate::Emulator::mcycles`]
+    pub mcycles: u64,
+}
+
+/// The PPU registers and where the PPU is within the frame, see
+/// [`crate::Emulator::ppu_state`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PpuStateSnapshot {
+    pub mode: PpuMode,
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+    /// The scanline the PPU is actually in, which doesn't always match LY
+    pub scanline: u8,
+    /// The machine cycle within the scanline (0-113)
+    pub scanline_mcycle: u8,
+    /// The line of the window that is drawn next, or `None` if WY didn't match LY in
+    /// the current frame yet. Only increases on lines where the window is visible.
+    pub window_line: Option<u8>,
+    /// How many sprites in the current scanline exceed the limit of 10 sprites per line
+    /// and are not drawn
+    pub dropped_sprites: u8,
+}
+
+/// The timer registers, see [`crate::Emulator::timer_state`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TimerState {
+    pub div: u8,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+    /// The whole 16-bit system counter, of which DIV is the upper half
+    pub div_internal: u16,
+}
+
+/// Everything above in one go, see [`crate::Emulator::state_snapshot`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StateSnapshot {
+    pub cpu: CpuStateSnapshot,
+    pub ppu: PpuStateSnapshot,
+    pub timer: TimerState,
+    pub banks: BankState,
+}
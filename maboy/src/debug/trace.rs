@@ -0,0 +1,188 @@
+//! Instruction traces: The CPU state before every executed instruction, streamed to a
+//! writer. The text format is the one used by
+//! [Gameboy Doctor](https://github.com/robert/gameboy-doctor), so traces can be diffed
+//! against other emulators:
+//!
+//! ```text
+//! A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//! ```
+//!
+//! MaBoy runs the boot ROM, so a trace only lines up with those of other emulators from
+//! `PC:0100` onwards.
+
+use super::{CpuEvt, CpuTrace, DbgEvtSrc};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// Exactly the Gameboy Doctor format
+    Doctor,
+    /// The Gameboy Doctor format with the machine cycle count appended (` CY:<mcycles>`)
+    Text,
+    /// The magic bytes `MBTR`, followed by 24 bytes per instruction: PC, SP (u16), A, F,
+    /// B, C, D, E, H, L, the 4 PCMEM bytes and the machine cycle count (u64). Everything
+    /// is little-endian.
+    Binary,
+}
+
+#[derive(Debug)]
+pub struct ParseTraceFormatError(pub String);
+
+impl TraceFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            TraceFormat::Doctor => "doctor",
+            TraceFormat::Text => "text",
+            TraceFormat::Binary => "binary",
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = ParseTraceFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "doctor" => Ok(TraceFormat::Doctor),
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(ParseTraceFormatError(s.to_owned())),
+        }
+    }
+}
+
+/// Identifies a binary trace
+const MAGIC: &[u8; 4] = b"MBTR";
+
+/// Writes every executed instruction to a writer (see the [module documentation](self)).
+/// Pass it to [`crate::Emulator::with_debugger`] as the CPU logger.
+///
+/// Since events can't fail, the first write error stops the trace. It is logged, and
+/// returned by the next call of [`TraceLogger::flush`].
+pub struct TraceLogger<W: Write> {
+    writer: BufWriter<W>,
+    format: TraceFormat,
+    instrs: u64,
+    stopped: bool,
+    /// The error that stopped the trace, until it is reported by `flush`
+    error: Option<io::Error>,
+}
+
+impl TraceLogger<File> {
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Self> {
+        Ok(TraceLogger::new(File::create(path)?, format))
+    }
+}
+
+impl<W: Write> TraceLogger<W> {
+    pub fn new(writer: W, format: TraceFormat) -> Self {
+        let mut logger = TraceLogger {
+            writer: BufWriter::new(writer),
+            format,
+            instrs: 0,
+            stopped: false,
+            error: None,
+        };
+
+        if format == TraceFormat::Binary {
+            if let Err(err) = logger.writer.write_all(MAGIC) {
+                logger.stop(err);
+            }
+        }
+
+        logger
+    }
+
+    /// The number of instructions that were traced so far
+    pub fn instructions(&self) -> u64 {
+        self.instrs
+    }
+
+    /// Writes out everything that is still buffered. Call this before looking at the
+    /// trace while the emulator is still running; Dropping the logger flushes as well,
+    /// but ignores errors.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        self.writer.flush()
+    }
+
+    fn stop(&mut self, err: io::Error) {
+        log::error!("Instruction trace stopped: {}", err);
+        self.stopped = true;
+        self.error = Some(err);
+    }
+
+    fn write(&mut self, trace: &CpuTrace) -> io::Result<()> {
+        let w = &mut self.writer;
+
+        match self.format {
+            TraceFormat::Doctor | TraceFormat::Text => {
+                write!(
+                    w,
+                    "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                    trace.a,
+                    trace.f,
+                    trace.b,
+                    trace.c,
+                    trace.d,
+                    trace.e,
+                    trace.h,
+                    trace.l,
+                    trace.sp,
+                    trace.pc,
+                    trace.pcmem[0],
+                    trace.pcmem[1],
+                    trace.pcmem[2],
+                    trace.pcmem[3]
+                )?;
+
+                if self.format == TraceFormat::Text {
+                    write!(w, " CY:{}", trace.mcycles)?;
+                }
+
+                writeln!(w)
+            }
+            TraceFormat::Binary => {
+                w.write_all(&trace.pc.to_le_bytes())?;
+                w.write_all(&trace.sp.to_le_bytes())?;
+                w.write_all(&[
+                    trace.a, trace.f, trace.b, trace.c, trace.d, trace.e, trace.h, trace.l,
+                ])?;
+                w.write_all(&trace.pcmem)?;
+                w.write_all(&trace.mcycles.to_le_bytes())
+            }
+        }
+    }
+}
+
+impl<W: Write> DbgEvtSrc<CpuEvt> for TraceLogger<W> {
+    fn push(&mut self, evt: CpuEvt) {
+        if let CpuEvt::Trace(trace) = evt {
+            if self.stopped {
+                return;
+            }
+
+            match self.write(&trace) {
+                Ok(()) => self.instrs += 1,
+                Err(err) => self.stop(err),
+            }
+        }
+    }
+
+    fn is_tracing(&self) -> bool {
+        !self.stopped
+    }
+}
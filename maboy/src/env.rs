@@ -0,0 +1,326 @@
+//! A gym-style environment for reinforcement learning and bots: Every [`Env::step`]
+//! holds down some buttons for a few frames and returns what the agent observes, how
+//! much reward it earned and whether the episode is over.
+//!
+//! Rewards and episode ends are defined via game variables (see [`crate::gamedb`]), so
+//! most experiments don't need any glue code:
+//!
+//! ```no_run
+//! # use maboy::{env::{Env, ObsKind}, gamedb::GameDb, Buttons, CartridgeVariant};
+//! # fn main() -> Result<(), maboy::CartridgeParseError> {
+//! let mut env = Env::new(CartridgeVariant::from_file("pokemon_red.gb")?);
+//! env.emulator_mut().set_game_db(Some(GameDb::load("pokemon_red.txt").unwrap()));
+//!
+//! env.set_obs_kind(ObsKind::Downsampled(4));
+//! env.add_var_reward("money", 0.01).unwrap();
+//! env.add_done_condition("badges >= 1").unwrap();
+//!
+//! let mut obs = env.reset();
+//!
+//! loop {
+//!     let step = env.step(Buttons::A);
+//!     obs = step.observation;
+//!
+//!     if step.done || step.truncated {
+//!         break;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Episodes start from a savestate that is captured when the environment is created
+//! (or set via [`Env::set_initial_state`]), so every episode starts the same way and
+//! the same actions always lead to the same observations.
+
+use crate::autosplit::{AutoSplitError, Condition};
+use crate::debug::{CpuEvt, DbgEvtSrc, NoDbgLogger, PpuEvt};
+use crate::gamedb::{GameDb, GameVar, GameVarError};
+use crate::headless::{HeadlessRunner, FRAME_HEIGHT, FRAME_WIDTH};
+use crate::pixel_format;
+use crate::{Buttons, Cartridge, Emulator, SaveStateError};
+use std::ops::RangeInclusive;
+
+/// What [`Env::reset`] and [`Env::step`] return as the observation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObsKind {
+    /// The whole frame as RGBA bytes, row by row
+    Frame,
+    /// The frame in grayscale, with every square of `n * n` pixels averaged into one
+    /// byte. `n` has to divide both the width and the height of the frame (1, 2, 4, 8
+    /// or 16).
+    Downsampled(usize),
+    /// The bytes at these addresses (see [`Emulator::peek`]), one range after another
+    Memory(Vec<RangeInclusive<u16>>),
+}
+
+impl ObsKind {
+    /// The dimensions of an observation, like numpy's `shape`: `[height, width, 4]`,
+    /// `[height, width]` or `[bytes]`
+    pub fn shape(&self) -> Vec<usize> {
+        match self {
+            ObsKind::Frame => vec![FRAME_HEIGHT, FRAME_WIDTH, 4],
+            ObsKind::Downsampled(n) => vec![FRAME_HEIGHT / n, FRAME_WIDTH / n],
+            ObsKind::Memory(ranges) => vec![ranges.iter().map(|range| range.clone().count()).sum()],
+        }
+    }
+}
+
+/// The result of [`Env::step`]
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub observation: Vec<u8>,
+    pub reward: f64,
+    /// One of the done conditions became true (see [`Env::add_done_condition`])
+    pub done: bool,
+    /// The episode reached the step limit (see [`Env::set_max_steps`])
+    pub truncated: bool,
+}
+
+/// Rewards the change of a game variable since the last step
+struct VarReward {
+    var: GameVar,
+    scale: f64,
+    last: i64,
+}
+
+type RewardFn<C, CpuDbg, PpuDbg> = Box<dyn FnMut(&Emulator<C, CpuDbg, PpuDbg>) -> f64>;
+
+/// Runs episodes of a game for an agent. See the [module documentation](self).
+pub struct Env<C, CpuDbg = NoDbgLogger, PpuDbg = NoDbgLogger> {
+    runner: HeadlessRunner<C, CpuDbg, PpuDbg>,
+    initial_state: Vec<u8>,
+    obs_kind: ObsKind,
+    frames_per_step: u32,
+    max_steps: Option<u64>,
+    steps: u64,
+    var_rewards: Vec<VarReward>,
+    reward_fn: Option<RewardFn<C, CpuDbg, PpuDbg>>,
+    done_conditions: Vec<Condition>,
+}
+
+impl<C: Cartridge> Env<C> {
+    pub fn new(cartridge: C) -> Self {
+        Self::from_emulator(Emulator::new(cartridge))
+    }
+}
+
+impl<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>> Env<C, CpuDbg, PpuDbg> {
+    /// Wraps an existing emulator. Its current state becomes the start of every episode.
+    pub fn from_emulator(emu: Emulator<C, CpuDbg, PpuDbg>) -> Self {
+        Env {
+            initial_state: emu.save_state(),
+            runner: HeadlessRunner::from_emulator(emu),
+            obs_kind: ObsKind::Frame,
+            frames_per_step: 4,
+            max_steps: None,
+            steps: 0,
+            var_rewards: Vec::new(),
+            reward_fn: None,
+            done_conditions: Vec::new(),
+        }
+    }
+
+    pub fn emulator(&self) -> &Emulator<C, CpuDbg, PpuDbg> {
+        self.runner.emulator()
+    }
+
+    /// Use this to install a [`GameDb`] before adding rewards or done conditions
+    pub fn emulator_mut(&mut self) -> &mut Emulator<C, CpuDbg, PpuDbg> {
+        self.runner.emulator_mut()
+    }
+
+    pub fn into_emulator(self) -> Emulator<C, CpuDbg, PpuDbg> {
+        self.runner.into_emulator()
+    }
+
+    /// Replaces the savestate that every episode starts from, e.g. one that skips the
+    /// title screen. Fails if the savestate doesn't fit the cartridge.
+    pub fn set_initial_state(&mut self, state: Vec<u8>) -> Result<(), SaveStateError> {
+        self.runner.emulator_mut().load_state(&state)?;
+        self.initial_state = state;
+        Ok(())
+    }
+
+    /// Panics if a [`ObsKind::Downsampled`] factor doesn't divide the frame size
+    pub fn set_obs_kind(&mut self, obs_kind: ObsKind) {
+        if let ObsKind::Downsampled(n) = obs_kind {
+            assert!(
+                n > 0 && FRAME_WIDTH.is_multiple_of(n) && FRAME_HEIGHT.is_multiple_of(n),
+                "Frame size is not divisible by {}",
+                n
+            );
+        }
+
+        self.obs_kind = obs_kind;
+    }
+
+    pub fn obs_kind(&self) -> &ObsKind {
+        &self.obs_kind
+    }
+
+    /// How many frames every action is held for (4 by default). Rewards and done
+    /// conditions are only checked after the last one.
+    pub fn set_frames_per_step(&mut self, frames: u32) {
+        self.frames_per_step = frames.max(1);
+    }
+
+    pub fn frames_per_step(&self) -> u32 {
+        self.frames_per_step
+    }
+
+    /// Ends episodes after this many steps (via [`Step::truncated`]), or never if `None`
+    /// is passed (the default)
+    pub fn set_max_steps(&mut self, max_steps: Option<u64>) {
+        self.max_steps = max_steps;
+    }
+
+    /// The number of steps in the current episode
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Rewards every change of the game variable `name` since the last step, multiplied
+    /// by `scale` (which can be negative for things the agent should avoid, like lost
+    /// health). The variable is looked up in the [`GameDb`] of the emulator.
+    pub fn add_var_reward(&mut self, name: &str, scale: f64) -> Result<(), GameVarError> {
+        let var = self
+            .emulator()
+            .game_db()
+            .ok_or(GameVarError::NoGameDb)?
+            .get(name)
+            .cloned()
+            .ok_or_else(|| GameVarError::UnknownVar(name.to_owned()))?;
+
+        let last = var.read(|addr| self.emulator().peek(addr));
+
+        self.var_rewards.push(VarReward { var, scale, last });
+        Ok(())
+    }
+
+    /// Adds the return value of `reward_fn` to the reward of every step, for anything
+    /// that game variables can't express. Replaces any previously set function.
+    pub fn set_reward_fn<F>(&mut self, reward_fn: F)
+    where
+        F: FnMut(&Emulator<C, CpuDbg, PpuDbg>) -> f64 + 'static,
+    {
+        self.reward_fn = Some(Box::new(reward_fn));
+    }
+
+    /// Ends the episode (via [`Step::done`]) as soon as the condition is true. It has
+    /// the format of the conditions in a splits file (see [`crate::autosplit`]), e.g.
+    /// `map_id == 118 && player_x > 3`, and refers to variables in the [`GameDb`] of the
+    /// emulator. Any one of multiple conditions ends the episode.
+    pub fn add_done_condition(&mut self, condition: &str) -> Result<(), AutoSplitError> {
+        let empty_db = GameDb::new();
+        let game_db = self.emulator().game_db().unwrap_or(&empty_db);
+
+        let condition = Condition::parse(condition, game_db)?;
+
+        self.done_conditions.push(condition);
+        Ok(())
+    }
+
+    /// Removes all rewards and done conditions
+    pub fn clear_hooks(&mut self) {
+        self.var_rewards.clear();
+        self.reward_fn = None;
+        self.done_conditions.clear();
+    }
+
+    /// Starts a new episode from the initial state and returns the first observation.
+    /// A single frame is run without any buttons pressed, so the observation shows the
+    /// game instead of an empty frame.
+    pub fn reset(&mut self) -> Vec<u8> {
+        self.runner
+            .emulator_mut()
+            .load_state(&self.initial_state)
+            .expect("Initial state of environment could not be loaded");
+
+        self.runner
+            .emulator_mut()
+            .notify_buttons_state(Buttons::empty());
+        self.runner.run_frame();
+
+        self.steps = 0;
+
+        for reward in &mut self.var_rewards {
+            let emu = self.runner.emulator();
+            reward.last = reward.var.read(|addr| emu.peek(addr));
+        }
+
+        self.observation()
+    }
+
+    /// Holds down `action` (and releases all other buttons) for
+    /// [`Env::frames_per_step`] frames and evaluates the result
+    pub fn step(&mut self, action: Buttons) -> Step {
+        self.runner.emulator_mut().notify_buttons_state(action);
+        self.runner.run_frames(self.frames_per_step as u64);
+
+        self.steps += 1;
+
+        let emu = self.runner.emulator();
+        let peek = |addr| emu.peek(addr);
+
+        let mut reward = 0.0;
+
+        for var_reward in &mut self.var_rewards {
+            let val = var_reward.var.read(peek);
+            reward += (val - var_reward.last) as f64 * var_reward.scale;
+            var_reward.last = val;
+        }
+
+        if let Some(reward_fn) = self.reward_fn.as_mut() {
+            reward += reward_fn(emu);
+        }
+
+        let done = self
+            .done_conditions
+            .iter()
+            .any(|condition| condition.eval(peek));
+
+        let truncated = self.max_steps.is_some_and(|max| self.steps >= max);
+
+        Step {
+            observation: self.observation(),
+            reward,
+            done,
+            truncated,
+        }
+    }
+
+    /// The observation of the current state, as configured via [`Env::set_obs_kind`]
+    pub fn observation(&self) -> Vec<u8> {
+        match &self.obs_kind {
+            ObsKind::Frame => pixel_format::as_rgba8(self.runner.frame()).to_vec(),
+            ObsKind::Downsampled(n) => downsample(pixel_format::as_rgba8(self.runner.frame()), *n),
+            ObsKind::Memory(ranges) => {
+                let emu = self.runner.emulator();
+
+                ranges
+                    .iter()
+                    .flat_map(|range| range.clone().map(|addr| emu.peek(addr)))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Averages squares of `n * n` pixels of an RGBA frame into grayscale bytes
+fn downsample(rgba: &[u8], n: usize) -> Vec<u8> {
+    let (width, height) = (FRAME_WIDTH / n, FRAME_HEIGHT / n);
+    let mut sums = vec![0u32; width * height];
+
+    for (idx, px) in rgba.chunks_exact(4).enumerate() {
+        let (x, y) = (idx % FRAME_WIDTH, idx / FRAME_WIDTH);
+
+        // Integer approximation of the Rec. 601 luma
+        let luma = (77 * px[0] as u32 + 150 * px[1] as u32 + 29 * px[2] as u32) >> 8;
+
+        sums[y / n * width + x / n] += luma;
+    }
+
+    let count = (n * n) as u32;
+    sums.into_iter().map(|sum| (sum / count) as u8).collect()
+}
@@ -0,0 +1,218 @@
+//! Writes consecutive frames to disk, so the rendering output can be analyzed with
+//! external tools. Two formats are supported:
+//!
+//! - [`DumpFormat::Npy`]: A single NumPy array file of shape `(frames, 144, 160, 4)`
+//!   with one `u8` per color channel (RGBA). The format is a short text header
+//!   followed by the densely packed pixel data, so it is easy to read without NumPy
+//!   as well. Load it with `numpy.load("dump.npy")`.
+//! - [`DumpFormat::Png`]: A directory with one PNG file per frame (`00000.png`,
+//!   `00001.png`, ...).
+//!
+//! Dumps can be written via [`crate::headless::HeadlessRunner::dump_frames`], or by
+//! feeding frames from any frontend into a [`FrameDump`].
+//!
+//! Single frames (e.g. from [`crate::Emulator::screenshot`]) are [`RgbaFrame`]s, which
+//! can be saved as PNG as well.
+
+use crate::headless::{FRAME_HEIGHT, FRAME_WIDTH};
+use crate::pixel_format;
+use crate::MemPixel;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DumpFormat {
+    Npy,
+    Png,
+}
+
+impl DumpFormat {
+    /// Parses the names used on the command line (`npy` and `png`)
+    pub fn from_name(name: &str) -> Option<DumpFormat> {
+        match name {
+            "npy" => Some(DumpFormat::Npy),
+            "png" => Some(DumpFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Size of the .npy header (including magic and version). The header is padded to
+/// a fixed size, so it can be rewritten with the actual frame count in the end.
+const NPY_HEADER_LEN: usize = 128;
+
+/// A dump in progress. Frames are written as they come in, until the requested
+/// number of frames is reached.
+pub struct FrameDump {
+    path: PathBuf,
+    target: DumpTarget,
+    frames_requested: usize,
+    frames_written: usize,
+}
+
+enum DumpTarget {
+    Npy(BufWriter<File>),
+    Png,
+}
+
+impl FrameDump {
+    /// Starts a dump of `frames` frames to `path`, which is a file for [`DumpFormat::Npy`]
+    /// and a directory (created if necessary) for [`DumpFormat::Png`].
+    pub fn start<P: AsRef<Path>>(path: P, format: DumpFormat, frames: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let target = match format {
+            DumpFormat::Npy => {
+                let mut file = BufWriter::new(File::create(&path)?);
+                file.write_all(&npy_header(frames))?;
+                DumpTarget::Npy(file)
+            }
+            DumpFormat::Png => {
+                fs::create_dir_all(&path)?;
+                DumpTarget::Png
+            }
+        };
+
+        Ok(FrameDump {
+            path,
+            target,
+            frames_requested: frames,
+            frames_written: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn frames_written(&self) -> usize {
+        self.frames_written
+    }
+
+    /// Whether all requested frames were written. Further frames are ignored.
+    pub fn is_complete(&self) -> bool {
+        self.frames_written >= self.frames_requested
+    }
+
+    /// Writes the next frame. Returns whether the dump is complete afterwards.
+    pub fn push_frame(&mut self, frame: &[MemPixel]) -> io::Result<bool> {
+        assert_eq!(
+            frame.len(),
+            FRAME_WIDTH * FRAME_HEIGHT,
+            "Invalid frame size"
+        );
+
+        if self.is_complete() {
+            return Ok(true);
+        }
+
+        let rgba = pixel_format::as_rgba8(frame);
+
+        match &mut self.target {
+            DumpTarget::Npy(file) => file.write_all(rgba)?,
+            DumpTarget::Png => {
+                let path = self.path.join(format!("{:05}.png", self.frames_written));
+                write_png(&path, FRAME_WIDTH, FRAME_HEIGHT, rgba)?;
+            }
+        }
+
+        self.frames_written += 1;
+
+        if self.is_complete() {
+            self.flush()?;
+        }
+
+        Ok(self.is_complete())
+    }
+
+    /// Ends the dump early. The .npy header is fixed up to contain the number of frames
+    /// that were actually written. Dropping the dump does the same, but ignores errors.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.frames_requested = self.frames_written;
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let DumpTarget::Npy(file) = &mut self.target {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&npy_header(self.frames_written))?;
+            file.seek(SeekFrom::End(0))?;
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for FrameDump {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            log::error!("Could not finish frame dump {:?}: {}", self.path, err);
+        }
+    }
+}
+
+/// Version 1.0 .npy header for an array of `frames` RGBA frames
+fn npy_header(frames: usize) -> Vec<u8> {
+    let dict = format!(
+        "{{'descr': '|u1', 'fortran_order': False, 'shape': ({}, {}, {}, 4), }}",
+        frames, FRAME_HEIGHT, FRAME_WIDTH
+    );
+
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&((NPY_HEADER_LEN - 10) as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+    header.resize(NPY_HEADER_LEN - 1, b' ');
+    header.push(b'\n');
+
+    header
+}
+
+/// An owned copy of a frame as RGBA8, ready to be encoded as an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaFrame {
+    pub width: usize,
+    pub height: usize,
+    /// `width * height * 4` bytes, row by row
+    pub rgba: Vec<u8>,
+}
+
+impl RgbaFrame {
+    /// Copies a frame of `FRAME_WIDTH * FRAME_HEIGHT` pixels
+    pub fn from_pixels(pixels: &[MemPixel]) -> RgbaFrame {
+        RgbaFrame {
+            width: FRAME_WIDTH,
+            height: FRAME_HEIGHT,
+            rgba: pixel_format::as_rgba8(pixels).to_vec(),
+        }
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        write_png(path.as_ref(), self.width, self.height, &self.rgba)
+    }
+
+    pub fn encode_png<W: Write>(&self, writer: W) -> io::Result<()> {
+        encode_png(writer, self.width, self.height, &self.rgba)
+    }
+}
+
+pub(crate) fn write_png(path: &Path, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    encode_png(BufWriter::new(File::create(path)?), width, height, rgba)
+}
+
+pub(crate) fn encode_png<W: Write>(
+    writer: W,
+    width: usize,
+    height: usize,
+    rgba: &[u8],
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgba))
+        .map_err(io::Error::other)
+}
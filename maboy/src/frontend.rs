@@ -0,0 +1,1295 @@
+//! Frontend logic that doesn't depend on the platform, so all frontends behave the
+//! same: Where savegames, cartridge metadata, savestates and screenshots are stored,
+//! how keys and gamepad buttons are mapped to Game Boy buttons (and where that mapping
+//! is configured, see [`InputConfig`]), which speeds the speed hotkeys step through, when frames are skipped on slow machines, what a
+//! paused game looks like, and which ROMs were opened recently (see [`RecentRoms`]).
+//!
+//! All files are stored next to the ROM, with the same name and different extensions.
+//! The functions take that name as a base path without an extension, see
+//! [`storage::save_base_path`] (which also decides whether it's the name of the ROM
+//! file or the title of the game).
+
+use crate::debug::{CpuEvt, DbgEvtSrc, PpuEvt};
+use crate::headless::{FRAME_HEIGHT, FRAME_WIDTH};
+use crate::storage::{self, ImportError, SavegameFormat};
+use crate::{
+    Buttons, Cartridge, CartridgeParseError, Emulator, FrameSkip, MemPixel, Metadata,
+    SaveStateError, Savegame,
+};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use storage::save_file_path;
+
+#[derive(Debug)]
+pub enum SaveFileError {
+    Io(io::Error),
+    Import(ImportError),
+    Metadata(CartridgeParseError),
+    SaveState(SaveStateError),
+}
+
+impl From<io::Error> for SaveFileError {
+    fn from(err: io::Error) -> Self {
+        SaveFileError::Io(err)
+    }
+}
+
+/// Loads the savegame that belongs to the ROM, if there is one. If there is no savegame
+/// of our own, we try to import a VisualBoyAdvance savestate (.sgm). Savegames of BGB
+/// and VBA are picked up as .sav files.
+pub fn load_savegame<C: Savegame + Metadata>(
+    base_path: &Path,
+    cartridge: &mut C,
+) -> Result<Option<SavegameFormat>, SaveFileError> {
+    if cartridge.savegame().is_none() && !cartridge.supports_metadata() {
+        return Ok(None);
+    }
+
+    for extension in &["sav", "sgm"] {
+        let path = save_file_path(base_path, extension);
+
+        if let Ok(data) = fs::read(&path) {
+            let format = storage::import_into(cartridge, &data).map_err(SaveFileError::Import)?;
+
+            log::info!("Loaded savegame {:?} ({:?})", path, format);
+            return Ok(Some(format));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads a boot ROM dump for [`Emulator::with_boot_rom`], which has to be exactly 256
+/// bytes long
+pub fn load_boot_rom<P: AsRef<Path>>(path: P) -> io::Result<[u8; 256]> {
+    let data = fs::read(path)?;
+
+    <[u8; 256]>::try_from(data.as_slice()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Boot ROM has {} bytes instead of 256", data.len()),
+        )
+    })
+}
+
+/// Overwrites (or creates) the .sav file with the contents of the cartridge RAM, keeping
+/// up to [`storage::DEFAULT_SAVEGAME_BACKUPS`] backups of the old ones
+pub fn store_savegame<C: Savegame>(base_path: &Path, cartridge: &C) -> Result<(), SaveFileError> {
+    store_savegame_with_backups(base_path, cartridge, storage::DEFAULT_SAVEGAME_BACKUPS)
+}
+
+/// Like [`store_savegame`], but keeps up to `backups` backups (see
+/// [`storage::backup_savegame`]). Nothing is written (and no backup is made) if the
+/// file already has the same contents.
+pub fn store_savegame_with_backups<C: Savegame>(
+    base_path: &Path,
+    cartridge: &C,
+    backups: usize,
+) -> Result<(), SaveFileError> {
+    if let Some(cram) = cartridge.savegame() {
+        let path = save_file_path(base_path, "sav");
+
+        if fs::read(&path).is_ok_and(|old| old == cram) {
+            return Ok(());
+        }
+
+        if cartridge.savegame_ram_enabled() {
+            log::warn!("The game left cartridge RAM enabled, so the savegame might be incomplete");
+        }
+
+        storage::backup_savegame(base_path, backups)?;
+        fs::write(path, cram)?;
+    }
+
+    Ok(())
+}
+
+/// Loads cartridge metadata (like the RTC state) from the .meta file, if there is one
+pub fn load_metadata<C: Metadata>(
+    base_path: &Path,
+    cartridge: &mut C,
+) -> Result<(), SaveFileError> {
+    if !cartridge.supports_metadata() {
+        return Ok(());
+    }
+
+    if let Ok(metadata) = fs::read(save_file_path(base_path, "meta")) {
+        cartridge
+            .deserialize_metadata(metadata)
+            .map_err(SaveFileError::Metadata)?;
+    }
+
+    Ok(())
+}
+
+pub fn store_metadata<C: Metadata>(base_path: &Path, cartridge: &C) -> Result<(), SaveFileError> {
+    if !cartridge.supports_metadata() {
+        return Ok(());
+    }
+
+    let metadata = cartridge
+        .serialize_metadata()
+        .map_err(SaveFileError::Metadata)?;
+
+    fs::write(save_file_path(base_path, "meta"), metadata)?;
+
+    Ok(())
+}
+
+/// How long the game has to stop writing to its savegame before [`AutoSave`] writes it
+/// to disk
+pub const AUTOSAVE_DELAY: Duration = Duration::from_secs(3);
+
+/// Writes the savegame (and metadata) to disk while the game is running, so a crash
+/// doesn't lose any progress. Games write their savegames byte by byte over several
+/// frames, so this waits until the game stopped writing for a while instead of saving
+/// after every write. If the game still has cartridge RAM enabled by then, it waits
+/// another delay (see [`Savegame::savegame_ram_enabled`]).
+pub struct AutoSave {
+    delay: Duration,
+    /// When we last saw the game write to the savegame, if that isn't on disk yet
+    last_write: Option<Instant>,
+    /// Whether we already waited an extra delay for the game to disable cartridge RAM
+    waited_for_ram: bool,
+}
+
+impl AutoSave {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            last_write: None,
+            waited_for_ram: false,
+        }
+    }
+
+    /// Needs to be called regularly (every frame is fine). Returns whether the savegame
+    /// was written. If that fails, it is tried again after another delay.
+    pub fn update<C: Savegame + Metadata>(
+        &mut self,
+        base_path: &Path,
+        cartridge: &mut C,
+    ) -> Result<bool, SaveFileError> {
+        let now = Instant::now();
+
+        if cartridge.savegame_dirty() {
+            cartridge.mark_savegame_clean();
+            self.last_write = Some(now);
+            self.waited_for_ram = false;
+        }
+
+        match self.last_write {
+            Some(last_write) if now - last_write >= self.delay => {
+                // Games usually disable RAM once they are done writing. Some never do,
+                // so we only wait for that once.
+                if cartridge.savegame_ram_enabled() && !self.waited_for_ram {
+                    self.waited_for_ram = true;
+                    self.last_write = Some(now);
+                    return Ok(false);
+                }
+
+                // Stays like this if writing fails, so we try again after another delay
+                self.last_write = Some(now);
+
+                store_savegame(base_path, cartridge)?;
+                store_metadata(base_path, cartridge)?;
+
+                self.last_write = None;
+                self.waited_for_ram = false;
+                log::info!("Savegame written to disk");
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+impl Default for AutoSave {
+    fn default() -> Self {
+        Self::new(AUTOSAVE_DELAY)
+    }
+}
+
+/// Saves a screenshot of the last completed frame next to the ROM (see
+/// [`storage::screenshot_path`]) and returns where it was written
+pub fn store_screenshot<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>(
+    base_path: &Path,
+    emu: &Emulator<C, CpuDbg, PpuDbg>,
+) -> io::Result<PathBuf> {
+    let path = storage::screenshot_path(base_path, SystemTime::now());
+    emu.screenshot().save_png(&path)?;
+
+    log::info!("Saved screenshot to {:?}", path);
+    Ok(path)
+}
+
+/// Writes a savestate to the .state file
+pub fn store_state<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>(
+    base_path: &Path,
+    emu: &Emulator<C, CpuDbg, PpuDbg>,
+) -> Result<(), SaveFileError> {
+    let path = save_file_path(base_path, "state");
+    fs::write(&path, emu.save_state())?;
+
+    log::info!("Saved state to {:?}", path);
+    Ok(())
+}
+
+/// Loads the savestate written by [`store_state`]. If that fails, the emulator is
+/// left unchanged.
+pub fn load_state<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>(
+    base_path: &Path,
+    emu: &mut Emulator<C, CpuDbg, PpuDbg>,
+) -> Result<(), SaveFileError> {
+    let path = save_file_path(base_path, "state");
+    let state = fs::read(&path)?;
+
+    emu.load_state(&state).map_err(SaveFileError::SaveState)?;
+
+    log::info!("Loaded state from {:?}", path);
+    Ok(())
+}
+
+/// The keyboard layout of all frontends, by key label
+pub const DEFAULT_KEYBOARD_LAYOUT: [(char, Buttons); 8] = [
+    ('K', Buttons::A),
+    ('J', Buttons::B),
+    ('N', Buttons::START),
+    ('B', Buttons::SELECT),
+    ('W', Buttons::UP),
+    ('D', Buttons::RIGHT),
+    ('S', Buttons::DOWN),
+    ('A', Buttons::LEFT),
+];
+
+/// The gamepad layout of all frontends that support gamepads, by the (Xbox) names of the
+/// gamepad buttons. A and B are swapped, so they sit where they are on the Game Boy.
+pub const DEFAULT_GAMEPAD_LAYOUT: [(&str, Buttons); 8] = [
+    ("B", Buttons::A),
+    ("A", Buttons::B),
+    ("START", Buttons::START),
+    ("BACK", Buttons::SELECT),
+    ("DPAD_UP", Buttons::UP),
+    ("DPAD_RIGHT", Buttons::RIGHT),
+    ("DPAD_DOWN", Buttons::DOWN),
+    ("DPAD_LEFT", Buttons::LEFT),
+];
+
+/// The turbo bindings of all frontends (see [`Turbo`]), right above A and B
+pub const DEFAULT_KEYBOARD_TURBO_LAYOUT: [(char, Buttons); 2] =
+    [('I', Buttons::A), ('U', Buttons::B)];
+
+/// The turbo bindings of all frontends that support gamepads, next to A and B
+pub const DEFAULT_GAMEPAD_TURBO_LAYOUT: [(&str, Buttons); 2] =
+    [("Y", Buttons::A), ("X", Buttons::B)];
+
+/// The emulation speeds (multiples of the Game Boy's frame rate) that the speed hotkeys
+/// of the frontends step through
+pub const SPEED_STEPS: [f32; 7] = [0.25, 0.5, 1.0, 1.5, 2.0, 3.0, 4.0];
+
+/// The next step in [`SPEED_STEPS`] after `speed`, in the given direction. Speeds
+/// between the steps (e.g. from `--speed`) go to the neighboring step, and the
+/// slowest and fastest steps stay where they are.
+pub fn next_speed(speed: f32, faster: bool) -> f32 {
+    if faster {
+        SPEED_STEPS
+            .iter()
+            .copied()
+            .find(|&step| step > speed)
+            .unwrap_or(SPEED_STEPS[SPEED_STEPS.len() - 1])
+    } else {
+        SPEED_STEPS
+            .iter()
+            .copied()
+            .rev()
+            .find(|&step| step < speed)
+            .unwrap_or(SPEED_STEPS[0])
+    }
+}
+
+/// Parses a speed multiplier like `2` or `0.25`, as passed to `--speed`
+pub fn parse_speed(text: &str) -> Option<f32> {
+    text.parse::<f32>()
+        .ok()
+        .filter(|speed| speed.is_finite() && *speed > 0.0)
+}
+
+/// The most frames [`FrameSkipMode::Auto`] skips in a row. Beyond that, the game gets too
+/// choppy to be worth playing at full speed.
+pub const MAX_AUTO_FRAME_SKIP: u8 = 3;
+
+/// How many frames in a row have to be late before [`FrameSkipMode::Auto`] skips more
+const FRAMES_BEHIND_TO_SKIP_MORE: u32 = 10;
+
+/// How many frames in a row have to be on time before [`FrameSkipMode::Auto`] skips less
+const FRAMES_ON_TIME_TO_SKIP_LESS: u32 = 120;
+
+/// The frame skip setting of a frontend (see [`Emulator::set_frame_skip`]), as passed
+/// to `--frame-skip`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameSkipMode {
+    /// Always skips the same number of frames between two drawn frames
+    Fixed(u8),
+    /// Skips more frames while the frontend can't keep up with real time, and fewer
+    /// again once it can
+    Auto {
+        skip: u8,
+        /// How many frames in a row were late (positive) or on time (negative)
+        streak: i32,
+    },
+}
+
+impl FrameSkipMode {
+    pub const AUTO: FrameSkipMode = FrameSkipMode::Auto { skip: 0, streak: 0 };
+
+    /// Parses `auto` or a number of frames to skip between two drawn frames
+    pub fn parse(text: &str) -> Option<FrameSkipMode> {
+        match text {
+            "auto" => Some(FrameSkipMode::AUTO),
+            n => n
+                .parse::<u8>()
+                .ok()
+                .filter(|&skip| skip < u8::MAX)
+                .map(FrameSkipMode::Fixed),
+        }
+    }
+
+    /// Needs to be called once per frame, with whether the frame was finished later than
+    /// it should have been. Returns the setting for the emulator.
+    pub fn update(&mut self, behind: bool) -> FrameSkip {
+        match self {
+            FrameSkipMode::Fixed(skip) => frame_skip(*skip),
+            FrameSkipMode::Auto { skip, streak } => {
+                *streak = match (behind, *streak) {
+                    (true, s) if s > 0 => s + 1,
+                    (true, _) => 1,
+                    (false, s) if s < 0 => s - 1,
+                    (false, _) => -1,
+                };
+
+                if *streak >= FRAMES_BEHIND_TO_SKIP_MORE as i32 && *skip < MAX_AUTO_FRAME_SKIP {
+                    *skip += 1;
+                    *streak = 0;
+                    log::info!("Falling behind, skipping {} of {} frames", skip, *skip + 1);
+                } else if *streak <= -(FRAMES_ON_TIME_TO_SKIP_LESS as i32) && *skip > 0 {
+                    *skip -= 1;
+                    *streak = 0;
+                    log::info!("Caught up, skipping {} of {} frames", skip, *skip + 1);
+                }
+
+                frame_skip(*skip)
+            }
+        }
+    }
+}
+
+impl Default for FrameSkipMode {
+    fn default() -> Self {
+        FrameSkipMode::Fixed(0)
+    }
+}
+
+/// Skips `skip` frames between two drawn frames
+fn frame_skip(skip: u8) -> FrameSkip {
+    FrameSkip {
+        skip,
+        period: skip + 1,
+    }
+}
+
+/// Maps the keys of a frontend (whatever type it uses for them) to Game Boy buttons.
+/// Turbo bindings press their buttons repeatedly while they are held (see [`Turbo`]).
+pub struct InputMap<K> {
+    bindings: Vec<(K, Buttons)>,
+    turbo_bindings: Vec<(K, Buttons)>,
+}
+
+impl<K: Copy + PartialEq> InputMap<K> {
+    pub fn new() -> Self {
+        InputMap {
+            bindings: Vec::new(),
+            turbo_bindings: Vec::new(),
+        }
+    }
+
+    /// Builds a map from a layout like [`DEFAULT_KEYBOARD_LAYOUT`]. `key_for_label`
+    /// translates key labels to the key type of the frontend. Labels it doesn't know
+    /// are skipped.
+    pub fn from_layout<F: Fn(char) -> Option<K>>(
+        layout: &[(char, Buttons)],
+        key_for_label: F,
+    ) -> Self {
+        let mut map = InputMap::new();
+
+        for &(label, buttons) in layout {
+            match key_for_label(label) {
+                Some(key) => map.bind(key, buttons),
+                None => log::warn!("No key for label '{}', {} is not bound", label, buttons),
+            }
+        }
+
+        map
+    }
+
+    /// Builds a map from the bindings of an [`InputConfig`]. `key_for_name` translates
+    /// the names of the config file to the key type of the frontend. Names it doesn't
+    /// know are skipped.
+    pub fn from_bindings<F: Fn(&str) -> Option<K>>(
+        bindings: &[(String, Buttons)],
+        key_for_name: F,
+    ) -> Self {
+        InputMap {
+            bindings: resolve_bindings(bindings, key_for_name),
+            turbo_bindings: Vec::new(),
+        }
+    }
+
+    /// Adds the turbo bindings of an [`InputConfig`], see [`InputMap::from_bindings`]
+    pub fn with_turbo_bindings<F: Fn(&str) -> Option<K>>(
+        mut self,
+        turbo_bindings: &[(String, Buttons)],
+        key_for_name: F,
+    ) -> Self {
+        self.turbo_bindings
+            .extend(resolve_bindings(turbo_bindings, key_for_name));
+        self
+    }
+
+    /// The opposite of [`InputMap::from_bindings`], e.g. to store the map after keys were
+    /// rebound. Keys without a name are skipped.
+    pub fn to_bindings<F: Fn(K) -> Option<String>>(
+        &self,
+        name_for_key: F,
+    ) -> Vec<(String, Buttons)> {
+        name_bindings(&self.bindings, name_for_key)
+    }
+
+    /// The opposite of [`InputMap::with_turbo_bindings`]
+    pub fn to_turbo_bindings<F: Fn(K) -> Option<String>>(
+        &self,
+        name_for_key: F,
+    ) -> Vec<(String, Buttons)> {
+        name_bindings(&self.turbo_bindings, name_for_key)
+    }
+
+    /// Makes `key` press `buttons`, in addition to everything else bound to it
+    pub fn bind(&mut self, key: K, buttons: Buttons) {
+        self.bindings.push((key, buttons));
+    }
+
+    /// Makes `key` press `buttons` repeatedly, in addition to everything else bound to it
+    pub fn bind_turbo(&mut self, key: K, buttons: Buttons) {
+        self.turbo_bindings.push((key, buttons));
+    }
+
+    /// Makes `key` the only key that presses `buttons`, and makes it press nothing else.
+    /// This is what a "press a key for ..." dialog does. Turbo bindings of the buttons
+    /// are kept.
+    pub fn rebind(&mut self, key: K, buttons: Buttons) {
+        self.unbind_key(key);
+        remove_buttons(&mut self.bindings, buttons);
+        self.bind(key, buttons);
+    }
+
+    /// Like [`InputMap::rebind`], but for the turbo bindings
+    pub fn rebind_turbo(&mut self, key: K, buttons: Buttons) {
+        self.unbind_key(key);
+        remove_buttons(&mut self.turbo_bindings, buttons);
+        self.bind_turbo(key, buttons);
+    }
+
+    /// Removes all bindings of `key`, including its turbo bindings
+    pub fn unbind_key(&mut self, key: K) {
+        self.bindings.retain(|&(bound, _)| bound != key);
+        self.turbo_bindings.retain(|&(bound, _)| bound != key);
+    }
+
+    /// All keys that are bound to the given button (without turbo)
+    pub fn keys_for(&self, button: Buttons) -> impl Iterator<Item = K> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, buttons)| buttons.intersects(button))
+            .map(|&(key, _)| key)
+    }
+
+    /// All keys that are bound to any buttons, with or without turbo
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        self.bindings
+            .iter()
+            .chain(self.turbo_bindings.iter())
+            .map(|&(key, _)| key)
+    }
+
+    /// The buttons that are pressed while the given keys are held down. Turbo bindings
+    /// are not included, see [`InputMap::turbo_buttons`].
+    pub fn buttons<I: IntoIterator<Item = K>>(&self, pressed_keys: I) -> Buttons {
+        bound_buttons(&self.bindings, pressed_keys)
+    }
+
+    /// The buttons that are pressed repeatedly while the given keys are held down. They
+    /// still need to go through [`Turbo::apply`].
+    pub fn turbo_buttons<I: IntoIterator<Item = K>>(&self, pressed_keys: I) -> Buttons {
+        bound_buttons(&self.turbo_bindings, pressed_keys)
+    }
+}
+
+fn resolve_bindings<K, F: Fn(&str) -> Option<K>>(
+    bindings: &[(String, Buttons)],
+    key_for_name: F,
+) -> Vec<(K, Buttons)> {
+    bindings
+        .iter()
+        .filter_map(|(name, buttons)| {
+            let key = key_for_name(name);
+
+            if key.is_none() {
+                log::warn!("Unknown key \"{}\", {} is not bound", name, buttons);
+            }
+
+            key.map(|key| (key, *buttons))
+        })
+        .collect()
+}
+
+fn name_bindings<K: Copy, F: Fn(K) -> Option<String>>(
+    bindings: &[(K, Buttons)],
+    name_for_key: F,
+) -> Vec<(String, Buttons)> {
+    bindings
+        .iter()
+        .filter_map(|&(key, buttons)| name_for_key(key).map(|name| (name, buttons)))
+        .collect()
+}
+
+/// Unbinds `buttons` from all keys, and removes the keys that don't press anything anymore
+fn remove_buttons<K>(bindings: &mut Vec<(K, Buttons)>, buttons: Buttons) {
+    for (_, bound) in bindings.iter_mut() {
+        bound.remove(buttons);
+    }
+
+    bindings.retain(|(_, bound)| !bound.is_empty());
+}
+
+fn bound_buttons<K: Copy + PartialEq, I: IntoIterator<Item = K>>(
+    bindings: &[(K, Buttons)],
+    pressed_keys: I,
+) -> Buttons {
+    pressed_keys
+        .into_iter()
+        .flat_map(|pressed| {
+            bindings
+                .iter()
+                .filter(move |&&(key, _)| key == pressed)
+                .map(|&(_, buttons)| buttons)
+        })
+        .fold(Buttons::empty(), |acc, buttons| acc | buttons)
+}
+
+/// The default of [`InputConfig::turbo_frames`]: 15 presses per second
+pub const DEFAULT_TURBO_FRAMES: u8 = 2;
+
+/// Autofire for the turbo bindings of an [`InputMap`]: Their buttons are pressed for
+/// some frames, then released for as many, and so on
+#[derive(Debug, Clone)]
+pub struct Turbo {
+    /// How many frames the buttons are pressed (and released) in a row
+    frames: u8,
+    frame: u32,
+}
+
+impl Turbo {
+    /// `frames` is clamped to at least 1, which presses the buttons every other frame
+    pub fn new(frames: u8) -> Turbo {
+        Turbo {
+            frames: frames.max(1),
+            frame: 0,
+        }
+    }
+
+    pub fn frames(&self) -> u8 {
+        self.frames
+    }
+
+    pub fn set_frames(&mut self, frames: u8) {
+        self.frames = frames.max(1);
+    }
+
+    /// Needs to be called once per emulated frame
+    pub fn next_frame(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// The turbo buttons that are pressed in the current frame
+    pub fn apply(&self, turbo_buttons: Buttons) -> Buttons {
+        if (self.frame / self.frames as u32).is_multiple_of(2) {
+            turbo_buttons
+        } else {
+            Buttons::empty()
+        }
+    }
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Turbo::new(DEFAULT_TURBO_FRAMES)
+    }
+}
+
+/// In which directions an analog stick moves the D-pad
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StickDirections {
+    /// The stick doesn't move the D-pad
+    Off,
+    /// Up, down, left or right, whichever is closest
+    Four,
+    /// Like [`StickDirections::Four`], plus the diagonals
+    Eight,
+}
+
+/// How the left analog stick of a gamepad is mapped to the D-pad, since the D-pads of
+/// many gamepads are hard to use
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StickConfig {
+    /// How far the stick has to be pushed (between 0.0 and 1.0) before it moves the D-pad
+    pub deadzone: f32,
+    pub directions: StickDirections,
+}
+
+impl StickConfig {
+    /// The D-pad buttons for a stick at `(x, y)`, which are between -1.0 and 1.0 (with
+    /// `y` pointing up). The stick snaps to the closest of the allowed directions.
+    pub fn dpad(&self, x: f32, y: f32) -> Buttons {
+        // Counter-clockwise, starting at the right
+        const EIGHT_WAY: [Buttons; 8] = [
+            Buttons::RIGHT,
+            Buttons::from_bits_truncate(Buttons::RIGHT.bits() | Buttons::UP.bits()),
+            Buttons::UP,
+            Buttons::from_bits_truncate(Buttons::LEFT.bits() | Buttons::UP.bits()),
+            Buttons::LEFT,
+            Buttons::from_bits_truncate(Buttons::LEFT.bits() | Buttons::DOWN.bits()),
+            Buttons::DOWN,
+            Buttons::from_bits_truncate(Buttons::RIGHT.bits() | Buttons::DOWN.bits()),
+        ];
+
+        let sectors = match self.directions {
+            StickDirections::Off => return Buttons::empty(),
+            StickDirections::Four => 4,
+            StickDirections::Eight => 8,
+        };
+
+        if x.hypot(y) <= self.deadzone {
+            return Buttons::empty();
+        }
+
+        let turns = y.atan2(x) / std::f32::consts::TAU;
+        let sector = (turns * sectors as f32).round() as i32;
+
+        EIGHT_WAY[sector.rem_euclid(sectors) as usize * (8 / sectors) as usize]
+    }
+}
+
+impl Default for StickConfig {
+    fn default() -> Self {
+        StickConfig {
+            deadzone: 0.3,
+            directions: StickDirections::Eight,
+        }
+    }
+}
+
+impl<K: Copy + PartialEq> Default for InputMap<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where frontends keep their [`InputConfig`]: `input.toml` next to the executable, so
+/// portable installations keep their bindings
+pub fn input_config_path() -> io::Result<PathBuf> {
+    Ok(std::env::current_exe()?.with_file_name("input.toml"))
+}
+
+#[derive(Debug)]
+pub enum InputConfigError {
+    Io(io::Error),
+    /// A line could not be parsed (line numbers start at 1)
+    InvalidLine {
+        line: usize,
+        content: String,
+    },
+}
+
+impl From<io::Error> for InputConfigError {
+    fn from(err: io::Error) -> Self {
+        InputConfigError::Io(err)
+    }
+}
+
+/// The key and gamepad bindings of a frontend, by name, as they are stored in the input
+/// config file. The frontends translate the names to their own key types when they
+/// build their [`InputMap`]s (see [`InputMap::from_bindings`]).
+///
+/// Keyboard keys are named after their labels (`K`, `Space`, `Enter`, `Up`, ...), and
+/// gamepad buttons after the buttons of an Xbox gamepad (`A`, `START`, `DPAD_UP`, ...).
+/// The file is a small subset of TOML, with one section per device and the buttons in
+/// their canonical textual representation (see [`Buttons`]). Turbo bindings have their
+/// own sections, and the `[turbo]` section sets how many frames their buttons are held
+/// (and released) in a row:
+///
+/// ```toml
+/// [keyboard]
+/// K = "A"
+/// Space = "A|B"
+///
+/// [keyboard.turbo]
+/// I = "A"
+///
+/// [gamepad]
+/// B = "A"
+///
+/// [gamepad.turbo]
+/// Y = "A"
+///
+/// [turbo]
+/// frames = 2
+/// ```
+///
+/// The `[gamepad.stick]` section configures the left analog stick (see [`StickConfig`])
+/// with `deadzone = 0.3` and `directions = 8` (or 4, or 0 to turn it off).
+///
+/// All sections are optional. Like in TOML, `#` starts a comment (outside of quotes), and
+/// keys that contain anything but letters, digits, `_` and `-` are quoted (`"=" = "A"`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputConfig {
+    pub keyboard: Vec<(String, Buttons)>,
+    pub keyboard_turbo: Vec<(String, Buttons)>,
+    pub gamepad: Vec<(String, Buttons)>,
+    pub gamepad_turbo: Vec<(String, Buttons)>,
+    /// See [`Turbo::new`]
+    pub turbo_frames: u8,
+    pub stick: StickConfig,
+}
+
+impl InputConfig {
+    /// Parses the contents of an input config file (see [`InputConfig`] for the format)
+    pub fn parse(text: &str) -> Result<InputConfig, InputConfigError> {
+        let mut config = InputConfig {
+            keyboard: Vec::new(),
+            keyboard_turbo: Vec::new(),
+            gamepad: Vec::new(),
+            gamepad_turbo: Vec::new(),
+            turbo_frames: DEFAULT_TURBO_FRAMES,
+            stick: StickConfig::default(),
+        };
+
+        let mut section = None;
+
+        for (idx, line) in text.lines().enumerate() {
+            let invalid = || InputConfigError::InvalidLine {
+                line: idx + 1,
+                content: line.trim().to_owned(),
+            };
+
+            let content = strip_comment(line).trim();
+
+            if content.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = content.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                let name = name.trim();
+
+                if !SETTINGS_SECTIONS.contains(&name) && config.bindings_mut(name).is_none() {
+                    return Err(invalid());
+                }
+
+                section = Some(name);
+                continue;
+            }
+
+            let (name, value) = split_key_value(content).ok_or_else(invalid)?;
+
+            match section {
+                Some("turbo") if name == "frames" => {
+                    config.turbo_frames = value
+                        .parse()
+                        .ok()
+                        .filter(|&frames| frames > 0)
+                        .ok_or_else(invalid)?;
+                }
+                Some("gamepad.stick") if name == "deadzone" => {
+                    config.stick.deadzone = value
+                        .parse()
+                        .ok()
+                        .filter(|deadzone| (0.0..=1.0).contains(deadzone))
+                        .ok_or_else(invalid)?;
+                }
+                Some("gamepad.stick") if name == "directions" => {
+                    config.stick.directions = match value {
+                        "0" => StickDirections::Off,
+                        "4" => StickDirections::Four,
+                        "8" => StickDirections::Eight,
+                        _ => return Err(invalid()),
+                    };
+                }
+                Some(section) if !name.is_empty() => {
+                    let buttons = value.parse().map_err(|_| invalid())?;
+                    let bindings = config.bindings_mut(section).ok_or_else(invalid)?;
+                    bindings.push((name.to_owned(), buttons));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<InputConfig, InputConfigError> {
+        InputConfig::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Loads the config file, or writes the default config to it if there is none yet,
+    /// so users find a file they can edit
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> Result<InputConfig, InputConfigError> {
+        match InputConfig::load(&path) {
+            Err(InputConfigError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                let config = InputConfig::default();
+                config.store(&path)?;
+                log::info!("Wrote default key bindings to {:?}", path.as_ref());
+                Ok(config)
+            }
+            result => result,
+        }
+    }
+
+    pub fn store<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// The bindings in the section with the given name
+    fn bindings_mut(&mut self, section: &str) -> Option<&mut Vec<(String, Buttons)>> {
+        match section {
+            "keyboard" => Some(&mut self.keyboard),
+            "keyboard.turbo" => Some(&mut self.keyboard_turbo),
+            "gamepad" => Some(&mut self.gamepad),
+            "gamepad.turbo" => Some(&mut self.gamepad_turbo),
+            _ => None,
+        }
+    }
+}
+
+/// The layouts of all frontends, see [`DEFAULT_KEYBOARD_LAYOUT`],
+/// [`DEFAULT_GAMEPAD_LAYOUT`] and their turbo counterparts
+impl Default for InputConfig {
+    fn default() -> Self {
+        let by_label = |layout: &[(char, Buttons)]| {
+            layout
+                .iter()
+                .map(|&(label, buttons)| (label.to_string(), buttons))
+                .collect()
+        };
+
+        let by_name = |layout: &[(&str, Buttons)]| {
+            layout
+                .iter()
+                .map(|&(name, buttons)| (name.to_owned(), buttons))
+                .collect()
+        };
+
+        InputConfig {
+            keyboard: by_label(&DEFAULT_KEYBOARD_LAYOUT),
+            keyboard_turbo: by_label(&DEFAULT_KEYBOARD_TURBO_LAYOUT),
+            gamepad: by_name(&DEFAULT_GAMEPAD_LAYOUT),
+            gamepad_turbo: by_name(&DEFAULT_GAMEPAD_TURBO_LAYOUT),
+            turbo_frames: DEFAULT_TURBO_FRAMES,
+            stick: StickConfig::default(),
+        }
+    }
+}
+
+/// Writes the format that [`InputConfig::parse`] reads
+impl fmt::Display for InputConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "# Key bindings of MaBoy. Every line binds a key or gamepad button to one"
+        )?;
+        writeln!(
+            f,
+            "# or more Game Boy buttons, e.g. `K = \"A\"` or `Space = \"A|B\"`."
+        )?;
+
+        let sections = [
+            ("keyboard", &self.keyboard),
+            ("keyboard.turbo", &self.keyboard_turbo),
+            ("gamepad", &self.gamepad),
+            ("gamepad.turbo", &self.gamepad_turbo),
+        ];
+
+        for (section, bindings) in sections {
+            writeln!(f, "\n[{}]", section)?;
+
+            for (name, buttons) in bindings {
+                if is_bare_key(name) {
+                    writeln!(f, "{} = \"{}\"", name, buttons)?;
+                } else {
+                    writeln!(f, "\"{}\" = \"{}\"", name, buttons)?;
+                }
+            }
+        }
+
+        let directions = match self.stick.directions {
+            StickDirections::Off => 0,
+            StickDirections::Four => 4,
+            StickDirections::Eight => 8,
+        };
+
+        writeln!(
+            f,
+            "\n# How far the left stick has to be pushed (0.0 - 1.0) to move the D-pad,"
+        )?;
+        writeln!(
+            f,
+            "# and in how many directions it moves it (4, 8 or 0 to turn it off)"
+        )?;
+        writeln!(f, "[gamepad.stick]")?;
+        writeln!(f, "deadzone = {}", self.stick.deadzone)?;
+        writeln!(f, "directions = {}", directions)?;
+
+        writeln!(
+            f,
+            "\n# How many frames turbo buttons are held (and released) in a row"
+        )?;
+        writeln!(f, "[turbo]")?;
+        writeln!(f, "frames = {}", self.turbo_frames)
+    }
+}
+
+/// The sections of the input config that contain settings instead of bindings
+const SETTINGS_SECTIONS: [&str; 2] = ["turbo", "gamepad.stick"];
+
+/// Strips the quotes around TOML strings
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+/// Cuts off a trailing comment. A `#` inside quotes doesn't start one.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..idx],
+            _ => (),
+        }
+    }
+
+    line
+}
+
+/// Splits `key = value` into the unquoted key and value. The key may be quoted, in which
+/// case it can contain `=` as well.
+fn split_key_value(content: &str) -> Option<(&str, &str)> {
+    let (name, rest) = match content.strip_prefix('"') {
+        Some(quoted) => {
+            let end = quoted.find('"')?;
+            (&quoted[..end], &quoted[end + 1..])
+        }
+        None => {
+            let end = content.find('=')?;
+            (content[..end].trim(), &content[end..])
+        }
+    };
+
+    let value = rest.trim_start().strip_prefix('=')?.trim();
+
+    Some((name, unquote(value)))
+}
+
+/// Whether a key can be written without quotes, like TOML's bare keys
+fn is_bare_key(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Darkens a frame and draws a pause symbol in its center, so it's obvious that the game
+/// is paused (e.g. by the debugger) and not frozen
+pub fn draw_pause_overlay(frame: &mut [MemPixel]) {
+    assert_eq!(frame.len(), FRAME_WIDTH * FRAME_HEIGHT);
+
+    for pixel in frame.iter_mut() {
+        pixel.r /= 3;
+        pixel.g /= 3;
+        pixel.b /= 3;
+    }
+
+    // Two bars of 6x24 pixels, 6 pixels apart
+    let (center_x, center_y) = (FRAME_WIDTH / 2, FRAME_HEIGHT / 2);
+
+    for y in center_y - 12..center_y + 12 {
+        for x in (center_x - 9..center_x - 3).chain(center_x + 3..center_x + 9) {
+            frame[y * FRAME_WIDTH + x] = MemPixel::LCD_OFF;
+        }
+    }
+}
+
+/// How many ROMs [`RecentRoms`] remembers
+pub const MAX_RECENT_ROMS: usize = 8;
+
+/// Where frontends keep their [`RecentRoms`]: `recent_roms.txt` next to the executable,
+/// just like the [`InputConfig`]
+pub fn recent_roms_path() -> io::Result<PathBuf> {
+    Ok(std::env::current_exe()?.with_file_name("recent_roms.txt"))
+}
+
+/// The ROMs that were opened last, most recent first, so frontends can offer them
+/// without going through a file dialog. The file has one path per line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecentRoms {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    pub fn parse(text: &str) -> RecentRoms {
+        RecentRoms {
+            paths: text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(PathBuf::from)
+                .take(MAX_RECENT_ROMS)
+                .collect(),
+        }
+    }
+
+    /// Reads the list from `path`. If the file doesn't exist yet, the list is empty.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<RecentRoms> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(RecentRoms::parse(&text)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(RecentRoms::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn store<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Moves `rom_path` to the front of the list (or puts it there), and forgets the
+    /// oldest ROM if there are more than [`MAX_RECENT_ROMS`]. Relative paths are made
+    /// absolute, so they still work when the frontend is started from somewhere else.
+    pub fn add<P: AsRef<Path>>(&mut self, rom_path: P) {
+        let rom_path = rom_path.as_ref();
+        let rom_path = std::env::current_dir()
+            .map(|dir| dir.join(rom_path))
+            .unwrap_or_else(|_| rom_path.to_path_buf());
+
+        self.paths.retain(|path| *path != rom_path);
+        self.paths.insert(0, rom_path);
+        self.paths.truncate(MAX_RECENT_ROMS);
+    }
+
+    /// Forgets the ROMs that don't exist anymore (e.g. because they were moved)
+    pub fn remove_missing(&mut self) {
+        self.paths.retain(|path| path.is_file());
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+impl fmt::Display for RecentRoms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for path in &self.paths {
+            writeln!(f, "{}", path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A battery-backed cartridge whose RAM enable gate is controlled by the test
+    struct TestCartridge {
+        ram: Vec<u8>,
+        dirty: bool,
+        ram_enabled: bool,
+    }
+
+    impl TestCartridge {
+        fn new(ram_enabled: bool) -> TestCartridge {
+            TestCartridge {
+                ram: vec![0x42; 0x2000],
+                dirty: true,
+                ram_enabled,
+            }
+        }
+    }
+
+    impl Savegame for TestCartridge {
+        fn savegame(&self) -> Option<&[u8]> {
+            Some(&self.ram)
+        }
+
+        fn savegame_dirty(&self) -> bool {
+            self.dirty
+        }
+
+        fn mark_savegame_clean(&mut self) {
+            self.dirty = false;
+        }
+
+        fn savegame_ram_enabled(&self) -> bool {
+            self.ram_enabled
+        }
+    }
+
+    impl Metadata for TestCartridge {}
+
+    /// A base path in a fresh temporary directory, which is removed when the test is done
+    struct TempBasePath(PathBuf);
+
+    impl TempBasePath {
+        fn new(name: &str) -> TempBasePath {
+            let dir = std::env::temp_dir().join(format!("maboy-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempBasePath(dir.join("game"))
+        }
+
+        fn savegame_exists(&self) -> bool {
+            save_file_path(&self.0, "sav").exists()
+        }
+    }
+
+    impl Drop for TempBasePath {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.parent().unwrap());
+        }
+    }
+
+    fn invalid_line(text: &str) -> Option<usize> {
+        match InputConfig::parse(text) {
+            Err(InputConfigError::InvalidLine { line, .. }) => Some(line),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn input_config_round_trips() {
+        let default = InputConfig::default();
+        assert_eq!(InputConfig::parse(&default.to_string()).unwrap(), default);
+
+        let custom = InputConfig {
+            keyboard: vec![
+                ("=".to_owned(), Buttons::A),
+                ("#".to_owned(), Buttons::B | Buttons::START),
+                ("Space".to_owned(), Buttons::SELECT),
+            ],
+            keyboard_turbo: Vec::new(),
+            gamepad: vec![("DPAD_UP".to_owned(), Buttons::UP)],
+            gamepad_turbo: vec![("Y".to_owned(), Buttons::A)],
+            turbo_frames: 5,
+            stick: StickConfig {
+                deadzone: 0.5,
+                directions: StickDirections::Four,
+            },
+        };
+        assert_eq!(InputConfig::parse(&custom.to_string()).unwrap(), custom);
+    }
+
+    #[test]
+    fn input_config_allows_comments_and_quoted_keys() {
+        let config = InputConfig::parse(
+            "# Bindings\n\
+             [keyboard] # the keyboard\n\
+             K = \"A\"  # jump\n\
+             \"=\" = \"B\"\n\
+             \"#\"=\"START\"#pause\n\
+             \n\
+             [gamepad.stick]\n\
+             deadzone = 0.25 # less than the default\n\
+             directions = 0\n\
+             [turbo]\n\
+             frames = 3 # 10 presses per second\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.keyboard,
+            [
+                ("K".to_owned(), Buttons::A),
+                ("=".to_owned(), Buttons::B),
+                ("#".to_owned(), Buttons::START),
+            ]
+        );
+        assert!(config.gamepad.is_empty());
+        assert_eq!(config.stick.deadzone, 0.25);
+        assert_eq!(config.stick.directions, StickDirections::Off);
+        assert_eq!(config.turbo_frames, 3);
+    }
+
+    #[test]
+    fn input_config_reports_invalid_lines() {
+        assert_eq!(invalid_line("[keyboard]\nK = \"A\"\n[mouse]"), Some(3));
+        assert_eq!(invalid_line("K = \"A\""), Some(1));
+        assert_eq!(invalid_line("[keyboard]\nK"), Some(2));
+        assert_eq!(invalid_line("[keyboard]\n = \"A\""), Some(2));
+        assert_eq!(
+            invalid_line("[keyboard]\n\n# Comment\nK = \"JUMP\""),
+            Some(4)
+        );
+        assert_eq!(invalid_line("[keyboard.turbo]\n\"K = \"A\""), Some(2));
+        assert_eq!(invalid_line("[turbo]\nframes = 0"), Some(2));
+        assert_eq!(invalid_line("[turbo]\nspeed = 2"), Some(2));
+        assert_eq!(invalid_line("[gamepad.stick]\ndeadzone = 1.5"), Some(2));
+        assert_eq!(invalid_line("[gamepad.stick]\ndirections = 6"), Some(2));
+        assert_eq!(invalid_line("[gamepad.stick]\nA = \"A\""), Some(2));
+    }
+
+    #[test]
+    fn autosave_writes_after_the_delay() {
+        let base_path = TempBasePath::new("autosave-disabled");
+        let mut cartridge = TestCartridge::new(false);
+        let mut autosave = AutoSave::new(Duration::ZERO);
+
+        assert!(autosave.update(&base_path.0, &mut cartridge).unwrap());
+        assert!(base_path.savegame_exists());
+
+        // Nothing changed since, so there is nothing to write
+        assert!(!autosave.update(&base_path.0, &mut cartridge).unwrap());
+    }
+
+    #[test]
+    fn autosave_waits_once_while_ram_is_enabled() {
+        let base_path = TempBasePath::new("autosave-enabled");
+        let mut cartridge = TestCartridge::new(true);
+        let mut autosave = AutoSave::new(Duration::ZERO);
+
+        assert!(!autosave.update(&base_path.0, &mut cartridge).unwrap());
+        assert!(!base_path.savegame_exists());
+
+        // The game never disabled RAM, so we write anyway
+        assert!(autosave.update(&base_path.0, &mut cartridge).unwrap());
+        assert!(base_path.savegame_exists());
+
+        // Another write means waiting again
+        cartridge.dirty = true;
+        assert!(!autosave.update(&base_path.0, &mut cartridge).unwrap());
+
+        cartridge.ram_enabled = false;
+        assert!(autosave.update(&base_path.0, &mut cartridge).unwrap());
+    }
+}
@@ -0,0 +1,302 @@
+//! Human-readable names for the variables that a game keeps in memory, like the
+//! player's position or the amount of money. They power overlays, auto-splitters and
+//! scripts, which can read `player_x` instead of `0xD362`.
+//!
+//! Definitions are loaded from simple text files, similar to the RAM maps that
+//! communities like Data Crystal maintain for many games. Every line defines one
+//! variable: its address, its type, its name and an optional description.
+//!
+//! ```text
+//! # Pokémon Red/Blue (excerpt)
+//! D35E  u8      map_id     Current map
+//! D361  u8      player_y
+//! D362  u8      player_x
+//! D347  bcd3be  money      Stored as 6 BCD digits, most significant first
+//! ```
+//!
+//! Addresses are in hex (optionally prefixed with `0x` or `$`). Lines starting with `#`
+//! or `;` are comments. These types are supported:
+//!
+//! - `u8`, `u16`, `u24`, `u32` and `i8`, `i16`, `i24`, `i32`: (Un)signed integers
+//! - `bcd1` to `bcd4`: Binary-coded decimals with 2 digits per byte
+//! - `bool`: A byte that is either zero (false) or not (true)
+//!
+//! Multi-byte values are little-endian (like the Game Boy's CPU), unless the type has a
+//! `be` suffix. Values are read from whatever is currently mapped at their address, so
+//! variables in banked memory only make sense while the right bank is mapped.
+//!
+//! Install a [`GameDb`] via [`crate::Emulator::set_game_db`] and read variables via
+//! [`crate::Emulator::game_var`].
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VarKind {
+    Unsigned,
+    Signed,
+    Bcd,
+    Bool,
+}
+
+/// How a variable is stored in memory. The canonical textual representation (e.g.
+/// `u16be`) is what variable-definition files use.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VarType {
+    pub kind: VarKind,
+    /// 1 to 4
+    pub bytes: u8,
+    pub big_endian: bool,
+}
+
+#[derive(Debug)]
+pub struct ParseVarTypeError(pub String);
+
+impl VarType {
+    /// Assembles the value from the variable's bytes, which are passed in memory order
+    fn decode(self, bytes: &[u8]) -> i64 {
+        let mut raw = 0u32;
+
+        for idx in 0..bytes.len() {
+            let byte = if self.big_endian {
+                bytes[idx]
+            } else {
+                bytes[bytes.len() - 1 - idx]
+            };
+
+            raw = raw << 8 | byte as u32;
+        }
+
+        match self.kind {
+            VarKind::Unsigned => raw as i64,
+            VarKind::Signed => {
+                let unused_bits = 32 - 8 * self.bytes as u32;
+                ((raw << unused_bits) as i32 >> unused_bits) as i64
+            }
+            // Digits above 9 are not valid BCD, but are taken as they are
+            VarKind::Bcd => (0..2 * self.bytes as u32)
+                .rev()
+                .fold(0, |val, digit| val * 10 + (raw >> (4 * digit) & 0xF) as i64),
+            VarKind::Bool => (raw != 0) as i64,
+        }
+    }
+}
+
+impl fmt::Display for VarType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            VarKind::Unsigned => write!(f, "u{}", 8 * self.bytes)?,
+            VarKind::Signed => write!(f, "i{}", 8 * self.bytes)?,
+            VarKind::Bcd => write!(f, "bcd{}", self.bytes)?,
+            VarKind::Bool => return f.write_str("bool"),
+        }
+
+        if self.big_endian {
+            f.write_str("be")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for VarType {
+    type Err = ParseVarTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseVarTypeError(s.to_owned());
+
+        let lower = s.trim().to_ascii_lowercase();
+
+        if lower == "bool" {
+            return Ok(VarType {
+                kind: VarKind::Bool,
+                bytes: 1,
+                big_endian: false,
+            });
+        }
+
+        let (lower, big_endian) = match lower.strip_suffix("be") {
+            Some(lower) => (lower, true),
+            None => (lower.as_str(), false),
+        };
+
+        let (kind, size) = if let Some(bytes) = lower.strip_prefix("bcd") {
+            (VarKind::Bcd, bytes.parse::<u8>().map_err(|_| err())?)
+        } else if let Some(bits) = lower.strip_prefix('u') {
+            (VarKind::Unsigned, bits_to_bytes(bits).ok_or_else(err)?)
+        } else if let Some(bits) = lower.strip_prefix('i') {
+            (VarKind::Signed, bits_to_bytes(bits).ok_or_else(err)?)
+        } else {
+            return Err(err());
+        };
+
+        // Endianness doesn't make sense for single bytes
+        if !(1..=4).contains(&size) || (size == 1 && big_endian) {
+            return Err(err());
+        }
+
+        Ok(VarType {
+            kind,
+            bytes: size,
+            big_endian,
+        })
+    }
+}
+
+fn bits_to_bytes(bits: &str) -> Option<u8> {
+    match bits.parse::<u8>().ok()? {
+        bits if bits % 8 == 0 => Some(bits / 8),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GameVar {
+    pub name: String,
+    pub addr: u16,
+    pub var_type: VarType,
+    pub description: Option<String>,
+}
+
+impl GameVar {
+    /// Reads the variable, with `peek` returning the byte at a given address
+    pub fn read<F: Fn(u16) -> u8>(&self, peek: F) -> i64 {
+        let bytes: Vec<u8> = (0..self.var_type.bytes as u16)
+            .map(|offset| peek(self.addr.wrapping_add(offset)))
+            .collect();
+
+        self.var_type.decode(&bytes)
+    }
+}
+
+#[derive(Debug)]
+pub enum GameDbError {
+    Io(io::Error),
+    /// A line could not be parsed (line numbers start at 1)
+    InvalidLine {
+        line: usize,
+        content: String,
+    },
+    /// Two variables have the same name
+    DuplicateName {
+        line: usize,
+        name: String,
+    },
+}
+
+impl From<io::Error> for GameDbError {
+    fn from(err: io::Error) -> Self {
+        GameDbError::Io(err)
+    }
+}
+
+/// Errors of [`crate::Emulator::game_var`]
+#[derive(Debug)]
+pub enum GameVarError {
+    /// No [`GameDb`] was installed
+    NoGameDb,
+    /// The installed [`GameDb`] doesn't define a variable with that name
+    UnknownVar(String),
+    /// The value doesn't fit into the requested type
+    OutOfRange(i64),
+}
+
+/// A set of variable definitions, usually for a single game
+#[derive(Default, Clone)]
+pub struct GameDb {
+    /// In the order they were defined
+    vars: Vec<GameVar>,
+    by_name: HashMap<String, usize>,
+}
+
+impl GameDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a variable-definition file (see the [module documentation](self) for the
+    /// format)
+    pub fn parse(text: &str) -> Result<GameDb, GameDbError> {
+        let mut db = GameDb::new();
+
+        for (idx, line) in text.lines().enumerate() {
+            let content = line.trim();
+
+            if content.is_empty() || content.starts_with('#') || content.starts_with(';') {
+                continue;
+            }
+
+            let var = parse_line(content).ok_or_else(|| GameDbError::InvalidLine {
+                line: idx + 1,
+                content: content.to_owned(),
+            })?;
+
+            if db.by_name.contains_key(&var.name) {
+                return Err(GameDbError::DuplicateName {
+                    line: idx + 1,
+                    name: var.name,
+                });
+            }
+
+            db.add(var);
+        }
+
+        Ok(db)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GameDb, GameDbError> {
+        GameDb::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Adds a variable, replacing any variable with the same name
+    pub fn add(&mut self, var: GameVar) {
+        match self.by_name.get(&var.name) {
+            Some(&idx) => self.vars[idx] = var,
+            None => {
+                self.by_name.insert(var.name.clone(), self.vars.len());
+                self.vars.push(var);
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&GameVar> {
+        self.by_name.get(name).map(|&idx| &self.vars[idx])
+    }
+
+    /// All variables, in the order they were defined
+    pub fn vars(&self) -> &[GameVar] {
+        &self.vars
+    }
+}
+
+/// `<address> <type> <name> [description]`
+fn parse_line(line: &str) -> Option<GameVar> {
+    let mut rest = line;
+    let mut next_token = || {
+        let token_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (token, remaining) = rest.split_at(token_end);
+        rest = remaining.trim_start();
+        Some(token).filter(|token| !token.is_empty())
+    };
+
+    let addr = next_token()?;
+    let addr = addr
+        .strip_prefix("0x")
+        .or_else(|| addr.strip_prefix('$'))
+        .unwrap_or(addr);
+    let addr = u16::from_str_radix(addr, 16).ok()?;
+
+    let var_type = next_token()?.parse().ok()?;
+    let name = next_token()?.to_owned();
+
+    Some(GameVar {
+        name,
+        addr,
+        var_type,
+        description: Some(rest.to_owned()).filter(|description| !description.is_empty()),
+    })
+}
@@ -0,0 +1,138 @@
+//! An object-safe view of the [`Emulator`], so frontends don't have to be generic over
+//! the cartridge and debugger types, see [`EmulatorHandle`]
+
+use crate::cartridge::{Cartridge, Savegame};
+use crate::debug::{CpuEvt, DbgEvtSrc, PpuEvt};
+use crate::frame_dump::RgbaFrame;
+use crate::joypad::Buttons;
+use crate::ppu::{FrameResult, VideoFrameStatus};
+use crate::savestate::SaveStateError;
+use crate::{Emulator, FrameStats, StepOutcome};
+
+/// The parts of [`Emulator`] that a frontend needs while the game is running: stepping,
+/// frames, buttons, savestates and savegames. Every method forwards to the method of the
+/// same name on [`Emulator`] (or its cartridge), where the documentation lives.
+///
+/// Code that only drives the emulator can take a `&mut dyn EmulatorHandle` (or hold a
+/// `Box<dyn EmulatorHandle>`) instead of being monomorphized for every cartridge type.
+/// Everything else (cartridge access, debuggers, settings) still requires the concrete
+/// [`Emulator`].
+pub trait EmulatorHandle {
+    fn emulate_step(&mut self) -> StepOutcome;
+
+    fn run_frame(&mut self) -> FrameResult<'_>;
+
+    fn query_video_frame_status(&mut self) -> VideoFrameStatus<'_>;
+
+    fn mcycles(&self) -> u64;
+
+    fn last_frame_stats(&self) -> Option<FrameStats>;
+
+    fn peek(&self, addr: u16) -> u8;
+
+    fn poke(&mut self, addr: u16, val: u8);
+
+    fn notify_buttons_pressed(&mut self, buttons: Buttons);
+
+    fn notify_buttons_released(&mut self, buttons: Buttons);
+
+    fn notify_buttons_state(&mut self, buttons: Buttons);
+
+    fn poll_reset_combo(&mut self) -> bool;
+
+    fn poll_rumble(&mut self) -> bool;
+
+    fn reset(&mut self);
+
+    fn soft_reset(&mut self);
+
+    fn save_state(&self) -> Vec<u8>;
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError>;
+
+    fn screenshot(&self) -> RgbaFrame;
+
+    fn savegame(&self) -> Option<&[u8]>;
+
+    fn savegame_mut(&mut self) -> Option<&mut [u8]>;
+}
+
+impl<C: Cartridge + Savegame, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>> EmulatorHandle
+    for Emulator<C, CpuDbg, PpuDbg>
+{
+    fn emulate_step(&mut self) -> StepOutcome {
+        Emulator::emulate_step(self)
+    }
+
+    fn run_frame(&mut self) -> FrameResult<'_> {
+        Emulator::run_frame(self)
+    }
+
+    fn query_video_frame_status(&mut self) -> VideoFrameStatus<'_> {
+        Emulator::query_video_frame_status(self)
+    }
+
+    fn mcycles(&self) -> u64 {
+        Emulator::mcycles(self)
+    }
+
+    fn last_frame_stats(&self) -> Option<FrameStats> {
+        Emulator::last_frame_stats(self)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Emulator::peek(self, addr)
+    }
+
+    fn poke(&mut self, addr: u16, val: u8) {
+        Emulator::poke(self, addr, val)
+    }
+
+    fn notify_buttons_pressed(&mut self, buttons: Buttons) {
+        Emulator::notify_buttons_pressed(self, buttons)
+    }
+
+    fn notify_buttons_released(&mut self, buttons: Buttons) {
+        Emulator::notify_buttons_released(self, buttons)
+    }
+
+    fn notify_buttons_state(&mut self, buttons: Buttons) {
+        Emulator::notify_buttons_state(self, buttons)
+    }
+
+    fn poll_reset_combo(&mut self) -> bool {
+        Emulator::poll_reset_combo(self)
+    }
+
+    fn poll_rumble(&mut self) -> bool {
+        Emulator::poll_rumble(self)
+    }
+
+    fn reset(&mut self) {
+        Emulator::reset(self)
+    }
+
+    fn soft_reset(&mut self) {
+        Emulator::soft_reset(self)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        Emulator::save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        Emulator::load_state(self, state)
+    }
+
+    fn screenshot(&self) -> RgbaFrame {
+        Emulator::screenshot(self)
+    }
+
+    fn savegame(&self) -> Option<&[u8]> {
+        self.cartridge().savegame()
+    }
+
+    fn savegame_mut(&mut self) -> Option<&mut [u8]> {
+        self.cartridge_mut().savegame_mut()
+    }
+}
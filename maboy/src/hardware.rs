@@ -0,0 +1,52 @@
+//! The Game Boy models that the emulator can pretend to be (see
+//! [`crate::Emulator::new_with_model`])
+
+use crate::memory::DMG_BOOT_ROM;
+
+/// A hardware revision of the Game Boy. The models run games the same way (there is no
+/// Game Boy Color mode, so a [`HardwareModel::Cgb`] runs them in compatibility mode),
+/// but differ in the register values that their boot ROM leaves behind, which games
+/// use to detect the model they are running on, and in a few hardware quirks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum HardwareModel {
+    /// The first revision of the original Game Boy, which was only sold in Japan
+    Dmg0,
+    /// The original Game Boy. This is the default.
+    #[default]
+    Dmg,
+    /// The Game Boy Pocket (and Light)
+    Mgb,
+    /// The Super Game Boy
+    Sgb,
+    /// The Game Boy Color
+    Cgb,
+}
+
+impl HardwareModel {
+    /// The boot ROM that the emulator runs for this model unless another one is set via
+    /// [`crate::Emulator::with_boot_rom`]. Only the boot ROMs of the original Game Boy
+    /// and the Game Boy Pocket (which differs in a single byte) are built in, so the
+    /// other models start without one.
+    pub fn built_in_boot_rom(self) -> Option<[u8; 256]> {
+        match self {
+            HardwareModel::Dmg => Some(DMG_BOOT_ROM),
+            HardwareModel::Mgb => {
+                // The Game Boy Pocket hands 0xFF to the game in register A instead of 0x01
+                let mut boot_rom = DMG_BOOT_ROM;
+                boot_rom[0xFD] = 0xFF;
+                Some(boot_rom)
+            }
+            HardwareModel::Dmg0 | HardwareModel::Sgb | HardwareModel::Cgb => None,
+        }
+    }
+
+    /// See [`crate::Emulator::set_stat_write_bug`]
+    pub fn has_stat_write_bug(self) -> bool {
+        !matches!(self, HardwareModel::Cgb)
+    }
+
+    /// See [`crate::Emulator::set_serial_high_speed`]
+    pub fn has_serial_high_speed(self) -> bool {
+        matches!(self, HardwareModel::Cgb)
+    }
+}
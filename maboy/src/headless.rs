@@ -0,0 +1,199 @@
+//! Drives the emulator without any window, input or timing, as fast as the host
+//! allows. This is useful for scripting, automated testing and anything else that
+//! doesn't need a human looking at the screen.
+//!
+//! ```no_run
+//! # use maboy::{headless::HeadlessRunner, CartridgeVariant};
+//! # fn main() -> Result<(), maboy::CartridgeParseError> {
+//! let mut runner = HeadlessRunner::new(CartridgeVariant::from_file("tetris.gb")?);
+//! runner.schedule_screenshot(600);
+//!
+//! let hashes = runner.collect_hashes(1000);
+//! let screenshot = runner.take_screenshots().remove(0);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::debug::{CpuEvt, DbgEvtSrc, NoDbgLogger, PpuEvt};
+use crate::frame_dump::{DumpFormat, FrameDump};
+use crate::pixel_format;
+use crate::util::fnv1a;
+use crate::{Cartridge, Emulator, FrameResult, MemPixel};
+use std::io;
+use std::path::Path;
+
+/// Width of a frame in pixels
+pub const FRAME_WIDTH: usize = 160;
+
+/// Height of a frame in pixels
+pub const FRAME_HEIGHT: usize = 144;
+
+/// A frame that was captured via [`HeadlessRunner::schedule_screenshot`]
+#[derive(Clone)]
+pub struct Screenshot {
+    /// The frame number (see [`HeadlessRunner::frame_count`])
+    pub frame: u64,
+    /// `FRAME_WIDTH * FRAME_HEIGHT` pixels, row by row
+    pub pixels: Vec<MemPixel>,
+}
+
+/// Runs an [`Emulator`] frame by frame at unthrottled speed and keeps the last frame
+/// around. Frames where the LCD is turned off are blank (the color of a turned off LCD),
+/// so every frame has the same size and can be compared or hashed.
+pub struct HeadlessRunner<C, CpuDbg = NoDbgLogger, PpuDbg = NoDbgLogger> {
+    emu: Emulator<C, CpuDbg, PpuDbg>,
+    frame: Vec<MemPixel>,
+    frame_count: u64,
+    /// Frame numbers that should be captured, sorted in descending order so the next
+    /// one is always at the end
+    scheduled_screenshots: Vec<u64>,
+    screenshots: Vec<Screenshot>,
+}
+
+impl<C: Cartridge> HeadlessRunner<C> {
+    /// Uses the cached interpreter (see [`Emulator::set_block_cache`]), since nobody is
+    /// looking at the individual memory accesses here
+    pub fn new(cartridge: C) -> Self {
+        let mut emu = Emulator::new(cartridge);
+        emu.set_block_cache(true);
+        Self::from_emulator(emu)
+    }
+}
+
+impl<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>
+    HeadlessRunner<C, CpuDbg, PpuDbg>
+{
+    /// Wraps an existing emulator, e.g. one with a debugger attached or a savestate loaded
+    pub fn from_emulator(emu: Emulator<C, CpuDbg, PpuDbg>) -> Self {
+        HeadlessRunner {
+            frame: vec![emu.dmg_palette()[0]; FRAME_WIDTH * FRAME_HEIGHT],
+            emu,
+            frame_count: 0,
+            scheduled_screenshots: Vec::new(),
+            screenshots: Vec::new(),
+        }
+    }
+
+    pub fn emulator(&self) -> &Emulator<C, CpuDbg, PpuDbg> {
+        &self.emu
+    }
+
+    /// Use this to press buttons, load savestates and so on between frames
+    pub fn emulator_mut(&mut self) -> &mut Emulator<C, CpuDbg, PpuDbg> {
+        &mut self.emu
+    }
+
+    pub fn into_emulator(self) -> Emulator<C, CpuDbg, PpuDbg> {
+        self.emu
+    }
+
+    /// Number of frames that were run so far. The first frame is frame 1.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// The most recent frame, or a blank one if no frame was run yet
+    pub fn frame(&self) -> &[MemPixel] {
+        &self.frame
+    }
+
+    /// A hash of the most recent frame. Two frames with the same content always have
+    /// the same hash, so this is a cheap way to compare frames against known-good ones.
+    pub fn frame_hash(&self) -> u64 {
+        hash_frame(&self.frame)
+    }
+
+    /// Runs a single frame and returns it
+    pub fn run_frame(&mut self) -> &[MemPixel] {
+        match self.emu.run_frame() {
+            FrameResult::Frame(frame) => self.frame.copy_from_slice(frame),
+            FrameResult::LcdOff => self.frame.fill(self.emu.dmg_palette()[0]),
+        }
+
+        self.frame_count += 1;
+
+        while self.scheduled_screenshots.last() == Some(&self.frame_count) {
+            self.scheduled_screenshots.pop();
+            self.screenshots.push(Screenshot {
+                frame: self.frame_count,
+                pixels: self.frame.clone(),
+            });
+        }
+
+        &self.frame
+    }
+
+    pub fn run_frames(&mut self, count: u64) {
+        for _ in 0..count {
+            self.run_frame();
+        }
+    }
+
+    /// Runs frames until [`HeadlessRunner::frame_count`] reaches `frame` and returns the
+    /// most recent frame. Doesn't run anything if that frame was already reached.
+    pub fn run_until_frame(&mut self, frame: u64) -> &[MemPixel] {
+        while self.frame_count < frame {
+            self.run_frame();
+        }
+
+        &self.frame
+    }
+
+    /// Runs `count` frames and returns copies of all of them
+    pub fn collect_frames(&mut self, count: u64) -> Vec<Vec<MemPixel>> {
+        (0..count).map(|_| self.run_frame().to_vec()).collect()
+    }
+
+    /// Runs `count` frames and returns their hashes (see [`HeadlessRunner::frame_hash`])
+    pub fn collect_hashes(&mut self, count: u64) -> Vec<u64> {
+        (0..count).map(|_| hash_frame(self.run_frame())).collect()
+    }
+
+    /// Runs `count` frames and writes them to `path` (see [`crate::frame_dump`])
+    pub fn dump_frames<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        format: DumpFormat,
+        count: usize,
+    ) -> io::Result<()> {
+        let mut dump = FrameDump::start(path, format, count)?;
+
+        while !dump.push_frame(self.run_frame())? {}
+
+        Ok(())
+    }
+
+    /// Captures the given frame as soon as it is reached by any of the `run_*` or
+    /// `collect_*` methods. Frames that were already run (or scheduled) are ignored.
+    pub fn schedule_screenshot(&mut self, frame: u64) {
+        if frame <= self.frame_count {
+            log::warn!(
+                "Frame {} was already run, no screenshot will be taken",
+                frame
+            );
+            return;
+        }
+
+        if self.scheduled_screenshots.contains(&frame) {
+            return;
+        }
+
+        let idx = self
+            .scheduled_screenshots
+            .iter()
+            .position(|&scheduled| scheduled < frame)
+            .unwrap_or(self.scheduled_screenshots.len());
+
+        self.scheduled_screenshots.insert(idx, frame);
+    }
+
+    /// All screenshots that were captured so far (in the order of their frames). They
+    /// are removed from the runner.
+    pub fn take_screenshots(&mut self) -> Vec<Screenshot> {
+        std::mem::take(&mut self.screenshots)
+    }
+}
+
+fn hash_frame(frame: &[MemPixel]) -> u64 {
+    fnv1a(pixel_format::as_rgba8(frame).iter().copied())
+}
@@ -0,0 +1,346 @@
+//! Just enough HTTP/1.1 for the servers that frontends poll once per frame (see
+//! [`crate::state_server`] and the `remote` module). Every connection carries exactly one
+//! request, and request bodies are ignored, so all parameters go into the query string.
+//! Of the headers, only `Origin` is looked at (see [`Access`]).
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// How long a client may take to send its request line and headers in total before it is
+/// dropped. Requests are answered on the emulation thread, so a slow client must not
+/// stall it.
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// How long a single write of the response may block, so a client that stops reading
+/// can't stall the emulation thread either
+const WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// At most this many bytes of a request are read. Longer requests are cut off.
+const MAX_REQUEST_LEN: u64 = 8 * 1024;
+
+/// Who may talk to a server from a web page. Programs other than browsers don't send an
+/// `Origin` header and are always allowed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// Every response allows cross-origin requests, so a web page from anywhere can read
+    /// them. Only for servers that don't change any state.
+    AnyOrigin,
+    /// Requests from web pages that weren't loaded from `localhost` are rejected, and
+    /// responses don't allow cross-origin reads. Otherwise any web page the user has open
+    /// could send requests to the server, since browsers send simple POST requests to
+    /// other origins without asking first.
+    LocalOrigin,
+}
+
+pub(crate) struct Request {
+    pub method: String,
+    /// Without the query string and without a trailing `/`
+    pub path: String,
+    query: Vec<(String, String)>,
+    /// The value of the `Origin` header, which browsers send with cross-origin requests
+    origin: Option<String>,
+}
+
+impl Request {
+    /// The (percent-decoded) value of a query parameter
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub(crate) struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(json: String) -> Response {
+        Response {
+            status: "200 OK",
+            content_type: "application/json",
+            body: json.into_bytes(),
+        }
+    }
+
+    pub fn error(status: &'static str, msg: &str) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::json!({ "error": msg }).to_string().into_bytes(),
+        }
+    }
+
+    /// 200 if serialization worked, 500 otherwise
+    pub fn from_json(json: serde_json::Result<String>) -> Response {
+        match json {
+            Ok(json) => Response::json(json),
+            Err(err) => Response::error("500 Internal Server Error", &err.to_string()),
+        }
+    }
+}
+
+/// A non-blocking listener. `name` is only used for logging.
+pub(crate) fn bind<A: ToSocketAddrs>(addr: A, name: &str) -> io::Result<TcpListener> {
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+
+    log::info!("{} listening on {}", name, listener.local_addr()?);
+
+    Ok(listener)
+}
+
+/// Answers all requests that arrived since the last call, without blocking
+pub(crate) fn serve_pending<F: FnMut(&Request) -> Response>(
+    listener: &TcpListener,
+    name: &str,
+    access: Access,
+    mut handler: F,
+) {
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(err) = respond(stream, access, &mut handler) {
+                    log::warn!("{} could not answer a request ({:?})", name, err);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+            Err(err) => {
+                log::warn!("{} could not accept a connection ({:?})", name, err);
+                return;
+            }
+        }
+    }
+}
+
+fn respond<F: FnMut(&Request) -> Response>(
+    mut stream: TcpStream,
+    access: Access,
+    handler: &mut F,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+
+    let head = read_request_head(&stream)?;
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+
+    let origin = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("origin"))
+        .map(|(_, value)| value.trim().to_owned());
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let target = parts.next().unwrap_or_default();
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect();
+
+    let request = Request {
+        method,
+        path: path.trim_end_matches('/').to_owned(),
+        query,
+        origin,
+    };
+
+    let response = match (access, &request.origin) {
+        (Access::LocalOrigin, Some(origin)) if !is_local_origin(origin) => Response::error(
+            "403 Forbidden",
+            "Only web pages from localhost may send requests",
+        ),
+        _ => handler(&request),
+    };
+
+    let cors_header = match access {
+        Access::AnyOrigin => "Access-Control-Allow-Origin: *\r\n",
+        Access::LocalOrigin => "",
+    };
+
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        cors_header,
+    )?;
+    stream.write_all(&response.body)?;
+
+    stream.flush()
+}
+
+/// Reads the request line and the headers, up to the empty line that ends them. Bodies are
+/// ignored. The read timeout only applies to a single read, so it is shortened before
+/// every read to make [`REQUEST_TIMEOUT`] a deadline for the whole head.
+fn read_request_head(stream: &TcpStream) -> io::Result<String> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_LEN));
+    let mut head = Vec::new();
+
+    loop {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "Request took too long"))?;
+        stream.set_read_timeout(Some(remaining))?;
+
+        let buf = reader.fill_buf()?;
+
+        // The client closed the connection, or the request is too long
+        if buf.is_empty() {
+            break;
+        }
+
+        let len = buf.len();
+        head.extend_from_slice(buf);
+        reader.consume(len);
+
+        if let Some(end) = find_end_of_head(&head) {
+            head.truncate(end);
+            break;
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Where the empty line after the headers starts, if it was received already. Accepts bare
+/// `\n` line endings as well.
+fn find_end_of_head(head: &[u8]) -> Option<usize> {
+    head.windows(2)
+        .position(|pair| pair == b"\n\n")
+        .or_else(|| head.windows(3).position(|triple| triple == b"\n\r\n"))
+        .map(|end| end + 1)
+}
+
+/// Whether an `Origin` header names a page that was loaded from this machine, e.g.
+/// `http://localhost:8080`. Pages without a real origin (`null`, e.g. local files) are
+/// not trusted.
+fn is_local_origin(origin: &str) -> bool {
+    let authority = match origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+    {
+        Some(authority) => authority.trim_end_matches('/'),
+        None => return false,
+    };
+
+    let host = match authority.strip_prefix('[') {
+        // IPv6, e.g. [::1]:8080
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+
+    host.eq_ignore_ascii_case("localhost") || host == "127.0.0.1" || host == "::1"
+}
+
+/// Decodes `%XX` escapes and `+`. Invalid escapes are kept as they are.
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (byte, escaped) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            (b'+', _) => {
+                bytes.push(b' ');
+                rest = tail;
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+
+    /// Sends `request` to a server with the given access and returns the response
+    fn round_trip(access: Access, request: &str) -> String {
+        let listener = bind("127.0.0.1:0", "Test server").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        serve_pending(&listener, "Test server", access, |request| {
+            Response::json(format!("\"{} {}\"", request.method, request.path))
+        });
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn local_origins() {
+        assert!(is_local_origin("http://localhost"));
+        assert!(is_local_origin("http://localhost:8080"));
+        assert!(is_local_origin("https://127.0.0.1:3000"));
+        assert!(is_local_origin("http://[::1]:8080"));
+
+        assert!(!is_local_origin("null"));
+        assert!(!is_local_origin("https://example.com"));
+        assert!(!is_local_origin("http://localhost.example.com"));
+        assert!(!is_local_origin("http://127.0.0.1.example.com"));
+    }
+
+    #[test]
+    fn local_origin_access_rejects_other_web_pages() {
+        let response = round_trip(
+            Access::LocalOrigin,
+            "POST /pause HTTP/1.1\r\nHost: localhost\r\nOrigin: https://example.com\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        assert!(!response.contains("Access-Control-Allow-Origin"));
+
+        let response = round_trip(
+            Access::LocalOrigin,
+            "POST /pause HTTP/1.1\r\norigin: http://localhost:8080\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("\"POST /pause\""));
+        assert!(!response.contains("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn requests_without_origin_are_allowed() {
+        let response = round_trip(Access::LocalOrigin, "POST /pause HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        let response = round_trip(
+            Access::AnyOrigin,
+            "GET /cpu/ HTTP/1.1\r\nOrigin: https://example.com\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("Access-Control-Allow-Origin: *"));
+        assert!(response.ends_with("\"GET /cpu\""));
+    }
+}
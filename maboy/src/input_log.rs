@@ -0,0 +1,327 @@
+//! Input recording and deterministic playback, e.g. for tool-assisted speedruns or for
+//! reproducing bugs.
+//!
+//! A [`Movie`] consists of a savestate and the buttons that were held down during each
+//! frame after it. Since the emulator is deterministic, loading the savestate and
+//! feeding it the same buttons at the same points in time reproduces the recording
+//! exactly.
+//!
+//! The important part is "at the same points in time": While a recording is running
+//! (or an [`InputProvider`] is installed), button changes from the frontend don't
+//! reach the game right away. They are latched, and only applied at the start of the
+//! next *input frame*. Input frames are [`crate::MCYCLES_PER_FRAME`] machine cycles
+//! long and start when the recording starts. They are not synchronized to the PPU, so
+//! they keep ticking while the LCD is off.
+//!
+//! An [`InputProvider`] replaces the frontend as the source of button presses. Playing
+//! back a movie is the most obvious use, but a provider can also be a script or a bot:
+//!
+//! ```ignore
+//! emu.set_input_provider(|frame: u64| match frame {
+//!     0..=59 => Some(Buttons::empty()),
+//!     60..=64 => Some(Buttons::START),
+//!     _ => None, // Hand control back to the frontend
+//! });
+//! ```
+
+use crate::{Buttons, MCYCLES_PER_FRAME};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// A source of button presses. Installed via [`crate::Emulator::set_input_provider`].
+/// Needs to be `Send`, so the emulator can be moved to another thread.
+pub trait InputProvider: Send {
+    /// Called at the start of every input frame (counting from 0 when the provider was
+    /// installed). Returns the buttons that are held down during that frame, or `None`
+    /// if the provider is done, in which case it is removed and the frontend takes over
+    /// again.
+    fn buttons(&mut self, frame: u64) -> Option<Buttons>;
+}
+
+impl<F: FnMut(u64) -> Option<Buttons> + Send> InputProvider for F {
+    fn buttons(&mut self, frame: u64) -> Option<Buttons> {
+        self(frame)
+    }
+}
+
+/// A recording of the buttons that were held down during each input frame, together
+/// with the savestate that the recording started from
+#[derive(Clone)]
+pub struct Movie {
+    pub initial_state: Vec<u8>,
+    pub inputs: Vec<Buttons>,
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    /// The data does not start with the movie magic bytes
+    InvalidMagic,
+    /// The movie was created by an incompatible version of MaBoy
+    UnsupportedVersion(u8),
+    /// The data ended before the whole movie could be read
+    UnexpectedEnd,
+}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            MovieError::UnexpectedEnd
+        } else {
+            MovieError::Io(err)
+        }
+    }
+}
+
+/// Identifies a MaBoy movie
+const MAGIC: &[u8; 4] = b"MBMV";
+
+/// Needs to be bumped whenever the file layout changes. Changes to the savestate layout
+/// are covered by the savestate version.
+const MOVIE_VERSION: u8 = 1;
+
+impl Movie {
+    /// The number of recorded input frames
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Writes the movie in MaBoy's movie format (gzip-compressed):
+    ///
+    /// - magic bytes `MBMV` and a version byte
+    /// - the length of the savestate (u32, little-endian), followed by the savestate
+    /// - the number of frames (u32, little-endian), followed by one byte per frame, with
+    ///   the bits of [`Buttons`]
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+
+        encoder.write_all(MAGIC)?;
+        encoder.write_all(&[MOVIE_VERSION])?;
+        encoder.write_all(&(self.initial_state.len() as u32).to_le_bytes())?;
+        encoder.write_all(&self.initial_state)?;
+        encoder.write_all(&(self.inputs.len() as u32).to_le_bytes())?;
+
+        let inputs: Vec<u8> = self.inputs.iter().map(|buttons| buttons.bits()).collect();
+        encoder.write_all(&inputs)?;
+
+        encoder.finish()?.flush()
+    }
+
+    /// Reads a movie that was written by [`Movie::write_to`]. The savestate is only
+    /// validated once the movie is played.
+    pub fn read_from<R: Read>(reader: R) -> Result<Movie, MovieError> {
+        let mut decoder = GzDecoder::new(reader);
+
+        let mut magic = [0; 4];
+        decoder.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(MovieError::InvalidMagic);
+        }
+
+        let mut version = [0];
+        decoder.read_exact(&mut version)?;
+
+        if version[0] != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version[0]));
+        }
+
+        let initial_state = read_chunk(&mut decoder)?;
+
+        // All 8 bits of Buttons are in use, so every byte is a valid frame
+        let inputs = read_chunk(&mut decoder)?
+            .into_iter()
+            .map(Buttons::from_bits_truncate)
+            .collect();
+
+        Ok(Movie {
+            initial_state,
+            inputs,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Movie, MovieError> {
+        Movie::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+/// A length-prefixed block of bytes
+fn read_chunk<R: Read>(reader: &mut R) -> Result<Vec<u8>, MovieError> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+
+    let len = u32::from_le_bytes(len) as u64;
+
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+
+    if data.len() as u64 != len {
+        return Err(MovieError::UnexpectedEnd);
+    }
+
+    Ok(data)
+}
+
+/// Plays back the inputs of a [`Movie`]. The savestate is not touched; Use
+/// [`crate::Emulator::play_movie`] to start the playback from the right state.
+pub struct MoviePlayer {
+    inputs: Vec<Buttons>,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> MoviePlayer {
+        MoviePlayer {
+            inputs: movie.inputs,
+        }
+    }
+}
+
+impl InputProvider for MoviePlayer {
+    fn buttons(&mut self, frame: u64) -> Option<Buttons> {
+        self.inputs.get(frame as usize).copied()
+    }
+}
+
+/// Keeps track of input frames, the installed [`InputProvider`] and a running
+/// recording. Lives in the emulator, which asks it for the buttons at the start of
+/// every input frame.
+pub(crate) struct InputLog {
+    provider: Option<Box<dyn InputProvider>>,
+    /// The frame number that the next call of the provider gets
+    provider_frame: u64,
+    recording: Option<Movie>,
+    /// What the frontend wants the buttons to be. Applied at the next input frame if
+    /// there is no provider.
+    latched: Buttons,
+    /// The machine cycle at which the next input frame starts. `u64::MAX` if there
+    /// neither is a provider nor a recording, so the check in the emulation loop stays
+    /// cheap.
+    next_frame_at: u64,
+}
+
+impl InputLog {
+    pub fn new() -> InputLog {
+        InputLog {
+            provider: None,
+            provider_frame: 0,
+            recording: None,
+            latched: Buttons::empty(),
+            next_frame_at: u64::MAX,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.provider.is_some() || self.recording.is_some()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn has_provider(&self) -> bool {
+        self.provider.is_some()
+    }
+
+    /// The buttons that the frontend changes while inputs are latched, or `None` if
+    /// the frontend's changes should be applied right away
+    pub fn latched_buttons(&mut self) -> Option<&mut Buttons> {
+        if self.is_active() {
+            Some(&mut self.latched)
+        } else {
+            None
+        }
+    }
+
+    /// Starts a new input frame at `mcycles`, i.e. right away. `held` are the buttons
+    /// that the frontend currently holds down.
+    pub fn set_provider(&mut self, provider: Box<dyn InputProvider>, held: Buttons, mcycles: u64) {
+        self.restart_frames(held, mcycles);
+        self.provider = Some(provider);
+        self.provider_frame = 0;
+    }
+
+    pub fn take_provider(&mut self) -> Option<Box<dyn InputProvider>> {
+        let provider = self.provider.take();
+        self.update_next_frame();
+        provider
+    }
+
+    /// Like [`InputLog::set_provider`], the first input frame starts right away
+    pub fn start_recording(&mut self, initial_state: Vec<u8>, held: Buttons, mcycles: u64) {
+        self.restart_frames(held, mcycles);
+        self.recording = Some(Movie {
+            initial_state,
+            inputs: Vec::new(),
+        });
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        let movie = self.recording.take();
+        self.update_next_frame();
+        movie
+    }
+
+    #[inline]
+    pub fn frame_due(&self, mcycles: u64) -> bool {
+        mcycles >= self.next_frame_at
+    }
+
+    /// How many machine cycles are left until the next input frame is due
+    pub fn mcycles_until_frame(&self, mcycles: u64) -> u64 {
+        self.next_frame_at.saturating_sub(mcycles)
+    }
+
+    /// Starts the next input frame and returns the buttons that are held down during it
+    pub fn next_frame(&mut self) -> Buttons {
+        let frame = self.provider_frame;
+        let provided = self
+            .provider
+            .as_mut()
+            .and_then(|provider| provider.buttons(frame));
+
+        self.provider_frame += 1;
+
+        if provided.is_none() && self.provider.take().is_some() {
+            log::info!("Input provider finished, the frontend controls the buttons again");
+        }
+
+        let buttons = provided.unwrap_or(self.latched);
+
+        if let Some(movie) = self.recording.as_mut() {
+            movie.inputs.push(buttons);
+        }
+
+        // Added to the previous start (instead of the current cycle), so frames don't
+        // drift because instructions overshoot the boundary
+        self.next_frame_at += MCYCLES_PER_FRAME;
+        self.update_next_frame();
+
+        buttons
+    }
+
+    fn restart_frames(&mut self, held: Buttons, mcycles: u64) {
+        if !self.is_active() {
+            self.latched = held;
+        }
+
+        self.next_frame_at = mcycles;
+    }
+
+    fn update_next_frame(&mut self) {
+        if !self.is_active() {
+            self.next_frame_at = u64::MAX;
+        }
+    }
+}
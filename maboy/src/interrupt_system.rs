@@ -1,5 +1,6 @@
 //! Useful structs and enums concerning interrupt handling on the CPU
 
+use super::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use super::util::BitOps;
 
 /// Provides storage for the two interrupt related registers (IF and IE)
@@ -12,7 +13,7 @@ pub struct InterruptSystem {
 /// All interrupts that can occur on the Game Boy system. The value of each
 /// variant is a bitmask that can be used on IF/IE to set the corresponding
 /// interrupt bit.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Interrupt {
     VBlank = 1 << 0,
@@ -22,6 +23,28 @@ pub enum Interrupt {
     Joypad = 1 << 4,
 }
 
+impl Interrupt {
+    /// In order of priority
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LcdStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Lowercase name, e.g. for the debugger
+    pub fn name(self) -> &'static str {
+        match self {
+            Interrupt::VBlank => "vblank",
+            Interrupt::LcdStat => "stat",
+            Interrupt::Timer => "timer",
+            Interrupt::Serial => "serial",
+            Interrupt::Joypad => "joypad",
+        }
+    }
+}
+
 /// The read-mask if the IF register
 const IF_MASK: u8 = 0b_1110_0000;
 
@@ -76,3 +99,16 @@ impl InterruptSystem {
         self.if_reg |= interrupt as u8
     }
 }
+
+impl SaveState for InterruptSystem {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.if_reg);
+        writer.write_u8(self.ie_reg);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.write_if(reader.read_u8()?);
+        self.ie_reg = reader.read_u8()?;
+        Ok(())
+    }
+}
@@ -3,9 +3,19 @@
 //! some of the methods on [`JoyPad`] are exposed through the library API.
 
 use super::interrupt_system::{Interrupt, InterruptSystem};
+use super::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use bitflags::bitflags;
+use std::fmt;
+use std::str::FromStr;
 
-/// Storage for the P1/JOYP register and the states of all buttons
+/// Storage for the P1/JOYP register and the states of all buttons.
+///
+/// The buttons are wired as a 2x4 matrix: Bits 4 and 5 of P1 select the directional
+/// buttons and the other buttons (active low), and the lower 4 bits are the input lines,
+/// which every selected button pulls low while it is pressed. The joypad interrupt is
+/// requested whenever one of these input lines goes from high to low, which can happen
+/// because a button of a selected group is pressed, or because a group with pressed
+/// buttons is selected.
 pub struct JoyPad {
     /// aka JOYP
     p1_reg: u8,
@@ -14,13 +24,21 @@ pub struct JoyPad {
     pressed: Buttons,
     /// Which group of buttons is currently mapped to the P1 register
     active_buttons: ActiveButtonGroup,
+    /// Whether button changes bounce (see [`JoyPad::set_bounce`])
+    bounce: bool,
+    /// The machine cycle of the last change of every button (by bit index), while
+    /// `bounce` is enabled
+    changed_at: [u64; 8],
 }
 
 enum ActiveButtonGroup {
     Neither,
     Directional,
     General, // TODO: Think of a better name
-    Both,    // TODO: Investigate if this state is even possible
+    /// Both groups are connected to the input lines at once, so each line is low if
+    /// either of its two buttons is pressed. Some games use this to detect the Super
+    /// Game Boy, which answers with the player ID instead when neither is selected.
+    Both,
 }
 
 bitflags! {
@@ -39,33 +57,150 @@ bitflags! {
     }
 }
 
+/// The bits of A, B, Select and Start, which many games interpret as a soft reset request
+/// when held down together
+const RESET_COMBO: u8 = 0b_1111_0000;
+
+/// Controls what the emulator does when A+B+Select+Start are held down at the same time.
+/// Regardless of the setting, the buttons are always passed through to the game.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResetCombo {
+    /// The combination is not treated specially (default)
+    Disabled,
+    /// The emulator performs a hardware-style reset at the next frame boundary and
+    /// reports it via [`Emulator::poll_reset_combo`]
+    Reset,
+    /// The combination is only reported via [`Emulator::poll_reset_combo`], leaving
+    /// it to the frontend to decide what to do.
+    Notify,
+}
+
 /// The write-mask of the P1 register
 const P1_MASK: u8 = 0b_0011_0000;
 
+/// How long a button bounces after it was pressed or released (about 1 ms)
+const BOUNCE_MCYCLES: u64 = 1024;
+
+/// How long each bounce lasts. The line alternates between the old and the new state
+/// 8 times before it settles.
+const BOUNCE_PERIOD: u64 = BOUNCE_MCYCLES / 8;
+
+/// `changed_at` of buttons that never changed
+const NEVER_CHANGED: u64 = u64::MAX;
+
 impl JoyPad {
     pub fn new() -> JoyPad {
         JoyPad {
             p1_reg: 0xff,
             pressed: Buttons::all(),
             active_buttons: ActiveButtonGroup::Neither,
+            bounce: false,
+            changed_at: [NEVER_CHANGED; 8],
         }
     }
 
-    pub fn read_p1(&self) -> u8 {
-        (self.p1_reg & 0xf0)
-            | match self.active_buttons {
-                ActiveButtonGroup::Neither => 0,
-                ActiveButtonGroup::Directional => self.pressed.bits() & 0x0f,
-                ActiveButtonGroup::General => self.pressed.bits() >> 4,
-                ActiveButtonGroup::Both => {
-                    (self.pressed.bits() & 0x0f) | (self.pressed.bits() >> 4)
+    /// See documentation at [`Emulator::set_joypad_bounce`]
+    pub fn set_bounce(&mut self, bounce: bool) {
+        self.bounce = bounce;
+        self.changed_at = [NEVER_CHANGED; 8];
+    }
+
+    pub fn bounce(&self) -> bool {
+        self.bounce
+    }
+
+    /// Resets the P1 register. The state of the buttons is not affected, since
+    /// the user is probably still holding them down.
+    pub fn reset(&mut self) {
+        self.p1_reg = 0xff;
+        self.active_buttons = ActiveButtonGroup::Neither;
+    }
+
+    /// The buttons that are currently held down (in the format of
+    /// [`Emulator::notify_buttons_state`])
+    pub fn held_buttons(&self) -> Buttons {
+        !self.pressed
+    }
+
+    /// Changes the held buttons without requesting a joypad interrupt, e.g. to put the
+    /// buttons into a known state before playing back a movie
+    pub fn set_held_buttons(&mut self, buttons: Buttons) {
+        self.pressed = !buttons;
+    }
+
+    /// Whether A+B+Select+Start are all currently held down
+    pub fn reset_combo_held(&self) -> bool {
+        self.pressed.bits() & RESET_COMBO == 0
+    }
+
+    /// Whether any button of the currently selected group(s) is pressed, i.e. whether
+    /// one of the input lines of P1 is low
+    pub fn input_low(&self) -> bool {
+        self.input_lines(self.pressed.bits()) != 0x0f
+    }
+
+    pub fn read_p1(&self, mcycles: u64) -> u8 {
+        (self.p1_reg & 0xf0) | self.input_lines(self.lines(mcycles))
+    }
+
+    /// The four input lines of P1 for the given state of all buttons (in the format of
+    /// `pressed`). Selecting both groups connects both of them to the input lines, so a
+    /// line is low if a button of either group is pressed. Selecting neither leaves all
+    /// lines high.
+    fn input_lines(&self, buttons: u8) -> u8 {
+        match self.active_buttons {
+            ActiveButtonGroup::Neither => 0x0f,
+            ActiveButtonGroup::Directional => buttons & 0x0f,
+            ActiveButtonGroup::General => buttons >> 4,
+            ActiveButtonGroup::Both => (buttons & 0x0f) & (buttons >> 4),
+        }
+    }
+
+    /// Requests the joypad interrupt if one of the input lines went low since they were
+    /// `old_lines`. This ignores bouncing, which would otherwise request one interrupt
+    /// per bounce.
+    fn check_falling_edge(&self, ir_system: &mut InterruptSystem, old_lines: u8) {
+        if old_lines & !self.input_lines(self.pressed.bits()) != 0 {
+            ir_system.schedule_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    /// The state of all buttons as the game sees it right now (in the format of
+    /// `pressed`), which differs from `pressed` while buttons bounce
+    fn lines(&self, mcycles: u64) -> u8 {
+        let mut lines = self.pressed.bits();
+
+        if !self.bounce {
+            return lines;
+        }
+
+        for (bit, &changed_at) in self.changed_at.iter().enumerate() {
+            // None after savestates were loaded, since time may have gone backwards
+            let elapsed = mcycles.checked_sub(changed_at);
+
+            if let Some(elapsed) = elapsed.filter(|&elapsed| elapsed < BOUNCE_MCYCLES) {
+                // Every other period, starting right away, the line is back in the old
+                // state
+                if (elapsed / BOUNCE_PERIOD).is_multiple_of(2) {
+                    lines ^= 1 << bit;
                 }
             }
+        }
+
+        lines
     }
 
-    pub fn write_p1(&mut self, val: u8) {
+    /// Selecting a group with pressed buttons pulls input lines low, which requests the
+    /// joypad interrupt just like pressing them does
+    pub fn write_p1(&mut self, ir_system: &mut InterruptSystem, val: u8) {
+        let old_lines = self.input_lines(self.pressed.bits());
+
         self.p1_reg = (self.p1_reg & (!P1_MASK)) | (val & P1_MASK);
+        self.update_active_buttons();
+        self.check_falling_edge(ir_system, old_lines);
+    }
 
+    fn update_active_buttons(&mut self) {
         self.active_buttons = match self.p1_reg & 0b_0011_0000 {
             0b_0000_0000 => ActiveButtonGroup::Both,
             0b_0001_0000 => ActiveButtonGroup::General,
@@ -76,27 +211,223 @@ impl JoyPad {
     }
 
     /// See documentation at [`Emulator::notify_buttons_pressed`]
-    pub fn notify_buttons_pressed(&mut self, ir_system: &mut InterruptSystem, buttons: Buttons) {
-        if self.pressed.bits() & buttons.bits() != 0 {
-            ir_system.schedule_interrupt(Interrupt::Joypad);
-        }
-
-        self.pressed.remove(buttons);
+    pub fn notify_buttons_pressed(
+        &mut self,
+        ir_system: &mut InterruptSystem,
+        buttons: Buttons,
+        mcycles: u64,
+    ) {
+        let old_lines = self.input_lines(self.pressed.bits());
+        self.set_pressed(self.pressed - buttons, mcycles);
+        self.check_falling_edge(ir_system, old_lines);
     }
 
     /// See documentation at [`Emulator::notify_buttons_released`]
-    pub fn notify_buttons_released(&mut self, buttons: Buttons) {
-        self.pressed.insert(buttons);
+    pub fn notify_buttons_released(&mut self, buttons: Buttons, mcycles: u64) {
+        self.set_pressed(self.pressed | buttons, mcycles);
     }
 
     /// See documentation at [`Emulator::notify_buttons_state`]
-    pub fn notify_buttons_state(&mut self, ir_system: &mut InterruptSystem, buttons: Buttons) {
-        if self.pressed.bits() & buttons.bits() != 0 {
-            ir_system.schedule_interrupt(Interrupt::Joypad);
+    pub fn notify_buttons_state(
+        &mut self,
+        ir_system: &mut InterruptSystem,
+        buttons: Buttons,
+        mcycles: u64,
+    ) {
+        let old_lines = self.input_lines(self.pressed.bits());
+        self.set_pressed(!buttons, mcycles);
+        self.check_falling_edge(ir_system, old_lines);
+    }
+
+    fn set_pressed(&mut self, pressed: Buttons, mcycles: u64) {
+        if self.bounce {
+            let changed = (self.pressed ^ pressed).bits();
+
+            for (bit, changed_at) in self.changed_at.iter_mut().enumerate() {
+                if changed & (1 << bit) != 0 {
+                    *changed_at = mcycles;
+                }
+            }
+        }
+
+        self.pressed = pressed;
+    }
+}
+
+/// The state of the buttons is not part of a savestate, since it is controlled by the
+/// user, not by the game.
+impl SaveState for JoyPad {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.p1_reg);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.p1_reg = reader.read_u8()?;
+        self.update_active_buttons();
+
+        Ok(())
+    }
+}
+
+/// Canonical names of all buttons, in the order they appear in textual representations
+const BUTTON_NAMES: [(Buttons, &str); 8] = [
+    (Buttons::RIGHT, "RIGHT"),
+    (Buttons::LEFT, "LEFT"),
+    (Buttons::UP, "UP"),
+    (Buttons::DOWN, "DOWN"),
+    (Buttons::A, "A"),
+    (Buttons::B, "B"),
+    (Buttons::SELECT, "SELECT"),
+    (Buttons::START, "START"),
+];
+
+/// Written when no button is set
+const NO_BUTTONS: &str = "NONE";
+
+/// The canonical textual representation of a set of buttons, e.g. `A|START`. Buttons
+/// are always written in the same order, and an empty set is written as `NONE`. This
+/// is what config files, input scripts and any other textual formats should use.
+impl fmt::Display for Buttons {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str(NO_BUTTONS);
+        }
+
+        let mut first = true;
+
+        for (button, name) in BUTTON_NAMES.iter() {
+            if self.contains(*button) {
+                if !first {
+                    f.write_str("|")?;
+                }
+
+                f.write_str(name)?;
+                first = false;
+            }
         }
 
-        // We don't need checking here since all bits of the Buttons flag are in use;
-        // There are no illegal values
-        self.pressed = unsafe { Buttons::from_bits_unchecked(!buttons.bits()) };
+        Ok(())
+    }
+}
+
+/// A button name that [`Buttons::from_str`] didn't recognize
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseButtonsError(pub String);
+
+/// Parses the format written by the [`fmt::Display`] implementation. Parsing is a bit
+/// more lenient though: Names are case-insensitive, may be surrounded by whitespace and
+/// can appear in any order. Both `NONE` and the empty string are parsed as no buttons.
+impl FromStr for Buttons {
+    type Err = ParseButtonsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s.is_empty() || s.eq_ignore_ascii_case(NO_BUTTONS) {
+            return Ok(Buttons::empty());
+        }
+
+        s.split('|').try_fold(Buttons::empty(), |buttons, name| {
+            let name = name.trim();
+
+            BUTTON_NAMES
+                .iter()
+                .find(|(_, canonical)| canonical.eq_ignore_ascii_case(name))
+                .map(|(button, _)| buttons | *button)
+                .ok_or_else(|| ParseButtonsError(name.to_owned()))
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Buttons {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Buttons {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+
+        s.parse().map_err(|ParseButtonsError(name)| {
+            serde::de::Error::custom(format!("unknown button \"{}\"", name))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joypad_requested(ir_system: &InterruptSystem) -> bool {
+        ir_system.read_if() & Interrupt::Joypad as u8 != 0
+    }
+
+    #[test]
+    fn p1_reads_every_select_combination() {
+        let mut ir_system = InterruptSystem::new();
+        let mut joypad = JoyPad::new();
+
+        // RIGHT is on input line 0, B on input line 1
+        joypad.notify_buttons_state(&mut ir_system, Buttons::RIGHT | Buttons::B, 0);
+
+        for &(select, expected) in &[(0x30, 0xFF), (0x20, 0xEE), (0x10, 0xDD), (0x00, 0xCC)] {
+            joypad.write_p1(&mut ir_system, select);
+            assert_eq!(joypad.read_p1(0), expected, "P1 = {:#04X}", select);
+        }
+    }
+
+    #[test]
+    fn selecting_pressed_buttons_requests_interrupt() {
+        let mut ir_system = InterruptSystem::new();
+        let mut joypad = JoyPad::new();
+
+        joypad.write_p1(&mut ir_system, 0x20);
+        joypad.notify_buttons_state(&mut ir_system, Buttons::START, 0);
+        assert!(!joypad_requested(&ir_system));
+
+        joypad.write_p1(&mut ir_system, 0x10);
+        assert!(joypad_requested(&ir_system));
+    }
+
+    #[test]
+    fn pressed_buttons_bounce_before_settling() {
+        const PRESSED_AT: u64 = 5000;
+
+        let mut ir_system = InterruptSystem::new();
+        let mut joypad = JoyPad::new();
+        joypad.set_bounce(true);
+        joypad.write_p1(&mut ir_system, 0x10);
+
+        // A is on input line 0
+        joypad.notify_buttons_pressed(&mut ir_system, Buttons::A, PRESSED_AT);
+        assert!(joypad_requested(&ir_system));
+
+        let a_low = |mcycles| joypad.read_p1(mcycles) & 1 == 0;
+
+        // The line alternates between the old and the new state every period
+        for period in 0..8 {
+            let mcycles = PRESSED_AT + period * BOUNCE_PERIOD;
+            assert_eq!(a_low(mcycles), period % 2 == 1, "Period {}", period);
+            assert_eq!(a_low(mcycles + BOUNCE_PERIOD - 1), period % 2 == 1);
+        }
+
+        assert!(a_low(PRESSED_AT + BOUNCE_MCYCLES));
+        assert!(a_low(PRESSED_AT + 10 * BOUNCE_MCYCLES));
+    }
+
+    #[test]
+    fn buttons_dont_bounce_by_default() {
+        let mut ir_system = InterruptSystem::new();
+        let mut joypad = JoyPad::new();
+        joypad.write_p1(&mut ir_system, 0x10);
+
+        joypad.notify_buttons_pressed(&mut ir_system, Buttons::A, 5000);
+        assert_eq!(joypad.read_p1(5000) & 1, 0);
+
+        joypad.notify_buttons_released(Buttons::A, 6000);
+        assert_eq!(joypad.read_p1(6000) & 1, 1);
     }
 }
@@ -19,22 +19,12 @@
 //!     let cartridge =
 //!         CartridgeVariant::from_file(rom_path).expect("Could not open rom file");
 //!     
-//!     dispatch_emulator(rom_path, cartridge);
+//!     run_emu(rom_path, cartridge);
 //! }
 //!
-//! fn dispatch_emulator(rom_path: &str, mut cartridge: CartridgeVariant) {
-//!     match &mut cartridge {
-//!         CartridgeVariant::Rom(c) => run_emu(rom_path, c),
-//!         CartridgeVariant::RomRam(c) => run_emu(rom_path, c),
-//!         CartridgeVariant::RomRamBanked(c) => run_emu(rom_path, c),
-//!         CartridgeVariant::MBC1(c) => run_emu(rom_path, c),
-//!         CartridgeVariant::MBC1Ram(c) => run_emu(rom_path, c),
-//!         // And so on... you get the idea
-//!         _ => unimplemented(),
-//!     }
-//! }
-//!
-//! fn run_emu<C: Cartridge + Savegame + Metadata>(rom_path: &str, mut cartridge: C) {
+//! // CartridgeVariant implements Cartridge itself. See its documentation if you want to
+//! // dispatch the emulation loop statically instead.
+//! fn run_emu(rom_path: &str, mut cartridge: CartridgeVariant) {
 //!     // If you want useful debugging features, pass a debug logger (to CPU and/or PPU)
 //!     // via DbgEvtLogger::new() instead of NoDbgLogger
 //!     let mut emu = Emulator::with_debugger(&mut cartridge, NoDbgLogger, NoDbgLogger);
@@ -58,6 +48,11 @@
 //!                 // how you would want to do that, so I won't give any example here.
 //!                 true
 //!             }
+//!             VideoFrameStatus::SgbReady(sgb_frame) => {
+//!                 // Only happens when emulating a Super Game Boy. Same as above, but the
+//!                 // frame is colored and has a border.
+//!                 true
+//!             }
 //!             VideoFrameStatus::LcdTurnedOff => {
 //!                 // Basically the same as the previous match arm, but you should render a
 //!                 // blank screen instead of a frame
@@ -74,10 +69,9 @@
 //!     }
 //! }
 //!
-//! // I promise this function signature will become a bit prettier some day...
-//! fn os_update<CMem: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>(
-//!     emu: &mut Emulator<CMem, CpuDbg, PpuDbg>,
-//! ) -> bool {
+//! // Emulator implements EmulatorHandle no matter which debuggers it was created with,
+//! // so this doesn't need to be generic
+//! fn os_update(emu: &mut dyn EmulatorHandle) -> bool {
 //!     // Handle window events here. If the user closed the window or terminated the
 //!     // application any other way, return false
 //!
@@ -85,7 +79,7 @@
 //!
 //!     // Here, query the current input state and write it to `buttons`
 //!
-//!     emu.notify_buttons_state(button_states);
+//!     emu.notify_buttons_state(buttons);
 //!
 //!     true
 //! }
@@ -93,37 +87,138 @@
 //! ```
 
 mod address;
+mod audio;
+pub mod autosplit;
 mod board;
 mod cartridge;
+pub mod cheats;
 mod cpu;
 pub mod debug;
+pub mod env;
+pub mod frame_dump;
+pub mod frontend;
+pub mod gamedb;
+mod handle;
+mod hardware;
+pub mod headless;
+#[cfg(feature = "http")]
+mod http;
+pub mod input_log;
 mod interrupt_system;
 mod joypad;
+mod link_cable;
 mod memory;
+pub mod pixel_format;
 mod ppu;
+#[cfg(feature = "remote")]
+pub mod remote;
+mod rewind;
+pub mod rom_loader;
+mod savestate;
 mod serial_port;
+mod sgb;
+#[cfg(feature = "http")]
+pub mod state_server;
+pub mod storage;
+pub mod test_harness;
 mod timer;
 mod util;
 
-use board::BoardImpl;
-use cpu::CPU;
+use address::{Addr, PpuReg, TimerReg};
+use board::{Board, BoardImpl};
+use cheats::{Cheat, CheatId, ParseCheatError};
+use cpu::{HaltState, CPU};
 use debug::*;
+use gamedb::{GameDb, GameVarError};
+use input_log::{InputLog, InputProvider, Movie, MoviePlayer};
 use memory::{InternalMem, Memory};
+use savestate::SaveState;
+use std::convert::TryFrom;
+use std::io;
+use std::path::Path;
 
+pub use audio::{AudioChannel, AudioConfig, WavWriter};
+pub use board::{
+    BlockCache, ClockRatio, Component, DebugTrap, FrameStats, ParseClockRatioError,
+    ParseStepOrderError, StepOrder, TrapCause, ValueMatch, WatchKind, Watchpoint, WatchpointSet,
+};
 pub use cartridge::*;
 
-pub use joypad::Buttons;
-pub use ppu::{MemPixel, VideoFrameStatus};
+pub use cpu::IllegalInstr;
+pub use frame_dump::RgbaFrame;
+pub use handle::EmulatorHandle;
+pub use hardware::HardwareModel;
+pub use interrupt_system::Interrupt;
+pub use joypad::{Buttons, ParseButtonsError, ResetCombo};
+pub use link_cable::{
+    LinkCable, LinkCableEnd, SerialEcho, SerialSpeed, SerialTransport, TcpSerialTransport,
+};
+pub use ppu::dmg_palette::{self, DmgPalette};
+pub use ppu::{FrameResult, FrameSkip, MemPixel, VideoFrameStatus};
+pub use rewind::RewindBuffer;
+pub use savestate::{SaveStateError, StateReader, StateWriter, SAVESTATE_VERSION};
+pub use sgb::{SgbFrame, SGB_FRAME_HEIGHT, SGB_FRAME_WIDTH};
+
+/// The number of machine cycles it takes the PPU to draw a single frame
+pub const MCYCLES_PER_FRAME: u64 = 17556;
+
+/// The most cycles [`Emulator::emulate_step`] skips while the CPU is halted (one scanline)
+const MAX_IDLE_SKIP: u64 = 114;
+
+/// Notable conditions that occurred during a call to [`Emulator::emulate_step`].
+/// Frontends can use this to react to things that would otherwise go unnoticed, like
+/// a crashed game.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// Nothing out of the ordinary happened
+    Normal,
+    /// The boot ROM finished executing and was unmapped; The game starts now
+    BootRomFinished,
+    /// The CPU executed a STOP instruction
+    StopEntered,
+    /// The CPU executed an illegal opcode and is stuck. It will never execute another
+    /// instruction until the emulator is reset. This is returned for every step
+    /// while the CPU is stuck, so frontends don't spin forever without noticing.
+    Stuck,
+    /// The step accessed memory that is watched by a watchpoint (see
+    /// [`Emulator::watchpoints_mut`]). The access has already happened. Takes precedence
+    /// over the other outcomes.
+    Watchpoint(DebugTrap),
+}
 
 pub struct Emulator<C, CpuDbg, PpuDbg> {
     cpu: CPU,
     board: BoardImpl<C, CpuDbg, PpuDbg>,
+    reset_combo: ResetCombo,
+    /// Whether the reset combo was held down during the last button update. Used
+    /// to only react to the moment the combo is first pressed.
+    reset_combo_held: bool,
+    /// Set when the reset combo was detected, until the frontend polls it
+    reset_combo_detected: bool,
+    /// Set when a reset was requested via the reset combo, but the current frame
+    /// has not finished drawing yet
+    reset_pending: bool,
+    rewind: Option<RewindBuffer>,
+    input_log: InputLog,
+    game_db: Option<GameDb>,
+    /// The watchpoint that was hit during the last step, if any
+    debug_trap: Option<DebugTrap>,
+    /// Where the budget of the last [`Emulator::run_cycles`] call ended, and the cycle
+    /// it actually returned at. The next budget starts at the former, unless the
+    /// emulator was stepped in between.
+    cycle_budget_end: Option<(u64, u64)>,
 }
 
 impl<C: Cartridge> Emulator<C, NoDbgLogger, NoDbgLogger> {
     pub fn new(cartridge: C) -> Self {
         Self::with_debugger(cartridge, NoDbgLogger, NoDbgLogger)
     }
+
+    /// Creates an emulator that behaves like the given Game Boy model (see
+    /// [`Emulator::with_model`])
+    pub fn new_with_model(cartridge: C, model: HardwareModel) -> Self {
+        Self::new(cartridge).with_model(model)
+    }
 }
 
 impl<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>
@@ -135,35 +230,1040 @@ impl<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>
         Self {
             cpu: CPU::new(),
             board: BoardImpl::new(mem, cpu_logger, ppu_logger),
+            reset_combo: ResetCombo::Disabled,
+            reset_combo_held: false,
+            reset_combo_detected: false,
+            reset_pending: false,
+            rewind: None,
+            input_log: InputLog::new(),
+            game_db: None,
+            debug_trap: None,
+            cycle_budget_end: None,
         }
     }
 
-    pub fn emulate_step(&mut self) {
+    /// Makes the emulator behave like the given Game Boy model: Its boot ROM is used
+    /// (or none, if it isn't built in, see [`HardwareModel::built_in_boot_rom`]), games
+    /// start with the register values that it leaves behind, and the accuracy options
+    /// that depend on the model ([`Emulator::set_stat_write_bug`] and
+    /// [`Emulator::set_serial_high_speed`]) are set accordingly. Those can still be
+    /// changed afterwards, and so can the boot ROM. Resets the emulator.
+    pub fn with_model(mut self, model: HardwareModel) -> Self {
+        self.board.set_model(model);
+        self.board.mem.set_boot_rom(model.built_in_boot_rom());
+        self.reset();
+        self
+    }
+
+    /// The Game Boy model that the emulator behaves like (see [`Emulator::with_model`])
+    pub fn hardware_model(&self) -> HardwareModel {
+        self.board.model()
+    }
+
+    /// Replaces the boot ROM of the original Game Boy (which is built in) with another
+    /// one, e.g. a dump of a real one or a homebrew boot ROM. With `None`, the emulator
+    /// doesn't run any boot ROM at all and games start right away, with the CPU and the
+    /// IO registers set to what the boot ROM would have left behind (see
+    /// [`Emulator::has_boot_rom`]). Resets the emulator.
+    pub fn with_boot_rom(mut self, boot_rom: Option<[u8; 256]>) -> Self {
+        self.board.mem.set_boot_rom(boot_rom);
+        self.reset();
+        self
+    }
+
+    /// False if the emulator skips the boot ROM (see [`Emulator::with_boot_rom`])
+    pub fn has_boot_rom(&self) -> bool {
+        self.board.mem.has_boot_rom()
+    }
+
+    /// Executes a single instruction (or handles an interrupt, or waits a single machine
+    /// cycle if the CPU is halted). See [`StepOutcome`] for the conditions that are
+    /// reported back.
+    pub fn emulate_step(&mut self) -> StepOutcome {
+        self.emulate_step_within(u64::MAX)
+    }
+
+    /// Like [`Emulator::emulate_step`], but a halted CPU skips at most `max_skip` idle
+    /// cycles (see [`Emulator::set_idle_skipping`])
+    fn emulate_step_within(&mut self, max_skip: u64) -> StepOutcome {
+        // Resets requested via the reset combo are delayed until the PPU is idle, so
+        // the frontend never sees a half-drawn frame
+        if self.reset_pending && self.board.ppu.is_idle() {
+            self.reset();
+        }
+
+        if self.input_log.frame_due(self.board.mcycles) {
+            let buttons = self.input_log.next_frame();
+            self.board.notify_buttons_state(buttons);
+            self.check_reset_combo();
+        }
+
+        let was_stopped = matches!(self.cpu.halt_state, HaltState::Stopped);
+        let boot_rom_mapped = self.board.mem.boot_rom_mapped();
+        let instr_start = self.cpu.reg.pc;
+        let halted = matches!(self.cpu.halt_state, HaltState::Halted);
+
+        // While the CPU waits for an interrupt, the cycles in which nothing else happens
+        // either are skipped in one go. At most a scanline at a time, so `run_frame` and
+        // input frames are still checked about as often as without skipping.
+        if halted && self.board.ir_system.query_interrupt_request().is_none() {
+            let max = self
+                .input_log
+                .mcycles_until_frame(self.board.mcycles)
+                .min(MAX_IDLE_SKIP)
+                .min(max_skip);
+            let skipped = self.board.skip_idle_mcycles(max as u32);
+            self.board.frame_stats.count_halt(skipped as u64);
+        }
+
+        let start_mcycles = self.board.mcycles;
+
         self.cpu.step_instr(&mut self.board);
+
+        if halted {
+            let mcycles = self.board.mcycles - start_mcycles;
+            self.board.frame_stats.count_halt(mcycles);
+        }
+
+        self.debug_trap = self.board.watchpoints.take_trap().map(|trap| DebugTrap {
+            pc: instr_start,
+            ..trap
+        });
+
+        if let Some(trap) = self.debug_trap {
+            return StepOutcome::Watchpoint(trap);
+        }
+
+        match self.cpu.halt_state {
+            HaltState::Stuck => StepOutcome::Stuck,
+            HaltState::Stopped if !was_stopped => StepOutcome::StopEntered,
+            _ if boot_rom_mapped && !self.board.mem.boot_rom_mapped() => {
+                StepOutcome::BootRomFinished
+            }
+            _ => StepOutcome::Normal,
+        }
+    }
+
+    /// Runs the emulator until the next frame is finished and returns it. This is a
+    /// convenience alternative to calling [`Emulator::emulate_step`] and
+    /// [`Emulator::query_video_frame_status`] in a loop.
+    ///
+    /// If the LCD is turned off (or the CPU is in STOP mode), no frames are drawn at all.
+    /// In that case, this method returns [`FrameResult::LcdOff`] after (roughly) the time
+    /// it would usually take to draw a frame, so the frontend can keep a steady frame rate.
+    pub fn run_frame(&mut self) -> FrameResult<'_> {
+        let start = self.board.mcycles;
+
+        loop {
+            self.emulate_step();
+
+            if self.board.ppu.has_frame_status() {
+                break;
+            }
+
+            let no_frames = !self.board.ppu.lcd_enabled() || self.is_stopped();
+
+            if no_frames && self.board.mcycles - start >= MCYCLES_PER_FRAME {
+                return FrameResult::LcdOff;
+            }
+        }
+
+        match self.board.query_video_frame_status() {
+            VideoFrameStatus::Ready(frame) => FrameResult::Frame(frame),
+            VideoFrameStatus::SgbReady(frame) => FrameResult::Frame(frame.screen),
+            VideoFrameStatus::LcdTurnedOff => FrameResult::LcdOff,
+            VideoFrameStatus::NotReady => unreachable!(),
+        }
+    }
+
+    /// Runs the emulator for a budget of `mcycles` machine cycles and returns the number
+    /// of frames that were finished in that time. This lets netplay and TAS tools keep
+    /// several emulators in sync by cycles instead of wall-clock frames: Given the same
+    /// inputs between the same calls, the emulation is bit-identical, no matter how fast
+    /// the host is.
+    ///
+    /// Instructions can't be interrupted, so a call might return a few cycles late. That
+    /// overshoot is taken from the budget of the next call, so consecutive calls add up
+    /// exactly: After budgets of `a` and `b` cycles, the emulator is never more than an
+    /// instruction past `a + b` cycles. This only holds if the emulator isn't stepped in
+    /// between (in which case the next budget starts at the current cycle).
+    ///
+    /// Finished frames are consumed like [`Emulator::query_video_frame_status`] does, the
+    /// last one is available via [`Emulator::completed_frame`]. Watchpoints don't stop
+    /// the emulator early.
+    pub fn run_cycles(&mut self, mcycles: u64) -> u32 {
+        let start = match self.cycle_budget_end {
+            Some((end, returned_at)) if returned_at == self.board.mcycles => end,
+            _ => self.board.mcycles,
+        };
+
+        let end = start + mcycles;
+        let mut frames = 0;
+
+        while self.board.mcycles < end {
+            // A halted CPU needs one more cycle after skipping, so this lands right on `end`
+            self.emulate_step_within(end - self.board.mcycles - 1);
+
+            if self.board.ppu.has_frame_status() {
+                if let VideoFrameStatus::Ready(_) | VideoFrameStatus::SgbReady(_) =
+                    self.board.query_video_frame_status()
+                {
+                    frames += 1;
+                }
+            }
+        }
+
+        self.cycle_budget_end = Some((end, self.board.mcycles));
+        frames
+    }
+
+    /// The number of machine cycles that have passed since the emulator was created
+    pub fn mcycles(&self) -> u64 {
+        self.board.mcycles
+    }
+
+    /// Statistics about the last finished frame (see [`FrameStats`]), or `None` before the
+    /// first frame is finished. Frontends can compare [`FrameStats::mcycles`] with the
+    /// time it took to emulate the frame to show the emulation speed.
+    pub fn last_frame_stats(&self) -> Option<FrameStats> {
+        self.board.frame_stats.last()
+    }
+
+    /// Whether the CPU is in STOP mode. The system clock is halted, so the screen stays
+    /// blank until one of the buttons that the game is listening to is pressed.
+    pub fn is_stopped(&self) -> bool {
+        matches!(self.cpu.halt_state, HaltState::Stopped)
+    }
+
+    /// Whether the CPU executed an illegal instruction and is stuck. Only a reset
+    /// gets the CPU running again.
+    pub fn is_stuck(&self) -> bool {
+        matches!(self.cpu.halt_state, HaltState::Stuck)
+    }
+
+    /// The illegal instruction that got the CPU stuck (see [`Emulator::is_stuck`])
+    pub fn illegal_instr(&self) -> Option<IllegalInstr> {
+        self.cpu.illegal_instr
+    }
+
+    /// Reads a byte the way the CPU would see it, but without any side effects and
+    /// without any time passing. Meant for debuggers, overlays and scripts.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.board.read8_instant(Addr::from(addr))
+    }
+
+    /// Writes a byte the way the CPU would, including all side effects (like bank
+    /// switches or register writes), but without any time passing. Watchpoints don't
+    /// trigger. Meant for debuggers and scripts; Writes to ROM only reach the MBC.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        self.board.write8_instant(addr, val);
+    }
+
+    /// A copy of the CPU registers, e.g. for a dashboard. See [`debug::StateSnapshot`].
+    pub fn cpu_state(&self) -> debug::CpuStateSnapshot {
+        let reg = &self.cpu.reg;
+        let [b, c] = reg.bc.to_be_bytes();
+        let [d, e] = reg.de.to_be_bytes();
+        let [h, l] = reg.hl.to_be_bytes();
+
+        debug::CpuStateSnapshot {
+            a: reg.a,
+            f: reg.flags.bits(),
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: reg.sp,
+            pc: reg.pc,
+            ime: self.cpu.ime,
+            halted: matches!(self.cpu.halt_state, HaltState::Halted),
+            stopped: matches!(self.cpu.halt_state, HaltState::Stopped),
+            illegal_instr: self.cpu.illegal_instr,
+            mcycles: self.board.mcycles,
+        }
+    }
+
+    /// A copy of the PPU registers, e.g. for a dashboard
+    pub fn ppu_state(&self) -> debug::PpuStateSnapshot {
+        let ppu = &self.board.ppu;
+
+        debug::PpuStateSnapshot {
+            mode: ppu.mode(),
+            lcdc: ppu.read_reg(PpuReg::LCDC),
+            stat: ppu.read_reg(PpuReg::LCDS),
+            scy: ppu.read_reg(PpuReg::SCY),
+            scx: ppu.read_reg(PpuReg::SCX),
+            ly: ppu.read_reg(PpuReg::LY),
+            lyc: ppu.read_reg(PpuReg::LYC),
+            bgp: ppu.read_reg(PpuReg::BGP),
+            obp0: ppu.read_reg(PpuReg::OBP0),
+            obp1: ppu.read_reg(PpuReg::OBP1),
+            wy: ppu.read_reg(PpuReg::WY),
+            wx: ppu.read_reg(PpuReg::WX),
+            scanline: ppu.ly_internal(),
+            scanline_mcycle: ppu.scanline_mcycle_internal(),
+            window_line: ppu.window_line_internal(),
+            dropped_sprites: ppu.dropped_sprites_internal(),
+        }
+    }
+
+    /// A copy of the timer registers, e.g. for a dashboard
+    pub fn timer_state(&self) -> debug::TimerState {
+        let timer = &self.board.timer;
+
+        debug::TimerState {
+            div: timer.read_reg(TimerReg::DIV),
+            tima: timer.read_reg(TimerReg::TIMA),
+            tma: timer.read_reg(TimerReg::TMA),
+            tac: timer.read_reg(TimerReg::TAC),
+            div_internal: timer.div_internal(),
+        }
+    }
+
+    /// The CPU, PPU, timer and bank state in one snapshot
+    pub fn state_snapshot(&self) -> debug::StateSnapshot {
+        debug::StateSnapshot {
+            cpu: self.cpu_state(),
+            ppu: self.ppu_state(),
+            timer: self.timer_state(),
+            banks: self.current_banks(),
+        }
+    }
+
+    /// Renders the tiles, tile maps and sprites in video memory, e.g. for a VRAM viewer
+    pub fn ppu_inspector(&self) -> debug::PpuInspector<'_> {
+        debug::PpuInspector::new(&self.board.ppu)
+    }
+
+    /// Installs the variable definitions that [`Emulator::game_var`] uses, or removes
+    /// them if `None` is passed
+    pub fn set_game_db(&mut self, game_db: Option<GameDb>) {
+        self.game_db = game_db;
+    }
+
+    pub fn game_db(&self) -> Option<&GameDb> {
+        self.game_db.as_ref()
+    }
+
+    /// Reads the variable called `name` from the installed [`GameDb`] and converts it
+    /// to the requested type, e.g. `let x: u16 = emu.game_var("player_x")?;`
+    pub fn game_var<T: TryFrom<i64>>(&self, name: &str) -> Result<T, GameVarError> {
+        let var = self
+            .game_db
+            .as_ref()
+            .ok_or(GameVarError::NoGameDb)?
+            .get(name)
+            .ok_or_else(|| GameVarError::UnknownVar(name.to_owned()))?;
+
+        let val = var.read(|addr| self.peek(addr));
+
+        T::try_from(val).map_err(|_| GameVarError::OutOfRange(val))
+    }
+
+    /// The CPU event logger that was passed to [`Emulator::with_debugger`], e.g. for
+    /// flushing a [`TraceLogger`]
+    pub fn cpu_logger(&self) -> &CpuDbg {
+        &self.board.cpu_evt_src
+    }
+
+    pub fn cpu_logger_mut(&mut self) -> &mut CpuDbg {
+        &mut self.board.cpu_evt_src
+    }
+
+    /// The PPU event logger that was passed to [`Emulator::with_debugger`]
+    pub fn ppu_logger(&self) -> &PpuDbg {
+        &self.board.ppu_evt_src
+    }
+
+    pub fn ppu_logger_mut(&mut self) -> &mut PpuDbg {
+        &mut self.board.ppu_evt_src
+    }
+
+    /// The cartridge that the emulator runs, e.g. for accessing its savegame if the
+    /// emulator owns it
+    pub fn cartridge(&self) -> &C {
+        self.board.mem.cartridge()
+    }
+
+    /// Mutable access to the cartridge. Changing the savegame while a game runs works,
+    /// but the game might not notice until it reads the cartridge RAM again.
+    pub fn cartridge_mut(&mut self) -> &mut C {
+        self.board.mem.cartridge_mut()
+    }
+
+    /// The ROM and RAM banks that are currently mapped into the address space, e.g. for
+    /// showing the active mapping in a debugger
+    pub fn current_banks(&self) -> BankState {
+        self.board.mem.cartridge().bank_state()
+    }
+
+    /// Returns the raw contents of ROM bank `bank`, or `None` if the cartridge doesn't
+    /// have that bank. Useful for inspecting (e.g. disassembling) banks that are not
+    /// currently mapped.
+    pub fn rom_bank(&self, bank: u16) -> Option<&[u8]> {
+        let start = bank as usize * 0x4000;
+        self.board.mem.cartridge().rom().get(start..start + 0x4000)
+    }
+
+    /// **Debugging tool:** Maps ROM bank `bank` to 0x4000-0x7FFF, no matter which bank
+    /// the game selects, until the override is removed by passing `None`. Returns false
+    /// (and changes nothing) if the cartridge has no MBC or doesn't have that bank.
+    ///
+    /// Caveats: The game will almost certainly crash if it executes code from the forced
+    /// bank while it expects another one. The bank selected by the game is still tracked,
+    /// and is mapped again once the override is removed. The override is not part of
+    /// savestates, but survives loading one (as well as resets).
+    pub fn force_rom_bank(&mut self, bank: Option<u16>) -> bool {
+        self.board.leave_cached_block();
+        self.board.mem.cartridge_mut().force_rom_bank(bank)
+    }
+
+    /// Whether the rumble motor of the cartridge was on at any time since the last call.
+    /// Games pulse the motor to control its strength, so it can be off right now even
+    /// though it's meant to rumble. Frontends should call this once per frame and
+    /// forward it to the gamepad. Always false for cartridges without a motor.
+    pub fn poll_rumble(&mut self) -> bool {
+        self.board.mem.cartridge_mut().poll_rumble()
+    }
+
+    /// Adds a GameShark or Game Genie code (see [`cheats`]), which is enabled right away
+    pub fn add_cheat(&mut self, code: &str) -> Result<CheatId, ParseCheatError> {
+        let id = self.board.mem.cheats_mut().add(code)?;
+        log::info!("Added cheat {}", code.trim());
+        Ok(id)
+    }
+
+    /// Returns false if there is no cheat with that id
+    pub fn set_cheat_enabled(&mut self, id: CheatId, enabled: bool) -> bool {
+        self.board.mem.cheats_mut().set_enabled(id, enabled)
+    }
+
+    /// Returns the removed cheat, if there was one with that id
+    pub fn remove_cheat(&mut self, id: CheatId) -> Option<Cheat> {
+        self.board.mem.cheats_mut().remove(id)
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.board.mem.cheats_mut().clear();
+    }
+
+    /// All cheats, in the order they were added
+    pub fn cheats(&self) -> &[Cheat] {
+        self.board.mem.cheats().cheats()
+    }
+
+    /// **Debugging tool:** Runs the CPU faster or slower than the rest of the system (see
+    /// [`ClockRatio`]), e.g. to find out whether a bug depends on timing, or to play
+    /// sprite-heavy games without slowdown. The PPU keeps its speed, so frames are still
+    /// produced at the usual rate.
+    ///
+    /// Caveats: Anything that games time with the CPU (instead of the timer or the PPU)
+    /// will run at the wrong speed, and some games might not work at all. The ratio is not
+    /// part of savestates, but survives loading one (as well as resets).
+    pub fn set_clock_ratio(&mut self, ratio: ClockRatio) {
+        log::info!("CPU clock ratio set to {}", ratio);
+        self.board.set_clock_ratio(ratio);
+    }
+
+    pub fn clock_ratio(&self) -> ClockRatio {
+        self.board.clock_ratio()
+    }
+
+    /// **Debugging tool:** Changes the order in which timer, PPU, serial port and OAM DMA
+    /// are advanced within a machine cycle (see [`StepOrder`]). Anything but
+    /// [`StepOrder::DEFAULT`] is likely to make the emulation less accurate. The order is
+    /// not part of savestates, but survives loading one (as well as resets).
+    pub fn set_step_order(&mut self, step_order: StepOrder) {
+        self.board.set_step_order(step_order);
+    }
+
+    pub fn step_order(&self) -> StepOrder {
+        self.board.step_order()
+    }
+
+    /// **Debugging tool:** While the CPU is halted, the cycles in which none of the other
+    /// components has anything to do are skipped in one go, instead of emulating them one
+    /// by one. This greatly speeds up games that wait for VBlank with HALT (i.e. most of
+    /// them), and doesn't change the result of the emulation. Turning it off is only useful
+    /// to rule it out as the cause of a bug. Enabled by default, and kept across resets.
+    pub fn set_idle_skipping(&mut self, enabled: bool) {
+        self.board.set_idle_skipping(enabled);
+    }
+
+    pub fn idle_skipping(&self) -> bool {
+        self.board.idle_skipping()
+    }
+
+    /// Enables the cached interpreter, for speed-hungry uses like fuzzing, computing rewind
+    /// points ahead of time or headless runs: Straight-line code in ROM is decoded into
+    /// blocks once, and the CPU fetches its instructions from there instead of reading
+    /// memory (see [`BlockCache`]). The emulation stays cycle-accurate.
+    ///
+    /// While a debugger, watchpoints, memory statistics or cheats are active, the CPU
+    /// reads its instructions from memory as usual, so they see every access. Disabled by
+    /// default. Enabling it again starts with an empty cache.
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.board.set_block_cache(enabled);
+    }
+
+    /// `None` if the cached interpreter is disabled
+    pub fn block_cache(&self) -> Option<&BlockCache> {
+        self.board.block_cache()
+    }
+
+    /// **Debugging tool:** Runs a battery of tiny built-in test programs that check timer
+    /// edges, OAM DMA locking, interrupt timing and MBC banking, and reports which of
+    /// them behave like the hardware (see [`test_harness::SelfTestReport`]). This is a
+    /// quick way to verify a build on a new platform without downloading any test ROMs.
+    ///
+    /// The programs run on fresh emulators with the same step order and clock ratio as
+    /// this one, so the state of this emulator is not affected.
+    pub fn self_test(&self) -> test_harness::SelfTestReport {
+        test_harness::run_self_test(self.step_order(), self.clock_ratio())
+    }
+
+    /// **Accuracy option:** Lets buttons bounce for about a millisecond after they were
+    /// pressed or released, like the contacts of a real Game Boy do: Until they settle,
+    /// reads of P1 alternate between the old and the new state. Some games' debounce
+    /// code expects this, while most games don't care at all, so it's off by default.
+    /// The bouncing is deterministic, so it doesn't break movies. The option is not part
+    /// of savestates, but survives loading one (as well as resets).
+    pub fn set_joypad_bounce(&mut self, enabled: bool) {
+        self.board.joypad.set_bounce(enabled);
+    }
+
+    pub fn joypad_bounce(&self) -> bool {
+        self.board.joypad.bounce()
+    }
+
+    /// **Accuracy option:** Emulates the OAM bug of the original Game Boy: When `INC rr`,
+    /// `DEC rr`, `PUSH` or `POP` put an address in 0xFE00 - 0xFEFF on the bus while the
+    /// PPU searches OAM (mode 2), the sprite attributes that the PPU reads in that cycle
+    /// get garbled. Some test ROMs check for it, and a few games trigger it by accident
+    /// (usually with visible glitches). Off by default. The option is not part of
+    /// savestates, but survives loading one (as well as resets).
+    pub fn set_oam_bug(&mut self, enabled: bool) {
+        self.board.ppu.set_oam_bug(enabled);
+    }
+
+    pub fn oam_bug(&self) -> bool {
+        self.board.ppu.oam_bug()
+    }
+
+    /// **Accuracy option:** Emulates the STAT write bug of the original Game Boy: While
+    /// LCDS is written, all LCD Stat interrupt conditions are briefly enabled, so the
+    /// write requests an interrupt in HBlank, VBlank or when LY == LYC. Road Rash and Zerd
+    /// no Densetsu rely on these spurious interrupts. The Game Boy Color doesn't have the
+    /// bug, so turn this off to emulate one. On by default. The option is not part of
+    /// savestates, but survives loading one (as well as resets).
+    pub fn set_stat_write_bug(&mut self, enabled: bool) {
+        self.board.ppu.set_stat_write_bug(enabled);
+    }
+
+    pub fn stat_write_bug(&self) -> bool {
+        self.board.ppu.stat_write_bug()
+    }
+
+    /// Sets the RGBA colors that the four shades of the Game Boy are drawn with, from
+    /// lightest to darkest, e.g. one of the presets in [`dmg_palette`]. Takes effect with
+    /// the next scanline that is drawn, so frames that were already drawn keep their
+    /// colors. The palette is not part of savestates, but survives loading one (as well
+    /// as resets).
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.board.ppu.set_dmg_palette(palette);
+    }
+
+    /// The colors of the four shades, see [`Emulator::set_dmg_palette`]. Frontends
+    /// should show the first one while the LCD is off.
+    pub fn dmg_palette(&self) -> &DmgPalette {
+        self.board.ppu.dmg_palette()
+    }
+
+    /// Skips drawing some frames to save CPU time on slow machines (see [`FrameSkip`]).
+    /// The emulation itself is unaffected; Frames are still completed at the usual rate
+    /// (see [`Emulator::run_frame`]), but skipped frames repeat the last frame that was
+    /// drawn. [`frontend::FrameSkipMode`] picks a setting based on whether the frontend
+    /// keeps up with real time. The setting is not part of savestates, but survives
+    /// loading one (as well as resets).
+    pub fn set_frame_skip(&mut self, frame_skip: FrameSkip) {
+        self.board.ppu.set_frame_skip(frame_skip);
+    }
+
+    pub fn frame_skip(&self) -> FrameSkip {
+        self.board.ppu.frame_skip()
+    }
+
+    /// Without double buffering, the PPU draws directly into the frame that
+    /// [`VideoFrameStatus::Ready`] and [`FrameResult::Frame`] hand out, so the frontend
+    /// has to copy it before the emulator runs into the next frame, or it gets a partially
+    /// drawn one. With double buffering, the PPU draws into a second frame and swaps the
+    /// two at the start of VBlank, so the last completed frame (see
+    /// [`Emulator::completed_frame`]) stays intact until the next one is done. This costs
+    /// one more frame of memory, but nothing else. The setting is not part of savestates,
+    /// but survives loading one (as well as resets).
+    pub fn set_double_buffered(&mut self, double_buffered: bool) {
+        self.board.ppu.set_double_buffered(double_buffered);
+    }
+
+    pub fn double_buffered(&self) -> bool {
+        self.board.ppu.double_buffered()
+    }
+
+    /// The last completed frame, which can be read at any time with double buffering
+    /// (see [`Emulator::set_double_buffered`]). Without it, this is the frame that the
+    /// PPU is currently drawing, which is only complete right after a frame was reported
+    /// as ready. Frames that were skipped (see [`Emulator::set_frame_skip`]) or while the
+    /// LCD is off don't count as completed. On the Super Game Boy, this is the colored
+    /// screen (see [`SgbFrame::screen`]).
+    pub fn completed_frame(&self) -> &[MemPixel] {
+        match &self.board.sgb {
+            Some(sgb) => sgb.frame().screen,
+            None => self.board.ppu.completed_frame(),
+        }
+    }
+
+    /// A copy of the last completed frame (see [`Emulator::completed_frame`]), or a blank
+    /// one while the LCD is off. Enable double buffering to take screenshots at any time;
+    /// Without it, this should only be called right after a frame was finished.
+    pub fn screenshot(&self) -> RgbaFrame {
+        if self.board.ppu.lcd_enabled() {
+            RgbaFrame::from_pixels(self.completed_frame())
+        } else {
+            let lcd_off = self.dmg_palette()[0];
+            RgbaFrame::from_pixels(&vec![
+                lcd_off;
+                headless::FRAME_WIDTH * headless::FRAME_HEIGHT
+            ])
+        }
+    }
+
+    /// **Debugging tool:** The memory watchpoints, which are checked on every read and
+    /// write of the CPU. When one of them is hit, [`Emulator::emulate_step`] reports it
+    /// via [`StepOutcome::Watchpoint`]. [`Emulator::run_frame`] doesn't stop for
+    /// watchpoints. They are not part of savestates, but survive loading one (as well as
+    /// resets).
+    pub fn watchpoints_mut(&mut self) -> &mut WatchpointSet {
+        &mut self.board.watchpoints
+    }
+
+    pub fn watchpoints(&self) -> &WatchpointSet {
+        &self.board.watchpoints
+    }
+
+    /// The watchpoint that was hit during the last call of [`Emulator::emulate_step`],
+    /// if any
+    pub fn debug_trap(&self) -> Option<DebugTrap> {
+        self.debug_trap
+    }
+
+    /// Starts counting the memory accesses of the CPU (see [`debug::MemStats`]). If a
+    /// capture is already running, its counts are discarded.
+    pub fn start_mem_stats(&mut self) {
+        match self.board.mem_stats.as_mut() {
+            Some(stats) => stats.clear(),
+            None => self.board.mem_stats = Some(debug::MemStats::new()),
+        }
+    }
+
+    /// Ends the capture and returns the counts, if a capture was running
+    pub fn stop_mem_stats(&mut self) -> Option<debug::MemStats> {
+        self.board.mem_stats.take()
+    }
+
+    /// The counts of the running capture so far
+    pub fn mem_stats(&self) -> Option<&debug::MemStats> {
+        self.board.mem_stats.as_ref()
+    }
+
+    /// Starts counting the executed instructions (see [`debug::Profiler`]). If the
+    /// profiler is already running, its counts are discarded.
+    pub fn start_profiler(&mut self) {
+        match self.board.profiler.as_mut() {
+            Some(profiler) => profiler.clear(),
+            None => self.board.profiler = Some(debug::Profiler::new()),
+        }
+    }
+
+    /// Stops profiling and returns the counts, if the profiler was running
+    pub fn stop_profiler(&mut self) -> Option<debug::Profiler> {
+        self.board.profiler.take()
+    }
+
+    /// The counts of the running profiler so far
+    pub fn profiler(&self) -> Option<&debug::Profiler> {
+        self.board.profiler.as_ref()
+    }
+
+    /// Performs a hardware-style reset, as if the Game Boy was turned off and on
+    /// again. The boot ROM is mapped back in and executed again (or skipped, see
+    /// [`Emulator::with_boot_rom`]). The cartridge (including its RAM) is kept, but its
+    /// banking state is reset.
+    pub fn reset(&mut self) {
+        log::info!("Resetting emulator");
+
+        self.board.reset();
+
+        if self.board.mem.has_boot_rom() {
+            self.cpu = CPU::new();
+        } else {
+            self.cpu = CPU::after_boot(self.board.model());
+            self.board.skip_boot();
+        }
+
+        self.reset_pending = false;
+    }
+
+    /// Like [`Emulator::reset`], but always starts the game right away in the state the
+    /// boot ROM would have left behind, even if a boot ROM is set
+    pub fn soft_reset(&mut self) {
+        log::info!("Resetting emulator (skipping the boot ROM)");
+
+        self.board.reset();
+        self.cpu = CPU::after_boot(self.board.model());
+        self.board.skip_boot();
+
+        self.reset_pending = false;
+    }
+
+    /// Configures how the emulator reacts to A+B+Select+Start being held down together.
+    /// See [`ResetCombo`] for the available options.
+    pub fn set_reset_combo(&mut self, reset_combo: ResetCombo) {
+        self.reset_combo = reset_combo;
+
+        if reset_combo != ResetCombo::Reset {
+            self.reset_pending = false;
+        }
+    }
+
+    /// Returns true (once) if the reset combo was pressed since the last call. Always
+    /// returns false if the reset combo is [`ResetCombo::Disabled`]. Note that with
+    /// [`ResetCombo::Reset`], the reset might not have happened yet when this returns,
+    /// since it is delayed until the current frame has been drawn.
+    pub fn poll_reset_combo(&mut self) -> bool {
+        std::mem::replace(&mut self.reset_combo_detected, false)
+    }
+
+    /// Has to be called after each change of the button state
+    fn check_reset_combo(&mut self) {
+        let held = self.board.joypad.reset_combo_held();
+
+        if held && !self.reset_combo_held {
+            match self.reset_combo {
+                ResetCombo::Disabled => (),
+                ResetCombo::Reset => {
+                    self.reset_combo_detected = true;
+                    self.reset_pending = true;
+                }
+                ResetCombo::Notify => self.reset_combo_detected = true,
+            }
+        }
+
+        self.reset_combo_held = held;
+    }
+
+    /// Captures the complete state of the emulator (except for the cartridge ROM)
+    /// in a byte vector, which can be restored via [`Emulator::load_state`].
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        writer.write_header(self.board.mem.cartridge_id());
+        self.cpu.save_state(&mut writer);
+        self.board.save_state(&mut writer);
+
+        writer.into_inner()
+    }
+
+    /// Restores a state previously captured via [`Emulator::save_state`]. The state has
+    /// to come from the same cartridge. If loading fails, the emulator is left unchanged.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let backup = self.save_state();
+
+        let result = self.try_load_state(state);
+
+        if result.is_err() {
+            self.try_load_state(&backup)
+                .expect("Failed to restore emulator state after failed load");
+        }
+
+        result
+    }
+
+    /// Loads a savestate, but might leave the emulator in a half-loaded state on error
+    fn try_load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(state);
+
+        if reader.read_header()? != self.board.mem.cartridge_id() {
+            return Err(SaveStateError::CartridgeMismatch);
+        }
+
+        self.cpu.load_state(&mut reader)?;
+        self.board.load_state(&mut reader)?;
+
+        reader.finish()
+    }
+
+    /// Enables rewinding by installing a [`RewindBuffer`], or disables it if `None`
+    /// is passed. Any previously recorded history is dropped.
+    pub fn set_rewind_buffer(&mut self, rewind: Option<RewindBuffer>) {
+        self.rewind = rewind;
+    }
+
+    pub fn rewind_buffer(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    /// Should be called by the frontend once per frame (e.g. whenever
+    /// [`VideoFrameStatus::Ready`] is returned) while the emulator is running normally.
+    /// Depending on the interval of the rewind buffer, this records a new rewind point.
+    /// Does nothing if no rewind buffer is set.
+    pub fn push_rewind_point(&mut self) {
+        let due = match self.rewind.as_mut() {
+            Some(rewind) => rewind.advance_frame(),
+            None => return,
+        };
+
+        if due {
+            let state = self.save_state();
+
+            if let Some(rewind) = self.rewind.as_mut() {
+                rewind.push(state);
+            }
+        }
+    }
+
+    /// Goes back in time by (at least) `frames` frames, limited by the history stored
+    /// in the rewind buffer. Since rewind points are only recorded every few frames,
+    /// this might rewind a few more frames than requested. Returns the number of frames
+    /// that were actually rewound, which is 0 if there is nothing to rewind.
+    pub fn rewind(&mut self, frames: u32) -> u32 {
+        let mut rewind = match self.rewind.take() {
+            Some(rewind) => rewind,
+            None => return 0,
+        };
+
+        let rewound = match rewind.step_back(frames) {
+            Some((state, rewound)) => {
+                self.try_load_state(state)
+                    .expect("Rewind buffer contained an invalid savestate");
+                rewound
+            }
+            None => 0,
+        };
+
+        self.rewind = Some(rewind);
+        rewound
+    }
+
+    /// Lets `provider` control the buttons instead of the frontend (see [`input_log`]),
+    /// starting right away. Button changes from the frontend are ignored until the
+    /// provider is done or removed. Replaces any previously installed provider.
+    pub fn set_input_provider<P: InputProvider + 'static>(&mut self, provider: P) {
+        let held = self.board.joypad.held_buttons();
+
+        self.input_log
+            .set_provider(Box::new(provider), held, self.board.mcycles);
+    }
+
+    /// Removes the input provider, if there is one. The buttons stay as they are until
+    /// the next input frame starts (or until the frontend changes them, if nothing is
+    /// being recorded).
+    pub fn clear_input_provider(&mut self) -> Option<Box<dyn InputProvider>> {
+        self.input_log.take_provider()
+    }
+
+    pub fn has_input_provider(&self) -> bool {
+        self.input_log.has_provider()
+    }
+
+    /// Starts recording a [`Movie`] from the current state. Any running recording is
+    /// discarded.
+    ///
+    /// Caveats: Button changes only reach the game at the start of each input frame (see
+    /// [`input_log`]) while recording. Loading a savestate or rewinding doesn't affect
+    /// the recording, so the movie won't play back correctly if that happens.
+    pub fn start_recording(&mut self) {
+        log::info!("Started recording inputs");
+
+        let state = self.save_state();
+        let held = self.board.joypad.held_buttons();
+
+        self.input_log
+            .start_recording(state, held, self.board.mcycles);
+    }
+
+    /// Stops the recording and returns the movie, if something was recorded
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        self.input_log.stop_recording()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.input_log.is_recording()
+    }
+
+    /// Loads the initial savestate of `movie` and installs a [`MoviePlayer`] as the
+    /// input provider. Once the movie ends, the frontend controls the buttons again. If
+    /// the savestate can't be loaded, nothing changes.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), SaveStateError> {
+        self.load_state(&movie.initial_state)?;
+
+        // The buttons are not part of the savestate. Without this, the first frame could
+        // request a joypad interrupt that didn't happen during the recording.
+        if let Some(&buttons) = movie.inputs.first() {
+            self.board.joypad.set_held_buttons(buttons);
+        }
+
+        log::info!("Playing movie with {} frames", movie.len());
+
+        self.set_input_provider(MoviePlayer::new(movie));
+        Ok(())
     }
 
     pub fn query_video_frame_status(&mut self) -> VideoFrameStatus {
         self.board.query_video_frame_status()
     }
 
+    /// Plugs a link cable into the serial port. This can be one end of a [`LinkCable`],
+    /// a [`TcpSerialTransport`] or any other [`SerialTransport`]. Returns the previously
+    /// connected transport, if there was one.
+    pub fn connect_link_cable<T: SerialTransport + 'static>(
+        &mut self,
+        link: T,
+    ) -> Option<Box<dyn SerialTransport>> {
+        self.board.serial_port.connect(Box::new(link))
+    }
+
+    /// Unplugs the link cable, if one is connected
+    pub fn disconnect_link_cable(&mut self) -> Option<Box<dyn SerialTransport>> {
+        self.board.serial_port.disconnect()
+    }
+
+    /// **Accuracy option:** Makes bit 1 of SC select the high-speed serial clock
+    /// ([`SerialSpeed::High`]), like on the Game Boy Color. On the original Game Boy, the
+    /// bit always reads as 1, which some games use to detect the Game Boy Color, so
+    /// this is off by default. The option is not part of savestates, but survives
+    /// loading one (as well as resets).
+    pub fn set_serial_high_speed(&mut self, available: bool) {
+        self.board.serial_port.set_high_speed_available(available);
+    }
+
+    pub fn serial_high_speed(&self) -> bool {
+        self.board.serial_port.high_speed_available()
+    }
+
+    /// The speed that the game selected for transfers with internal clock
+    pub fn serial_speed(&self) -> SerialSpeed {
+        self.board.serial_port.speed()
+    }
+
+    /// Enables audio output at the given sample rate (in Hz, e.g. 48000), or disables
+    /// it if `None` is passed (the default). Any buffered samples are discarded.
+    pub fn set_audio_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.board.audio.set_sample_rate(sample_rate);
+    }
+
+    pub fn audio_sample_rate(&self) -> Option<u32> {
+        self.board.audio.sample_rate()
+    }
+
+    /// Mutes or solos single sound channels and sets the master volume (see
+    /// [`AudioConfig`]), e.g. to only listen to the wave channel while debugging it.
+    /// The setting is not part of savestates, but survives loading one (as well as
+    /// resets). Since there is no APU yet, all channels are silent either way.
+    pub fn set_audio_config(&mut self, config: AudioConfig) {
+        self.board.audio.set_config(config);
+    }
+
+    pub fn audio_config(&self) -> &AudioConfig {
+        self.board.audio.config()
+    }
+
+    /// **Debugging tool:** Records the audio output to a WAV file at the given sample
+    /// rate, independent of [`Emulator::set_audio_sample_rate`]. The recording contains
+    /// what the frontend would play (see [`Emulator::set_audio_config`]). With `stems`,
+    /// the output of every channel is additionally recorded to a file of its own, e.g.
+    /// `out.noise.wav` for `out.wav`, regardless of whether it is muted.
+    ///
+    /// A recording that is already running is finished first. If writing fails while
+    /// recording, the recording is stopped and the error is logged.
+    ///
+    /// Since there is no APU yet, this is a stub for now: All files only contain silence,
+    /// which is why neither frontend offers a hotkey for it.
+    pub fn start_audio_recording<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        sample_rate: u32,
+        stems: bool,
+    ) -> io::Result<()> {
+        self.board
+            .audio
+            .start_recording(path.as_ref(), sample_rate, stems)
+    }
+
+    /// Finishes the recording started via [`Emulator::start_audio_recording`], if any.
+    /// Files of recordings that were never stopped are still playable, but their
+    /// headers claim that they are empty.
+    pub fn stop_audio_recording(&mut self) -> io::Result<()> {
+        self.board.audio.stop_recording()
+    }
+
+    pub fn is_recording_audio(&self) -> bool {
+        self.board.audio.is_recording()
+    }
+
+    /// The number of `i16` values that [`Emulator::drain_audio_samples`] can currently
+    /// provide (two values per stereo sample)
+    pub fn audio_samples_available(&self) -> usize {
+        self.board.audio.available()
+    }
+
+    /// Moves buffered audio into `target` as interleaved stereo samples (left, right,
+    /// left, ...) at the rate configured via [`Emulator::set_audio_sample_rate`].
+    /// Returns the number of values that were written, which is always even.
+    ///
+    /// Around a quarter of a second of audio is buffered internally; If the frontend
+    /// doesn't drain fast enough, the oldest samples are dropped.
+    pub fn drain_audio_samples(&mut self, target: &mut [i16]) -> usize {
+        self.board.audio.drain(target)
+    }
+
     /// Call this if your frontend encounters a KEY_DOWN event (or sth equivalent).
     /// `Buttons::A | Buttons::B` means A and B were both pressed, with no info
     /// available about the other buttons, which will remain unchanged.
     pub fn notify_buttons_pressed(&mut self, buttons: Buttons) {
+        if let Some(latched) = self.input_log.latched_buttons() {
+            latched.insert(buttons);
+            return;
+        }
+
         self.board.notify_buttons_pressed(buttons);
+        self.check_reset_combo();
     }
 
     /// Call this if your frontend encounters a KEY_UP event (or sth equivalent).
     /// `Buttons::A | Buttons::B` means A and B were both released, with no info
     /// available about the other buttons, which will remain unchanged.
     pub fn notify_buttons_released(&mut self, buttons: Buttons) {
+        if let Some(latched) = self.input_log.latched_buttons() {
+            latched.remove(buttons);
+            return;
+        }
+
         self.board.notify_buttons_released(buttons);
+        self.check_reset_combo();
     }
 
     /// Alternative API if your frontend isn't suited for or doesn't provide 'KEY_UP'
     //and 'KEY_DOWN' events. `Buttons::A | Buttons::B` means A and B are pressed,
     /// and the rest of the buttons are not pressed.
     pub fn notify_buttons_state(&mut self, buttons: Buttons) {
+        if let Some(latched) = self.input_log.latched_buttons() {
+            *latched = buttons;
+            return;
+        }
+
         self.board.notify_buttons_state(buttons);
+        self.check_reset_combo();
     }
 }
@@ -0,0 +1,77 @@
+//! See [`SerialEcho`]
+
+use super::{SerialSpeed, SerialTransport};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// A device on the serial port that collects everything the game sends and never
+/// answers (so the game receives 0xFF, just like without a cable). Test ROMs (like
+/// Blargg's) and homebrew use this to print debug output.
+///
+/// Since nobody has to keep up with the transfers, they finish after
+/// [`SerialEcho::set_transfer_mcycles`] machine cycles instead of taking as long as on
+/// hardware, so long prints don't stall the game.
+pub struct SerialEcho {
+    output: Arc<Mutex<Vec<u8>>>,
+    transfer_mcycles: Option<u16>,
+    print: bool,
+}
+
+impl SerialEcho {
+    pub fn new() -> SerialEcho {
+        SerialEcho {
+            output: Arc::new(Mutex::new(Vec::new())),
+            transfer_mcycles: Some(1),
+            print: false,
+        }
+    }
+
+    /// How many machine cycles a transfer takes, or `None` to take as long as on hardware
+    /// (which depends on the [`SerialSpeed`] that the game selected). The default is a
+    /// single machine cycle.
+    pub fn set_transfer_mcycles(&mut self, mcycles: Option<u16>) {
+        self.transfer_mcycles = mcycles.map(|mcycles| mcycles.max(1));
+    }
+
+    /// Also prints every received byte to stdout (as text, which is what test ROMs send)
+    pub fn set_print(&mut self, print: bool) {
+        self.print = print;
+    }
+
+    /// Everything that was sent so far. The buffer is shared, so it can be read while the
+    /// echo device is plugged into an emulator.
+    pub fn output(&self) -> Arc<Mutex<Vec<u8>>> {
+        Arc::clone(&self.output)
+    }
+}
+
+impl Default for SerialEcho {
+    fn default() -> Self {
+        SerialEcho::new()
+    }
+}
+
+impl SerialTransport for SerialEcho {
+    fn publish(&mut self, _sb: u8, _listening: bool) {}
+
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        self.output.lock().unwrap().push(outgoing);
+
+        if self.print {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(&[outgoing]);
+            let _ = stdout.flush();
+        }
+
+        0xFF
+    }
+
+    fn take_incoming(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn start_transfer(&mut self, speed: SerialSpeed) -> u16 {
+        self.transfer_mcycles
+            .unwrap_or_else(|| speed.mcycles_per_byte())
+    }
+}
@@ -0,0 +1,190 @@
+//! A virtual link cable that connects the serial ports of two [`Emulator`] instances
+//! running in the same process (e.g. for Tetris 2-player or Pokémon trading).
+//!
+//! The Game Boy that starts a transfer with its internal clock (the "master") drives
+//! the clock of the other Game Boy (the "slave"), which has to have its own transfer
+//! started with the external clock at that point. Both shift registers are exchanged
+//! once the transfer completes. If the slave isn't ready, the master just receives
+//! 0xFF, which is also what happens on hardware when no cable is plugged in.
+//!
+//! Transfers are only exchanged at the end of each byte, so both emulators should be
+//! kept roughly in sync; The simplest way to do so is to always step the emulator that
+//! is behind:
+//!
+//! ```ignore
+//! let (end_a, end_b) = LinkCable::new().split();
+//! emu_a.connect_link_cable(end_a);
+//! emu_b.connect_link_cable(end_b);
+//!
+//! loop {
+//!     if emu_a.mcycles() <= emu_b.mcycles() {
+//!         emu_a.emulate_step();
+//!     } else {
+//!         emu_b.emulate_step();
+//!     }
+//! }
+//! ```
+//!
+//! The serial port talks to the other Game Boy via the [`SerialTransport`] trait, so
+//! the other side doesn't have to live in the same process. See [`TcpSerialTransport`]
+//! for linking emulators over the network, or [`SerialEcho`] for collecting what a
+//! game prints to the serial port.
+
+mod echo;
+mod tcp;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+pub use echo::SerialEcho;
+pub use tcp::TcpSerialTransport;
+
+/// The clock speed of a transfer with internal clock, selected by bit 1 of SC
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SerialSpeed {
+    /// 8192 Hz, the only speed of the original Game Boy
+    Normal,
+    /// 262144 Hz, only available on the Game Boy Color (see
+    /// [`crate::Emulator::set_serial_high_speed`])
+    High,
+}
+
+impl SerialSpeed {
+    /// How many machine cycles it takes to shift a whole byte at this speed
+    pub fn mcycles_per_byte(self) -> u16 {
+        match self {
+            SerialSpeed::Normal => 8 * 128,
+            SerialSpeed::High => 8 * 4,
+        }
+    }
+
+    /// Clock frequency in Hz (one bit per clock)
+    pub fn hz(self) -> u32 {
+        match self {
+            SerialSpeed::Normal => 8192,
+            SerialSpeed::High => 262_144,
+        }
+    }
+}
+
+impl fmt::Display for SerialSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz", self.hz())
+    }
+}
+
+/// Everything the serial port needs to talk to another Game Boy. All methods are
+/// called from the emulation loop, so they should never block for long.
+pub trait SerialTransport: Send {
+    /// Called whenever the state of our serial port changes. `listening` is true if a
+    /// transfer with external clock was started, meaning that we are waiting for the
+    /// other side to drive the transfer.
+    fn publish(&mut self, sb: u8, listening: bool);
+
+    /// Performs a transfer driven by our internal clock. Returns the byte that was
+    /// shifted in from the other side, or 0xFF if it wasn't listening (or isn't there).
+    fn exchange(&mut self, outgoing: u8) -> u8;
+
+    /// Polled while we are listening. Returns the byte that the other side shifted
+    /// into our serial port, if a transfer was driven by the other side.
+    fn take_incoming(&mut self) -> Option<u8>;
+
+    /// Called when we start a transfer with internal clock, with the speed that the
+    /// game selected. Returns how many machine cycles pass until [`exchange`] is called.
+    ///
+    /// By default, that's as long as on hardware. Devices that don't have to be timed
+    /// accurately (like [`SerialEcho`]) can finish transfers sooner, so games that print
+    /// a lot don't stall.
+    ///
+    /// [`exchange`]: SerialTransport::exchange
+    fn start_transfer(&mut self, speed: SerialSpeed) -> u16 {
+        speed.mcycles_per_byte()
+    }
+}
+
+/// A link cable with two (connected) ends
+pub struct LinkCable {
+    a: LinkCableEnd,
+    b: LinkCableEnd,
+}
+
+impl LinkCable {
+    pub fn new() -> LinkCable {
+        let shared = Arc::new(Mutex::new([PortState::new(), PortState::new()]));
+
+        LinkCable {
+            a: LinkCableEnd {
+                shared: Arc::clone(&shared),
+                side: 0,
+            },
+            b: LinkCableEnd { shared, side: 1 },
+        }
+    }
+
+    /// Returns both ends of the cable. Plug each end into an emulator via
+    /// [`Emulator::connect_link_cable`].
+    pub fn split(self) -> (LinkCableEnd, LinkCableEnd) {
+        (self.a, self.b)
+    }
+}
+
+impl Default for LinkCable {
+    fn default() -> Self {
+        LinkCable::new()
+    }
+}
+
+/// One end of a [`LinkCable`]. This is `Send`, so linked emulators can also run on
+/// different threads.
+pub struct LinkCableEnd {
+    shared: Arc<Mutex<[PortState; 2]>>,
+    side: usize,
+}
+
+/// What each serial port publishes to the other end of the cable
+struct PortState {
+    /// Current content of the SB register
+    sb: u8,
+    /// Whether a transfer with external clock was started and is waiting for the
+    /// other side to provide the clock
+    listening: bool,
+    /// A byte that was shifted in by the other side, but not picked up yet
+    inbox: Option<u8>,
+}
+
+impl PortState {
+    fn new() -> PortState {
+        PortState {
+            sb: 0,
+            listening: false,
+            inbox: None,
+        }
+    }
+}
+
+impl SerialTransport for LinkCableEnd {
+    fn publish(&mut self, sb: u8, listening: bool) {
+        let mut shared = self.shared.lock().unwrap();
+        let port = &mut shared[self.side];
+
+        port.sb = sb;
+        port.listening = listening;
+    }
+
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        let mut shared = self.shared.lock().unwrap();
+        let other = &mut shared[1 - self.side];
+
+        if other.listening {
+            other.listening = false;
+            other.inbox = Some(outgoing);
+            other.sb
+        } else {
+            0xFF
+        }
+    }
+
+    fn take_incoming(&mut self) -> Option<u8> {
+        self.shared.lock().unwrap()[self.side].inbox.take()
+    }
+}
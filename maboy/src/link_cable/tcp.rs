@@ -0,0 +1,218 @@
+//! A [`SerialTransport`] that links two emulators over TCP, so they can run in
+//! different processes or on different machines.
+//!
+//! Waiting for a network round trip on every transferred byte would slow emulation
+//! down to a crawl, so the transport never blocks. Instead, both sides continuously
+//! send the state of their serial port to each other. When our Game Boy drives a
+//! transfer, the result is taken from the most recent state that the other side sent
+//! us, and our byte is sent over to be picked up by the other side whenever it arrives.
+//! Received bytes are buffered until the serial port picks them up.
+//!
+//! The connection is kept alive with heartbeats. If we don't hear anything from the
+//! other side for longer than the timeout, it is treated as unplugged, so transfers
+//! with internal clock receive 0xFF just like without a cable.
+//!
+//! The protocol is dead simple: Every message consists of two bytes, a tag and a value.
+
+use super::SerialTransport;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The other side is not listening, value is its SB register
+const TAG_IDLE: u8 = 0;
+/// The other side is listening, value is its SB register
+const TAG_LISTENING: u8 = 1;
+/// The other side drove a transfer, value is the byte it shifted out
+const TAG_DATA: u8 = 2;
+/// Keeps the connection alive, value is ignored
+const TAG_HEARTBEAT: u8 = 3;
+
+/// How often a heartbeat is sent if nothing else happens
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Default value for [`TcpSerialTransport::set_timeout`]
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Links the serial port to another MaBoy instance via TCP. See the
+/// [module documentation](self) for how this works.
+pub struct TcpSerialTransport {
+    shared: Arc<Mutex<Shared>>,
+    outbox: Sender<[u8; 2]>,
+    timeout: Duration,
+}
+
+/// State that is shared with the reader thread
+struct Shared {
+    /// Last known SB register of the other side
+    remote_sb: u8,
+    /// Whether the other side was listening when it last told us about its state
+    remote_listening: bool,
+    /// When we last received a message from the other side
+    last_received: Instant,
+    /// Set by the reader thread once the connection is gone for good
+    closed: bool,
+    /// Whether our own serial port is listening. Bytes that arrive while we are
+    /// not listening were sent based on outdated information and are dropped.
+    local_listening: bool,
+    /// Bytes that the other side shifted into our serial port, oldest first
+    inbox: VecDeque<u8>,
+}
+
+impl TcpSerialTransport {
+    /// Connects to another MaBoy instance that is waiting in [`TcpSerialTransport::listen`]
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpSerialTransport> {
+        TcpSerialTransport::from_stream(TcpStream::connect(addr)?)
+    }
+
+    /// Waits (blocking) until another MaBoy instance connects to `addr`
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<TcpSerialTransport> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        TcpSerialTransport::from_stream(stream)
+    }
+
+    /// Uses an already established connection. This spawns two background threads
+    /// that run until the transport is dropped or the connection is closed.
+    pub fn from_stream(stream: TcpStream) -> io::Result<TcpSerialTransport> {
+        // Messages are tiny, so we don't want them to be held back
+        stream.set_nodelay(true)?;
+
+        let shared = Arc::new(Mutex::new(Shared {
+            remote_sb: 0xFF,
+            remote_listening: false,
+            last_received: Instant::now(),
+            closed: false,
+            local_listening: false,
+            inbox: VecDeque::new(),
+        }));
+
+        let (outbox, outbox_receiver) = mpsc::channel::<[u8; 2]>();
+
+        let mut writer = stream.try_clone()?;
+        thread::spawn(move || {
+            loop {
+                let msg = match outbox_receiver.recv_timeout(HEARTBEAT_INTERVAL) {
+                    Ok(msg) => msg,
+                    Err(RecvTimeoutError::Timeout) => [TAG_HEARTBEAT, 0],
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                if writer.write_all(&msg).is_err() {
+                    break;
+                }
+            }
+
+            // Also unblocks the reader thread
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+
+        let mut reader = stream;
+        let reader_shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let mut msg = [0u8; 2];
+
+            while reader.read_exact(&mut msg).is_ok() {
+                let mut shared = reader_shared.lock().unwrap();
+                shared.last_received = Instant::now();
+
+                match msg {
+                    [TAG_IDLE, sb] | [TAG_LISTENING, sb] => {
+                        shared.remote_sb = sb;
+                        shared.remote_listening = msg[0] == TAG_LISTENING;
+                    }
+                    [TAG_DATA, val] => {
+                        if shared.local_listening {
+                            shared.inbox.push_back(val);
+                        }
+                    }
+                    [TAG_HEARTBEAT, _] => (),
+                    _ => {
+                        log::warn!("Invalid message on serial link: {:?}", msg);
+                        break;
+                    }
+                }
+            }
+
+            reader_shared.lock().unwrap().closed = true;
+        });
+
+        Ok(TcpSerialTransport {
+            shared,
+            outbox,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Sets how long the other side may stay silent before it is treated as unplugged.
+    /// Should be well above [`HEARTBEAT_INTERVAL`] (250ms). The default is 2 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Whether the other side is still connected and responsive
+    pub fn is_connected(&self) -> bool {
+        let shared = self.shared.lock().unwrap();
+        !shared.closed && shared.last_received.elapsed() <= self.timeout
+    }
+
+    fn send(&self, tag: u8, val: u8) {
+        // If the writer thread is gone, the reader will notice soon enough
+        let _ = self.outbox.send([tag, val]);
+    }
+}
+
+impl SerialTransport for TcpSerialTransport {
+    fn publish(&mut self, sb: u8, listening: bool) {
+        let mut shared = self.shared.lock().unwrap();
+
+        if !listening {
+            shared.inbox.clear();
+        }
+        shared.local_listening = listening;
+
+        drop(shared);
+
+        self.send(if listening { TAG_LISTENING } else { TAG_IDLE }, sb);
+    }
+
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        if !self.is_connected() {
+            return 0xFF;
+        }
+
+        let mut shared = self.shared.lock().unwrap();
+
+        if shared.remote_listening {
+            // The other side will stop listening once it receives our byte. Until it
+            // tells us otherwise, we assume that it already did.
+            shared.remote_listening = false;
+            let incoming = shared.remote_sb;
+
+            drop(shared);
+
+            self.send(TAG_DATA, outgoing);
+            incoming
+        } else {
+            0xFF
+        }
+    }
+
+    fn take_incoming(&mut self) -> Option<u8> {
+        self.shared.lock().unwrap().inbox.pop_front()
+    }
+}
+
+impl Drop for TcpSerialTransport {
+    fn drop(&mut self) {
+        // Let the other side know right away instead of waiting for the timeout
+        self.send(TAG_IDLE, 0xFF);
+    }
+}
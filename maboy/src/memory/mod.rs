@@ -6,7 +6,9 @@
 mod internal_mem;
 
 use super::cartridge::Cartridge;
-use crate::address::{CRomAddr, MemAddr};
+use crate::address::{Addr, CRomAddr, MemAddr};
+use crate::cheats::CheatEngine;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 
 pub use internal_mem::InternalMem;
 
@@ -15,7 +17,10 @@ pub use internal_mem::InternalMem;
 pub struct Memory<C> {
     internal: InternalMem,
     cartridge: C,
+    /// `None` if the emulator starts without a boot ROM (see [`Memory::set_boot_rom`])
+    boot_rom: Option<Box<[u8; 256]>>,
     boot_rom_mapped: bool,
+    cheats: CheatEngine,
 }
 
 impl<C: Cartridge> Memory<C> {
@@ -23,7 +28,9 @@ impl<C: Cartridge> Memory<C> {
         Memory {
             internal: internal_mem,
             cartridge: cartridge,
+            boot_rom: Some(Box::new(DMG_BOOT_ROM)),
             boot_rom_mapped: true,
+            cheats: CheatEngine::new(),
         }
     }
 
@@ -32,8 +39,13 @@ impl<C: Cartridge> Memory<C> {
         use MemAddr::*;
 
         match addr {
-            CROM(CROM0(addr)) if self.boot_rom_mapped && addr < 0x100 => BOOT_ROM[addr as usize],
-            CROM(addr) => self.cartridge.read_rom(addr),
+            CROM(CROM0(addr)) if self.boot_rom_mapped && addr < 0x100 => {
+                // The boot ROM is never mapped if there is none
+                self.boot_rom
+                    .as_ref()
+                    .map_or(0xFF, |rom| rom[addr as usize])
+            }
+            CROM(addr) => self.cheats.patch_rom(addr, self.cartridge.read_rom(addr)),
             CRAM(addr) => self.cartridge.read_cram(addr),
             WRAM(addr) => self.internal.wram[addr as usize],
             ECHO(addr) => self.internal.wram[addr as usize],
@@ -53,20 +65,100 @@ impl<C: Cartridge> Memory<C> {
         }
     }
 
-    /// The boot rom writes 1 to 0xff50 to disable itself after completing
+    /// Clears internal memory, maps the boot ROM back in (if there is one) and resets
+    /// the cartridge to its power-on state.
+    pub fn reset(&mut self) {
+        self.internal = InternalMem::new();
+        self.boot_rom_mapped = self.boot_rom.is_some();
+        self.cartridge.reset();
+    }
+
+    /// Replaces the boot ROM, or removes it if `None` is passed. Only takes effect
+    /// after the next reset.
+    pub fn set_boot_rom(&mut self, boot_rom: Option<[u8; 256]>) {
+        self.boot_rom = boot_rom.map(Box::new);
+    }
+
+    pub fn has_boot_rom(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    pub fn cheats(&self) -> &CheatEngine {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut CheatEngine {
+        &mut self.cheats
+    }
+
+    /// Performs the RAM writes of all enabled GameShark codes
+    pub fn apply_ram_cheats(&mut self) {
+        let writes: Vec<(u16, u8)> = self.cheats.ram_writes().collect();
+
+        for (addr, val) in writes {
+            if let Addr::Mem(mem_addr) = Addr::from(addr) {
+                self.write8(mem_addr, val);
+            }
+        }
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    pub fn cartridge(&self) -> &C {
+        &self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut C {
+        &mut self.cartridge
+    }
+
+    /// Identifies the inserted cartridge via the header checksum and the global
+    /// checksum in its header (0x14D - 0x14F). Used to make sure savestates are only
+    /// loaded for the cartridge that created them.
+    pub fn cartridge_id(&self) -> [u8; 3] {
+        [
+            self.cartridge.read_rom(CRomAddr::CROM0(0x14D)),
+            self.cartridge.read_rom(CRomAddr::CROM0(0x14E)),
+            self.cartridge.read_rom(CRomAddr::CROM0(0x14F)),
+        ]
+    }
+
+    /// The boot rom writes 1 to 0xff50 to disable itself after completing (0xFF on the
+    /// Game Boy Pocket). Any value other than 0 does that, and once it is disabled, the
+    /// boot rom can't be mapped in again.
     pub fn write_ff50(&mut self, val: u8) {
-        if val == 1 {
+        if val != 0 {
             self.boot_rom_mapped = false;
-        } else {
-            unimplemented!("Don't know what happens here")
         }
     }
 }
 
+impl<C: Cartridge> SaveState for Memory<C> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.internal.wram);
+        writer.write_bytes(&self.internal.hram);
+        writer.write_bool(self.boot_rom_mapped);
+        self.cartridge.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes(&mut self.internal.wram)?;
+        reader.read_bytes(&mut self.internal.hram)?;
+        // A savestate from the middle of the boot process can be loaded by an emulator
+        // without boot ROM, but the boot ROM won't be there
+        self.boot_rom_mapped = reader.read_bool()? && self.boot_rom.is_some();
+        self.cartridge.load_state(reader)
+    }
+}
+
 /// When the Game Boy boots up, these 256 bytes are mapped to the lowest 256 addresses instead of
 /// the corresponding bytes in the cartridge ROM. This re-mapping is disabled after this boot rom
-/// has successfully finished executing (see [`Memory::write_ff50`]).
-const BOOT_ROM: [u8; 256] = [
+/// has successfully finished executing (see [`Memory::write_ff50`]). This is the boot ROM of the
+/// original Game Boy (DMG), which is used unless another one is set via
+/// [`Memory::set_boot_rom`].
+pub(crate) const DMG_BOOT_ROM: [u8; 256] = [
     0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32, 0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
     0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3, 0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
     0x47, 0x11, 0x04, 0x01, 0x21, 0x10, 0x80, 0x1A, 0xCD, 0x95, 0x00, 0xCD, 0x96, 0x00, 0x13, 0x7B,
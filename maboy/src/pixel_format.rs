@@ -0,0 +1,215 @@
+//! Conversions from the [`MemPixel`]s of a frame to the pixel formats that frontends
+//! usually want to upload, so not every frontend has to write its own loop:
+//!
+//! - RGBA8 (web canvases, wgpu, PNG): [`as_rgba8`] doesn't even need a copy
+//! - BGRA8 (Direct3D swap chains, most OS window surfaces): [`to_bgra8`]
+//! - 0RGB in a `u32` (softbuffer, minifb, SDL with `ARGB8888`): [`to_xrgb8888`]
+//! - RGB565 (small embedded LCDs): [`to_rgb565`]
+//! - Planar RGB, one plane per channel (video encoders, image processing):
+//!   [`to_planar_rgb`]
+//!
+//! The converting functions write into a buffer that the caller owns, so a frontend
+//! can reuse it for every frame. They panic if the buffer doesn't have the right size.
+//!
+//! There is no hand-written SIMD here: The loops are branchless and work on whole
+//! pixels, which leaves vectorizing them to the compiler (how well that works depends
+//! on the target features). Run the `pixel_formats` example to see how fast they are
+//! on your machine.
+
+use crate::MemPixel;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8,
+    Bgra8,
+    Xrgb8888,
+    Rgb565,
+    PlanarRgb,
+}
+
+#[derive(Debug)]
+pub struct ParsePixelFormatError(pub String);
+
+impl PixelFormat {
+    pub const ALL: [PixelFormat; 5] = [
+        PixelFormat::Rgba8,
+        PixelFormat::Bgra8,
+        PixelFormat::Xrgb8888,
+        PixelFormat::Rgb565,
+        PixelFormat::PlanarRgb,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Rgba8 => "rgba8",
+            PixelFormat::Bgra8 => "bgra8",
+            PixelFormat::Xrgb8888 => "xrgb8888",
+            PixelFormat::Rgb565 => "rgb565",
+            PixelFormat::PlanarRgb => "planar-rgb",
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 | PixelFormat::Xrgb8888 => 4,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::PlanarRgb => 3,
+        }
+    }
+
+    /// Converts `pixels` into this format, as raw bytes. Multi-byte pixels (0RGB and
+    /// RGB565) are stored in little-endian. `dst` needs to be exactly
+    /// `pixels.len() * self.bytes_per_pixel()` bytes long.
+    pub fn convert(self, pixels: &[MemPixel], dst: &mut [u8]) {
+        assert_eq!(
+            dst.len(),
+            pixels.len() * self.bytes_per_pixel(),
+            "Destination buffer has the wrong size for this pixel format"
+        );
+
+        match self {
+            PixelFormat::Rgba8 => to_rgba8(pixels, dst),
+            PixelFormat::Bgra8 => to_bgra8(pixels, dst),
+            PixelFormat::Xrgb8888 => {
+                for (dst, &pixel) in dst.chunks_exact_mut(4).zip(pixels) {
+                    dst.copy_from_slice(&xrgb8888(pixel).to_le_bytes());
+                }
+            }
+            PixelFormat::Rgb565 => {
+                for (dst, &pixel) in dst.chunks_exact_mut(2).zip(pixels) {
+                    dst.copy_from_slice(&rgb565(pixel).to_le_bytes());
+                }
+            }
+            PixelFormat::PlanarRgb => to_planar_rgb(pixels, dst),
+        }
+    }
+
+    /// Like [`PixelFormat::convert`], but allocates the buffer
+    pub fn to_vec(self, pixels: &[MemPixel]) -> Vec<u8> {
+        let mut dst = vec![0; pixels.len() * self.bytes_per_pixel()];
+        self.convert(pixels, &mut dst);
+        dst
+    }
+}
+
+impl std::fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = ParsePixelFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PixelFormat::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| ParsePixelFormatError(s.to_owned()))
+    }
+}
+
+/// The pixels as RGBA bytes, without copying anything. [`MemPixel`] has exactly that
+/// layout.
+pub fn as_rgba8(pixels: &[MemPixel]) -> &[u8] {
+    // SAFETY: MemPixel is repr(C) and consists of four u8 fields, so it has a size of 4,
+    // an alignment of 1 and no padding.
+    unsafe { std::slice::from_raw_parts(pixels.as_ptr() as *const u8, pixels.len() * 4) }
+}
+
+/// Copies the pixels as RGBA bytes. `dst` needs to be exactly `4 * pixels.len()` long.
+pub fn to_rgba8(pixels: &[MemPixel], dst: &mut [u8]) {
+    assert_eq!(
+        dst.len(),
+        pixels.len() * 4,
+        "Destination buffer has the wrong size"
+    );
+
+    dst.copy_from_slice(as_rgba8(pixels));
+}
+
+/// Converts the pixels to BGRA bytes. `dst` needs to be exactly `4 * pixels.len()` long.
+pub fn to_bgra8(pixels: &[MemPixel], dst: &mut [u8]) {
+    assert_eq!(
+        dst.len(),
+        pixels.len() * 4,
+        "Destination buffer has the wrong size"
+    );
+
+    for (dst, src) in dst
+        .chunks_exact_mut(4)
+        .zip(as_rgba8(pixels).chunks_exact(4))
+    {
+        let bgra = swap_red_blue(u32::from_le_bytes([src[0], src[1], src[2], src[3]]));
+        dst.copy_from_slice(&bgra.to_le_bytes());
+    }
+}
+
+/// Converts the pixels to 0RGB `u32`s (see [`xrgb8888`]). `dst` needs to be exactly as
+/// long as `pixels`.
+pub fn to_xrgb8888(pixels: &[MemPixel], dst: &mut [u32]) {
+    assert_eq!(
+        dst.len(),
+        pixels.len(),
+        "Destination buffer has the wrong size"
+    );
+
+    for (dst, src) in dst.iter_mut().zip(as_rgba8(pixels).chunks_exact(4)) {
+        *dst = swap_red_blue(u32::from_le_bytes([src[0], src[1], src[2], src[3]])) & 0x00FF_FFFF;
+    }
+}
+
+/// Converts the pixels to RGB565 `u16`s (see [`rgb565`]). `dst` needs to be exactly as
+/// long as `pixels`.
+pub fn to_rgb565(pixels: &[MemPixel], dst: &mut [u16]) {
+    assert_eq!(
+        dst.len(),
+        pixels.len(),
+        "Destination buffer has the wrong size"
+    );
+
+    for (dst, &pixel) in dst.iter_mut().zip(pixels) {
+        *dst = rgb565(pixel);
+    }
+}
+
+/// Splits the pixels into three planes: First all red values, then all green values,
+/// then all blue values. `dst` needs to be exactly `3 * pixels.len()` long.
+pub fn to_planar_rgb(pixels: &[MemPixel], dst: &mut [u8]) {
+    assert_eq!(
+        dst.len(),
+        pixels.len() * 3,
+        "Destination buffer has the wrong size"
+    );
+
+    let (r, gb) = dst.split_at_mut(pixels.len());
+    let (g, b) = gb.split_at_mut(pixels.len());
+
+    let planes = r.iter_mut().zip(g.iter_mut()).zip(b.iter_mut());
+
+    for (((r, g), b), pixel) in planes.zip(pixels) {
+        *r = pixel.r;
+        *g = pixel.g;
+        *b = pixel.b;
+    }
+}
+
+/// Red in bits 16-23, green in bits 8-15, blue in bits 0-7 and the top byte empty
+pub fn xrgb8888(pixel: MemPixel) -> u32 {
+    (pixel.r as u32) << 16 | (pixel.g as u32) << 8 | pixel.b as u32
+}
+
+/// 5 bits red, 6 bits green, 5 bits blue (from the most significant bit). The lower
+/// bits of each channel are cut off.
+pub fn rgb565(pixel: MemPixel) -> u16 {
+    (pixel.r as u16 >> 3) << 11 | (pixel.g as u16 >> 2) << 5 | pixel.b as u16 >> 3
+}
+
+/// Turns a little-endian RGBA word into a little-endian BGRA word and vice versa. Works
+/// on a whole word at a time, which is what lets the compiler vectorize the loops.
+#[inline(always)]
+fn swap_red_blue(rgba: u32) -> u32 {
+    (rgba & 0xFF00_FF00) | (rgba >> 16 & 0xFF) | (rgba & 0xFF) << 16
+}
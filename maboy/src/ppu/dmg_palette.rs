@@ -0,0 +1,66 @@
+//! The RGBA colors that the four shades of the original Game Boy are displayed with.
+//! The emulator draws with [`GREEN_LCD`] by default, but any palette can be set via
+//! [`crate::Emulator::set_dmg_palette`]. Index 0 is the lightest shade (and the color
+//! of a turned off LCD), index 3 the darkest.
+
+use super::MemPixel;
+
+/// A mapping from the four DMG shades to RGBA colors, from lightest to darkest
+pub type DmgPalette = [MemPixel; 4];
+
+/// The signature green tint of the original Game Boy's LCD
+pub const GREEN_LCD: DmgPalette = [
+    MemPixel::new(239, 255, 222, 255),
+    MemPixel::new(173, 215, 148, 255),
+    MemPixel::new(82, 146, 115, 255),
+    MemPixel::new(24, 52, 66, 255),
+];
+
+/// Evenly spaced gray values from white to black
+pub const GRAYSCALE: DmgPalette = [
+    MemPixel::new(255, 255, 255, 255),
+    MemPixel::new(170, 170, 170, 255),
+    MemPixel::new(85, 85, 85, 255),
+    MemPixel::new(0, 0, 0, 255),
+];
+
+/// The slightly olive, low-contrast grays of the Game Boy Pocket's LCD
+pub const POCKET: DmgPalette = [
+    MemPixel::new(196, 207, 161, 255),
+    MemPixel::new(139, 149, 109, 255),
+    MemPixel::new(77, 83, 60, 255),
+    MemPixel::new(31, 31, 31, 255),
+];
+
+/// Parses the name of a preset (`green`, `gray` or `pocket`) or a custom palette of
+/// four comma-separated hex colors from lightest to darkest, like
+/// `e0f8d0,88c070,346856,081820`
+pub fn parse(text: &str) -> Option<DmgPalette> {
+    match text.trim().to_ascii_lowercase().as_str() {
+        "green" => return Some(GREEN_LCD),
+        "gray" | "grey" | "grayscale" => return Some(GRAYSCALE),
+        "pocket" => return Some(POCKET),
+        _ => (),
+    }
+
+    let mut palette = GREEN_LCD;
+    let mut colors = text.split(',');
+
+    for shade in palette.iter_mut() {
+        let hex = colors.next()?.trim();
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+
+        if hex.len() != 6 {
+            return None;
+        }
+
+        let rgb = u32::from_str_radix(hex, 16).ok()?;
+        *shade = MemPixel::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255);
+    }
+
+    if colors.next().is_some() {
+        return None;
+    }
+
+    Some(palette)
+}
@@ -6,7 +6,7 @@ use crate::util::BitOps;
 #[derive(Copy, Clone)]
 pub struct LCDC(pub u8);
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum SpriteSize {
     W8H8,
     W8H16,
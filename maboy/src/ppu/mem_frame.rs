@@ -1,18 +1,23 @@
 //! See documentation of [`MemFrame`]
 
 use super::color::Color;
+use super::dmg_palette;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 
 const WIDTH: usize = 160;
 const HEIGHT: usize = 144;
 
 /// RGBA array representing the Game Boys LCD screen. This is the direct target
 /// of all rendering code; Each scanline is written directly into [`MemFrame`],
-/// without any buffering.
+/// without any buffering. With double buffering (see
+/// [`crate::Emulator::set_double_buffered`]), the PPU keeps a second one for the
+/// frontend to read from, and swaps the two whenever a frame is finished.
 ///
 /// In some cases (e.g. when the LCD is turned off), this array will contain old
 /// data. This should never be a problem for normal operation of the emulator,
 /// since it will only display finished frames, but is important to keep in mind
 /// during frame debugging.
+#[derive(Clone)]
 pub struct MemFrame {
     data: Box<[MemPixel]>,
 }
@@ -51,23 +56,11 @@ impl MemFrame {
     }
 }
 
-// TODO: Make this configurable
-/// The conversion from 2-bit color values to RGBA values
+/// The conversion from 2-bit color values to RGBA values with the default palette. The
+/// PPU draws with its own palette instead (see [`dmg_palette`]).
 impl From<Color> for MemPixel {
     fn from(col: Color) -> Self {
-        // These values simulate the original Game Boy's signature green tint...
-
-        use super::color::ColorVal;
-        match col.into_val() {
-            ColorVal::C00 => MemPixel::new(239, 255, 222, 255),
-            ColorVal::C01 => MemPixel::new(173, 215, 148, 255),
-            ColorVal::C10 => MemPixel::new(82, 146, 115, 255),
-            ColorVal::C11 => MemPixel::new(24, 52, 66, 255),
-        }
-
-        // ... and this conversion results in a direct mapping to grayscale values
-
-        // MemPixel::from_grayscale(255 - 85 * col.into_raw())
+        dmg_palette::GREEN_LCD[col.into_raw() as usize]
     }
 }
 
@@ -75,12 +68,32 @@ impl MemPixel {
     /// A fully transparent black pixel
     const CLEAR: MemPixel = MemPixel::new(0, 0, 0, 0);
 
+    /// What a turned off LCD looks like with the default palette (the same as color 0).
+    /// See [`crate::Emulator::dmg_palette`] for the palette that is actually used.
+    pub const LCD_OFF: MemPixel = dmg_palette::GREEN_LCD[0];
+
     pub const fn new(r: u8, g: u8, b: u8, a: u8) -> MemPixel {
         MemPixel { r, g, b, a }
     }
+}
+
+/// The frame is part of the savestate because a savestate might be taken in the
+/// middle of a frame, in which case the already drawn scanlines need to be kept.
+impl SaveState for MemFrame {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for pixel in self.data.iter() {
+            writer.write_bytes(&[pixel.r, pixel.g, pixel.b, pixel.a]);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut rgba = [0u8; 4];
+
+        for pixel in self.data.iter_mut() {
+            reader.read_bytes(&mut rgba)?;
+            *pixel = MemPixel::new(rgba[0], rgba[1], rgba[2], rgba[3]);
+        }
 
-    /// Private to avoid confusion about what kind of color values this takes (it is 0-255)
-    const fn _from_grayscale(grayscale: u8) -> MemPixel {
-        MemPixel::new(grayscale, grayscale, grayscale, 0xff)
+        Ok(())
     }
 }
@@ -5,6 +5,7 @@
 //! do each cycle. For more info, see [`PPU`].
 
 mod color;
+pub mod dmg_palette;
 mod lcdc;
 mod lcds;
 mod mem_frame;
@@ -13,25 +14,31 @@ mod palette;
 mod pixel_queue;
 mod ppu_registers;
 mod sprite;
+#[cfg(test)]
+mod tests;
 mod tile_data;
 mod tile_maps;
 
 use crate::address::{PpuReg, VideoMemAddr};
+use crate::debug::{DbgEvtSrc, PpuEvt};
 use crate::interrupt_system::{Interrupt, InterruptSystem};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::sgb::SgbFrame;
+use dmg_palette::DmgPalette;
 use mem_frame::MemFrame;
 use num_enum::UnsafeFromPrimitive;
 use oam::OAM;
-use palette::Palette;
 use pixel_queue::PixelQueue;
 use ppu_registers::PPURegisters;
 use tile_data::TileData;
 use tile_maps::TileMaps;
 
+pub(crate) use color::Color;
 pub use lcdc::LCDC;
 pub use lcds::LCDS;
 pub use mem_frame::MemPixel;
+pub use oam::OamBugAccess;
 
-// TODO: Replace some debug logs with PpuEvt
 // TODO: This whole file is kind of messy. Rethink the state machine approach.
 // TODO: Consistent naming of PPU vs Ppu
 
@@ -64,10 +71,15 @@ pub struct PPU {
     ///
     /// This field can have values in the range 0..=153
     ly: u8,
-    /// Cached value of the WY register. Apparently, the effect of this register persists
-    /// during an entire frame, but the backing value can still be changed arbitrarily.
-    /// This field saves the value of WY at the beginning of a frame.
-    wy: u8,
+    /// Whether LY matched WY at the start of a line in the current frame. Only then can
+    /// the window be drawn, and it stays possible for the rest of the frame, even if WY
+    /// changes in between.
+    window_triggered: bool,
+    /// The internal line counter of the window, i.e. the line of the window that is drawn
+    /// next. Unlike `ly - wy`, it only increases on lines where the window is actually
+    /// drawn, so turning the window off for a few lines (or moving it out of the screen
+    /// with WX) doesn't skip any of its lines.
+    window_line: u8,
     /// The part of VRAM responsible for the content of each tile (0x8000 - 0x97FF)
     tile_data: TileData,
     /// The part of VRAM responsible for indexes into the tile data that are rendered on
@@ -80,11 +92,61 @@ pub struct PPU {
     /// The backing data of the current frame. This data gets exposed via the API at the
     /// beginning of each VBlank period.
     mem_frame: MemFrame,
+    /// With double buffering, the last finished frame, which is swapped with `mem_frame`
+    /// at the beginning of each VBlank period. A setting like the palette, so it is
+    /// neither reset nor part of savestates.
+    front_frame: Option<MemFrame>,
+    /// The shades (0 - 3) of the pixels of the current frame, which are recorded for the
+    /// Super Game Boy (see [`PPU::set_shade_frame`]). Whether there is one at all is a
+    /// setting, but its content is part of savestates.
+    shade_frame: Option<Box<[u8]>>,
     /// Used as an indicator for the frontend whether a frame is ready / should be rendered.
     frame_ready: Option<FrameReady>,
     /// Used to skip the drawing of frames in case the LCD was just turned on. This behaviour
     /// is present on hardware.
     skip_frames: u8,
+    /// The RGBA colors of the four shades. Not part of the hardware state, so it is
+    /// neither reset nor part of savestates.
+    dmg_palette: DmgPalette,
+    /// Which frames are drawn into `mem_frame`. Like the palette, this is a setting that
+    /// is neither reset nor part of savestates (and neither is the counter).
+    frame_skip: FrameSkip,
+    /// Position of the current frame within the period of `frame_skip`
+    frame_skip_counter: u8,
+    /// Whether the current frame is drawn into `mem_frame`
+    rasterizing: bool,
+    /// Whether the OAM bug is emulated (see [`crate::Emulator::set_oam_bug`]). An
+    /// accuracy option, so it is neither reset nor part of savestates.
+    oam_bug: bool,
+    /// Whether writes to LCDS can trigger LCD Stat interrupts (see
+    /// [`crate::Emulator::set_stat_write_bug`]). Also an accuracy option.
+    stat_write_bug: bool,
+}
+
+/// Skips drawing `skip` out of every `period` frames (see
+/// [`crate::Emulator::set_frame_skip`]). Everything else (timing, interrupts, OAM search)
+/// still runs as usual, so games behave exactly the same.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameSkip {
+    pub skip: u8,
+    pub period: u8,
+}
+
+impl FrameSkip {
+    /// Draws every frame
+    pub const OFF: FrameSkip = FrameSkip { skip: 0, period: 1 };
+
+    /// Whether the frame at the given position within the period is skipped. The skipped
+    /// frames come first, so the last frame of every period is drawn (if `skip < period`).
+    fn skips(self, frame_idx: u8) -> bool {
+        frame_idx < self.skip
+    }
+}
+
+impl Default for FrameSkip {
+    fn default() -> Self {
+        FrameSkip::OFF
+    }
 }
 
 /// The (internally stored) type of frame that is ready to be drawn by the frontend
@@ -95,6 +157,15 @@ enum FrameReady {
     LcdOffFrame,
 }
 
+/// Result of [`Emulator::run_frame`]
+pub enum FrameResult<'a> {
+    /// A frame was completed; Frontend should draw its content
+    Frame(&'a [MemPixel]),
+    /// The LCD was turned off, or stayed off for the duration of an entire frame;
+    /// Frontend should draw a blank frame
+    LcdOff,
+}
+
 /// The type of frame *and* frame content that the frontend should draw
 pub enum VideoFrameStatus<'a> {
     /// Frontend should not draw anything
@@ -103,9 +174,13 @@ pub enum VideoFrameStatus<'a> {
     LcdTurnedOff,
     /// Frontend should draw the content of the frame
     Ready(&'a [MemPixel]),
+    /// Like [`VideoFrameStatus::Ready`], but on the Super Game Boy (see
+    /// [`crate::HardwareModel::Sgb`]), whose frames are colored and have a border
+    SgbReady(SgbFrame<'a>),
 }
 
-#[derive(Copy, Clone, Debug, UnsafeFromPrimitive)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, UnsafeFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[repr(u8)]
 pub enum Mode {
     LCDOff = 4,
@@ -131,29 +206,152 @@ impl PPU {
             mode: Mode::LCDOff,
             reg: PPURegisters::new(),
             ly: 0,
-            wy: 0,
+            window_triggered: false,
+            window_line: 0,
             tile_data: TileData::new(),
             tile_maps: TileMaps::new(),
             oam: OAM::new(),
             pixel_queue: PixelQueue::new(),
             mem_frame: MemFrame::new(),
+            front_frame: None,
+            shade_frame: None,
             frame_ready: None,
             skip_frames: 0,
+            dmg_palette: dmg_palette::GREEN_LCD,
+            frame_skip: FrameSkip::OFF,
+            frame_skip_counter: 0,
+            rasterizing: true,
+            oam_bug: false,
+            stat_write_bug: true,
+        }
+    }
+
+    /// See [`crate::Emulator::set_frame_skip`]. Takes effect with the next frame.
+    pub fn set_frame_skip(&mut self, frame_skip: FrameSkip) {
+        self.frame_skip = frame_skip;
+        self.frame_skip_counter = 0;
+    }
+
+    pub fn frame_skip(&self) -> FrameSkip {
+        self.frame_skip
+    }
+
+    /// See [`crate::Emulator::set_oam_bug`]
+    pub fn set_oam_bug(&mut self, enabled: bool) {
+        self.oam_bug = enabled;
+    }
+
+    pub fn oam_bug(&self) -> bool {
+        self.oam_bug
+    }
+
+    /// See [`crate::Emulator::set_stat_write_bug`]
+    pub fn set_stat_write_bug(&mut self, enabled: bool) {
+        self.stat_write_bug = enabled;
+    }
+
+    pub fn stat_write_bug(&self) -> bool {
+        self.stat_write_bug
+    }
+
+    /// To be called when the CPU puts an address in 0xFE00 - 0xFEFF on the bus. Corrupts
+    /// OAM if the OAM bug is enabled and OAM search is running (see [`OAM::corrupt`]).
+    pub fn trigger_oam_bug(&mut self, access: OamBugAccess) {
+        if self.oam_bug && matches!(self.mode, Mode::OAMSearch) {
+            self.oam.corrupt(access);
         }
     }
 
+    /// See [`crate::Emulator::set_double_buffered`]. When it's turned on, the current
+    /// content of the frame is the first completed frame.
+    pub fn set_double_buffered(&mut self, double_buffered: bool) {
+        if double_buffered == self.front_frame.is_some() {
+            return;
+        }
+
+        self.front_frame = if double_buffered {
+            Some(self.mem_frame.clone())
+        } else {
+            None
+        };
+    }
+
+    pub fn double_buffered(&self) -> bool {
+        self.front_frame.is_some()
+    }
+
+    /// Enables or disables recording the shades of all pixels of the current frame (see
+    /// [`PPU::shade_frame`]), which the Super Game Boy colors with its own palettes
+    pub fn set_shade_frame(&mut self, enabled: bool) {
+        if enabled != self.shade_frame.is_some() {
+            self.shade_frame = if enabled {
+                Some(vec![0; 160 * 144].into_boxed_slice())
+            } else {
+                None
+            };
+        }
+    }
+
+    /// The shades (0 - 3, after BGP/OBP0/OBP1 were applied) of the pixels of the current
+    /// frame, row by row, if recording them is enabled. Like the frame itself, this is only
+    /// complete right after a frame was finished.
+    pub fn shade_frame(&self) -> Option<&[u8]> {
+        self.shade_frame.as_deref()
+    }
+
+    /// See [`crate::Emulator::set_dmg_palette`]
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+    }
+
+    pub fn dmg_palette(&self) -> &DmgPalette {
+        &self.dmg_palette
+    }
+
     /// Used to make internal state visible to debugger
     pub fn ly_internal(&self) -> u8 {
         self.ly
     }
 
+    /// Used to make internal state visible to debugger. `None` if the window wasn't
+    /// triggered in the current frame yet.
+    pub fn window_line_internal(&self) -> Option<u8> {
+        Some(self.window_line).filter(|_| self.window_triggered)
+    }
+
+    /// Used to make internal state visible to debugger. The number of sprites in the
+    /// current scanline that OAM search left out because of the sprite limit.
+    pub fn dropped_sprites_internal(&self) -> u8 {
+        self.oam.dropped_sprites()
+    }
+
     /// Used to make internal state visible to debugger
-    pub fn wy_internal(&self) -> u8 {
-        self.wy
+    pub fn scanline_mcycle_internal(&self) -> u8 {
+        self.scanline_mcycle
+    }
+
+    /// Whether the PPU is currently not drawing anything, i.e. it is either in VBlank
+    /// or the LCD is turned off.
+    pub fn is_idle(&self) -> bool {
+        matches!(self.mode, Mode::VBlank | Mode::LCDOff)
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        !matches!(self.mode, Mode::LCDOff)
+    }
+
+    /// Whether [`PPU::query_frame_status`] would currently return anything other
+    /// than [`VideoFrameStatus::NotReady`]
+    pub fn has_frame_status(&self) -> bool {
+        self.frame_ready.is_some()
     }
 
     // TODO: Accurate timings for Mode 2 interrupt.. This is hard!
-    pub fn advance_mcycle(&mut self, ir_system: &mut InterruptSystem) {
+    pub fn advance_mcycle<E: DbgEvtSrc<PpuEvt>>(
+        &mut self,
+        ir_system: &mut InterruptSystem,
+        evts: &mut E,
+    ) {
         // We don't do anything if the LCD is turned off
         if matches!(self.mode, Mode::LCDOff) {
             return;
@@ -163,42 +361,34 @@ impl PPU {
         match self.ly {
             0 => match self.scanline_mcycle {
                 0 => {
-                    // TODO: Investigate the timing of this further
-                    // Save the current value of the WY register for the duration of the frame
-                    self.wy = self.reg.wy;
+                    self.window_triggered = false;
+                    self.window_line = 0;
+
+                    self.rasterizing = !self.frame_skip.skips(self.frame_skip_counter);
+                    self.frame_skip_counter =
+                        (self.frame_skip_counter + 1) % self.frame_skip.period.max(1);
 
                     self.reg.ly = 0;
                     // TODO: Check if this can cause HBlank interrupts. If yes, use
                     // self.update_mode(ir_system, Mode::HBlank);
                     self.mode = Mode::HBlank;
                     self.reg.lcds.set_mode(Mode::HBlank);
+                    evts.push(PpuEvt::ModeChange(0, Mode::HBlank));
                 }
                 1 => {
-                    self.update_mode_with_interrupts(ir_system, Mode::OAMSearch);
+                    self.update_mode_with_interrupts(ir_system, evts, Mode::OAMSearch);
+                    self.oam.start_search();
+                    self.oam.search_step(self.ly, self.reg.lcdc.sprite_size());
+                    self.check_window_trigger();
                 }
+                n if n <= 20 => self.oam.search_step(self.ly, self.reg.lcdc.sprite_size()),
                 21 => {
-                    self.update_mode_with_interrupts(ir_system, Mode::PixelTransfer);
-                    self.oam.rebuild();
-                    self.tile_data.rebuild();
-                    let num_sprites = self.pixel_queue.push_scanline(
-                        &self.reg,
-                        &self.tile_maps,
-                        &self.tile_data,
-                        &self.oam,
-                    );
-                    self.scanline_sprite_delay = num_sprites * 2;
-                }
-                n if n > 21 && n <= 61 => {
-                    self.pixel_queue.pop_pixel_quad(
-                        &self.tile_data,
-                        &self.tile_maps,
-                        &self.reg,
-                        self.mem_frame.line(self.ly),
-                        n - 22,
-                    );
+                    self.update_mode_with_interrupts(ir_system, evts, Mode::PixelTransfer);
+                    self.scanline_sprite_delay = self.push_scanline() * 2;
                 }
                 n if n == 64 + self.scanline_sprite_delay => {
-                    self.update_mode_with_interrupts(ir_system, Mode::HBlank);
+                    self.draw_scanline(40);
+                    self.update_mode_with_interrupts(ir_system, evts, Mode::HBlank);
                 }
                 _ => (),
             },
@@ -212,16 +402,23 @@ impl PPU {
 
                     if self.skip_frames == 0 {
                         self.frame_ready = Some(FrameReady::VideoFrame);
+
+                        // Skipped frames leave the last drawn one in front
+                        if let (Some(front_frame), true) = (&mut self.front_frame, self.rasterizing)
+                        {
+                            std::mem::swap(&mut self.mem_frame, front_frame);
+                        }
                     } else {
                         log::debug!("Skipped frame display");
+                        evts.push(PpuEvt::FrameSkipped);
                         self.skip_frames -= 1;
                     }
 
                     ir_system.schedule_interrupt(Interrupt::VBlank);
-                    self.update_lyc_equals_ly(ir_system, 144);
+                    self.update_lyc_equals_ly(ir_system, evts, 144);
                     // TODO: VBLANK IR isn't triggered when IF is manually written to this cycle... JESUS
                     // Actually, this might already happen... hmmm
-                    self.update_mode_with_interrupts(ir_system, Mode::VBlank);
+                    self.update_mode_with_interrupts(ir_system, evts, Mode::VBlank);
                 }
                 _ => (),
             },
@@ -232,11 +429,11 @@ impl PPU {
                 }
                 1 => {
                     self.reg.ly = 0;
-                    self.update_lyc_equals_ly(ir_system, 153);
+                    self.update_lyc_equals_ly(ir_system, evts, 153);
                 }
                 2 => self.reg.lcds.set_lyc_equals_ly(false),
                 3 => {
-                    self.update_lyc_equals_ly(ir_system, 0);
+                    self.update_lyc_equals_ly(ir_system, evts, 0);
                 }
                 _ => (),
             },
@@ -246,32 +443,20 @@ impl PPU {
                     self.reg.lcds.set_lyc_equals_ly(false);
                 }
                 1 => {
-                    self.update_mode_with_interrupts(ir_system, Mode::OAMSearch);
-                    self.update_lyc_equals_ly(ir_system, line);
+                    self.update_mode_with_interrupts(ir_system, evts, Mode::OAMSearch);
+                    self.oam.start_search();
+                    self.oam.search_step(self.ly, self.reg.lcdc.sprite_size());
+                    self.check_window_trigger();
+                    self.update_lyc_equals_ly(ir_system, evts, line);
                 }
+                n if n <= 20 => self.oam.search_step(self.ly, self.reg.lcdc.sprite_size()),
                 21 => {
-                    self.update_mode_with_interrupts(ir_system, Mode::PixelTransfer);
-                    self.oam.rebuild();
-                    self.tile_data.rebuild();
-                    let num_sprites = self.pixel_queue.push_scanline(
-                        &self.reg,
-                        &self.tile_maps,
-                        &self.tile_data,
-                        &self.oam,
-                    );
-                    self.scanline_sprite_delay = num_sprites * 2;
-                }
-                n if n > 21 && n <= 61 => {
-                    self.pixel_queue.pop_pixel_quad(
-                        &self.tile_data,
-                        &self.tile_maps,
-                        &self.reg,
-                        self.mem_frame.line(self.ly),
-                        n - 22,
-                    );
+                    self.update_mode_with_interrupts(ir_system, evts, Mode::PixelTransfer);
+                    self.scanline_sprite_delay = self.push_scanline() * 2;
                 }
                 n if n == 64 + self.scanline_sprite_delay => {
-                    self.update_mode_with_interrupts(ir_system, Mode::HBlank);
+                    self.draw_scanline(40);
+                    self.update_mode_with_interrupts(ir_system, evts, Mode::HBlank);
                 }
                 _ => (),
             },
@@ -281,14 +466,56 @@ impl PPU {
                     self.reg.lcds.set_lyc_equals_ly(false)
                 }
                 1 => {
-                    self.update_lyc_equals_ly(ir_system, line);
+                    self.update_lyc_equals_ly(ir_system, evts, line);
                 }
                 _ => (),
             },
         };
 
+        self.advance_scanline_mcycles(1);
+    }
+
+    /// How many of the next machine cycles the PPU does nothing in (except count them),
+    /// see [`PPU::skip_mcycles`]. Every scanline starts with a few busy cycles, so this
+    /// never reaches past the end of the current one.
+    pub fn idle_mcycles(&self) -> u32 {
+        if matches!(self.mode, Mode::LCDOff) {
+            return u32::MAX;
+        }
+
+        // Cycles from this one on are idle until the end of the scanline, except for the
+        // end of mode 3 in visible lines
+        let (idle_from, hblank_at) = match self.ly {
+            0..=143 => (22, Some(64 + self.scanline_sprite_delay)),
+            153 => (4, None),
+            _ => (2, None),
+        };
+
+        let next_event = match hblank_at {
+            Some(hblank_at) if self.scanline_mcycle <= hblank_at => hblank_at,
+            _ => 114,
+        };
+
+        if self.scanline_mcycle < idle_from {
+            0
+        } else {
+            (next_event - self.scanline_mcycle) as u32
+        }
+    }
+
+    /// Advances the PPU by `mcycles` machine cycles at once. Must not be more than
+    /// [`PPU::idle_mcycles`].
+    pub fn skip_mcycles(&mut self, mcycles: u32) {
+        debug_assert!(mcycles <= self.idle_mcycles());
+
+        if !matches!(self.mode, Mode::LCDOff) {
+            self.advance_scanline_mcycles(mcycles as u8);
+        }
+    }
+
+    fn advance_scanline_mcycles(&mut self, mcycles: u8) {
         // Advance internal state machine
-        self.scanline_mcycle += 1;
+        self.scanline_mcycle += mcycles;
         if self.scanline_mcycle == 114 {
             self.scanline_mcycle = 0;
 
@@ -299,10 +526,84 @@ impl PPU {
         }
     }
 
+    /// To be called at the start of OAM search. The window is triggered for the rest of
+    /// the frame once WY matches the line at this point.
+    fn check_window_trigger(&mut self) {
+        if self.reg.wy == self.ly {
+            self.window_triggered = true;
+        }
+    }
+
+    /// Prepares the pixels of the current line at the start of mode 3. Returns the number
+    /// of sprites in the line, which is needed for the timing even if the frame is
+    /// skipped.
+    fn push_scanline(&mut self) -> u8 {
+        // WX values up to 166 put at least one pixel of the window on the screen. Lower
+        // values than 7 move its left edge out of the screen.
+        let window_line =
+            if self.window_triggered && self.reg.lcdc.window_enabled() && self.reg.wx <= 166 {
+                self.window_line += 1;
+                Some(self.window_line - 1)
+            } else {
+                None
+            };
+
+        if !self.rasterizing {
+            return if self.reg.lcdc.sprites_enabled() {
+                self.oam.sprites_in_line().count() as u8
+            } else {
+                0
+            };
+        }
+
+        self.tile_data.rebuild();
+        self.pixel_queue.push_scanline(
+            &self.reg,
+            &self.tile_maps,
+            &self.tile_data,
+            &self.oam,
+            window_line,
+        )
+    }
+
+    /// Draws the quads before `end_quad` of the current line at the end of mode 3,
+    /// unless the frame is skipped
+    fn draw_scanline(&mut self, end_quad: u8) {
+        if !self.rasterizing {
+            return;
+        }
+
+        let line = 160 * self.ly as usize..160 * (self.ly as usize + 1);
+
+        self.pixel_queue.draw_line(
+            &self.tile_data,
+            &mut self.tile_maps,
+            &self.dmg_palette,
+            self.mem_frame.line(self.ly),
+            self.shade_frame
+                .as_deref_mut()
+                .map(|shades| &mut shades[line]),
+            end_quad,
+        );
+
+        self.tile_maps.notify_lcdc_changed(self.reg.lcdc);
+    }
+
+    /// The quad that is shifted out in the current cycle of mode 3. Up to 40 quads are
+    /// shifted out, one per cycle, starting right after the cycle that enters mode 3.
+    fn current_quad(&self) -> u8 {
+        self.scanline_mcycle.saturating_sub(22)
+    }
+
+    /// See [`Emulator::completed_frame`]
+    pub fn completed_frame(&self) -> &[MemPixel] {
+        self.front_frame.as_ref().unwrap_or(&self.mem_frame).data()
+    }
+
     /// See [`Emulator::query_video_frame_status`]
     pub fn query_frame_status(&mut self) -> VideoFrameStatus {
         match self.frame_ready.take() {
-            Some(FrameReady::VideoFrame) => VideoFrameStatus::Ready(self.mem_frame.data()),
+            Some(FrameReady::VideoFrame) => VideoFrameStatus::Ready(self.completed_frame()),
             Some(FrameReady::LcdOffFrame) => VideoFrameStatus::LcdTurnedOff,
             None => VideoFrameStatus::NotReady,
         }
@@ -312,13 +613,28 @@ impl PPU {
         self.reg.cpu_read(reg)
     }
 
-    pub fn write_reg(&mut self, ir_system: &mut InterruptSystem, reg: PpuReg, val: u8) {
+    pub fn write_reg<E: DbgEvtSrc<PpuEvt>>(
+        &mut self,
+        ir_system: &mut InterruptSystem,
+        evts: &mut E,
+        reg: PpuReg,
+        val: u8,
+    ) {
+        if matches!(reg, PpuReg::LCDS) && self.stat_write_bug {
+            self.trigger_stat_write_bug(ir_system);
+        }
+
         self.reg.cpu_write(reg, val);
 
-        // TODO: Trigger the false LCD Stat interrupts that seem to occur when writing to LCDS
+        if matches!(self.mode, Mode::PixelTransfer) && self.rasterizing && self.current_quad() < 40
+        {
+            self.pixel_queue
+                .log_reg_write(self.current_quad(), reg, val);
+        }
+
         match reg {
-            PpuReg::LCDC => self.notify_lcdc_changed(ir_system),
-            PpuReg::LYC => self.update_lyc_equals_ly(ir_system, self.reg.ly), // TODO: Check if this behaviour is correct
+            PpuReg::LCDC => self.notify_lcdc_changed(ir_system, evts),
+            PpuReg::LYC => self.update_lyc_equals_ly(ir_system, evts, self.reg.ly), // TODO: Check if this behaviour is correct
             _ => (),
         }
     }
@@ -367,24 +683,60 @@ impl PPU {
         }
     }
 
+    /// Reads video memory regardless of the PPU mode, e.g. for debugging tools that
+    /// look at VRAM while it's in use
+    pub fn peek_video_mem(&self, addr: VideoMemAddr) -> u8 {
+        match addr {
+            VideoMemAddr::TileData(addr) => self.tile_data[addr],
+            VideoMemAddr::TileMaps(addr) => self.tile_maps.mem[addr as usize],
+            VideoMemAddr::OAM(addr) => self.oam[addr],
+        }
+    }
+
+    /// Whether the CPU can currently access the given part of video memory. Accesses
+    /// are blocked while the PPU uses the memory itself.
+    pub fn video_mem_accessible(&self, addr: VideoMemAddr) -> bool {
+        PPU::video_mem_accessible_in(self.mode, addr)
+    }
+
+    /// Whether the CPU can access the given part of video memory while the PPU is in the
+    /// given (internal) mode. Also used for [`crate::debug::describe_memory_map`].
+    pub fn video_mem_accessible_in(mode: Mode, addr: VideoMemAddr) -> bool {
+        match addr {
+            VideoMemAddr::TileData(_) | VideoMemAddr::TileMaps(_) => {
+                !matches!(mode, Mode::PixelTransfer)
+            }
+            VideoMemAddr::OAM(_) => !matches!(mode, Mode::OAMSearch | Mode::PixelTransfer),
+        }
+    }
+
+    /// The *internal* mode, see [`PPU`]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
     fn vram_accessible(&self) -> bool {
-        !matches!(self.mode, Mode::PixelTransfer)
+        self.video_mem_accessible(VideoMemAddr::TileData(0))
     }
 
     fn oam_accessible(&self) -> bool {
-        !matches!(self.mode, Mode::OAMSearch | Mode::PixelTransfer)
+        self.video_mem_accessible(VideoMemAddr::OAM(0))
     }
 
     /// To be called after the CPU writes to LCDC. Notifies all subsystems of the change and
     /// handles the logic for turning the LCD on and off.
-    fn notify_lcdc_changed(&mut self, ir_system: &mut InterruptSystem) {
+    fn notify_lcdc_changed<E: DbgEvtSrc<PpuEvt>>(
+        &mut self,
+        ir_system: &mut InterruptSystem,
+        evts: &mut E,
+    ) {
         self.tile_maps.notify_lcdc_changed(self.reg.lcdc);
-        self.oam.notify_lcdc_changed(self.reg.lcdc);
 
         if self.reg.lcdc.lcd_enabled() {
             if matches!(self.mode, Mode::LCDOff) {
                 // Turn LCD on
                 log::info!("Turned LCD on");
+                evts.push(PpuEvt::LcdOn);
 
                 // TODO: 5+ frames skipped fixes a graphical glitch in Pokemon Red
                 // that renders garbage for a few frames. On actual hardware, however,
@@ -392,7 +744,7 @@ impl PPU {
                 self.skip_frames = 1;
 
                 // TODO: Investigate the timing of this...
-                self.update_mode_with_interrupts(ir_system, Mode::HBlank);
+                self.update_mode_with_interrupts(ir_system, evts, Mode::HBlank);
             }
         } else {
             if !matches!(self.mode, Mode::LCDOff) {
@@ -402,6 +754,12 @@ impl PPU {
 
                 // Turn LCD off
                 log::info!("Turned LCD off");
+                evts.push(PpuEvt::LcdOff(self.ly));
+
+                // The rest of the line is never drawn
+                if matches!(self.mode, Mode::PixelTransfer) {
+                    self.draw_scanline(self.current_quad());
+                }
 
                 self.frame_ready = Some(FrameReady::LcdOffFrame);
 
@@ -412,30 +770,60 @@ impl PPU {
                 self.ly = 0;
                 self.scanline_mcycle = 0;
 
-                self.update_mode_with_interrupts(ir_system, Mode::LCDOff);
+                self.update_mode_with_interrupts(ir_system, evts, Mode::LCDOff);
             }
         }
     }
 
+    /// On the original Game Boy, all LCD Stat interrupt conditions are enabled for one
+    /// cycle while LCDS is written. So the write itself requests an interrupt in HBlank,
+    /// VBlank or when LY == LYC, unless the conditions that were already enabled block it.
+    fn trigger_stat_write_bug(&mut self, ir_system: &mut InterruptSystem) {
+        let lcds = &self.reg.lcds;
+
+        if matches!(self.mode, Mode::LCDOff) || lcds.any_conditions_met() {
+            return;
+        }
+
+        if lcds.lyc_equals_ly() || matches!(lcds.mode(), Mode::HBlank | Mode::VBlank) {
+            ir_system.schedule_interrupt(Interrupt::LcdStat);
+        }
+    }
+
     /// Call this whenever a LCD Stat interrupt caused by LY==LYC could happen. The `ly`
     /// parameter is the value that the LYC register is compared against to determine
     /// whether to throw the interrupt.
-    fn update_lyc_equals_ly(&mut self, ir_system: &mut InterruptSystem, ly: u8) {
+    fn update_lyc_equals_ly<E: DbgEvtSrc<PpuEvt>>(
+        &mut self,
+        ir_system: &mut InterruptSystem,
+        evts: &mut E,
+        ly: u8,
+    ) {
         let ly_lyc_equal = ly == self.reg.lyc;
 
-        if ly_lyc_equal
-            && self.reg.lcds.ly_coincidence_interrupt()
-            && (!self.reg.lcds.any_conditions_met())
-        {
-            ir_system.schedule_interrupt(Interrupt::LcdStat);
+        if ly_lyc_equal {
+            let interrupt =
+                self.reg.lcds.ly_coincidence_interrupt() && (!self.reg.lcds.any_conditions_met());
+
+            if interrupt {
+                ir_system.schedule_interrupt(Interrupt::LcdStat);
+            }
+
+            evts.push(PpuEvt::LycMatch(ly, interrupt));
         }
 
         self.reg.lcds.set_lyc_equals_ly(ly_lyc_equal);
     }
 
     /// Updates the internal mode and the LCDS register and triggers any potential LCD Stat interrupts.
-    fn update_mode_with_interrupts(&mut self, ir_system: &mut InterruptSystem, mode: Mode) {
+    fn update_mode_with_interrupts<E: DbgEvtSrc<PpuEvt>>(
+        &mut self,
+        ir_system: &mut InterruptSystem,
+        evts: &mut E,
+        mode: Mode,
+    ) {
         self.mode = mode;
+        evts.push(PpuEvt::ModeChange(self.ly, mode));
 
         if !self.reg.lcds.any_conditions_met() {
             match mode {
@@ -457,3 +845,71 @@ impl PPU {
         self.reg.lcds.set_mode(mode);
     }
 }
+
+impl SaveState for PPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.scanline_mcycle);
+        writer.write_u8(self.scanline_sprite_delay);
+        writer.write_u8(self.mode as u8);
+        self.reg.save_state(writer);
+        writer.write_u8(self.ly);
+        writer.write_bool(self.window_triggered);
+        writer.write_u8(self.window_line);
+        self.tile_data.save_state(writer);
+        self.tile_maps.save_state(writer);
+        self.oam.save_state(writer);
+        self.pixel_queue.save_state(writer);
+        self.mem_frame.save_state(writer);
+        writer.write_bool(self.shade_frame.is_some());
+        if let Some(shades) = &self.shade_frame {
+            writer.write_bytes(shades);
+        }
+        writer.write_u8(match self.frame_ready {
+            None => 0,
+            Some(FrameReady::VideoFrame) => 1,
+            Some(FrameReady::LcdOffFrame) => 2,
+        });
+        writer.write_u8(self.skip_frames);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.scanline_mcycle = reader.read_u8()?;
+        self.scanline_sprite_delay = reader.read_u8()?;
+
+        let mode = reader.read_u8()?;
+        if mode > Mode::LCDOff as u8 {
+            return Err(SaveStateError::InvalidValue);
+        }
+        // Safe because we just checked that the value corresponds to an existing variant
+        self.mode = unsafe { Mode::from_unchecked(mode) };
+
+        self.reg.load_state(reader)?;
+        self.ly = reader.read_u8()?;
+        self.window_triggered = reader.read_bool()?;
+        self.window_line = reader.read_u8()?;
+        self.tile_data.load_state(reader)?;
+        self.tile_maps.load_state(reader)?;
+        self.oam.load_state(reader)?;
+        self.pixel_queue.load_state(reader)?;
+        self.mem_frame.load_state(reader)?;
+        // Savestates of other Game Boy models can't be loaded
+        if reader.read_bool()? != self.shade_frame.is_some() {
+            return Err(SaveStateError::InvalidValue);
+        }
+        if let Some(shades) = &mut self.shade_frame {
+            reader.read_bytes(shades)?;
+        }
+        self.frame_ready = match reader.read_u8()? {
+            0 => None,
+            1 => Some(FrameReady::VideoFrame),
+            2 => Some(FrameReady::LcdOffFrame),
+            _ => return Err(SaveStateError::InvalidValue),
+        };
+        self.skip_frames = reader.read_u8()?;
+
+        // Restore the LCDC mirrors that are not part of the state
+        self.tile_maps.notify_lcdc_changed(self.reg.lcdc);
+
+        Ok(())
+    }
+}
@@ -1,99 +1,175 @@
 //! See [`OAM`]
 
-use super::lcdc::{SpriteSize, LCDC};
+use super::lcdc::SpriteSize;
 use super::sprite::Sprite;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use std::ops::{Index, IndexMut};
 
-/// OAM memory (0xFE00 - 0xFEA0) with an internal cache structure to
-/// provide faster access to releavent sprites.
+/// OAM memory (0xFE00 - 0xFEA0) together with the state of OAM search (Mode 2), which
+/// selects the sprites of the current scanline.
+///
+/// Like on hardware, OAM search checks two entries per mcycle, in OAM order, against
+/// the scanline and the sprite size *at that moment*. This means that changes to OAM
+/// (via OAM DMA) or to the sprite size during OAM search only affect the entries that
+/// were not checked yet.
 pub struct OAM {
     /// The raw, unaltered OAM memory
     mem: Box<[u8]>,
-    /// Contains the indexes of a *potentially visible* sprites
-    /// sorted by their x coordinate (ascending). This allows for
-    /// very efficient search for visible sprites on a given scanline.
-    visible_sorted: Vec<u8>,
-    /// True if [`self.visible_sorted`] *might* not represent the current
-    /// contents of [`mem`] correctly. This is set by the IndexMut impl.
-    is_dirty: bool,
-    /// Sprite size for which [`self.visible_sorted`] was built. If the global
-    /// sprite size is changed, this cache needs to be rebuilt.
-    sprite_size: SpriteSize,
+    /// The sprites that OAM search found so far, copied when they were checked. Once
+    /// the search is complete, they are sorted by their x coordinate.
+    line_sprites: Vec<Sprite>,
+    /// The entry that OAM search checks next (0..=40)
+    next_entry: u8,
+    /// For debugging, see [`OAM::dropped_sprites`]
+    dropped_sprites: u8,
 }
 
 const SPRITE_BYTE_WIDTH: usize = 4;
 
+/// The number of entries in OAM
+const ENTRIES: u8 = 40;
+
+/// The maximum amount of sprites that the Game Boy can draw in a scanline
+const MAX_LINE_SPRITES: usize = 10;
+
+/// OAM search reads one row (two entries) of OAM per mcycle
+const ROW_BYTE_WIDTH: usize = 2 * SPRITE_BYTE_WIDTH;
+
+/// The number of rows in OAM
+const ROWS: usize = ENTRIES as usize / 2;
+
+/// The kind of CPU access that triggers the OAM bug (see [`OAM::corrupt`])
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OamBugAccess {
+    /// A write, or a 16-bit increase or decrease without memory access (like `INC rr`)
+    Write,
+    /// A read during which the address register is increased or decreased at the same
+    /// time (like `POP rr`)
+    ReadIncDec,
+}
+
 impl OAM {
     pub fn new() -> OAM {
         OAM {
             mem: vec![0; 0xFEA0 - 0xFE00].into_boxed_slice(),
-            visible_sorted: Vec::with_capacity(40),
-            is_dirty: true,
-            sprite_size: SpriteSize::W8H8,
+            line_sprites: Vec::with_capacity(MAX_LINE_SPRITES),
+            next_entry: ENTRIES,
+            dropped_sprites: 0,
         }
     }
 
-    /// Must be called after the LCDC register was written to
-    pub fn notify_lcdc_changed(&mut self, lcdc: LCDC) {
-        // If sprite size was changed, we have to rebuild our visible sprite cache
-        if self.sprite_size != lcdc.sprite_size() {
-            self.sprite_size = lcdc.sprite_size();
-            self.is_dirty = true;
-        }
+    /// Must be called at the beginning of OAM search (Mode 2), before the first call
+    /// of [`OAM::search_step`]
+    pub fn start_search(&mut self) {
+        self.line_sprites.clear();
+        self.next_entry = 0;
+        self.dropped_sprites = 0;
     }
 
-    /// Returns an iterator of up to 10 sprites in a given scanline, since this is the
-    /// maximum amount of sprites that the Game Boy can draw.
-    pub fn sprites_in_line<'a>(&'a self, ly: u8) -> impl 'a + Iterator<Item = Sprite> {
-        debug_assert!(!self.is_dirty);
-
-        // TODO: Check if this is true... Sprites with higher priority, but color 0b00,
-        // might actually STILL draw over other sprites at the same X. In that case, the `dedup_by` call
-        // below is incorrect, and we need more complicated handling of the situation.
-        // For now, we just let all sprites live.
-
-        self.visible_sorted
-            .iter()
-            .copied()
-            .map(move |id| (id, self.mem[id as usize * SPRITE_BYTE_WIDTH] as i16 - 16))
-            .filter(move |(_, sprite_y)| {
-                (ly as i16) >= *sprite_y
-                    && (ly as i16) < *sprite_y + self.sprite_size.height() as i16
-            })
-            .take(10)
-            .map(move |(id, _sprite_y)| {
-                Sprite::from_slice(
-                    &self.mem[id as usize * SPRITE_BYTE_WIDTH..id as usize * SPRITE_BYTE_WIDTH + 4],
-                )
-            })
-        // .dedup_by(|s1, s2| s1.x == s2.x)
+    /// Performs one mcycle of OAM search, which checks the next two entries. Called 20
+    /// times per scanline, after which all 40 entries have been checked.
+    pub fn search_step(&mut self, ly: u8, sprite_size: SpriteSize) {
+        for _ in 0..2 {
+            if self.next_entry == ENTRIES {
+                return;
+            }
+
+            let start = self.next_entry as usize * SPRITE_BYTE_WIDTH;
+            let sprite = Sprite::from_slice(&self.mem[start..start + SPRITE_BYTE_WIDTH]);
+
+            let sprite_y = sprite.y as i16 - 16;
+            let in_line =
+                ly as i16 >= sprite_y && (ly as i16) < sprite_y + sprite_size.height() as i16;
+
+            // Sprites count towards the limit even if they are not visible horizontally
+            if in_line {
+                if self.line_sprites.len() < MAX_LINE_SPRITES {
+                    self.line_sprites.push(sprite);
+                } else {
+                    self.dropped_sprites += 1;
+                }
+            }
+
+            self.next_entry += 1;
+        }
+
+        if self.next_entry == ENTRIES {
+            // Sprites with a lower x coordinate are drawn on top. For equal x
+            // coordinates, the one that comes first in OAM wins. The sort is stable,
+            // so that order is kept.
+            self.line_sprites.sort_by_key(|sprite| sprite.x);
+        }
     }
 
-    /// Rebuilds the internal cache; It is necessary to call this each scanline, after OAM
-    /// becomes inaccesible for the CPU but before [`self.sprites_in_line`] is called.
-    pub fn rebuild(&mut self) {
-        if !self.is_dirty {
+    /// Emulates the OAM bug of the original Game Boy: If the CPU puts an address in
+    /// 0xFE00 - 0xFEFF on the bus during OAM search, the row that OAM search reads in
+    /// the same mcycle is garbled with the row before it. Must only be called during OAM
+    /// search, after the [`OAM::search_step`] of the current mcycle.
+    ///
+    /// The patterns are the ones that were observed on hardware (see Pan Docs, "OAM
+    /// Corruption Bug"). The first row is never corrupted.
+    pub fn corrupt(&mut self, access: OamBugAccess) {
+        // The row that the last search step read
+        let row = (self.next_entry as usize / 2).saturating_sub(1);
+
+        if row == 0 {
             return;
         }
 
-        self.visible_sorted.clear();
+        // Reads that increase or decrease at the same time garble the two rows before
+        // the current one as well, and then behave like regular reads
+        if access == OamBugAccess::ReadIncDec && (4..ROWS - 1).contains(&row) {
+            let a = self.word(row - 2, 0);
+            let b = self.word(row - 1, 0);
+            let c = self.word(row, 0);
+            let d = self.word(row - 1, 2);
+
+            self.set_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+            self.copy_row(row - 1, row);
+            self.copy_row(row - 1, row - 2);
+        }
 
-        for sprite_id in 0..40 {
-            let sprite_y = self.mem[sprite_id as usize * SPRITE_BYTE_WIDTH];
-            let sprite_x = self.mem[sprite_id as usize * SPRITE_BYTE_WIDTH + 1];
+        let a = self.word(row, 0);
+        let b = self.word(row - 1, 0);
+        let c = self.word(row - 1, 2);
 
-            // TODO: Investigate if sprites with x == 0 count towards the sprite limit.
-            // if no, we can already fltier them out here
-            if sprite_y > self.sprite_size.height() && sprite_y < 160 && sprite_x < 168 {
-                self.visible_sorted.push(sprite_id);
-            }
-        }
+        let first_word = match access {
+            OamBugAccess::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamBugAccess::ReadIncDec => b | (a & c),
+        };
+
+        self.copy_row(row - 1, row);
+        self.set_word(row, 0, first_word);
+    }
+
+    /// The `idx`th (0..4) little-endian word of an OAM row
+    fn word(&self, row: usize, idx: usize) -> u16 {
+        let start = row * ROW_BYTE_WIDTH + 2 * idx;
+        u16::from_le_bytes([self.mem[start], self.mem[start + 1]])
+    }
+
+    fn set_word(&mut self, row: usize, idx: usize, val: u16) {
+        let start = row * ROW_BYTE_WIDTH + 2 * idx;
+        self.mem[start..start + 2].copy_from_slice(&val.to_le_bytes());
+    }
 
-        // We take this ref to get around a borrowing conflict on self
-        let mem = &self.mem;
-        self.visible_sorted
-            .sort_unstable_by_key(|id| mem[*id as usize * SPRITE_BYTE_WIDTH + 1]);
-        self.is_dirty = false;
+    fn copy_row(&mut self, from: usize, to: usize) {
+        self.mem.copy_within(
+            from * ROW_BYTE_WIDTH..(from + 1) * ROW_BYTE_WIDTH,
+            to * ROW_BYTE_WIDTH,
+        );
+    }
+
+    /// How many sprites in the current scanline were left out because of the limit of 10
+    /// sprites per line (so far, if OAM search is still running)
+    pub fn dropped_sprites(&self) -> u8 {
+        self.dropped_sprites
+    }
+
+    /// The sprites that OAM search selected for the current scanline (up to 10), in
+    /// the order of their priority (highest first)
+    pub fn sprites_in_line(&self) -> impl '_ + Iterator<Item = Sprite> {
+        self.line_sprites.iter().copied()
     }
 }
 
@@ -107,11 +183,44 @@ impl Index<u16> for OAM {
 
 impl IndexMut<u16> for OAM {
     fn index_mut(&mut self, index: u16) -> &mut Self::Output {
-        // TODO: See if it is worth not implementing this and making an explicit write method;
-        // That way, we could check if the new data is actually different from the old one,
-        // and only set the dirty flag in case it is.
-        self.is_dirty = true;
-
         &mut self.mem[index as usize]
     }
 }
+
+impl SaveState for OAM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.mem);
+        writer.write_u8(self.next_entry);
+        writer.write_u8(self.dropped_sprites);
+        writer.write_u8(self.line_sprites.len() as u8);
+
+        for sprite in &self.line_sprites {
+            writer.write_bytes(&sprite.to_bytes());
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes(&mut self.mem)?;
+
+        self.next_entry = reader.read_u8()?;
+        self.dropped_sprites = reader.read_u8()?;
+        let sprite_count = reader.read_u8()? as usize;
+
+        if self.next_entry > ENTRIES
+            || sprite_count > MAX_LINE_SPRITES
+            || self.dropped_sprites > ENTRIES
+        {
+            return Err(SaveStateError::InvalidValue);
+        }
+
+        self.line_sprites.clear();
+
+        for _ in 0..sprite_count {
+            let mut bytes = [0; SPRITE_BYTE_WIDTH];
+            reader.read_bytes(&mut bytes)?;
+            self.line_sprites.push(Sprite::from_slice(&bytes));
+        }
+
+        Ok(())
+    }
+}
@@ -10,23 +10,55 @@
 //!
 //! [`PixelQuad`] stores the origin of each pixel along with the color since some pixels
 //! might later be overwritten.
+//!
+//! The quads are not drawn cycle by cycle, but all at once when the pixel transfer ends.
+//! To still get mid-scanline effects right, the CPU's register writes during the pixel
+//! transfer are logged along with the quad that was being shifted out at the time (see
+//! [`PixelQueue::log_reg_write`]), and replayed while drawing the line.
+//!
+//! Like on hardware, the palettes are applied when a pixel is shifted out, so palette
+//! writes affect all remaining pixels of the line (including window and sprite pixels).
+//! The scroll registers are different: The lower 3 bits of SCX are only read at the
+//! start of the line, while the rest of SCX and SCY are read whenever the next
+//! background tile is fetched. So a mid-scanline scroll change never splits a tile.
 
 // TODO: Clean up functions with tons of parameters
 // TODO: Don't render sprites during OAM DMA
 // TODO: Rewrite this whole thing to be prettier
 
-use super::color::{Color, ColorVal};
+use super::color::Color;
+use super::dmg_palette::DmgPalette;
 use super::mem_frame::MemPixel;
 use super::oam::OAM;
 use super::ppu_registers::PPURegisters;
 use super::sprite::Sprite;
 use super::tile_data::{SpriteTileRow, TileData, TileRow};
 use super::tile_maps::{TileMaps, TileRowAddr};
-use super::Palette;
+use crate::address::{IOReg, PpuReg};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use std::convert::TryFrom;
 
 /// See the [`module documentation`]
 pub struct PixelQueue {
     quads: [PixelQuad; 40],
+    /// The registers at the start of the pixel transfer
+    line_start_reg: PPURegisters,
+    /// Register writes during the pixel transfer, in the order they happened
+    reg_writes: Vec<RegWrite>,
+    /// Only used while drawing a line, so it's not part of savestates
+    bg_fetcher: BgFetcher,
+    /// The shades of the pixels that were drawn in the current line. Also only used
+    /// while drawing.
+    line_shades: [u8; 160],
+}
+
+/// A write of the CPU to a PPU register during the pixel transfer
+#[derive(Copy, Clone)]
+struct RegWrite {
+    /// The first quad that sees the new value
+    quad_id: u8,
+    reg: PpuReg,
+    val: u8,
 }
 
 /// The source of a pixel can be precomputed at the beginning of a scanline,
@@ -35,7 +67,7 @@ pub struct PixelQueue {
 /// deferred, since it can change mid-scanline.
 #[derive(Copy, Clone)]
 struct PixelQuad {
-    /// Contains the actual *paletted* pixel colors with the leftmost pixel color
+    /// Contains the *unpaletted* pixel colors with the leftmost pixel color
     /// being at the least significant 2 bits. If color is unknown (for BG sprites),
     /// pixel color is undefined. Sprite colors are also contained, even if the
     /// color might later get overwritten.
@@ -43,37 +75,111 @@ struct PixelQuad {
 
     /// In the same order as `pixel_types`, each two bits describe a pixel source:
     /// 0b00 - Background (needs to be calculated later)
-    /// 0b01 - Window or blended Sprite [Priority 1] - This pixel is final and paletted
-    /// 0b10 - Sprite with priority 1 over BG - Paletted, even though it might be overwritten
-    /// 0b11 - Sprite [Priority 0] (over BG or Window) - This pixel is final and paletted
+    /// 0b01 - Window or blended Sprite [Priority 1] - This pixel is final
+    /// 0b10 - Sprite with priority 1 over BG - Might still be overwritten by the BG
+    /// 0b11 - Sprite [Priority 0] (over BG or Window) - This pixel is final
     /// Note that in this representation, the lower bit indicates if a pixel is final.
     pixel_src: u8,
+
+    /// In the same order as `pixel_col`, each two bits select the palette that is
+    /// applied to the color when the pixel is shifted out (one of the `PAL_*` constants)
+    pixel_pal: u8,
 }
 
+/// The color is the shade, without any palette (used for the disabled background)
+const PAL_NONE: u8 = 0b00;
+const PAL_BGP: u8 = 0b01;
+const PAL_OBP0: u8 = 0b10;
+const PAL_OBP1: u8 = 0b11;
+
 impl PixelQuad {
     fn zero() -> PixelQuad {
         PixelQuad {
             pixel_col: 0,
             pixel_src: 0,
+            pixel_pal: 0,
         }
     }
 }
 
+/// The part of the scroll registers that the background tile fetcher uses (see the
+/// [`module documentation`])
+#[derive(Default)]
+struct BgFetcher {
+    /// The lower 3 bits of SCX at the start of the line
+    fine_x: u8,
+    /// SCX (without the lower 3 bits) when the current tile was fetched
+    coarse_x: u8,
+    /// SCY when the current tile was fetched
+    scy: u8,
+}
+
+impl SaveState for PixelQueue {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for quad in self.quads.iter() {
+            writer.write_u8(quad.pixel_col);
+            writer.write_u8(quad.pixel_src);
+            writer.write_u8(quad.pixel_pal);
+        }
+
+        self.line_start_reg.save_state(writer);
+        writer.write_u8(self.reg_writes.len() as u8);
+
+        for write in self.reg_writes.iter() {
+            writer.write_u8(write.quad_id);
+            writer.write_u16(IOReg::Ppu(write.reg).addr());
+            writer.write_u8(write.val);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        for quad in self.quads.iter_mut() {
+            quad.pixel_col = reader.read_u8()?;
+            quad.pixel_src = reader.read_u8()?;
+            quad.pixel_pal = reader.read_u8()?;
+        }
+
+        self.line_start_reg.load_state(reader)?;
+        self.reg_writes.clear();
+
+        for _ in 0..reader.read_u8()? {
+            let quad_id = reader.read_u8()?;
+
+            let reg = match IOReg::try_from(reader.read_u16()?) {
+                Ok(IOReg::Ppu(reg)) => reg,
+                _ => return Err(SaveStateError::InvalidValue),
+            };
+
+            let val = reader.read_u8()?;
+            self.reg_writes.push(RegWrite { quad_id, reg, val });
+        }
+
+        Ok(())
+    }
+}
+
 impl PixelQueue {
     pub fn new() -> PixelQueue {
         PixelQueue {
             quads: [PixelQuad::zero(); 40],
+            line_start_reg: PPURegisters::new(),
+            reg_writes: Vec::new(),
+            bg_fetcher: BgFetcher::default(),
+            line_shades: [0; 160],
         }
     }
 
     /// To be called at the beginning of the pixel transfer mode (Mode 3). Pre-calculates
     /// the source and color of as many pixels as possible to avoid duplicate work later.
+    /// `window_line` is the line of the window that is drawn, or `None` if the window is
+    /// not visible in this line.
     pub fn push_scanline(
         &mut self,
         ppu_reg: &PPURegisters,
         tile_maps: &TileMaps,
         tile_data: &TileData,
         oam: &OAM,
+        window_line: Option<u8>,
     ) -> u8 {
         // TODO: See if BG, Window and Sprites can be enabled mid scanline
         // If yes, we cannot really mark any pixel as final and might just
@@ -81,32 +187,21 @@ impl PixelQueue {
 
         // Forget about the last line
         self.quads = [PixelQuad::zero(); 40];
+        self.line_start_reg = ppu_reg.clone();
+        self.reg_writes.clear();
 
         let mut num_sprites = 0;
 
         if ppu_reg.lcdc.sprites_enabled() {
-            for sprite in oam.sprites_in_line(ppu_reg.ly) {
-                self.draw_sprite(tile_data, ppu_reg, sprite, (ppu_reg.ly + 16) - sprite.y);
+            for sprite in oam.sprites_in_line() {
+                let sprite_line = (ppu_reg.ly + 16).wrapping_sub(sprite.y);
+                self.draw_sprite(tile_data, ppu_reg, sprite, sprite_line);
                 num_sprites += 1;
             }
         }
 
-        // `ppu.wx_reg >= 7` is not a requirement on hardware, so this boolean is technically incorrect.
-        // Anyway, stuff gets much easier to write if we do it this way for now. The PPU
-        // currently outputs a warning if any value < 7 is written to WX. TODO: Implement correctly
-        let window_in_line = ppu_reg.lcdc.window_enabled()
-            && ppu_reg.ly >= ppu_reg.wy
-            && ppu_reg.wx <= 166
-            && ppu_reg.wx >= 7;
-
-        if window_in_line {
-            self.draw_window(
-                tile_data,
-                tile_maps,
-                ppu_reg.bgp,
-                ppu_reg.wx,
-                ppu_reg.ly - ppu_reg.wy,
-            );
+        if let Some(window_line) = window_line {
+            self.draw_window(tile_data, tile_maps, ppu_reg.wx, window_line);
         }
 
         // Optimization: If BG is disabled, we can also mark those pixels as final
@@ -118,67 +213,120 @@ impl PixelQueue {
         num_sprites
     }
 
+    /// To be called when the CPU writes to a PPU register during the pixel transfer,
+    /// while quad `quad_id` is being shifted out. The write only affects that quad and
+    /// those after it. Writes after the last quad don't need to be logged.
+    pub fn log_reg_write(&mut self, quad_id: u8, reg: PpuReg, val: u8) {
+        self.reg_writes.push(RegWrite { quad_id, reg, val });
+    }
+
+    /// To be called at the end of the pixel transfer (or when it is cut short by turning
+    /// off the LCD). Draws the quads before `end_quad` into `line`, with the register
+    /// values that were current when each of them was shifted out. The shades of the
+    /// pixels (before `dmg_palette` is applied) are also written to `shades`, if given.
+    ///
+    /// `tile_maps` is left with the LCDC settings of the last drawn quad, so the caller
+    /// needs to call [`TileMaps::notify_lcdc_changed`] afterwards.
+    pub fn draw_line(
+        &mut self,
+        tile_data: &TileData,
+        tile_maps: &mut TileMaps,
+        dmg_palette: &DmgPalette,
+        line: &mut [MemPixel],
+        shades: Option<&mut [u8]>,
+        end_quad: u8,
+    ) {
+        let mut reg = self.line_start_reg.clone();
+        tile_maps.notify_lcdc_changed(reg.lcdc);
+
+        self.bg_fetcher = BgFetcher {
+            fine_x: reg.scx & 0b111,
+            coarse_x: reg.scx & !0b111,
+            scy: reg.scy,
+        };
+
+        // Taken out for the duration of the loop, since drawing needs `self` mutably
+        let reg_writes = std::mem::take(&mut self.reg_writes);
+        let mut writes = reg_writes.iter().peekable();
+
+        for quad_id in 0..end_quad.min(40) {
+            while let Some(write) = writes.next_if(|write| write.quad_id <= quad_id) {
+                reg.cpu_write(write.reg, write.val);
+
+                if let PpuReg::LCDC = write.reg {
+                    tile_maps.notify_lcdc_changed(reg.lcdc);
+                }
+            }
+
+            self.pop_pixel_quad(tile_data, tile_maps, &reg, dmg_palette, line, quad_id);
+        }
+
+        if let Some(shades) = shades {
+            let end = end_quad.min(40) as usize * 4;
+            shades[..end].copy_from_slice(&self.line_shades[..end]);
+        }
+
+        // Keeps the allocation around for the next line
+        self.reg_writes = reg_writes;
+        self.reg_writes.clear();
+    }
+
     /// Draws a group of four pixels into the frame buffer (at position quad_id * 4).
-    pub fn pop_pixel_quad(
-        &self,
+    fn pop_pixel_quad(
+        &mut self,
         tile_data: &TileData,
         tile_maps: &TileMaps,
         ppu_reg: &PPURegisters,
+        dmg_palette: &DmgPalette,
         line: &mut [MemPixel],
         quad_id: u8,
     ) {
         let mut quad = self.quads[quad_id as usize];
-
-        // PERF: Do once and pass in
-        let bg_y = ppu_reg.ly.wrapping_add(ppu_reg.scy);
+        let fetcher = &mut self.bg_fetcher;
 
         for pidx in (quad_id * 4)..(quad_id * 4 + 4) {
-            let pix = &mut line[pidx as usize];
+            // The pixel's position in the background row, counted from the first pixel of
+            // the first (partially visible) tile
+            let fetch_x = pidx + fetcher.fine_x;
 
-            *pix = match quad.pixel_src & 0b11 {
+            if fetch_x & 0b111 == 0 || pidx == 0 {
+                fetcher.coarse_x = ppu_reg.scx & !0b111;
+                fetcher.scy = ppu_reg.scy;
+            }
+
+            let bg_x = fetcher.coarse_x.wrapping_add(fetch_x);
+            let bg_y = ppu_reg.ly.wrapping_add(fetcher.scy);
+
+            let col = Color::from_u8_lsb(quad.pixel_col);
+            let pal = quad.pixel_pal & 0b11;
+
+            let shade = match quad.pixel_src & 0b11 {
                 0b00 => {
-                    let col = self.fetch_bg_pix(
-                        tile_data,
-                        tile_maps,
-                        pidx.wrapping_add(ppu_reg.scx),
-                        bg_y,
-                    );
-                    MemPixel::from(ppu_reg.bgp.apply(col))
+                    let bg_col = fetch_bg_pix(tile_data, tile_maps, bg_x, bg_y);
+                    ppu_reg.bgp.apply(bg_col)
                 }
                 0b10 => {
-                    let bg_col = self.fetch_bg_pix(
-                        tile_data,
-                        tile_maps,
-                        pidx.wrapping_add(ppu_reg.scx),
-                        bg_y,
-                    );
-
-                    let sprite_col = Color::from_u8_lsb(quad.pixel_col);
-
-                    MemPixel::from(blend_sprite_col(sprite_col, bg_col, ppu_reg.bgp))
+                    // The sprite is only visible where the background has color 0
+                    let bg_col = fetch_bg_pix(tile_data, tile_maps, bg_x, bg_y);
+
+                    if bg_col.is_zero() {
+                        apply_palette(ppu_reg, pal, col)
+                    } else {
+                        ppu_reg.bgp.apply(bg_col)
+                    }
                 }
-                _ => MemPixel::from(Color::from_u8_lsb(quad.pixel_col)),
+                _ => apply_palette(ppu_reg, pal, col),
             };
 
+            line[pidx as usize] = dmg_palette[shade.into_raw() as usize];
+            self.line_shades[pidx as usize] = shade.into_raw();
+
             quad.pixel_col >>= 2;
             quad.pixel_src >>= 2;
+            quad.pixel_pal >>= 2;
         }
     }
 
-    fn fetch_bg_pix(
-        &self,
-        tile_data: &TileData,
-        tile_maps: &TileMaps,
-        bg_x: u8,
-        bg_y: u8,
-    ) -> Color {
-        let row_addr = tile_maps.bg_tile_row_at(bg_x, bg_y);
-        let mut row = tile_data.get_row(row_addr);
-
-        row.discard_leftmost(bg_x % 8);
-        row.pop_leftmost()
-    }
-
     fn draw_sprite(
         &mut self,
         tile_data: &TileData,
@@ -186,12 +334,16 @@ impl PixelQueue {
         sprite: Sprite,
         sprite_line: u8,
     ) {
+        // The sprite was selected with the sprite size during OAM search, but the tile
+        // is fetched with the current one. If the sprite got shorter in between, the
+        // hardware simply ignores the upper bit of the line.
         let sprite_size = ppu_reg.lcdc.sprite_size();
+        let sprite_line = sprite_line & (sprite_size.height() - 1);
 
         let row_addr = if sprite.flags.y_flipped() {
             TileRowAddr::from_sprite_tile_id(
                 sprite.id,
-                sprite_size.height() - sprite_line,
+                sprite_size.height() - 1 - sprite_line,
                 sprite_size,
             )
         } else {
@@ -211,46 +363,34 @@ impl PixelQueue {
         };
 
         match row {
-            SpriteTileRow::InOrder(row) => self.draw_sprite_row(ppu_reg, row, sprite, pixel_src),
-            SpriteTileRow::Reverse(row) => self.draw_sprite_row(ppu_reg, row, sprite, pixel_src),
+            SpriteTileRow::InOrder(row) => self.draw_sprite_row(row, sprite, pixel_src),
+            SpriteTileRow::Reverse(row) => self.draw_sprite_row(row, sprite, pixel_src),
         }
     }
 
-    fn draw_window(
-        &mut self,
-        tile_data: &TileData,
-        tile_maps: &TileMaps,
-        bgp: Palette,
-        wx: u8,
-        window_line: u8,
-    ) {
-        // TODO: Investigate WX < 7 and handle it correctly
-
-        let window_pix_width = 167 - wx;
-        let visible_tiles = (window_pix_width + 7) / 8;
-        let mut tile_rows = (0..visible_tiles).map(|tile_idx| {
-            let row_addr = tile_maps.wnd_tile_row_at(tile_idx * 8, window_line);
-            tile_data.get_row(row_addr)
-        });
-
-        let mut pidx = wx.saturating_sub(7);
-
-        // Draw all tiles that are completely on-screen
-        for mut row in tile_rows.by_ref().take(visible_tiles as usize - 1) {
-            for _ in 0..8 {
-                let col = row.pop_leftmost();
-                self.draw_window_pix(bgp, pidx, col);
-                pidx += 1;
-            }
-        }
+    fn draw_window(&mut self, tile_data: &TileData, tile_maps: &TileMaps, wx: u8, window_line: u8) {
+        // The window starts at screen x = WX - 7. For WX < 7, its first pixels are left of
+        // the screen and are dropped.
+        let first_pidx = wx.saturating_sub(7);
+        let first_wnd_x = 7u8.saturating_sub(wx);
+
+        let fetch_row = |wnd_x: u8| {
+            let mut row = tile_data.get_row(tile_maps.wnd_tile_row_at(wnd_x, window_line));
+            row.discard_leftmost(wnd_x % 8);
+            row
+        };
 
-        // Draw the last (possibly incomplete) tile
-        let mut last_row = tile_rows.next().unwrap();
+        let mut row = fetch_row(first_wnd_x);
+
+        for pidx in first_pidx..160 {
+            let wnd_x = first_wnd_x + (pidx - first_pidx);
+
+            if wnd_x & 0b111 == 0 && pidx != first_pidx {
+                row = fetch_row(wnd_x);
+            }
 
-        while pidx < 160 {
-            let col = last_row.pop_leftmost();
-            self.draw_window_pix(bgp, pidx, col);
-            pidx += 1;
+            let col = row.pop_leftmost();
+            self.draw_window_pix(pidx, col);
         }
     }
 
@@ -262,31 +402,17 @@ impl PixelQueue {
         }
     }
 
-    fn draw_sprite_row<R: TileRow>(
-        &mut self,
-        ppu_reg: &PPURegisters,
-        mut row: R,
-        sprite: Sprite,
-        pixel_src: u8,
-    ) {
+    fn draw_sprite_row<R: TileRow>(&mut self, mut row: R, sprite: Sprite, pixel_src: u8) {
         // If the sprite goes over the left edge of the screen, we disacrd some pixels
         row.discard_leftmost(7u8.saturating_sub(sprite.x));
 
         for pidx in sprite.x.max(8) - 8..sprite.x.min(159) {
             let col = row.pop_leftmost();
-            self.draw_sprite_pix(sprite, ppu_reg.obp0, ppu_reg.obp1, pidx, col, pixel_src);
+            self.draw_sprite_pix(sprite, pidx, col, pixel_src);
         }
     }
 
-    fn draw_sprite_pix(
-        &mut self,
-        sprite: Sprite,
-        obp0: Palette,
-        obp1: Palette,
-        pidx: u8,
-        col: Color,
-        src: u8,
-    ) {
+    fn draw_sprite_pix(&mut self, sprite: Sprite, pidx: u8, col: Color, src: u8) {
         // TODO: Check if this handles the situation of overwriting
         // a higher priority sprite with color value 00 correctly.
 
@@ -303,18 +429,19 @@ impl PixelQueue {
                 return; // A higher priority sprite was already drawn here
             }
 
-            let col = if sprite.flags.uses_alternative_pallette() {
-                obp1.apply(col)
+            let pal = if sprite.flags.uses_alternative_pallette() {
+                PAL_OBP1
             } else {
-                obp0.apply(col)
+                PAL_OBP0
             };
 
             quad.pixel_col |= col.into_raw() << (quad_subidx * 2);
             quad.pixel_src |= src << (quad_subidx * 2);
+            quad.pixel_pal |= pal << (quad_subidx * 2);
         }
     }
 
-    fn draw_window_pix(&mut self, bgp: Palette, pidx: u8, col: Color) {
+    fn draw_window_pix(&mut self, pidx: u8, col: Color) {
         let quad_idx = pidx / 4;
         let quad_subidx = pidx % 4;
         let quad = &mut self.quads[quad_idx as usize];
@@ -327,31 +454,35 @@ impl PixelQueue {
             return; // The pixel is already final, we are done here
         }
 
-        let col = if old_src & 0b10 == 0b10 {
-            // This pixel is a partially occluded sprite... Jesus Christ!
+        // This pixel might be a partially occluded sprite... Jesus Christ! It stays
+        // visible where the window has color 0, otherwise the window is drawn over it.
+        if old_src & 0b10 == 0 || !col.is_zero() {
+            let mask = (!0b11u8).rotate_left(quad_shift as u32);
 
-            // Clear pixel color and pixel source
-            quad.pixel_col &= (!0b11u8).rotate_left(quad_shift as u32);
-            quad.pixel_src &= (!0b11u8).rotate_left(quad_shift as u32);
-
-            // Blend sprite and BG color
-            let sprite_col = Color::from_u8_lsb(quad.pixel_col >> quad_shift);
-            blend_sprite_col(sprite_col, col, bgp)
-        } else {
-            // The pixel has col and src 0b00, so we draw over it
-            bgp.apply(col)
-        };
+            quad.pixel_col = (quad.pixel_col & mask) | col.into_raw() << quad_shift;
+            quad.pixel_pal = (quad.pixel_pal & mask) | PAL_BGP << quad_shift;
+        }
 
-        quad.pixel_col |= col.into_raw() << quad_shift;
-        quad.pixel_src |= 0b01 << quad_shift;
+        // Either way, the pixel is final now
+        quad.pixel_src =
+            (quad.pixel_src & (!0b11u8).rotate_left(quad_shift as u32)) | 0b01 << quad_shift;
     }
 }
 
-/// For sprites with OBJ-to-BG priority 1, this function calculates
-/// the resulting color of a blend with a BG/WND color
-fn blend_sprite_col(sprite_col: Color, bg_col: Color, bg_palette: Palette) -> Color {
-    match bg_col.into_val() {
-        ColorVal::C00 => sprite_col,
-        _ => bg_palette.apply(bg_col),
+/// Applies the palette that `pal` (one of the `PAL_*` constants) selects to `col`
+fn apply_palette(ppu_reg: &PPURegisters, pal: u8, col: Color) -> Color {
+    match pal {
+        PAL_NONE => col,
+        PAL_BGP => ppu_reg.bgp.apply(col),
+        PAL_OBP0 => ppu_reg.obp0.apply(col),
+        _ => ppu_reg.obp1.apply(col),
     }
 }
+
+fn fetch_bg_pix(tile_data: &TileData, tile_maps: &TileMaps, bg_x: u8, bg_y: u8) -> Color {
+    let row_addr = tile_maps.bg_tile_row_at(bg_x, bg_y);
+    let mut row = tile_data.get_row(row_addr);
+
+    row.discard_leftmost(bg_x % 8);
+    row.pop_leftmost()
+}
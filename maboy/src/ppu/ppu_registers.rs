@@ -2,6 +2,7 @@ use super::lcdc::LCDC;
 use super::lcds::LCDS;
 use super::palette::Palette;
 use crate::address::PpuReg;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 
 /// A wrapper struct to group all PPU IO registers
 #[derive(Clone)]
@@ -70,3 +71,34 @@ impl PPURegisters {
         }
     }
 }
+
+impl SaveState for PPURegisters {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.ly);
+        writer.write_u8(self.lyc);
+        writer.write_u8(self.scx);
+        writer.write_u8(self.scy);
+        writer.write_u8(self.wy);
+        writer.write_u8(self.wx);
+        writer.write_u8(self.bgp.0);
+        writer.write_u8(self.obp0.0);
+        writer.write_u8(self.obp1.0);
+        writer.write_u8(self.lcdc.0);
+        writer.write_u8(self.lcds.read());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ly = reader.read_u8()?;
+        self.lyc = reader.read_u8()?;
+        self.scx = reader.read_u8()?;
+        self.scy = reader.read_u8()?;
+        self.wy = reader.read_u8()?;
+        self.wx = reader.read_u8()?;
+        self.bgp.0 = reader.read_u8()?;
+        self.obp0.0 = reader.read_u8()?;
+        self.obp1.0 = reader.read_u8()?;
+        self.lcdc.0 = reader.read_u8()?;
+        self.lcds = LCDS::from_raw(reader.read_u8()?);
+        Ok(())
+    }
+}
@@ -23,6 +23,11 @@ impl Sprite {
             flags: SpriteFlags(mem[3]),
         }
     }
+
+    /// The bytes of the sprite, in the same layout as in OAM RAM
+    pub fn to_bytes(self) -> [u8; 4] {
+        [self.y, self.x, self.id, self.flags.0]
+    }
 }
 
 impl SpriteFlags {
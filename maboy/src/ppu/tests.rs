@@ -0,0 +1,338 @@
+//! Tests of the sprite selection during OAM search, especially when OAM or the sprite
+//! size (LCDC bit 2) change in the middle of a frame, which a few games do between
+//! scanlines for effects. They drive the PPU directly with synthetic OAM and tile data.
+//!
+//! All sprites start at line 8. Their first tile is filled with color 3 and their
+//! second one (the bottom half of an 8x16 sprite) with color 1, so the color of a pixel
+//! tells which tile was fetched. The background is turned off, so it is always color 0.
+//!
+//! The tests at the end check which LCDS writes request an LCD Stat interrupt because
+//! of the STAT write bug.
+
+use super::{Color, MemPixel, Mode, VideoFrameStatus, PPU};
+use crate::address::{PpuReg, VideoMemAddr};
+use crate::debug::NoDbgLogger;
+use crate::interrupt_system::{Interrupt, InterruptSystem};
+
+/// LCD and sprites on, background off
+const LCDC_BASE: u8 = 0x82;
+const LCDC_TALL_SPRITES: u8 = 0x04;
+
+/// Sprite 0 at x = 8 and sprite 39 at x = 32, which OAM search checks in the first and
+/// in the last mcycle
+const FIRST_AND_LAST: &[(u8, usize)] = &[(0, 10), (39, 34)];
+
+/// Sprites 0 to 9 next to each other, starting at x = 16, and sprite 10 at x = 0
+const ELEVEN_IN_LINE: &[(u8, usize)] = &[
+    (0, 18),
+    (1, 26),
+    (2, 34),
+    (3, 42),
+    (4, 50),
+    (5, 58),
+    (6, 66),
+    (7, 74),
+    (8, 82),
+    (9, 90),
+    (10, 2),
+];
+
+#[derive(Copy, Clone)]
+enum Event {
+    /// Switches between 8x8 and 8x16 sprites
+    SpriteSize(bool),
+    /// Writes the y coordinate of a sprite in OAM (like OAM DMA would)
+    MoveSprite(u8, u8),
+}
+
+struct Scenario {
+    name: &'static str,
+    /// `(sprite, x)`: The OAM index of a sprite and the screen x coordinate of one of
+    /// its pixels
+    sprites: &'static [(u8, usize)],
+    /// Whether the frame starts with 8x16 sprites
+    start_tall: bool,
+    /// `(ly, mcycle, event)`: Happens right before the PPU handles that mcycle
+    events: &'static [(u8, u8, Event)],
+    /// `(ly, colors)`: The expected color of every sprite pixel in that line, in the
+    /// order of `sprites`
+    expected: &'static [(u8, &'static [u8])],
+}
+
+const SPRITE_SIZE_SCENARIOS: [Scenario; 4] = [
+    Scenario {
+        name: "8x16 sprites",
+        sprites: FIRST_AND_LAST,
+        start_tall: true,
+        events: &[],
+        expected: &[
+            (7, &[0, 0]),
+            (8, &[3, 3]),
+            (15, &[3, 3]),
+            (16, &[1, 1]),
+            (23, &[1, 1]),
+            (24, &[0, 0]),
+        ],
+    },
+    Scenario {
+        name: "8x16 to 8x8 between scanlines",
+        sprites: FIRST_AND_LAST,
+        start_tall: true,
+        events: &[(12, 0, Event::SpriteSize(false))],
+        expected: &[(11, &[3, 3]), (12, &[3, 3]), (15, &[3, 3]), (16, &[0, 0])],
+    },
+    Scenario {
+        // Sprite 0 is checked as an 8x8 sprite and doesn't reach line 20 anymore
+        name: "8x8 to 8x16 during OAM search",
+        sprites: FIRST_AND_LAST,
+        start_tall: false,
+        events: &[
+            (20, 11, Event::SpriteSize(true)),
+            (21, 0, Event::SpriteSize(false)),
+        ],
+        expected: &[(15, &[3, 3]), (16, &[0, 0]), (20, &[0, 1]), (21, &[0, 0])],
+    },
+    Scenario {
+        // Sprite 0 is selected as an 8x16 sprite, but its tile is fetched as an 8x8
+        // one, which ignores the upper bit of the line within the sprite
+        name: "8x16 to 8x8 at the end of OAM search",
+        sprites: FIRST_AND_LAST,
+        start_tall: true,
+        events: &[
+            (18, 20, Event::SpriteSize(false)),
+            (19, 0, Event::SpriteSize(true)),
+        ],
+        expected: &[(17, &[1, 1]), (18, &[3, 0]), (19, &[1, 1])],
+    },
+];
+
+const OAM_SEARCH_SCENARIOS: [Scenario; 2] = [
+    Scenario {
+        // Only the first 10 sprites in OAM order are selected, regardless of their x
+        // coordinates
+        name: "11 sprites in a line",
+        sprites: ELEVEN_IN_LINE,
+        start_tall: false,
+        events: &[],
+        expected: &[(8, &[3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 0])],
+    },
+    Scenario {
+        // Sprite 0 was already selected when both sprites are moved away, sprite 39
+        // wasn't checked yet
+        name: "Moving sprites during OAM search",
+        sprites: FIRST_AND_LAST,
+        start_tall: false,
+        events: &[
+            (12, 5, Event::MoveSprite(0, 0)),
+            (12, 5, Event::MoveSprite(39, 0)),
+        ],
+        expected: &[(11, &[3, 3]), (12, &[3, 0]), (13, &[0, 0])],
+    },
+];
+
+#[test]
+fn sprite_size_changes() {
+    assert_scenarios(&SPRITE_SIZE_SCENARIOS);
+}
+
+#[test]
+fn oam_search() {
+    assert_scenarios(&OAM_SEARCH_SCENARIOS);
+}
+
+/// Runs the scenarios and fails with a description of every pixel that doesn't have
+/// the expected color
+fn assert_scenarios(scenarios: &[Scenario]) {
+    let mut problems = Vec::new();
+
+    for scenario in scenarios {
+        let frame = run_scenario(scenario);
+
+        for &(ly, colors) in scenario.expected {
+            for (&(sprite, x), &expected) in scenario.sprites.iter().zip(colors.iter()) {
+                let actual = color_of(frame[ly as usize * 160 + x]);
+
+                if actual != Some(expected) {
+                    problems.push(format!(
+                        "{}: Sprite {} in line {} has color {:?}, expected {}",
+                        scenario.name, sprite, ly, actual, expected
+                    ));
+                }
+            }
+        }
+    }
+
+    assert!(problems.is_empty(), "{}", problems.join("\n"));
+}
+
+/// Returns the first frame that is shown after turning the LCD on
+fn run_scenario(scenario: &Scenario) -> Vec<MemPixel> {
+    let mut ppu = PPU::new();
+    let mut ir_system = InterruptSystem::new();
+
+    // Tile 0 is color 3, tile 1 is color 1
+    for row in 0..8 {
+        ppu.write_video_mem_unchecked(VideoMemAddr::TileData(row * 2), 0xFF);
+        ppu.write_video_mem_unchecked(VideoMemAddr::TileData(row * 2 + 1), 0xFF);
+        ppu.write_video_mem_unchecked(VideoMemAddr::TileData(16 + row * 2), 0xFF);
+    }
+
+    for &(sprite, x) in scenario.sprites {
+        let oam = sprite as u16 * 4;
+        ppu.write_video_mem_unchecked(VideoMemAddr::OAM(oam), 16 + 8);
+        ppu.write_video_mem_unchecked(VideoMemAddr::OAM(oam + 1), x as u8 / 8 * 8 + 8);
+    }
+
+    for &reg in &[PpuReg::BGP, PpuReg::OBP0] {
+        ppu.write_reg(&mut ir_system, &mut NoDbgLogger, reg, 0b11_10_01_00);
+    }
+
+    // The first frame after turning the LCD on is not shown, so the scenario only
+    // starts in the second one
+    for mcycle_idx in 0..2 * 154 * 114 {
+        let shown_frame = mcycle_idx >= 154 * 114;
+        let position = (ppu.ly_internal(), ppu.scanline_mcycle_internal());
+
+        if position == (0, 0) {
+            let tall = scenario.start_tall;
+            apply(&mut ppu, &mut ir_system, Event::SpriteSize(tall));
+        }
+
+        for &(ly, mcycle, event) in scenario.events {
+            if shown_frame && (ly, mcycle) == position {
+                apply(&mut ppu, &mut ir_system, event);
+            }
+        }
+
+        ppu.advance_mcycle(&mut ir_system, &mut NoDbgLogger);
+
+        if let VideoFrameStatus::Ready(frame) = ppu.query_frame_status() {
+            return frame.to_vec();
+        }
+    }
+
+    panic!("PPU didn't finish a frame");
+}
+
+fn apply(ppu: &mut PPU, ir_system: &mut InterruptSystem, event: Event) {
+    match event {
+        Event::SpriteSize(tall) => {
+            let lcdc = if tall {
+                LCDC_BASE | LCDC_TALL_SPRITES
+            } else {
+                LCDC_BASE
+            };
+
+            ppu.write_reg(ir_system, &mut NoDbgLogger, PpuReg::LCDC, lcdc);
+        }
+        Event::MoveSprite(sprite, y) => {
+            ppu.write_video_mem_unchecked(VideoMemAddr::OAM(sprite as u16 * 4), y);
+        }
+    }
+}
+
+fn color_of(pixel: MemPixel) -> Option<u8> {
+    (0..4).find(|&col| {
+        let reference = MemPixel::from(Color::from_u8_lsb(col));
+        (pixel.r, pixel.g, pixel.b) == (reference.r, reference.g, reference.b)
+    })
+}
+
+/// LCDS bit 3: The HBlank condition is enabled
+const LCDS_HBLANK: u8 = 0x08;
+
+struct StatWrite {
+    stat_write_bug: bool,
+    lcd_on: bool,
+    lyc: u8,
+    /// The LCDS value that is written before the LCD is turned on
+    lcds_before: u8,
+    /// The write happens in the first mcycle in which the PPU is in this mode and line
+    at: (Mode, u8),
+}
+
+impl Default for StatWrite {
+    fn default() -> Self {
+        StatWrite {
+            stat_write_bug: true,
+            lcd_on: true,
+            lyc: 0xFF,
+            lcds_before: 0x00,
+            at: (Mode::HBlank, 5),
+        }
+    }
+}
+
+impl StatWrite {
+    /// Whether writing 0 to LCDS requests an LCD Stat interrupt
+    fn requests_interrupt(self) -> bool {
+        let mut ppu = PPU::new();
+        let mut ir_system = InterruptSystem::new();
+        ppu.set_stat_write_bug(self.stat_write_bug);
+
+        ppu.write_reg(&mut ir_system, &mut NoDbgLogger, PpuReg::LYC, self.lyc);
+        ppu.write_reg(
+            &mut ir_system,
+            &mut NoDbgLogger,
+            PpuReg::LCDS,
+            self.lcds_before,
+        );
+
+        if self.lcd_on {
+            ppu.write_reg(&mut ir_system, &mut NoDbgLogger, PpuReg::LCDC, LCDC_BASE);
+
+            while (ppu.mode(), ppu.ly_internal()) != self.at {
+                ppu.advance_mcycle(&mut ir_system, &mut NoDbgLogger);
+            }
+        }
+
+        ir_system.write_if(0);
+        ppu.write_reg(&mut ir_system, &mut NoDbgLogger, PpuReg::LCDS, 0x00);
+
+        ir_system.read_if() & Interrupt::LcdStat as u8 != 0
+    }
+}
+
+#[test]
+fn stat_write_bug_requests_interrupts() {
+    let hblank = StatWrite::default();
+    let vblank = StatWrite {
+        at: (Mode::VBlank, 150),
+        ..StatWrite::default()
+    };
+    let lyc_match = StatWrite {
+        lyc: 5,
+        at: (Mode::PixelTransfer, 5),
+        ..StatWrite::default()
+    };
+
+    assert!(hblank.requests_interrupt());
+    assert!(vblank.requests_interrupt());
+    assert!(lyc_match.requests_interrupt());
+}
+
+#[test]
+fn stat_write_bug_is_blocked() {
+    let oam_search = StatWrite {
+        at: (Mode::OAMSearch, 5),
+        ..StatWrite::default()
+    };
+    let already_met = StatWrite {
+        lcds_before: LCDS_HBLANK,
+        ..StatWrite::default()
+    };
+    let lcd_off = StatWrite {
+        lcd_on: false,
+        lyc: 0,
+        ..StatWrite::default()
+    };
+    let disabled = StatWrite {
+        stat_write_bug: false,
+        ..StatWrite::default()
+    };
+
+    assert!(!oam_search.requests_interrupt());
+    assert!(!already_met.requests_interrupt());
+    assert!(!lcd_off.requests_interrupt());
+    assert!(!disabled.requests_interrupt());
+}
@@ -2,6 +2,7 @@
 
 use super::color::Color;
 use super::tile_maps::TileRowAddr;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use fixedbitset::FixedBitSet;
 use std::ops::{Index, IndexMut};
 
@@ -147,3 +148,22 @@ impl TileRow for ReverseTileRow {
         self.0 = self.0.rotate_left(n as u32 * 2);
     }
 }
+
+impl SaveState for TileData {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.raw_mem);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes(&mut self.raw_mem)?;
+
+        // The pretty layout is derived from the raw memory, so we just rebuild all of it.
+        // This has to happen right away, since the state might have been saved during
+        // pixel transfer, when the PPU expects the pretty layout to be up to date.
+        self.dirty_tiles.insert_range(..);
+        self.is_dirty = true;
+        self.rebuild();
+
+        Ok(())
+    }
+}
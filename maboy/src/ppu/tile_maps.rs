@@ -1,4 +1,5 @@
 use super::lcdc::{SpriteSize, LCDC};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 
 /// Memory from 0x9800 to 0x9FFF.
 /// Contains ids for Window and Background tiles.
@@ -77,3 +78,15 @@ impl TileRowAddr {
         self.0
     }
 }
+
+/// Only saves the backing memory. The LCDC mirrors need to be restored via
+/// [`TileMaps::notify_lcdc_changed`] after loading.
+impl SaveState for TileMaps {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.mem);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes(&mut self.mem)
+    }
+}
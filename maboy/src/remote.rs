@@ -0,0 +1,250 @@
+//! An HTTP server that lets external tools (test frameworks, bots, scripts in any
+//! language) drive the emulator without linking against it. Only available with the
+//! `remote` feature.
+//!
+//! Like the [`crate::state_server`], it never blocks and doesn't run on a thread of its
+//! own. The frontend calls [`RemoteServer::poll`] once per frame, hands every frame to
+//! [`RemoteServer::update_frame`], and asks [`RemoteServer::should_run_frame`] before
+//! running the next one:
+//!
+//! ```text
+//! GET  /state, /cpu, /ppu,          Same as the state server
+//!      /timer, /banks
+//! GET  /screenshot                  The most recent frame as PNG
+//! GET  /mem?addr=<a>&len=<n>        n bytes (default 1) as a JSON array, see Emulator::peek
+//! POST /mem?addr=<a>&bytes=<b>,...  Writes the bytes starting at a, see Emulator::poke
+//! POST /pause
+//! POST /resume
+//! POST /step?frames=<n>             Runs n frames (default 1), then pauses again
+//! POST /buttons?held=<buttons>      Sets which buttons are held, e.g. A|START or NONE
+//! POST /press?buttons=<buttons>     Presses buttons, leaving the others as they are
+//! POST /release?buttons=<buttons>   Releases buttons, leaving the others as they are
+//! ```
+//!
+//! Numbers can be decimal or hex (`0xC000`), and `|` may be sent as `%7C`. The control
+//! endpoints answer with `{"paused": ..., "pending_steps": ...}`.
+//!
+//! Anyone who can reach the server can write to memory, so only bind it to `localhost`.
+//! Web pages could still reach it through the browser, so requests from pages that
+//! weren't loaded from `localhost` (judging by their `Origin` header) are rejected with
+//! 403, and responses don't allow cross-origin reads.
+//! There are no WebSockets (yet); Clients that want to follow along have to poll.
+
+use crate::debug::{CpuEvt, DbgEvtSrc, PpuEvt};
+use crate::frame_dump::encode_png;
+use crate::headless::{FRAME_HEIGHT, FRAME_WIDTH};
+use crate::http::{self, Access, Request, Response};
+use crate::state_server::snapshot_response;
+use crate::{pixel_format, Buttons, Cartridge, Emulator, FrameResult, MemPixel};
+use std::convert::TryFrom;
+use std::io;
+use std::net::{TcpListener, ToSocketAddrs};
+
+/// Used by the frontends unless another address is passed
+pub const DEFAULT_ADDR: &str = "localhost:8018";
+
+/// See the [module documentation](self)
+pub struct RemoteServer {
+    listener: TcpListener,
+    paused: bool,
+    /// Frames that still run while paused, see [`RemoteServer::should_run_frame`]
+    pending_steps: u32,
+    /// A copy of the most recent frame, for screenshots
+    frame: Vec<MemPixel>,
+}
+
+impl RemoteServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<RemoteServer> {
+        Ok(RemoteServer {
+            listener: http::bind(addr, "Remote server")?,
+            paused: false,
+            pending_steps: 0,
+            frame: vec![MemPixel::LCD_OFF; FRAME_WIDTH * FRAME_HEIGHT],
+        })
+    }
+
+    /// Whether a client paused the emulator
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Lets the frontend pause and resume on its own, e.g. via a hotkey
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.pending_steps = 0;
+    }
+
+    /// Whether the frontend should run the next frame. This is always true unless a client
+    /// paused the emulator, in which case only frames requested via `/step` run.
+    pub fn should_run_frame(&mut self) -> bool {
+        if !self.paused {
+            true
+        } else if self.pending_steps > 0 {
+            self.pending_steps -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Keeps a copy of the frame for `/screenshot`
+    pub fn update_frame(&mut self, frame: &FrameResult) {
+        match frame {
+            FrameResult::Frame(pixels) => self.frame.copy_from_slice(pixels),
+            FrameResult::LcdOff => self.frame.fill(MemPixel::LCD_OFF),
+        }
+    }
+
+    /// Answers all requests that arrived since the last call
+    pub fn poll<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>(
+        &mut self,
+        emu: &mut Emulator<C, CpuDbg, PpuDbg>,
+    ) {
+        let RemoteServer {
+            listener,
+            paused,
+            pending_steps,
+            frame,
+        } = self;
+
+        http::serve_pending(listener, "Remote server", Access::LocalOrigin, |request| {
+            let result = match (request.method.as_str(), request.path.as_str()) {
+                ("GET", "/screenshot") => screenshot(frame),
+                ("GET", "/mem") => peek(emu, request),
+                ("POST", "/mem") => poke(emu, request),
+                ("POST", "/pause") => {
+                    *paused = true;
+                    *pending_steps = 0;
+                    Ok(status(*paused, *pending_steps))
+                }
+                ("POST", "/resume") => {
+                    *paused = false;
+                    *pending_steps = 0;
+                    Ok(status(*paused, *pending_steps))
+                }
+                ("POST", "/step") => number_param(request, "frames").map(|frames| {
+                    *paused = true;
+                    *pending_steps = pending_steps.saturating_add(frames.unwrap_or(1));
+                    status(*paused, *pending_steps)
+                }),
+                ("POST", "/buttons") => buttons_param(request, "held").map(|buttons| {
+                    emu.notify_buttons_state(buttons);
+                    status(*paused, *pending_steps)
+                }),
+                ("POST", "/press") => buttons_param(request, "buttons").map(|buttons| {
+                    emu.notify_buttons_pressed(buttons);
+                    status(*paused, *pending_steps)
+                }),
+                ("POST", "/release") => buttons_param(request, "buttons").map(|buttons| {
+                    emu.notify_buttons_released(buttons);
+                    status(*paused, *pending_steps)
+                }),
+                ("GET", path) => snapshot_response(&emu.state_snapshot(), path)
+                    .ok_or_else(|| Response::error("404 Not Found", "Unknown path")),
+                ("POST", _) => Err(Response::error("404 Not Found", "Unknown path")),
+                _ => Err(Response::error(
+                    "405 Method Not Allowed",
+                    "Only GET and POST are supported",
+                )),
+            };
+
+            result.unwrap_or_else(|err| err)
+        });
+    }
+}
+
+fn status(paused: bool, pending_steps: u32) -> Response {
+    Response::json(
+        serde_json::json!({ "paused": paused, "pending_steps": pending_steps }).to_string(),
+    )
+}
+
+fn screenshot(frame: &[MemPixel]) -> Result<Response, Response> {
+    let mut png = Vec::new();
+
+    encode_png(
+        &mut png,
+        FRAME_WIDTH,
+        FRAME_HEIGHT,
+        pixel_format::as_rgba8(frame),
+    )
+    .map_err(|err| Response::error("500 Internal Server Error", &err.to_string()))?;
+
+    Ok(Response {
+        status: "200 OK",
+        content_type: "image/png",
+        body: png,
+    })
+}
+
+fn peek<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>(
+    emu: &Emulator<C, CpuDbg, PpuDbg>,
+    request: &Request,
+) -> Result<Response, Response> {
+    let addr = addr_param(request)?;
+    let len = number_param(request, "len")?.unwrap_or(1);
+
+    if len > 0x10000 {
+        return Err(bad_request("len must not be larger than 0x10000"));
+    }
+
+    let bytes: Vec<u8> = (0..len)
+        .map(|offset| emu.peek(addr.wrapping_add(offset as u16)))
+        .collect();
+
+    Ok(Response::from_json(serde_json::to_string(&bytes)))
+}
+
+fn poke<C: Cartridge, CpuDbg: DbgEvtSrc<CpuEvt>, PpuDbg: DbgEvtSrc<PpuEvt>>(
+    emu: &mut Emulator<C, CpuDbg, PpuDbg>,
+    request: &Request,
+) -> Result<Response, Response> {
+    let addr = addr_param(request)?;
+
+    let bytes = request
+        .param("bytes")
+        .ok_or_else(|| bad_request("Missing parameter: bytes"))?
+        .split(',')
+        .map(|byte| parse_int::parse::<u8>(byte.trim()).map_err(|_| ()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| bad_request("bytes must be a comma-separated list of bytes"))?;
+
+    for (offset, &byte) in bytes.iter().enumerate() {
+        emu.poke(addr.wrapping_add(offset as u16), byte);
+    }
+
+    Ok(Response::json(
+        serde_json::json!({ "written": bytes.len() }).to_string(),
+    ))
+}
+
+/// A number parameter, or `None` if it is missing
+fn number_param(request: &Request, key: &str) -> Result<Option<u32>, Response> {
+    request
+        .param(key)
+        .map(|value| {
+            parse_int::parse::<u32>(value)
+                .map_err(|_| bad_request(&format!("Invalid {}: {}", key, value)))
+        })
+        .transpose()
+}
+
+fn addr_param(request: &Request) -> Result<u16, Response> {
+    number_param(request, "addr")?
+        .ok_or_else(|| bad_request("Missing parameter: addr"))
+        .and_then(|addr| u16::try_from(addr).map_err(|_| bad_request("addr must be below 0x10000")))
+}
+
+fn buttons_param(request: &Request, key: &str) -> Result<Buttons, Response> {
+    let value = request
+        .param(key)
+        .ok_or_else(|| bad_request(&format!("Missing parameter: {}", key)))?;
+
+    value
+        .parse()
+        .map_err(|_| bad_request(&format!("Invalid buttons: {}", value)))
+}
+
+fn bad_request(msg: &str) -> Response {
+    Response::error("400 Bad Request", msg)
+}
@@ -0,0 +1,231 @@
+//! A rewind buffer that allows the frontend to step backwards in time. It is built
+//! on top of savestates: Every few frames, a savestate is taken. Only the most
+//! recent savestate is kept in full. For all older ones, we only keep a compressed
+//! delta that turns the next newer savestate into the older one.
+//!
+//! Since two savestates that are only a few frames apart are almost identical, we
+//! XOR them together and run-length encode the resulting zero bytes. This reduces
+//! the size of a rewind point from ~150KB to a few KB in most games.
+
+use std::collections::VecDeque;
+
+/// Stores the history of the emulator state for rewinding. See the
+/// [module documentation](self) for details on how this works.
+pub struct RewindBuffer {
+    /// Maximum number of rewind points (including the most recent one)
+    capacity: usize,
+    /// Number of frames between two rewind points
+    interval: u32,
+    /// Number of frames that passed since [`latest`] was taken
+    frames_since_latest: u32,
+    /// The most recent savestate, stored in full
+    latest: Option<Vec<u8>>,
+    /// Reverse deltas, ordered from oldest to newest. Applying the last delta to
+    /// [`latest`] yields the savestate taken before [`latest`], and so on.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+/// Delta tags
+const TAG_XOR: u8 = 0;
+const TAG_FULL: u8 = 1;
+
+impl RewindBuffer {
+    /// Creates a rewind buffer that takes a snapshot every `interval` frames and keeps
+    /// at most `capacity` snapshots. This means that you can rewind for up to
+    /// `capacity * interval` frames.
+    ///
+    /// Panics if either `capacity` or `interval` is 0.
+    pub fn new(capacity: usize, interval: u32) -> RewindBuffer {
+        assert!(capacity > 0, "Rewind buffer capacity must not be 0");
+        assert!(interval > 0, "Rewind interval must not be 0");
+
+        RewindBuffer {
+            capacity,
+            interval,
+            frames_since_latest: 0,
+            latest: None,
+            deltas: VecDeque::with_capacity(capacity - 1),
+        }
+    }
+
+    /// The number of rewind points that are currently stored
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// The number of frames that can currently be rewound
+    pub fn frames_available(&self) -> u32 {
+        if self.latest.is_some() {
+            self.frames_since_latest + self.deltas.len() as u32 * self.interval
+        } else {
+            0
+        }
+    }
+
+    /// Forgets all rewind points
+    pub fn clear(&mut self) {
+        self.frames_since_latest = 0;
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Has to be called once per frame. Returns true if a new rewind point should be
+    /// pushed via [`RewindBuffer::push`].
+    pub(crate) fn advance_frame(&mut self) -> bool {
+        if self.latest.is_none() {
+            return true;
+        }
+
+        self.frames_since_latest += 1;
+        self.frames_since_latest >= self.interval
+    }
+
+    /// Stores a new rewind point
+    pub(crate) fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &previous));
+
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+
+        self.latest = Some(state);
+        self.frames_since_latest = 0;
+    }
+
+    /// Goes back to the newest rewind point that is at least `frames` frames old, or to
+    /// the oldest rewind point if there is none. Returns the savestate of that rewind
+    /// point and how many frames were actually rewound, or `None` if there is nothing
+    /// to rewind.
+    pub(crate) fn step_back(&mut self, frames: u32) -> Option<(&[u8], u32)> {
+        let latest = self.latest.as_mut()?;
+
+        let deltas_to_apply = if frames > self.frames_since_latest {
+            let remaining = frames - self.frames_since_latest;
+            let needed = remaining.div_ceil(self.interval);
+            (needed as usize).min(self.deltas.len())
+        } else {
+            0
+        };
+
+        let rewound = self.frames_since_latest + deltas_to_apply as u32 * self.interval;
+
+        if rewound == 0 {
+            return None;
+        }
+
+        for _ in 0..deltas_to_apply {
+            let delta = self
+                .deltas
+                .pop_back()
+                .expect("Rewind delta count out of sync");
+
+            apply_delta(latest, &delta);
+        }
+
+        self.frames_since_latest = 0;
+
+        Some((latest, rewound))
+    }
+}
+
+/// Creates a delta that turns `newer` into `older`
+fn encode_delta(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    // Savestates only change size if the cartridge changes, which shouldn't happen,
+    // but we can handle it anyway
+    if newer.len() != older.len() {
+        let mut delta = Vec::with_capacity(older.len() + 1);
+        delta.push(TAG_FULL);
+        delta.extend_from_slice(older);
+        return delta;
+    }
+
+    let mut delta = vec![TAG_XOR];
+    let mut pos = 0;
+
+    while pos < newer.len() {
+        let run_start = pos;
+        while pos < newer.len() && newer[pos] == older[pos] {
+            pos += 1;
+        }
+        write_varint(&mut delta, pos - run_start);
+
+        // A literal ends once we encounter at least two unchanged bytes in a row, since
+        // a new zero run is cheaper than continuing the literal at that point
+        let literal_start = pos;
+        while pos < newer.len()
+            && (newer[pos] != older[pos]
+                || (pos + 1 < newer.len() && newer[pos + 1] != older[pos + 1]))
+        {
+            pos += 1;
+        }
+        write_varint(&mut delta, pos - literal_start);
+
+        delta.extend(
+            newer[literal_start..pos]
+                .iter()
+                .zip(&older[literal_start..pos])
+                .map(|(n, o)| n ^ o),
+        );
+    }
+
+    delta
+}
+
+/// Applies a delta created by [`encode_delta`] in-place
+fn apply_delta(state: &mut Vec<u8>, delta: &[u8]) {
+    let (&tag, mut delta) = delta.split_first().expect("Empty rewind delta");
+
+    if tag == TAG_FULL {
+        state.clear();
+        state.extend_from_slice(delta);
+        return;
+    }
+
+    let mut pos = 0;
+
+    while !delta.is_empty() {
+        pos += read_varint(&mut delta);
+
+        let literal_len = read_varint(&mut delta);
+        let (literal, rest) = delta.split_at(literal_len);
+
+        for (byte, xor) in state[pos..pos + literal_len].iter_mut().zip(literal) {
+            *byte ^= xor;
+        }
+
+        pos += literal_len;
+        delta = rest;
+    }
+}
+
+/// LEB128-style variable length encoding
+fn write_varint(target: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        target.push((val as u8 & 0x7f) | 0x80);
+        val >>= 7;
+    }
+    target.push(val as u8);
+}
+
+fn read_varint(source: &mut &[u8]) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+
+    loop {
+        let (&byte, rest) = source.split_first().expect("Truncated rewind delta");
+        *source = rest;
+
+        val |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return val;
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Unpacks ROMs that come in archives, since many ROMs are distributed zipped.
+//!
+//! [`unpack`] detects the format by the first bytes of the file, not by its extension:
+//!
+//! - Zip archives: The first `.gb` or `.gbc` file in the archive is the ROM. Entries
+//!   can be stored or deflated, which is what basically every zip tool produces.
+//! - Gzip files (`.gz`): The whole decompressed content is the ROM.
+//! - Everything else is returned as is, so it can be passed to
+//!   [`crate::CartridgeVariant::from_bytes`] no matter where it came from.
+//!
+//! [`crate::CartridgeVariant::from_file`] does this on its own, so frontends that open
+//! ROMs from disk support archives without doing anything.
+
+use flate2::read::{DeflateDecoder, GzDecoder};
+use std::convert::TryInto;
+use std::io::{self, Read};
+
+/// The largest ROM that any cartridge supports is 8 MiB. Anything that unpacks to more
+/// than this is not a ROM (and might be a zip bomb).
+pub const MAX_ROM_SIZE: usize = 8 * 1024 * 1024;
+
+const ZIP_LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+const ZIP_END_OF_CENTRAL_DIR_SIG: u32 = 0x0605_4b50;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Only the ROMs of these extensions are picked out of zip archives
+const ROM_EXTENSIONS: [&str; 2] = [".gb", ".gbc"];
+
+#[derive(Debug)]
+pub enum RomArchiveError {
+    /// The compressed data is broken
+    Io(io::Error),
+    /// The zip archive is truncated or otherwise broken
+    InvalidArchive,
+    /// The zip archive doesn't contain a `.gb` or `.gbc` file
+    NoRom,
+    /// The ROM in the zip archive is encrypted or compressed with something other than
+    /// deflate
+    UnsupportedEntry(String),
+    /// The archive unpacks to more than [`MAX_ROM_SIZE`]
+    TooLarge,
+}
+
+impl From<io::Error> for RomArchiveError {
+    fn from(err: io::Error) -> Self {
+        RomArchiveError::Io(err)
+    }
+}
+
+/// Unpacks the ROM if `data` is an archive, or returns `data` unchanged otherwise
+pub fn unpack(data: Vec<u8>) -> Result<Vec<u8>, RomArchiveError> {
+    if data.starts_with(&ZIP_LOCAL_HEADER_SIG.to_le_bytes()) {
+        unpack_zip(&data)
+    } else if data.starts_with(&GZIP_MAGIC) {
+        read_limited(GzDecoder::new(&data[..]))
+    } else {
+        Ok(data)
+    }
+}
+
+/// Goes through the central directory at the end of the archive, since the sizes in
+/// the local headers are missing if the archive was written as a stream. All offsets and
+/// lengths come from the archive, so they are never added up unchecked.
+fn unpack_zip(data: &[u8]) -> Result<Vec<u8>, RomArchiveError> {
+    use RomArchiveError::InvalidArchive;
+
+    // The end of central directory record is 22 bytes, followed by a comment of up
+    // to 64 KiB
+    let eocd = (0..=data.len().saturating_sub(22))
+        .rev()
+        .take(0x10000)
+        .find(|&pos| read_u32(data, pos) == Some(ZIP_END_OF_CENTRAL_DIR_SIG))
+        .ok_or(InvalidArchive)?;
+
+    let entries = read_u16(data, eocd + 10).ok_or(InvalidArchive)?;
+    let mut pos = read_u32(data, eocd + 16).ok_or(InvalidArchive)? as usize;
+
+    for _ in 0..entries {
+        let header = slice_at(data, pos, 46).ok_or(InvalidArchive)?;
+
+        if read_u32(header, 0) != Some(ZIP_CENTRAL_HEADER_SIG) {
+            return Err(InvalidArchive);
+        }
+
+        let flags = read_u16(header, 8).ok_or(InvalidArchive)?;
+        let method = read_u16(header, 10).ok_or(InvalidArchive)?;
+        let compressed_len = read_u32(header, 20).ok_or(InvalidArchive)? as usize;
+        let name_len = read_u16(header, 28).ok_or(InvalidArchive)? as usize;
+        let extra_len = read_u16(header, 30).ok_or(InvalidArchive)? as usize;
+        let comment_len = read_u16(header, 32).ok_or(InvalidArchive)? as usize;
+        let local_header = read_u32(header, 42).ok_or(InvalidArchive)? as usize;
+
+        let name_start = pos.checked_add(46).ok_or(InvalidArchive)?;
+        let name = slice_at(data, name_start, name_len).ok_or(InvalidArchive)?;
+        let name = String::from_utf8_lossy(name);
+
+        pos = name_start
+            .checked_add(name_len + extra_len + comment_len)
+            .ok_or(InvalidArchive)?;
+
+        let lowercase_name = name.to_lowercase();
+        if !ROM_EXTENSIONS
+            .iter()
+            .any(|extension| lowercase_name.ends_with(extension))
+        {
+            continue;
+        }
+
+        // Bit 0 means that the entry is encrypted
+        if flags & 1 != 0 || (method != 0 && method != 8) {
+            return Err(RomArchiveError::UnsupportedEntry(name.into_owned()));
+        }
+
+        let local = slice_at(data, local_header, 30).ok_or(InvalidArchive)?;
+
+        if read_u32(local, 0) != Some(ZIP_LOCAL_HEADER_SIG) {
+            return Err(InvalidArchive);
+        }
+
+        let local_name_len = read_u16(local, 26).ok_or(InvalidArchive)? as usize;
+        let local_extra_len = read_u16(local, 28).ok_or(InvalidArchive)? as usize;
+        let start = local_header
+            .checked_add(30 + local_name_len + local_extra_len)
+            .ok_or(InvalidArchive)?;
+        let compressed = slice_at(data, start, compressed_len).ok_or(InvalidArchive)?;
+
+        return match method {
+            0 => read_limited(compressed),
+            _ => read_limited(DeflateDecoder::new(compressed)),
+        };
+    }
+
+    Err(RomArchiveError::NoRom)
+}
+
+fn read_limited<R: Read>(reader: R) -> Result<Vec<u8>, RomArchiveError> {
+    let mut rom = Vec::new();
+    reader.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut rom)?;
+
+    if rom.len() > MAX_ROM_SIZE {
+        Err(RomArchiveError::TooLarge)
+    } else {
+        Ok(rom)
+    }
+}
+
+/// `len` bytes starting at `pos`, or `None` if they are (partly) out of bounds
+fn slice_at(data: &[u8], pos: usize, len: usize) -> Option<&[u8]> {
+    data.get(pos..)?.get(..len)
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    let bytes = slice_at(data, pos, 2)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = slice_at(data, pos, 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{DeflateEncoder, GzEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    const STORED: u16 = 0;
+    const DEFLATED: u16 = 8;
+    const ENCRYPTED: u16 = 1;
+
+    struct Entry<'a> {
+        name: &'a str,
+        method: u16,
+        flags: u16,
+        content: &'a [u8],
+    }
+
+    impl<'a> Entry<'a> {
+        fn new(name: &'a str, method: u16, content: &'a [u8]) -> Entry<'a> {
+            Entry {
+                name,
+                method,
+                flags: 0,
+                content,
+            }
+        }
+    }
+
+    fn deflate(content: &[u8]) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A zip archive as zip tools write it: The local headers with the data, followed by
+    /// the central directory and the end of central directory record
+    fn zip(entries: &[Entry]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut central_dir = Vec::new();
+
+        for entry in entries {
+            let compressed = match entry.method {
+                DEFLATED => deflate(entry.content),
+                _ => entry.content.to_vec(),
+            };
+            let local_header = data.len() as u32;
+
+            data.extend_from_slice(&ZIP_LOCAL_HEADER_SIG.to_le_bytes());
+            data.extend_from_slice(&[20, 0]);
+            data.extend_from_slice(&entry.flags.to_le_bytes());
+            data.extend_from_slice(&entry.method.to_le_bytes());
+            data.extend_from_slice(&[0; 8]); // Time, date, CRC
+            data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(entry.content.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(entry.name.as_bytes());
+            data.extend_from_slice(&compressed);
+
+            central_dir.extend_from_slice(&ZIP_CENTRAL_HEADER_SIG.to_le_bytes());
+            central_dir.extend_from_slice(&[20, 0, 20, 0]);
+            central_dir.extend_from_slice(&entry.flags.to_le_bytes());
+            central_dir.extend_from_slice(&entry.method.to_le_bytes());
+            central_dir.extend_from_slice(&[0; 8]); // Time, date, CRC
+            central_dir.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            central_dir.extend_from_slice(&(entry.content.len() as u32).to_le_bytes());
+            central_dir.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            central_dir.extend_from_slice(&[0; 12]); // Extra, comment, disk, attributes
+            central_dir.extend_from_slice(&local_header.to_le_bytes());
+            central_dir.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let central_dir_offset = data.len() as u32;
+        data.extend_from_slice(&central_dir);
+
+        data.extend_from_slice(&ZIP_END_OF_CENTRAL_DIR_SIG.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(central_dir.len() as u32).to_le_bytes());
+        data.extend_from_slice(&central_dir_offset.to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+
+        data
+    }
+
+    fn rom() -> Vec<u8> {
+        (0..0x8000).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn other_data_is_returned_as_is() {
+        assert_eq!(unpack(rom()).unwrap(), rom());
+    }
+
+    #[test]
+    fn stored_and_deflated_roms_are_unpacked() {
+        let rom = rom();
+
+        let stored = zip(&[Entry::new("game.gb", STORED, &rom)]);
+        assert_eq!(unpack(stored).unwrap(), rom);
+
+        let deflated = zip(&[Entry::new("game.gb", DEFLATED, &rom)]);
+        assert_eq!(unpack(deflated).unwrap(), rom);
+
+        assert_eq!(unpack(gzip(&rom)).unwrap(), rom);
+    }
+
+    #[test]
+    fn the_first_rom_in_a_zip_is_picked() {
+        let rom = rom();
+        let archive = zip(&[
+            Entry::new("readme.txt", STORED, b"Not a ROM"),
+            Entry::new("GAME.GBC", DEFLATED, &rom),
+            Entry::new("other.gb", STORED, b"Another ROM"),
+        ]);
+
+        assert_eq!(unpack(archive).unwrap(), rom);
+    }
+
+    #[test]
+    fn zips_without_rom_are_rejected() {
+        let archive = zip(&[Entry::new("readme.txt", STORED, b"Not a ROM")]);
+        assert!(matches!(unpack(archive), Err(RomArchiveError::NoRom)));
+    }
+
+    #[test]
+    fn encrypted_roms_are_rejected() {
+        let rom = rom();
+        let archive = zip(&[Entry {
+            flags: ENCRYPTED,
+            ..Entry::new("game.gb", STORED, &rom)
+        }]);
+
+        match unpack(archive) {
+            Err(RomArchiveError::UnsupportedEntry(name)) => assert_eq!(name, "game.gb"),
+            other => panic!("Unexpected result {:?}", other.map(|rom| rom.len())),
+        }
+    }
+
+    #[test]
+    fn archives_larger_than_any_rom_are_rejected() {
+        let huge = vec![0; MAX_ROM_SIZE + 1];
+
+        let archive = zip(&[Entry::new("game.gb", DEFLATED, &huge)]);
+        assert!(matches!(unpack(archive), Err(RomArchiveError::TooLarge)));
+
+        assert!(matches!(
+            unpack(gzip(&huge)),
+            Err(RomArchiveError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn out_of_bounds_offsets_are_rejected() {
+        let rom = rom();
+        let archive = zip(&[Entry::new("game.gb", STORED, &rom)]);
+        let eocd = archive.len() - 22;
+        let central_dir = read_u32(&archive, eocd + 16).unwrap() as usize;
+
+        // Central directory offset
+        let mut broken = archive.clone();
+        broken[eocd + 16..eocd + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            unpack(broken),
+            Err(RomArchiveError::InvalidArchive)
+        ));
+
+        // Local header offset
+        let mut broken = archive.clone();
+        broken[central_dir + 42..central_dir + 46].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            unpack(broken),
+            Err(RomArchiveError::InvalidArchive)
+        ));
+
+        // Compressed length
+        let mut broken = archive;
+        broken[central_dir + 20..central_dir + 24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            unpack(broken),
+            Err(RomArchiveError::InvalidArchive)
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+//! Savestates capture the complete state of the emulator (everything except the
+//! cartridge ROM) in a flat byte vector, which can later be loaded again to
+//! continue emulation from that exact point.
+//!
+//! Every component that carries state implements the crate-internal [`SaveState`]
+//! trait and writes its fields in a fixed order via [`StateWriter`]. Loading
+//! reads the fields back in the same order via [`StateReader`]. There is no
+//! self-describing format here; A savestate is only valid for the exact same
+//! emulator version and cartridge that produced it.
+
+use std::convert::TryInto;
+
+/// Identifies a MaBoy savestate
+const MAGIC: &[u8; 4] = b"MBSS";
+
+/// Needs to be bumped whenever the layout of any component's state changes
+pub const SAVESTATE_VERSION: u8 = 17;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    /// The data does not start with the savestate magic bytes
+    InvalidMagic,
+    /// The savestate was created by an incompatible version of MaBoy
+    UnsupportedVersion(u8),
+    /// The savestate was created with a different cartridge
+    CartridgeMismatch,
+    /// The data ended before all state could be read
+    UnexpectedEnd,
+    /// Some value in the data is not valid for the component it belongs to
+    InvalidValue,
+    /// There is more data than expected at the end of the savestate
+    TrailingData,
+}
+
+/// Implemented by every component of the emulator that has internal state
+pub(crate) trait SaveState {
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+/// Serializes component state into a byte vector. All integers are little-endian.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    /// Writes the savestate header
+    pub(crate) fn write_header(&mut self, cartridge_id: [u8; 3]) {
+        self.data.extend_from_slice(MAGIC);
+        self.data.push(SAVESTATE_VERSION);
+        self.data.extend_from_slice(&cartridge_id);
+    }
+
+    pub(crate) fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, val: u8) {
+        self.data.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.data.push(val as u8);
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    /// Writes a chunk of memory without length information, so it has to be
+    /// read back into a slice of the exact same length
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+/// Reads back component state that was written by [`StateWriter`]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    /// Checks the savestate header and returns the cartridge id stored in it
+    pub(crate) fn read_header(&mut self) -> Result<[u8; 3], SaveStateError> {
+        let mut magic = [0u8; 4];
+        self.read_bytes(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(SaveStateError::InvalidMagic);
+        }
+
+        let version = self.read_u8()?;
+        if version != SAVESTATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        let mut cartridge_id = [0u8; 3];
+        self.read_bytes(&mut cartridge_id)?;
+        Ok(cartridge_id)
+    }
+
+    /// Makes sure that all data has been consumed
+    pub(crate) fn finish(self) -> Result<(), SaveStateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(SaveStateError::TrailingData)
+        }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        let (&val, rest) = self
+            .data
+            .split_first()
+            .ok_or(SaveStateError::UnexpectedEnd)?;
+        self.data = rest;
+        Ok(val)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidValue),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Fills `target` completely, failing if there is not enough data left
+    pub fn read_bytes(&mut self, target: &mut [u8]) -> Result<(), SaveStateError> {
+        target.copy_from_slice(self.take(target.len())?);
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::UnexpectedEnd);
+        }
+
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+}
@@ -1,43 +1,214 @@
 //! Implementation of the Serial Port of your Game Boy, used for connecting
-//! two Game Boys via a link cable. This module is almost completely unfinished;
-//! It is only implemented up to a point where it doesn't crash any games.
+//! two Game Boys via a link cable (see [`crate::LinkCable`]).
+//!
+//! Transfers with the internal clock take 8 bits * 128 machine cycles, or 8 bits * 4
+//! machine cycles with the high-speed clock of the Game Boy Color (see
+//! [`SerialSpeed`]). Devices on the other end can shorten that (see
+//! [`SerialTransport::start_transfer`]). The actual exchange with the other Game Boy
+//! happens at the end of the transfer, not bit by bit. Without a cable, the internal clock transfer still completes,
+//! but only 1-bits (0xFF) are shifted in. Transfers with external clock never
+//! complete without a cable, just like on hardware.
 
 use super::address::SerialReg;
+use super::interrupt_system::{Interrupt, InterruptSystem};
+use super::link_cable::{SerialSpeed, SerialTransport};
+use super::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use super::util::BitOps;
 
-/// Storage for the SB register
+/// Bits of SC that always read as 1
+const SC_UNUSED_MASK: u8 = 0b_0111_1110;
+
+/// Bits of SC that always read as 1 if the high-speed clock is available
+const SC_UNUSED_MASK_HIGH_SPEED: u8 = 0b_0111_1100;
+
+/// Storage for the SB and SC registers and the state of a running transfer
 pub struct SerialPort {
     sb_reg: u8,
+    sc_reg: u8,
+    /// Remaining machine cycles of a transfer with internal clock
+    transfer_mcycles_left: u16,
+    link: Option<Box<dyn SerialTransport>>,
+    /// Whether bit 1 of SC selects the high-speed clock (Game Boy Color only)
+    high_speed_available: bool,
 }
 
 impl SerialPort {
     pub fn new() -> SerialPort {
-        SerialPort { sb_reg: 0 }
+        SerialPort {
+            sb_reg: 0,
+            sc_reg: 0,
+            transfer_mcycles_left: 0,
+            link: None,
+            high_speed_available: false,
+        }
+    }
+
+    /// Makes bit 1 of SC select the high-speed clock, like on the Game Boy Color. Bit 1
+    /// is cleared when this is turned off, since it always reads as 1 on the original.
+    pub fn set_high_speed_available(&mut self, available: bool) {
+        self.high_speed_available = available;
+        self.sc_reg &= !self.unused_mask();
+    }
+
+    pub fn high_speed_available(&self) -> bool {
+        self.high_speed_available
+    }
+
+    /// The speed that transfers with internal clock run at
+    pub fn speed(&self) -> SerialSpeed {
+        if self.sc_reg.bit(1) {
+            SerialSpeed::High
+        } else {
+            SerialSpeed::Normal
+        }
+    }
+
+    fn unused_mask(&self) -> u8 {
+        if self.high_speed_available {
+            SC_UNUSED_MASK_HIGH_SPEED
+        } else {
+            SC_UNUSED_MASK
+        }
+    }
+
+    /// Puts the serial port into its power-on state. The link cable stays plugged in.
+    pub fn reset(&mut self) {
+        self.sb_reg = 0;
+        self.sc_reg = 0;
+        self.transfer_mcycles_left = 0;
+        self.publish();
+    }
+
+    /// Plugs in a link cable, returning the previously plugged in one (if any)
+    pub fn connect(&mut self, link: Box<dyn SerialTransport>) -> Option<Box<dyn SerialTransport>> {
+        let previous = self.disconnect();
+        self.link = Some(link);
+        self.publish();
+        previous
+    }
+
+    pub fn disconnect(&mut self) -> Option<Box<dyn SerialTransport>> {
+        if let Some(link) = &mut self.link {
+            link.publish(self.sb_reg, false);
+        }
+
+        self.link.take()
+    }
+
+    pub fn advance_mcycle(&mut self, ir_system: &mut InterruptSystem) {
+        if !self.sc_reg.bit(7) {
+            return;
+        }
+
+        if self.sc_reg.bit(0) {
+            // Internal clock: We drive the transfer
+            self.transfer_mcycles_left -= 1;
+
+            if self.transfer_mcycles_left == 0 {
+                let incoming = match &mut self.link {
+                    Some(link) => link.exchange(self.sb_reg),
+                    None => 0xFF,
+                };
+
+                self.complete_transfer(ir_system, incoming);
+            }
+        } else if let Some(link) = &mut self.link {
+            // External clock: We wait for the other side to drive the transfer
+            if let Some(incoming) = link.take_incoming() {
+                self.complete_transfer(ir_system, incoming);
+            }
+        }
+    }
+
+    /// How many of the next machine cycles can pass without a transfer completing, see
+    /// [`SerialPort::skip_mcycles`]
+    pub fn idle_mcycles(&self) -> u32 {
+        if !self.sc_reg.bit(7) {
+            u32::MAX
+        } else if self.sc_reg.bit(0) {
+            (self.transfer_mcycles_left as u32).saturating_sub(1)
+        } else if self.link.is_some() {
+            // The other side might start the transfer at any time
+            0
+        } else {
+            u32::MAX
+        }
+    }
+
+    /// Advances the serial port by `mcycles` machine cycles at once. Must not be more
+    /// than [`SerialPort::idle_mcycles`].
+    pub fn skip_mcycles(&mut self, mcycles: u32) {
+        debug_assert!(mcycles <= self.idle_mcycles());
+
+        if self.sc_reg.bit(7) && self.sc_reg.bit(0) {
+            self.transfer_mcycles_left -= mcycles as u16;
+        }
     }
 
     pub fn write_reg(&mut self, reg: SerialReg, val: u8) {
         match reg {
             SerialReg::SB => self.sb_reg = val,
             SerialReg::SC => {
-                if val == 0x81 {
-                    // Blargg's test ROMs use this to output debug info; Uncomment
-                    // to print it to the console in addition to the LCD. Useful if
-                    // your LCD implementation is really broken.
-                    // print!("{}", self.sb_reg as char)
-                }
+                // Blargg's test ROMs use transfers with internal clock to output debug
+                // info; Connect a `SerialEcho` to see it.
+                self.sc_reg = val & !self.unused_mask();
+
+                if self.sc_reg.bit(7) {
+                    // Starting a transfer while one is running just restarts it
+                    let speed = self.speed();
 
-                // This is logged as `info`, not `warn`, because some games tend to spam it massively
-                log::info!("Unimplemented write to SC (Serial Port Control) register");
+                    self.transfer_mcycles_left = match &mut self.link {
+                        Some(link) if self.sc_reg.bit(0) => link.start_transfer(speed).max(1),
+                        _ => speed.mcycles_per_byte(),
+                    };
+                }
             }
         }
+
+        self.publish();
     }
 
     pub fn read_reg(&self, reg: SerialReg) -> u8 {
         match reg {
             SerialReg::SB => self.sb_reg,
-            SerialReg::SC => {
-                log::warn!("Unimplemented read of SC register");
-                0
-            }
+            SerialReg::SC => self.sc_reg | self.unused_mask(),
+        }
+    }
+
+    fn complete_transfer(&mut self, ir_system: &mut InterruptSystem, incoming: u8) {
+        self.sb_reg = incoming;
+        self.sc_reg = self.sc_reg.with_bit(7, false);
+        ir_system.schedule_interrupt(Interrupt::Serial);
+        self.publish();
+    }
+
+    /// Lets the other end of the cable know if we are waiting for a transfer
+    fn publish(&mut self) {
+        if let Some(link) = &mut self.link {
+            link.publish(self.sb_reg, self.sc_reg.bit(7) && !self.sc_reg.bit(0));
+        }
+    }
+}
+
+/// The link cable is not part of the savestate, and neither is the availability of the
+/// high-speed clock
+impl SaveState for SerialPort {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.sb_reg);
+        writer.write_u8(self.sc_reg);
+        writer.write_u16(self.transfer_mcycles_left);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.sb_reg = reader.read_u8()?;
+        self.sc_reg = reader.read_u8()? & !self.unused_mask();
+        self.transfer_mcycles_left = reader.read_u16()?;
+
+        if self.sc_reg.bit(7) && self.sc_reg.bit(0) && self.transfer_mcycles_left == 0 {
+            return Err(SaveStateError::InvalidValue);
         }
+
+        self.publish();
+        Ok(())
     }
 }
@@ -0,0 +1,106 @@
+//! Colors and the border that the Super Game Boy puts around the Game Boy's screen
+
+use crate::MemPixel;
+
+/// Size of the picture that the Super Game Boy sends to the TV
+pub const SGB_FRAME_WIDTH: usize = 256;
+pub const SGB_FRAME_HEIGHT: usize = 224;
+
+/// Where the Game Boy's screen is placed inside of the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+/// A finished frame of the Super Game Boy (see [`crate::VideoFrameStatus::SgbReady`])
+pub struct SgbFrame<'a> {
+    /// The whole `SGB_FRAME_WIDTH` x `SGB_FRAME_HEIGHT` picture, row by row: The border
+    /// with the colored screen of the Game Boy in its middle
+    pub frame: &'a [MemPixel],
+    /// Only the (colored) 160x144 screen of the Game Boy, for frontends that don't show
+    /// the border
+    pub screen: &'a [MemPixel],
+    /// The palette (0 - 3) of every 8x8 area of the screen, row by row (20 x 18)
+    pub attributes: &'a [u8],
+    /// The colors of the four palettes. Color 0 is the same in all of them.
+    pub palettes: [[MemPixel; 4]; 4],
+}
+
+/// Converts one of the SNES's 15 bit colors (`0bbbbbgggggrrrrr`)
+pub fn rgb555(color: u16) -> MemPixel {
+    let channel = |shift: u16| {
+        let val = (color >> shift) as u8 & 0x1F;
+        (val << 3) | (val >> 2)
+    };
+
+    MemPixel::new(channel(0), channel(5), channel(10), 0xFF)
+}
+
+/// Colors the shades of the Game Boy's screen with the palettes of their 8x8 areas
+pub fn colorize(
+    shades: &[u8],
+    attributes: &[u8],
+    palettes: &[[u16; 4]; 4],
+    screen: &mut [MemPixel],
+) {
+    for (idx, (pixel, &shade)) in screen.iter_mut().zip(shades.iter()).enumerate() {
+        let area = idx / 160 / 8 * 20 + idx % 160 / 8;
+        let palette = &palettes[attributes[area] as usize & 0b11];
+
+        *pixel = rgb555(palette[shade as usize & 0b11]);
+    }
+}
+
+/// Draws the screen into `frame`, with the border on top of it. `tiles` are 256 SNES
+/// tiles (4 bits per pixel), `map` is the 32x32 tile map of the border, followed by its
+/// four palettes (the SNES's palettes 4 - 7), as they are transferred by PCT_TRN. Color
+/// 0 of the border is transparent, so `backdrop` shows wherever neither covers it.
+pub fn render_frame(
+    screen: &[MemPixel],
+    tiles: &[u8],
+    map: &[u8],
+    backdrop: MemPixel,
+    frame: &mut [MemPixel],
+) {
+    for pixel in frame.iter_mut() {
+        *pixel = backdrop;
+    }
+
+    for (y, row) in screen.chunks_exact(160).enumerate() {
+        let start = (SCREEN_Y + y) * SGB_FRAME_WIDTH + SCREEN_X;
+        frame[start..start + 160].copy_from_slice(row);
+    }
+
+    // The map has 32 rows, but only 28 of them are visible
+    for (idx, entry) in map[..0x800]
+        .chunks_exact(2)
+        .take(32 * SGB_FRAME_HEIGHT / 8)
+        .enumerate()
+    {
+        let entry = u16::from_le_bytes([entry[0], entry[1]]);
+        let tile = &tiles[(entry & 0xFF) as usize * 32..][..32];
+        let palette = &map[0x800 + ((entry >> 10) & 0b11) as usize * 32..][..32];
+        let (flip_x, flip_y) = (entry & 0x4000 != 0, entry & 0x8000 != 0);
+
+        for ty in 0..8 {
+            let row = if flip_y { 7 - ty } else { ty };
+            let planes = [
+                tile[row * 2],
+                tile[row * 2 + 1],
+                tile[16 + row * 2],
+                tile[17 + row * 2],
+            ];
+
+            for tx in 0..8 {
+                let bit = if flip_x { tx } else { 7 - tx };
+                let col = planes.iter().enumerate().fold(0, |col, (plane, byte)| {
+                    col | ((byte >> bit) as usize & 1) << plane
+                });
+
+                if col != 0 {
+                    let color = u16::from_le_bytes([palette[col * 2], palette[col * 2 + 1]]);
+                    let (x, y) = (idx % 32 * 8 + tx, idx / 32 * 8 + ty);
+                    frame[y * SGB_FRAME_WIDTH + x] = rgb555(color);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,522 @@
+//! Implementation of the Super Game Boy, as far as SGB-enhanced games can tell: Games
+//! send it commands in 16 byte packets over the P1 register, which it uses to color the
+//! screen with its own palettes (see [`SgbFrame`]), surround it with a border and read
+//! the buttons of multiple players.
+//!
+//! Larger amounts of data (palettes, attributes and the border) are transferred by
+//! showing them on the screen, which the Super Game Boy reads back from the LCD output.
+//! So these transfers take the shades of a whole frame (see
+//! [`crate::ppu::PPU::shade_frame`]), not the content of VRAM.
+//!
+//! Only the commands that games use for their colors and borders are implemented. The
+//! others (sound effects, SNES code and the like) are ignored.
+
+mod frame;
+
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::MemPixel;
+
+pub use frame::{SgbFrame, SGB_FRAME_HEIGHT, SGB_FRAME_WIDTH};
+
+/// Size of a VRAM transfer in bytes
+const TRANSFER_SIZE: usize = 4096;
+
+/// The attribute files of ATTR_TRN, each with a palette (2 bits) for every 8x8 area
+const ATTRIBUTE_FILES: usize = 45;
+const ATTRIBUTE_FILE_SIZE: usize = 90;
+
+/// Number of 8x8 areas on the screen (20 x 18)
+const AREAS: usize = 360;
+
+/// The colors of the SGB's default palette (1-A), in the SNES's 15 bit format
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10BD, 0x2866];
+
+/// What the Super Game Boy shows instead of the Game Boy's screen (set via MASK_EN).
+/// Games hide the screen while they transfer data through it.
+#[derive(Copy, Clone)]
+enum Mask {
+    Off,
+    /// Keeps showing the last frame
+    Freeze,
+    Black,
+    /// The screen is filled with color 0
+    Color0,
+}
+
+/// The VRAM transfers that read their data from the next frame
+#[derive(Copy, Clone)]
+enum Transfer {
+    /// PAL_TRN: The 512 system palettes that PAL_SET picks from
+    SystemPalettes,
+    /// CHR_TRN: Half of the border tiles (`true` for tiles 0x80 - 0xFF)
+    BorderTiles(bool),
+    /// PCT_TRN: The tile map and palettes of the border
+    BorderMap,
+    /// ATTR_TRN: The attribute files that ATTR_SET and PAL_SET pick from
+    AttributeFiles,
+}
+
+pub struct Sgb {
+    /// Bits 4 and 5 of the last value that was written to P1
+    p1_lines: u8,
+    /// Whether a packet is being received, i.e. the game sent a reset pulse
+    receiving: bool,
+    packet: [u8; 16],
+    /// Number of bits of `packet` that were received (the stop bit comes after 128)
+    packet_bits: u8,
+    /// The packets of the current command, which can take up to 7 of them
+    command: Vec<u8>,
+    /// Number of players (1, 2 or 4), set via MLT_REQ
+    players: u8,
+    /// The player whose buttons P1 currently reads
+    player: u8,
+    /// The four palettes of the screen. Color 0 is the same in all of them.
+    palettes: [[u16; 4]; 4],
+    /// The palette of every 8x8 area of the screen
+    attributes: [u8; AREAS],
+    /// See [`Transfer::SystemPalettes`]
+    system_palettes: Box<[u8]>,
+    /// See [`Transfer::AttributeFiles`]
+    attribute_files: Box<[u8]>,
+    /// See [`Transfer::BorderTiles`]
+    border_tiles: Box<[u8]>,
+    /// See [`Transfer::BorderMap`]
+    border_map: Box<[u8]>,
+    mask: Mask,
+    /// A transfer that was requested, and the number of frames to wait before it
+    transfer: Option<(Transfer, u8)>,
+    /// The colored screen of the last frame
+    screen: Box<[MemPixel]>,
+    /// The whole picture of the last frame, with the border around `screen`. Not part of
+    /// savestates, since it is drawn again at the end of every frame.
+    frame: Box<[MemPixel]>,
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        Sgb {
+            p1_lines: 0x30,
+            receiving: false,
+            packet: [0; 16],
+            packet_bits: 0,
+            command: Vec::new(),
+            players: 1,
+            player: 0,
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: [0; AREAS],
+            system_palettes: vec![0; TRANSFER_SIZE].into_boxed_slice(),
+            attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE].into_boxed_slice(),
+            border_tiles: vec![0; 2 * TRANSFER_SIZE].into_boxed_slice(),
+            border_map: vec![0; 0x880].into_boxed_slice(),
+            mask: Mask::Off,
+            transfer: None,
+            screen: vec![frame::rgb555(DEFAULT_PALETTE[0]); 160 * 144].into_boxed_slice(),
+            frame: vec![MemPixel::new(0, 0, 0, 0xFF); SGB_FRAME_WIDTH * SGB_FRAME_HEIGHT]
+                .into_boxed_slice(),
+        }
+    }
+
+    /// See [`SgbFrame`]
+    pub fn frame(&self) -> SgbFrame<'_> {
+        let mut palettes = [[MemPixel::new(0, 0, 0, 0xFF); 4]; 4];
+
+        for (colors, palette) in palettes.iter_mut().zip(self.palettes.iter()) {
+            for (color, &rgb) in colors.iter_mut().zip(palette.iter()) {
+                *color = frame::rgb555(rgb);
+            }
+        }
+
+        SgbFrame {
+            frame: &self.frame,
+            screen: &self.screen,
+            attributes: &self.attributes,
+            palettes,
+        }
+    }
+
+    /// Bits are sent by pulling one of the P1 select lines low: P15 for a 1, P14 for a 0.
+    /// Pulling both of them low starts a new packet. With several players, pulling P15
+    /// high again switches to the next player.
+    pub fn write_p1(&mut self, val: u8) {
+        let lines = val & 0x30;
+        let prev = std::mem::replace(&mut self.p1_lines, lines);
+
+        match (prev, lines) {
+            (_, 0x00) => {
+                self.receiving = true;
+                self.packet = [0; 16];
+                self.packet_bits = 0;
+            }
+            (0x30, 0x10) | (0x30, 0x20) if self.receiving => self.receive_bit(lines == 0x10),
+            (0x10, 0x30) if !self.receiving => self.player = (self.player + 1) % self.players,
+            _ => (),
+        }
+    }
+
+    /// With several players, the lower nibble of P1 holds the ID of the current player
+    /// (0xF for the first one, 0xE for the second one, ...) while no buttons are selected.
+    /// Only the buttons of the first player are emulated, the others never press any.
+    pub fn read_p1(&self, p1: u8) -> u8 {
+        if self.players == 1 {
+            p1
+        } else if p1 & 0x30 == 0x30 {
+            (p1 & 0xF0) | (0xF - self.player)
+        } else if self.player != 0 {
+            p1 | 0x0F
+        } else {
+            p1
+        }
+    }
+
+    fn receive_bit(&mut self, bit: bool) {
+        if self.packet_bits == 128 {
+            // The stop bit (always 0)
+            self.receiving = false;
+            self.receive_packet();
+            return;
+        }
+
+        if bit {
+            self.packet[self.packet_bits as usize / 8] |= 1 << (self.packet_bits % 8);
+        }
+
+        self.packet_bits += 1;
+    }
+
+    /// The first byte of a command holds the command code (upper 5 bits) and the number
+    /// of packets it consists of (lower 3 bits)
+    fn receive_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+
+        let packets = (self.command[0] & 0b111).max(1) as usize;
+
+        if self.command.len() >= packets * 16 {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            0x00 => self.set_palettes(0, 1, data),
+            0x01 => self.set_palettes(2, 3, data),
+            0x02 => self.set_palettes(0, 3, data),
+            0x03 => self.set_palettes(1, 2, data),
+            0x04 => self.set_attribute_blocks(data),
+            0x0A => self.set_system_palettes(data),
+            0x0B => self.transfer = Some((Transfer::SystemPalettes, 1)),
+            0x11 => {
+                self.players = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            0x13 => self.transfer = Some((Transfer::BorderTiles(data[1] & 1 != 0), 1)),
+            0x14 => self.transfer = Some((Transfer::BorderMap, 1)),
+            0x15 => self.transfer = Some((Transfer::AttributeFiles, 1)),
+            0x16 => {
+                self.apply_attribute_file(data[1] & 0x3F);
+
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::Off;
+                }
+            }
+            0x17 => {
+                self.mask = match data[1] & 0b11 {
+                    0 => Mask::Off,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                }
+            }
+            command => log::debug!("Ignored SGB command {:#04X}", command),
+        }
+    }
+
+    /// PAL01, PAL23, PAL03 and PAL12: Colors 1 - 3 of two palettes, and color 0 of all
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color = |idx: usize| u16::from_le_bytes([data[1 + idx * 2], data[2 + idx * 2]]);
+
+        for col in 1..4 {
+            self.palettes[first][col] = color(col);
+            self.palettes[second][col] = color(col + 3);
+        }
+
+        self.set_color0(color(0));
+    }
+
+    fn set_color0(&mut self, color: u16) {
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color;
+        }
+    }
+
+    /// ATTR_BLK: Assigns palettes to the inside, the outline and the outside of up to 18
+    /// rectangles of 8x8 areas
+    fn set_attribute_blocks(&mut self, data: &[u8]) {
+        for block in data[2..].chunks_exact(6).take(data[1] as usize) {
+            let control = block[0] & 0b111;
+            let inside = block[1] & 0b11;
+            let outside = (block[1] >> 4) & 0b11;
+            let (x1, y1) = (block[2] as usize & 0x1F, block[3] as usize & 0x1F);
+            let (x2, y2) = (block[4] as usize & 0x1F, block[5] as usize & 0x1F);
+
+            // Changing only the inside or only the outside changes the outline as well
+            let outline = match control {
+                0b001 => Some(inside),
+                0b100 => Some(outside),
+                _ if control & 0b010 != 0 => Some((block[1] >> 2) & 0b11),
+                _ => None,
+            };
+
+            for (area, palette) in self.attributes.iter_mut().enumerate() {
+                let (x, y) = (area % 20, area / 20);
+
+                let new_palette = if x > x1 && x < x2 && y > y1 && y < y2 {
+                    Some(inside).filter(|_| control & 0b001 != 0)
+                } else if x >= x1 && x <= x2 && y >= y1 && y <= y2 {
+                    outline
+                } else {
+                    Some(outside).filter(|_| control & 0b100 != 0)
+                };
+
+                if let Some(new_palette) = new_palette {
+                    *palette = new_palette;
+                }
+            }
+        }
+    }
+
+    /// PAL_SET: Picks the four palettes from the system palettes. Color 0 of the first one
+    /// is used for all of them.
+    fn set_system_palettes(&mut self, data: &[u8]) {
+        for (palette, number) in self.palettes.iter_mut().zip(data[1..9].chunks_exact(2)) {
+            let number = u16::from_le_bytes([number[0], number[1]]) as usize & 0x1FF;
+            let colors = &self.system_palettes[number * 8..number * 8 + 8];
+
+            for (col, color) in palette.iter_mut().zip(colors.chunks_exact(2)) {
+                *col = u16::from_le_bytes([color[0], color[1]]);
+            }
+        }
+
+        self.set_color0(self.palettes[0][0]);
+
+        if data[9] & 0x80 != 0 {
+            self.apply_attribute_file(data[9] & 0x3F);
+        }
+
+        if data[9] & 0x40 != 0 {
+            self.mask = Mask::Off;
+        }
+    }
+
+    /// Attribute files hold the palettes of four 8x8 areas per byte, starting with the
+    /// upper bits
+    fn apply_attribute_file(&mut self, file: u8) {
+        let file = file as usize;
+
+        if file >= ATTRIBUTE_FILES {
+            return;
+        }
+
+        let data = &self.attribute_files[file * ATTRIBUTE_FILE_SIZE..][..ATTRIBUTE_FILE_SIZE];
+
+        for (area, palette) in self.attributes.iter_mut().enumerate() {
+            *palette = (data[area / 4] >> (6 - area % 4 * 2)) & 0b11;
+        }
+    }
+
+    /// To be called at the beginning of VBlank, with the shades of the frame that was just
+    /// finished. Performs a pending VRAM transfer and draws the frame.
+    pub fn notify_vblank(&mut self, shades: &[u8]) {
+        match self.transfer {
+            Some((transfer, 0)) => {
+                self.transfer = None;
+                self.receive_transfer(transfer, &decode_transfer(shades));
+            }
+            Some((transfer, frames_left)) => self.transfer = Some((transfer, frames_left - 1)),
+            None => (),
+        }
+
+        let color0 = frame::rgb555(self.palettes[0][0]);
+
+        match self.mask {
+            Mask::Off => {
+                frame::colorize(shades, &self.attributes, &self.palettes, &mut self.screen)
+            }
+            Mask::Freeze => (),
+            Mask::Black => self.fill_screen(MemPixel::new(0, 0, 0, 0xFF)),
+            Mask::Color0 => self.fill_screen(color0),
+        }
+
+        frame::render_frame(
+            &self.screen,
+            &self.border_tiles,
+            &self.border_map,
+            color0,
+            &mut self.frame,
+        );
+    }
+
+    fn fill_screen(&mut self, color: MemPixel) {
+        for pixel in self.screen.iter_mut() {
+            *pixel = color;
+        }
+    }
+
+    fn receive_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        match transfer {
+            Transfer::SystemPalettes => self.system_palettes.copy_from_slice(data),
+            Transfer::BorderTiles(upper) => {
+                let start = if upper { TRANSFER_SIZE } else { 0 };
+                self.border_tiles[start..start + TRANSFER_SIZE].copy_from_slice(data);
+            }
+            Transfer::BorderMap => {
+                let len = self.border_map.len();
+                self.border_map.copy_from_slice(&data[..len]);
+            }
+            Transfer::AttributeFiles => {
+                let len = self.attribute_files.len();
+                self.attribute_files.copy_from_slice(&data[..len]);
+            }
+        }
+    }
+}
+
+/// Reads the data of a VRAM transfer back from the shades of a frame: The first 256 8x8
+/// tiles of the screen (20 per row) in the Game Boy's tile format, with the shades as
+/// color numbers. Games set BGP to 0xE4 for transfers, so that's what they were in VRAM.
+fn decode_transfer(shades: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; TRANSFER_SIZE];
+
+    for (tile, bytes) in data.chunks_exact_mut(16).enumerate() {
+        let (tile_x, tile_y) = (tile % 20 * 8, tile / 20 * 8);
+
+        for (row, bytes) in bytes.chunks_exact_mut(2).enumerate() {
+            let start = (tile_y + row) * 160 + tile_x;
+
+            for (x, &shade) in shades[start..start + 8].iter().enumerate() {
+                bytes[0] |= (shade & 1) << (7 - x);
+                bytes[1] |= ((shade >> 1) & 1) << (7 - x);
+            }
+        }
+    }
+
+    data
+}
+
+/// The picture of the last frame is not part of the state, since it is drawn again at
+/// the end of every frame. Only the colored screen is kept, for masks that freeze it.
+impl SaveState for Sgb {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.p1_lines);
+        writer.write_bool(self.receiving);
+        writer.write_bytes(&self.packet);
+        writer.write_u8(self.packet_bits);
+        writer.write_u8(self.command.len() as u8);
+        writer.write_bytes(&self.command);
+        writer.write_u8(self.players);
+        writer.write_u8(self.player);
+
+        for palette in self.palettes.iter() {
+            for &color in palette.iter() {
+                writer.write_u16(color);
+            }
+        }
+
+        writer.write_bytes(&self.attributes);
+        writer.write_bytes(&self.system_palettes);
+        writer.write_bytes(&self.attribute_files);
+        writer.write_bytes(&self.border_tiles);
+        writer.write_bytes(&self.border_map);
+        writer.write_u8(self.mask as u8);
+
+        match self.transfer {
+            None => writer.write_u8(0),
+            Some((transfer, frames_left)) => {
+                writer.write_u8(match transfer {
+                    Transfer::SystemPalettes => 1,
+                    Transfer::BorderTiles(false) => 2,
+                    Transfer::BorderTiles(true) => 3,
+                    Transfer::BorderMap => 4,
+                    Transfer::AttributeFiles => 5,
+                });
+                writer.write_u8(frames_left);
+            }
+        }
+
+        for pixel in self.screen.iter() {
+            writer.write_bytes(&[pixel.r, pixel.g, pixel.b, pixel.a]);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.p1_lines = reader.read_u8()? & 0x30;
+        self.receiving = reader.read_bool()?;
+        reader.read_bytes(&mut self.packet)?;
+        self.packet_bits = reader.read_u8()?;
+
+        let command_len = reader.read_u8()? as usize;
+        if self.packet_bits > 128 || command_len > 7 * 16 || !command_len.is_multiple_of(16) {
+            return Err(SaveStateError::InvalidValue);
+        }
+        self.command = vec![0; command_len];
+        reader.read_bytes(&mut self.command)?;
+
+        self.players = reader.read_u8()?;
+        self.player = reader.read_u8()?;
+        if !matches!(self.players, 1 | 2 | 4) || self.player >= self.players {
+            return Err(SaveStateError::InvalidValue);
+        }
+
+        for palette in self.palettes.iter_mut() {
+            for color in palette.iter_mut() {
+                *color = reader.read_u16()?;
+            }
+        }
+
+        reader.read_bytes(&mut self.attributes)?;
+        if self.attributes.iter().any(|&palette| palette > 3) {
+            return Err(SaveStateError::InvalidValue);
+        }
+
+        reader.read_bytes(&mut self.system_palettes)?;
+        reader.read_bytes(&mut self.attribute_files)?;
+        reader.read_bytes(&mut self.border_tiles)?;
+        reader.read_bytes(&mut self.border_map)?;
+
+        self.mask = match reader.read_u8()? {
+            0 => Mask::Off,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => return Err(SaveStateError::InvalidValue),
+        };
+
+        self.transfer = match reader.read_u8()? {
+            0 => None,
+            transfer => {
+                let transfer = match transfer {
+                    1 => Transfer::SystemPalettes,
+                    2 => Transfer::BorderTiles(false),
+                    3 => Transfer::BorderTiles(true),
+                    4 => Transfer::BorderMap,
+                    5 => Transfer::AttributeFiles,
+                    _ => return Err(SaveStateError::InvalidValue),
+                };
+                Some((transfer, reader.read_u8()?))
+            }
+        };
+
+        let mut rgba = [0u8; 4];
+
+        for pixel in self.screen.iter_mut() {
+            reader.read_bytes(&mut rgba)?;
+            *pixel = MemPixel::new(rgba[0], rgba[1], rgba[2], rgba[3]);
+        }
+
+        Ok(())
+    }
+}
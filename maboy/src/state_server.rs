@@ -0,0 +1,72 @@
+//! A tiny HTTP server that lets external dashboards (web UIs, Jupyter notebooks, ...) poll
+//! the emulator state as JSON. Only available with the `http` feature.
+//!
+//! The server never blocks and doesn't run on a thread of its own. Instead, the frontend
+//! calls [`StateServer::poll`] once per frame, which answers all pending requests with a
+//! fresh [`StateSnapshot`]:
+//!
+//! ```text
+//! GET /state   Everything below in one object
+//! GET /cpu     CpuStateSnapshot
+//! GET /ppu     PpuStateSnapshot
+//! GET /timer   TimerState
+//! GET /banks   BankState
+//! ```
+//!
+//! Every response allows cross-origin requests, so a web page from anywhere can poll it.
+//! The server only reads state, so there is nothing to protect, but it should still be
+//! bound to `localhost` unless the whole network is supposed to see it.
+
+use crate::debug::StateSnapshot;
+use crate::http::{self, Access, Response};
+use std::io;
+use std::net::{TcpListener, ToSocketAddrs};
+
+/// Used by the frontends unless another address is passed
+pub const DEFAULT_ADDR: &str = "localhost:8017";
+
+/// See the [module documentation](self)
+pub struct StateServer {
+    listener: TcpListener,
+}
+
+impl StateServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<StateServer> {
+        Ok(StateServer {
+            listener: http::bind(addr, "State server")?,
+        })
+    }
+
+    /// Answers all requests that arrived since the last call. `snapshot` is only called
+    /// if there are any.
+    pub fn poll<F: Fn() -> StateSnapshot>(&mut self, snapshot: F) {
+        http::serve_pending(
+            &self.listener,
+            "State server",
+            Access::AnyOrigin,
+            |request| {
+                if request.method != "GET" {
+                    return Response::error("405 Method Not Allowed", "Only GET is supported");
+                }
+
+                snapshot_response(&snapshot(), &request.path)
+                    .unwrap_or_else(|| Response::error("404 Not Found", "Unknown path"))
+            },
+        );
+    }
+}
+
+/// Answers one of the paths in the module documentation, or returns `None` if `path`
+/// isn't one of them
+pub(crate) fn snapshot_response(state: &StateSnapshot, path: &str) -> Option<Response> {
+    let json = match path {
+        "" | "/state" => serde_json::to_string(state),
+        "/cpu" => serde_json::to_string(&state.cpu),
+        "/ppu" => serde_json::to_string(&state.ppu),
+        "/timer" => serde_json::to_string(&state.timer),
+        "/banks" => serde_json::to_string(&state.banks),
+        _ => return None,
+    };
+
+    Some(Response::from_json(json))
+}
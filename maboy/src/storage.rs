@@ -0,0 +1,585 @@
+//! Importers for savegames created by other emulators, so users can continue their
+//! progress when migrating their library to MaBoy.
+//!
+//! The following formats are detected automatically by [`detect_format`]:
+//!
+//! - Raw cartridge RAM dumps, which is what MaBoy, BGB, VisualBoyAdvance and most
+//!   other emulators store in their `.sav` files.
+//! - Raw cartridge RAM followed by a real-time clock footer. BGB and VBA-M append
+//!   this to the `.sav` files of MBC3 cartridges with a clock (e.g. Pokémon Gold).
+//!   The clock state is imported as well, so no time is lost.
+//! - VisualBoyAdvance savestates (`.sgm`). These are gzip-compressed and contain the
+//!   whole emulator state, out of which only the cartridge RAM is imported.
+//! - MBC2 savegames of older MaBoy versions, which packed two 4-bit values into
+//!   every byte.
+//!
+//! The easiest way to import a savegame is [`import_into`], which writes the imported
+//! data straight into a cartridge.
+//!
+//! Where the files that belong to a ROM are stored is decided by [`SaveNaming`]. By
+//! default, they are named like the ROM file, but they can also be named after the
+//! cartridge header, so renaming or moving ROM files doesn't orphan their saves.
+//!
+//! Before a savegame is overwritten, the old one is kept as a backup with a timestamp
+//! in its name (see [`backup_savegame`]). Only the newest few backups are kept, and any
+//! of them can be restored with [`restore_backup`]. This protects against games
+//! corrupting their own savegames, which is a real risk with cheats or savestates.
+
+use crate::cartridge::{
+    rtc_metadata_from_regs, CartridgeDesc, CartridgeParseError, Metadata, Savegame, MBC2_RAM_LEN,
+};
+use flate2::read::GzDecoder;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// The savegame formats that can be imported
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SavegameFormat {
+    /// Plain cartridge RAM, without anything else
+    Raw,
+    /// Cartridge RAM followed by the BGB/VBA-M real-time clock footer
+    RawWithRtc,
+    /// A gzip-compressed VisualBoyAdvance savestate (`.sgm`)
+    VbaSavestate,
+    /// MBC2 RAM with two 4-bit values per byte (the lower half holds the even address),
+    /// as written by older MaBoy versions
+    PackedMbc2,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    /// The cartridge has neither battery-backed RAM nor a real-time clock, so there
+    /// is nothing to import
+    NothingToImport,
+    /// The data doesn't look like any supported format. This usually means that the
+    /// savegame belongs to a different cartridge, since the format is mostly detected
+    /// via the size of the cartridge RAM.
+    UnrecognizedFormat { len: usize, ram_len: usize },
+    /// The data looks like a VisualBoyAdvance savestate, but could not be decompressed
+    Decompression(io::Error),
+    /// The data is a VisualBoyAdvance savestate, but it doesn't contain cartridge RAM
+    /// of the expected size
+    RamNotFound,
+    /// The imported real-time clock state was rejected by the cartridge
+    InvalidRtc(CartridgeParseError),
+}
+
+/// The result of [`import_savegame`]
+pub struct ImportedSavegame {
+    pub format: SavegameFormat,
+    /// The cartridge RAM (has the length that was passed to [`import_savegame`])
+    pub ram: Vec<u8>,
+    /// RTC state in the format understood by [`Metadata::deserialize_metadata`], if
+    /// the savegame contained any
+    pub rtc_metadata: Option<Vec<u8>>,
+}
+
+/// The first two bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Size of the RTC footer with a 64-bit timestamp (BGB and newer VBA-M versions)
+const RTC_FOOTER_LEN: usize = 48;
+
+/// Size of the RTC footer with a 32-bit timestamp (older VBA-M versions)
+const RTC_FOOTER_LEN_SHORT: usize = 44;
+
+/// Determines the format of `data`, where `ram_len` is the size of the cartridge
+/// RAM of the cartridge that the savegame belongs to. Returns `None` if the format
+/// is not recognized.
+pub fn detect_format(data: &[u8], ram_len: usize) -> Option<SavegameFormat> {
+    // A raw dump could theoretically start with the gzip magic bytes, so we check
+    // the sizes first
+    if data.len() == ram_len {
+        Some(SavegameFormat::Raw)
+    } else if data.len() == ram_len + RTC_FOOTER_LEN || data.len() == ram_len + RTC_FOOTER_LEN_SHORT
+    {
+        Some(SavegameFormat::RawWithRtc)
+    } else if data.starts_with(&GZIP_MAGIC) {
+        Some(SavegameFormat::VbaSavestate)
+    } else if ram_len == MBC2_RAM_LEN && data.len() == MBC2_RAM_LEN / 2 {
+        Some(SavegameFormat::PackedMbc2)
+    } else {
+        None
+    }
+}
+
+/// Extracts cartridge RAM (`ram_len` bytes) and RTC state (if present) from `data`
+pub fn import_savegame(data: &[u8], ram_len: usize) -> Result<ImportedSavegame, ImportError> {
+    let format = detect_format(data, ram_len).ok_or(ImportError::UnrecognizedFormat {
+        len: data.len(),
+        ram_len,
+    })?;
+
+    match format {
+        SavegameFormat::Raw => Ok(ImportedSavegame {
+            format,
+            ram: data.to_vec(),
+            rtc_metadata: None,
+        }),
+        SavegameFormat::RawWithRtc => {
+            let (ram, footer) = data.split_at(ram_len);
+
+            Ok(ImportedSavegame {
+                format,
+                ram: ram.to_vec(),
+                rtc_metadata: Some(parse_rtc_footer(footer)),
+            })
+        }
+        SavegameFormat::VbaSavestate => Ok(ImportedSavegame {
+            format,
+            ram: extract_vba_ram(data, ram_len)?,
+            rtc_metadata: None,
+        }),
+        SavegameFormat::PackedMbc2 => Ok(ImportedSavegame {
+            format,
+            ram: data
+                .iter()
+                .flat_map(|&byte| [byte & 0x0F, byte >> 4])
+                .collect(),
+            rtc_metadata: None,
+        }),
+    }
+}
+
+/// Imports a savegame into the cartridge RAM (and real-time clock, if the cartridge
+/// has one). Returns the detected format.
+///
+/// If the savegame contains RTC state but the cartridge has no RTC, the RTC state is
+/// ignored. This also works the other way around; The RTC is left untouched if there
+/// is no RTC state in the savegame.
+pub fn import_into<C: Savegame + Metadata>(
+    cartridge: &mut C,
+    data: &[u8],
+) -> Result<SavegameFormat, ImportError> {
+    let ram_len = cartridge.savegame().map_or(0, |ram| ram.len());
+
+    if ram_len == 0 && !cartridge.supports_metadata() {
+        return Err(ImportError::NothingToImport);
+    }
+
+    let imported = import_savegame(data, ram_len)?;
+
+    if let Some(ram) = cartridge.savegame_mut() {
+        ram.copy_from_slice(&imported.ram);
+    }
+
+    if let Some(rtc_metadata) = imported.rtc_metadata {
+        if cartridge.supports_metadata() {
+            cartridge
+                .deserialize_metadata(rtc_metadata)
+                .map_err(ImportError::InvalidRtc)?;
+        }
+    }
+
+    Ok(imported.format)
+}
+
+/// The footer consists of the 5 current RTC registers, the 5 latched ones and a unix
+/// timestamp of when it was written. Every value is a little-endian u32, except for the
+/// timestamp, which can also be a u64.
+fn parse_rtc_footer(footer: &[u8]) -> Vec<u8> {
+    let u32_at = |idx: usize| u32::from_le_bytes(footer[idx * 4..idx * 4 + 4].try_into().unwrap());
+
+    let mut regs = [0u8; 5];
+    let mut latched = [0u8; 5];
+    for idx in 0..5 {
+        regs[idx] = u32_at(idx) as u8;
+        latched[idx] = u32_at(idx + 5) as u8;
+    }
+
+    // The upper half of the 64-bit timestamp is zero for the next few decades, so
+    // the lower half is all we need in both cases
+    let timestamp = Duration::from_secs(u32_at(10) as u64);
+
+    rtc_metadata_from_regs(SystemTime::UNIX_EPOCH + timestamp, regs, Some(latched))
+}
+
+/// VBA savestates start with the savestate version (a u32), the title from the
+/// cartridge header (15 bytes) and two u32 flags concerning the boot ROM.
+const VBA_HEADER_LEN: usize = 4 + 15 + 4 + 4;
+
+/// Oldest and newest VBA savestate version that we know of. Anything outside of this
+/// range is probably not a VBA savestate at all.
+const VBA_VERSIONS: std::ops::RangeInclusive<u32> = 1..=20;
+
+/// Cartridge RAM is stored after the whole 32KB of memory starting at 0x8000,
+/// prefixed by its length as a u32. Everything in between is a dump of VBA-internal
+/// structs whose size depends on the VBA version (and platform!), so we can't
+/// calculate the exact position of the RAM. Instead, we look for the first length
+/// prefix that matches the RAM size after the point where the RAM can start at the
+/// earliest.
+fn extract_vba_ram(data: &[u8], ram_len: usize) -> Result<Vec<u8>, ImportError> {
+    let mut state = Vec::new();
+    GzDecoder::new(data)
+        .read_to_end(&mut state)
+        .map_err(ImportError::Decompression)?;
+
+    if state.len() < VBA_HEADER_LEN {
+        return Err(ImportError::UnrecognizedFormat {
+            len: data.len(),
+            ram_len,
+        });
+    }
+
+    let version = u32::from_le_bytes(state[..4].try_into().unwrap());
+    if !VBA_VERSIONS.contains(&version) {
+        return Err(ImportError::UnrecognizedFormat {
+            len: data.len(),
+            ram_len,
+        });
+    }
+
+    if ram_len == 0 {
+        return Ok(Vec::new());
+    }
+
+    let prefix = (ram_len as u32).to_le_bytes();
+    let earliest = VBA_HEADER_LEN + 0x8000;
+
+    (earliest..state.len().saturating_sub(4 + ram_len - 1))
+        .find(|&pos| state[pos..pos + 4] == prefix)
+        .map(|pos| state[pos + 4..pos + 4 + ram_len].to_vec())
+        .ok_or(ImportError::RamNotFound)
+}
+
+/// How the files that belong to a ROM (savegame, metadata, savestates) are named. They
+/// are always stored next to the ROM, and only differ in their extension.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SaveNaming {
+    /// The name of the ROM file, e.g. `Pokemon Red (UE).sav`
+    #[default]
+    RomFileName,
+    /// The title and global checksum from the cartridge header, e.g.
+    /// `POKEMON RED-91E6.sav`. Survives renaming the ROM file, and two ROMs with the
+    /// same title (like different revisions) still get their own saves.
+    HeaderTitle,
+}
+
+#[derive(Debug)]
+pub struct ParseSaveNamingError(pub String);
+
+impl SaveNaming {
+    pub fn name(self) -> &'static str {
+        match self {
+            SaveNaming::RomFileName => "rom",
+            SaveNaming::HeaderTitle => "title",
+        }
+    }
+}
+
+impl fmt::Display for SaveNaming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SaveNaming {
+    type Err = ParseSaveNamingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "rom" => Ok(SaveNaming::RomFileName),
+            "title" => Ok(SaveNaming::HeaderTitle),
+            _ => Err(ParseSaveNamingError(s.to_owned())),
+        }
+    }
+}
+
+/// The extensions of all files that are stored per ROM, see [`crate::frontend`]
+pub const SAVE_EXTENSIONS: [&str; 4] = ["sav", "sgm", "meta", "state"];
+
+/// The extensions of ROMs inside a gzip archive, e.g. `tetris.gb.gz`
+const GZIPPED_ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "sgb"];
+
+/// Where the files that belong to a ROM are stored, without an extension (see
+/// [`save_file_path`]). `rom` is the complete ROM (see [`crate::Cartridge::rom`]),
+/// which is only looked at for [`SaveNaming::HeaderTitle`].
+pub fn save_base_path(rom_path: &Path, rom: &[u8], naming: SaveNaming) -> PathBuf {
+    match naming {
+        // Gzipped ROMs usually have two extensions (`tetris.gb.gz`), but other dots in
+        // the name have to stay (`tetris.v1.1.gz`)
+        SaveNaming::RomFileName if has_extension(rom_path, "gz") => {
+            let inner = rom_path.with_extension("");
+
+            if GZIPPED_ROM_EXTENSIONS
+                .iter()
+                .any(|ext| has_extension(&inner, ext))
+            {
+                inner.with_extension("")
+            } else {
+                inner
+            }
+        }
+        SaveNaming::RomFileName => rom_path.with_extension(""),
+        SaveNaming::HeaderTitle => rom_path.with_file_name(header_save_name(rom)),
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Appends an extension to a path returned by [`save_base_path`]. Unlike
+/// `Path::with_extension`, this keeps everything after other dots in the file name.
+pub fn save_file_path(base_path: &Path, extension: &str) -> PathBuf {
+    let mut path = base_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// `<title>-<global checksum>`, with every character that might cause trouble in a file
+/// name replaced by an underscore. Dots are replaced as well, since they would be
+/// mistaken for an extension.
+pub fn header_save_name(rom: &[u8]) -> String {
+    let header = CartridgeDesc::from_header(&rom[0x100..=0x14F]);
+
+    let title: String = header
+        .title()
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let title = if title.is_empty() { "UNTITLED" } else { &title };
+
+    format!("{}-{:04X}", title, header.global_checksum())
+}
+
+/// Renames the files that were stored with the ROM's file name so they match
+/// `naming`, e.g. when a frontend switches to [`SaveNaming::HeaderTitle`]. Files are
+/// never overwritten; If a file already exists under the new name, the old one is
+/// left alone. Returns the files that were renamed (old and new path).
+pub fn migrate_saves(
+    rom_path: &Path,
+    rom: &[u8],
+    naming: SaveNaming,
+) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let old_base = save_base_path(rom_path, rom, SaveNaming::RomFileName);
+    let new_base = save_base_path(rom_path, rom, naming);
+
+    let mut renamed = Vec::new();
+
+    if old_base == new_base {
+        return Ok(renamed);
+    }
+
+    for extension in SAVE_EXTENSIONS.iter() {
+        let old_path = save_file_path(&old_base, extension);
+        let new_path = save_file_path(&new_base, extension);
+
+        if !old_path.is_file() {
+            continue;
+        }
+
+        if new_path.exists() {
+            log::warn!(
+                "Not migrating {:?}, since {:?} already exists",
+                old_path,
+                new_path
+            );
+            continue;
+        }
+
+        fs::rename(&old_path, &new_path)?;
+        log::info!("Migrated {:?} to {:?}", old_path, new_path);
+
+        renamed.push((old_path, new_path));
+    }
+
+    Ok(renamed)
+}
+
+/// How many backups of a savegame [`crate::frontend::store_savegame`] keeps
+pub const DEFAULT_SAVEGAME_BACKUPS: usize = 5;
+
+/// A copy of a savegame that was made right before it was overwritten (see
+/// [`backup_savegame`])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavegameBackup {
+    pub path: PathBuf,
+    /// When the backup was made, in UTC (`YYYYMMDD-HHMMSS`)
+    pub timestamp: String,
+}
+
+/// The prefix and suffix of the backups of a savegame, e.g. `Tetris.sav.` and `.bak`
+/// for `Tetris.sav.20240131-235959.bak`
+fn backup_name_parts(base_path: &Path) -> (String, &'static str) {
+    let sav_path = save_file_path(base_path, "sav");
+    let sav_name = sav_path.file_name().unwrap_or_default().to_string_lossy();
+
+    (format!("{}.", sav_name), ".bak")
+}
+
+/// Copies the current `.sav` file (if there is one) to a backup named after the current
+/// time, and deletes the oldest backups so at most `keep` remain. With `keep == 0`, no
+/// backup is made, but old ones are still deleted. Returns the path of the new backup.
+///
+/// There is at most one backup per second; If the savegame is backed up again within the
+/// same second, the earlier (older) backup is kept.
+pub fn backup_savegame(base_path: &Path, keep: usize) -> io::Result<Option<PathBuf>> {
+    let sav_path = save_file_path(base_path, "sav");
+    let mut backup_path = None;
+
+    if keep > 0 && sav_path.is_file() {
+        let (prefix, suffix) = backup_name_parts(base_path);
+        let name = format!("{}{}{}", prefix, utc_timestamp(SystemTime::now()), suffix);
+        let path = sav_path.with_file_name(name);
+
+        if !path.exists() {
+            fs::copy(&sav_path, &path)?;
+            log::info!("Backed up savegame to {:?}", path);
+        }
+
+        backup_path = Some(path);
+    }
+
+    for old_backup in list_backups(base_path)?.iter().skip(keep) {
+        fs::remove_file(&old_backup.path)?;
+        log::info!("Deleted old savegame backup {:?}", old_backup.path);
+    }
+
+    Ok(backup_path)
+}
+
+/// All backups of the savegame, the newest first
+pub fn list_backups(base_path: &Path) -> io::Result<Vec<SavegameBackup>> {
+    let sav_path = save_file_path(base_path, "sav");
+    let (prefix, suffix) = backup_name_parts(base_path);
+
+    let dir = match sav_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut backups = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        let timestamp = name
+            .strip_prefix(prefix.as_str())
+            .and_then(|rest| rest.strip_suffix(suffix));
+
+        if let Some(timestamp) = timestamp {
+            backups.push(SavegameBackup {
+                timestamp: timestamp.to_owned(),
+                path,
+            });
+        }
+    }
+
+    // The timestamps sort chronologically
+    backups.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+
+    Ok(backups)
+}
+
+/// Replaces the `.sav` file with a backup. The current savegame is backed up first
+/// (keeping up to `keep` backups, see [`backup_savegame`]), so restoring can be undone.
+///
+/// This only changes the file; The savegame has to be loaded again (usually by
+/// restarting the game) to take effect, and must not be overwritten by the running game
+/// before that.
+pub fn restore_backup(base_path: &Path, backup: &SavegameBackup, keep: usize) -> io::Result<()> {
+    // Read first, since the backup might be deleted by the rotation
+    let data = fs::read(&backup.path)?;
+
+    backup_savegame(base_path, keep)?;
+    fs::write(save_file_path(base_path, "sav"), data)?;
+
+    log::info!("Restored savegame from {:?}", backup.path);
+    Ok(())
+}
+
+/// Where a screenshot taken at the given time is stored: `<name>.YYYYMMDD-HHMMSS.png`
+/// (in UTC), next to the savegame. If that already exists (more than one screenshot per
+/// second), a number is appended.
+pub fn screenshot_path(base_path: &Path, time: SystemTime) -> PathBuf {
+    let timestamp = utc_timestamp(time);
+    let mut path = save_file_path(base_path, &format!("{}.png", timestamp));
+    let mut n = 2;
+
+    while path.exists() {
+        path = save_file_path(base_path, &format!("{}-{}.png", timestamp, n));
+        n += 1;
+    }
+
+    path
+}
+
+/// `YYYYMMDD-HHMMSS` in UTC
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Converts days since 1970-01-01 to a date in the proleptic Gregorian calendar
+    // (see Howard Hinnant's `civil_from_days`)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base_name(rom_path: &str) -> PathBuf {
+        save_base_path(Path::new(rom_path), &[], SaveNaming::RomFileName)
+    }
+
+    #[test]
+    fn save_base_path_strips_rom_extensions() {
+        assert_eq!(base_name("roms/tetris.gb"), Path::new("roms/tetris"));
+        assert_eq!(base_name("roms/tetris.zip"), Path::new("roms/tetris"));
+        assert_eq!(base_name("roms/tetris.gb.gz"), Path::new("roms/tetris"));
+        assert_eq!(base_name("roms/tetris.GBC.gz"), Path::new("roms/tetris"));
+    }
+
+    #[test]
+    fn save_base_path_keeps_other_dots_in_gzipped_names() {
+        assert_eq!(base_name("roms/tetris.gz"), Path::new("roms/tetris"));
+        assert_eq!(
+            base_name("roms/tetris.v1.1.gz"),
+            Path::new("roms/tetris.v1.1")
+        );
+        assert_eq!(
+            base_name("roms/tetris.v1.1.gb.gz"),
+            Path::new("roms/tetris.v1.1")
+        );
+    }
+}
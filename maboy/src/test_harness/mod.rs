@@ -0,0 +1,225 @@
+//! Runs test ROMs headlessly and detects whether they passed or failed. This is
+//! meant for checking the accuracy of the emulator against test suites like the
+//! ones by Blargg, either for single ROMs via [`run_test_rom`] or for whole
+//! directories of them via [`TestSuite`].
+//!
+//! Test ROMs report their results in different ways (see [`TestProtocol`]), all of
+//! which are watched at the same time:
+//!
+//! - Everything they "print" to the serial port is sent over the link cable, where
+//!   we record it and look for "Passed" or "Failed".
+//! - Blargg's ROMs also write a result code and text to cartridge RAM.
+//! - Mooneye's ROMs execute `LD B,B` with magic values in the CPU registers.
+//!
+//! Some parts of the emulator can be checked without any ROMs at all, by running tiny
+//! programs that are generated on the fly with a [`RomBuilder`], like the self-test
+//! ([`crate::Emulator::self_test`]).
+
+mod protocol;
+mod rom_builder;
+mod self_test;
+mod suite;
+
+use crate::debug::NoDbgLogger;
+use crate::{
+    CartridgeParseError, CartridgeVariant, Emulator, SerialEcho, StepOrder, MCYCLES_PER_FRAME,
+};
+use protocol::MooneyeBreakpoint;
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub use protocol::TestProtocol;
+pub use rom_builder::RomBuilder;
+pub use self_test::{run_self_test, SelfTestArea, SelfTestReport, SelfTestResult};
+pub use suite::{Regression, SuiteResult, SuiteResults, TestSuite};
+
+/// Identifies the emulator build (git commit + hash of all source files). Cached
+/// test results are only valid for the build that produced them.
+pub const BUILD_ID: &str = env!("MABOY_BUILD_ID");
+
+/// The result of running a single test ROM
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    /// The ROM didn't report a result before the machine cycle limit was reached
+    CycleLimit,
+    /// The ROM didn't report a result before the (wall-clock) timeout. Unlike all
+    /// other outcomes, this depends on the speed of the machine, so it is never cached.
+    Timeout,
+    /// The CPU got stuck on an illegal instruction
+    Crashed,
+    /// The ROM could not be loaded
+    InvalidRom,
+}
+
+impl TestOutcome {
+    /// Whether the outcome only depends on the emulator and the ROM (and thus can be
+    /// cached)
+    pub fn is_deterministic(self) -> bool {
+        self != TestOutcome::Timeout
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            TestOutcome::Passed => "passed",
+            TestOutcome::Failed => "failed",
+            TestOutcome::CycleLimit => "cycle-limit",
+            TestOutcome::Timeout => "timeout",
+            TestOutcome::Crashed => "crashed",
+            TestOutcome::InvalidRom => "invalid-rom",
+        }
+    }
+
+    /// Inverse of the [`fmt::Display`] implementation
+    fn parse(s: &str) -> Option<TestOutcome> {
+        [
+            TestOutcome::Passed,
+            TestOutcome::Failed,
+            TestOutcome::CycleLimit,
+            TestOutcome::Timeout,
+            TestOutcome::Crashed,
+            TestOutcome::InvalidRom,
+        ]
+        .iter()
+        .copied()
+        .find(|outcome| outcome.as_str() == s)
+    }
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Limits for running a single test ROM
+#[derive(Debug, Copy, Clone)]
+pub struct TestConfig {
+    /// Machine cycles after which the test is aborted with [`TestOutcome::CycleLimit`]
+    pub max_mcycles: u64,
+    /// Wall-clock time after which the test is aborted with [`TestOutcome::Timeout`]
+    pub timeout: Duration,
+    /// Order of the components within a machine cycle. Only changed to find out which
+    /// ROMs depend on it (see the `cycle_skew` example).
+    pub step_order: StepOrder,
+}
+
+impl Default for TestConfig {
+    /// One minute of emulated time, but at most 30 seconds of real time
+    fn default() -> Self {
+        TestConfig {
+            max_mcycles: 60 * 1_048_576,
+            timeout: Duration::from_secs(30),
+            step_order: StepOrder::DEFAULT,
+        }
+    }
+}
+
+/// Everything we know about a test run
+#[derive(Debug, Clone)]
+pub struct TestReport {
+    pub outcome: TestOutcome,
+    /// How the ROM reported the outcome. `None` if it didn't report anything.
+    pub protocol: Option<TestProtocol>,
+    /// Everything the ROM sent over the serial port
+    pub serial_output: String,
+    /// The text that the ROM wrote to cartridge RAM (see [`TestProtocol::BlarggMemory`])
+    pub memory_output: String,
+    /// Machine cycles it took until the outcome was determined
+    pub mcycles: u64,
+}
+
+/// Runs a test ROM from disk. See [`run_test_rom`].
+pub fn run_test_rom_file<P: AsRef<Path>>(path: P, config: &TestConfig) -> TestReport {
+    match CartridgeVariant::from_file(path) {
+        Ok(cartridge) => run_cartridge(cartridge, config),
+        Err(err) => invalid_rom(err),
+    }
+}
+
+/// Runs a test ROM until it reports a result or one of the limits in `config` is hit
+pub fn run_test_rom(rom: Vec<u8>, config: &TestConfig) -> TestReport {
+    match CartridgeVariant::from_bytes(rom) {
+        Ok(cartridge) => run_cartridge(cartridge, config),
+        Err(err) => invalid_rom(err),
+    }
+}
+
+fn invalid_rom(err: CartridgeParseError) -> TestReport {
+    log::warn!("Could not load test ROM: {:?}", err);
+
+    TestReport {
+        outcome: TestOutcome::InvalidRom,
+        protocol: None,
+        serial_output: String::new(),
+        memory_output: String::new(),
+        mcycles: 0,
+    }
+}
+
+fn run_cartridge(cartridge: CartridgeVariant, config: &TestConfig) -> TestReport {
+    let mut emu = Emulator::with_debugger(cartridge, MooneyeBreakpoint::default(), NoDbgLogger);
+    emu.set_step_order(config.step_order);
+
+    // Test ROMs might depend on the timing of transfers
+    let mut recorder = SerialEcho::new();
+    recorder.set_transfer_mcycles(None);
+    let output = recorder.output();
+    emu.connect_link_cable(recorder);
+
+    let started = Instant::now();
+    let mut next_check = 0;
+
+    let (outcome, protocol) = loop {
+        emu.emulate_step();
+
+        // The registers have to be checked right at the breakpoint, since the ROM might
+        // change them afterwards
+        if emu.board.cpu_evt_src.hit {
+            emu.board.cpu_evt_src.hit = false;
+
+            if let Some(outcome) = protocol::outcome_from_registers(&emu.cpu.reg) {
+                break (outcome, Some(TestProtocol::MooneyeRegisters));
+            }
+        }
+
+        // Checking everything else once per frame is plenty
+        if emu.mcycles() < next_check {
+            continue;
+        }
+
+        next_check = emu.mcycles() + MCYCLES_PER_FRAME;
+
+        if let Some(outcome) = protocol::outcome_from_serial(&output.lock().unwrap()) {
+            break (outcome, Some(TestProtocol::Serial));
+        }
+
+        if let Some(outcome) = protocol::outcome_from_memory(&emu.board) {
+            break (outcome, Some(TestProtocol::BlarggMemory));
+        }
+
+        if emu.is_stuck() {
+            break (TestOutcome::Crashed, None);
+        }
+
+        if emu.mcycles() >= config.max_mcycles {
+            break (TestOutcome::CycleLimit, None);
+        }
+
+        if started.elapsed() >= config.timeout {
+            break (TestOutcome::Timeout, None);
+        }
+    };
+
+    let serial_output = String::from_utf8_lossy(&output.lock().unwrap()).into_owned();
+
+    TestReport {
+        outcome,
+        protocol,
+        serial_output,
+        memory_output: protocol::text_from_memory(&emu.board),
+        mcycles: emu.mcycles(),
+    }
+}
@@ -0,0 +1,115 @@
+//! The different ways in which test ROMs report their results. Blargg's ROMs print
+//! their results to the serial port and (some of them) to cartridge RAM, while the
+//! Mooneye test suite uses a magic breakpoint instruction and register values.
+
+use super::TestOutcome;
+use crate::address::Addr;
+use crate::board::Board;
+use crate::cpu::{ByteInstr, Registers, R8};
+use crate::debug::{CpuEvt, DbgEvtSrc};
+use std::fmt;
+
+/// How a test ROM reported its result
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TestProtocol {
+    /// "Passed" or "Failed" was printed to the serial port
+    Serial,
+    /// A result code was written to cartridge RAM at 0xA000, marked by the signature
+    /// `DE B0 61` at 0xA001 (used by Blargg's ROMs that don't fit on a single screen)
+    BlarggMemory,
+    /// `LD B,B` was executed with the Fibonacci numbers 3/5/8/13/21/34 (passed) or
+    /// 0x42 (failed) in B/C/D/E/H/L (used by the Mooneye test suite)
+    MooneyeRegisters,
+}
+
+impl fmt::Display for TestProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TestProtocol::Serial => "serial",
+            TestProtocol::BlarggMemory => "blargg-memory",
+            TestProtocol::MooneyeRegisters => "mooneye-registers",
+        })
+    }
+}
+
+/// Blargg's test ROMs print "Passed" or "Failed" once they are done
+pub(super) fn outcome_from_serial(output: &[u8]) -> Option<TestOutcome> {
+    let contains = |needle: &[u8]| output.windows(needle.len()).any(|w| w == needle);
+
+    if contains(b"Passed") {
+        Some(TestOutcome::Passed)
+    } else if contains(b"Failed") {
+        Some(TestOutcome::Failed)
+    } else {
+        None
+    }
+}
+
+const BLARGG_STATUS: u16 = 0xA000;
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_TEXT: u16 = 0xA004;
+
+/// While the test is running, the status byte is 0x80. 0x81 means that the ROM wants
+/// to be reset, which we don't support (and no single-ROM test needs). Any other value
+/// is the final result code, with 0 meaning that all tests passed.
+pub(super) fn outcome_from_memory<B: Board>(board: &B) -> Option<TestOutcome> {
+    let signature = [
+        read(board, BLARGG_STATUS + 1),
+        read(board, BLARGG_STATUS + 2),
+        read(board, BLARGG_STATUS + 3),
+    ];
+
+    if signature != BLARGG_SIGNATURE {
+        return None;
+    }
+
+    match read(board, BLARGG_STATUS) {
+        0x80 | 0x81 => None,
+        0x00 => Some(TestOutcome::Passed),
+        _ => Some(TestOutcome::Failed),
+    }
+}
+
+/// The zero-terminated text that Blargg's ROMs write to cartridge RAM alongside the
+/// result code. Empty if there is no signature.
+pub(super) fn text_from_memory<B: Board>(board: &B) -> String {
+    if outcome_from_memory(board).is_none() {
+        return String::new();
+    }
+
+    let text: Vec<u8> = (BLARGG_TEXT..0xC000)
+        .map(|addr| read(board, addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+fn read<B: Board>(board: &B, addr: u16) -> u8 {
+    board.read8_instant(Addr::from(addr))
+}
+
+/// Only meaningful right after [`MooneyeBreakpoint`] fired
+pub(super) fn outcome_from_registers(reg: &Registers) -> Option<TestOutcome> {
+    let values = [R8::B, R8::C, R8::D, R8::E, R8::H, R8::L].map(|r| reg.get_r8(r));
+
+    match values {
+        [3, 5, 8, 13, 21, 34] => Some(TestOutcome::Passed),
+        [0x42, 0x42, 0x42, 0x42, 0x42, 0x42] => Some(TestOutcome::Failed),
+        _ => None,
+    }
+}
+
+/// Watches the executed instructions for the `LD B,B` breakpoint of the Mooneye suite
+#[derive(Default)]
+pub(super) struct MooneyeBreakpoint {
+    pub hit: bool,
+}
+
+impl DbgEvtSrc<CpuEvt> for MooneyeBreakpoint {
+    fn push(&mut self, evt: CpuEvt) {
+        if let CpuEvt::Exec(_, ByteInstr::LD_B_B) = evt {
+            self.hit = true;
+        }
+    }
+}
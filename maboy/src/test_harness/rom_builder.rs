@@ -0,0 +1,101 @@
+//! See [`RomBuilder`]
+
+/// Where the entry point at 0x100 jumps to, right after the cartridge header
+const CODE_START: usize = 0x150;
+
+const BANK_SIZE: usize = 0x4000;
+
+/// Builds small ROM images from raw machine code, e.g. for synthetic tests that don't
+/// need a whole test ROM. The header is filled in with a valid checksum, so the ROMs can
+/// be loaded like any other (see [`crate::CartridgeVariant::from_rom`]).
+///
+/// The ROMs don't contain the Nintendo logo, so the boot ROM would lock up on them. Run
+/// them without a boot ROM (see [`crate::Emulator::with_boot_rom`]).
+///
+/// ```
+/// # use maboy::test_harness::RomBuilder;
+/// let mut builder = RomBuilder::new();
+///
+/// // LD A,$42; LD ($C000),A
+/// builder.code(&[0x3E, 0x42, 0xEA, 0x00, 0xC0]);
+/// let done = builder.code_addr();
+/// // JR -2
+/// builder.code(&[0x18, 0xFE]);
+///
+/// let rom = builder.build();
+/// assert_eq!(rom.len(), 0x8000);
+/// assert_eq!(done, 0x155);
+/// ```
+pub struct RomBuilder {
+    rom: Vec<u8>,
+    /// ROM offset of the next byte that [`RomBuilder::code`] appends
+    code_pos: usize,
+}
+
+impl RomBuilder {
+    /// A 32 KiB ROM without MBC
+    pub fn new() -> RomBuilder {
+        RomBuilder::with_mbc(0x00, 2, 0x00)
+    }
+
+    /// A ROM with the given cartridge type (header byte 0x147), number of 16 KiB banks
+    /// (a power of two, at least 2) and RAM size code (header byte 0x149)
+    pub fn with_mbc(cartridge_type: u8, rom_banks: usize, ram_size: u8) -> RomBuilder {
+        assert!(
+            rom_banks >= 2 && rom_banks.is_power_of_two(),
+            "Invalid number of ROM banks"
+        );
+
+        let mut rom = vec![0; rom_banks * BANK_SIZE];
+
+        // Entry point: NOP; JP $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, CODE_START as u8, 0x01]);
+
+        rom[0x134..0x13C].copy_from_slice(b"MABOYGEN");
+        rom[0x147] = cartridge_type;
+        rom[0x148] = rom_banks.trailing_zeros() as u8 - 1;
+        rom[0x149] = ram_size;
+
+        RomBuilder {
+            rom,
+            code_pos: CODE_START,
+        }
+    }
+
+    /// Appends machine code to the program, which starts right after the header
+    pub fn code(&mut self, bytes: &[u8]) -> &mut Self {
+        self.bytes_at(self.code_pos, bytes);
+        self.code_pos += bytes.len();
+        self
+    }
+
+    /// The address of the next byte that [`RomBuilder::code`] appends, e.g. to jump
+    /// there later
+    pub fn code_addr(&self) -> u16 {
+        self.code_pos as u16
+    }
+
+    /// Writes bytes at a ROM offset (not an address, so banks other than the first two
+    /// can be reached too), e.g. interrupt handlers or data
+    pub fn bytes_at(&mut self, offset: usize, bytes: &[u8]) -> &mut Self {
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    /// Finishes the ROM by calculating the header checksum
+    pub fn build(&self) -> Vec<u8> {
+        let mut rom = self.rom.clone();
+
+        rom[0x14D] = rom[0x134..=0x14C]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+
+        rom
+    }
+}
+
+impl Default for RomBuilder {
+    fn default() -> Self {
+        RomBuilder::new()
+    }
+}
@@ -0,0 +1,681 @@
+//! A battery of tiny synthetic test programs (built with [`RomBuilder`]) that check
+//! some of the timing-sensitive parts of the emulator without any external test ROMs.
+//! See [`crate::Emulator::self_test`].
+//!
+//! Every program writes its results to WRAM at 0xC000 and then loops forever at a
+//! known address, at which point the results are compared with what the hardware would
+//! have written.
+
+use super::RomBuilder;
+use crate::{CartridgeVariant, ClockRatio, Emulator, StepOrder, MCYCLES_PER_FRAME};
+use std::fmt;
+
+/// Where the programs write their results
+const RESULT_ADDR: u16 = 0xC000;
+
+/// Every program is done after a few frames. If it isn't, it is stuck somewhere.
+const MAX_MCYCLES: u64 = 10 * MCYCLES_PER_FRAME;
+
+/// The part of the system that a self-test checks
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SelfTestArea {
+    Timer,
+    OamDma,
+    Interrupts,
+    Mbc,
+}
+
+impl fmt::Display for SelfTestArea {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SelfTestArea::Timer => "Timer",
+            SelfTestArea::OamDma => "OAM DMA",
+            SelfTestArea::Interrupts => "Interrupts",
+            SelfTestArea::Mbc => "MBC",
+        })
+    }
+}
+
+/// The outcome of a single self-test
+#[derive(Debug, Clone)]
+pub struct SelfTestResult {
+    pub area: SelfTestArea,
+    pub name: &'static str,
+    /// The bytes that the program should have written
+    pub expected: Vec<u8>,
+    /// The bytes that the program actually wrote, or `None` if it didn't finish
+    pub actual: Option<Vec<u8>>,
+}
+
+impl SelfTestResult {
+    pub fn passed(&self) -> bool {
+        self.actual.as_ref() == Some(&self.expected)
+    }
+}
+
+impl fmt::Display for SelfTestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.passed() { "PASS" } else { "FAIL" };
+        write!(f, "{} {}: {}", status, self.area, self.name)?;
+
+        match &self.actual {
+            _ if self.passed() => Ok(()),
+            Some(actual) => write!(f, " (expected {:02X?}, got {:02X?})", self.expected, actual),
+            None => write!(f, " (didn't finish)"),
+        }
+    }
+}
+
+/// The result of [`crate::Emulator::self_test`]
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    pub results: Vec<SelfTestResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(SelfTestResult::passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &SelfTestResult> {
+        self.results.iter().filter(|result| !result.passed())
+    }
+}
+
+/// One line per test and a summary at the end
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            writeln!(f, "{}", result)?;
+        }
+
+        let passed = self.results.iter().filter(|result| result.passed()).count();
+        write!(f, "{}/{} self-tests passed", passed, self.results.len())
+    }
+}
+
+struct SelfTest {
+    area: SelfTestArea,
+    name: &'static str,
+    program: fn() -> RomBuilder,
+    expected: &'static [u8],
+}
+
+const SELF_TESTS: [SelfTest; 16] = [
+    SelfTest {
+        area: SelfTestArea::Timer,
+        name: "TIMA counts at all four frequencies",
+        program: timer_frequencies,
+        expected: &[10, 10, 10, 10],
+    },
+    SelfTest {
+        area: SelfTestArea::Timer,
+        name: "TIMA overflow reloads TMA and requests an interrupt",
+        program: timer_overflow,
+        expected: &[0x04, 0xFF],
+    },
+    SelfTest {
+        area: SelfTestArea::Timer,
+        name: "Writing DIV resets it",
+        program: div_reset,
+        expected: &[0, 1],
+    },
+    SelfTest {
+        area: SelfTestArea::OamDma,
+        name: "OAM is locked during OAM DMA, which copies 160 bytes",
+        program: oam_dma,
+        expected: &[0xFF, 0x20, 0xBF],
+    },
+    SelfTest {
+        area: SelfTestArea::Interrupts,
+        name: "EI takes effect after the next instruction",
+        program: ei_delay,
+        expected: &[0x01, 0x50, 0x02],
+    },
+    SelfTest {
+        area: SelfTestArea::Interrupts,
+        name: "Interrupts are dispatched by priority, RETI enables them immediately",
+        program: interrupt_priority,
+        expected: &[0x40, 0x50, 0x03],
+    },
+    SelfTest {
+        area: SelfTestArea::Interrupts,
+        name: "HALT without IME (including the HALT bug)",
+        program: halt_without_ime,
+        expected: &[0x02, 0x04],
+    },
+    SelfTest {
+        area: SelfTestArea::Mbc,
+        name: "MBC1 ROM banking (bank 0 selects bank 1)",
+        program: mbc1_banking,
+        expected: &[0xB1, 0xB2, 0xB3, 0xB1],
+    },
+    SelfTest {
+        area: SelfTestArea::Mbc,
+        name: "MBC5 ROM banking (bank 0 is selectable)",
+        program: mbc5_banking,
+        expected: &[0xB1, 0xB2, 0xB3, 0xB0],
+    },
+    SelfTest {
+        area: SelfTestArea::Mbc,
+        name: "MBC1 RAM is only accessible while enabled",
+        program: mbc1_ram_enable,
+        expected: &[0xFF, 0x42],
+    },
+    SelfTest {
+        area: SelfTestArea::Mbc,
+        name: "MBC1 RAM enable only looks at the lower nibble",
+        program: mbc1_ram_enable_gate,
+        expected: &[0xF5, 0xFF, 0xF5],
+    },
+    SelfTest {
+        area: SelfTestArea::Mbc,
+        name: "MBC2 RAM enable only looks at the lower nibble",
+        program: mbc2_ram_enable_gate,
+        expected: &[0xF5, 0xFF, 0xF5],
+    },
+    SelfTest {
+        area: SelfTestArea::Mbc,
+        name: "MBC3 RAM enable only looks at the lower nibble",
+        program: mbc3_ram_enable_gate,
+        expected: &[0xF5, 0xFF, 0xF5],
+    },
+    SelfTest {
+        area: SelfTestArea::Mbc,
+        name: "MBC5 RAM enable only looks at the lower nibble",
+        program: mbc5_ram_enable_gate,
+        expected: &[0xF5, 0xFF, 0xF5],
+    },
+    SelfTest {
+        area: SelfTestArea::Mbc,
+        name: "HuC1 RAM is accessible unless the IR port is mapped",
+        program: huc1_ir_select,
+        expected: &[0xC0, 0xF5],
+    },
+    SelfTest {
+        area: SelfTestArea::Mbc,
+        name: "HuC3 RAM is read-only unless mode $A is selected",
+        program: huc3_ram_enable,
+        expected: &[0xF5, 0x42],
+    },
+];
+
+/// Runs all self-tests with the given accuracy-relevant settings. See
+/// [`crate::Emulator::self_test`].
+pub fn run_self_test(step_order: StepOrder, clock_ratio: ClockRatio) -> SelfTestReport {
+    let results = SELF_TESTS
+        .iter()
+        .map(|test| {
+            let mut program = (test.program)();
+
+            // JR -2
+            let done_addr = program.code_addr();
+            program.code(&[0x18, 0xFE]);
+
+            let settings = Settings {
+                step_order,
+                clock_ratio,
+                done_addr,
+                result_len: test.expected.len(),
+            };
+
+            SelfTestResult {
+                area: test.area,
+                name: test.name,
+                expected: test.expected.to_vec(),
+                actual: run_program(program.build(), &settings),
+            }
+        })
+        .collect();
+
+    SelfTestReport { results }
+}
+
+struct Settings {
+    step_order: StepOrder,
+    clock_ratio: ClockRatio,
+    /// The address of the final loop
+    done_addr: u16,
+    result_len: usize,
+}
+
+fn run_program(rom: Vec<u8>, settings: &Settings) -> Option<Vec<u8>> {
+    let cartridge = CartridgeVariant::from_bytes(rom).ok()?;
+
+    run_cartridge(cartridge, settings)
+}
+
+fn run_cartridge(cartridge: CartridgeVariant, settings: &Settings) -> Option<Vec<u8>> {
+    // The programs don't contain the Nintendo logo, which the boot ROM would check
+    let mut emu = Emulator::new(cartridge).with_boot_rom(None);
+    emu.set_step_order(settings.step_order);
+    emu.set_clock_ratio(settings.clock_ratio);
+
+    while emu.cpu.reg.pc != settings.done_addr {
+        if emu.is_stuck() || emu.mcycles() >= MAX_MCYCLES {
+            return None;
+        }
+
+        emu.emulate_step();
+    }
+
+    let results = RESULT_ADDR..RESULT_ADDR + settings.result_len as u16;
+    Some(results.map(|addr| emu.peek(addr)).collect())
+}
+
+/// Waits for exactly `mcycles` machine cycles
+fn delay(program: &mut RomBuilder, mut mcycles: u32) {
+    while mcycles >= 5 {
+        // LD C,n; DEC C; JR NZ,-3 takes 4n+1 cycles
+        let n = ((mcycles - 1) / 4).min(255);
+        program.code(&[0x0E, n as u8, 0x0D, 0x20, 0xFD]);
+        mcycles -= 4 * n + 1;
+    }
+
+    for _ in 0..mcycles {
+        program.code(&[0x00]);
+    }
+}
+
+/// Lets TIMA count for 10 of its periods at every frequency and stores how far it got
+fn timer_frequencies() -> RomBuilder {
+    let mut program = RomBuilder::new();
+    program.code(&[0xF3]); // DI
+
+    // (TAC, machine cycles per TIMA increment)
+    let frequencies = [(0x05, 4), (0x06, 16), (0x07, 64), (0x04, 256)];
+
+    for (idx, &(tac, period)) in frequencies.iter().enumerate() {
+        program.code(&[
+            0xAF, // XOR A
+            0xE0, 0x05, // LDH (TIMA),A
+            0x3E, tac, // LD A,tac
+            0xE0, 0x07, // LDH (TAC),A
+            0xF0, 0x05, // LDH A,(TIMA)
+            0x47, // LD B,A
+        ]);
+
+        // The reads of TIMA are 10 periods apart
+        delay(&mut program, 10 * period - 4);
+
+        program.code(&[
+            0xF0, 0x05, // LDH A,(TIMA)
+            0x90, // SUB B
+            0xEA, idx as u8, 0xC0, // LD ($C00n),A
+        ]);
+    }
+
+    program
+}
+
+/// Lets TIMA overflow, then stores the timer bit of IF and whether TIMA continued
+/// counting from TMA
+fn timer_overflow() -> RomBuilder {
+    let mut program = RomBuilder::new();
+
+    program.code(&[
+        0xF3, // DI
+        0xAF, // XOR A
+        0xE0, 0x0F, // LDH (IF),A
+        0x3E, 0xAB, // LD A,$AB
+        0xE0, 0x06, // LDH (TMA),A
+        0x3E, 0xFE, // LD A,$FE
+        0xE0, 0x05, // LDH (TIMA),A
+        0x3E, 0x05, // LD A,$05
+        0xE0, 0x07, // LDH (TAC),A
+    ]);
+
+    // About 10 increments
+    delay(&mut program, 40);
+
+    program.code(&[
+        0xF0, 0x0F, // LDH A,(IF)
+        0xE6, 0x04, // AND $04
+        0xEA, 0x00, 0xC0, // LD ($C000),A
+        0xF0, 0x05, // LDH A,(TIMA)
+        0xD6, 0xAB, // SUB $AB
+        0xFE, 0x10, // CP $10
+        0x9F, // SBC A,A ($FF if TIMA was in $AB..$BB)
+        0xEA, 0x01, 0xC0, // LD ($C001),A
+    ]);
+
+    program
+}
+
+/// Resets DIV and reads it half a period and one and a half periods later
+fn div_reset() -> RomBuilder {
+    let mut program = RomBuilder::new();
+
+    program.code(&[
+        0xF3, // DI
+        0xE0, 0x04, // LDH (DIV),A
+    ]);
+
+    delay(&mut program, 29);
+
+    program.code(&[
+        0xF0, 0x04, // LDH A,(DIV)
+        0xEA, 0x00, 0xC0, // LD ($C000),A
+    ]);
+
+    delay(&mut program, 57);
+
+    program.code(&[
+        0xF0, 0x04, // LDH A,(DIV)
+        0xEA, 0x01, 0xC0, // LD ($C001),A
+    ]);
+
+    program
+}
+
+/// Copies $C100-$C19F to OAM from a routine in HRAM, which reads OAM during the copy.
+/// Stores that read and the first and last byte in OAM afterwards.
+fn oam_dma() -> RomBuilder {
+    let mut program = RomBuilder::new();
+
+    // The routine that starts OAM DMA and waits for it to finish. Only HRAM can be
+    // accessed while OAM DMA is running, so it is copied there.
+    program.bytes_at(
+        0x1000,
+        &[
+            0xE0, 0x46, // LDH (DMA),A
+            0xFA, 0x00, 0xFE, // LD A,($FE00)
+            0xE0, 0x90, // LDH ($90),A
+            0x3E, 0x30, // LD A,$30
+            0x3D, // DEC A
+            0x20, 0xFD, // JR NZ,-3
+            0xC9, // RET
+        ],
+    );
+
+    program.code(&[
+        0xF3, // DI
+        // Turn off the LCD in VBlank, so OAM can be read afterwards
+        0xF0, 0x44, // LDH A,(LY)
+        0xFE, 0x90, // CP 144
+        0x20, 0xFA, // JR NZ,-6
+        0xAF, // XOR A
+        0xE0, 0x40, // LDH (LCDC),A
+        // Fill $C100-$C19F with $20-$BF
+        0x21, 0x00, 0xC1, // LD HL,$C100
+        0x06, 0xA0, // LD B,160
+        0x7D, // LD A,L
+        0xC6, 0x20, // ADD A,$20
+        0x22, // LD (HL+),A
+        0x05, // DEC B
+        0x20, 0xF9, // JR NZ,-7
+        // Copy the routine to $FF80
+        0x21, 0x00, 0x10, // LD HL,$1000
+        0x0E, 0x80, // LD C,$80
+        0x06, 0x0D, // LD B,13
+        0x2A, // LD A,(HL+)
+        0xE2, // LDH (C),A
+        0x0C, // INC C
+        0x05, // DEC B
+        0x20, 0xFA, // JR NZ,-6
+        // Run it
+        0x3E, 0xC1, // LD A,$C1
+        0xCD, 0x80, 0xFF, // CALL $FF80
+        0xF0, 0x90, // LDH A,($90)
+        0xEA, 0x00, 0xC0, // LD ($C000),A
+        0xFA, 0x00, 0xFE, // LD A,($FE00)
+        0xEA, 0x01, 0xC0, // LD ($C001),A
+        0xFA, 0x9F, 0xFE, // LD A,($FE9F)
+        0xEA, 0x02, 0xC0, // LD ($C002),A
+    ]);
+
+    program
+}
+
+/// A program whose VBlank and timer interrupt handlers write $40 and $50 to (HL+).
+/// It clears $C000-$C002 and points HL to $C000.
+fn recording_handlers() -> RomBuilder {
+    let mut program = RomBuilder::new();
+
+    for &vector in &[0x40, 0x50] {
+        program.bytes_at(
+            vector,
+            &[
+                0x36,
+                vector as u8, // LD (HL),vector
+                0x23,         // INC HL
+                0xD9,         // RETI
+            ],
+        );
+    }
+
+    program.code(&[
+        0xF3, // DI
+        0x21, 0x00, 0xC0, // LD HL,$C000
+        0xAF, // XOR A
+        0x22, 0x22, 0x22, // LD (HL+),A (3 times)
+        0x21, 0x00, 0xC0, // LD HL,$C000
+    ]);
+
+    program
+}
+
+/// Requests a timer interrupt and tries to handle it with EI; DI and with EI; NOP
+fn ei_delay() -> RomBuilder {
+    let mut program = recording_handlers();
+
+    program.code(&[
+        0x3E, 0x04, // LD A,$04
+        0xE0, 0xFF, // LDH (IE),A
+        0xE0, 0x0F, // LDH (IF),A
+        0xFB, // EI
+        0xF3, // DI
+        0x36, 0x01, // LD (HL),$01
+        0x23, // INC HL
+        0xFB, // EI
+        0x00, // NOP
+        0xF3, // DI
+        0x36, 0x02, // LD (HL),$02
+        0x23, // INC HL
+    ]);
+
+    program
+}
+
+/// Requests VBlank and timer interrupts at the same time
+fn interrupt_priority() -> RomBuilder {
+    let mut program = recording_handlers();
+
+    program.code(&[
+        0x3E, 0x05, // LD A,$05
+        0xE0, 0xFF, // LDH (IE),A
+        0xE0, 0x0F, // LDH (IF),A
+        0xFB, // EI
+        0x00, // NOP
+        0xF3, // DI
+        0x36, 0x03, // LD (HL),$03
+        0x23, // INC HL
+    ]);
+
+    program
+}
+
+/// HALT with an interrupt pending (which triggers the HALT bug), then HALT until a
+/// timer interrupt is requested
+fn halt_without_ime() -> RomBuilder {
+    let mut program = RomBuilder::new();
+
+    program.code(&[
+        0xF3, // DI
+        0x3E, 0x04, // LD A,$04
+        0xE0, 0xFF, // LDH (IE),A
+        0xE0, 0x0F, // LDH (IF),A
+        0x06, 0x00, // LD B,0
+        0x76, // HALT
+        0x04, // INC B (executed twice)
+        0x78, // LD A,B
+        0xEA, 0x00, 0xC0, // LD ($C000),A
+        0xAF, // XOR A
+        0xE0, 0x0F, // LDH (IF),A
+        0xE0, 0x06, // LDH (TMA),A
+        0x3E, 0xF0, // LD A,$F0
+        0xE0, 0x05, // LDH (TIMA),A
+        0x3E, 0x05, // LD A,$05
+        0xE0, 0x07, // LDH (TAC),A
+        0x76, // HALT
+        0x00, // NOP
+        0xF0, 0x0F, // LDH A,(IF)
+        0xE6, 0x04, // AND $04
+        0xEA, 0x01, 0xC0, // LD ($C001),A
+    ]);
+
+    program
+}
+
+fn mbc1_banking() -> RomBuilder {
+    rom_banking(0x01)
+}
+
+fn mbc5_banking() -> RomBuilder {
+    rom_banking(0x19)
+}
+
+/// Selects banks 1, 2, 3 and 0 and stores the marker byte ($B0 + bank) of each
+fn rom_banking(cartridge_type: u8) -> RomBuilder {
+    let mut program = RomBuilder::with_mbc(cartridge_type, 4, 0x00);
+
+    for bank in 0..4 {
+        program.bytes_at(bank * 0x4000 + 0x2000, &[0xB0 + bank as u8]);
+    }
+
+    for (idx, &bank) in [1, 2, 3, 0].iter().enumerate() {
+        program.code(&[
+            0x3E, bank, // LD A,bank
+            0xEA, 0x00, 0x20, // LD ($2000),A
+            0xFA, 0x00, 0x60, // LD A,($6000)
+            0xEA, idx as u8, 0xC0, // LD ($C00n),A
+        ]);
+    }
+
+    program
+}
+
+/// Writes to cartridge RAM, disables it and reads it while disabled and after enabling
+/// it again
+fn mbc1_ram_enable() -> RomBuilder {
+    let mut program = RomBuilder::with_mbc(0x02, 2, 0x02);
+
+    program.code(&[
+        0x3E, 0x0A, // LD A,$0A
+        0xEA, 0x00, 0x00, // LD ($0000),A
+        0x3E, 0x42, // LD A,$42
+        0xEA, 0x00, 0xA0, // LD ($A000),A
+        0xAF, // XOR A
+        0xEA, 0x00, 0x00, // LD ($0000),A
+        0xFA, 0x00, 0xA0, // LD A,($A000)
+        0xEA, 0x00, 0xC0, // LD ($C000),A
+        0x3E, 0x0A, // LD A,$0A
+        0xEA, 0x00, 0x00, // LD ($0000),A
+        0xFA, 0x00, 0xA0, // LD A,($A000)
+        0xEA, 0x01, 0xC0, // LD ($C001),A
+    ]);
+
+    program
+}
+
+fn mbc1_ram_enable_gate() -> RomBuilder {
+    ram_enable_gate(0x02, 0x02)
+}
+
+fn mbc2_ram_enable_gate() -> RomBuilder {
+    ram_enable_gate(0x05, 0x00)
+}
+
+fn mbc3_ram_enable_gate() -> RomBuilder {
+    ram_enable_gate(0x12, 0x02)
+}
+
+fn mbc5_ram_enable_gate() -> RomBuilder {
+    ram_enable_gate(0x1A, 0x02)
+}
+
+/// Writes $F5 to cartridge RAM without enabling it (HuC1 has no RAM enable gate), then
+/// reads it with the IR port ($0E) and RAM ($00) mapped
+fn huc1_ir_select() -> RomBuilder {
+    let mut program = RomBuilder::with_mbc(0xFF, 2, 0x02);
+
+    program.code(&[
+        0x3E, 0xF5, // LD A,$F5
+        0xEA, 0x00, 0xA0, // LD ($A000),A
+    ]);
+
+    for (idx, &select) in [0x0E, 0x00].iter().enumerate() {
+        program.code(&[
+            0x3E, select, // LD A,select
+            0xEA, 0x00, 0x00, // LD ($0000),A
+            0xFA, 0x00, 0xA0, // LD A,($A000)
+            0xEA, idx as u8, 0xC0, // LD ($C00n),A
+        ]);
+    }
+
+    program
+}
+
+/// Writes $F5 to cartridge RAM, tries to overwrite it with $42 in the read-only mode
+/// and then again after selecting mode $A with $1A (only the lower nibble counts)
+fn huc3_ram_enable() -> RomBuilder {
+    let mut program = RomBuilder::with_mbc(0xFE, 2, 0x02);
+
+    program.code(&[
+        0x3E, 0x0A, // LD A,$0A
+        0xEA, 0x00, 0x00, // LD ($0000),A
+        0x3E, 0xF5, // LD A,$F5
+        0xEA, 0x00, 0xA0, // LD ($A000),A
+    ]);
+
+    for (idx, &mode) in [0x00, 0x1A].iter().enumerate() {
+        program.code(&[
+            0x3E, mode, // LD A,mode
+            0xEA, 0x00, 0x00, // LD ($0000),A
+            0x3E, 0x42, // LD A,$42
+            0xEA, 0x00, 0xA0, // LD ($A000),A
+            0xFA, 0x00, 0xA0, // LD A,($A000)
+            0xEA, idx as u8, 0xC0, // LD ($C00n),A
+        ]);
+    }
+
+    program
+}
+
+/// Writes $F5 to cartridge RAM (MBC2 only stores the lower nibble, but reads the upper
+/// one as 1s), then reads it after writing $1A, $0B and $FA to the RAM enable register.
+/// Only the lower nibble counts, so $0B disables RAM.
+fn ram_enable_gate(cartridge_type: u8, ram_size: u8) -> RomBuilder {
+    let mut program = RomBuilder::with_mbc(cartridge_type, 2, ram_size);
+
+    program.code(&[
+        0x3E, 0x0A, // LD A,$0A
+        0xEA, 0x00, 0x00, // LD ($0000),A
+        0x3E, 0xF5, // LD A,$F5
+        0xEA, 0x00, 0xA0, // LD ($A000),A
+    ]);
+
+    for (idx, &enable) in [0x1A, 0x0B, 0xFA].iter().enumerate() {
+        program.code(&[
+            0x3E, enable, // LD A,enable
+            0xEA, 0x00, 0x00, // LD ($0000),A
+            0xFA, 0x00, 0xA0, // LD A,($A000)
+            0xEA, idx as u8, 0xC0, // LD ($C00n),A
+        ]);
+    }
+
+    program
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_self_tests_pass() {
+        let report = run_self_test(StepOrder::DEFAULT, ClockRatio::default());
+
+        assert_eq!(report.results.len(), SELF_TESTS.len());
+        assert!(report.passed(), "\n{}", report);
+    }
+}
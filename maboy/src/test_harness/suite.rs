@@ -0,0 +1,345 @@
+//! Runs whole collections of test ROMs in parallel and compares the results against
+//! "golden" results that were recorded earlier, so only regressions need to be looked at.
+//!
+//! Since accuracy runs over hundreds of ROMs take a while, results can be cached on
+//! disk. Cache entries are keyed by the emulator build ([`super::BUILD_ID`]), a hash
+//! of the ROM, the cycle limit and the step order, so they are invalidated by any change
+//! to the emulator source code.
+//!
+//! Both the cache and the golden results are plain text files with one entry per line.
+
+use super::{run_test_rom, TestConfig, TestOutcome, BUILD_ID};
+use crate::util::fnv1a;
+use crate::StepOrder;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A collection of test ROMs that are run together
+pub struct TestSuite {
+    /// Test name (usually the path relative to the suite directory) and ROM path
+    roms: Vec<(String, PathBuf)>,
+    config: TestConfig,
+    threads: usize,
+    cache_file: Option<PathBuf>,
+}
+
+/// The result of a single ROM in a [`TestSuite`]
+#[derive(Debug, Clone)]
+pub struct SuiteResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+    /// Whether the outcome was taken from the cache instead of running the ROM
+    pub cached: bool,
+    pub duration: Duration,
+}
+
+/// Results of a [`TestSuite`] run, in the order the ROMs were added
+#[derive(Debug, Clone)]
+pub struct SuiteResults {
+    pub results: Vec<SuiteResult>,
+}
+
+/// A ROM that passed according to the golden results, but doesn't anymore
+#[derive(Debug, Clone)]
+pub struct Regression {
+    pub name: String,
+    pub expected: TestOutcome,
+    pub actual: TestOutcome,
+}
+
+impl TestSuite {
+    /// Creates an empty suite that runs on all available CPU cores
+    pub fn new(config: TestConfig) -> TestSuite {
+        TestSuite {
+            roms: Vec::new(),
+            config,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            cache_file: None,
+        }
+    }
+
+    /// Creates a suite from all ROMs (.gb and .gbc) in `dir` and its subdirectories.
+    /// Tests are named after their path relative to `dir`.
+    pub fn from_dir<P: AsRef<Path>>(dir: P, config: TestConfig) -> io::Result<TestSuite> {
+        let mut suite = TestSuite::new(config);
+        let mut roms = Vec::new();
+
+        collect_roms(dir.as_ref(), &mut roms)?;
+        roms.sort();
+
+        for path in roms {
+            let name = path
+                .strip_prefix(&dir)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+
+            suite.add_rom(name, path);
+        }
+
+        Ok(suite)
+    }
+
+    pub fn add_rom(&mut self, name: String, path: PathBuf) {
+        self.roms.push((name, path));
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    /// Number of ROMs that are run at the same time. Panics if `threads` is 0.
+    pub fn set_threads(&mut self, threads: usize) {
+        assert!(threads > 0, "Test suite needs at least one thread");
+        self.threads = threads;
+    }
+
+    /// Enables (or disables) caching of results in the given file
+    pub fn set_cache_file(&mut self, cache_file: Option<PathBuf>) {
+        self.cache_file = cache_file;
+    }
+
+    /// Runs all ROMs that don't have a cached result
+    pub fn run(&self) -> SuiteResults {
+        let cache = self.cache_file.as_ref().map_or_else(HashMap::new, |path| {
+            load_cache(path).unwrap_or_else(|err| {
+                log::warn!("Could not load test result cache: {}", err);
+                HashMap::new()
+            })
+        });
+
+        let cache = Mutex::new(cache);
+        let results = Mutex::new(vec![None; self.roms.len()]);
+        let next_rom = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(self.roms.len()) {
+                scope.spawn(|| loop {
+                    let idx = next_rom.fetch_add(1, Ordering::Relaxed);
+
+                    if idx >= self.roms.len() {
+                        break;
+                    }
+
+                    let result = self.run_rom(idx, &cache);
+                    results.lock().unwrap()[idx] = Some(result);
+                });
+            }
+        });
+
+        if let Some(path) = &self.cache_file {
+            if let Err(err) = save_cache(path, &cache.into_inner().unwrap()) {
+                log::warn!("Could not save test result cache: {}", err);
+            }
+        }
+
+        SuiteResults {
+            results: results
+                .into_inner()
+                .unwrap()
+                .into_iter()
+                .map(|result| result.expect("Test ROM was not run"))
+                .collect(),
+        }
+    }
+
+    fn run_rom(&self, idx: usize, cache: &Mutex<HashMap<CacheKey, TestOutcome>>) -> SuiteResult {
+        let (name, path) = &self.roms[idx];
+        let started = Instant::now();
+
+        let rom = match fs::read(path) {
+            Ok(rom) => rom,
+            Err(err) => {
+                log::warn!("Could not read test ROM {:?}: {}", path, err);
+
+                return SuiteResult {
+                    name: name.clone(),
+                    outcome: TestOutcome::InvalidRom,
+                    cached: false,
+                    duration: started.elapsed(),
+                };
+            }
+        };
+
+        let key = CacheKey {
+            rom_hash: fnv1a(rom.iter().copied()),
+            max_mcycles: self.config.max_mcycles,
+            step_order: self.config.step_order,
+        };
+
+        if let Some(&outcome) = cache.lock().unwrap().get(&key) {
+            return SuiteResult {
+                name: name.clone(),
+                outcome,
+                cached: true,
+                duration: started.elapsed(),
+            };
+        }
+
+        let outcome = run_test_rom(rom, &self.config).outcome;
+
+        log::info!("{}: {}", name, outcome);
+
+        if outcome.is_deterministic() {
+            cache.lock().unwrap().insert(key, outcome);
+        }
+
+        SuiteResult {
+            name: name.clone(),
+            outcome,
+            cached: false,
+            duration: started.elapsed(),
+        }
+    }
+}
+
+impl SuiteResults {
+    /// Number of ROMs with the given outcome
+    pub fn count(&self, outcome: TestOutcome) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.outcome == outcome)
+            .count()
+    }
+
+    /// All ROMs that passed according to `golden`, but didn't pass in this run. ROMs
+    /// that are missing from `golden` are ignored.
+    pub fn regressions(&self, golden: &HashMap<String, TestOutcome>) -> Vec<Regression> {
+        self.results
+            .iter()
+            .filter_map(|result| match golden.get(&result.name) {
+                Some(&TestOutcome::Passed) if result.outcome != TestOutcome::Passed => {
+                    Some(Regression {
+                        name: result.name.clone(),
+                        expected: TestOutcome::Passed,
+                        actual: result.outcome,
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Stores the results as golden results, to be loaded with [`SuiteResults::load_golden`]
+    pub fn save_golden<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut content = String::from("# Expected test ROM results (outcome, test name)\n");
+
+        for result in &self.results {
+            content.push_str(&format!("{} {}\n", result.outcome, result.name));
+        }
+
+        fs::write(path, content)
+    }
+
+    /// Loads golden results saved via [`SuiteResults::save_golden`]
+    pub fn load_golden<P: AsRef<Path>>(path: P) -> io::Result<HashMap<String, TestOutcome>> {
+        let mut golden = HashMap::new();
+
+        for line in fs::read_to_string(path)?.lines() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (outcome, name) = line
+                .split_once(' ')
+                .and_then(|(outcome, name)| Some((TestOutcome::parse(outcome)?, name)))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid line in golden results: {}", line),
+                    )
+                })?;
+
+            golden.insert(name.to_owned(), outcome);
+        }
+
+        Ok(golden)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    rom_hash: u64,
+    max_mcycles: u64,
+    step_order: StepOrder,
+}
+
+/// Loads all cache entries that belong to the current emulator build. Each line
+/// consists of build id, ROM hash, cycle limit, step order and outcome.
+fn load_cache(path: &Path) -> io::Result<HashMap<CacheKey, TestOutcome>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut cache = HashMap::new();
+
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+
+        // Entries of other builds (and broken lines) are dropped
+        if let [BUILD_ID, rom_hash, max_mcycles, step_order, outcome] = fields[..] {
+            let entry = (
+                u64::from_str_radix(rom_hash, 16),
+                max_mcycles.parse(),
+                step_order.parse(),
+                TestOutcome::parse(outcome),
+            );
+
+            if let (Ok(rom_hash), Ok(max_mcycles), Ok(step_order), Some(outcome)) = entry {
+                cache.insert(
+                    CacheKey {
+                        rom_hash,
+                        max_mcycles,
+                        step_order,
+                    },
+                    outcome,
+                );
+            }
+        }
+    }
+
+    Ok(cache)
+}
+
+fn save_cache(path: &Path, cache: &HashMap<CacheKey, TestOutcome>) -> io::Result<()> {
+    let mut content = String::new();
+
+    for (key, outcome) in cache {
+        content.push_str(&format!(
+            "{} {:016x} {} {} {}\n",
+            BUILD_ID, key.rom_hash, key.max_mcycles, key.step_order, outcome
+        ));
+    }
+
+    fs::write(path, content)
+}
+
+fn collect_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_roms(&path, roms)?;
+        } else if let Some(ext) = path.extension() {
+            let ext = ext.to_string_lossy().to_ascii_lowercase();
+
+            if ext == "gb" || ext == "gbc" {
+                roms.push(path);
+            }
+        }
+    }
+
+    Ok(())
+}
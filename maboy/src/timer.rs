@@ -2,7 +2,9 @@
 //! Please consult an external source (like TCAGBD) to learn about it.
 
 use super::address::TimerReg;
+use super::hardware::HardwareModel;
 use super::interrupt_system::{Interrupt, InterruptSystem};
+use super::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use super::util::BitOps;
 
 // TODO:  If register IF is written during TimaReloadState::RightAfterReload,
@@ -12,7 +14,13 @@ use super::util::BitOps;
 /// This implementation should be close enough without introducing
 /// unneccessary complexity.
 pub struct Timer {
-    div_reg: u16,
+    /// The 16-bit counter that runs through the whole system, increased with every
+    /// clock (so by 4 every machine cycle). DIV is its upper byte. TIMA and the frame
+    /// sequencer of the APU are clocked by falling edges of its bits.
+    system_counter: u16,
+    /// Falling edges of [`FRAME_SEQUENCER_BIT`] that haven't been picked up yet, see
+    /// [`Timer::take_frame_sequencer_clocks`]
+    frame_sequencer_clocks: u32,
     tima_reg: u8,
     tma_reg: u8,
     tac_reg: u8,
@@ -24,6 +32,10 @@ pub struct Timer {
 
 const TAC_WRITE_MASK: u8 = 0b111;
 
+/// The bit of the system counter (bit 4 of DIV) whose falling edges clock the frame
+/// sequencer of the APU, 512 times per second
+const FRAME_SEQUENCER_BIT: u16 = 1 << 12;
+
 /// Enum values are the bitmask for DIV that triggers an increase in TIMA on falling edges.
 /// That was poorly explained... So basically when the bit that is 1 in Fxx goes from 1
 /// to 0 in the DIV register, TIMA is increased.
@@ -36,20 +48,38 @@ enum TimaFrequency {
     F11 = 0b00_1000_0000,
 }
 
+impl TimaFrequency {
+    fn from_tac(tac: u8) -> TimaFrequency {
+        match tac & 0b11 {
+            0b00 => TimaFrequency::F00,
+            0b01 => TimaFrequency::F01,
+            0b10 => TimaFrequency::F10,
+            0b11 => TimaFrequency::F11,
+            _ => unreachable!(),
+        }
+    }
+}
+
 /// The timer has some behaviour with VERY tight timing. This enum is used
 /// to keep track of the exact internal state at all times, even the one that
 /// cannot be expressed via register values alone.
+///
+/// After TIMA overflows, it reads as 0 for one machine cycle (`InReload`) before it is
+/// reloaded from TMA and the interrupt is requested. Writing TIMA in that cycle cancels
+/// both, and TMA can still be changed for the reload. In the cycle of the reload
+/// (`RightAfterReload`), writes to TIMA are ignored, while writes to TMA go to TIMA as
+/// well.
 enum TimaReloadState {
     NotReloading,
-    /// Contains a new value for TIMA or `None` if TIMA should be set to TMA
-    InReload(Option<u8>),
+    InReload,
     RightAfterReload,
 }
 
 impl Timer {
     pub fn new() -> Timer {
         Timer {
-            div_reg: 0,
+            system_counter: 0,
+            frame_sequencer_clocks: 0,
             tima_reg: 0,
             tma_reg: 0,
             tac_reg: !TAC_WRITE_MASK,
@@ -59,52 +89,109 @@ impl Timer {
         }
     }
 
-    pub fn advance_mcycle(&mut self, ir_system: &mut InterruptSystem) {
-        let old_div = self.div_reg;
-        self.div_reg = self.div_reg.wrapping_add(4);
+    /// The state that the boot ROM of the given model leaves the timer in, right before
+    /// it jumps to the cartridge. The boot ROMs of the Super Game Boy and the Game Boy
+    /// Color don't always take the same time, so they get the value of the original
+    /// Game Boy.
+    pub fn after_boot(model: HardwareModel) -> Timer {
+        let system_counter = match model {
+            HardwareModel::Dmg0 => 0x18CC,
+            _ => 0xABCC,
+        };
+
+        Timer {
+            system_counter,
+            ..Timer::new()
+        }
+    }
 
-        if let TimaReloadState::InReload(new_tima) = self.tima_reload_state {
-            self.tima_reg = new_tima.unwrap_or(self.tma_reg);
+    /// Used to make internal state visible to debugger
+    pub fn div_internal(&self) -> u16 {
+        self.system_counter
+    }
+
+    pub fn advance_mcycle(&mut self, ir_system: &mut InterruptSystem) {
+        if let TimaReloadState::InReload = self.tima_reload_state {
+            self.tima_reg = self.tma_reg;
             ir_system.schedule_interrupt(Interrupt::Timer);
             self.tima_reload_state = TimaReloadState::RightAfterReload;
         } else {
             self.tima_reload_state = TimaReloadState::NotReloading;
         }
 
-        self.update_tima(old_div, self.div_reg);
+        self.set_system_counter(self.system_counter.wrapping_add(4));
+    }
+
+    /// How often the frame sequencer of the APU was clocked since the last call. This
+    /// includes the extra clocks caused by resetting DIV while bit 4 of it is set.
+    pub fn take_frame_sequencer_clocks(&mut self) -> u32 {
+        std::mem::take(&mut self.frame_sequencer_clocks)
+    }
+
+    /// How many of the next machine cycles only increase DIV (and maybe TIMA), without
+    /// TIMA overflowing, see [`Timer::skip_mcycles`]
+    pub fn idle_mcycles(&self) -> u32 {
+        if !matches!(self.tima_reload_state, TimaReloadState::NotReloading) {
+            return 0;
+        }
+
+        match self.tima_enabled {
+            None => u32::MAX,
+            Some(()) => {
+                // TIMA increases once per period of the frequency bit (in DIV units)
+                let period = 2 * self.tima_freq as u32;
+                let div = self.system_counter as u32;
+                let increases_left = 0x100 - self.tima_reg as u32;
+                let overflow_div = (div / period + increases_left) * period;
+
+                // The cycle that overflows TIMA is not idle
+                (overflow_div - div) / 4 - 1
+            }
+        }
+    }
+
+    /// Advances the timer by `mcycles` machine cycles at once. Must not be more than
+    /// [`Timer::idle_mcycles`].
+    pub fn skip_mcycles(&mut self, mcycles: u32) {
+        debug_assert!(mcycles <= self.idle_mcycles());
+
+        let old_div = self.system_counter as u32;
+        let new_div = old_div + 4 * mcycles;
+
+        if self.tima_enabled.is_some() {
+            let period = 2 * self.tima_freq as u32;
+            self.tima_reg += (new_div / period - old_div / period) as u8;
+        }
+
+        let period = 2 * FRAME_SEQUENCER_BIT as u32;
+        self.frame_sequencer_clocks += new_div / period - old_div / period;
+
+        // DIV wraps around at a multiple of every period, so this doesn't miss edges
+        self.system_counter = new_div as u16;
     }
 
     pub fn read_reg(&self, reg: TimerReg) -> u8 {
         match reg {
-            TimerReg::DIV => (self.div_reg >> 8) as u8,
+            TimerReg::DIV => (self.system_counter >> 8) as u8,
             TimerReg::TIMA => self.tima_reg,
             TimerReg::TMA => self.tma_reg,
             TimerReg::TAC => self.tac_reg,
         }
     }
 
-    pub fn write_reg(&mut self, ir_system: &mut InterruptSystem, reg: TimerReg, val: u8) {
+    /// Writes to DIV and TAC can cause a falling edge of the signal that TIMA counts
+    /// (see [`Timer::tima_signal`]), which increases TIMA just like a regular tick
+    pub fn write_reg(&mut self, reg: TimerReg, val: u8) {
         match reg {
-            TimerReg::DIV => {
-                if self.div_reg & self.tima_freq as u16 != 0 {
-                    if self.incr_tima() {
-                        self.tima_reload_state = TimaReloadState::InReload(None);
-                    }
-                }
-
-                self.div_reg = 0;
-            }
-            TimerReg::TIMA => {
-                if let TimaReloadState::RightAfterReload = self.tima_reload_state {
-                    self.tima_reg = self.tma_reg;
-                } else {
+            TimerReg::DIV => self.set_system_counter(0),
+            TimerReg::TIMA => match self.tima_reload_state {
+                TimaReloadState::NotReloading => self.tima_reg = val,
+                TimaReloadState::InReload => {
                     self.tima_reg = val;
-
-                    if let TimaReloadState::InReload(_) = self.tima_reload_state {
-                        self.tima_reload_state = TimaReloadState::InReload(Some(val));
-                    }
+                    self.tima_reload_state = TimaReloadState::NotReloading;
                 }
-            }
+                TimaReloadState::RightAfterReload => (),
+            },
             TimerReg::TMA => {
                 self.tma_reg = val;
 
@@ -112,19 +199,40 @@ impl Timer {
                     self.tima_reg = val;
                 }
             }
-            TimerReg::TAC => self.write_tac(ir_system, val),
+            TimerReg::TAC => {
+                let old_signal = self.tima_signal();
+                self.tima_freq = TimaFrequency::from_tac(val);
+                self.tima_enabled = if val.bit(2) { Some(()) } else { None };
+                self.tac_reg = (self.tac_reg & (!TAC_WRITE_MASK)) | (val & TAC_WRITE_MASK);
+                self.check_falling_edge(old_signal);
+            }
         }
     }
 
-    fn update_tima(&mut self, old_div: u16, new_div: u16) {
-        // TIMA is increased when a falling edge is detected from a certain bit in
-        // DIV, with the index of the bit depending on the frequence setting in TAC
+    /// Every change of the system counter goes through here, so that no falling edge
+    /// is missed, no matter if it comes from ticking or from resetting DIV
+    fn set_system_counter(&mut self, value: u16) {
+        let old_signal = self.tima_signal();
+        let falling_edges = self.system_counter & !value;
+        self.system_counter = value;
 
-        let freq_mask = self.tima_freq as u16 & self.tima_enabled.map(|_| 0xFFFF).unwrap_or(0);
-        if old_div & freq_mask > new_div & freq_mask {
-            if self.incr_tima() {
-                self.tima_reload_state = TimaReloadState::InReload(None);
-            }
+        if falling_edges & FRAME_SEQUENCER_BIT != 0 {
+            self.frame_sequencer_clocks += 1;
+        }
+
+        self.check_falling_edge(old_signal);
+    }
+
+    /// TIMA is increased on the falling edges of this signal: The bit of DIV that is
+    /// selected by the frequency in TAC, AND the enable bit of TAC. So besides DIV
+    /// ticking, resetting DIV or changing TAC can increase TIMA as well.
+    fn tima_signal(&self) -> bool {
+        self.tima_enabled.is_some() && self.system_counter & self.tima_freq as u16 != 0
+    }
+
+    fn check_falling_edge(&mut self, old_signal: bool) {
+        if old_signal && !self.tima_signal() && self.incr_tima() {
+            self.tima_reload_state = TimaReloadState::InReload;
         }
     }
 
@@ -139,40 +247,39 @@ impl Timer {
             true
         }
     }
+}
 
-    fn write_tac(&mut self, ir_system: &mut InterruptSystem, val: u8) {
-        // Writing to TAC can lead to some unexpected increases in TIMA
-
-        let new_freq = match val & 0b11 {
-            0b00 => TimaFrequency::F00,
-            0b01 => TimaFrequency::F01,
-            0b10 => TimaFrequency::F10,
-            0b11 => TimaFrequency::F11,
-            _ => unreachable!(),
-        };
+impl SaveState for Timer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.system_counter);
+        writer.write_u8(self.tima_reg);
+        writer.write_u8(self.tma_reg);
+        writer.write_u8(self.tac_reg);
 
-        if val.bit(2) {
-            self.tima_enabled = Some(());
+        match self.tima_reload_state {
+            TimaReloadState::NotReloading => writer.write_u8(0),
+            TimaReloadState::InReload => writer.write_u8(1),
+            TimaReloadState::RightAfterReload => writer.write_u8(2),
+        }
+    }
 
-            // This is pure black magic, but is documented in TCAGBD
-            if self.div_reg & self.tima_freq as u16 == 0 && self.div_reg & new_freq as u16 != 0 {
-                if self.incr_tima() {
-                    ir_system.schedule_interrupt(Interrupt::Timer);
-                }
-            }
-        } else {
-            self.tima_enabled = None;
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.system_counter = reader.read_u16()?;
+        self.tima_reg = reader.read_u8()?;
+        self.tma_reg = reader.read_u8()?;
+        self.tac_reg = reader.read_u8()?;
 
-            // Leads to falling edge => increases tima
-            if self.tac_reg.bit(2) && self.div_reg & self.tima_freq as u16 != 0 {
-                if self.incr_tima() {
-                    ir_system.schedule_interrupt(Interrupt::Timer);
-                }
-            }
-        }
+        // These are derived from TAC
+        self.tima_freq = TimaFrequency::from_tac(self.tac_reg);
+        self.tima_enabled = if self.tac_reg.bit(2) { Some(()) } else { None };
 
-        self.tima_freq = new_freq;
+        self.tima_reload_state = match reader.read_u8()? {
+            0 => TimaReloadState::NotReloading,
+            1 => TimaReloadState::InReload,
+            2 => TimaReloadState::RightAfterReload,
+            _ => return Err(SaveStateError::InvalidValue),
+        };
 
-        self.tac_reg = (self.tac_reg & (!TAC_WRITE_MASK)) | (val & TAC_WRITE_MASK);
+        Ok(())
     }
 }
@@ -0,0 +1,7 @@
+/// 64-bit FNV-1a. Not cryptographically secure, but fast and more than good enough to
+/// tell ROMs or frames apart.
+pub fn fnv1a<I: IntoIterator<Item = u8>>(data: I) -> u64 {
+    data.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
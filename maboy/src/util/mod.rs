@@ -1,3 +1,5 @@
 mod bit_ops;
+mod hash;
 
 pub use bit_ops::BitOps;
+pub use hash::fnv1a;
@@ -0,0 +1,856 @@
+//! The Windows frontend itself. Only compiled on Windows, see `main.rs`.
+
+use maboy::debug::*;
+use maboy::frontend::{self, InputConfig, InputConfigError, InputMap, RecentRoms, Turbo};
+use maboy::*;
+use maboy_windows::*;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+const DEBUG_KEY: KeyboardKey = KeyboardKey::G;
+const REWIND_KEY: KeyboardKey = KeyboardKey::Backspace;
+const QUICK_SAVE_KEY: KeyboardKey = KeyboardKey::F5;
+const QUICK_LOAD_KEY: KeyboardKey = KeyboardKey::F9;
+const SCALING_KEY: KeyboardKey = KeyboardKey::F2;
+const FILTER_KEY: KeyboardKey = KeyboardKey::F3;
+const SLOWER_KEY: KeyboardKey = KeyboardKey::F6;
+const FASTER_KEY: KeyboardKey = KeyboardKey::F7;
+const TURBO_KEY: KeyboardKey = KeyboardKey::Tab;
+const SCREENSHOT_KEY: KeyboardKey = KeyboardKey::F12;
+const PAUSE_KEY: KeyboardKey = KeyboardKey::P;
+const RESET_KEY: KeyboardKey = KeyboardKey::F4;
+const SOFT_RESET_KEY: KeyboardKey = KeyboardKey::F11;
+
+/// What we display while the LCD is off
+const LCD_OFF_WHITE: MemPixel = MemPixel::new(255, 255, 255, 255);
+
+/// A rewind point is recorded every `REWIND_INTERVAL` frames, and up to
+/// `REWIND_CAPACITY` of them are kept (roughly 20 seconds)
+const REWIND_INTERVAL: u32 = 2;
+const REWIND_CAPACITY: usize = 600;
+/// How many frames we go back for every frame displayed while rewinding
+const REWIND_SPEED: u32 = 4;
+
+/// The menu of the game window: "Open ROM...", then a separator and the recent ROMs
+const OPEN_ROM_MENU_ITEM: usize = 0;
+const FIRST_RECENT_ROM_MENU_ITEM: usize = 2;
+
+pub fn run() {
+    env_logger::init();
+
+    let mut recent_roms = load_recent_roms();
+
+    // Dropping a ROM onto the executable passes it as the first argument. Otherwise, the
+    // user selects one in the file dialog.
+    let rom_path = rom_path_from_args()
+        .or_else(open_rom_dialog)
+        .expect_msg_box("Could not open ROM file");
+
+    let cartridge =
+        CartridgeVariant::from_file(&rom_path).expect_msg_box("Could not open rom file");
+
+    let mut next_rom = Some((rom_path, cartridge));
+
+    // Another ROM can be opened from the menu or dropped onto the window while playing
+    while let Some((rom_path, cartridge)) = next_rom {
+        recent_roms.add(&rom_path);
+        store_recent_roms(&recent_roms);
+
+        next_rom = run_emu(&rom_path, cartridge, &recent_roms);
+    }
+}
+
+/// Returns the ROM that should be played next, if the user opened another one
+fn run_emu(
+    rom_path: &Path,
+    mut cartridge: CartridgeVariant,
+    recent_roms: &RecentRoms,
+) -> Option<(PathBuf, CartridgeVariant)> {
+    let rom_path = rom_path.to_path_buf();
+    let save_naming = save_naming_from_args();
+
+    storage::migrate_saves(&rom_path, cartridge.rom(), save_naming)
+        .expect_msg_box("Could not rename existing saves");
+
+    let save_path = storage::save_base_path(&rom_path, cartridge.rom(), save_naming);
+
+    frontend::load_savegame(&save_path, &mut cartridge).expect_msg_box("Failed to load savegame");
+
+    frontend::load_metadata(&save_path, &mut cartridge)
+        .expect_msg_box("Metadata file was found, but had invalid contents");
+
+    let mut emu = Emulator::with_debugger(&mut cartridge, cpu_logger(), NoDbgLogger);
+
+    if std::env::args().any(|arg| arg == "--skip-boot") {
+        emu = emu.with_boot_rom(None);
+    } else if let Some(path) = path_from_args("--boot-rom") {
+        let boot_rom = frontend::load_boot_rom(path).expect_msg_box("Could not read boot ROM");
+        emu = emu.with_boot_rom(Some(boot_rom));
+    }
+    emu.set_reset_combo(ResetCombo::Reset);
+    emu.set_rewind_buffer(Some(RewindBuffer::new(REWIND_CAPACITY, REWIND_INTERVAL)));
+    // Screenshots can be taken in the middle of a frame
+    emu.set_double_buffered(true);
+
+    if let Some(ratio) = clock_ratio_from_args() {
+        emu.set_clock_ratio(ratio);
+    }
+
+    emu.set_joypad_bounce(std::env::args().any(|arg| arg == "--joypad-bounce"));
+
+    if let Some(palette) = palette_from_args() {
+        emu.set_dmg_palette(palette);
+    }
+
+    for cheat in cheats_from_args() {
+        emu.add_cheat(&cheat)
+            .expect_msg_box("Invalid cheat code (expected GameShark or Game Genie format)");
+    }
+
+    if let Some(path) = path_from_args("--play-movie") {
+        let movie = input_log::Movie::load(path).expect_msg_box("Could not read movie file");
+        emu.play_movie(movie)
+            .expect_msg_box("Movie doesn't belong to this ROM");
+    }
+
+    let mut autosplit = None;
+
+    if let Some(path) = path_from_args("--game-db") {
+        let game_db = gamedb::GameDb::load(path).expect_msg_box("Could not read game database");
+
+        if let Some(path) = path_from_args("--splits") {
+            let splitter = autosplit::AutoSplitter::load(path, &game_db)
+                .expect_msg_box("Could not read splits file");
+
+            autosplit = Some(autosplit::LiveSplitSession::connect(
+                splitter,
+                livesplit_addr_from_args(),
+            ));
+        }
+
+        emu.set_game_db(Some(game_db));
+    }
+
+    // Rewinding would break the movie
+    let record_movie = path_from_args("--record-movie");
+
+    if record_movie.is_some() {
+        emu.set_rewind_buffer(None);
+        emu.start_recording();
+    }
+
+    let (mut haptics, feedback) = Haptics::new(haptics_config_from_args());
+
+    if let Some(link) = link_from_args() {
+        emu.connect_link_cable(link);
+        let _ = feedback.send(FeedbackEvent::LinkConnected);
+    }
+
+    #[cfg(debug_assertions)]
+    let mut cpu_debugger = CpuDebugger::new();
+
+    // Initialize input system
+    let input_config = frontend::input_config_path()
+        .map_err(InputConfigError::from)
+        .and_then(InputConfig::load_or_create)
+        .expect_msg_box("Could not read input config");
+
+    let mut input_maps = InputMaps {
+        keyboard: InputMap::from_bindings(&input_config.keyboard, KeyboardKey::from_name)
+            .with_turbo_bindings(&input_config.keyboard_turbo, KeyboardKey::from_name),
+        gamepad: InputMap::from_bindings(&input_config.gamepad, GamepadButtons::from_name)
+            .with_turbo_bindings(&input_config.gamepad_turbo, GamepadButtons::from_name),
+        turbo: Turbo::new(input_config.turbo_frames),
+    };
+
+    let watched_keys: Vec<KeyboardKey> = input_maps
+        .keyboard
+        .keys()
+        .chain([
+            DEBUG_KEY,
+            REWIND_KEY,
+            QUICK_SAVE_KEY,
+            QUICK_LOAD_KEY,
+            SCALING_KEY,
+            FILTER_KEY,
+            SLOWER_KEY,
+            FASTER_KEY,
+            TURBO_KEY,
+            SCREENSHOT_KEY,
+            PAUSE_KEY,
+            RESET_KEY,
+            SOFT_RESET_KEY,
+        ])
+        .collect();
+
+    let window_input = Rc::new(RefCell::new(WindowInput::from_watched_keys(&watched_keys)));
+
+    let mut gamepad_input = GamePadInput::new();
+    gamepad_input.set_stick_config(input_config.stick);
+
+    // Initialize Window
+    let window_factory = WindowFactory::new();
+
+    // The new size of the window's client area, until the backbuffer is resized
+    let pending_resize = Rc::new(Cell::new(None));
+
+    // The ROM that was dropped onto the window and the menu item that was selected, until
+    // the main loop picks them up
+    let dropped_rom = Rc::new(Cell::new(None));
+    let selected_menu_item = Rc::new(Cell::new(None));
+
+    let game_window = {
+        let window_input = Rc::clone(&window_input);
+        let pending_resize = Rc::clone(&pending_resize);
+        let dropped_rom = Rc::clone(&dropped_rom);
+        let selected_menu_item = Rc::clone(&selected_menu_item);
+        window_factory
+            .create_window(
+                "MaBoy Emulatin'",
+                160 * 2,
+                144 * 2,
+                Box::new(move |msg, w_param, l_param| {
+                    window_input.borrow_mut().update(msg, w_param);
+
+                    if let Some(size) = client_size_from_msg(msg, l_param) {
+                        pending_resize.set(Some(size));
+                    }
+
+                    if let Some(path) = dropped_file_from_msg(msg, w_param) {
+                        dropped_rom.set(Some(path));
+                    }
+
+                    if let Some(item) = menu_item_from_msg(msg, w_param) {
+                        selected_menu_item.set(Some(item));
+                    }
+
+                    MsgHandlerResult::RunDefaultMsgHandler
+                }),
+            )
+            .expect_msg_box("Could not create game window")
+    };
+    game_window.accept_dropped_files();
+
+    let recent_rom_names: Vec<String> = recent_roms
+        .paths()
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+
+    let mut menu = vec![MenuItem::Entry("Open ROM...")];
+    if !recent_rom_names.is_empty() {
+        menu.push(MenuItem::Separator);
+        menu.extend(
+            recent_rom_names
+                .iter()
+                .map(|name| MenuItem::Entry(name.as_str())),
+        );
+    }
+
+    if !game_window.set_menu("File", &menu) {
+        log::warn!("Could not create the menu of the game window");
+    }
+
+    game_window.show();
+
+    // Initialize DirectX to draw into the window
+    let gfx_device = GfxDevice::new().expect_msg_box("Could not access graphics device");
+    let mut gfx_window = gfx_device
+        .create_gfx_window(&game_window, 160, 144)
+        .expect_msg_box("Could not attach graphics device to game window");
+
+    if let Some(scaling) = scaling_from_args() {
+        gfx_window.set_scaling_mode(scaling);
+    }
+
+    if let Some(filter) = filter_from_args() {
+        gfx_window.set_filter(filter);
+    }
+
+    // The last frame that was presented, so it can be presented again while the game is
+    // paused (by the pause key or the debugger). Starts out white (screen off should usually be black, but
+    // that looks jarring at the very beginning).
+    let mut last_frame = vec![LCD_OFF_WHITE; 160 * 144];
+
+    {
+        let mut frame = gfx_window.next_frame();
+        frame.copy_from_slice(&last_frame);
+        frame
+            .present(false)
+            .expect_msg_box("Could not present frame");
+    }
+
+    let mut last_os_update = Instant::now();
+
+    // Used to report a crashed game only once instead of every step
+    let mut cpu_stuck = false;
+
+    let mut autosave = frontend::AutoSave::default();
+
+    // Quick save/load and switching scaling modes and filters only trigger once per
+    // key press
+    let mut quick_save_held = false;
+    let mut quick_load_held = false;
+    let mut scaling_held = false;
+    let mut filter_held = false;
+    let mut slower_held = false;
+    let mut faster_held = false;
+    let mut screenshot_held = false;
+    let mut pause_held = false;
+    let mut reset_held = false;
+
+    let mut paused = false;
+
+    // Initialize throttle clock
+    // TODO: Investigate why I suddenly need a factor of 2 here. I definitely didn't need it before...
+    let mut os_timing = OsTiming::new(2.0 * 59.7)
+        .expect_msg_box("Could not create OS timer. This timer is used to throttle the game.");
+
+    if let Some(speed) = speed_from_args() {
+        os_timing.set_speed(speed);
+    }
+
+    let mut frame_skip = frame_skip_from_args();
+
+    let mut next_rom = None;
+
+    loop {
+        if let Some((width, height)) = pending_resize.take() {
+            gfx_window
+                .resize(width, height)
+                .expect_msg_box("Could not resize the game window");
+        }
+
+        let requested_rom = dropped_rom.take().or_else(|| {
+            selected_menu_item
+                .take()
+                .and_then(|item| rom_from_menu(item, recent_roms))
+        });
+
+        if let Some(path) = requested_rom {
+            match CartridgeVariant::from_file(&path) {
+                Ok(cartridge) => {
+                    log::info!("Opening {}", path.display());
+                    next_rom = Some((path, cartridge));
+                    break;
+                }
+                Err(err) => log::error!("Could not open {} ({:?})", path.display(), err),
+            }
+        }
+
+        if paused {
+            // Nothing is emulated, we only keep the window alive until the game is resumed
+            let mut paused_frame = last_frame.clone();
+            frontend::draw_pause_overlay(&mut paused_frame);
+
+            let mut frame = gfx_window.next_frame();
+            frame.copy_from_slice(&paused_frame);
+            present_frame(frame, &mut os_timing);
+
+            if !window_factory.dispatch_window_msgs() {
+                break;
+            }
+
+            let pause = window_input.borrow().is_pressed(PAUSE_KEY);
+            if pause && !pause_held {
+                paused = false;
+                log::info!("Resumed");
+            }
+            pause_held = pause;
+
+            continue;
+        }
+
+        #[cfg(debug_assertions)]
+        {
+            if cpu_debugger.poll(&mut emu) == DebuggerStatus::Paused {
+                // The debugger waits for commands in the console. In the meantime, we keep
+                // the window alive and show that the game is paused.
+                let mut paused_frame = last_frame.clone();
+                frontend::draw_pause_overlay(&mut paused_frame);
+
+                let mut frame = gfx_window.next_frame();
+                frame.copy_from_slice(&paused_frame);
+                present_frame(frame, &mut os_timing);
+
+                if !window_factory.dispatch_window_msgs() {
+                    break;
+                }
+
+                continue;
+            }
+        }
+
+        match emu.emulate_step() {
+            StepOutcome::Stuck => {
+                if !cpu_stuck {
+                    if let Some(illegal_instr) = emu.illegal_instr() {
+                        log::error!(
+                            "The game crashed (illegal instruction {:#04X} @ {:#06X}). Hold A+B+Select+Start to reset.",
+                            illegal_instr.opcode,
+                            illegal_instr.pc
+                        );
+                    }
+                    cpu_stuck = true;
+                }
+            }
+            _ => cpu_stuck = false,
+        }
+
+        let perform_os_update = match emu.query_video_frame_status() {
+            VideoFrameStatus::NotReady => last_os_update.elapsed() > Duration::from_millis(5),
+            VideoFrameStatus::Ready(frame_data)
+            | VideoFrameStatus::SgbReady(SgbFrame {
+                screen: frame_data, ..
+            }) => {
+                #[cfg(debug_assertions)]
+                cpu_debugger.notify_frame(frame_data);
+
+                last_frame.copy_from_slice(frame_data);
+
+                let mut frame = gfx_window.next_frame();
+                frame.copy_from_slice(&last_frame);
+                let behind = present_frame(frame, &mut os_timing);
+
+                let new_frame_skip = frame_skip.update(behind);
+                if new_frame_skip != emu.frame_skip() {
+                    emu.set_frame_skip(new_frame_skip);
+                }
+
+                input_maps.turbo.next_frame();
+
+                if window_input.borrow().is_pressed(REWIND_KEY) {
+                    emu.rewind(REWIND_SPEED);
+                } else {
+                    emu.push_rewind_point();
+                }
+
+                if let Some(autosplit) = &mut autosplit {
+                    autosplit.update(|addr| emu.peek(addr));
+                }
+
+                true
+            }
+            VideoFrameStatus::LcdTurnedOff => {
+                last_frame.fill(LCD_OFF_WHITE);
+
+                let mut frame = gfx_window.next_frame();
+                frame.copy_from_slice(&last_frame);
+                present_frame(frame, &mut os_timing);
+
+                true
+            }
+        };
+
+        if perform_os_update {
+            if !os_update(
+                &mut emu,
+                &window_factory,
+                &window_input,
+                &input_maps,
+                &mut gamepad_input,
+                &mut haptics,
+                &feedback,
+            ) {
+                break;
+            }
+            last_os_update = Instant::now();
+
+            if let Err(err) = autosave.update(&save_path, emu.cartridge_mut()) {
+                log::error!("Could not write savegame to disk: {:?}", err);
+            }
+
+            let quick_save = window_input.borrow().is_pressed(QUICK_SAVE_KEY);
+            if quick_save && !quick_save_held {
+                match frontend::store_state(&save_path, &emu) {
+                    Ok(()) => {
+                        let _ = feedback.send(FeedbackEvent::StateSaved);
+                    }
+                    Err(err) => log::error!("Could not save state: {:?}", err),
+                }
+            }
+            quick_save_held = quick_save;
+
+            let quick_load = window_input.borrow().is_pressed(QUICK_LOAD_KEY);
+            if quick_load && !quick_load_held {
+                match frontend::load_state(&save_path, &mut emu) {
+                    Ok(()) => {
+                        let _ = feedback.send(FeedbackEvent::StateLoaded);
+                    }
+                    Err(err) => log::warn!("Could not load state: {:?}", err),
+                }
+            }
+            quick_load_held = quick_load;
+
+            let next_scaling = window_input.borrow().is_pressed(SCALING_KEY);
+            if next_scaling && !scaling_held {
+                let scaling = gfx_window.scaling_mode().next();
+                gfx_window.set_scaling_mode(scaling);
+                log::info!("Scaling mode: {}", scaling);
+            }
+            scaling_held = next_scaling;
+
+            let next_filter = window_input.borrow().is_pressed(FILTER_KEY);
+            if next_filter && !filter_held {
+                let filter = gfx_window.filter().next();
+                gfx_window.set_filter(filter);
+                log::info!("Screen filter: {}", filter);
+            }
+            filter_held = next_filter;
+
+            let slower = window_input.borrow().is_pressed(SLOWER_KEY);
+            let faster = window_input.borrow().is_pressed(FASTER_KEY);
+            if (slower && !slower_held) || (faster && !faster_held) {
+                let speed = frontend::next_speed(os_timing.speed(), faster);
+                os_timing.set_speed(speed);
+                log::info!("Speed: {}x", speed);
+            }
+            slower_held = slower;
+            faster_held = faster;
+
+            let screenshot = window_input.borrow().is_pressed(SCREENSHOT_KEY);
+            if screenshot && !screenshot_held {
+                if let Err(err) = frontend::store_screenshot(&save_path, &emu) {
+                    log::error!("Could not save screenshot: {}", err);
+                }
+            }
+            screenshot_held = screenshot;
+
+            let pause = window_input.borrow().is_pressed(PAUSE_KEY);
+            if pause && !pause_held {
+                paused = true;
+                log::info!("Paused");
+            }
+            pause_held = pause;
+
+            let reset = window_input.borrow().is_pressed(RESET_KEY);
+            let soft_reset = window_input.borrow().is_pressed(SOFT_RESET_KEY);
+            if (reset || soft_reset) && !reset_held {
+                if soft_reset {
+                    emu.soft_reset();
+                } else {
+                    emu.reset();
+                }
+                let _ = feedback.send(FeedbackEvent::Reset);
+            }
+            reset_held = reset || soft_reset;
+
+            os_timing.set_turbo(window_input.borrow().is_pressed(TURBO_KEY));
+
+            #[cfg(debug_assertions)]
+            {
+                if window_input.borrow().is_pressed(DEBUG_KEY) {
+                    cpu_debugger.request_break();
+                }
+            }
+        }
+    }
+
+    if let (Some(path), Some(movie)) = (record_movie, emu.stop_recording()) {
+        movie
+            .save(path)
+            .expect_msg_box("Could not write movie file");
+    }
+
+    frontend::store_savegame(&save_path, &cartridge)
+        .expect_msg_box("Could not write savegame to disk");
+
+    frontend::store_metadata(&save_path, &cartridge)
+        .expect_msg_box("Could not write cartridge metadata to disk");
+
+    next_rom
+}
+
+/// Lets the user select a ROM in the file dialog
+fn open_rom_dialog() -> Option<PathBuf> {
+    open_file_dialog(
+        "Please select a cartridge rom",
+        vec![FileFilter::new(
+            "Cartridge ROM (.gb, .rom, .gbc, .zip, .gz)",
+            vec!["*.GB", "*.ROM", "*.GBC", "*.ZIP", "*.GZ"],
+        )],
+    )
+    .map(PathBuf::from)
+}
+
+/// The ROM that was selected in the menu of the game window, see [`OPEN_ROM_MENU_ITEM`]
+fn rom_from_menu(item: usize, recent_roms: &RecentRoms) -> Option<PathBuf> {
+    if item == OPEN_ROM_MENU_ITEM {
+        open_rom_dialog()
+    } else {
+        let idx = item.checked_sub(FIRST_RECENT_ROM_MENU_ITEM)?;
+        recent_roms.paths().get(idx).cloned()
+    }
+}
+
+/// The ROMs that were opened before, without the ones that don't exist anymore
+fn load_recent_roms() -> RecentRoms {
+    match frontend::recent_roms_path().and_then(RecentRoms::load) {
+        Ok(mut recent_roms) => {
+            recent_roms.remove_missing();
+            recent_roms
+        }
+        Err(err) => {
+            log::warn!("Could not read recent ROMs: {}", err);
+            RecentRoms::default()
+        }
+    }
+}
+
+fn store_recent_roms(recent_roms: &RecentRoms) {
+    if let Err(err) = frontend::recent_roms_path().and_then(|path| recent_roms.store(path)) {
+        log::warn!("Could not write recent ROMs: {}", err);
+    }
+}
+
+/// The ROM passed as the first argument, before any options
+fn rom_path_from_args() -> Option<PathBuf> {
+    std::env::args_os()
+        .nth(1)
+        .filter(|arg| !arg.to_string_lossy().starts_with("--"))
+        .map(PathBuf::from)
+}
+
+/// Connects to another instance of MaBoy if requested via `--link-listen <addr>`
+/// or `--link-connect <addr>`
+fn link_from_args() -> Option<TcpSerialTransport> {
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let transport = match arg.as_str() {
+            "--link-listen" => {
+                let addr = args
+                    .next()
+                    .expect_msg_box("--link-listen requires an address");
+                TcpSerialTransport::listen(addr)
+            }
+            "--link-connect" => {
+                let addr = args
+                    .next()
+                    .expect_msg_box("--link-connect requires an address");
+                TcpSerialTransport::connect(addr)
+            }
+            _ => continue,
+        };
+
+        return Some(transport.expect_msg_box("Could not establish link cable connection"));
+    }
+
+    None
+}
+
+/// Lets the CPU run faster or slower than the rest of the system if requested via
+/// `--cpu-clock <ratio>`, e.g. `--cpu-clock 2` or `--cpu-clock 1/2`
+fn clock_ratio_from_args() -> Option<ClockRatio> {
+    let ratio = std::env::args()
+        .skip_while(|arg| arg != "--cpu-clock")
+        .nth(1);
+
+    ratio.map(|ratio| {
+        ratio
+            .parse::<ClockRatio>()
+            .expect_msg_box("--cpu-clock requires a ratio like 2 or 3/2")
+    })
+}
+
+/// The colors of the four shades if requested via `--palette <name|colors>`, e.g.
+/// `--palette gray` or `--palette e0f8d0,88c070,346856,081820`
+fn palette_from_args() -> Option<DmgPalette> {
+    let palette = std::env::args().skip_while(|arg| arg != "--palette").nth(1);
+
+    palette.map(|palette| {
+        dmg_palette::parse(&palette)
+            .expect_msg_box("--palette requires green, gray, pocket or four hex colors")
+    })
+}
+
+/// How the frame is scaled to the window if requested via `--scaling <mode>`, e.g.
+/// `--scaling bilinear`
+fn scaling_from_args() -> Option<ScalingMode> {
+    let scaling = std::env::args().skip_while(|arg| arg != "--scaling").nth(1);
+
+    scaling.map(|scaling| {
+        scaling
+            .parse()
+            .expect_msg_box("--scaling requires integer, aspect or bilinear")
+    })
+}
+
+/// The screen filter if requested via `--filter <filter>`, e.g. `--filter scanlines`
+fn filter_from_args() -> Option<ScreenFilter> {
+    let filter = std::env::args().skip_while(|arg| arg != "--filter").nth(1);
+
+    filter.map(|filter| {
+        filter
+            .parse()
+            .expect_msg_box("--filter requires none, scanlines or lcd")
+    })
+}
+
+/// The speed multiplier if requested via `--speed <multiplier>`, e.g. `--speed 2`
+fn speed_from_args() -> Option<f32> {
+    let speed = std::env::args().skip_while(|arg| arg != "--speed").nth(1);
+
+    speed.map(|speed| {
+        frontend::parse_speed(&speed)
+            .expect_msg_box("--speed requires a positive number like 2 or 0.5")
+    })
+}
+
+/// Cheat codes passed via `--cheat <code>` (any number of times)
+/// The frame skip setting if requested via `--frame-skip <auto|frames>`, e.g.
+/// `--frame-skip auto`
+fn frame_skip_from_args() -> frontend::FrameSkipMode {
+    let frame_skip = std::env::args()
+        .skip_while(|arg| arg != "--frame-skip")
+        .nth(1);
+
+    frame_skip
+        .map(|frame_skip| {
+            frontend::FrameSkipMode::parse(&frame_skip)
+                .expect_msg_box("--frame-skip requires \"auto\" or a number of frames like 1")
+        })
+        .unwrap_or_default()
+}
+
+fn cheats_from_args() -> Vec<String> {
+    let args: Vec<String> = std::env::args().collect();
+
+    args.windows(2)
+        .filter(|pair| pair[0] == "--cheat")
+        .map(|pair| pair[1].clone())
+        .collect()
+}
+
+/// Where the LiveSplit Server runs, `localhost` unless `--livesplit <address:port>`
+/// says otherwise
+fn livesplit_addr_from_args() -> String {
+    std::env::args()
+        .skip_while(|arg| arg != "--livesplit")
+        .nth(1)
+        .unwrap_or_else(|| format!("localhost:{}", autosplit::LIVESPLIT_PORT))
+}
+
+/// Saves are named like the ROM file unless `--save-naming title` asks for the title
+/// from the cartridge header (see [`storage::SaveNaming`])
+fn save_naming_from_args() -> storage::SaveNaming {
+    let naming = std::env::args()
+        .skip_while(|arg| arg != "--save-naming")
+        .nth(1);
+
+    naming.map_or_else(storage::SaveNaming::default, |naming| {
+        naming
+            .parse()
+            .expect_msg_box("--save-naming requires either rom or title")
+    })
+}
+
+/// Files passed via `--play-movie <file>`, `--record-movie <file>`, `--game-db <file>`,
+/// `--splits <file>` and `--boot-rom <file>`
+fn path_from_args(flag: &str) -> Option<PathBuf> {
+    std::env::args()
+        .skip_while(|arg| arg != flag)
+        .nth(1)
+        .map(PathBuf::from)
+}
+
+/// Rumble (both feedback and the motor of rumble cartridges) is on by default and can be
+/// disabled with `--no-rumble`
+fn haptics_config_from_args() -> HapticsConfig {
+    HapticsConfig {
+        enabled: !std::env::args().any(|arg| arg == "--no-rumble"),
+        ..HapticsConfig::default()
+    }
+}
+
+/// Returns whether the frame took a lot longer than it should have, i.e. whether we
+/// can't keep up with real time
+fn present_frame(frame: GfxFrame, os_timing: &mut OsTiming) -> bool {
+    os_timing.wait_frame_remaining().unwrap();
+
+    let frame_duration = os_timing.notify_frame_start().unwrap();
+
+    log::info!("Frame took {:.2} ms", frame_duration.as_secs_f64() * 1000.0);
+
+    let behind = frame_duration > os_timing.target_frame_duration().mul_f32(1.5);
+
+    // Most frames are dropped in turbo mode
+    if !os_timing.should_present() {
+        return behind;
+    }
+
+    frame
+        .present(false)
+        .expect_msg_box("Could not present frame");
+
+    behind
+}
+
+/// Which keys and gamepad buttons press which Game Boy buttons, see [`InputConfig`]
+struct InputMaps {
+    keyboard: InputMap<KeyboardKey>,
+    gamepad: InputMap<GamepadButtons>,
+    turbo: Turbo,
+}
+
+fn os_update(
+    emu: &mut dyn EmulatorHandle,
+    window_factory: &WindowFactory,
+    window_input: &RefCell<WindowInput>,
+    input_maps: &InputMaps,
+    gamepad_input: &mut GamePadInput,
+    haptics: &mut Haptics,
+    feedback: &Sender<FeedbackEvent>,
+) -> bool {
+    if !window_factory.dispatch_window_msgs() {
+        return false;
+    }
+
+    let window_input = window_input.borrow();
+    let keyboard = &input_maps.keyboard;
+
+    let mut button_states = keyboard.buttons(window_input.depressed_keys());
+    let mut turbo_buttons = keyboard.turbo_buttons(window_input.depressed_keys());
+
+    for evt in gamepad_input.poll() {
+        match evt {
+            GamepadEvent::Connected(slot) => {
+                log::info!("Gamepad {} connected", slot + 1);
+                let _ = feedback.send(FeedbackEvent::GamepadConnected);
+            }
+            GamepadEvent::Disconnected(slot) => log::info!("Gamepad {} disconnected", slot + 1),
+            GamepadEvent::Activated(slot) => log::info!("Using gamepad {}", slot + 1),
+        }
+    }
+
+    let pressed = gamepad_input.button_state();
+    button_states |= input_maps.gamepad.buttons(pressed.pressed());
+    turbo_buttons |= input_maps.gamepad.turbo_buttons(pressed.pressed());
+
+    emu.notify_buttons_state(button_states | input_maps.turbo.apply(turbo_buttons));
+
+    if emu.poll_reset_combo() {
+        log::info!("Reset combo (A+B+Select+Start) pressed, resetting at the end of the frame");
+        let _ = feedback.send(FeedbackEvent::Reset);
+    }
+
+    haptics.set_cartridge_rumble(emu.poll_rumble());
+    haptics.update(gamepad_input);
+
+    true
+}
+
+#[cfg(debug_assertions)]
+fn cpu_logger() -> DbgEvtLogger<CpuEvt> {
+    DbgEvtLogger::new()
+}
+
+#[cfg(not(debug_assertions))]
+fn cpu_logger() -> NoDbgLogger {
+    NoDbgLogger
+}
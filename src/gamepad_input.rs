@@ -1,86 +1,208 @@
-//! Support for an Xbox gamepad
+//! Support for Xbox gamepads (via XInput). Any number of them (XInput supports up to
+//! four) can be connected and disconnected while the emulator runs.
 
 use bitflags::bitflags;
+use maboy::frontend::StickConfig;
 use maboy::Buttons;
-use std::mem::MaybeUninit;
+use std::time::{Duration, Instant};
 use winapi::shared::minwindef::DWORD;
 use winapi::shared::winerror::ERROR_SUCCESS;
-use winapi::um::xinput::{XInputGetState, XINPUT_STATE};
+use winapi::um::xinput::{XInputGetState, XInputSetState, XINPUT_STATE, XINPUT_VIBRATION};
 
-/// Used to query the state of a connected Xbox gamepad
-/// Supports only a single device (since it's only a GameBoy... What more do you want?)
-pub struct GamePadInput(DWORD);
+/// XInput supports up to four gamepads, one per slot
+const SLOTS: DWORD = 4;
+
+/// How often empty slots are checked for newly connected gamepads. Microsoft warns that
+/// querying empty slots is slow, so this doesn't happen on every poll.
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Something that happened to a gamepad, by its slot (0 - 3, which is also the player
+/// number that its LED shows, minus 1)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GamepadEvent {
+    Connected(u32),
+    Disconnected(u32),
+    /// This gamepad is now the one in use, see [`GamePadInput::active_gamepad`]
+    Activated(u32),
+}
+
+/// Keeps track of all connected Xbox gamepads. The buttons of all of them are merged,
+/// so any gamepad can be used to play. The left sticks move the D-pad (see
+/// [`GamePadInput::set_stick_config`]).
+pub struct GamePadInput {
+    /// The buttons held on the gamepad in every slot, or `None` if the slot is empty
+    slots: [Option<GamepadButtons>; SLOTS as usize],
+    /// The slot of the gamepad that pressed a button last
+    active: Option<u32>,
+    /// The motor speeds that were set last, so gamepads that are connected later get
+    /// them as well
+    vibration: (f32, f32),
+    stick: StickConfig,
+    next_scan: Instant,
+}
 
 impl GamePadInput {
-    /// Returns the first gamepad that was found, or `None`. Microsoft warns about calling
-    /// this in a tight loop, so I'll do the same.
-    pub fn find_gamepad() -> Option<GamePadInput> {
-        unsafe {
-            let mut input_state: XINPUT_STATE = MaybeUninit::uninit().assume_init();
-
-            for user_index in 0..4 {
-                if ERROR_SUCCESS == XInputGetState(user_index, &mut input_state) {
-                    return Some(GamePadInput(user_index));
+    /// Starts without any gamepads. They are found by the first [`GamePadInput::poll`].
+    pub fn new() -> GamePadInput {
+        GamePadInput {
+            slots: [None; SLOTS as usize],
+            active: None,
+            vibration: (0.0, 0.0),
+            stick: StickConfig::default(),
+            next_scan: Instant::now(),
+        }
+    }
+
+    /// Queries the state of all gamepads and returns what happened to them since the
+    /// last call. Needs to be called regularly (every OS update is fine).
+    pub fn poll(&mut self) -> Vec<GamepadEvent> {
+        let now = Instant::now();
+        let scan = now >= self.next_scan;
+        let mut events = Vec::new();
+
+        if scan {
+            self.next_scan = now + SCAN_INTERVAL;
+        }
+
+        for slot in 0..SLOTS {
+            let previous = self.slots[slot as usize];
+            let was_connected = previous.is_some();
+
+            if !was_connected && !scan {
+                continue;
+            }
+
+            let buttons = query_buttons(slot, &self.stick);
+
+            match (was_connected, buttons) {
+                (false, Some(_)) => {
+                    events.push(GamepadEvent::Connected(slot));
+                    set_vibration(slot, self.vibration);
                 }
+                (true, None) => events.push(GamepadEvent::Disconnected(slot)),
+                _ => (),
+            }
+
+            // Only newly pressed buttons count, otherwise two gamepads that hold buttons
+            // (or have drifting sticks) would take turns being active
+            let pressed = buttons.map_or(GamepadButtons::empty(), |buttons| {
+                buttons & !previous.unwrap_or(GamepadButtons::empty())
+            });
+
+            if !pressed.is_empty() && self.active != Some(slot) {
+                self.active = Some(slot);
+                events.push(GamepadEvent::Activated(slot));
+            }
+
+            self.slots[slot as usize] = buttons;
+        }
+
+        // Until a button is pressed, the first gamepad is the one in use
+        if !self
+            .active
+            .is_some_and(|slot| self.slots[slot as usize].is_some())
+        {
+            self.active = self.connected_gamepads().next();
+
+            if let Some(slot) = self.active {
+                events.push(GamepadEvent::Activated(slot));
+            }
+        }
+
+        events
+    }
+
+    /// The buttons that are held on any of the gamepads (as of the last
+    /// [`GamePadInput::poll`]), including the D-pad buttons that the left sticks press.
+    /// They are mapped to Game Boy buttons via an [`maboy::frontend::InputMap`], see
+    /// [`GamepadButtons::pressed`].
+    pub fn button_state(&self) -> GamepadButtons {
+        self.slots
+            .iter()
+            .flatten()
+            .fold(GamepadButtons::empty(), |acc, &buttons| acc | buttons)
+    }
+
+    /// The slot of the gamepad that is in use, i.e. the one that pressed a button last,
+    /// or `None` if there are no gamepads
+    pub fn active_gamepad(&self) -> Option<u32> {
+        self.active
+    }
+
+    /// How the left stick is mapped to the D-pad. Takes effect with the next
+    /// [`GamePadInput::poll`].
+    pub fn set_stick_config(&mut self, stick: StickConfig) {
+        self.stick = stick;
+    }
+
+    /// The slots of all connected gamepads
+    pub fn connected_gamepads(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..SLOTS).filter(move |&slot| self.slots[slot as usize].is_some())
+    }
+
+    /// Sets the speed of the low-frequency (left) and high-frequency (right) rumble
+    /// motors of all gamepads, each between 0.0 and 1.0. The motors keep running until
+    /// this is called again.
+    pub fn set_vibration(&mut self, low_freq: f32, high_freq: f32) {
+        self.vibration = (low_freq, high_freq);
+
+        for slot in 0..SLOTS {
+            if self.slots[slot as usize].is_some() {
+                set_vibration(slot, self.vibration);
             }
+        }
+    }
+}
+
+/// The buttons held on the gamepad in the given slot, with the D-pad buttons that its
+/// left stick presses, or `None` if there is no gamepad
+fn query_buttons(slot: DWORD, stick: &StickConfig) -> Option<GamepadButtons> {
+    let gamepad = unsafe {
+        let mut input_state: XINPUT_STATE = std::mem::zeroed();
 
-            None
+        if ERROR_SUCCESS != XInputGetState(slot, &mut input_state) {
+            return None;
+        }
+
+        input_state.Gamepad
+    };
+
+    // The stick axes go from -32768 to 32767
+    let axis = |value: i16| (value as f32 / i16::MAX as f32).max(-1.0);
+    let dpad = stick.dpad(axis(gamepad.sThumbLX), axis(gamepad.sThumbLY));
+
+    let mut buttons = GamepadButtons::from_bits_truncate(gamepad.wButtons);
+
+    for &(direction, dpad_button) in &[
+        (Buttons::UP, GamepadButtons::DPAD_UP),
+        (Buttons::DOWN, GamepadButtons::DPAD_DOWN),
+        (Buttons::LEFT, GamepadButtons::DPAD_LEFT),
+        (Buttons::RIGHT, GamepadButtons::DPAD_RIGHT),
+    ] {
+        if dpad.contains(direction) {
+            buttons.insert(dpad_button);
         }
     }
 
-    /// Queries which buttons on the gamepad are pressed and directly converts them
-    /// to the corresponding Game Boy buttons. This might be a bit overkill... By
-    /// converting them directly, we prevent the user from re-mapping their controls.
-    /// This will probably be changed in the future.
-    pub fn button_state(&self) -> Buttons {
-        let gamepad_buttons = unsafe {
-            let mut input_state: XINPUT_STATE = MaybeUninit::uninit().assume_init();
-            XInputGetState(self.0, &mut input_state);
-            GamepadButtons::from_bits_unchecked(input_state.Gamepad.wButtons)
-        };
-
-        let mut emu_buttons = Buttons::empty();
-
-        emu_buttons.set(
-            Buttons::LEFT,
-            gamepad_buttons.contains(GamepadButtons::DPAD_LEFT),
-        );
-
-        emu_buttons.set(
-            Buttons::UP,
-            gamepad_buttons.contains(GamepadButtons::DPAD_UP),
-        );
-
-        emu_buttons.set(
-            Buttons::RIGHT,
-            gamepad_buttons.contains(GamepadButtons::DPAD_RIGHT),
-        );
-
-        emu_buttons.set(
-            Buttons::DOWN,
-            gamepad_buttons.contains(GamepadButtons::DPAD_DOWN),
-        );
-
-        emu_buttons.set(Buttons::A, gamepad_buttons.contains(GamepadButtons::B));
-
-        emu_buttons.set(Buttons::B, gamepad_buttons.contains(GamepadButtons::A));
-
-        emu_buttons.set(
-            Buttons::START,
-            gamepad_buttons.contains(GamepadButtons::START),
-        );
-
-        emu_buttons.set(
-            Buttons::SELECT,
-            gamepad_buttons.contains(GamepadButtons::BACK),
-        );
-
-        emu_buttons
+    Some(buttons)
+}
+
+fn set_vibration(slot: DWORD, (low_freq, high_freq): (f32, f32)) {
+    let to_speed = |value: f32| (value.max(0.0).min(1.0) * u16::MAX as f32) as u16;
+
+    let mut vibration = XINPUT_VIBRATION {
+        wLeftMotorSpeed: to_speed(low_freq),
+        wRightMotorSpeed: to_speed(high_freq),
+    };
+
+    unsafe {
+        XInputSetState(slot, &mut vibration);
     }
 }
 
 bitflags! {
-    struct GamepadButtons: u16 {
+    /// The buttons of an Xbox gamepad
+    pub struct GamepadButtons: u16 {
         const DPAD_UP = 0x0001;
         const DPAD_DOWN = 0x0002;
         const DPAD_LEFT = 0x0004;
@@ -97,3 +219,47 @@ bitflags! {
         const Y = 0x8000;
     }
 }
+
+/// The names of the buttons in the input config (see [`maboy::frontend::InputConfig`])
+const BUTTON_NAMES: [(GamepadButtons, &str); 14] = [
+    (GamepadButtons::DPAD_UP, "DPAD_UP"),
+    (GamepadButtons::DPAD_DOWN, "DPAD_DOWN"),
+    (GamepadButtons::DPAD_LEFT, "DPAD_LEFT"),
+    (GamepadButtons::DPAD_RIGHT, "DPAD_RIGHT"),
+    (GamepadButtons::START, "START"),
+    (GamepadButtons::BACK, "BACK"),
+    (GamepadButtons::LEFT_THUMB, "LEFT_THUMB"),
+    (GamepadButtons::RIGHT_THUMB, "RIGHT_THUMB"),
+    (GamepadButtons::LEFT_SHOULDER, "LEFT_SHOULDER"),
+    (GamepadButtons::RIGHT_SHOULDER, "RIGHT_SHOULDER"),
+    (GamepadButtons::A, "A"),
+    (GamepadButtons::B, "B"),
+    (GamepadButtons::X, "X"),
+    (GamepadButtons::Y, "Y"),
+];
+
+impl GamepadButtons {
+    /// The single button with the given name in the input config, case-insensitive
+    pub fn from_name(name: &str) -> Option<GamepadButtons> {
+        BUTTON_NAMES
+            .iter()
+            .find(|(_, button_name)| button_name.eq_ignore_ascii_case(name))
+            .map(|&(button, _)| button)
+    }
+
+    /// The name of a single button in the input config
+    pub fn name(self) -> Option<String> {
+        BUTTON_NAMES
+            .iter()
+            .find(|&&(button, _)| button == self)
+            .map(|(_, name)| (*name).to_owned())
+    }
+
+    /// The single buttons that are contained in `self`, one by one
+    pub fn pressed(self) -> impl Iterator<Item = GamepadButtons> {
+        BUTTON_NAMES
+            .iter()
+            .map(|&(button, _)| button)
+            .filter(move |&button| self.contains(button))
+    }
+}
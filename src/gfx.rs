@@ -4,13 +4,21 @@
 //!
 //! For an example on how to use these structs, please consult the crate root documentation.
 
+//!
+//! The frame of the Game Boy is uploaded into a texture of its own, which is then drawn
+//! into the window by a tiny shader, scaled according to a [`ScalingMode`] and with an
+//! optional [`ScreenFilter`].
+
 use super::hresult_error::*;
+use super::scaling::{ScalingMode, ScreenFilter};
 use super::window::Window;
 use maboy::MemPixel;
+use std::ffi::{c_void, CString};
 use std::marker::PhantomData;
-use std::mem::MaybeUninit;
+use std::mem::{self, MaybeUninit};
 use std::pin::Pin;
 use std::ptr;
+use std::slice;
 use winapi::shared::dxgi::*;
 use winapi::shared::dxgiformat::*;
 use winapi::shared::minwindef::*;
@@ -18,10 +26,79 @@ use winapi::shared::winerror::*;
 use winapi::shared::{dxgi1_2::*, dxgitype::*};
 use winapi::um::d3d11::*;
 use winapi::um::d3dcommon::*;
+use winapi::um::d3dcompiler::*;
 use winapi::um::unknwnbase::IUnknown;
 use winapi::Interface;
 use wio::com::ComPtr;
 
+/// Draws the frame texture into the viewport as a quad (a triangle strip of 4 vertices
+/// that is generated without any vertex buffer) and applies the filters
+const SHADER_SOURCE: &str = r#"
+cbuffer Params : register(b0)
+{
+    float2 frame_size;
+    float2 viewport_size;
+    uint filter;
+};
+
+Texture2D frame : register(t0);
+SamplerState frame_sampler : register(s0);
+
+struct VsOutput
+{
+    float4 pos : SV_Position;
+    float2 uv : TEXCOORD0;
+};
+
+VsOutput vs_main(uint id : SV_VertexID)
+{
+    VsOutput output;
+    output.uv = float2(id & 1, id >> 1);
+    output.pos = float4(output.uv.x * 2.0 - 1.0, 1.0 - output.uv.y * 2.0, 0.0, 1.0);
+    return output;
+}
+
+float4 ps_main(VsOutput input) : SV_Target
+{
+    float3 color = frame.Sample(frame_sampler, input.uv).rgb;
+
+    // Position within the Game Boy pixel and window pixels per Game Boy pixel
+    float2 cell = frac(input.uv * frame_size);
+    float2 scale = viewport_size / frame_size;
+
+    // Filters need a few window pixels per Game Boy pixel, so they fade in with the scale
+    float strength = saturate((min(scale.x, scale.y) - 1.0) / 3.0);
+
+    if (filter == 1)
+    {
+        // Scanlines
+        color *= 1.0 - 0.4 * strength * step(0.6, cell.y);
+    }
+    else if (filter == 2)
+    {
+        // LCD grid
+        float2 edge = step(1.0 - 1.0 / scale, cell);
+        color *= 1.0 - 0.3 * strength * max(edge.x, edge.y);
+    }
+
+    return float4(color, 1.0);
+}
+"#;
+
+/// Layout of the `Params` constant buffer in [`SHADER_SOURCE`]. Constant buffers have
+/// to be a multiple of 16 bytes in size.
+#[repr(C)]
+struct ShaderParams {
+    frame_size: [f32; 2],
+    viewport_size: [f32; 2],
+    filter: u32,
+    _padding: [u32; 3],
+}
+
+/// What the area around the frame is filled with, unless [`GfxFrame::clear`] says
+/// otherwise
+const BAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
 /// Usually the first graphics object to initialize. Represents a physical graphics
 /// device and provides some methods to do more useful things (like creating a swap-chain
 /// for a window).
@@ -89,19 +166,20 @@ impl GfxDevice {
         }
     }
 
-    /// Creates a swap-chain and backbuffer for a given window
-    pub fn create_gfx_window<I: Into<Option<u32>>>(
+    /// Creates a swap-chain for a given window, which always has the size of its client
+    /// area (see [`GfxWindow::resize`]), and a texture for frames of the given size
+    pub fn create_gfx_window(
         &self,
         window: &Pin<Box<Window>>,
-        width: I,
-        height: I,
+        frame_width: u32,
+        frame_height: u32,
     ) -> Result<GfxWindow, HResultError> {
         unsafe {
-            // Create swap-chain
+            // Create swap-chain (a size of 0 means the size of the window)
 
             let scd = DXGI_SWAP_CHAIN_DESC1 {
-                Width: width.into().unwrap_or(0),
-                Height: height.into().unwrap_or(0),
+                Width: 0,
+                Height: 0,
                 // For a flip-model swap chain (that is, a swap chain that has the DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL value
                 // set in the SwapEffect member), you must set the Format member to DXGI_FORMAT_R16G16B16A16_FLOAT,
                 // DXGI_FORMAT_B8G8R8A8_UNORM, or DXGI_FORMAT_R8G8B8A8_UNORM;
@@ -113,7 +191,7 @@ impl GfxDevice {
                 },
                 BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
                 BufferCount: 2,
-                Scaling: DXGI_SCALING_STRETCH,
+                Scaling: DXGI_SCALING_NONE,
                 SwapEffect: DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL,
                 AlphaMode: DXGI_ALPHA_MODE_UNSPECIFIED,
                 Flags: DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING,
@@ -132,56 +210,200 @@ impl GfxDevice {
                 .into_result()?;
             let swap_chain = ComPtr::from_raw(swap_chain); //IDXGISwapChain1
 
-            // Get backbuffer from swap-chain
+            // Create the texture that frames are uploaded into
 
-            let mut backbuffer = ptr::null_mut();
-            swap_chain
-                .GetBuffer(0, &ID3D11Texture2D::uuidof(), &mut backbuffer)
+            let texture_desc = D3D11_TEXTURE2D_DESC {
+                Width: frame_width,
+                Height: frame_height,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: D3D11_BIND_SHADER_RESOURCE,
+                CPUAccessFlags: 0,
+                MiscFlags: 0,
+            };
+
+            let mut frame_texture = ptr::null_mut();
+            self.d
+                .CreateTexture2D(&texture_desc, ptr::null(), &mut frame_texture)
                 .into_result()?;
-            let backbuffer = ComPtr::from_raw(backbuffer as *mut ID3D11Texture2D);
+            let frame_texture = ComPtr::from_raw(frame_texture);
 
-            let mut backbuffer_desc: D3D11_TEXTURE2D_DESC = MaybeUninit::zeroed().assume_init();
-            backbuffer.GetDesc(&mut backbuffer_desc);
+            let mut frame_srv = ptr::null_mut();
+            self.d
+                .CreateShaderResourceView(
+                    frame_texture.as_raw() as *mut ID3D11Resource,
+                    ptr::null(),
+                    &mut frame_srv,
+                )
+                .into_result()?;
+            let frame_srv = ComPtr::from_raw(frame_srv);
+
+            // Create samplers for both kinds of scaling
 
-            // Create viewport from backbuffer dimensions
+            let point_sampler = self.create_sampler(D3D11_FILTER_MIN_MAG_MIP_POINT)?;
+            let linear_sampler = self.create_sampler(D3D11_FILTER_MIN_MAG_MIP_LINEAR)?;
 
-            let mut viewport: D3D11_VIEWPORT = MaybeUninit::zeroed().assume_init();
-            viewport.Height = backbuffer_desc.Height as f32;
-            viewport.Width = backbuffer_desc.Width as f32;
-            viewport.MinDepth = 0.0;
-            viewport.MaxDepth = 1.0;
+            // Compile shaders
 
-            // Create RTV for backbuffer
-            let mut backbuffer_rtv = ptr::null_mut();
+            let vs_code = compile_shader("vs_main", "vs_4_0")?;
+            let mut vertex_shader = ptr::null_mut();
             self.d
-                .CreateRenderTargetView(
-                    backbuffer.as_raw() as *mut ID3D11Resource, // TODO: .up::<ID3D11Resource>() ???
-                    ptr::null(),
-                    &mut backbuffer_rtv,
+                .CreateVertexShader(
+                    vs_code.GetBufferPointer(),
+                    vs_code.GetBufferSize(),
+                    ptr::null_mut(),
+                    &mut vertex_shader,
                 )
                 .into_result()?;
-            let backbuffer_rtv = ComPtr::from_raw(backbuffer_rtv);
+            let vertex_shader = ComPtr::from_raw(vertex_shader);
 
-            Ok(GfxWindow {
+            let ps_code = compile_shader("ps_main", "ps_4_0")?;
+            let mut pixel_shader = ptr::null_mut();
+            self.d
+                .CreatePixelShader(
+                    ps_code.GetBufferPointer(),
+                    ps_code.GetBufferSize(),
+                    ptr::null_mut(),
+                    &mut pixel_shader,
+                )
+                .into_result()?;
+            let pixel_shader = ComPtr::from_raw(pixel_shader);
+
+            // Create constant buffer for the shader parameters
+
+            let params_desc = D3D11_BUFFER_DESC {
+                ByteWidth: mem::size_of::<ShaderParams>() as u32,
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: D3D11_BIND_CONSTANT_BUFFER,
+                CPUAccessFlags: 0,
+                MiscFlags: 0,
+                StructureByteStride: 0,
+            };
+
+            let mut params = ptr::null_mut();
+            self.d
+                .CreateBuffer(&params_desc, ptr::null(), &mut params)
+                .into_result()?;
+            let params = ComPtr::from_raw(params);
+
+            let mut gfx_window = GfxWindow {
+                device: self.d.clone(),
                 device_context: self.dc.clone(),
                 swap_chain,
-                backbuffer,
-                backbuffer_rtv,
-                viewport,
+                backbuffer_rtv: None,
+                backbuffer_size: (0, 0),
+                frame_texture,
+                frame_srv,
+                frame_size: (frame_width, frame_height),
+                point_sampler,
+                linear_sampler,
+                vertex_shader,
+                pixel_shader,
+                params,
+                scaling: ScalingMode::default(),
+                filter: ScreenFilter::default(),
                 _window: PhantomData,
-            })
+            };
+
+            gfx_window.create_backbuffer_rtv()?;
+
+            Ok(gfx_window)
         }
     }
+
+    unsafe fn create_sampler(
+        &self,
+        filter: D3D11_FILTER,
+    ) -> Result<ComPtr<ID3D11SamplerState>, HResultError> {
+        let sampler_desc = D3D11_SAMPLER_DESC {
+            Filter: filter,
+            AddressU: D3D11_TEXTURE_ADDRESS_CLAMP,
+            AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+            AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+            MipLODBias: 0.0,
+            MaxAnisotropy: 1,
+            ComparisonFunc: D3D11_COMPARISON_NEVER,
+            BorderColor: [0.0; 4],
+            MinLOD: 0.0,
+            MaxLOD: D3D11_FLOAT32_MAX,
+        };
+
+        let mut sampler = ptr::null_mut();
+        self.d
+            .CreateSamplerState(&sampler_desc, &mut sampler)
+            .into_result()?;
+
+        Ok(ComPtr::from_raw(sampler))
+    }
+}
+
+/// Compiles an entry point of [`SHADER_SOURCE`]. Compiler errors are logged, since an
+/// HRESULT alone doesn't say much about them.
+unsafe fn compile_shader(
+    entry_point: &str,
+    target: &str,
+) -> Result<ComPtr<ID3DBlob>, HResultError> {
+    let entry_point = CString::new(entry_point).unwrap();
+    let target = CString::new(target).unwrap();
+
+    let mut code = ptr::null_mut();
+    let mut errors = ptr::null_mut();
+
+    let result = D3DCompile(
+        SHADER_SOURCE.as_ptr() as *const c_void,
+        SHADER_SOURCE.len(),
+        ptr::null(),
+        ptr::null(),
+        ptr::null_mut(),
+        entry_point.as_ptr(),
+        target.as_ptr(),
+        D3DCOMPILE_OPTIMIZATION_LEVEL3,
+        0,
+        &mut code,
+        &mut errors,
+    );
+
+    if !errors.is_null() {
+        let errors = ComPtr::from_raw(errors);
+        let messages = slice::from_raw_parts(
+            errors.GetBufferPointer() as *const u8,
+            errors.GetBufferSize(),
+        );
+
+        log::error!("Shader compiler: {}", String::from_utf8_lossy(messages));
+    }
+
+    result.into_result()?;
+
+    Ok(ComPtr::from_raw(code))
 }
 
-/// The swap-chain and backbuffer for a window. Provides [`GfxWindow::next_frame`],
-/// which is used to display content.
+/// The swap-chain and backbuffer for a window, as well as everything that is needed to
+/// draw frames into it. Provides [`GfxWindow::next_frame`], which is used to display
+/// content.
 pub struct GfxWindow<'w> {
+    device: ComPtr<ID3D11Device>,
     device_context: ComPtr<ID3D11DeviceContext>,
     swap_chain: ComPtr<IDXGISwapChain1>,
-    backbuffer: ComPtr<ID3D11Texture2D>,
-    backbuffer_rtv: ComPtr<ID3D11RenderTargetView>,
-    viewport: D3D11_VIEWPORT,
+    /// `None` only while the backbuffer is resized
+    backbuffer_rtv: Option<ComPtr<ID3D11RenderTargetView>>,
+    backbuffer_size: (u32, u32),
+    frame_texture: ComPtr<ID3D11Texture2D>,
+    frame_srv: ComPtr<ID3D11ShaderResourceView>,
+    frame_size: (u32, u32),
+    point_sampler: ComPtr<ID3D11SamplerState>,
+    linear_sampler: ComPtr<ID3D11SamplerState>,
+    vertex_shader: ComPtr<ID3D11VertexShader>,
+    pixel_shader: ComPtr<ID3D11PixelShader>,
+    params: ComPtr<ID3D11Buffer>,
+    scaling: ScalingMode,
+    filter: ScreenFilter,
     _window: PhantomData<&'w ()>,
 }
 
@@ -190,57 +412,173 @@ impl<'w> GfxWindow<'w> {
     /// presented or discarded before the next frame is created. This is upheld
     /// by the borrow checker at compile time, so don't worry about it.
     pub fn next_frame(&mut self) -> GfxFrame<'_, 'w> {
-        // Note: Seems like we don't need this stuff. I'll leave it out for now
+        GfxFrame {
+            window: self,
+            bar_color: BAR_COLOR,
+        }
+    }
 
-        // Might need to set depth-stencil in here at some point
-        // self.device
-        //     .dc
-        //     .OMSetRenderTargets(1, &self.backbuffer_rtv.as_raw(), ptr::null_mut());
+    /// Resizes the backbuffer to the new size of the window's client area. Call this
+    /// whenever the window receives `WM_SIZE` (see [`crate::client_size_from_msg`]).
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), HResultError> {
+        // Minimized windows have a size of 0, which the swap-chain doesn't accept
+        if width == 0 || height == 0 || (width, height) == self.backbuffer_size {
+            return Ok(());
+        }
 
-        // self.device.dc.RSSetViewports(1, &self.viewport);
+        unsafe {
+            // All references to the backbuffer have to be gone before resizing it
+            self.device_context
+                .OMSetRenderTargets(0, ptr::null(), ptr::null_mut());
+            self.backbuffer_rtv = None;
+
+            self.swap_chain
+                .ResizeBuffers(
+                    0,
+                    width,
+                    height,
+                    DXGI_FORMAT_UNKNOWN,
+                    DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING,
+                )
+                .into_result()?;
+
+            self.create_backbuffer_rtv()
+        }
+    }
+
+    pub fn set_scaling_mode(&mut self, scaling: ScalingMode) {
+        self.scaling = scaling;
+    }
 
-        GfxFrame(self)
+    pub fn scaling_mode(&self) -> ScalingMode {
+        self.scaling
+    }
+
+    pub fn set_filter(&mut self, filter: ScreenFilter) {
+        self.filter = filter;
+    }
+
+    pub fn filter(&self) -> ScreenFilter {
+        self.filter
+    }
+
+    /// Creates the render target view for the current backbuffer and remembers its size
+    unsafe fn create_backbuffer_rtv(&mut self) -> Result<(), HResultError> {
+        let mut backbuffer = ptr::null_mut();
+        self.swap_chain
+            .GetBuffer(0, &ID3D11Texture2D::uuidof(), &mut backbuffer)
+            .into_result()?;
+        let backbuffer = ComPtr::from_raw(backbuffer as *mut ID3D11Texture2D);
+
+        let mut backbuffer_desc: D3D11_TEXTURE2D_DESC = MaybeUninit::zeroed().assume_init();
+        backbuffer.GetDesc(&mut backbuffer_desc);
+
+        let mut backbuffer_rtv = ptr::null_mut();
+        self.device
+            .CreateRenderTargetView(
+                backbuffer.as_raw() as *mut ID3D11Resource,
+                ptr::null(),
+                &mut backbuffer_rtv,
+            )
+            .into_result()?;
+
+        self.backbuffer_rtv = Some(ComPtr::from_raw(backbuffer_rtv));
+        self.backbuffer_size = (backbuffer_desc.Width, backbuffer_desc.Height);
+
+        Ok(())
+    }
+
+    /// Draws the frame texture into the backbuffer, surrounded by bars of the given color
+    unsafe fn draw(&self, bar_color: &[f32; 4]) {
+        let backbuffer_rtv = match &self.backbuffer_rtv {
+            Some(rtv) => rtv,
+            None => return,
+        };
+
+        let dc = &self.device_context;
+
+        // The flip model unbinds the render target on every present, so this has to be
+        // done for every frame
+        dc.OMSetRenderTargets(1, &backbuffer_rtv.as_raw(), ptr::null_mut());
+        dc.ClearRenderTargetView(backbuffer_rtv.as_raw(), bar_color);
+
+        let (x, y, width, height) = self.scaling.fit(self.frame_size, self.backbuffer_size);
+
+        let viewport = D3D11_VIEWPORT {
+            TopLeftX: x as f32,
+            TopLeftY: y as f32,
+            Width: width as f32,
+            Height: height as f32,
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        };
+        dc.RSSetViewports(1, &viewport);
+
+        let params = ShaderParams {
+            frame_size: [self.frame_size.0 as f32, self.frame_size.1 as f32],
+            viewport_size: [width as f32, height as f32],
+            filter: self.filter.shader_id(),
+            _padding: [0; 3],
+        };
+        dc.UpdateSubresource(
+            self.params.as_raw() as *mut ID3D11Resource,
+            0,
+            ptr::null(),
+            &params as *const _ as *const c_void,
+            0,
+            0,
+        );
+
+        let sampler = if self.scaling.is_bilinear() {
+            &self.linear_sampler
+        } else {
+            &self.point_sampler
+        };
+
+        dc.IASetInputLayout(ptr::null_mut());
+        dc.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP);
+        dc.VSSetShader(self.vertex_shader.as_raw(), ptr::null(), 0);
+        dc.PSSetShader(self.pixel_shader.as_raw(), ptr::null(), 0);
+        dc.PSSetShaderResources(0, 1, &self.frame_srv.as_raw());
+        dc.PSSetSamplers(0, 1, &sampler.as_raw());
+        dc.PSSetConstantBuffers(0, 1, &self.params.as_raw());
+
+        dc.Draw(4, 0);
     }
 }
 
 /// A single frame that is tied to a window
-pub struct GfxFrame<'a, 'w>(&'a mut GfxWindow<'w>);
+pub struct GfxFrame<'a, 'w> {
+    window: &'a mut GfxWindow<'w>,
+    bar_color: [f32; 4],
+}
 
 impl GfxFrame<'_, '_> {
-    /// Clears the entire frame to the given RGBA color
+    /// Sets the RGBA color of the area around the game frame (black by default)
     pub fn clear(&mut self, color: &[f32; 4]) {
-        unsafe {
-            // Might need this someday:
-            // m_d3dContext->ClearDepthStencilView(m_depthStencilView.Get(),
-            //     D3D11_CLEAR_DEPTH | D3D11_CLEAR_STENCIL, 1.0f, 0);
-
-            // Appararently, on Xbox One, this needs to go BEFORE OMSetRenderTargets:
-            // https://github.com/microsoft/DirectXTK/wiki/The-basic-game-loop
-            self.0
-                .device_context
-                .ClearRenderTargetView(self.0.backbuffer_rtv.as_raw(), color);
-        }
+        self.bar_color = *color;
     }
 
     /// Uploads frame data from main memory to the GPU. This method will panic
-    /// if your frame data doesn't have the correct length to fill the backbuffer
-    /// of your window.
+    /// if your frame data doesn't have the frame size that the window was created with.
     ///
     /// TODO: This kind of sucks. This API should not panic.
     pub fn copy_from_slice(&mut self, data: &[MemPixel]) {
+        let (width, height) = self.window.frame_size;
+
         unsafe {
             assert_eq!(
                 data.len(),
-                self.0.viewport.Width as usize * self.0.viewport.Height as usize,
-                "Slice does not have the exact number of pixels that the window backbuffer requires"
+                width as usize * height as usize,
+                "Slice does not have the exact number of pixels of a frame"
             );
 
-            self.0.device_context.UpdateSubresource(
-                self.0.backbuffer.as_raw() as *mut ID3D11Resource,
+            self.window.device_context.UpdateSubresource(
+                self.window.frame_texture.as_raw() as *mut ID3D11Resource,
                 0,
                 ptr::null(),
-                data as *const _ as *const std::ffi::c_void,
-                self.0.viewport.Width as u32 * 4,
+                data as *const _ as *const c_void,
+                width * 4,
                 0,
             );
         }
@@ -252,6 +590,8 @@ impl GfxFrame<'_, '_> {
     /// If you want to wait for VSync intervals, set the blocking parameter to true.
     pub fn present(self, blocking: bool) -> Result<(), HResultError> {
         unsafe {
+            self.window.draw(&self.bar_color);
+
             // TODO: Read up on whatever sync intervals are for DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL
             // TODO: Think about DXGI_PRESENT_DO_NOT_WAIT
             // TODO: Really read up on the tearing docs at https://docs.microsoft.com/en-us/windows/win32/direct3ddxgi/dxgi-present
@@ -263,7 +603,7 @@ impl GfxFrame<'_, '_> {
             };
 
             let result = self
-                .0
+                .window
                 .swap_chain
                 .Present(sync_interval, flags)
                 .into_result();
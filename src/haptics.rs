@@ -0,0 +1,133 @@
+//! Short rumble pulses on the gamepad as feedback for things that happen in the
+//! frontend, like savestates or link cable connections.
+//!
+//! This is pure UI feedback and has nothing to do with the rumble motor that some
+//! cartridges have. To keep the two apart, UI pulses only use the small
+//! high-frequency motor of the gamepad, while the cartridge motor (see
+//! [`Haptics::set_cartridge_rumble`]) drives the big low-frequency one.
+
+use crate::GamePadInput;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+/// Something the user should be notified about. Events are sent from anywhere in
+/// the frontend via the [`Sender`] returned by [`Haptics::new`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FeedbackEvent {
+    StateSaved,
+    StateLoaded,
+    LinkConnected,
+    Reset,
+    GamepadConnected,
+}
+
+impl FeedbackEvent {
+    /// How long the pulse for this event lasts
+    fn pulse_duration(self) -> Duration {
+        match self {
+            FeedbackEvent::StateSaved | FeedbackEvent::StateLoaded => Duration::from_millis(80),
+            FeedbackEvent::Reset | FeedbackEvent::GamepadConnected => Duration::from_millis(150),
+            FeedbackEvent::LinkConnected => Duration::from_millis(300),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct HapticsConfig {
+    pub enabled: bool,
+    /// Motor speed of the pulses, between 0.0 and 1.0
+    pub strength: f32,
+}
+
+impl Default for HapticsConfig {
+    fn default() -> Self {
+        HapticsConfig {
+            enabled: true,
+            strength: 0.5,
+        }
+    }
+}
+
+/// Speed of the low-frequency motor while the cartridge rumbles. The motors in rumble
+/// cartridges are quite strong, so this is full speed.
+const CARTRIDGE_RUMBLE_STRENGTH: f32 = 1.0;
+
+/// Turns [`FeedbackEvent`]s into rumble pulses. Lives in the input layer, since that's
+/// where the gamepad is.
+pub struct Haptics {
+    config: HapticsConfig,
+    events: Receiver<FeedbackEvent>,
+    /// When the current pulse ends, if there is one
+    pulse_end: Option<Instant>,
+    cartridge_rumble: bool,
+    /// The motor speeds (low, high) that were last sent to the gamepad
+    motor_speeds: (f32, f32),
+}
+
+impl Haptics {
+    /// Creates the haptics layer and the channel through which events are reported to it
+    pub fn new(config: HapticsConfig) -> (Haptics, Sender<FeedbackEvent>) {
+        let (sender, events) = mpsc::channel();
+
+        let haptics = Haptics {
+            config,
+            events,
+            pulse_end: None,
+            cartridge_rumble: false,
+            motor_speeds: (0.0, 0.0),
+        };
+
+        (haptics, sender)
+    }
+
+    pub fn config(&self) -> HapticsConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: HapticsConfig) {
+        self.config = config;
+    }
+
+    /// Whether the rumble motor of the cartridge is running (see
+    /// [`maboy::Emulator::poll_rumble`]). Takes effect with the next [`Haptics::update`].
+    pub fn set_cartridge_rumble(&mut self, rumble: bool) {
+        self.cartridge_rumble = rumble;
+    }
+
+    /// Handles all pending events and stops pulses that are over. Needs to be called
+    /// regularly (every OS update is fine), even without a gamepad, so events don't
+    /// pile up.
+    pub fn update(&mut self, gamepads: &mut GamePadInput) {
+        let now = Instant::now();
+
+        for evt in self.events.try_iter() {
+            log::debug!("Feedback event: {:?}", evt);
+
+            if self.config.enabled {
+                let end = now + evt.pulse_duration();
+                self.pulse_end = Some(self.pulse_end.map_or(end, |current| current.max(end)));
+            }
+        }
+
+        let high_freq = match self.pulse_end {
+            Some(end) if end > now => self.config.strength,
+            Some(_) => {
+                self.pulse_end = None;
+                0.0
+            }
+            None => 0.0,
+        };
+
+        let low_freq = if self.config.enabled && self.cartridge_rumble {
+            CARTRIDGE_RUMBLE_STRENGTH
+        } else {
+            0.0
+        };
+
+        // XInput calls aren't free, and this runs a few hundred times per second
+        if (low_freq, high_freq) != self.motor_speeds {
+            gamepads.set_vibration(low_freq, high_freq);
+            self.motor_speeds = (low_freq, high_freq);
+        }
+    }
+}
@@ -1,23 +1,34 @@
-//! This crate contains the Windows (DirectX 11) frontend for the 
+//! This crate contains the Windows (DirectX 11) frontend for the
 //! [Maboy Gameboy Emulator](https://github.com/1HPorange/maboy).
 //! It handles window management, input and graphics for the emulator backend.
+//!
+//! Everything in here is Windows-only, so the crate is empty on other platforms.
+
+#![cfg(windows)]
 
 mod expect_msg_box;
 mod gamepad_input;
 mod gfx;
+mod haptics;
 mod hresult_error;
 mod open_file_dialog;
 mod os_timing;
+mod scaling;
 mod util;
 mod window;
 mod window_factory;
 mod window_input;
 
 pub use expect_msg_box::ExpectMsgBox;
-pub use gamepad_input::GamePadInput;
+pub use gamepad_input::{GamePadInput, GamepadButtons, GamepadEvent};
 pub use gfx::{GfxDevice, GfxFrame, GfxWindow};
+pub use haptics::{FeedbackEvent, Haptics, HapticsConfig};
 pub use open_file_dialog::{open_file_dialog, FileFilter};
 pub use os_timing::OsTiming;
-pub use window::{MsgHandler, MsgHandlerResult, Window};
+pub use scaling::{ScalingMode, ScreenFilter};
+pub use window::{
+    client_size_from_msg, dropped_file_from_msg, menu_item_from_msg, MenuItem, MsgHandler,
+    MsgHandlerResult, Window,
+};
 pub use window_factory::WindowFactory;
 pub use window_input::{KeyboardKey, WindowInput};
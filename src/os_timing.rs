@@ -5,10 +5,15 @@
 //!
 //! MaBoy uses the Win32 API `WaitableTimer` to achieve this. Although the resolution
 //! is somewhat low, practical results show that it is sufficient for our purposes.
+//!
+//! The frame rate can be scaled with a speed multiplier (see [`OsTiming::set_speed`]),
+//! or the throttle can be turned off completely (see [`OsTiming::set_turbo`]). In turbo
+//! mode, only a few frames per second are worth presenting, which
+//! [`OsTiming::should_present`] decides.
 
 use std::mem::{self, MaybeUninit};
 use std::ptr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use winapi::shared::minwindef::{FALSE, TRUE};
 use winapi::um::errhandlingapi::GetLastError;
 use winapi::um::handleapi::CloseHandle;
@@ -18,8 +23,18 @@ use winapi::um::winbase::{INFINITE, WAIT_FAILED};
 use winapi::um::winnt::HANDLE;
 use winapi::um::winnt::LARGE_INTEGER;
 
+/// How often frames are presented in turbo mode
+const TURBO_PRESENT_INTERVAL: Duration = Duration::from_nanos(16_666_667);
+
 /// Provides access to operating system level timing functionality
 pub struct OsTiming {
+    /// The frame rate at a speed of 1.0
+    target_frame_rate: f64,
+    speed: f32,
+    turbo: bool,
+    /// When the last frame was presented in turbo mode
+    last_present: Instant,
+    /// In units of the QueryPerformanceCounter, already scaled by the speed
     target_frame_duration: i64,
     waitable_timer: HANDLE,
     /// Frequency of the QueryPerformanceCounter
@@ -54,19 +69,78 @@ impl OsTiming {
             }
 
             let mut os_timing = OsTiming {
-                target_frame_duration: ((1.0 / target_frame_rate) * *qpc_freq.QuadPart() as f64)
-                    as i64,
+                target_frame_rate,
+                speed: 1.0,
+                turbo: false,
+                last_present: Instant::now(),
+                target_frame_duration: 0,
                 waitable_timer: t_handle,
                 qpc_freq,
                 last_frame_start: MaybeUninit::uninit().assume_init(),
             };
 
+            os_timing.set_speed(1.0);
             OsTiming::query_qpc(&mut os_timing.last_frame_start)?;
 
             Ok(os_timing)
         }
     }
 
+    /// Scales the frame rate, e.g. 4.0 for fast-forwarding or 0.25 for slow motion.
+    /// Speeds that are not positive are ignored.
+    pub fn set_speed(&mut self, speed: f32) {
+        if speed <= 0.0 || !speed.is_finite() {
+            return;
+        }
+
+        self.speed = speed;
+
+        unsafe {
+            let frame_rate = self.target_frame_rate * speed as f64;
+            self.target_frame_duration = (*self.qpc_freq.QuadPart() as f64 / frame_rate) as i64;
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// How long a frame should take at the current speed
+    pub fn target_frame_duration(&self) -> Duration {
+        unsafe {
+            let secs = self.target_frame_duration as f64 / *self.qpc_freq.QuadPart() as f64;
+            Duration::from_secs_f64(secs)
+        }
+    }
+
+    /// Turns the throttle off completely, so [`OsTiming::wait_frame_remaining`] never
+    /// waits. The speed multiplier is kept for when turbo mode ends.
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    pub fn turbo(&self) -> bool {
+        self.turbo
+    }
+
+    /// Whether the current frame should be presented. Always true, except in turbo mode,
+    /// where frames are produced way faster than any display can show them (and
+    /// presenting them would slow us down), so only one is presented per display frame.
+    pub fn should_present(&mut self) -> bool {
+        if !self.turbo {
+            return true;
+        }
+
+        let now = Instant::now();
+
+        if now - self.last_present >= TURBO_PRESENT_INTERVAL {
+            self.last_present = now;
+            true
+        } else {
+            false
+        }
+    }
+
     /// Also returns last frame duration for logging / debugging purposes.
     pub fn notify_frame_start(&mut self) -> Result<Duration, TimerError> {
         unsafe {
@@ -85,8 +159,12 @@ impl OsTiming {
         }
     }
 
-    /// Does not wait at all if you are already too slow
+    /// Does not wait at all if you are already too slow (or in turbo mode)
     pub fn wait_frame_remaining(&self) -> Result<(), TimerError> {
+        if self.turbo {
+            return Ok(());
+        }
+
         unsafe {
             let mut current_pc = MaybeUninit::uninit().assume_init();
             OsTiming::query_qpc(&mut current_pc)?;
@@ -0,0 +1,155 @@
+//! How the 160x144 pixels of the Game Boy are fit into a window of any size, and
+//! which filters are applied on the way (see [`ScalingMode`] and [`ScreenFilter`]).
+//! The actual drawing happens on the GPU, see [`crate::GfxWindow`].
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How the frame is scaled to fill the window. The aspect ratio is kept in all modes;
+/// The remaining space is filled with black bars.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScalingMode {
+    /// The largest integer multiple of the frame size that fits, so every Game Boy pixel
+    /// is drawn as a square of the same size
+    Integer,
+    /// As large as possible, with nearest-neighbor sampling. Some pixels end up a bit
+    /// larger than others.
+    AspectFit,
+    /// As large as possible, with bilinear filtering. Blurry, but no uneven pixels.
+    Bilinear,
+}
+
+impl ScalingMode {
+    /// The next mode, for cycling through all of them with a single key
+    pub fn next(self) -> ScalingMode {
+        match self {
+            ScalingMode::Integer => ScalingMode::AspectFit,
+            ScalingMode::AspectFit => ScalingMode::Bilinear,
+            ScalingMode::Bilinear => ScalingMode::Integer,
+        }
+    }
+
+    /// Whether the frame is sampled with bilinear filtering instead of nearest-neighbor
+    pub fn is_bilinear(self) -> bool {
+        self == ScalingMode::Bilinear
+    }
+
+    /// The rectangle (x, y, width, height) that a frame of `src` size is drawn into,
+    /// centered in a window (client area) of `dst` size
+    pub fn fit(self, src: (u32, u32), dst: (u32, u32)) -> (u32, u32, u32, u32) {
+        let (width, height) = match self {
+            ScalingMode::Integer => {
+                // Windows that are smaller than the frame still show something
+                let scale = (dst.0 / src.0).min(dst.1 / src.1).max(1);
+                (src.0 * scale, src.1 * scale)
+            }
+            ScalingMode::AspectFit | ScalingMode::Bilinear => {
+                if dst.0 as u64 * src.1 as u64 <= dst.1 as u64 * src.0 as u64 {
+                    // Limited by the width
+                    (dst.0, (dst.0 as u64 * src.1 as u64 / src.0 as u64) as u32)
+                } else {
+                    ((dst.1 as u64 * src.0 as u64 / src.1 as u64) as u32, dst.1)
+                }
+            }
+        };
+
+        (
+            dst.0.saturating_sub(width) / 2,
+            dst.1.saturating_sub(height) / 2,
+            width,
+            height,
+        )
+    }
+}
+
+impl Default for ScalingMode {
+    fn default() -> Self {
+        ScalingMode::Integer
+    }
+}
+
+impl fmt::Display for ScalingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScalingMode::Integer => "integer",
+            ScalingMode::AspectFit => "aspect",
+            ScalingMode::Bilinear => "bilinear",
+        })
+    }
+}
+
+impl FromStr for ScalingMode {
+    type Err = ();
+
+    /// Inverse of the [`fmt::Display`] implementation
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(ScalingMode::Integer),
+            "aspect" => Ok(ScalingMode::AspectFit),
+            "bilinear" => Ok(ScalingMode::Bilinear),
+            _ => Err(()),
+        }
+    }
+}
+
+/// An effect that is applied by the pixel shader while the frame is scaled up. Filters
+/// need a few window pixels per Game Boy pixel to look like anything, so they fade out
+/// in small windows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScreenFilter {
+    None,
+    /// Darkens the lower part of every line, like the gaps between the lines of a CRT
+    Scanlines,
+    /// Darkens the edges of every pixel, like the grid of the Game Boy's LCD
+    LcdGrid,
+}
+
+impl ScreenFilter {
+    /// The next filter, for cycling through all of them with a single key
+    pub fn next(self) -> ScreenFilter {
+        match self {
+            ScreenFilter::None => ScreenFilter::Scanlines,
+            ScreenFilter::Scanlines => ScreenFilter::LcdGrid,
+            ScreenFilter::LcdGrid => ScreenFilter::None,
+        }
+    }
+
+    /// How the pixel shader identifies the filter
+    pub(crate) fn shader_id(self) -> u32 {
+        match self {
+            ScreenFilter::None => 0,
+            ScreenFilter::Scanlines => 1,
+            ScreenFilter::LcdGrid => 2,
+        }
+    }
+}
+
+impl Default for ScreenFilter {
+    fn default() -> Self {
+        ScreenFilter::None
+    }
+}
+
+impl fmt::Display for ScreenFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScreenFilter::None => "none",
+            ScreenFilter::Scanlines => "scanlines",
+            ScreenFilter::LcdGrid => "lcd",
+        })
+    }
+}
+
+impl FromStr for ScreenFilter {
+    type Err = ();
+
+    /// Inverse of the [`fmt::Display`] implementation
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ScreenFilter::None),
+            "scanlines" => Ok(ScreenFilter::Scanlines),
+            "lcd" => Ok(ScreenFilter::LcdGrid),
+            _ => Err(()),
+        }
+    }
+}
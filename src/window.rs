@@ -1,14 +1,19 @@
 //! Utilities for creating native windows on Win32. For now, there is
-//! no support for any UI besides the window frame. All drawing for
-//! the emulator is done through DirectX.
+//! no support for any UI besides the window frame and a simple menu bar.
+//! All drawing for the emulator is done through DirectX.
 
+use super::util::EncodeWideNulTerm;
 use super::window_factory::WindowFactory;
+use std::ffi::OsString;
 use std::marker::PhantomPinned;
-use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+use std::os::windows::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::ptr;
+use winapi::shared::minwindef::{LPARAM, LRESULT, TRUE, UINT, WPARAM};
 use winapi::shared::windef::HWND;
-use winapi::um::winuser::{ShowWindow, SW_SHOW};
+use winapi::um::shellapi::{DragAcceptFiles, DragFinish, DragQueryFileW, HDROP};
+use winapi::um::winuser::*;
 
-// TODO: Impl drop closing the window properly
 /// A native window with its own message handler routine. Don't forget
 /// to display the window after creating it by calling [`Window::show`].
 pub struct Window<'f> {
@@ -28,6 +33,12 @@ pub enum MsgHandlerResult {
     DoNotRunDefaultMsgHandler(LRESULT),
 }
 
+/// An entry of the menu set via [`Window::set_menu`]
+pub enum MenuItem<'a> {
+    Entry(&'a str),
+    Separator,
+}
+
 impl<'f> Window<'f> {
     pub(super) fn new(factory: &WindowFactory, hwnd: HWND, msg_handler: MsgHandler) -> Window {
         Window {
@@ -58,4 +69,115 @@ impl<'f> Window<'f> {
             ShowWindow(self.hwnd, SW_SHOW);
         }
     }
+
+    /// Lets the user drop files onto the window. The message handler routine receives
+    /// them as `WM_DROPFILES`, see [`dropped_file_from_msg`].
+    pub fn accept_dropped_files(&self) {
+        unsafe {
+            DragAcceptFiles(self.hwnd, TRUE);
+        }
+    }
+
+    /// Replaces the menu bar with a single menu called `title`. When one of its entries
+    /// is selected, the message handler routine receives `WM_COMMAND`, see
+    /// [`menu_item_from_msg`]. Returns false if the menu could not be created.
+    pub fn set_menu(&self, title: &str, items: &[MenuItem]) -> bool {
+        unsafe {
+            let menu = CreatePopupMenu();
+            let menu_bar = CreateMenu();
+
+            if menu.is_null() || menu_bar.is_null() {
+                return false;
+            }
+
+            for (idx, item) in items.iter().enumerate() {
+                match item {
+                    MenuItem::Entry(text) => {
+                        // A single '&' would underline the next letter instead
+                        let text = OsString::from(text.replace('&', "&&")).encode_wide_nul_term();
+                        AppendMenuW(menu, MF_STRING, idx + 1, text.as_ptr());
+                    }
+                    MenuItem::Separator => {
+                        AppendMenuW(menu, MF_SEPARATOR, 0, ptr::null());
+                    }
+                }
+            }
+
+            let title = OsString::from(title).encode_wide_nul_term();
+            AppendMenuW(menu_bar, MF_POPUP, menu as usize, title.as_ptr());
+
+            let old_menu_bar = GetMenu(self.hwnd);
+
+            if SetMenu(self.hwnd, menu_bar) == 0 {
+                DestroyMenu(menu_bar);
+                return false;
+            }
+
+            if !old_menu_bar.is_null() {
+                DestroyMenu(old_menu_bar);
+            }
+
+            true
+        }
+    }
+}
+
+impl Drop for Window<'_> {
+    fn drop(&mut self) {
+        self.factory.forget_window(self.hwnd);
+
+        unsafe {
+            // The window procedure must not find this struct anymore while the window is
+            // destroyed. Fails harmlessly if the user already closed the window.
+            SetWindowLongPtrW(self.hwnd, GWLP_USERDATA, 0);
+            DestroyWindow(self.hwnd);
+        }
+    }
+}
+
+/// The new size of the client area if the message says that the window was resized.
+/// Call this from the message handler routine of your window and pass the size on to
+/// [`crate::GfxWindow::resize`].
+pub fn client_size_from_msg(msg: u32, l_param: isize) -> Option<(u32, u32)> {
+    if msg == WM_SIZE {
+        Some(((l_param & 0xFFFF) as u32, ((l_param >> 16) & 0xFFFF) as u32))
+    } else {
+        None
+    }
+}
+
+/// The first file that was dropped onto the window, if the message says that files
+/// were dropped (see [`Window::accept_dropped_files`])
+pub fn dropped_file_from_msg(msg: u32, w_param: usize) -> Option<PathBuf> {
+    if msg != WM_DROPFILES {
+        return None;
+    }
+
+    unsafe {
+        let hdrop = w_param as HDROP;
+
+        // Without the nul terminator
+        let len = DragQueryFileW(hdrop, 0, ptr::null_mut(), 0) as usize;
+        let mut path = vec![0u16; len + 1];
+        let copied = DragQueryFileW(hdrop, 0, path.as_mut_ptr(), path.len() as u32) as usize;
+
+        DragFinish(hdrop);
+
+        if copied == 0 {
+            None
+        } else {
+            Some(PathBuf::from(OsString::from_wide(&path[..copied])))
+        }
+    }
+}
+
+/// The index of the menu item (in the slice passed to [`Window::set_menu`]) if the
+/// message says that it was selected
+pub fn menu_item_from_msg(msg: u32, w_param: usize) -> Option<usize> {
+    // The high word is 0 for menus, 1 for accelerators
+    if msg == WM_COMMAND && (w_param >> 16) & 0xFFFF == 0 {
+        (w_param & 0xFFFF).checked_sub(1)
+    } else {
+        None
+    }
 }
@@ -115,6 +115,14 @@ impl WindowFactory {
         }
     }
 
+    /// Stops tracking a window that is destroyed by its owner (see the `Drop` impl of
+    /// [`Window`]), so closing it doesn't end the message loop
+    pub(super) fn forget_window(&self, hwnd: HWND) {
+        self.active_windows
+            .borrow_mut()
+            .retain(|&other_hwnd| other_hwnd != hwnd);
+    }
+
     pub fn dispatch_window_msgs(&self) -> bool {
         unsafe {
             let mut msg: MSG = mem::MaybeUninit::uninit().assume_init();
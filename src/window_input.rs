@@ -53,6 +53,68 @@ pub enum KeyboardKey {
     LeftArrow = VK_LEFT,
     ControlLeft = VK_CONTROL,
     ControlRight = VK_RCONTROL,
+    Tab = VK_TAB,
+    F2 = VK_F2,
+    F3 = VK_F3,
+    F4 = VK_F4,
+    F5 = VK_F5,
+    F6 = VK_F6,
+    F7 = VK_F7,
+    F9 = VK_F9,
+    F11 = VK_F11,
+    F12 = VK_F12,
+}
+
+/// The keys that are named in the input config (see [`maboy::frontend::InputConfig`]).
+/// The letters are named after themselves.
+const KEY_NAMES: [(KeyboardKey, &str); 8] = [
+    (KeyboardKey::Space, "Space"),
+    (KeyboardKey::Return, "Enter"),
+    (KeyboardKey::Backspace, "Backspace"),
+    (KeyboardKey::UpArrow, "Up"),
+    (KeyboardKey::RightArrow, "Right"),
+    (KeyboardKey::DownArrow, "Down"),
+    (KeyboardKey::LeftArrow, "Left"),
+    (KeyboardKey::Tab, "Tab"),
+];
+
+impl KeyboardKey {
+    /// The key with the given label, for the letters A-Z (except Q)
+    pub fn from_label(label: char) -> Option<KeyboardKey> {
+        use KeyboardKey::*;
+
+        const LETTERS: [KeyboardKey; 25] = [
+            A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, R, S, T, U, V, W, X, Y, Z,
+        ];
+
+        LETTERS
+            .iter()
+            .copied()
+            .find(|&key| key as i32 == label.to_ascii_uppercase() as i32)
+    }
+
+    /// The key with the given name in the input config, case-insensitive
+    pub fn from_name(name: &str) -> Option<KeyboardKey> {
+        let mut chars = name.chars();
+
+        match (chars.next(), chars.next()) {
+            (Some(label), None) => KeyboardKey::from_label(label),
+            _ => KEY_NAMES
+                .iter()
+                .find(|(_, key_name)| key_name.eq_ignore_ascii_case(name))
+                .map(|&(key, _)| key),
+        }
+    }
+
+    /// The name of the key in the input config, or `None` for keys that can't be bound
+    pub fn name(self) -> Option<String> {
+        match KEY_NAMES.iter().find(|&&(key, _)| key == self) {
+            Some((_, name)) => Some((*name).to_owned()),
+            None => Some(self as i32 as u8 as char)
+                .filter(|label| label.is_ascii_uppercase())
+                .map(String::from),
+        }
+    }
 }
 
 impl WindowInput {